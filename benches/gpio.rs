@@ -0,0 +1,41 @@
+//! Measures the board this runs on; see `rustberrypi::bench`. Toggles
+//! `RUSTBERRYPI_BENCH_PIN` (GPIO 17 by default), and if
+//! `RUSTBERRYPI_BENCH_LOOPBACK` names two pins wired together, e.g.
+//! `20,21`, times edge callbacks from the first to the second.
+
+use rustberrypi::bench::{self, Measurement};
+use rustberrypi::GPIO;
+
+use std::time::Duration;
+
+
+fn report(name: &str, measurement: Result<Measurement, rustberrypi::Error>) {
+    match measurement {
+        Ok(m) => println!("{:<14} {:>12.0}/s  mean {:>10?}  min {:>10?}  max {:>10?}",
+            name, m.rate(), m.mean(), m.min, m.max),
+        Err(e) => println!("{:<14} failed: {}", name, e),
+    }
+}
+
+fn main() {
+    let gpio = match GPIO::new() {
+        Ok(gpio) => gpio,
+        Err(e) => {
+            println!("skipping the GPIO benchmarks: {}", e);
+            return;
+        }
+    };
+    let pin = std::env::var("RUSTBERRYPI_BENCH_PIN").ok().and_then(|pin| pin.parse().ok()).unwrap_or(17);
+
+    report("toggle", bench::toggle(&gpio, pin, 1_000_000));
+    report("fast toggle", bench::fast_toggle(&gpio, pin, 1_000_000));
+    report("set_function", bench::set_function(&gpio, pin, 10_000));
+    report("read", bench::read(&gpio, pin, 1_000_000));
+    report("fast read", bench::fast_read(&gpio, pin, 1_000_000));
+
+    let loopback = std::env::var("RUSTBERRYPI_BENCH_LOOPBACK").ok();
+    let pins: Option<Vec<u32>> = loopback.map(|pins| pins.split(',').filter_map(|pin| pin.trim().parse().ok()).collect());
+    if let Some([output, input]) = pins.as_deref() {
+        report("edge dispatch", bench::edge_dispatch(&gpio, *output, *input, 1_000, Duration::from_secs(1)));
+    }
+}
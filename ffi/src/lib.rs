@@ -0,0 +1,3 @@
+//! Builds the C interface in `rustberrypi::ffi` into `librustberrypi.so`.
+
+pub use gpio::ffi::*;
@@ -0,0 +1,74 @@
+/*
+ * C interface to the rustberrypi GPIO library; see src/ffi.rs. Link with
+ * -lrustberrypi, from a build with
+ * `cargo build --release -p rustberrypi-ffi`.
+ *
+ * Pins are BCM GPIO numbers. Functions returning int return a
+ * non-negative value on success and RBP_FAILED on failure, with
+ * rbp_last_error() describing it.
+ */
+
+#ifndef RUSTBERRYPI_H
+#define RUSTBERRYPI_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define RBP_FAILED (-1)
+#define RBP_TIMED_OUT (-2)
+
+/* GPFSEL values, as bcm2835_gpio_fsel takes them. */
+#define RBP_FSEL_INPUT 0
+#define RBP_FSEL_OUTPUT 1
+#define RBP_FSEL_ALT0 4
+#define RBP_FSEL_ALT1 5
+#define RBP_FSEL_ALT2 6
+#define RBP_FSEL_ALT3 7
+#define RBP_FSEL_ALT4 3
+#define RBP_FSEL_ALT5 2
+
+#define RBP_PULL_NONE 0
+#define RBP_PULL_UP 1
+#define RBP_PULL_DOWN 2
+
+#define RBP_TRIGGER_RISING 1
+#define RBP_TRIGGER_FALLING 2
+#define RBP_TRIGGER_BOTH 3
+
+typedef struct rbp_gpio rbp_gpio;
+
+/* Opens the GPIOs. Returns NULL on failure. */
+rbp_gpio *rbp_open(void);
+/* Releases a handle from rbp_open. NULL is ignored. */
+void rbp_close(rbp_gpio *gpio);
+/* The last failure on this thread, valid until the next one. */
+const char *rbp_last_error(void);
+
+/* The pin's level, 0 or 1. */
+int rbp_read(const rbp_gpio *gpio, uint32_t pin);
+/* Drives the pin low if level is 0 and high otherwise. */
+int rbp_write(const rbp_gpio *gpio, uint32_t pin, int level);
+
+/* One of RBP_FSEL_*. */
+int rbp_set_function(const rbp_gpio *gpio, uint32_t pin, int function);
+int rbp_get_function(const rbp_gpio *gpio, uint32_t pin);
+
+/* One of RBP_PULL_*. */
+int rbp_set_pull(const rbp_gpio *gpio, uint32_t pin, int pull);
+int rbp_get_pull(const rbp_gpio *gpio, uint32_t pin);
+
+/*
+ * Waits for an RBP_TRIGGER_* edge; a negative timeout_ms waits forever.
+ * Returns 1 for a rising edge, 0 for a falling one and RBP_TIMED_OUT if
+ * none came in time.
+ */
+int rbp_wait_for_edge(const rbp_gpio *gpio, uint32_t pin, int trigger, int64_t timeout_ms);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
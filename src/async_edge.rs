@@ -0,0 +1,179 @@
+use crate::gpiochip::read_edge_event;
+use crate::{check_pin, Error, Input, Pin, PinEvent, PinFunction, Trigger, GPIO};
+
+use embedded_hal_async::digital::Wait;
+use futures_core::Stream;
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+
+use std::fs::File;
+use std::task::{Context, Poll};
+
+
+/// Edges on one pin as a `Stream`, driven by tokio's reactor rather than a
+/// dedicated thread. Only line backends such as gpiochip can provide one.
+/// Dropping the stream turns edge detection off again.
+pub struct EdgeEvents<'a> {
+    gpio: &'a GPIO,
+    pin: u32,
+    fd: AsyncFd<File>,
+}
+
+impl<'a> EdgeEvents<'a> {
+
+    // Must be called from within a tokio runtime.
+    fn new(gpio: &'a GPIO, pin: u32, file: File) -> Result<Self, Error> {
+        let fd = AsyncFd::with_interest(file, Interest::READABLE)
+            .map_err(|e| Error::from_io(format!("failed to register pin {} with the tokio reactor", pin), e))?;
+        Ok(Self { gpio, pin, fd })
+    }
+
+    fn read_error(&self, error: std::io::Error) -> Error {
+        Error::from_io(format!("failed to read an edge event for pin {}", self.pin), error)
+    }
+
+    /// Waits for the next edge.
+    pub async fn next_edge(&mut self) -> Result<PinEvent, Error> {
+        loop {
+            let mut guard = self.fd.readable().await.map_err(|e| self.read_error(e))?;
+            match guard.try_io(|fd| read_edge_event(fd.get_ref())) {
+                Ok(Ok(Some(event))) if self.gpio.debounce.accept(self.pin, event.timestamp) => return Ok(event),
+                Ok(Ok(Some(_))) => continue,
+                Ok(Ok(None)) | Err(_) => continue,
+                Ok(Err(error)) => return Err(self.read_error(error)),
+            }
+        }
+    }
+}
+
+impl<'a> Stream for EdgeEvents<'a> {
+    type Item = Result<PinEvent, Error>;
+
+    fn poll_next(self: std::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            let mut guard = match self.fd.poll_read_ready(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Ok(guard)) => guard,
+                Poll::Ready(Err(error)) => return Poll::Ready(Some(Err(self.read_error(error)))),
+            };
+            match guard.try_io(|fd| read_edge_event(fd.get_ref())) {
+                Ok(Ok(Some(event))) if self.gpio.debounce.accept(self.pin, event.timestamp) => return Poll::Ready(Some(Ok(event))),
+                Ok(Ok(Some(_))) => continue,
+                Ok(Ok(None)) | Err(_) => continue,
+                Ok(Err(error)) => return Poll::Ready(Some(Err(self.read_error(error)))),
+            }
+        }
+    }
+}
+
+impl<'a> Drop for EdgeEvents<'a> {
+    fn drop(&mut self) {
+        // Setting the function reconfigures the line without edge flags.
+        let _ = self.gpio.driver.set_function(self.pin, PinFunction::Input);
+    }
+}
+
+
+impl GPIO {
+
+    /// A stream of `trigger` edges on `pin`. Must be called from within a
+    /// tokio runtime.
+    pub fn edge_events(&self, pin: u32, trigger: Trigger) -> Result<EdgeEvents<'_>, Error> {
+        check_pin(pin)?;
+        EdgeEvents::new(self, pin, self.driver.edge_event_file(pin, trigger)?)
+    }
+
+    /// The async counterpart of `wait_for_edge`, without a timeout; wrap it
+    /// in `tokio::time::timeout` for one.
+    pub async fn wait_for_edge_async(&self, pin: u32, trigger: Trigger) -> Result<PinEvent, Error> {
+        self.edge_events(pin, trigger)?.next_edge().await
+    }
+}
+
+
+// Waits for `pin` to read `high`. The stream is opened before the level is
+// checked, so a change in between still arrives as an edge.
+async fn wait_for_level(pin: &Pin<'_, Input>, high: bool) -> Result<(), Error> {
+    if pin.read()? == high {
+        return Ok(());
+    }
+    let mut events = pin.edge_events(if high { Trigger::Rising } else { Trigger::Falling })?;
+    if pin.read()? == high {
+        return Ok(());
+    }
+    events.next_edge().await.map(|_| ())
+}
+
+impl<'a> Wait for Pin<'a, Input> {
+    async fn wait_for_high(&mut self) -> Result<(), Error> {
+        wait_for_level(self, true).await
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Error> {
+        wait_for_level(self, false).await
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Error> {
+        self.wait_for_edge_async(Trigger::Rising).await.map(|_| ())
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Error> {
+        self.wait_for_edge_async(Trigger::Falling).await.map(|_| ())
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Error> {
+        self.wait_for_edge_async(Trigger::Both).await.map(|_| ())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EdgeKind;
+
+    use std::io::Write;
+    use std::os::unix::io::FromRawFd;
+
+    // A gpio_v2_line_event as the kernel writes it.
+    fn line_event(id: u32, offset: u32) -> [u8; 48] {
+        let mut bytes = [0u8; 48];
+        bytes[8..12].copy_from_slice(&id.to_ne_bytes());
+        bytes[12..16].copy_from_slice(&offset.to_ne_bytes());
+        bytes
+    }
+
+    #[tokio::test]
+    async fn test_edge_events_from_line_fd() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let (read, write) = nix::unistd::pipe2(nix::fcntl::OFlag::O_NONBLOCK).unwrap();
+        let (read, mut write) = unsafe { (File::from_raw_fd(read), File::from_raw_fd(write)) };
+
+        let mut events = EdgeEvents::new(&gpio, 17, read).unwrap();
+        write.write_all(&line_event(2, 17)).unwrap();
+        write.write_all(&line_event(1, 17)).unwrap();
+        assert_eq!(events.next_edge().await.unwrap().edge, EdgeKind::Falling);
+        let event = std::future::poll_fn(|cx| std::pin::Pin::new(&mut events).poll_next(cx)).await.unwrap().unwrap();
+        assert_eq!(event.edge, EdgeKind::Rising);
+        assert_eq!(event.pin, 17);
+    }
+
+    #[tokio::test]
+    async fn test_edge_events_need_a_line_backend() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        assert!(matches!(gpio.edge_events(17, Trigger::Both), Err(Error::Unsupported(_))));
+        assert!(matches!(gpio.wait_for_edge_async(58, Trigger::Both).await, Err(Error::InvalidPin { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_level() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let mut pin = gpio.pin(27).unwrap().into_input().unwrap();
+        // Already at the level, so no edge stream is needed.
+        pin.wait_for_low().await.unwrap();
+        unsafe { gpio.write_raw(0x34, 1 << 27) };
+        pin.wait_for_high().await.unwrap();
+        assert!(matches!(pin.wait_for_low().await, Err(Error::Unsupported(_))));
+        assert!(matches!(pin.wait_for_any_edge().await, Err(Error::Unsupported(_))));
+    }
+}
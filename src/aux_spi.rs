@@ -0,0 +1,260 @@
+use crate::auxiliary::{Aux, AuxPeripheral, AUX_BASE_OFFSET};
+use crate::region::MappedRegion;
+use crate::{detect_peripheral_base, Error, PinFunction, Soc, SpiBackend, SpiMode, GPIO, DEV_MEM_PATH};
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+
+const SPI1_OFFSET: i64 = 0x80;
+const SPI2_OFFSET: i64 = 0xc0;
+const AUX_SPI_BLOCK_LEN: usize = 0x34;
+
+const AUX_SPI_CNTL0: usize = 0x00;
+const AUX_SPI_CNTL1: usize = 0x04;
+const AUX_SPI_STAT: usize = 0x08;
+// Writes to IO end the transfer after the word, raising chip select; writes
+// to TXHOLD keep it asserted for the next.
+const AUX_SPI_IO: usize = 0x20;
+const AUX_SPI_TXHOLD: usize = 0x30;
+
+const CNTL0_MSBF_OUT: u32 = 1 << 6;
+const CNTL0_CPOL: u32 = 1 << 7;
+const CNTL0_OUT_RISING: u32 = 1 << 8;
+const CNTL0_CLEAR_FIFOS: u32 = 1 << 9;
+const CNTL0_IN_RISING: u32 = 1 << 10;
+const CNTL0_ENABLE: u32 = 1 << 11;
+// Each word written carries its own shift length in bits 24 to 28.
+const CNTL0_VAR_WIDTH: u32 = 1 << 14;
+const CNTL0_CS_SHIFT: u32 = 17;
+const CNTL0_CS_MASK: u32 = 0b111 << CNTL0_CS_SHIFT;
+const CNTL0_SPEED_SHIFT: u32 = 20;
+const CNTL0_SPEED_MAX: u32 = 0xfff;
+
+const CNTL1_MSBF_IN: u32 = 1 << 1;
+
+const STAT_RX_EMPTY: u32 = 1 << 7;
+const STAT_TX_FULL: u32 = 1 << 10;
+
+const WIDTH_SHIFT: u32 = 24;
+const MAX_WORD_BITS: u32 = 24;
+const FIFO_DEPTH: usize = 4;
+
+// MISO, MOSI and SCLK, then chip selects 0 to 2, on ALT4.
+const SPI1_PINS: [u32; 6] = [19, 20, 21, 18, 17, 16];
+const SPI2_PINS: [u32; 6] = [40, 41, 42, 43, 44, 45];
+
+// How long a transfer may go without progress before giving up.
+const TRANSFER_TIMEOUT: Duration = Duration::from_millis(100);
+
+
+// The clock is the core clock over 2 × (SPEED + 1).
+fn speed_field(core_clock_hz: u32, freq_hz: u32) -> Result<u32, Error> {
+    if freq_hz == 0 {
+        return Err(Error::Other("SPI clock frequency must be positive".to_string()));
+    }
+    let speed = core_clock_hz.div_ceil(2 * freq_hz).max(1) - 1;
+    if speed > CNTL0_SPEED_MAX {
+        return Err(Error::Other(format!("{} Hz is below the slowest auxiliary SPI clock", freq_hz)));
+    }
+    Ok(speed)
+}
+
+
+/// One of the auxiliary SPI masters, SPI1 on GPIO 16 to 21 or SPI2 on GPIO
+/// 40 to 45, which run alongside SPI0. Their 4-word FIFOs take words of 1
+/// to 24 bits, so odd-width devices such as 9-bit displays can be driven
+/// directly with `transfer_words`. The controller's chip selects are
+/// active low only.
+///
+/// Use it as an `Spi` through `Spi::aux`, or as a `SpiBackend`.
+pub struct AuxSpi {
+    region: MappedRegion,
+    core_clock_hz: u32,
+    cntl0: u32,
+}
+
+impl AuxSpi {
+
+    /// Enables SPI `bus`, 1 or 2, in the AUX block and switches its data
+    /// pins and `chip_select`, 0 to 2, to it. The kernel's driver for the
+    /// bus, e.g. the `spi1-1cs` overlay, must not be using it.
+    pub fn new(gpio: &GPIO, bus: u8, chip_select: u8) -> Result<Self, Error> {
+        let base = detect_peripheral_base()?;
+        let (peripheral, offset, pins) = match bus {
+            1 => (AuxPeripheral::Spi1, SPI1_OFFSET, SPI1_PINS),
+            2 => (AuxPeripheral::Spi2, SPI2_OFFSET, SPI2_PINS),
+            _ => return Err(Error::Other(format!("there is no auxiliary SPI{}", bus))),
+        };
+        Aux::open(DEV_MEM_PATH, base + AUX_BASE_OFFSET)?.enable(peripheral)?;
+        let spi = Self::open(DEV_MEM_PATH, base + AUX_BASE_OFFSET + offset, chip_select, gpio.soc())?;
+        gpio.switch_to_alt(&[pins[0], pins[1], pins[2], pins[3 + chip_select as usize]], PinFunction::Alt4)?;
+        Ok(spi)
+    }
+
+    pub(crate) fn open(path: impl AsRef<Path>, phys_base: i64, chip_select: u8, soc: Soc) -> Result<Self, Error> {
+        if chip_select > 2 {
+            return Err(Error::Other(format!("the auxiliary SPI controllers have no chip select {}", chip_select)));
+        }
+        let core_clock_hz = if soc == Soc::Bcm2711 { 500_000_000 } else { 250_000_000 };
+        let region = MappedRegion::open(path.as_ref(), phys_base, AUX_SPI_BLOCK_LEN)?;
+        // Keep whatever clock rate it was left at, as SPI0 does.
+        let speed = region.read_reg(AUX_SPI_CNTL0)? >> CNTL0_SPEED_SHIFT;
+        let chip_selects = CNTL0_CS_MASK & !(1 << (CNTL0_CS_SHIFT + chip_select as u32));
+        let spi = Self {
+            region,
+            core_clock_hz,
+            cntl0: speed << CNTL0_SPEED_SHIFT | chip_selects | CNTL0_ENABLE | CNTL0_VAR_WIDTH | CNTL0_MSBF_OUT
+                | CNTL0_IN_RISING,
+        };
+        spi.region.write_reg(AUX_SPI_CNTL1, CNTL1_MSBF_IN)?;
+        spi.region.write_reg(AUX_SPI_CNTL0, spi.cntl0 | CNTL0_CLEAR_FIFOS)?;
+        spi.region.write_reg(AUX_SPI_CNTL0, spi.cntl0)?;
+        Ok(spi)
+    }
+
+    // Shifts each `(word, bits)` out MSB first with chip select held
+    // throughout, and returns the words shifted in.
+    fn shift(&self, words: &[(u32, u32)]) -> Result<Vec<u32>, Error> {
+        let mut received = Vec::with_capacity(words.len());
+        let mut sent = 0;
+        let mut progress = Instant::now();
+        while received.len() < words.len() {
+            let stat = self.region.read_reg(AUX_SPI_STAT)?;
+            if sent < words.len() && stat & STAT_TX_FULL == 0 && sent - received.len() < FIFO_DEPTH {
+                let (word, bits) = words[sent];
+                let register = if sent + 1 < words.len() { AUX_SPI_TXHOLD } else { AUX_SPI_IO };
+                self.region.write_reg(register, bits << WIDTH_SHIFT | word << (MAX_WORD_BITS - bits))?;
+                sent += 1;
+                progress = Instant::now();
+            } else if received.len() < sent && stat & STAT_RX_EMPTY == 0 {
+                let bits = words[received.len()].1;
+                received.push(self.region.read_reg(AUX_SPI_IO)? & ((1 << bits) - 1));
+                progress = Instant::now();
+            } else if progress.elapsed() > TRANSFER_TIMEOUT {
+                return Err(Error::Timeout(format!(
+                    "auxiliary SPI transfer stalled after {} of {} words", received.len(), words.len())));
+            } else {
+                std::hint::spin_loop();
+            }
+        }
+        Ok(received)
+    }
+
+    /// Sends each of `words` as `bits` bits, 1 to 24, and replaces it with
+    /// the word clocked in at the same time, with chip select asserted
+    /// throughout.
+    pub fn transfer_words(&mut self, words: &mut [u32], bits: u32) -> Result<(), Error> {
+        if !(1..=MAX_WORD_BITS).contains(&bits) {
+            return Err(Error::Other(format!("auxiliary SPI words are 1 to 24 bits, not {}", bits)));
+        }
+        if let Some(word) = words.iter().find(|&&word| word >> bits != 0) {
+            return Err(Error::Other(format!("{:#x} doesn't fit in {} bits", word, bits)));
+        }
+        let shifts: Vec<(u32, u32)> = words.iter().map(|&word| (word, bits)).collect();
+        let received = self.shift(&shifts)?;
+        words.copy_from_slice(&received);
+        Ok(())
+    }
+}
+
+impl SpiBackend for AuxSpi {
+    // Bytes go three to a FIFO word.
+    fn transfer(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        let words: Vec<(u32, u32)> = buffer.chunks(3)
+            .map(|chunk| (chunk.iter().fold(0, |word, &byte| word << 8 | byte as u32), 8 * chunk.len() as u32))
+            .collect();
+        let received = self.shift(&words)?;
+        for (chunk, word) in buffer.chunks_mut(3).zip(received) {
+            let len = chunk.len();
+            for (index, byte) in chunk.iter_mut().enumerate() {
+                *byte = (word >> (8 * (len - 1 - index))) as u8;
+            }
+        }
+        Ok(())
+    }
+
+    fn set_mode(&mut self, mode: SpiMode) -> Result<(), Error> {
+        let bits = match mode {
+            SpiMode::Mode0 => CNTL0_IN_RISING,
+            SpiMode::Mode1 => CNTL0_OUT_RISING,
+            SpiMode::Mode2 => CNTL0_CPOL | CNTL0_OUT_RISING,
+            SpiMode::Mode3 => CNTL0_CPOL | CNTL0_IN_RISING,
+        };
+        self.cntl0 = self.cntl0 & !(CNTL0_CPOL | CNTL0_IN_RISING | CNTL0_OUT_RISING) | bits;
+        self.region.write_reg(AUX_SPI_CNTL0, self.cntl0)
+    }
+
+    fn set_frequency(&mut self, freq_hz: u32) -> Result<u32, Error> {
+        let speed = speed_field(self.core_clock_hz, freq_hz)?;
+        self.cntl0 = self.cntl0 & !(CNTL0_SPEED_MAX << CNTL0_SPEED_SHIFT) | speed << CNTL0_SPEED_SHIFT;
+        self.region.write_reg(AUX_SPI_CNTL0, self.cntl0)?;
+        Ok(self.core_clock_hz / (2 * (speed + 1)))
+    }
+
+    fn set_cs_active_high(&mut self, active_high: bool) -> Result<(), Error> {
+        if active_high {
+            return Err(Error::Unsupported("the auxiliary SPI chip selects are active low only".to_string()));
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_speed_field() {
+        assert_eq!(speed_field(250_000_000, 1_000_000).ok(), Some(124));
+        assert_eq!(speed_field(500_000_000, 3_000_000).ok(), Some(83));
+        assert_eq!(speed_field(250_000_000, 200_000_000).ok(), Some(0));
+        assert!(speed_field(250_000_000, 30_000).is_err());
+        assert!(speed_field(250_000_000, 0).is_err());
+    }
+
+    #[test]
+    fn test_aux_spi() {
+        let path = std::env::temp_dir().join(format!("rustberrypi-aux-spi-{}", std::process::id()));
+        std::fs::write(&path, vec![0u8; 4096]).unwrap();
+        let mut spi = AuxSpi::open(&path, 0, 1, Soc::Bcm2837).ok().unwrap();
+        let cntl0 = spi.region.read_reg(AUX_SPI_CNTL0).unwrap();
+        assert_eq!(cntl0 & CNTL0_CS_MASK, 0b101 << CNTL0_CS_SHIFT);
+        assert_eq!(cntl0 & (CNTL0_ENABLE | CNTL0_VAR_WIDTH | CNTL0_CLEAR_FIFOS), CNTL0_ENABLE | CNTL0_VAR_WIDTH);
+        assert_eq!(spi.region.read_reg(AUX_SPI_CNTL1).ok(), Some(CNTL1_MSBF_IN));
+
+        assert_eq!(spi.set_frequency(1_000_000).ok(), Some(1_000_000));
+        assert_eq!(spi.region.read_reg(AUX_SPI_CNTL0).unwrap() >> CNTL0_SPEED_SHIFT, 124);
+        spi.set_mode(SpiMode::Mode2).unwrap();
+        let cntl0 = spi.region.read_reg(AUX_SPI_CNTL0).unwrap();
+        assert_eq!(cntl0 & (CNTL0_CPOL | CNTL0_IN_RISING | CNTL0_OUT_RISING), CNTL0_CPOL | CNTL0_OUT_RISING);
+        assert!(spi.set_cs_active_high(true).is_err());
+
+        // The file never fills or empties, and reading IO gives back the
+        // last word written, so a 24-bit word echoes.
+        let mut words = [0x12_3456];
+        spi.transfer_words(&mut words, 24).unwrap();
+        assert_eq!(words, [0x12_3456]);
+        assert_eq!(spi.region.read_reg(AUX_SPI_IO).ok(), Some(24 << 24 | 0x12_3456));
+        let mut nine_bit = [0x1a5, 0x0ff];
+        spi.transfer_words(&mut nine_bit, 9).unwrap();
+        assert_eq!(spi.region.read_reg(AUX_SPI_TXHOLD).ok(), Some(9 << 24 | 0x1a5 << 15));
+        assert_eq!(spi.region.read_reg(AUX_SPI_IO).ok(), Some(9 << 24 | 0x0ff << 15));
+        assert!(spi.transfer_words(&mut [0x200], 9).is_err());
+        assert!(spi.transfer_words(&mut [0], 25).is_err());
+
+        let mut bytes = [0xab, 0xcd, 0xef, 0x01];
+        spi.transfer(&mut bytes).unwrap();
+        assert_eq!(spi.region.read_reg(AUX_SPI_TXHOLD).ok(), Some(24 << 24 | 0xab_cdef));
+        assert_eq!(spi.region.read_reg(AUX_SPI_IO).ok(), Some(8 << 24 | 0x01 << 16));
+        // The byte words read back the IO word masked to their width.
+        assert_eq!(bytes, [0x01, 0x00, 0x00, 0x00]);
+
+        spi.region.write_reg(AUX_SPI_STAT, STAT_TX_FULL).unwrap();
+        assert!(matches!(spi.transfer(&mut [0]), Err(Error::Timeout(_))));
+        assert!(AuxSpi::open(&path, 0, 3, Soc::Bcm2837).is_err());
+        drop(spi);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,114 @@
+use crate::region::MappedRegion;
+use crate::{detect_peripheral_base, Error, DEV_MEM_PATH};
+
+use std::path::Path;
+
+
+pub(crate) const AUX_BASE_OFFSET: i64 = 0x215000;
+const AUX_CONTROL_LEN: usize = 0x08;
+
+const AUX_IRQ: usize = 0x00;
+pub(crate) const AUX_ENABLES: usize = 0x04;
+
+
+/// The peripherals of the AUX block, which share its enable and interrupt
+/// registers.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum AuxPeripheral {
+    MiniUart,
+    Spi1,
+    Spi2,
+}
+
+impl AuxPeripheral {
+
+    pub const ALL: [AuxPeripheral; 3] = [AuxPeripheral::MiniUart, AuxPeripheral::Spi1, AuxPeripheral::Spi2];
+
+    // Its bit in both AUX_IRQ and AUX_ENABLES.
+    pub(crate) fn mask(self) -> u32 {
+        match self {
+            AuxPeripheral::MiniUart => 1 << 0,
+            AuxPeripheral::Spi1 => 1 << 1,
+            AuxPeripheral::Spi2 => 1 << 2,
+        }
+    }
+}
+
+
+/// The AUX block's enable and interrupt status registers. A disabled
+/// auxiliary peripheral has no clock and ignores its registers, so the mini
+/// UART, SPI1 and SPI2 must each be enabled here before use. The kernel
+/// enables the ones its overlays use, e.g. `uart1` or `spi1-1cs`, and
+/// disabling one of those pulls it out from under the kernel's driver.
+pub struct Aux {
+    region: MappedRegion,
+}
+
+impl Aux {
+
+    /// Maps the AUX registers through `/dev/mem`.
+    pub fn new() -> Result<Self, Error> {
+        let base = detect_peripheral_base()?;
+        Self::open(DEV_MEM_PATH, base + AUX_BASE_OFFSET)
+    }
+
+    pub(crate) fn open(path: impl AsRef<Path>, phys_base: i64) -> Result<Self, Error> {
+        Ok(Self { region: MappedRegion::open(path.as_ref(), phys_base, AUX_CONTROL_LEN)? })
+    }
+
+    pub fn is_enabled(&self, peripheral: AuxPeripheral) -> Result<bool, Error> {
+        Ok(self.region.read_reg(AUX_ENABLES)? & peripheral.mask() != 0)
+    }
+
+    /// Enables `peripheral`, leaving the others as they are.
+    pub fn enable(&self, peripheral: AuxPeripheral) -> Result<(), Error> {
+        let enables = self.region.read_reg(AUX_ENABLES)?;
+        self.region.write_reg(AUX_ENABLES, enables | peripheral.mask())
+    }
+
+    /// Disables `peripheral`, which stops it mid-transfer and resets
+    /// nothing; its registers keep their values for when it's re-enabled.
+    pub fn disable(&self, peripheral: AuxPeripheral) -> Result<(), Error> {
+        let enables = self.region.read_reg(AUX_ENABLES)?;
+        self.region.write_reg(AUX_ENABLES, enables & !peripheral.mask())
+    }
+
+    /// The enabled peripherals.
+    pub fn enabled(&self) -> Result<Vec<AuxPeripheral>, Error> {
+        let enables = self.region.read_reg(AUX_ENABLES)?;
+        Ok(AuxPeripheral::ALL.iter().copied().filter(|peripheral| enables & peripheral.mask() != 0).collect())
+    }
+
+    /// The peripherals with an interrupt pending. They share one interrupt
+    /// line, so this tells which to service.
+    pub fn pending_interrupts(&self) -> Result<Vec<AuxPeripheral>, Error> {
+        let irq = self.region.read_reg(AUX_IRQ)?;
+        Ok(AuxPeripheral::ALL.iter().copied().filter(|peripheral| irq & peripheral.mask() != 0).collect())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aux_enables() {
+        let path = std::env::temp_dir().join(format!("rustberrypi-aux-{}", std::process::id()));
+        std::fs::write(&path, vec![0u8; 4096]).unwrap();
+        let aux = Aux::open(&path, 0).ok().unwrap();
+        assert_eq!(aux.enabled().ok(), Some(Vec::new()));
+        aux.enable(AuxPeripheral::Spi2).unwrap();
+        aux.enable(AuxPeripheral::MiniUart).unwrap();
+        assert_eq!(aux.region.read_reg(AUX_ENABLES).ok(), Some(0b101));
+        assert_eq!(aux.enabled().ok(), Some(vec![AuxPeripheral::MiniUart, AuxPeripheral::Spi2]));
+        aux.disable(AuxPeripheral::MiniUart).unwrap();
+        assert!(!aux.is_enabled(AuxPeripheral::MiniUart).unwrap());
+        assert!(aux.is_enabled(AuxPeripheral::Spi2).unwrap());
+
+        aux.region.write_reg(AUX_IRQ, 0b010).unwrap();
+        assert_eq!(aux.pending_interrupts().ok(), Some(vec![AuxPeripheral::Spi1]));
+        drop(aux);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,508 @@
+use crate::gpiochip::{self, GpioChip};
+use crate::rp1::Rp1Backend;
+use crate::sysfs::SysfsGpio;
+use crate::{
+    bit_in_bank, detect_peripheral_base, memory_barrier, open_file_with, Backend, Error,
+    PinFunction, Pull, Register, Soc, Trigger, PinEvent, DEV_GPIOCHIP_PATH, DEV_GPIOMEM0_PATH, DEV_GPIOMEM_PATH, DEV_MEM_PATH, GPIO, GPIO_BASE_OFFSET,
+    GPIO_PIN_COUNT, SYSFS_GPIO_PATH,
+};
+use crate::register_map::RegisterMap;
+#[cfg(feature = "trace")]
+use crate::trace::RegisterTrace;
+#[cfg(feature = "trace")]
+use crate::RegisterAccess;
+#[cfg(any(test, feature = "mock"))]
+use crate::registers::{GPIO_BLOCK_SIZE, REGISTER_SIZE};
+
+use nix::sys::mman;
+
+use std::ffi::c_void;
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "trace")]
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+
+const DEV_PATH: &str = "/dev";
+// What the RP1's pinctrl driver calls its gpiochip.
+const RP1_CHIP_LABEL: &str = "pinctrl-rp1";
+
+
+/// Pin-level access to the GPIO hardware. `GPIO` layers the write policy,
+/// the audit hook and the output shadow on top of whichever backend it was
+/// built with, so a new way of reaching the pins only has to implement this.
+/// `GPIO` serialises function and pull changes itself, but reads and level
+/// writes may arrive from several threads at once.
+pub trait GpioBackend: Send + Sync {
+    fn function(&self, pin: u32) -> Result<PinFunction, Error>;
+    fn set_function(&self, pin: u32, function: PinFunction) -> Result<(), Error>;
+    fn pull(&self, pin: u32) -> Result<Pull, Error>;
+    fn set_pull(&self, pin: u32, pull: Pull) -> Result<(), Error>;
+    fn write(&self, pin: u32, level: bool) -> Result<(), Error>;
+    fn read(&self, pin: u32) -> Result<bool, Error>;
+
+    /// Blocks until `trigger` fires on `pin`. Only consulted for backends
+    /// without `registers`.
+    fn wait_for_edge(&self, pin: u32, _trigger: Trigger, _timeout: Option<Duration>) -> Result<PinEvent, Error> {
+        Err(Error::Unsupported(format!("this backend can't wait for edges on pin {}", pin)))
+    }
+
+    /// A non-blocking file that becomes readable whenever `trigger` fires
+    /// on `pin`, for event loops and async runtimes. Edge detection stays
+    /// on until the pin's function is next set.
+    fn edge_event_file(&self, pin: u32, _trigger: Trigger) -> Result<File, Error> {
+        Err(Error::Unsupported(format!("this backend has no event file for pin {}", pin)))
+    }
+
+    /// The name the kernel gives `pin`'s line, e.g. `"GPIO18"` or
+    /// `"ID_SDA"`, for backends that know one.
+    fn line_name(&self, _pin: u32) -> Option<String> {
+        None
+    }
+
+    /// For each pin, the kernel driver holding its line, e.g. `"spi0 CS0"`
+    /// or `"kernel"` if it has no name, or `None` if the line is free. May
+    /// stop short of the last pin, or be empty for backends that can't tell.
+    fn kernel_consumers(&self) -> Vec<Option<String>> {
+        Vec::new()
+    }
+
+    /// The start of the GPIO register block, for backends that map it.
+    /// Register-level operations such as edge detection, snapshots and
+    /// square waves are only available when this is `Some`.
+    fn registers(&self) -> Option<*mut c_void> {
+        None
+    }
+
+    /// Closes any descriptor kept open beside the backend's mapping, which
+    /// stays valid without it, and returns whether there was one. Whatever
+    /// needed the descriptor, such as edge events, fails from then on.
+    fn release_fd(&mut self) -> bool {
+        false
+    }
+}
+
+
+enum Mapping {
+    Mapped,
+    #[cfg(any(test, feature = "mock"))]
+    Owned { _words: Box<[u32]> },
+}
+
+/// The GPIO register block, mapped from `/dev/mem` or `/dev/gpiomem`, or
+/// held in memory for tests.
+pub(crate) struct RegisterBackend {
+    buffer: *mut c_void,
+    mapping: Mapping,
+    pub(crate) soc: Soc,
+    pub(crate) register_map: RegisterMap,
+    pub(crate) legacy_pulls: Mutex<[Pull; GPIO_PIN_COUNT as usize]>,
+    #[cfg(feature = "trace")]
+    pub(crate) trace: Arc<RegisterTrace>,
+}
+
+impl RegisterBackend {
+
+    fn map(path: &str, gpio_offset: i64, soc: Soc, read_only: bool, register_map: RegisterMap) -> Result<Self, Error> {
+        let fp: std::fs::File = open_file_with(path, !read_only)?;
+        Self::map_file(&fp, path, gpio_offset, soc, read_only, register_map)
+    }
+
+    /// Maps the block from `gpio_offset` bytes into `fp`, which `name`
+    /// describes in errors. Regular files must be long enough to hold it,
+    /// as touching a page past their end would fault.
+    pub(crate) fn map_file(fp: &File, name: &str, gpio_offset: i64, soc: Soc, read_only: bool, register_map: RegisterMap)
+        -> Result<Self, Error>
+    {
+        let block_size = register_map.block_size();
+        let metadata = fp.metadata()
+            .map_err(|e| Error::from_io(format!("failed to inspect {}", name), e))?;
+        if metadata.is_file() && (gpio_offset < 0 || metadata.len() < gpio_offset as u64 + block_size as u64) {
+            return Err(Error::Other(format!(
+                "{} has no {:#x} byte register block at offset {:#x}", name, block_size, gpio_offset)));
+        }
+        let protection = if read_only {
+            mman::ProtFlags::PROT_READ
+        } else {
+            mman::ProtFlags::PROT_READ | mman::ProtFlags::PROT_WRITE
+        };
+        #[cfg(feature = "tracing")]
+        tracing::debug!(offset = gpio_offset, read_only, "mapping the GPIO registers from {}", name);
+        let buffer = unsafe {
+            mman::mmap(std::ptr::null_mut(), block_size, protection,
+                mman::MapFlags::MAP_SHARED, fp.as_raw_fd(), gpio_offset)
+                .map_err(
+                    |e| Error::MmapFailed {
+                        context: format!("failed to map the GPIO ({:#X}) from {}", gpio_offset, name),
+                        source: e,
+                    })?
+        };
+        Ok(Self::with_buffer(buffer, Mapping::Mapped, soc, register_map))
+    }
+
+    #[cfg(any(test, feature = "mock"))]
+    pub(crate) fn owned(buffer: Vec<u8>, soc: Soc) -> Self {
+        let mut words = vec![0u32; GPIO_BLOCK_SIZE / REGISTER_SIZE as usize].into_boxed_slice();
+        for (word, bytes) in words.iter_mut().zip(buffer.chunks(REGISTER_SIZE as usize)) {
+            let mut word_bytes = [0u8; 4];
+            word_bytes[..bytes.len()].copy_from_slice(bytes);
+            *word = u32::from_ne_bytes(word_bytes);
+        }
+        let buffer = words.as_mut_ptr() as *mut c_void;
+        Self::with_buffer(buffer, Mapping::Owned { _words: words }, soc, RegisterMap::default())
+    }
+
+    fn with_buffer(buffer: *mut c_void, mapping: Mapping, soc: Soc, register_map: RegisterMap) -> Self {
+        Self {
+            buffer,
+            mapping,
+            soc,
+            register_map,
+            legacy_pulls: Mutex::new([Pull::None; GPIO_PIN_COUNT as usize]),
+            #[cfg(feature = "trace")]
+            trace: Arc::default(),
+        }
+    }
+
+    fn register_ptr(&self, register: Register, pin: u32) -> Result<*mut u32, Error> {
+        Ok(self.buffer.wrapping_add(self.register_map.offset(register, pin)?) as *mut u32)
+    }
+
+    pub(crate) fn read_register(&self, register: Register, pin: u32) -> Result<u32, Error> {
+        let ptr = self.register_ptr(register, pin)?;
+        let value = unsafe { ptr.read_volatile() };
+        #[cfg(feature = "trace")]
+        self.trace.record(RegisterAccess::Read, ptr as usize - self.buffer as usize, value);
+        Ok(value)
+    }
+
+    pub(crate) fn write_register(&self, register: Register, pin: u32, value: u32) -> Result<(), Error> {
+        let ptr = self.register_ptr(register, pin)?;
+        unsafe { ptr.write_volatile(value) };
+        #[cfg(feature = "trace")]
+        self.trace.record(RegisterAccess::Write, ptr as usize - self.buffer as usize, value);
+        Ok(())
+    }
+}
+
+impl GpioBackend for RegisterBackend {
+
+    fn function(&self, pin: u32) -> Result<PinFunction, Error> {
+        Ok(PinFunction::from_bits(pin, self.read_register(Register::GPFSEL, pin)?))
+    }
+
+    fn set_function(&self, pin: u32, function: PinFunction) -> Result<(), Error> {
+        let value = self.read_register(Register::GPFSEL, pin)?;
+        self.write_register(Register::GPFSEL, pin, value & PinFunction::clear_mask(pin) | function.to_bits(pin))
+    }
+
+    fn pull(&self, pin: u32) -> Result<Pull, Error> {
+        if !self.soc.has_pull_control_registers() {
+            return Ok(self.get_pull_legacy(pin));
+        }
+        Ok(Pull::from_bits(pin, self.read_register(Register::GPPUPPDNCNTRL, pin)?))
+    }
+
+    fn set_pull(&self, pin: u32, pull: Pull) -> Result<(), Error> {
+        if !self.soc.has_pull_control_registers() {
+            return self.set_pull_legacy(pin, pull);
+        }
+        let value = self.read_register(Register::GPPUPPDNCNTRL, pin)?;
+        self.write_register(Register::GPPUPPDNCNTRL, pin, value & Pull::clear_mask(pin) | pull.to_bits(pin))
+    }
+
+    // GPSET/GPCLR are write-only and ignore zero bits, so a plain write of
+    // the pin's bit is enough; reading them back returns garbage. Level
+    // changes and reads are fenced, since they're the accesses most often
+    // interleaved with other peripherals (e.g. SPI or PWM).
+    fn write(&self, pin: u32, level: bool) -> Result<(), Error> {
+        let register = if level { Register::GPSET } else { Register::GPCLR };
+        memory_barrier();
+        self.write_register(register, pin, 1 << bit_in_bank(pin))
+    }
+
+    fn read(&self, pin: u32) -> Result<bool, Error> {
+        let value = self.read_register(Register::GPLEV, pin)?;
+        memory_barrier();
+        Ok(((value >> bit_in_bank(pin)) & 1) == 1)
+    }
+
+    // The registers don't say who else is using a pin, but the kernel's
+    // gpiochip for the same block does.
+    fn kernel_consumers(&self) -> Vec<Option<String>> {
+        match self.mapping {
+            Mapping::Mapped => gpiochip::kernel_consumers_at(DEV_GPIOCHIP_PATH),
+            #[cfg(any(test, feature = "mock"))]
+            Mapping::Owned { .. } => Vec::new(),
+        }
+    }
+
+    fn registers(&self) -> Option<*mut c_void> {
+        Some(self.buffer)
+    }
+}
+
+// The buffer is only reached through volatile word accesses, each of which
+// is a single bus transaction, and `GPIO` serialises the read-modify-write
+// sequences. The mapping lives as long as the backend.
+unsafe impl Send for RegisterBackend {}
+unsafe impl Sync for RegisterBackend {}
+
+impl Drop for RegisterBackend {
+    fn drop(&mut self) {
+        if matches!(self.mapping, Mapping::Mapped) {
+            unsafe {
+                let _ = mman::munmap(self.buffer, self.register_map.block_size());
+            }
+        }
+    }
+}
+
+
+/// Opens a `GPIO` with a chosen backend and options. `GPIO::new()` is
+/// `GpioBuilder::new().build()`.
+pub struct GpioBuilder {
+    backend: Backend,
+    base_address: Option<i64>,
+    gpio_base: Option<i64>,
+    register_map: RegisterMap,
+    soc: Option<Soc>,
+    chip_path: Option<PathBuf>,
+    read_only: bool,
+    custom: Option<Box<dyn GpioBackend>>,
+}
+
+impl Default for GpioBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GpioBuilder {
+
+    pub fn new() -> Self {
+        Self {
+            backend: Backend::Auto,
+            base_address: None,
+            gpio_base: None,
+            register_map: RegisterMap::default(),
+            soc: None,
+            chip_path: None,
+            read_only: false,
+            custom: None,
+        }
+    }
+
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// The physical peripheral base used to map `/dev/mem`, instead of
+    /// detecting it from the device tree or `/proc/cpuinfo`.
+    pub fn base_address(mut self, base_address: i64) -> Self {
+        self.base_address = Some(base_address);
+        self
+    }
+
+    /// The physical address of the GPIO block itself for `Backend::DevMem`,
+    /// for SoCs or machine models that don't put it 0x200000 past the
+    /// peripheral base.
+    pub fn gpio_base(mut self, gpio_base: i64) -> Self {
+        self.gpio_base = Some(gpio_base);
+        self
+    }
+
+    /// The layout of the register block, for the register backends.
+    pub fn register_map(mut self, register_map: RegisterMap) -> Self {
+        self.register_map = register_map;
+        self
+    }
+
+    /// The SoC, instead of detecting it. It picks the pull scheme and pin
+    /// count, and has to be given on boards that aren't Pis.
+    pub fn soc(mut self, soc: Soc) -> Self {
+        self.soc = Some(soc);
+        self
+    }
+
+    /// The character device used by `Backend::GpioChip`, instead of
+    /// `/dev/gpiochip0` or, on a Pi 5, the chip the RP1 driver registered.
+    pub fn chip_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.chip_path = Some(path.into());
+        self
+    }
+
+    /// Maps the registers read-only and rejects every write with an error,
+    /// for monitoring tools that shouldn't be able to disturb the pins.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Uses `backend` instead of any of the built-in ones.
+    pub fn custom(mut self, backend: impl GpioBackend + 'static) -> Self {
+        self.custom = Some(Box::new(backend));
+        self
+    }
+
+    pub fn build(self) -> Result<GPIO, Error> {
+        let soc = self.soc.or_else(|| Soc::detect(self.peripheral_base().ok()));
+        if let Some(custom) = self.custom {
+            #[cfg(feature = "tracing")]
+            tracing::info!("using a custom GPIO backend");
+            let mut gpio = GPIO::from_backend(custom, soc.unwrap_or(Soc::Bcm2711));
+            gpio.read_only = self.read_only;
+            return Ok(gpio);
+        }
+        let mut result = Err(Error::Unsupported("no GPIO backend available".to_string()));
+        for candidate in self.backend.candidates(soc, |path| Path::new(path).exists()) {
+            result = self.open_backend(candidate, soc);
+            #[cfg(feature = "tracing")]
+            match &result {
+                Ok(gpio) => tracing::info!(backend = ?candidate, soc = ?gpio.soc(), "opened the GPIO"),
+                Err(e) => tracing::debug!(backend = ?candidate, "GPIO backend unavailable: {}", e),
+            }
+            if result.is_ok() {
+                break;
+            }
+        }
+        result
+    }
+
+    fn open_backend(&self, backend: Backend, soc: Option<Soc>) -> Result<GPIO, Error> {
+        let mut gpio = match backend {
+            Backend::GpioChip | Backend::Sysfs => {
+                let lines: Box<dyn GpioBackend> = match backend {
+                    Backend::GpioChip => Box::new(GpioChip::open(self.gpiochip_path(soc))?),
+                    _ => Box::new(SysfsGpio::open(SYSFS_GPIO_PATH)?),
+                };
+                // Only the register-level pull sequence depends on the SoC,
+                // and the line backends never use it, so an unknown board
+                // is not an error here.
+                GPIO::from_backend(lines, soc.unwrap_or(Soc::Bcm2711))
+            }
+            Backend::Rp1 => {
+                let chip = GpioChip::open(self.gpiochip_path(Some(Soc::Bcm2712))).ok();
+                GPIO::from_backend(Box::new(Rp1Backend::open(DEV_GPIOMEM0_PATH, chip)?), Soc::Bcm2712)
+            }
+            _ => {
+                let (path, gpio_offset) = match (backend, self.gpio_base) {
+                    (Backend::GpioMem, _) => (DEV_GPIOMEM_PATH, 0),
+                    (_, Some(gpio_base)) => (DEV_MEM_PATH, gpio_base),
+                    _ => (DEV_MEM_PATH, self.peripheral_base()? + GPIO_BASE_OFFSET),
+                };
+                let soc = soc
+                    .ok_or_else(|| Error::UnsupportedBoard("unable to detect the SoC model".to_string()))?;
+                if soc == Soc::Bcm2712 {
+                    return Err(Error::UnsupportedBoard(
+                        "a Pi 5's GPIOs are on the RP1; use Backend::Rp1 or Backend::GpioChip".to_string()));
+                }
+                GPIO::from_registers(RegisterBackend::map(path, gpio_offset, soc, self.read_only, self.register_map)?, soc)
+            }
+        };
+        gpio.backend = Some(backend);
+        gpio.read_only = self.read_only;
+        Ok(gpio)
+    }
+
+    // The chip given to `chip_path`, or on a Pi 5 the RP1's, whose number
+    // changed between kernels, falling back to /dev/gpiochip0.
+    fn gpiochip_path(&self, soc: Option<Soc>) -> PathBuf {
+        if let Some(path) = &self.chip_path {
+            return path.clone();
+        }
+        if soc == Some(Soc::Bcm2712) {
+            if let Some(path) = gpiochip::find_chip_by_label(DEV_PATH, RP1_CHIP_LABEL) {
+                return path;
+            }
+        }
+        PathBuf::from(DEV_GPIOCHIP_PATH)
+    }
+
+    fn peripheral_base(&self) -> Result<i64, Error> {
+        match self.base_address {
+            Some(base_address) => Ok(base_address),
+            None => detect_peripheral_base(),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FixedBackend;
+
+    impl GpioBackend for FixedBackend {
+        fn function(&self, _pin: u32) -> Result<PinFunction, Error> { Ok(PinFunction::Output) }
+        fn set_function(&self, _pin: u32, _function: PinFunction) -> Result<(), Error> { Ok(()) }
+        fn pull(&self, _pin: u32) -> Result<Pull, Error> { Ok(Pull::Down) }
+        fn set_pull(&self, _pin: u32, _pull: Pull) -> Result<(), Error> { Ok(()) }
+        fn write(&self, _pin: u32, _level: bool) -> Result<(), Error> { Ok(()) }
+        fn read(&self, pin: u32) -> Result<bool, Error> { Ok(pin % 2 == 1) }
+    }
+
+    #[test]
+    fn test_builder_custom_backend() {
+        let gpio = GpioBuilder::new().custom(FixedBackend).build().ok().unwrap();
+        assert_eq!(gpio.backend(), None);
+        assert!(!gpio.is_mapped());
+        assert_eq!(gpio.get_function(3).ok(), Some(PinFunction::Output));
+        assert_eq!(gpio.get_pull(3).ok(), Some(Pull::Down));
+        assert_eq!(gpio.read(3).ok(), Some(true));
+        gpio.set_high(4).unwrap();
+        assert_eq!(gpio.output_state(4).ok(), Some(Some(true)));
+    }
+
+    #[test]
+    fn test_builder_read_only_rejects_writes() {
+        let gpio = GpioBuilder::new().custom(FixedBackend).read_only(true).build().ok().unwrap();
+        assert!(gpio.set_high(4).is_err());
+        assert!(gpio.set_function(4, PinFunction::Input).is_err());
+        assert!(gpio.set_pull(4, Pull::Up).is_err());
+        assert_eq!(gpio.output_state(4).ok(), Some(None));
+        assert_eq!(gpio.read(5).ok(), Some(true));
+    }
+
+    #[test]
+    fn test_register_backend_pin_access() {
+        let registers = RegisterBackend::owned(Vec::new(), Soc::Bcm2711);
+        registers.set_function(17, PinFunction::Output).unwrap();
+        registers.set_function(18, PinFunction::Alt5).unwrap();
+        assert_eq!(registers.function(17).ok(), Some(PinFunction::Output));
+        assert_eq!(registers.function(18).ok(), Some(PinFunction::Alt5));
+        registers.set_pull(40, Pull::Down).unwrap();
+        assert_eq!(registers.pull(40).ok(), Some(Pull::Down));
+        registers.write(40, true).unwrap();
+        assert_eq!(registers.read_register(Register::GPSET, 40).ok(), Some(1 << 8));
+        assert!(registers.registers().is_some());
+    }
+
+    #[test]
+    fn test_gpio_with_mapping() {
+        let path = std::env::temp_dir().join(format!("rustberrypi-mapping-{}", std::process::id()));
+        std::fs::write(&path, vec![0u8; 0x2000]).unwrap();
+        let word = |offset: usize| {
+            let bytes = std::fs::read(&path).unwrap();
+            let mut word = [0u8; 4];
+            word.copy_from_slice(&bytes[0x1000 + offset..0x1000 + offset + 4]);
+            u32::from_ne_bytes(word)
+        };
+        let file = std::fs::OpenOptions::new().read(true).write(true).open(&path).unwrap();
+        let gpio = GPIO::with_mapping(&file, 0x1000, Soc::Bcm2711).unwrap();
+        gpio.set_function(17, PinFunction::Output).unwrap();
+        gpio.set_high(17).unwrap();
+        gpio.set_pull(4, Pull::Up).unwrap();
+        assert_eq!(word(Register::GPFSEL as usize + 4), 1 << 21);
+        assert_eq!(word(Register::GPSET as usize), 1 << 17);
+        assert_eq!(word(Register::GPPUPPDNCNTRL as usize), 0b01 << 8);
+        assert!(GPIO::with_mapping(&file, 0x1004, Soc::Bcm2711).is_err());
+        drop(gpio);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
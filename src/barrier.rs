@@ -0,0 +1,20 @@
+use core::sync::atomic::{fence, Ordering};
+
+
+/// A full data memory barrier. The BCM283x/BCM2711 peripherals sit on an
+/// AXI bus that may return reads from different peripherals out of order,
+/// so the SoC documentation asks for a barrier before the first write to a
+/// peripheral and after the last read from one.
+///
+/// `fence` alone only orders normal memory within the inner shareable
+/// domain, which doesn't cover device memory, so ARMv7 and AArch64 targets
+/// also issue a system-wide `dmb`. ARMv6 (Pi 1 and Zero) has no `dmb`; the
+/// fence there is already the full CP15 barrier.
+#[inline]
+pub fn memory_barrier() {
+    fence(Ordering::SeqCst);
+    #[cfg(any(target_arch = "aarch64", all(target_arch = "arm", target_feature = "v7")))]
+    unsafe {
+        core::arch::asm!("dmb sy", options(nostack, preserves_flags));
+    }
+}
@@ -0,0 +1,185 @@
+//! Measurements of what the running board can do, for checking a
+//! protocol's timing budget before relying on it: how fast a pin can be
+//! toggled or read, how long a function change takes and how long an edge takes
+//! to reach an `on_edge` callback. Each puts the pins it uses back as it
+//! found them. Run them on an idle system, ideally after
+//! `realtime::lock_memory`; `cargo bench` runs them all on the pins given
+//! in its environment.
+
+use crate::{Error, PinFunction, Trigger, GPIO};
+
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+
+/// The time a repeated operation took.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Measurement {
+    pub iterations: u32,
+    pub total: Duration,
+    /// The fastest single operation. Operations too quick to time one by
+    /// one are only timed in total, and have this at the mean.
+    pub min: Duration,
+    /// The slowest single operation, as for `min`.
+    pub max: Duration,
+}
+
+impl Measurement {
+
+    fn from_total(iterations: u32, total: Duration) -> Self {
+        let mean = total / iterations.max(1);
+        Self { iterations, total, min: mean, max: mean }
+    }
+
+    pub(crate) fn from_samples(samples: &[Duration]) -> Self {
+        Self {
+            iterations: samples.len() as u32,
+            total: samples.iter().sum(),
+            min: samples.iter().min().copied().unwrap_or_default(),
+            max: samples.iter().max().copied().unwrap_or_default(),
+        }
+    }
+
+    pub fn mean(&self) -> Duration {
+        self.total / self.iterations.max(1)
+    }
+
+    /// Operations per second.
+    pub fn rate(&self) -> f64 {
+        self.iterations as f64 / self.total.as_secs_f64()
+    }
+}
+
+
+// Runs `measure` and puts `pins` back afterwards, whether it failed or not.
+pub(crate) fn restoring<T>(gpio: &GPIO, pins: &[u32], measure: impl FnOnce() -> Result<T, Error>) -> Result<T, Error> {
+    let snapshot = gpio.snapshot_pins(pins)?;
+    let result = measure();
+    gpio.restore(&snapshot)?;
+    result
+}
+
+/// Drives `pin` high then low `iterations` times through `GPIO::set_high`
+/// and `set_low`. The output's square wave is half the returned `rate`.
+pub fn toggle(gpio: &GPIO, pin: u32, iterations: u32) -> Result<Measurement, Error> {
+    restoring(gpio, &[pin], || {
+        gpio.set_function(pin, PinFunction::Output)?;
+        let start = Instant::now();
+        for _ in 0..iterations {
+            gpio.set_high(pin)?;
+            gpio.set_low(pin)?;
+        }
+        Ok(Measurement::from_total(2 * iterations, start.elapsed()))
+    })
+}
+
+/// As `toggle`, through a `Pin::fast_path` handle, so the fastest a pin
+/// can be bit-banged.
+pub fn fast_toggle(gpio: &GPIO, pin: u32, iterations: u32) -> Result<Measurement, Error> {
+    restoring(gpio, &[pin], || {
+        let output = gpio.pin(pin)?.into_output()?.fast_path()?;
+        let start = Instant::now();
+        for _ in 0..iterations {
+            output.set_high()?;
+            output.set_low()?;
+        }
+        Ok(Measurement::from_total(2 * iterations, start.elapsed()))
+    })
+}
+
+/// Reads `pin` `iterations` times through `GPIO::read`.
+pub fn read(gpio: &GPIO, pin: u32, iterations: u32) -> Result<Measurement, Error> {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        std::hint::black_box(gpio.read(pin)?);
+    }
+    Ok(Measurement::from_total(iterations, start.elapsed()))
+}
+
+/// As `read`, through `GPIO::read_pin_fast`, which needs the register
+/// block mapped.
+pub fn fast_read(gpio: &GPIO, pin: u32, iterations: u32) -> Result<Measurement, Error> {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        std::hint::black_box(gpio.read_pin_fast(pin)?);
+    }
+    Ok(Measurement::from_total(iterations, start.elapsed()))
+}
+
+/// Switches `pin` between input and output `iterations` times, timing
+/// each `set_function`.
+pub fn set_function(gpio: &GPIO, pin: u32, iterations: u32) -> Result<Measurement, Error> {
+    restoring(gpio, &[pin], || {
+        let mut samples = Vec::with_capacity(iterations as usize);
+        for i in 0..iterations {
+            let function = if i % 2 == 0 { PinFunction::Output } else { PinFunction::Input };
+            let start = Instant::now();
+            gpio.set_function(pin, function)?;
+            samples.push(start.elapsed());
+        }
+        Ok(Measurement::from_samples(&samples))
+    })
+}
+
+/// Times from driving `output` high to an `on_edge` callback on `input`
+/// running, `iterations` times. The two pins must be wired together.
+/// Fails with `Error::Timeout` if an edge doesn't arrive within `timeout`,
+/// e.g. because they aren't.
+pub fn edge_dispatch(gpio: &GPIO, output: u32, input: u32, iterations: u32, timeout: Duration)
+    -> Result<Measurement, Error>
+{
+    restoring(gpio, &[output, input], || {
+        gpio.set_function(input, PinFunction::Input)?;
+        gpio.set_function(output, PinFunction::Output)?;
+        gpio.set_low(output)?;
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let id = gpio.on_edge(input, Trigger::Rising, move |_| {
+            let _ = sender.lock().unwrap().send(Instant::now());
+        })?;
+
+        let result = (0..iterations)
+            .map(|_| {
+                let start = Instant::now();
+                gpio.set_high(output)?;
+                let dispatched = receiver.recv_timeout(timeout).map_err(|_| Error::Timeout(format!(
+                    "no rising edge on pin {} within {:?} of driving pin {} high", input, timeout, output)))?;
+                gpio.set_low(output)?;
+                Ok(dispatched.saturating_duration_since(start))
+            })
+            .collect::<Result<Vec<_>, Error>>();
+        gpio.remove_callback(id)?;
+        Ok(Measurement::from_samples(&result?))
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_measurements() {
+        let measurement = Measurement::from_samples(&[Duration::from_micros(1), Duration::from_micros(3)]);
+        assert_eq!(measurement.mean(), Duration::from_micros(2));
+        assert_eq!((measurement.min, measurement.max), (Duration::from_micros(1), Duration::from_micros(3)));
+        assert_eq!(Measurement::from_total(4, Duration::from_millis(2)).rate(), 2000.0);
+
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        gpio.set_function(17, PinFunction::Alt3).unwrap();
+        assert_eq!(toggle(&gpio, 17, 100).unwrap().iterations, 200);
+        assert_eq!(fast_toggle(&gpio, 17, 100).unwrap().iterations, 200);
+        assert_eq!(set_function(&gpio, 17, 10).unwrap().iterations, 10);
+        assert_eq!(read(&gpio, 17, 100).unwrap().iterations, 100);
+        assert_eq!(fast_read(&gpio, 17, 100).unwrap().iterations, 100);
+        assert!(fast_read(&crate::MockGpio::new().gpio().unwrap(), 17, 1).is_err());
+        assert_eq!(gpio.get_function(17).ok(), Some(PinFunction::Alt3));
+
+        // The test block's levels don't follow its outputs, as if the pins
+        // weren't wired together.
+        let error = edge_dispatch(&gpio, 17, 27, 1, Duration::from_millis(20)).unwrap_err();
+        assert!(matches!(error, Error::Timeout(_)));
+        assert_eq!(gpio.get_function(17).ok(), Some(PinFunction::Alt3));
+    }
+}
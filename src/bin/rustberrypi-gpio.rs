@@ -0,0 +1,259 @@
+//! Reads and sets GPIOs from the shell, in the manner of `raspi-gpio` and
+//! `pinctrl`:
+//!
+//! ```text
+//! rustberrypi-gpio get [PINS]
+//! rustberrypi-gpio set PINS [op|ip|a0-a5] [dh|dl] [pu|pd|pn]
+//! rustberrypi-gpio func PINS in|out|alt0-alt5
+//! rustberrypi-gpio pull PINS up|down|none
+//! rustberrypi-gpio watch PIN [rising|falling|both]
+//! rustberrypi-gpio pwm PIN FREQ_HZ DUTY [SECONDS]
+//! rustberrypi-gpio dump
+//! rustberrypi-gpio selftest OUT:IN[,OUT:IN...]
+//! ```
+//!
+//! `PINS` is a list of GPIO numbers and ranges such as `2-5,17`. `selftest`
+//! checks pairs of pins jumpered together, exiting with 1 if any check
+//! fails.
+
+use rustberrypi::selftest;
+use rustberrypi::{add_cleanup, install_signal_cleanup, run_cleanup, Error, PinFunction, Pull, SoftPwm, Trigger, GPIO};
+
+use std::process::exit;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+
+const USAGE: &str = "\
+usage: rustberrypi-gpio get [PINS]
+       rustberrypi-gpio set PINS [op|ip|a0-a5] [dh|dl] [pu|pd|pn]
+       rustberrypi-gpio func PINS in|out|alt0-alt5
+       rustberrypi-gpio pull PINS up|down|none
+       rustberrypi-gpio watch PIN [rising|falling|both]
+       rustberrypi-gpio pwm PIN FREQ_HZ DUTY [SECONDS]
+       rustberrypi-gpio dump
+       rustberrypi-gpio selftest OUT:IN[,OUT:IN...]
+PINS is a list of GPIO numbers and ranges, e.g. 2-5,17";
+
+const COMMANDS: [&str; 8] = ["get", "set", "func", "pull", "watch", "pwm", "dump", "selftest"];
+
+
+// A command line that doesn't parse, or a failure on the GPIOs.
+enum Failure {
+    Usage(String),
+    Gpio(Error),
+}
+
+impl From<Error> for Failure {
+    fn from(error: Error) -> Self {
+        Failure::Gpio(error)
+    }
+}
+
+fn usage<T>(message: impl Into<String>) -> Result<T, Failure> {
+    Err(Failure::Usage(message.into()))
+}
+
+
+fn parse_pins(list: &str) -> Result<Vec<u32>, Failure> {
+    let mut pins = Vec::new();
+    for part in list.split(',') {
+        let number = |text: &str| text.trim().parse::<u32>()
+            .or_else(|_| usage(format!("{:?} is not a GPIO number", text)));
+        match part.split_once('-') {
+            Some((first, last)) => pins.extend(number(first)?..=number(last)?),
+            None => pins.push(number(part)?),
+        }
+    }
+    Ok(pins)
+}
+
+// Jumpered pairs such as `17:27,5:6`, each the output then the input.
+fn parse_pairs(list: &str) -> Result<Vec<(u32, u32)>, Failure> {
+    list.split(',')
+        .map(|pair| match pair.split_once(':') {
+            Some((output, input)) => Ok((parse_number(output.trim(), "GPIO number")?, parse_number(input.trim(), "GPIO number")?)),
+            None => usage(format!("{:?} is not an OUT:IN pair", pair)),
+        })
+        .collect()
+}
+
+fn parse_function(name: &str) -> Result<PinFunction, Failure> {
+    Ok(match name {
+        "in" | "ip" => PinFunction::Input,
+        "out" | "op" => PinFunction::Output,
+        "alt0" | "a0" => PinFunction::Alt0,
+        "alt1" | "a1" => PinFunction::Alt1,
+        "alt2" | "a2" => PinFunction::Alt2,
+        "alt3" | "a3" => PinFunction::Alt3,
+        "alt4" | "a4" => PinFunction::Alt4,
+        "alt5" | "a5" => PinFunction::Alt5,
+        _ => return usage(format!("unknown function {:?}", name)),
+    })
+}
+
+fn parse_pull(name: &str) -> Result<Pull, Failure> {
+    Ok(match name {
+        "up" | "pu" => Pull::Up,
+        "down" | "pd" => Pull::Down,
+        "none" | "pn" => Pull::None,
+        _ => return usage(format!("unknown pull {:?}", name)),
+    })
+}
+
+fn parse_trigger(name: &str) -> Result<Trigger, Failure> {
+    Ok(match name {
+        "rising" => Trigger::Rising,
+        "falling" => Trigger::Falling,
+        "both" => Trigger::Both,
+        _ => return usage(format!("unknown edge {:?}", name)),
+    })
+}
+
+fn parse_number<T: std::str::FromStr>(text: &str, what: &str) -> Result<T, Failure> {
+    text.parse().or_else(|_| usage(format!("{:?} is not a valid {}", text, what)))
+}
+
+
+fn get(gpio: &GPIO, pins: Option<Vec<u32>>) -> Result<(), Failure> {
+    let dump = gpio.dump()?;
+    let pins = pins.unwrap_or_else(|| dump.pins().iter().map(|state| state.pin).collect());
+    for pin in pins {
+        match dump.pin(pin) {
+            Some(state) => println!("{}", state),
+            None => return Err(Error::InvalidPin { pin, register: None }.into()),
+        }
+    }
+    Ok(())
+}
+
+// `raspi-gpio set`: a function, a level to drive and a pull, in any order.
+fn set(gpio: &GPIO, pins: &[u32], settings: &[String]) -> Result<(), Failure> {
+    for setting in settings {
+        let setting = setting.as_str();
+        for &pin in pins {
+            match setting {
+                "dh" => {
+                    gpio.set_function(pin, PinFunction::Output)?;
+                    gpio.set_high(pin)?;
+                }
+                "dl" => {
+                    gpio.set_function(pin, PinFunction::Output)?;
+                    gpio.set_low(pin)?;
+                }
+                "pu" | "pd" | "pn" => gpio.set_pull(pin, parse_pull(setting)?)?,
+                _ => gpio.set_function(pin, parse_function(setting)?)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+fn watch(gpio: &GPIO, pin: u32, trigger: Trigger) -> Result<(), Failure> {
+    gpio.set_function(pin, PinFunction::Input)?;
+    let start = Instant::now();
+    loop {
+        let event = gpio.wait_for_edge(pin, trigger, None)?;
+        println!("GPIO {}: {:?} at {:.6}s", event.pin, event.edge,
+            event.timestamp.saturating_duration_since(start).as_secs_f64());
+    }
+}
+
+fn pwm(gpio: &Arc<GPIO>, pin: u32, freq_hz: f64, duty: f64, duration: Option<Duration>) -> Result<(), Failure> {
+    // Ctrl-C puts the pin back as it was rather than leaving it high.
+    let snapshot = gpio.snapshot_pins(&[pin])?;
+    let restore = Arc::clone(gpio);
+    add_cleanup(move || {
+        let _ = restore.restore(&snapshot);
+    });
+    install_signal_cleanup()?;
+
+    gpio.set_function(pin, PinFunction::Output)?;
+    let pwm = SoftPwm::new(gpio)?;
+    pwm.start(pin, freq_hz, duty)?;
+    match duration {
+        Some(duration) => std::thread::sleep(duration),
+        None => loop {
+            std::thread::park();
+        },
+    }
+    drop(pwm);
+    run_cleanup();
+    Ok(())
+}
+
+
+fn run(args: &[String]) -> Result<(), Failure> {
+    let (command, args) = match args.split_first() {
+        Some((command, args)) => (command.as_str(), args),
+        None => return usage("no command given"),
+    };
+    if !COMMANDS.contains(&command) {
+        return usage(format!("unknown command {:?}", command));
+    }
+    let arg = |index: usize, what: &str| match args.get(index) {
+        Some(arg) => Ok(arg.as_str()),
+        None => usage(format!("{} needs {}", command, what)),
+    };
+    let gpio = Arc::new(GPIO::new()?);
+
+    match command {
+        "get" => get(&gpio, args.first().map(|pins| parse_pins(pins)).transpose()?),
+        "set" => set(&gpio, &parse_pins(arg(0, "PINS")?)?, &args[1..]),
+        "func" => {
+            let function = parse_function(arg(1, "a function")?)?;
+            for pin in parse_pins(arg(0, "PINS")?)? {
+                gpio.set_function(pin, function)?;
+            }
+            Ok(())
+        }
+        "pull" => {
+            let pull = parse_pull(arg(1, "a pull")?)?;
+            for pin in parse_pins(arg(0, "PINS")?)? {
+                gpio.set_pull(pin, pull)?;
+            }
+            Ok(())
+        }
+        "watch" => {
+            let trigger = args.get(1).map_or(Ok(Trigger::Both), |name| parse_trigger(name))?;
+            watch(&gpio, parse_number(arg(0, "a PIN")?, "GPIO number")?, trigger)
+        }
+        "pwm" => {
+            let duration = args.get(3)
+                .map(|seconds| parse_number(seconds, "number of seconds").map(Duration::from_secs_f64))
+                .transpose()?;
+            pwm(&gpio, parse_number(arg(0, "a PIN")?, "GPIO number")?,
+                parse_number(arg(1, "a frequency")?, "frequency")?,
+                parse_number(arg(2, "a duty cycle")?, "duty cycle")?,
+                duration)
+        }
+        "dump" => {
+            print!("{}", gpio.dump()?);
+            Ok(())
+        }
+        "selftest" => {
+            let report = selftest::run(&gpio, &parse_pairs(arg(0, "OUT:IN pairs")?)?, &selftest::Options::default())?;
+            println!("{}", report);
+            if !report.passed() {
+                return Err(Error::Other("the self-test failed".to_string()).into());
+            }
+            Ok(())
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(()) => {}
+        Err(Failure::Usage(message)) => {
+            eprintln!("rustberrypi-gpio: {}\n{}", message, USAGE);
+            exit(2);
+        }
+        Err(Failure::Gpio(error)) => {
+            eprintln!("rustberrypi-gpio: {}", error);
+            exit(1);
+        }
+    }
+}
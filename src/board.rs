@@ -0,0 +1,408 @@
+//! The 40-pin header of every Pi since the Model B+, numbered as wiring
+//! diagrams number it: pin 1 is 3.3 V, by the SD card end, and odd pins
+//! run along the inside row. The original Model A and B's 26-pin header
+//! had GPIO 0, 1 and 21 where later boards have GPIO 2, 3 and 27, and
+//! isn't covered.
+//!
+//! `Board` tells the models apart, from the revision code in
+//! `/proc/cpuinfo`.
+
+use crate::soc::cpuinfo_revision;
+use crate::{check_pin, Error, Pin, Soc, Unconfigured, GPIO};
+
+use std::fmt::{self, Display};
+
+// As in `Soc::from_revision`.
+const REVISION_NEW_STYLE: u32 = 1 << 23;
+
+
+/// What a header pin is wired to.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum HeaderPin {
+    /// The BCM GPIO number.
+    Gpio(u32),
+    Power3V3,
+    Power5V,
+    Ground,
+}
+
+use HeaderPin::{Ground, Gpio, Power3V3, Power5V};
+
+const HEADER: [HeaderPin; 40] = [
+    Power3V3, Power5V,
+    Gpio(2), Power5V,
+    Gpio(3), Ground,
+    Gpio(4), Gpio(14),
+    Ground, Gpio(15),
+    Gpio(17), Gpio(18),
+    Gpio(27), Ground,
+    Gpio(22), Gpio(23),
+    Power3V3, Gpio(24),
+    Gpio(10), Ground,
+    Gpio(9), Gpio(25),
+    Gpio(11), Gpio(8),
+    Ground, Gpio(7),
+    Gpio(0), Gpio(1),
+    Gpio(5), Ground,
+    Gpio(6), Gpio(12),
+    Gpio(13), Ground,
+    Gpio(19), Gpio(16),
+    Gpio(26), Gpio(20),
+    Ground, Gpio(21),
+];
+
+
+/// BCM numbers of the header pins' usual functions, for passing to
+/// `GPIO::pin` and friends.
+pub mod pins {
+    /// The HAT ID EEPROM's I2C bus (I2C0), reserved for the firmware.
+    pub const ID_SD: u32 = 0;
+    pub const ID_SC: u32 = 1;
+    /// I2C1, with 1.8 kΩ pull-ups on the board.
+    pub const SDA1: u32 = 2;
+    pub const SCL1: u32 = 3;
+    pub const GPCLK0: u32 = 4;
+    pub const GPCLK1: u32 = 5;
+    pub const GPCLK2: u32 = 6;
+    /// SPI0.
+    pub const CE1: u32 = 7;
+    pub const CE0: u32 = 8;
+    pub const MISO: u32 = 9;
+    pub const MOSI: u32 = 10;
+    pub const SCLK: u32 = 11;
+    /// The primary UART.
+    pub const TXD: u32 = 14;
+    pub const RXD: u32 = 15;
+    /// PWM channels 0 and 1 on their usual pins, shared with PCM.
+    pub const PWM0: u32 = 18;
+    pub const PWM1: u32 = 19;
+    pub const PCM_CLK: u32 = 18;
+    pub const PCM_FS: u32 = 19;
+    pub const PCM_DIN: u32 = 20;
+    pub const PCM_DOUT: u32 = 21;
+    /// SPI1, the auxiliary SPI.
+    pub const SPI1_CE2: u32 = 16;
+    pub const SPI1_CE1: u32 = 17;
+    pub const SPI1_CE0: u32 = 18;
+    pub const SPI1_MISO: u32 = 19;
+    pub const SPI1_MOSI: u32 = 20;
+    pub const SPI1_SCLK: u32 = 21;
+}
+
+
+/// What header pin `physical` (1-40) is wired to.
+pub fn header_pin(physical: u32) -> Result<HeaderPin, Error> {
+    physical.checked_sub(1)
+        .and_then(|index| HEADER.get(index as usize))
+        .copied()
+        .ok_or_else(|| Error::Other(format!("there is no header pin {}; they're numbered 1 to 40", physical)))
+}
+
+/// The BCM GPIO number of header pin `physical`. Fails for power and
+/// ground pins.
+pub fn bcm_from_physical(physical: u32) -> Result<u32, Error> {
+    match header_pin(physical)? {
+        Gpio(bcm) => Ok(bcm),
+        other => Err(Error::Other(format!("header pin {} is {:?}, not a GPIO", physical, other))),
+    }
+}
+
+/// The header pin GPIO `bcm` comes out on, or `None` if it isn't on the
+/// header.
+pub fn physical_from_bcm(bcm: u32) -> Option<u32> {
+    HEADER.iter().position(|&pin| pin == Gpio(bcm)).map(|index| index as u32 + 1)
+}
+
+
+/// A Raspberry Pi model, as its revision code names it.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum BoardModel {
+    A,
+    B,
+    APlus,
+    BPlus,
+    Pi2B,
+    Pi3B,
+    Pi3BPlus,
+    Pi3APlus,
+    Pi4B,
+    Pi400,
+    Pi5,
+    Pi500,
+    Zero,
+    ZeroW,
+    Zero2W,
+    Cm1,
+    Cm3,
+    Cm3Plus,
+    Cm4,
+    Cm4S,
+    Cm5,
+    /// A revision code this crate doesn't know, or none at all.
+    Unknown,
+}
+
+impl BoardModel {
+
+    // The type field of new-style codes.
+    fn from_type(board_type: u32) -> BoardModel {
+        match board_type {
+            0x00 => BoardModel::A,
+            0x01 => BoardModel::B,
+            0x02 => BoardModel::APlus,
+            0x03 => BoardModel::BPlus,
+            0x04 => BoardModel::Pi2B,
+            0x06 => BoardModel::Cm1,
+            0x08 => BoardModel::Pi3B,
+            0x09 => BoardModel::Zero,
+            0x0a => BoardModel::Cm3,
+            0x0c => BoardModel::ZeroW,
+            0x0d => BoardModel::Pi3BPlus,
+            0x0e => BoardModel::Pi3APlus,
+            0x10 => BoardModel::Cm3Plus,
+            0x11 => BoardModel::Pi4B,
+            0x12 => BoardModel::Zero2W,
+            0x13 => BoardModel::Pi400,
+            0x14 => BoardModel::Cm4,
+            0x15 => BoardModel::Cm4S,
+            0x17 => BoardModel::Pi5,
+            0x18 => BoardModel::Cm5,
+            0x19 => BoardModel::Pi500,
+            _ => BoardModel::Unknown,
+        }
+    }
+
+    // Old-style codes, all BCM2835 boards, were a plain sequence.
+    fn from_old_revision(revision: u32) -> BoardModel {
+        match revision & 0xffff {
+            0x02..=0x06 | 0x0d..=0x0f => BoardModel::B,
+            0x07..=0x09 => BoardModel::A,
+            0x10 | 0x13 => BoardModel::BPlus,
+            0x11 | 0x14 => BoardModel::Cm1,
+            0x12 | 0x15 => BoardModel::APlus,
+            _ => BoardModel::Unknown,
+        }
+    }
+
+    pub fn is_compute_module(self) -> bool {
+        matches!(self, BoardModel::Cm1 | BoardModel::Cm3 | BoardModel::Cm3Plus | BoardModel::Cm4
+            | BoardModel::Cm4S | BoardModel::Cm5)
+    }
+}
+
+impl Display for BoardModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BoardModel::A => "Raspberry Pi Model A",
+            BoardModel::B => "Raspberry Pi Model B",
+            BoardModel::APlus => "Raspberry Pi Model A+",
+            BoardModel::BPlus => "Raspberry Pi Model B+",
+            BoardModel::Pi2B => "Raspberry Pi 2 Model B",
+            BoardModel::Pi3B => "Raspberry Pi 3 Model B",
+            BoardModel::Pi3BPlus => "Raspberry Pi 3 Model B+",
+            BoardModel::Pi3APlus => "Raspberry Pi 3 Model A+",
+            BoardModel::Pi4B => "Raspberry Pi 4 Model B",
+            BoardModel::Pi400 => "Raspberry Pi 400",
+            BoardModel::Pi5 => "Raspberry Pi 5",
+            BoardModel::Pi500 => "Raspberry Pi 500",
+            BoardModel::Zero => "Raspberry Pi Zero",
+            BoardModel::ZeroW => "Raspberry Pi Zero W",
+            BoardModel::Zero2W => "Raspberry Pi Zero 2 W",
+            BoardModel::Cm1 => "Raspberry Pi Compute Module",
+            BoardModel::Cm3 => "Raspberry Pi Compute Module 3",
+            BoardModel::Cm3Plus => "Raspberry Pi Compute Module 3+",
+            BoardModel::Cm4 => "Raspberry Pi Compute Module 4",
+            BoardModel::Cm4S => "Raspberry Pi Compute Module 4S",
+            BoardModel::Cm5 => "Raspberry Pi Compute Module 5",
+            BoardModel::Unknown => "unknown board",
+        };
+        f.write_str(name)
+    }
+}
+
+
+/// The board a `GPIO` runs on: its model and SoC, and what follows from
+/// them.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Board {
+    model: BoardModel,
+    soc: Soc,
+    revision: Option<u32>,
+}
+
+impl Board {
+
+    /// Decodes a revision code, as `/proc/cpuinfo` gives it.
+    pub fn from_revision(revision: u32) -> Option<Board> {
+        let model = match revision & REVISION_NEW_STYLE {
+            0 => BoardModel::from_old_revision(revision),
+            _ => BoardModel::from_type((revision >> 4) & 0xff),
+        };
+        Some(Board { model, soc: Soc::from_revision(revision)?, revision: Some(revision) })
+    }
+
+    /// The board described by `/proc/cpuinfo`, if it has a revision code.
+    pub fn detect() -> Option<Board> {
+        Board::from_revision(cpuinfo_revision()?)
+    }
+
+    /// A board of unknown model built around `soc`.
+    pub fn unknown(soc: Soc) -> Board {
+        Board { model: BoardModel::Unknown, soc, revision: None }
+    }
+
+    pub fn model(&self) -> BoardModel {
+        self.model
+    }
+
+    pub fn soc(&self) -> Soc {
+        self.soc
+    }
+
+    pub fn revision(&self) -> Option<u32> {
+        self.revision
+    }
+
+    pub fn gpio_count(&self) -> u32 {
+        self.soc.gpio_count()
+    }
+
+    /// The pin count of the GPIO header: 26 on the original Model A and B,
+    /// none on compute modules, and 40 otherwise.
+    pub fn header_pins(&self) -> u32 {
+        match self.model {
+            BoardModel::A | BoardModel::B => 26,
+            model if model.is_compute_module() => 0,
+            _ => 40,
+        }
+    }
+
+    /// Whether pulls can be read back, rather than only set through the
+    /// GPPUD/GPPUDCLK sequence. See `Soc::has_pull_control_registers`.
+    pub fn has_readable_pulls(&self) -> bool {
+        self.soc.has_pull_control_registers() || self.soc == Soc::Bcm2712
+    }
+
+    /// Whether the GPIO registers are the BCM block every Pi before the 5
+    /// has, which register-level features such as edge detection,
+    /// snapshots and square waves need.
+    pub fn has_bcm_gpio_block(&self) -> bool {
+        self.soc != Soc::Bcm2712
+    }
+}
+
+
+impl GPIO {
+
+    /// The board, from `/proc/cpuinfo` where it names one with this
+    /// `GPIO`'s SoC, otherwise `Board::unknown`.
+    pub fn board(&self) -> Board {
+        *self.board.get_or_init(|| {
+            Board::detect()
+                .filter(|board| board.soc == self.soc)
+                .unwrap_or_else(|| Board::unknown(self.soc))
+        })
+    }
+
+    // Pins past the SoC's last GPIO are rejected as invalid, rather than
+    // reaching registers that don't exist.
+    pub(crate) fn check_board_pin(&self, pin: u32) -> Result<(), Error> {
+        check_pin(pin)?;
+        if pin >= self.soc.gpio_count() {
+            return Err(Error::InvalidPin { pin, register: None });
+        }
+        Ok(())
+    }
+
+    // Every pin `check_board_pin` accepts.
+    pub(crate) fn board_pins(&self) -> std::ops::Range<u32> {
+        0..self.soc.gpio_count()
+    }
+}
+
+
+impl<'a> Pin<'a, Unconfigured> {
+
+    /// A handle to the GPIO on header pin `physical`, e.g. 12 for GPIO 18.
+    pub fn physical(gpio: &'a GPIO, physical: u32) -> Result<Self, Error> {
+        gpio.pin(bcm_from_physical(physical)?)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_numbering() {
+        assert_eq!(bcm_from_physical(12).ok(), Some(pins::PWM0));
+        assert_eq!(bcm_from_physical(3).ok(), Some(pins::SDA1));
+        assert_eq!(bcm_from_physical(19).ok(), Some(pins::MOSI));
+        assert_eq!(header_pin(6).ok(), Some(HeaderPin::Ground));
+        assert!(bcm_from_physical(1).is_err());
+        assert!(header_pin(0).is_err() && header_pin(41).is_err());
+        assert_eq!(physical_from_bcm(18), Some(12));
+        assert_eq!(physical_from_bcm(28), None);
+
+        // Every GPIO from 0 to 27 is on the header exactly once.
+        let mut gpios: Vec<u32> = (1..=40).filter_map(|physical| bcm_from_physical(physical).ok()).collect();
+        gpios.sort();
+        assert_eq!(gpios, (0..28).collect::<Vec<_>>());
+        assert!((0..28).all(|bcm| bcm_from_physical(physical_from_bcm(bcm).unwrap()).ok() == Some(bcm)));
+    }
+
+    #[test]
+    fn test_board_from_revision() {
+        let pi4 = Board::from_revision(0xc03114).unwrap();
+        assert_eq!((pi4.model(), pi4.soc(), pi4.revision()), (BoardModel::Pi4B, Soc::Bcm2711, Some(0xc03114)));
+        assert_eq!(pi4.gpio_count(), 58);
+        assert!(pi4.has_readable_pulls() && pi4.has_bcm_gpio_block());
+        assert_eq!(pi4.model().to_string(), "Raspberry Pi 4 Model B");
+
+        let zero2 = Board::from_revision(0x902120).unwrap();
+        assert_eq!((zero2.model(), zero2.soc()), (BoardModel::Zero2W, Soc::Bcm2837));
+        assert_eq!((zero2.gpio_count(), zero2.header_pins()), (54, 40));
+        assert!(!zero2.has_readable_pulls());
+        assert_eq!(Board::from_revision(0xc03130).map(|board| board.model()), Some(BoardModel::Pi400));
+        assert_eq!(Board::from_revision(0xa22082).map(|board| board.model()), Some(BoardModel::Pi3B));
+        assert_eq!(Board::from_revision(0x9000c1).map(|board| board.model()), Some(BoardModel::ZeroW));
+        assert_eq!(Board::from_revision(0xa01041).map(|board| board.model()), Some(BoardModel::Pi2B));
+
+        let pi5 = Board::from_revision(0xd04170).unwrap();
+        assert_eq!(pi5.model(), BoardModel::Pi5);
+        assert!(pi5.has_readable_pulls() && !pi5.has_bcm_gpio_block());
+
+        // Old-style codes
+        let b = Board::from_revision(0x000e).unwrap();
+        assert_eq!((b.model(), b.soc(), b.header_pins()), (BoardModel::B, Soc::Bcm2835, 26));
+        assert_eq!(Board::from_revision(0x0010).map(|board| board.model()), Some(BoardModel::BPlus));
+        let cm4 = Board::from_revision(0xb03140).unwrap();
+        assert_eq!((cm4.model(), cm4.header_pins()), (BoardModel::Cm4, 0));
+        assert_eq!(Board::from_revision(0xd05170), None);
+    }
+
+    #[test]
+    fn test_pins_checked_against_soc() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        assert_eq!(gpio.board().soc(), Soc::Bcm2711);
+        gpio.check_board_pin(57).unwrap();
+
+        let legacy = GPIO::from_backend(
+            Box::new(crate::backend::RegisterBackend::owned(Vec::new(), Soc::Bcm2837)), Soc::Bcm2837);
+        assert!(matches!(legacy.get_function(55), Err(Error::InvalidPin { pin: 55, register: None })));
+        assert!(legacy.set_high(54).is_err());
+        assert!(legacy.pin(56).is_err());
+        assert!(matches!(legacy.read(58), Err(Error::InvalidPin { pin: 58, .. })));
+        assert!(legacy.read(53).is_ok());
+    }
+
+    #[test]
+    fn test_physical_pin() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let pin = Pin::physical(&gpio, 40).unwrap();
+        assert_eq!(pin.number(), 21);
+        assert!(matches!(gpio.pin(21), Err(Error::PinInUse(21))));
+        assert!(Pin::physical(&gpio, 2).is_err());
+    }
+}
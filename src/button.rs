@@ -0,0 +1,398 @@
+use crate::{CallbackId, EdgeKind, Error, Input, Pin, PinEvent, Pull, Trigger, GPIO};
+
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+
+/// What a button did.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ButtonAction {
+    Pressed,
+    Released,
+    /// Still down the hold time after being pressed, which it's held for.
+    /// Reported once per press, before the release.
+    Held(Duration),
+    /// Pressed again within the double-click window of a short press's
+    /// release. Follows that press's `Pressed`.
+    DoubleClick,
+}
+
+/// A button action and when it happened. A `Held`'s timestamp is when the
+/// hold time ran out.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ButtonEvent {
+    pub action: ButtonAction,
+    pub timestamp: Instant,
+}
+
+type ButtonCallback = Box<dyn FnMut(ButtonEvent) + Send>;
+
+
+// Turns presses and releases into button events. A press that reaches the
+// hold time isn't a click, so can't start a double-click.
+struct Gestures {
+    hold_time: Duration,
+    double_click_window: Duration,
+    pressed_at: Option<Instant>,
+    held: bool,
+    // The release of the last short press, while it can still start a
+    // double-click.
+    clicked_at: Option<Instant>,
+}
+
+impl Gestures {
+    fn new(hold_time: Duration, double_click_window: Duration) -> Self {
+        Self { hold_time, double_click_window, pressed_at: None, held: false, clicked_at: None }
+    }
+
+    // Repeats of the state the button is already in, e.g. around an edge
+    // the debounce swallowed, are ignored.
+    fn edge(&mut self, pressed: bool, at: Instant) -> Vec<ButtonEvent> {
+        let event = |action| ButtonEvent { action, timestamp: at };
+        match (pressed, self.pressed_at) {
+            (true, None) => {
+                self.pressed_at = Some(at);
+                self.held = false;
+                let mut events = vec![event(ButtonAction::Pressed)];
+                let window = self.double_click_window;
+                if self.clicked_at.take().is_some_and(|clicked| at.saturating_duration_since(clicked) <= window) {
+                    events.push(event(ButtonAction::DoubleClick));
+                }
+                events
+            }
+            (false, Some(_)) => {
+                self.pressed_at = None;
+                self.clicked_at = (!self.held).then_some(at);
+                vec![event(ButtonAction::Released)]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    // When a `Held` is next due, if one is.
+    fn deadline(&self) -> Option<Instant> {
+        self.pressed_at.filter(|_| !self.held).map(|pressed| pressed + self.hold_time)
+    }
+
+    fn tick(&mut self, now: Instant) -> Option<ButtonEvent> {
+        let deadline = self.deadline().filter(|&deadline| deadline <= now)?;
+        self.held = true;
+        Some(ButtonEvent { action: ButtonAction::Held(self.hold_time), timestamp: deadline })
+    }
+}
+
+
+// What the edge callback and hold timers share. `generation` moves on with
+// every press, release and removal, so a timer left over from one of them
+// does nothing.
+struct Shared {
+    gestures: Gestures,
+    callback: ButtonCallback,
+    generation: u64,
+}
+
+impl Shared {
+    fn edge(shared: &Arc<Mutex<Shared>>, pressed: bool, at: Instant) {
+        let mut state = shared.lock().unwrap();
+        let state = &mut *state;
+        for event in state.gestures.edge(pressed, at) {
+            (state.callback)(event);
+        }
+        state.generation += 1;
+        if let Some(deadline) = state.gestures.deadline() {
+            let (shared, generation) = (Arc::clone(shared), state.generation);
+            std::thread::spawn(move || {
+                std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+                let mut state = shared.lock().unwrap();
+                if state.generation == generation {
+                    if let Some(event) = state.gestures.tick(Instant::now()) {
+                        (state.callback)(event);
+                    }
+                }
+            });
+        }
+    }
+}
+
+// The installed edge callback, removed when dropped.
+struct Handler<'a> {
+    gpio: &'a GPIO,
+    id: CallbackId,
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl<'a> Drop for Handler<'a> {
+    fn drop(&mut self) {
+        let _ = self.gpio.remove_callback(self.id);
+        self.shared.lock().unwrap().generation += 1;
+    }
+}
+
+
+/// A push button on an input pin, with the pin's internal pull resistor
+/// holding it at its released level and software debounce applied to its
+/// edges. Presses, releases, holds and double-clicks can be delivered to a
+/// callback, a channel or, with the `async` feature, a stream.
+pub struct Button<'a> {
+    pin: Pin<'a, Input>,
+    active_low: bool,
+    hold_time: Duration,
+    double_click_window: Duration,
+    handler: Option<Handler<'a>>,
+}
+
+impl<'a> Button<'a> {
+
+    pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(50);
+    pub const DEFAULT_HOLD_TIME: Duration = Duration::from_secs(1);
+    pub const DEFAULT_DOUBLE_CLICK_WINDOW: Duration = Duration::from_millis(300);
+
+    /// A button wired between the pin and ground, released high by the
+    /// pull-up.
+    pub fn pull_up(pin: Pin<'a, Input>) -> Result<Self, Error> {
+        Self::new(pin, Pull::Up, true)
+    }
+
+    /// A button wired between the pin and 3V3, released low by the
+    /// pull-down.
+    pub fn pull_down(pin: Pin<'a, Input>) -> Result<Self, Error> {
+        Self::new(pin, Pull::Down, false)
+    }
+
+    fn new(pin: Pin<'a, Input>, pull: Pull, active_low: bool) -> Result<Self, Error> {
+        pin.set_pull(pull)?;
+        pin.set_debounce(Self::DEFAULT_DEBOUNCE)?;
+        Ok(Self {
+            pin,
+            active_low,
+            hold_time: Self::DEFAULT_HOLD_TIME,
+            double_click_window: Self::DEFAULT_DOUBLE_CLICK_WINDOW,
+            handler: None,
+        })
+    }
+
+    pub fn set_debounce(&self, period: Duration) -> Result<(), Error> {
+        self.pin.set_debounce(period)
+    }
+
+    pub fn is_pressed(&self) -> Result<bool, Error> {
+        Ok(self.pin.read()? != self.active_low)
+    }
+
+    fn press_trigger(&self) -> Trigger {
+        if self.active_low { Trigger::Falling } else { Trigger::Rising }
+    }
+
+    fn wait_for(&self, trigger: Trigger, timeout: Option<Duration>) -> Result<Instant, Error> {
+        Ok(self.pin.wait_for_edge(trigger, timeout)?.timestamp)
+    }
+
+    /// Blocks until the button is pressed and returns when.
+    pub fn wait_for_press(&self, timeout: Option<Duration>) -> Result<Instant, Error> {
+        self.wait_for(self.press_trigger(), timeout)
+    }
+
+    /// Blocks until the button is released and returns when.
+    pub fn wait_for_release(&self, timeout: Option<Duration>) -> Result<Instant, Error> {
+        let release = match self.press_trigger() {
+            Trigger::Falling => Trigger::Rising,
+            _ => Trigger::Falling,
+        };
+        self.wait_for(release, timeout)
+    }
+
+    pub fn hold_time(&self) -> Duration {
+        self.hold_time
+    }
+
+    /// Sets how long the button must stay down for a `Held`, taking effect
+    /// from the next press.
+    pub fn set_hold_time(&mut self, hold_time: Duration) {
+        self.hold_time = hold_time;
+        if let Some(handler) = &self.handler {
+            handler.shared.lock().unwrap().gestures.hold_time = hold_time;
+        }
+    }
+
+    pub fn double_click_window(&self) -> Duration {
+        self.double_click_window
+    }
+
+    /// Sets how soon after a short press's release a second press has to
+    /// come to be a `DoubleClick`.
+    pub fn set_double_click_window(&mut self, window: Duration) {
+        self.double_click_window = window;
+        if let Some(handler) = &self.handler {
+            handler.shared.lock().unwrap().gestures.double_click_window = window;
+        }
+    }
+
+    /// Calls `callback` with each of the button's events: presses and
+    /// releases from the `GPIO`'s edge callback thread, `Held`s from a
+    /// timer thread started with each press. Replaces any callback, channel
+    /// or stream already receiving them. While events are being delivered,
+    /// the `wait_for_*` methods can't be used.
+    pub fn on_event(&mut self, callback: impl FnMut(ButtonEvent) + Send + 'static) -> Result<(), Error> {
+        self.remove_handler();
+        let gpio = self.pin.gpio();
+        let shared = Arc::new(Mutex::new(Shared {
+            gestures: Gestures::new(self.hold_time, self.double_click_window),
+            callback: Box::new(callback),
+            generation: 0,
+        }));
+        let active_low = self.active_low;
+        let edges = Arc::clone(&shared);
+        let id = gpio.on_edge(self.pin.number(), Trigger::Both, move |event: PinEvent| {
+            Shared::edge(&edges, (event.edge == EdgeKind::Falling) == active_low, event.timestamp);
+        })?;
+        self.handler = Some(Handler { gpio, id, shared });
+        Ok(())
+    }
+
+    /// The button's events on a channel, as `on_event` delivers them. The
+    /// channel closes once they go elsewhere or the button is dropped.
+    pub fn events(&mut self) -> Result<mpsc::Receiver<ButtonEvent>, Error> {
+        let (sender, receiver) = mpsc::channel();
+        self.on_event(move |event| {
+            let _ = sender.send(event);
+        })?;
+        Ok(receiver)
+    }
+
+    /// The button's events as a `Stream`, as `on_event` delivers them. The
+    /// stream ends once they go elsewhere or the button is dropped.
+    #[cfg(feature = "async")]
+    pub fn event_stream(&mut self) -> Result<ButtonEvents, Error> {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        self.on_event(move |event| {
+            let _ = sender.send(event);
+        })?;
+        Ok(ButtonEvents { receiver })
+    }
+
+    /// Stops delivering events. Returns whether they were being delivered.
+    pub fn remove_handler(&mut self) -> bool {
+        self.handler.take().is_some()
+    }
+
+    pub fn into_pin(self) -> Pin<'a, Input> {
+        self.pin
+    }
+}
+
+
+/// A button's events as a `Stream`, from `Button::event_stream`.
+#[cfg(feature = "async")]
+pub struct ButtonEvents {
+    receiver: tokio::sync::mpsc::UnboundedReceiver<ButtonEvent>,
+}
+
+#[cfg(feature = "async")]
+impl ButtonEvents {
+
+    /// Waits for the next event, or `None` once the stream has ended.
+    pub async fn next_event(&mut self) -> Option<ButtonEvent> {
+        self.receiver.recv().await
+    }
+}
+
+#[cfg(feature = "async")]
+impl futures_core::Stream for ButtonEvents {
+    type Item = ButtonEvent;
+
+    fn poll_next(mut self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>)
+        -> std::task::Poll<Option<ButtonEvent>>
+    {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GPIO;
+
+    #[test]
+    fn test_pull_up_button() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let button = Button::pull_up(gpio.pin(27).unwrap().into_input().unwrap()).unwrap();
+        assert_eq!(gpio.get_pull(27).ok(), Some(Pull::Up));
+        assert_eq!(gpio.debounce(27).ok(), Some(Button::DEFAULT_DEBOUNCE));
+        assert_eq!(button.is_pressed().ok(), Some(true));
+
+        unsafe { gpio.write_raw(0x34, 1 << 27) };
+        assert_eq!(button.is_pressed().ok(), Some(false));
+
+        let pressed = std::thread::scope(|scope| {
+            scope.spawn(|| {
+                std::thread::sleep(Duration::from_millis(20));
+                unsafe {
+                    gpio.write_raw(0x34, 0);
+                    gpio.write_raw(0x40, 1 << 27);
+                }
+            });
+            button.wait_for_press(Some(Duration::from_secs(5)))
+        });
+        assert!(pressed.is_ok());
+        assert_eq!(gpio.read_register(crate::Register::GPFEN, 27).ok(), Some(0));
+
+        let button = Button::pull_down(button.into_pin()).unwrap();
+        assert_eq!(gpio.get_pull(27).ok(), Some(Pull::Down));
+        assert_eq!(button.is_pressed().ok(), Some(false));
+    }
+
+    #[test]
+    fn test_gestures() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let actions = |events: Vec<ButtonEvent>| events.iter().map(|event| event.action).collect::<Vec<_>>();
+        let mut gestures = Gestures::new(Duration::from_millis(500), Duration::from_millis(200));
+
+        assert_eq!(actions(gestures.edge(true, at(0))), [ButtonAction::Pressed]);
+        assert!(gestures.edge(true, at(10)).is_empty());
+        assert_eq!(gestures.deadline(), Some(at(500)));
+        assert_eq!(gestures.tick(at(100)), None);
+        assert_eq!(actions(gestures.edge(false, at(100))), [ButtonAction::Released]);
+        assert_eq!(gestures.deadline(), None);
+
+        // A second press within the window, then a third that isn't one.
+        assert_eq!(actions(gestures.edge(true, at(250))), [ButtonAction::Pressed, ButtonAction::DoubleClick]);
+        gestures.edge(false, at(300));
+        assert_eq!(actions(gestures.edge(true, at(600))), [ButtonAction::Pressed]);
+
+        let held = gestures.tick(at(1200)).unwrap();
+        assert_eq!(held, ButtonEvent { action: ButtonAction::Held(Duration::from_millis(500)), timestamp: at(1100) });
+        assert_eq!((gestures.tick(at(1300)), gestures.deadline()), (None, None));
+        // A long press can't start a double-click.
+        gestures.edge(false, at(1400));
+        assert_eq!(actions(gestures.edge(true, at(1450))), [ButtonAction::Pressed]);
+        assert!(gestures.edge(false, at(1460)).len() == 1 && gestures.edge(false, at(1470)).is_empty());
+    }
+
+    #[test]
+    fn test_button_events() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let mut button = Button::pull_up(gpio.pin(22).unwrap().into_input().unwrap()).unwrap();
+        button.set_debounce(Duration::ZERO).unwrap();
+        button.set_hold_time(Duration::from_millis(100));
+        assert_eq!(button.double_click_window(), Button::DEFAULT_DOUBLE_CLICK_WINDOW);
+        let events = button.events().unwrap();
+        assert_eq!(gpio.read_register(crate::Register::GPFEN, 22).ok(), Some(1 << 22));
+
+        let edge = |level: u32| unsafe {
+            gpio.write_raw(0x34, level << 22);
+            gpio.write_raw(0x40, 1 << 22);
+        };
+        let next = || events.recv_timeout(Duration::from_secs(5)).map(|event| event.action).ok();
+        edge(0);
+        assert_eq!(next(), Some(ButtonAction::Pressed));
+        assert_eq!(next(), Some(ButtonAction::Held(Duration::from_millis(100))));
+        edge(1);
+        assert_eq!(next(), Some(ButtonAction::Released));
+
+        assert!(button.remove_handler());
+        assert!(events.recv_timeout(Duration::from_secs(5)).is_err());
+        assert_eq!(gpio.read_register(crate::Register::GPFEN, 22).ok(), Some(0));
+    }
+}
@@ -0,0 +1,191 @@
+use crate::pwm_output::PwmOutput;
+use crate::{Clock, ClockManager, ClockSource, Error, HwPwm, PinFunction, PwmChannel, SquareWaveHandle, GPIO};
+
+use std::time::Duration;
+
+
+enum ToneOutput<'a> {
+    Pwm(PwmOutput<'a>),
+    Clock(ClockManager, Clock),
+    SquareWave { gpio: &'a GPIO, pin: u32, wave: Option<SquareWaveHandle<'a>> },
+}
+
+
+/// A piezo buzzer or speaker driven with a square wave. Tones come from
+/// the hardware PWM controller or a general-purpose clock where the pin
+/// has one and `/dev/mem` can be mapped, and from a software-toggled pin
+/// otherwise. The buzzer is silenced when dropped.
+pub struct Buzzer<'a> {
+    output: ToneOutput<'a>,
+    frequency_hz: Option<f64>,
+}
+
+impl<'a> Buzzer<'a> {
+
+    pub fn new(gpio: &'a GPIO, pin: u32) -> Result<Self, Error> {
+        if let Some(Ok(pwm)) = PwmChannel::for_pin(pin).map(HwPwm::new) {
+            pwm.route(gpio, pin)?;
+            return Self::pwm(pwm);
+        }
+        if let Some(clock) = Clock::for_pin(pin) {
+            if let Ok(clocks) = ClockManager::new() {
+                clocks.route(gpio, clock, pin)?;
+                return Ok(Self::clock(clocks, clock));
+            }
+        }
+        Self::software(gpio, pin)
+    }
+
+    /// A buzzer on a hardware PWM channel that is already routed to its
+    /// pin.
+    pub fn pwm(pwm: HwPwm) -> Result<Self, Error> {
+        // The frequency is replaced by the first tone.
+        let output = PwmOutput::hardware(pwm, 440.0)?;
+        Ok(Self { output: ToneOutput::Pwm(output), frequency_hz: None })
+    }
+
+    /// A buzzer on a general-purpose clock that is already routed to its
+    /// pin. Clocks are divided down from the oscillator, so only tones
+    /// above a few kHz can be reached.
+    pub fn clock(clocks: ClockManager, clock: Clock) -> Self {
+        Self { output: ToneOutput::Clock(clocks, clock), frequency_hz: None }
+    }
+
+    /// A buzzer on `pin`, which is made an output and toggled from a
+    /// background thread while a tone plays.
+    pub fn software(gpio: &'a GPIO, pin: u32) -> Result<Self, Error> {
+        gpio.set_function(pin, PinFunction::Output)?;
+        gpio.set_low(pin)?;
+        Ok(Self { output: ToneOutput::SquareWave { gpio, pin, wave: None }, frequency_hz: None })
+    }
+
+    /// Starts a continuous tone, replacing any tone already playing.
+    pub fn tone(&mut self, freq_hz: f64) -> Result<(), Error> {
+        if !(freq_hz.is_finite() && freq_hz > 0.0) {
+            return Err(Error::Other(format!("tone frequency must be positive, not {}", freq_hz)));
+        }
+        match &mut self.output {
+            ToneOutput::Pwm(pwm) => {
+                pwm.set_frequency(freq_hz)?;
+                pwm.set_duty_cycle(0.5)?;
+            }
+            ToneOutput::Clock(clocks, clock) => {
+                clocks.set_frequency(*clock, ClockSource::Oscillator, freq_hz)?;
+            }
+            ToneOutput::SquareWave { gpio, pin, wave } => match wave {
+                Some(wave) => wave.set_frequency(freq_hz),
+                None => *wave = Some(gpio.square_wave(*pin, freq_hz)?),
+            },
+        }
+        self.frequency_hz = Some(freq_hz);
+        Ok(())
+    }
+
+    pub fn stop(&mut self) -> Result<(), Error> {
+        match &mut self.output {
+            ToneOutput::Pwm(pwm) => pwm.set_duty_cycle(0.0)?,
+            ToneOutput::Clock(clocks, clock) => clocks.disable(*clock)?,
+            // Dropping the handle stops the thread and leaves the pin low.
+            ToneOutput::SquareWave { wave, .. } => drop(wave.take()),
+        }
+        self.frequency_hz = None;
+        Ok(())
+    }
+
+    /// The frequency of the tone playing, if any.
+    pub fn frequency(&self) -> Option<f64> {
+        self.frequency_hz
+    }
+
+    /// Plays `freq_hz` for `duration`, then falls silent.
+    pub fn play_tone(&mut self, freq_hz: f64, duration: Duration) -> Result<(), Error> {
+        self.tone(freq_hz)?;
+        std::thread::sleep(duration);
+        self.stop()
+    }
+
+    /// Plays each `(frequency, milliseconds)` note in turn. A frequency of
+    /// 0 is a rest.
+    pub fn play(&mut self, notes: &[(f64, u64)]) -> Result<(), Error> {
+        for &(freq_hz, ms) in notes {
+            if freq_hz == 0.0 {
+                self.stop()?;
+                std::thread::sleep(Duration::from_millis(ms));
+            } else {
+                self.play_tone(freq_hz, Duration::from_millis(ms))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Drop for Buzzer<'a> {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Soc;
+
+    fn block_file(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("rustberrypi-buzzer-{}-{}", name, std::process::id()));
+        std::fs::write(&path, vec![0u8; 4096]).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_software_buzzer() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let mut buzzer = Buzzer::software(&gpio, 26).unwrap();
+        assert_eq!(gpio.get_function(26).ok(), Some(PinFunction::Output));
+        buzzer.tone(1000.0).unwrap();
+        assert_eq!(buzzer.frequency(), Some(1000.0));
+        buzzer.tone(2000.0).unwrap();
+        assert!(buzzer.tone(0.0).is_err());
+        buzzer.stop().unwrap();
+        assert_eq!(buzzer.frequency(), None);
+
+        buzzer.play(&[(440.0, 2), (0.0, 1), (880.0, 2)]).unwrap();
+        assert_eq!(buzzer.frequency(), None);
+    }
+
+    #[test]
+    fn test_pwm_buzzer() {
+        let path = block_file("pwm");
+        let pwm = HwPwm::open(&path, 0, PwmChannel::Pwm1).ok().unwrap();
+        let mut buzzer = Buzzer::pwm(pwm).unwrap();
+        buzzer.tone(1000.0).unwrap();
+        if let ToneOutput::Pwm(pwm) = &buzzer.output {
+            assert_eq!(pwm.duty_cycle(), 0.5);
+        }
+        buzzer.stop().unwrap();
+        if let ToneOutput::Pwm(pwm) = &buzzer.output {
+            assert_eq!(pwm.duty_cycle(), 0.0);
+        }
+        drop(buzzer);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_clock_buzzer() {
+        let path = block_file("clock");
+        let clocks = ClockManager::open(&path, 0, Soc::Bcm2837).ok().unwrap();
+        let mut buzzer = Buzzer::clock(clocks, Clock::Gp0);
+        buzzer.tone(9600.0).unwrap();
+        if let ToneOutput::Clock(clocks, _) = &buzzer.output {
+            assert!(clocks.is_enabled(Clock::Gp0).unwrap());
+        }
+        // Below what the oscillator can be divided down to.
+        assert!(buzzer.tone(440.0).is_err());
+        buzzer.stop().unwrap();
+        if let ToneOutput::Clock(clocks, _) = &buzzer.output {
+            assert!(!clocks.is_enabled(Clock::Gp0).unwrap());
+        }
+        drop(buzzer);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,150 @@
+use crate::board::physical_from_bcm;
+use crate::{alt_functions, bank_of, Error, PinFunction, Soc, GPIO};
+
+
+/// A kind of peripheral a pin can be switched to through its alternate
+/// functions.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Interface {
+    /// A hardware PWM channel.
+    Pwm,
+    I2c,
+    Spi,
+    Uart,
+    /// A general-purpose clock output.
+    Clock,
+}
+
+impl Interface {
+
+    /// Whether the alternate function signal `name` belongs to this kind
+    /// of peripheral, e.g. `"SDA1"` to I2C. The I2C/SPI slave's signals
+    /// don't count as either.
+    pub fn carries(self, name: &str) -> bool {
+        let numbered = |prefix: &str| {
+            name.strip_prefix(prefix).is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_digit()))
+        };
+        match self {
+            Interface::Pwm => numbered("PWM"),
+            Interface::I2c => numbered("SDA") || numbered("SCL"),
+            Interface::Spi => numbered("SPI"),
+            Interface::Uart => ["TXD", "RXD", "CTS", "RTS"].iter().any(|&prefix| numbered(prefix)),
+            Interface::Clock => numbered("GPCLK"),
+        }
+    }
+}
+
+
+/// What a pin can do on its SoC, from the datasheet's alternate function
+/// table, for tools and config checks that would otherwise hard-code it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PinCapabilities {
+    pin: u32,
+    physical: Option<u32>,
+    alt_functions: Vec<(PinFunction, &'static str)>,
+}
+
+impl PinCapabilities {
+
+    /// The capabilities of GPIO `pin` on `soc`. `header_pins` is how many
+    /// pins the board's header has, e.g. from `Board::header_pins`.
+    pub fn new(soc: Soc, pin: u32, header_pins: u32) -> Self {
+        Self {
+            pin,
+            physical: physical_from_bcm(pin).filter(|&physical| physical <= header_pins),
+            alt_functions: alt_functions(soc, pin),
+        }
+    }
+
+    pub fn pin(&self) -> u32 {
+        self.pin
+    }
+
+    /// The register bank holding the pin's bits: 0 for GPIOs 0-31, 1 for
+    /// the rest.
+    pub fn bank(&self) -> u32 {
+        bank_of(self.pin)
+    }
+
+    /// The header pin the GPIO comes out on, or `None` if it isn't on the
+    /// board's header.
+    pub fn physical(&self) -> Option<u32> {
+        self.physical
+    }
+
+    /// Every alternate function with the signal it carries. Empty on the
+    /// Pi 5, whose functions aren't described.
+    pub fn alt_functions(&self) -> &[(PinFunction, &'static str)] {
+        &self.alt_functions
+    }
+
+    /// The alternate functions that switch the pin to `interface`, e.g.
+    /// `[(Alt0, "PWM0_0")]` for GPIO 12 and `Interface::Pwm` on a BCM2711.
+    pub fn functions_for(&self, interface: Interface) -> Vec<(PinFunction, &'static str)> {
+        self.alt_functions.iter().copied().filter(|&(_, name)| interface.carries(name)).collect()
+    }
+
+    pub fn supports(&self, interface: Interface) -> bool {
+        self.alt_functions.iter().any(|&(_, name)| interface.carries(name))
+    }
+
+    pub fn is_pwm_capable(&self) -> bool {
+        self.supports(Interface::Pwm)
+    }
+}
+
+
+impl GPIO {
+
+    /// The capabilities of each of the SoC's GPIOs, lowest first.
+    pub fn pins(&self) -> impl Iterator<Item = PinCapabilities> {
+        let (soc, header_pins) = (self.soc, self.board().header_pins());
+        (0..soc.gpio_count()).map(move |pin| PinCapabilities::new(soc, pin, header_pins))
+    }
+
+    pub fn capabilities(&self, pin: u32) -> Result<PinCapabilities, Error> {
+        self.check_board_pin(pin)?;
+        Ok(PinCapabilities::new(self.soc, pin, self.board().header_pins()))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interfaces() {
+        assert!(Interface::Pwm.carries("PWM0_1") && Interface::Pwm.carries("PWM1"));
+        assert!(Interface::I2c.carries("SCL6") && !Interface::I2c.carries("I2CSL_SCL_SCLK"));
+        assert!(Interface::Spi.carries("SPI0_CE0_N") && !Interface::Spi.carries("SD1"));
+        assert!(Interface::Uart.carries("CTS0") && !Interface::Uart.carries("TE0"));
+        assert!(Interface::Clock.carries("GPCLK2") && !Interface::Clock.carries("PCM_CLK"));
+    }
+
+    #[test]
+    fn test_pin_capabilities() {
+        let pwm = PinCapabilities::new(Soc::Bcm2711, 12, 40);
+        assert!(pwm.is_pwm_capable());
+        assert_eq!(pwm.functions_for(Interface::Pwm), vec![(PinFunction::Alt0, "PWM0_0")]);
+        assert_eq!(pwm.functions_for(Interface::Uart), vec![(PinFunction::Alt4, "TXD5")]);
+        assert_eq!((pwm.bank(), pwm.physical()), (0, Some(32)));
+
+        let old = PinCapabilities::new(Soc::Bcm2835, 2, 26);
+        assert_eq!(old.functions_for(Interface::I2c), vec![(PinFunction::Alt0, "SDA1")]);
+        assert!(!old.supports(Interface::Uart));
+        assert_eq!(PinCapabilities::new(Soc::Bcm2835, 12, 26).physical(), None);
+        assert_eq!(PinCapabilities::new(Soc::Bcm2711, 40, 40).bank(), 1);
+        assert!(PinCapabilities::new(Soc::Bcm2712, 12, 40).alt_functions().is_empty());
+    }
+
+    #[test]
+    fn test_gpio_pins() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let pins: Vec<_> = gpio.pins().collect();
+        assert_eq!(pins.len() as u32, gpio.soc.gpio_count());
+        assert!(pins.iter().enumerate().all(|(index, capabilities)| capabilities.pin() == index as u32));
+        assert_eq!(gpio.capabilities(18).ok().as_ref(), pins.get(18));
+        assert!(gpio.capabilities(58).is_err());
+    }
+}
@@ -0,0 +1,143 @@
+use crate::Error;
+
+use nix::fcntl::OFlag;
+use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use std::convert::TryFrom;
+use std::os::raw::c_int;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+
+type CleanupAction = Box<dyn FnMut() + Send>;
+
+// The cleanup actions, in the order they were added.
+static ACTIONS: Mutex<Vec<(CleanupId, CleanupAction)>> = Mutex::new(Vec::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+// The write end of the pipe the signal handler wakes the cleanup thread
+// through, or -1 until `install_signal_cleanup` has run.
+static SIGNAL_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+const CLEANUP_SIGNALS: [Signal; 2] = [Signal::SIGINT, Signal::SIGTERM];
+
+
+/// Identifies an action added with `add_cleanup`.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct CleanupId(u64);
+
+
+/// Adds an action for `run_cleanup` to run, e.g. restoring a
+/// `PinSnapshot`, driving a motor enable pin low or stopping a `SoftPwm`.
+/// Actions run at most once, the most recently added first. The action
+/// usually holds the `GPIO` it acts on through an `Arc`.
+pub fn add_cleanup(action: impl FnMut() + Send + 'static) -> CleanupId {
+    let id = CleanupId(NEXT_ID.fetch_add(1, Ordering::SeqCst));
+    ACTIONS.lock().unwrap_or_else(|e| e.into_inner()).push((id, Box::new(action)));
+    id
+}
+
+/// Removes an action that hasn't run yet, e.g. once the hardware it
+/// protects has been shut down normally. Returns `false` if it had already
+/// run or been removed.
+pub fn remove_cleanup(id: CleanupId) -> bool {
+    let mut actions = ACTIONS.lock().unwrap_or_else(|e| e.into_inner());
+    let count = actions.len();
+    actions.retain(|&(action_id, _)| action_id != id);
+    actions.len() != count
+}
+
+/// Runs and removes every cleanup action, the most recently added first.
+/// The signal handler calls this; call it from error paths that should
+/// leave the hardware as safely as an interrupt would. A panicking action
+/// doesn't stop the others.
+pub fn run_cleanup() {
+    let actions = std::mem::take(&mut *ACTIONS.lock().unwrap_or_else(|e| e.into_inner()));
+    for (_, mut action) in actions.into_iter().rev() {
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(&mut action));
+    }
+}
+
+/// Runs the cleanup actions when the process gets SIGINT or SIGTERM, so a
+/// script stopped with Ctrl-C doesn't leave motors spinning, then lets the
+/// signal end the process as it would have. The actions run on a thread of
+/// their own rather than in the signal handler, so they can lock and
+/// allocate. Replaces any handler already installed for the two signals;
+/// calling it again does nothing.
+pub fn install_signal_cleanup() -> Result<(), Error> {
+    // Held while installing, so two threads can't both create the pipe.
+    let _actions = ACTIONS.lock().unwrap_or_else(|e| e.into_inner());
+    if SIGNAL_PIPE.load(Ordering::SeqCst) >= 0 {
+        return Ok(());
+    }
+    let (read, write) = nix::unistd::pipe2(OFlag::O_CLOEXEC)
+        .map_err(|e| Error::from_nix("failed to create the signal cleanup pipe", e))?;
+    std::thread::Builder::new()
+        .name("rustberrypi-cleanup".to_string())
+        .spawn(move || wait_for_signal(read))
+        .map_err(|e| Error::from_io("failed to start the signal cleanup thread", e))?;
+    SIGNAL_PIPE.store(write, Ordering::SeqCst);
+
+    let action = SigAction::new(SigHandler::Handler(on_signal), SaFlags::SA_RESTART, SigSet::empty());
+    for &signal in CLEANUP_SIGNALS.iter() {
+        unsafe { signal::sigaction(signal, &action) }
+            .map_err(|e| Error::from_nix(format!("failed to install a {} handler", signal), e))?;
+    }
+    Ok(())
+}
+
+// Only async-signal-safe calls are allowed here, so all it does is pass
+// the signal on to the cleanup thread.
+extern "C" fn on_signal(signal: c_int) {
+    let byte = signal as u8;
+    unsafe { nix::libc::write(SIGNAL_PIPE.load(Ordering::SeqCst), &byte as *const u8 as *const _, 1) };
+}
+
+fn wait_for_signal(read: RawFd) {
+    let mut byte = [0u8];
+    loop {
+        match nix::unistd::read(read, &mut byte) {
+            Ok(1) => break,
+            Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+            _ => return,
+        }
+    }
+    run_cleanup();
+    // Put the default disposition back and raise the signal again, so the
+    // process exits with the status the signal would have given it.
+    if let Ok(signal) = Signal::try_from(byte[0] as c_int) {
+        let default = SigAction::new(SigHandler::SigDfl, SaFlags::empty(), SigSet::empty());
+        unsafe {
+            let _ = signal::sigaction(signal, &default);
+        }
+        let _ = signal::raise(signal);
+    }
+    std::process::exit(128 + byte[0] as i32);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_cleanup_actions() {
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let push = |name: &'static str| {
+            let order = Arc::clone(&order);
+            move || order.lock().unwrap().push(name)
+        };
+        add_cleanup(push("stop pwm"));
+        let removed = add_cleanup(push("removed"));
+        add_cleanup(|| panic!("a failing action"));
+        add_cleanup(push("motor off"));
+        assert!(remove_cleanup(removed));
+        assert!(!remove_cleanup(removed));
+
+        run_cleanup();
+        assert_eq!(*order.lock().unwrap(), vec!["motor off", "stop pwm"]);
+        run_cleanup();
+        assert_eq!(order.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,261 @@
+use crate::region::MappedRegion;
+use crate::{detect_peripheral_base, Error, PinFunction, Soc, GPIO, DEV_MEM_PATH};
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+
+const CM_BASE_OFFSET: i64 = 0x101000;
+const CM_BLOCK_LEN: usize = 0xa8;
+
+// Every write to a clock manager register must carry this in its top byte
+// or it is ignored.
+const CM_PASSWORD: u32 = 0x5a << 24;
+
+const CTL_SRC_MASK: u32 = 0xf;
+const CTL_ENAB: u32 = 1 << 4;
+const CTL_BUSY: u32 = 1 << 7;
+const CTL_MASH_SHIFT: u32 = 9;
+
+const DIV_INTEGER_SHIFT: u32 = 12;
+const DIV_INTEGER_MAX: u32 = 0xfff;
+const DIV_FRACTION_MAX: u32 = 0xfff;
+
+// How long a clock may stay busy after being disabled.
+const BUSY_TIMEOUT: Duration = Duration::from_millis(10);
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Clock {
+    Gp0,
+    Gp1,
+    Gp2,
+    /// The clock feeding the PCM/I2S controller's bit clock.
+    Pcm,
+    /// The clock feeding the PWM controller.
+    Pwm,
+}
+
+impl Clock {
+
+    fn ctl_register(self) -> usize {
+        match self {
+            Clock::Gp0 => 0x70,
+            Clock::Gp1 => 0x78,
+            Clock::Gp2 => 0x80,
+            Clock::Pcm => 0x98,
+            Clock::Pwm => 0xa0,
+        }
+    }
+
+    fn div_register(self) -> usize {
+        self.ctl_register() + 4
+    }
+
+    /// The pins this clock can be output on, with the alternate function
+    /// that does it. The PCM and PWM clocks have no pin of their own.
+    pub fn pins(self) -> &'static [(u32, PinFunction)] {
+        match self {
+            Clock::Gp0 => &[(4, PinFunction::Alt0), (20, PinFunction::Alt5), (32, PinFunction::Alt0), (34, PinFunction::Alt0)],
+            Clock::Gp1 => &[(5, PinFunction::Alt0), (21, PinFunction::Alt5), (42, PinFunction::Alt0), (44, PinFunction::Alt0)],
+            Clock::Gp2 => &[(6, PinFunction::Alt0), (43, PinFunction::Alt0)],
+            Clock::Pcm | Clock::Pwm => &[],
+        }
+    }
+
+    /// The general-purpose clock that can be output on `pin`, if any.
+    pub fn for_pin(pin: u32) -> Option<Clock> {
+        [Clock::Gp0, Clock::Gp1, Clock::Gp2].iter().copied()
+            .find(|clock| clock.pins().iter().any(|&(candidate, _)| candidate == pin))
+    }
+}
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum ClockSource {
+    Oscillator = 1,
+    PllA = 4,
+    PllC = 5,
+    PllD = 6,
+    Hdmi = 7,
+}
+
+impl ClockSource {
+
+    /// The source's nominal rate, where it is fixed. PLLA, PLLC and the
+    /// HDMI clock are reprogrammed by the firmware, so have no fixed rate.
+    pub fn frequency_hz(self, soc: Soc) -> Option<u32> {
+        match (self, soc) {
+            (ClockSource::Oscillator, Soc::Bcm2711) => Some(54_000_000),
+            (ClockSource::Oscillator, _) => Some(19_200_000),
+            (ClockSource::PllD, Soc::Bcm2711) => Some(750_000_000),
+            (ClockSource::PllD, _) => Some(500_000_000),
+            _ => None,
+        }
+    }
+}
+
+
+/// Noise shaping of fractional divisors. Each stage spreads the output
+/// over more source cycles and needs a larger integer divisor.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Mash {
+    /// The fractional part of the divisor is ignored.
+    Integer = 0,
+    Stage1 = 1,
+    Stage2 = 2,
+    Stage3 = 3,
+}
+
+impl Mash {
+
+    fn min_integer_divisor(self) -> u32 {
+        match self {
+            Mash::Integer => 1,
+            Mash::Stage1 => 2,
+            Mash::Stage2 => 3,
+            Mash::Stage3 => 5,
+        }
+    }
+}
+
+
+/// The general-purpose, PCM and PWM clocks of the clock manager.
+pub struct ClockManager {
+    region: MappedRegion,
+    soc: Soc,
+}
+
+impl ClockManager {
+
+    /// Maps the clock manager through `/dev/mem`.
+    pub fn new() -> Result<Self, Error> {
+        let base = detect_peripheral_base()?;
+        let soc = Soc::detect(Some(base)).unwrap_or(Soc::Bcm2711);
+        Self::open(DEV_MEM_PATH, base + CM_BASE_OFFSET, soc)
+    }
+
+    pub(crate) fn open(path: impl AsRef<Path>, phys_base: i64, soc: Soc) -> Result<Self, Error> {
+        Ok(Self { region: MappedRegion::open(path.as_ref(), phys_base, CM_BLOCK_LEN)?, soc })
+    }
+
+    pub fn soc(&self) -> Soc {
+        self.soc
+    }
+
+    pub fn is_enabled(&self, clock: Clock) -> Result<bool, Error> {
+        Ok(self.region.read_reg(clock.ctl_register())? & CTL_ENAB != 0)
+    }
+
+    pub fn is_busy(&self, clock: Clock) -> Result<bool, Error> {
+        Ok(self.region.read_reg(clock.ctl_register())? & CTL_BUSY != 0)
+    }
+
+    /// Stops `clock` and waits for it to finish its current cycle.
+    pub fn disable(&self, clock: Clock) -> Result<(), Error> {
+        let ctl = self.region.read_reg(clock.ctl_register())?;
+        self.region.write_reg(clock.ctl_register(), CM_PASSWORD | (ctl & !CTL_ENAB & 0xffffff))?;
+        let deadline = Instant::now() + BUSY_TIMEOUT;
+        while self.is_busy(clock)? {
+            if Instant::now() >= deadline {
+                return Err(Error::Timeout(format!("{:?} clock still busy after {:?}", clock, BUSY_TIMEOUT)));
+            }
+            std::thread::yield_now();
+        }
+        Ok(())
+    }
+
+    /// Stops `clock`, then restarts it from `source` divided by
+    /// `integer + fraction / 4096`. The divisor can't be changed while
+    /// the clock runs, hence the stop.
+    pub fn configure(&self, clock: Clock, source: ClockSource, integer: u32, fraction: u32, mash: Mash)
+        -> Result<(), Error>
+    {
+        if integer < mash.min_integer_divisor() || integer > DIV_INTEGER_MAX || fraction > DIV_FRACTION_MAX {
+            return Err(Error::Other(format!(
+                "divisor {} + {}/4096 is out of range for {:?}", integer, fraction, mash)));
+        }
+        self.disable(clock)?;
+        self.region.write_reg(clock.div_register(), CM_PASSWORD | integer << DIV_INTEGER_SHIFT | fraction)?;
+        let ctl = CM_PASSWORD | (mash as u32) << CTL_MASH_SHIFT | (source as u32 & CTL_SRC_MASK);
+        self.region.write_reg(clock.ctl_register(), ctl)?;
+        self.region.write_reg(clock.ctl_register(), ctl | CTL_ENAB)
+    }
+
+    /// Runs `clock` as close to `freq_hz` as `source` allows, using a
+    /// first-stage MASH fractional divisor, and returns the rate achieved.
+    pub fn set_frequency(&self, clock: Clock, source: ClockSource, freq_hz: f64) -> Result<f64, Error> {
+        let source_hz = source.frequency_hz(self.soc).ok_or_else(
+            || Error::Unsupported(format!("{:?} has no fixed rate to divide", source)))?;
+        let divisor = source_hz as f64 / freq_hz;
+        if !divisor.is_finite() || divisor < Mash::Stage1.min_integer_divisor() as f64
+            || divisor >= (DIV_INTEGER_MAX + 1) as f64
+        {
+            return Err(Error::Other(format!("{} Hz is out of reach of {:?}", freq_hz, source)));
+        }
+        let integer = divisor.trunc() as u32;
+        let fraction = (((divisor - integer as f64) * 4096.0).round() as u32).min(DIV_FRACTION_MAX);
+        self.configure(clock, source, integer, fraction, Mash::Stage1)?;
+        Ok(source_hz as f64 / (integer as f64 + fraction as f64 / 4096.0))
+    }
+
+    /// Switches `pin` to the alternate function that outputs `clock`.
+    pub fn route(&self, gpio: &GPIO, clock: Clock, pin: u32) -> Result<(), Error> {
+        match clock.pins().iter().find(|&&(candidate, _)| candidate == pin) {
+            Some(&(_, function)) => gpio.set_function(pin, function),
+            None => Err(Error::Other(format!("{:?} clock can't be routed to pin {}", clock, pin))),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block_file(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("rustberrypi-clock-{}-{}", name, std::process::id()));
+        std::fs::write(&path, vec![0u8; 4096]).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_configure_clock() {
+        let path = block_file("configure");
+        let clocks = ClockManager::open(&path, 0, Soc::Bcm2837).ok().unwrap();
+        clocks.configure(Clock::Gp0, ClockSource::PllD, 500, 0, Mash::Integer).unwrap();
+        assert_eq!(clocks.region.read_reg(0x74).ok(), Some(0x5a << 24 | 500 << 12));
+        assert_eq!(clocks.region.read_reg(0x70).ok(), Some(0x5a << 24 | CTL_ENAB | 6));
+        assert!(clocks.is_enabled(Clock::Gp0).unwrap());
+
+        let achieved = clocks.set_frequency(Clock::Pwm, ClockSource::Oscillator, 7_680_000.0).unwrap();
+        assert_eq!(achieved, 7_680_000.0);
+        assert_eq!(clocks.region.read_reg(0xa4).ok(), Some(0x5a << 24 | 2 << 12 | 2048));
+        assert_eq!(clocks.region.read_reg(0xa0).ok(), Some(0x5a << 24 | 1 << 9 | CTL_ENAB | 1));
+
+        clocks.disable(Clock::Gp0).unwrap();
+        assert!(!clocks.is_enabled(Clock::Gp0).unwrap());
+        assert!(clocks.configure(Clock::Gp1, ClockSource::PllD, 4, 0, Mash::Stage3).is_err());
+        assert!(clocks.set_frequency(Clock::Gp1, ClockSource::PllC, 1_000.0).is_err());
+        drop(clocks);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_busy_clock_times_out() {
+        let path = block_file("busy");
+        let clocks = ClockManager::open(&path, 0, Soc::Bcm2711).ok().unwrap();
+        // Writes go through to the file, so BUSY stays set.
+        clocks.region.write_reg(0x78, CTL_BUSY).unwrap();
+        assert!(matches!(clocks.disable(Clock::Gp1), Err(Error::Timeout(_))));
+
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        assert_eq!(Clock::for_pin(21), Some(Clock::Gp1));
+        assert_eq!(Clock::for_pin(7), None);
+        clocks.route(&gpio, Clock::Gp2, 6).unwrap();
+        assert_eq!(gpio.get_function(6).ok(), Some(PinFunction::Alt0));
+        assert!(clocks.route(&gpio, Clock::Pwm, 6).is_err());
+        drop(clocks);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
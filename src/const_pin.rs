@@ -0,0 +1,187 @@
+use crate::registers::GPIO_PIN_COUNT;
+use crate::{bit_in_bank, DropPolicy, Error, Input, Output, Pin, Register, RegisterMap, Unconfigured, GPIO};
+
+
+// The base of the `GPIO`'s register block, for a pin on a fast path.
+#[derive(Copy, Clone)]
+struct Block(*mut u32);
+
+// The block is the `GPIO`'s, which the handle borrows, and only reached
+// through volatile word accesses.
+unsafe impl Send for Block {}
+unsafe impl Sync for Block {}
+
+
+/// A `Pin` whose number is part of its type, e.g. `ConstPin<'_, 17,
+/// Output>`, so the words and bit it touches are worked out at compile
+/// time. Numbers the GPIO block doesn't have fail to compile:
+///
+/// ```compile_fail
+/// let gpio = rustberrypi::GPIO::new()?;
+/// let pin = gpio.const_pin::<58>()?;
+/// # Ok::<(), rustberrypi::Error>(())
+/// ```
+///
+/// Whether the board has the pin is still checked when it's claimed.
+/// Everything a `Pin` offers is reached through `as_pin`.
+pub struct ConstPin<'a, const N: u32, Mode = Unconfigured> {
+    pin: Pin<'a, Mode>,
+    block: Option<Block>,
+}
+
+impl<'a, const N: u32, Mode> ConstPin<'a, N, Mode> {
+
+    const VALID: () = assert!(N < GPIO_PIN_COUNT, "the GPIO block has pins 0 to 57");
+
+    pub const NUMBER: u32 = N;
+
+    /// The pin's bit in its words of GPSET, GPCLR, GPLEV and the event
+    /// registers.
+    pub const MASK: u32 = 1 << bit_in_bank(N);
+
+    const SET: usize = Register::GPSET.offset_of(N);
+    const CLEAR: usize = Register::GPCLR.offset_of(N);
+    const LEVEL: usize = Register::GPLEV.offset_of(N);
+
+    fn with_pin<NewMode>(pin: Pin<'a, NewMode>) -> ConstPin<'a, N, NewMode> {
+        ConstPin { pin, block: None }
+    }
+
+    pub fn as_pin(&self) -> &Pin<'a, Mode> {
+        &self.pin
+    }
+
+    /// Gives up the compile-time number for a `Pin`, keeping the claim
+    /// and drop policy.
+    pub fn into_pin(self) -> Pin<'a, Mode> {
+        self.pin
+    }
+
+    /// See `Pin::on_drop`.
+    pub fn on_drop(self, policy: DropPolicy) -> Result<Self, Error> {
+        Ok(Self { pin: self.pin.on_drop(policy)?, block: self.block })
+    }
+
+    pub fn into_input(self) -> Result<ConstPin<'a, N, Input>, Error> {
+        Ok(Self::with_pin(self.pin.into_input()?))
+    }
+
+    pub fn into_output(self) -> Result<ConstPin<'a, N, Output>, Error> {
+        Ok(Self::with_pin(self.pin.into_output()?))
+    }
+
+    pub fn into_alt<const A: u8>(self) -> Result<ConstPin<'a, N, crate::Alt<A>>, Error> {
+        Ok(Self::with_pin(self.pin.into_alt::<A>()?))
+    }
+
+    pub fn read(&self) -> Result<bool, Error> {
+        self.pin.gpio().read(N)
+    }
+}
+
+impl<'a, const N: u32> ConstPin<'a, N, Output> {
+
+    /// As `Pin::fast_path`, with the same limits. With the datasheet
+    /// register layout, `set_high` and `set_low` are then each one store
+    /// to an address fixed at compile time; with another layout they fall
+    /// back to `Pin`'s fast path.
+    pub fn fast_path(self) -> Result<Self, Error> {
+        let pin = self.pin.fast_path()?;
+        let gpio = pin.gpio();
+        let block = (gpio.register_map == RegisterMap::default()).then_some(Block(gpio.buffer as *mut u32));
+        Ok(Self { pin, block })
+    }
+
+    #[inline]
+    pub fn set_high(&self) -> Result<(), Error> {
+        match self.block {
+            Some(block) => {
+                unsafe { block.0.wrapping_byte_add(Self::SET).write_volatile(Self::MASK) };
+                Ok(())
+            }
+            None => self.pin.set_high(),
+        }
+    }
+
+    #[inline]
+    pub fn set_low(&self) -> Result<(), Error> {
+        match self.block {
+            Some(block) => {
+                unsafe { block.0.wrapping_byte_add(Self::CLEAR).write_volatile(Self::MASK) };
+                Ok(())
+            }
+            None => self.pin.set_low(),
+        }
+    }
+
+    pub fn toggle(&self) -> Result<(), Error> {
+        match self.block {
+            Some(block) => {
+                let level = unsafe { block.0.wrapping_byte_add(Self::LEVEL).read_volatile() };
+                let offset = if level & Self::MASK != 0 { Self::CLEAR } else { Self::SET };
+                unsafe { block.0.wrapping_byte_add(offset).write_volatile(Self::MASK) };
+                Ok(())
+            }
+            None => self.pin.toggle(),
+        }
+    }
+
+    /// See `Pin::output_state`.
+    pub fn output_state(&self) -> Option<bool> {
+        self.pin.output_state()
+    }
+}
+
+
+impl GPIO {
+
+    /// A handle to pin `N`, as `pin` gives for a number known at run time.
+    /// `N` must be below 58, or the call fails to compile.
+    pub fn const_pin<const N: u32>(&self) -> Result<ConstPin<'_, N>, Error> {
+        #[allow(clippy::let_unit_value)]
+        let () = ConstPin::<N>::VALID;
+        Ok(ConstPin { pin: self.pin(N)?, block: None })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PinFunction;
+
+    #[test]
+    fn test_const_pin_offsets() {
+        assert_eq!(ConstPin::<40>::MASK, 1 << 8);
+        assert_eq!(ConstPin::<40>::SET, 0x20);
+        assert_eq!(ConstPin::<40>::CLEAR, 0x2c);
+        assert_eq!(ConstPin::<5>::LEVEL, 0x34);
+        assert_eq!(ConstPin::<17, Output>::NUMBER, 17);
+    }
+
+    #[test]
+    fn test_const_pin() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let clock = gpio.const_pin::<40>().unwrap().into_output().unwrap();
+        assert!(matches!(gpio.pin(40).err(), Some(Error::PinInUse(40))));
+        assert_eq!(gpio.get_function(40).ok(), Some(PinFunction::Output));
+        clock.set_high().unwrap();
+        assert_eq!(clock.output_state(), Some(true));
+
+        let clock = clock.fast_path().unwrap();
+        clock.set_low().unwrap();
+        assert_eq!(unsafe { gpio.read_raw(0x2c) }, 1 << 8);
+        unsafe {
+            gpio.write_raw(0x38, 1 << 8);
+            gpio.write_raw(0x2c, 0);
+        }
+        clock.toggle().unwrap();
+        assert_eq!(unsafe { gpio.read_raw(0x2c) }, 1 << 8);
+        assert!(clock.read().unwrap());
+
+        let clock = clock.into_input().unwrap();
+        assert_eq!(clock.as_pin().number(), 40);
+        drop(clock.into_pin());
+        assert!(gpio.pin(40).is_ok());
+    }
+}
@@ -0,0 +1,134 @@
+use crate::{CallbackId, Error, Input, Pin, Trigger, GPIO};
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+
+struct Counts {
+    total: u64,
+    window: Duration,
+    // When each edge within the window came.
+    recent: VecDeque<Instant>,
+}
+
+impl Counts {
+    fn edge(&mut self, timestamp: Instant) {
+        self.total = self.total.wrapping_add(1);
+        self.recent.push_back(timestamp);
+        self.prune(timestamp);
+    }
+
+    fn prune(&mut self, now: Instant) {
+        while let Some(&oldest) = self.recent.front() {
+            if now.saturating_duration_since(oldest) <= self.window {
+                break;
+            }
+            self.recent.pop_front();
+        }
+    }
+
+    fn rate(&mut self, now: Instant) -> f64 {
+        self.prune(now);
+        self.recent.len() as f64 / self.window.as_secs_f64()
+    }
+}
+
+
+/// Counts edges on an input pin from `GPIO::on_edge` callbacks, for
+/// anemometers, energy meter pulse outputs and the like. Besides the
+/// running total it gives the rate over a sliding window.
+///
+/// Register backends only check for edges once a millisecond and count at
+/// most one per pin in that time, so faster signals need a line backend.
+pub struct Counter<'a> {
+    gpio: &'a GPIO,
+    pin: Pin<'a, Input>,
+    counts: Arc<Mutex<Counts>>,
+    callback: CallbackId,
+}
+
+impl<'a> Counter<'a> {
+
+    pub const DEFAULT_WINDOW: Duration = Duration::from_secs(1);
+
+    pub fn new(pin: Pin<'a, Input>, trigger: Trigger) -> Result<Self, Error> {
+        let counts = Arc::new(Mutex::new(Counts { total: 0, window: Self::DEFAULT_WINDOW, recent: VecDeque::new() }));
+        let gpio = pin.gpio();
+        let callback = {
+            let counts = Arc::clone(&counts);
+            gpio.on_edge(pin.number(), trigger, move |event| counts.lock().unwrap().edge(event.timestamp))?
+        };
+        Ok(Self { gpio, pin, counts, callback })
+    }
+
+    pub fn pin(&self) -> u32 {
+        self.pin.number()
+    }
+
+    /// The edges counted since the counter was set up or last reset. It
+    /// wraps around to 0 after `u64::MAX`.
+    pub fn count(&self) -> u64 {
+        self.counts.lock().unwrap().total
+    }
+
+    /// Sets the count back to 0 and returns what it was. The rate isn't
+    /// affected.
+    pub fn reset(&self) -> u64 {
+        std::mem::take(&mut self.counts.lock().unwrap().total)
+    }
+
+    /// The span `rate` averages over.
+    pub fn set_window(&self, window: Duration) -> Result<(), Error> {
+        if window.is_zero() {
+            return Err(Error::Other("the rate window can't be empty".to_string()));
+        }
+        self.counts.lock().unwrap().window = window;
+        Ok(())
+    }
+
+    /// Edges per second over the last window.
+    pub fn rate(&self) -> f64 {
+        self.counts.lock().unwrap().rate(Instant::now())
+    }
+}
+
+impl<'a> Drop for Counter<'a> {
+    fn drop(&mut self) {
+        let _ = self.gpio.remove_callback(self.callback);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_and_rate() {
+        let start = Instant::now();
+        let mut counts = Counts { total: u64::MAX - 1, window: Duration::from_millis(500), recent: VecDeque::new() };
+        for ms in (0..1000).step_by(100) {
+            counts.edge(start + Duration::from_millis(ms));
+        }
+        assert_eq!(counts.total, 8);
+        // The edges at 500 to 900 ms are within the window at 1 s.
+        assert!((counts.rate(start + Duration::from_secs(1)) - 10.0).abs() < 1e-9);
+        assert_eq!(counts.rate(start + Duration::from_secs(5)), 0.0);
+    }
+
+    #[test]
+    fn test_counter() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let counter = Counter::new(gpio.pin(26).unwrap().into_input().unwrap(), Trigger::Rising).unwrap();
+        counter.counts.lock().unwrap().edge(Instant::now());
+        counter.counts.lock().unwrap().edge(Instant::now());
+        assert_eq!(counter.count(), 2);
+        assert!(counter.rate() > 0.0);
+        assert!(counter.set_window(Duration::ZERO).is_err());
+        assert_eq!(counter.reset(), 2);
+        assert_eq!(counter.count(), 0);
+        drop(counter);
+        assert!(gpio.on_edge(26, Trigger::Both, |_| {}).is_ok());
+    }
+}
@@ -0,0 +1,332 @@
+use crate::pwm_output::PwmOutput;
+use crate::{Error, GpioPin, Output, Pin, GPIO};
+
+use std::time::{Duration, Instant};
+
+
+// How often a ramp updates the speed.
+const RAMP_STEP: Duration = Duration::from_millis(10);
+
+
+fn check_speed(speed: f64) -> Result<(), Error> {
+    if !(0.0..=1.0).contains(&speed) {
+        return Err(Error::Other(format!("motor speed {} is outside 0 to 1", speed)));
+    }
+    Ok(())
+}
+
+
+// How the bridge's inputs are wired.
+enum Bridge<'a> {
+    // L298N style: the inputs set the direction and the enable is PWMed.
+    Enable { in1: Pin<'a, Output>, in2: Pin<'a, Output>, enable: PwmOutput<'a> },
+    // DRV8833 style: one input is PWMed while the other is held low.
+    Inputs { in1: PwmOutput<'a>, in2: PwmOutput<'a> },
+}
+
+impl<'a> Bridge<'a> {
+    // Drives at `speed`, from -1 for full reverse to 1 for full forward.
+    fn drive(&mut self, speed: f64) -> Result<(), Error> {
+        match self {
+            Bridge::Enable { in1, in2, enable } => {
+                in1.set(speed > 0.0)?;
+                in2.set(speed < 0.0)?;
+                enable.set_duty_cycle(speed.abs())
+            }
+            Bridge::Inputs { in1, in2 } => {
+                in1.set_duty_cycle(speed.max(0.0))?;
+                in2.set_duty_cycle((-speed).max(0.0))
+            }
+        }
+    }
+
+    // Shorts the motor's terminals together, stopping it quickly.
+    fn brake(&mut self) -> Result<(), Error> {
+        match self {
+            Bridge::Enable { in1, in2, enable } => {
+                in1.set_high()?;
+                in2.set_high()?;
+                enable.set_duty_cycle(1.0)
+            }
+            Bridge::Inputs { in1, in2 } => {
+                in1.set_duty_cycle(1.0)?;
+                in2.set_duty_cycle(1.0)
+            }
+        }
+    }
+
+    // Leaves the motor's terminals floating, so it spins down freely.
+    fn coast(&mut self) -> Result<(), Error> {
+        match self {
+            Bridge::Enable { in1, in2, enable } => {
+                enable.set_duty_cycle(0.0)?;
+                in1.set_low()?;
+                in2.set_low()
+            }
+            Bridge::Inputs { in1, in2 } => {
+                in1.set_duty_cycle(0.0)?;
+                in2.set_duty_cycle(0.0)
+            }
+        }
+    }
+}
+
+
+/// A brushed DC motor on an H-bridge, such as one channel of an L298N or
+/// DRV8833. Speeds run from 0 to 1, each way. With an acceleration set,
+/// speed changes ramp from the calling thread and return once the new
+/// speed is reached; `brake` and `coast` always act at once. PWM is on the
+/// hardware controller where the pin has a channel and software otherwise.
+/// The motor is left coasting when dropped.
+pub struct DcMotor<'a> {
+    bridge: Bridge<'a>,
+    speed: f64,
+    acceleration: f64,
+}
+
+impl<'a> DcMotor<'a> {
+
+    /// Within the range of both bridges, and low enough for software PWM.
+    pub const DEFAULT_FREQUENCY_HZ: f64 = 1000.0;
+
+    /// A motor on a bridge with direction inputs `in1` and `in2` and a
+    /// PWM enable, as the L298N has. Forward drives `in1` high.
+    pub fn new(gpio: &'a GPIO, in1: u32, in2: u32, enable: u32) -> Result<Self, Error> {
+        let bridge = Bridge::Enable {
+            in1: gpio.pin(in1)?.into_output()?,
+            in2: gpio.pin(in2)?.into_output()?,
+            enable: PwmOutput::open(gpio, enable, Self::DEFAULT_FREQUENCY_HZ)?,
+        };
+        Self::with_bridge(bridge)
+    }
+
+    /// A motor on a bridge with only the two inputs, both PWMed, as the
+    /// DRV8833 has. Forward PWMs `in1`, and the motor coasts through the
+    /// off part of each period.
+    pub fn two_pin(gpio: &'a GPIO, in1: u32, in2: u32) -> Result<Self, Error> {
+        let bridge = Bridge::Inputs {
+            in1: PwmOutput::open(gpio, in1, Self::DEFAULT_FREQUENCY_HZ)?,
+            in2: PwmOutput::open(gpio, in2, Self::DEFAULT_FREQUENCY_HZ)?,
+        };
+        Self::with_bridge(bridge)
+    }
+
+    fn with_bridge(mut bridge: Bridge<'a>) -> Result<Self, Error> {
+        bridge.coast()?;
+        Ok(Self { bridge, speed: 0.0, acceleration: 0.0 })
+    }
+
+    /// How fast speed may change, in full speeds per second. 0, the
+    /// default, changes it at once.
+    pub fn set_acceleration(&mut self, per_sec: f64) -> Result<(), Error> {
+        if !(per_sec.is_finite() && per_sec >= 0.0) {
+            return Err(Error::Other(format!("motor acceleration can't be {}", per_sec)));
+        }
+        self.acceleration = per_sec;
+        Ok(())
+    }
+
+    pub fn acceleration(&self) -> f64 {
+        self.acceleration
+    }
+
+    /// The speed being driven, negative in reverse. 0 while braked or
+    /// coasting.
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    pub fn forward(&mut self, speed: f64) -> Result<(), Error> {
+        check_speed(speed)?;
+        self.set_speed(speed)
+    }
+
+    pub fn reverse(&mut self, speed: f64) -> Result<(), Error> {
+        check_speed(speed)?;
+        self.set_speed(-speed)
+    }
+
+    /// Drives at `speed`, from -1 for full reverse to 1 for full forward,
+    /// ramping to it if an acceleration is set.
+    pub fn set_speed(&mut self, speed: f64) -> Result<(), Error> {
+        check_speed(speed.abs())?;
+        ramp(&mut [(self, speed)])
+    }
+
+    // Moves the speed towards `target` by at most `step`, returning
+    // whether it got there.
+    fn step_towards(&mut self, target: f64, step: f64) -> Result<bool, Error> {
+        let speed = if self.acceleration == 0.0 || (target - self.speed).abs() <= step {
+            target
+        } else {
+            self.speed + step.copysign(target - self.speed)
+        };
+        self.bridge.drive(speed)?;
+        self.speed = speed;
+        Ok(speed == target)
+    }
+
+    pub fn brake(&mut self) -> Result<(), Error> {
+        self.bridge.brake()?;
+        self.speed = 0.0;
+        Ok(())
+    }
+
+    pub fn coast(&mut self) -> Result<(), Error> {
+        self.bridge.coast()?;
+        self.speed = 0.0;
+        Ok(())
+    }
+}
+
+impl<'a> Drop for DcMotor<'a> {
+    fn drop(&mut self) {
+        let _ = self.coast();
+    }
+}
+
+// Ramps each motor to its target speed at its own acceleration, together,
+// returning once all have arrived.
+fn ramp(motors: &mut [(&mut DcMotor, f64)]) -> Result<(), Error> {
+    let mut last = Instant::now();
+    loop {
+        let elapsed = last.elapsed().as_secs_f64();
+        last = Instant::now();
+        let mut arrived = true;
+        for (motor, target) in motors.iter_mut() {
+            let step = motor.acceleration * elapsed;
+            arrived &= motor.step_towards(*target, step)?;
+        }
+        if arrived {
+            return Ok(());
+        }
+        std::thread::sleep(RAMP_STEP);
+    }
+}
+
+
+/// Two motors driving the wheels or tracks on either side of a robot
+/// chassis, which steers by running them at different speeds.
+pub struct DifferentialDrive<'a> {
+    left: DcMotor<'a>,
+    right: DcMotor<'a>,
+}
+
+impl<'a> DifferentialDrive<'a> {
+
+    pub fn new(left: DcMotor<'a>, right: DcMotor<'a>) -> Self {
+        Self { left, right }
+    }
+
+    pub fn left(&mut self) -> &mut DcMotor<'a> {
+        &mut self.left
+    }
+
+    pub fn right(&mut self) -> &mut DcMotor<'a> {
+        &mut self.right
+    }
+
+    /// Drives each side at its own speed, from -1 to 1, ramping both
+    /// together.
+    pub fn tank(&mut self, left: f64, right: f64) -> Result<(), Error> {
+        check_speed(left.abs())?;
+        check_speed(right.abs())?;
+        ramp(&mut [(&mut self.left, left), (&mut self.right, right)])
+    }
+
+    /// Drives forward at `throttle` while turning right by `turn`, both
+    /// from -1 to 1; a negative throttle reverses and a negative turn
+    /// turns left. Where the sum would be beyond full speed, both sides
+    /// are scaled down so the turn is kept.
+    pub fn drive(&mut self, throttle: f64, turn: f64) -> Result<(), Error> {
+        check_speed(throttle.abs())?;
+        check_speed(turn.abs())?;
+        let (left, right) = (throttle + turn, throttle - turn);
+        let scale = left.abs().max(right.abs()).max(1.0);
+        self.tank(left / scale, right / scale)
+    }
+
+    /// The left and right speeds being driven.
+    pub fn speeds(&self) -> (f64, f64) {
+        (self.left.speed(), self.right.speed())
+    }
+
+    pub fn brake(&mut self) -> Result<(), Error> {
+        self.left.brake()?;
+        self.right.brake()
+    }
+
+    pub fn coast(&mut self) -> Result<(), Error> {
+        self.left.coast()?;
+        self.right.coast()
+    }
+
+    pub fn into_motors(self) -> (DcMotor<'a>, DcMotor<'a>) {
+        (self.left, self.right)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn duty_cycles(motor: &DcMotor) -> Vec<f64> {
+        match &motor.bridge {
+            Bridge::Enable { enable, .. } => vec![enable.duty_cycle()],
+            Bridge::Inputs { in1, in2 } => vec![in1.duty_cycle(), in2.duty_cycle()],
+        }
+    }
+
+    #[test]
+    fn test_enable_motor() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let mut motor = DcMotor::new(&gpio, 5, 6, 22).unwrap();
+        assert_eq!(duty_cycles(&motor), [0.0]);
+
+        motor.forward(0.5).unwrap();
+        assert_eq!((gpio.output_state(5).ok(), gpio.output_state(6).ok()), (Some(Some(true)), Some(Some(false))));
+        assert_eq!((motor.speed(), duty_cycles(&motor)), (0.5, vec![0.5]));
+        motor.reverse(0.25).unwrap();
+        assert_eq!((gpio.output_state(5).ok(), gpio.output_state(6).ok()), (Some(Some(false)), Some(Some(true))));
+        assert_eq!(motor.speed(), -0.25);
+        assert!(motor.forward(1.5).is_err());
+
+        motor.brake().unwrap();
+        assert_eq!((gpio.output_state(5).ok(), gpio.output_state(6).ok()), (Some(Some(true)), Some(Some(true))));
+        assert_eq!((motor.speed(), duty_cycles(&motor)), (0.0, vec![1.0]));
+        drop(motor);
+        assert_eq!(gpio.output_state(5).ok(), Some(Some(false)));
+    }
+
+    #[test]
+    fn test_two_pin_motor_ramps() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let mut motor = DcMotor::two_pin(&gpio, 23, 24).unwrap();
+        motor.set_acceleration(20.0).unwrap();
+        let started = Instant::now();
+        motor.forward(1.0).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(40));
+        assert_eq!(duty_cycles(&motor), [1.0, 0.0]);
+        motor.set_speed(-0.5).unwrap();
+        assert_eq!(duty_cycles(&motor), [0.0, 0.5]);
+        assert!(motor.set_acceleration(-1.0).is_err());
+    }
+
+    #[test]
+    fn test_differential_drive() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let left = DcMotor::two_pin(&gpio, 5, 6).unwrap();
+        let right = DcMotor::two_pin(&gpio, 23, 24).unwrap();
+        let mut drive = DifferentialDrive::new(left, right);
+        drive.drive(0.5, 0.25).unwrap();
+        assert_eq!(drive.speeds(), (0.75, 0.25));
+        drive.drive(1.0, -1.0).unwrap();
+        assert_eq!(drive.speeds(), (0.0, 1.0));
+        drive.tank(-0.5, 0.5).unwrap();
+        assert_eq!(duty_cycles(drive.left()), [0.0, 0.5]);
+        drive.brake().unwrap();
+        assert_eq!(drive.speeds(), (0.0, 0.0));
+        assert!(drive.drive(2.0, 0.0).is_err());
+    }
+}
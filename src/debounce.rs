@@ -0,0 +1,89 @@
+use crate::{check_pin, Error, GPIO, GPIO_PIN_COUNT};
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+
+#[derive(Copy, Clone)]
+struct PinDebounce {
+    period: Duration,
+    last_accepted: Option<Instant>,
+}
+
+/// Per-pin software debounce shared by every edge event API. An edge is
+/// dropped if it arrives within the pin's debounce period of the last edge
+/// that was let through.
+pub(crate) struct Debounce {
+    pins: Mutex<[PinDebounce; GPIO_PIN_COUNT as usize]>,
+}
+
+impl Debounce {
+
+    pub(crate) fn new() -> Self {
+        Self {
+            pins: Mutex::new([PinDebounce { period: Duration::ZERO, last_accepted: None }; GPIO_PIN_COUNT as usize]),
+        }
+    }
+
+    /// Whether an edge seen on `pin` at `at` should be reported.
+    pub(crate) fn accept(&self, pin: u32, at: Instant) -> bool {
+        let state = &mut self.pins.lock().unwrap()[pin as usize];
+        if let Some(last) = state.last_accepted {
+            if at.saturating_duration_since(last) < state.period {
+                return false;
+            }
+        }
+        state.last_accepted = Some(at);
+        true
+    }
+}
+
+
+impl GPIO {
+
+    /// Drops edges on `pin` that follow the last reported one by less than
+    /// `period`. This applies to `wait_for_edge`, `on_edge` callbacks and
+    /// async edge streams. `Duration::ZERO` turns debouncing off.
+    pub fn set_debounce(&self, pin: u32, period: Duration) -> Result<(), Error> {
+        check_pin(pin)?;
+        self.debounce.pins.lock().unwrap()[pin as usize].period = period;
+        Ok(())
+    }
+
+    pub fn debounce(&self, pin: u32) -> Result<Duration, Error> {
+        check_pin(pin)?;
+        Ok(self.debounce.pins.lock().unwrap()[pin as usize].period)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debounce_drops_bounces() {
+        let debounce = Debounce::new();
+        let start = Instant::now();
+        assert!(debounce.accept(17, start));
+        assert!(debounce.accept(17, start + Duration::from_millis(1)));
+
+        debounce.pins.lock().unwrap()[17].period = Duration::from_millis(50);
+        let press = start + Duration::from_millis(100);
+        assert!(debounce.accept(17, press));
+        assert!(!debounce.accept(17, press + Duration::from_millis(2)));
+        assert!(!debounce.accept(17, press + Duration::from_millis(49)));
+        assert!(debounce.accept(17, press + Duration::from_millis(50)));
+        // Other pins are unaffected.
+        assert!(debounce.accept(18, press + Duration::from_millis(51)));
+    }
+
+    #[test]
+    fn test_set_debounce() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        assert_eq!(gpio.debounce(5).ok(), Some(Duration::ZERO));
+        gpio.set_debounce(5, Duration::from_millis(20)).unwrap();
+        assert_eq!(gpio.debounce(5).ok(), Some(Duration::from_millis(20)));
+        assert!(gpio.set_debounce(58, Duration::from_millis(20)).is_err());
+    }
+}
@@ -0,0 +1,151 @@
+use crate::timing::{wait_until, SPIN_THRESHOLD};
+use crate::{Error, SystemTimer};
+
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+
+// Mapped on the first delay, for the process's lifetime. `None` where it
+// can't be, e.g. without root or off a Pi.
+static TIMER: OnceLock<Option<SystemTimer>> = OnceLock::new();
+
+fn system_timer() -> Option<&'static SystemTimer> {
+    TIMER.get_or_init(|| SystemTimer::new().ok()).as_ref()
+}
+
+// Sleeps through all but the last stretch of `us` microseconds on the
+// system timer, then spins on it. Fails only if the timer can't be read.
+fn delay_on_timer(timer: &SystemTimer, us: u64) -> Result<(), Error> {
+    let start = timer.now_us()?;
+    let threshold = SPIN_THRESHOLD.as_micros() as u64;
+    if us > threshold {
+        std::thread::sleep(Duration::from_micros(us - threshold));
+    }
+    while timer.now_us()?.wrapping_sub(start) < us {
+        std::hint::spin_loop();
+    }
+    Ok(())
+}
+
+/// Waits `us` microseconds, sleeping for the bulk of it and spinning on
+/// the system timer for the last 100 µs, so the wait comes out within a
+/// few microseconds of `us` without holding a core for long ones. Where
+/// the timer can't be mapped, the spin is on `Instant` instead.
+pub fn delay_us(us: u32) {
+    let deadline = Instant::now() + Duration::from_micros(us as u64);
+    if let Some(timer) = system_timer() {
+        let _ = delay_on_timer(timer, us as u64);
+    }
+    // Covers a timer that ticked just after the start, or failed.
+    wait_until(deadline);
+}
+
+/// As `delay_us`, with the part short of a whole microsecond spun on
+/// `Instant`, as the system timer counts in microseconds.
+pub fn delay_ns(ns: u32) {
+    let deadline = Instant::now() + Duration::from_nanos(ns as u64);
+    delay_us(ns / 1000);
+    wait_until(deadline);
+}
+
+/// The async counterpart of `delay_us`: the bulk of the wait is a tokio
+/// sleep, which frees the task's thread, and the last 100 µs are spun on
+/// that thread. Must be called from within a tokio runtime.
+#[cfg(feature = "async")]
+pub async fn delay_us_async(us: u32) {
+    delay_async(Duration::from_micros(us as u64)).await
+}
+
+/// The async counterpart of `delay_ns`.
+#[cfg(feature = "async")]
+pub async fn delay_ns_async(ns: u32) {
+    delay_async(Duration::from_nanos(ns as u64)).await
+}
+
+#[cfg(feature = "async")]
+async fn delay_async(duration: Duration) {
+    let deadline = Instant::now() + duration;
+    if duration > SPIN_THRESHOLD {
+        tokio::time::sleep(duration - SPIN_THRESHOLD).await;
+    }
+    if let Some(timer) = system_timer() {
+        let remaining = deadline.saturating_duration_since(Instant::now()).as_micros() as u64;
+        let _ = delay_on_timer(timer, remaining);
+    }
+    wait_until(deadline);
+}
+
+
+/// Blocking delays that sleep for the bulk of the wait and spin through
+/// the last stretch, so short delays come out accurate to a microsecond or
+/// so rather than to a scheduler tick. `delay_us` and `delay_ns` spin on
+/// the system timer, as the free functions of the same names do.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Delay;
+
+impl Delay {
+
+    pub fn new() -> Self {
+        Delay
+    }
+
+    pub fn delay(&self, duration: Duration) {
+        wait_until(Instant::now() + duration);
+    }
+
+    pub fn delay_ns(&self, ns: u32) {
+        delay_ns(ns);
+    }
+
+    pub fn delay_us(&self, us: u32) {
+        delay_us(us);
+    }
+
+    pub fn delay_ms(&self, ms: u32) {
+        self.delay(Duration::from_millis(ms as u64));
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay_waits_at_least_as_long_as_asked() {
+        let delay = Delay::new();
+        for &us in &[1, 50, 500, 2000] {
+            let started = Instant::now();
+            delay.delay_us(us);
+            assert!(started.elapsed() >= Duration::from_micros(us as u64));
+        }
+        let started = Instant::now();
+        delay.delay_ms(3);
+        assert!(started.elapsed() >= Duration::from_millis(3));
+    }
+
+    #[test]
+    fn test_delay_functions() {
+        for &ns in &[0, 700, 1500, 250_000] {
+            let started = Instant::now();
+            delay_ns(ns);
+            assert!(started.elapsed() >= Duration::from_nanos(ns as u64));
+        }
+        let started = Instant::now();
+        delay_us(300);
+        assert!(started.elapsed() >= Duration::from_micros(300));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_async_delays() {
+        for &us in &[5, 2000] {
+            let started = Instant::now();
+            delay_us_async(us).await;
+            assert!(started.elapsed() >= Duration::from_micros(us as u64));
+        }
+        let started = Instant::now();
+        delay_ns_async(1500).await;
+        assert!(started.elapsed() >= Duration::from_nanos(1500));
+    }
+}
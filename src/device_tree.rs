@@ -0,0 +1,187 @@
+use std::path::Path;
+
+
+const DEVICE_TREE_ROOT: &str = "/proc/device-tree";
+
+// Address of the peripheral block as seen from the VideoCore bus, which is
+// the child side of the soc node's `ranges` on every BCM283x/BCM2711.
+const BUS_PERIPHERAL_BASE: u64 = 0x7e00_0000;
+
+// Defaults from the devicetree specification when a node omits them.
+const DEFAULT_ADDRESS_CELLS: usize = 2;
+const DEFAULT_SIZE_CELLS: usize = 1;
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Range {
+    pub child: u64,
+    pub parent: u64,
+    pub size: u64,
+}
+
+
+fn read_cells(cells: &[u8]) -> u64 {
+    cells.chunks(4).fold(0, |value, cell| {
+        let mut word = [0u8; 4];
+        word[..cell.len()].copy_from_slice(cell);
+        (value << 32) | u32::from_be_bytes(word) as u64
+    })
+}
+
+/// Splits a `ranges` property into entries. Each entry is the child address
+/// (`child_cells` wide, from the node's own `#address-cells`), the parent
+/// address (`parent_cells` wide, from the parent's `#address-cells`) and the
+/// size (`size_cells` wide, from the node's `#size-cells`), all as 32-bit
+/// big-endian cells. A trailing partial entry is ignored.
+pub(crate) fn parse_ranges(ranges: &[u8], child_cells: usize, parent_cells: usize, size_cells: usize) -> Vec<Range> {
+    let stride = 4 * (child_cells + parent_cells + size_cells);
+    if stride == 0 {
+        return Vec::new();
+    }
+    ranges.chunks_exact(stride)
+        .map(|entry| {
+            let (child, rest) = entry.split_at(4 * child_cells);
+            let (parent, size) = rest.split_at(4 * parent_cells);
+            Range {
+                child: read_cells(child),
+                parent: read_cells(parent),
+                size: read_cells(size),
+            }
+        })
+        .collect()
+}
+
+/// Returns the CPU physical address of the peripheral block from the entry
+/// whose child window contains the peripheral bus address.
+pub(crate) fn peripheral_base_from_ranges(ranges: &[Range]) -> Option<u64> {
+    ranges.iter()
+        .find(|range| range.child <= BUS_PERIPHERAL_BASE && BUS_PERIPHERAL_BASE - range.child < range.size)
+        .map(|range| range.parent + (BUS_PERIPHERAL_BASE - range.child))
+}
+
+fn read_cell_count(path: &Path, default: usize) -> usize {
+    match std::fs::read(path) {
+        Ok(bytes) if bytes.len() == 4 => read_cells(&bytes) as usize,
+        _ => default,
+    }
+}
+
+pub(crate) fn peripheral_base_from(root: &Path) -> Option<u64> {
+    let soc = root.join("soc");
+    let ranges = std::fs::read(soc.join("ranges")).ok()?;
+    let child_cells = read_cell_count(&soc.join("#address-cells"), DEFAULT_ADDRESS_CELLS);
+    let size_cells = read_cell_count(&soc.join("#size-cells"), DEFAULT_SIZE_CELLS);
+    let parent_cells = read_cell_count(&root.join("#address-cells"), DEFAULT_ADDRESS_CELLS);
+    peripheral_base_from_ranges(&parse_ranges(&ranges, child_cells, parent_cells, size_cells))
+}
+
+// A string list property: NUL-terminated strings back to back.
+fn parse_string_list(bytes: &[u8]) -> Vec<String> {
+    let bytes = bytes.strip_suffix(&[0]).unwrap_or(bytes);
+    if bytes.is_empty() {
+        return Vec::new();
+    }
+    bytes.split(|&byte| byte == 0).map(|name| String::from_utf8_lossy(name).into_owned()).collect()
+}
+
+/// The `gpio-line-names` of the SoC's GPIO controller, indexed by GPIO
+/// number. Unnamed lines have empty names.
+pub(crate) fn gpio_line_names_from(root: &Path) -> Option<Vec<String>> {
+    std::fs::read_dir(root.join("soc")).ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("gpio@"))
+        .find_map(|entry| std::fs::read(entry.path().join("gpio-line-names")).ok())
+        .map(|names| parse_string_list(&names))
+}
+
+pub(crate) fn compatible() -> Option<Vec<u8>> {
+    std::fs::read(Path::new(DEVICE_TREE_ROOT).join("compatible")).ok()
+}
+
+pub(crate) fn peripheral_base() -> Option<u64> {
+    peripheral_base_from(Path::new(DEVICE_TREE_ROOT))
+}
+
+pub(crate) fn gpio_line_names() -> Option<Vec<String>> {
+    gpio_line_names_from(Path::new(DEVICE_TREE_ROOT))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cells(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|value| value.to_be_bytes()).collect()
+    }
+
+    #[test]
+    fn test_parse_ranges_pi3_single_entry() {
+        let ranges = parse_ranges(&cells(&[0x7e000000, 0x3f000000, 0x01000000]), 1, 1, 1);
+        assert_eq!(ranges, vec![Range { child: 0x7e000000, parent: 0x3f000000, size: 0x01000000 }]);
+        assert_eq!(peripheral_base_from_ranges(&ranges), Some(0x3f000000));
+    }
+
+    #[test]
+    fn test_parse_ranges_pi4_two_cell_parent() {
+        let raw = cells(&[
+            0x7e000000, 0x0, 0xfe000000, 0x01800000,
+            0x7c000000, 0x0, 0xfc000000, 0x02000000,
+            0x40000000, 0x0, 0xff800000, 0x00800000,
+        ]);
+        let ranges = parse_ranges(&raw, 1, 2, 1);
+        assert_eq!(ranges.len(), 3);
+        assert_eq!(ranges[2], Range { child: 0x40000000, parent: 0xff800000, size: 0x00800000 });
+        assert_eq!(peripheral_base_from_ranges(&ranges), Some(0xfe000000));
+    }
+
+    #[test]
+    fn test_parse_ranges_picks_containing_entry() {
+        let raw = cells(&[
+            0x0, 0x40000000, 0x0, 0x0, 0x10000000,
+            0x0, 0x7c000000, 0x4, 0x7c000000, 0x04000000,
+        ]);
+        let ranges = parse_ranges(&raw, 2, 2, 1);
+        assert_eq!(ranges[1], Range { child: 0x7c000000, parent: 0x4_7c000000, size: 0x04000000 });
+        assert_eq!(peripheral_base_from_ranges(&ranges), Some(0x4_7e000000));
+    }
+
+    #[test]
+    fn test_parse_ranges_two_cell_size_and_partial_entry() {
+        let mut raw = cells(&[0x7e000000, 0x20000000, 0x0, 0x02000000]);
+        raw.extend_from_slice(&[0x7e, 0x00]);
+        let ranges = parse_ranges(&raw, 1, 1, 2);
+        assert_eq!(ranges, vec![Range { child: 0x7e000000, parent: 0x20000000, size: 0x02000000 }]);
+        assert_eq!(peripheral_base_from_ranges(&ranges), Some(0x20000000));
+        assert_eq!(peripheral_base_from_ranges(&[]), None);
+    }
+
+    #[test]
+    fn test_peripheral_base_from_tree() {
+        let root = std::env::temp_dir().join(format!("rustberrypi-dt-{}", std::process::id()));
+        let soc = root.join("soc");
+        std::fs::create_dir_all(&soc).unwrap();
+        std::fs::write(root.join("#address-cells"), cells(&[2])).unwrap();
+        std::fs::write(soc.join("#address-cells"), cells(&[1])).unwrap();
+        std::fs::write(soc.join("#size-cells"), cells(&[1])).unwrap();
+        std::fs::write(soc.join("ranges"), cells(&[0x7e000000, 0x0, 0xfe000000, 0x01800000])).unwrap();
+
+        let base = peripheral_base_from(&root);
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(base, Some(0xfe000000));
+    }
+
+    #[test]
+    fn test_gpio_line_names_from_tree() {
+        let root = std::env::temp_dir().join(format!("rustberrypi-dt-names-{}", std::process::id()));
+        let gpio = root.join("soc").join("gpio@7e200000");
+        std::fs::create_dir_all(&gpio).unwrap();
+        assert_eq!(gpio_line_names_from(&root), None);
+        std::fs::write(gpio.join("gpio-line-names"), b"ID_SDA\0ID_SCL\0\0GPIO3\0").unwrap();
+
+        let names = gpio_line_names_from(&root);
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(names, Some(vec!["ID_SDA".to_string(), "ID_SCL".to_string(), String::new(), "GPIO3".to_string()]));
+        assert!(parse_string_list(b"").is_empty());
+    }
+}
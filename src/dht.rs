@@ -0,0 +1,249 @@
+use crate::{Error, Pin, PinFunction, Pull, Unconfigured, GPIO};
+
+use std::time::{Duration, Instant};
+
+
+// High pulses longer than this are 1 bits (about 70 µs), shorter ones 0
+// bits (about 27 µs).
+const ONE_THRESHOLD: Duration = Duration::from_micros(50);
+
+// The whole reply takes under 5 ms.
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(10);
+
+const DATA_BITS: usize = 40;
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum DhtKind {
+    /// 0–50 °C and 20–90 % in whole steps, one reading a second.
+    Dht11,
+    /// −40–80 °C and 0–100 % in tenths, one reading every two seconds.
+    /// Also covers the AM2302.
+    Dht22,
+}
+
+impl DhtKind {
+
+    // How long the host holds the line low to wake the sensor.
+    fn start_pulse(self) -> Duration {
+        match self {
+            DhtKind::Dht11 => Duration::from_millis(18),
+            DhtKind::Dht22 => Duration::from_millis(1),
+        }
+    }
+
+    /// The shortest time the sensor allows between readings.
+    pub fn min_interval(self) -> Duration {
+        match self {
+            DhtKind::Dht11 => Duration::from_secs(1),
+            DhtKind::Dht22 => Duration::from_secs(2),
+        }
+    }
+}
+
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DhtReading {
+    /// Degrees Celsius.
+    pub temperature: f64,
+    /// Percent relative humidity.
+    pub humidity: f64,
+}
+
+
+// Turns the line's transitions, as (time, new level) pairs, into the five
+// data bytes. The last 40 complete high pulses are the data bits; any
+// before them belong to the sensor's response.
+fn decode(edges: &[(Instant, bool)]) -> Result<[u8; 5], Error> {
+    let highs: Vec<Duration> = edges.windows(2)
+        .filter(|pair| pair[0].1 && !pair[1].1)
+        .map(|pair| pair[1].0 - pair[0].0)
+        .collect();
+    if highs.len() < DATA_BITS {
+        return Err(Error::Other(format!("DHT reply was cut short after {} bits", highs.len())));
+    }
+    let mut bytes = [0u8; 5];
+    for (index, &high) in highs[highs.len() - DATA_BITS..].iter().enumerate() {
+        if high > ONE_THRESHOLD {
+            bytes[index / 8] |= 0x80 >> (index % 8);
+        }
+    }
+    Ok(bytes)
+}
+
+fn parse(bytes: [u8; 5], kind: DhtKind) -> Result<DhtReading, Error> {
+    let sum = bytes[..4].iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+    if sum != bytes[4] {
+        return Err(Error::Other(format!("DHT checksum {:#04x} doesn't match {:#04x}", bytes[4], sum)));
+    }
+    Ok(match kind {
+        DhtKind::Dht11 => {
+            let magnitude = bytes[2] as f64 + (bytes[3] & 0x7f) as f64 / 10.0;
+            DhtReading {
+                temperature: if bytes[3] & 0x80 != 0 { -magnitude } else { magnitude },
+                humidity: bytes[0] as f64 + bytes[1] as f64 / 10.0,
+            }
+        }
+        DhtKind::Dht22 => {
+            let magnitude = u16::from_be_bytes([bytes[2] & 0x7f, bytes[3]]) as f64 / 10.0;
+            DhtReading {
+                temperature: if bytes[2] & 0x80 != 0 { -magnitude } else { magnitude },
+                humidity: u16::from_be_bytes([bytes[0], bytes[1]]) as f64 / 10.0,
+            }
+        }
+    })
+}
+
+
+/// A DHT11 or DHT22 temperature and humidity sensor on its single data
+/// line. The reply is timed by polling the pin, so a badly timed context
+/// switch can spoil a reading; `read` retries when the checksum or timing
+/// is off.
+pub struct Dht<'a> {
+    pin: Pin<'a, Unconfigured>,
+    kind: DhtKind,
+    retries: u32,
+    last_read: Option<Instant>,
+}
+
+impl<'a> Dht<'a> {
+
+    pub const DEFAULT_RETRIES: u32 = 3;
+
+    pub fn new(pin: Pin<'a, Unconfigured>, kind: DhtKind) -> Result<Self, Error> {
+        let dht = Self { pin, kind, retries: Self::DEFAULT_RETRIES, last_read: None };
+        let gpio = dht.gpio();
+        gpio.set_pull(dht.pin.number(), Pull::Up)?;
+        gpio.set_low(dht.pin.number())?;
+        gpio.set_function(dht.pin.number(), PinFunction::Input)?;
+        Ok(dht)
+    }
+
+    fn gpio(&self) -> &'a GPIO {
+        self.pin.gpio()
+    }
+
+    pub fn kind(&self) -> DhtKind {
+        self.kind
+    }
+
+    /// How many more attempts `read` makes after a failed one.
+    pub fn set_retries(&mut self, retries: u32) {
+        self.retries = retries;
+    }
+
+    pub fn retries(&self) -> u32 {
+        self.retries
+    }
+
+    // Waits out the sensor's minimum interval since the last reading.
+    fn wait_for_sensor(&self) {
+        if let Some(last) = self.last_read {
+            let ready = last + self.kind.min_interval();
+            let now = Instant::now();
+            if ready > now {
+                std::thread::sleep(ready - now);
+            }
+        }
+    }
+
+    // Wakes the sensor and records every transition of its reply.
+    fn capture(&self) -> Result<Vec<(Instant, bool)>, Error> {
+        let (gpio, pin) = (self.gpio(), self.pin.number());
+        gpio.set_function(pin, PinFunction::Output)?;
+        std::thread::sleep(self.kind.start_pulse());
+        gpio.set_function(pin, PinFunction::Input)?;
+        let started = Instant::now();
+        let mut level = true;
+        let mut edges = vec![(started, level)];
+        // The reply ends with the line going high once all the bits are in.
+        while edges.len() < 2 * DATA_BITS + 5 {
+            if started.elapsed() > RESPONSE_TIMEOUT {
+                if edges.len() == 1 {
+                    return Err(Error::Timeout(format!("no DHT sensor answered on pin {}", pin)));
+                }
+                break;
+            }
+            if gpio.read(pin)? != level {
+                level = !level;
+                edges.push((Instant::now(), level));
+            }
+        }
+        Ok(edges)
+    }
+
+    fn read_once(&mut self) -> Result<DhtReading, Error> {
+        self.wait_for_sensor();
+        let edges = self.capture();
+        self.last_read = Some(Instant::now());
+        parse(decode(&edges?)?, self.kind)
+    }
+
+    /// Takes a reading, first waiting out the sensor's minimum interval
+    /// since the last one. Fails with the last attempt's error once the
+    /// retries run out.
+    pub fn read(&mut self) -> Result<DhtReading, Error> {
+        let mut attempt = 0;
+        loop {
+            match self.read_once() {
+                Err(_) if attempt < self.retries => attempt += 1,
+                result => return result,
+            }
+        }
+    }
+}
+
+impl<'a> Drop for Dht<'a> {
+    fn drop(&mut self) {
+        let _ = self.gpio().set_function(self.pin.number(), PinFunction::Input);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The transitions a sensor sending `bytes` would make.
+    fn reply(bytes: [u8; 5]) -> Vec<(Instant, bool)> {
+        let start = Instant::now();
+        let mut at = Duration::from_micros(30);
+        let mut edges = vec![(start, true)];
+        let mut push = |edges: &mut Vec<(Instant, bool)>, level, length: u64| {
+            edges.push((start + at, level));
+            at += Duration::from_micros(length);
+        };
+        push(&mut edges, false, 80);
+        push(&mut edges, true, 80);
+        for index in 0..DATA_BITS {
+            push(&mut edges, false, 50);
+            let bit = bytes[index / 8] & (0x80 >> (index % 8)) != 0;
+            push(&mut edges, true, if bit { 70 } else { 27 });
+        }
+        push(&mut edges, false, 50);
+        push(&mut edges, true, 0);
+        edges
+    }
+
+    #[test]
+    fn test_decode_and_parse() {
+        let bytes = [0x02, 0x8c, 0x80, 0x65, 0x73];
+        assert_eq!(decode(&reply(bytes)).ok(), Some(bytes));
+        assert_eq!(parse(bytes, DhtKind::Dht22).ok(), Some(DhtReading { temperature: -10.1, humidity: 65.2 }));
+        let bytes = [45, 0, 23, 4, 72];
+        assert_eq!(parse(bytes, DhtKind::Dht11).ok(), Some(DhtReading { temperature: 23.4, humidity: 45.0 }));
+        assert!(parse([45, 0, 23, 4, 73], DhtKind::Dht11).is_err());
+        assert!(decode(&reply(bytes)[..40]).is_err());
+    }
+
+    #[test]
+    fn test_dht_without_a_sensor() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        unsafe { gpio.write_raw(0x34, 1 << 4) };
+        let mut dht = Dht::new(gpio.pin(4).unwrap(), DhtKind::Dht22).unwrap();
+        assert_eq!(gpio.get_pull(4).unwrap(), Pull::Up);
+        dht.set_retries(0);
+        assert!(matches!(dht.read(), Err(Error::Timeout(_))));
+        assert_eq!(gpio.get_function(4).unwrap(), PinFunction::Input);
+    }
+}
@@ -0,0 +1,187 @@
+use crate::mailbox::Mailbox;
+use crate::region::MappedRegion;
+use crate::{Error, Soc, DEV_MEM_PATH};
+
+use std::time::{Duration, Instant};
+
+
+const DMA_BASE_OFFSET: i64 = 0x7000;
+const DMA_CHANNEL_STRIDE: i64 = 0x100;
+const DMA_CHANNEL_LEN: usize = 0x24;
+// Channel 15 lives elsewhere and the rest are used by the firmware.
+pub(crate) const DMA_CHANNELS: u32 = 15;
+
+const DMA_CS: usize = 0x00;
+const DMA_CONBLK_AD: usize = 0x04;
+const DMA_DEBUG: usize = 0x20;
+
+const CS_ACTIVE: u32 = 1 << 0;
+const CS_END: u32 = 1 << 1;
+const CS_PRIORITY_SHIFT: u32 = 16;
+const CS_PANIC_PRIORITY_SHIFT: u32 = 20;
+const CS_WAIT_FOR_OUTSTANDING_WRITES: u32 = 1 << 28;
+const CS_ABORT: u32 = 1 << 30;
+const CS_RESET: u32 = 1 << 31;
+// Read-error, FIFO-error and read-last-not-set flags, write-1-to-clear.
+const DEBUG_CLEAR_ERRORS: u32 = 0x7;
+
+pub(crate) const TI_WAIT_RESP: u32 = 1 << 3;
+pub(crate) const TI_DEST_INC: u32 = 1 << 4;
+pub(crate) const TI_DEST_DREQ: u32 = 1 << 6;
+pub(crate) const TI_SRC_INC: u32 = 1 << 8;
+pub(crate) const TI_SRC_DREQ: u32 = 1 << 10;
+pub(crate) const TI_PERMAP_SHIFT: u32 = 16;
+pub(crate) const TI_NO_WIDE_BURSTS: u32 = 1 << 26;
+pub(crate) const DREQ_PCM_TX: u32 = 2;
+pub(crate) const DREQ_PWM: u32 = 5;
+pub(crate) const DREQ_SPI0_TX: u32 = 6;
+pub(crate) const DREQ_SPI0_RX: u32 = 7;
+
+/// Where the DMA controller sees the peripherals, on every model.
+pub(crate) const PERIPHERAL_BUS_BASE: u32 = 0x7e00_0000;
+
+// The lite channels can only move this much per control block.
+pub(crate) const MAX_TRANSFER_LEN: u32 = 0xfffc;
+
+// Bus addresses carry the cache alias in their top two bits.
+const BUS_ALIAS_MASK: u32 = 0xc000_0000;
+
+
+/// A DMA control block. The controller needs them 32-byte aligned.
+#[repr(C, align(32))]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct ControlBlock {
+    pub(crate) transfer_info: u32,
+    pub(crate) source: u32,
+    pub(crate) destination: u32,
+    pub(crate) length: u32,
+    pub(crate) stride: u32,
+    pub(crate) next: u32,
+    pub(crate) reserved: [u32; 2],
+}
+
+pub(crate) const CONTROL_BLOCK_SIZE: u32 = std::mem::size_of::<ControlBlock>() as u32;
+
+impl ControlBlock {
+
+    pub(crate) fn words(&self) -> [u32; 8] {
+        [self.transfer_info, self.source, self.destination, self.length, self.stride, self.next,
+         self.reserved[0], self.reserved[1]]
+    }
+}
+
+
+/// Uncached memory allocated from the firmware, mapped through `/dev/mem`
+/// at its physical address so both the CPU and the DMA controller see the
+/// same bytes.
+pub(crate) struct DmaMemory {
+    mailbox: Mailbox,
+    handle: u32,
+    bus_address: u32,
+    region: MappedRegion,
+}
+
+impl DmaMemory {
+
+    pub(crate) fn allocate(size: u32, soc: Soc) -> Result<Self, Error> {
+        let mailbox = Mailbox::open()?;
+        // Direct (uncached) on later SoCs; the BCM2835 needs the L2
+        // non-allocating alias instead.
+        let flags = if soc == Soc::Bcm2835 { 0x0c } else { 0x04 };
+        let size = size.div_ceil(4096) * 4096;
+        let handle = mailbox.allocate(size, 4096, flags)?;
+        let bus_address = match mailbox.lock(handle) {
+            Ok(address) => address,
+            Err(error) => {
+                let _ = mailbox.release(handle);
+                return Err(error);
+            }
+        };
+        let region = MappedRegion::open(DEV_MEM_PATH, (bus_address & !BUS_ALIAS_MASK) as i64, size as usize);
+        match region {
+            Ok(region) => Ok(Self { mailbox, handle, bus_address, region }),
+            Err(error) => {
+                let _ = mailbox.unlock(handle);
+                let _ = mailbox.release(handle);
+                Err(error)
+            }
+        }
+    }
+
+    pub(crate) fn bus_address(&self) -> u32 {
+        self.bus_address
+    }
+
+    pub(crate) fn write_words(&self, offset: usize, words: &[u32]) -> Result<(), Error> {
+        for (index, &word) in words.iter().enumerate() {
+            self.region.write_reg(offset + 4 * index, word)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn read_words(&self, offset: usize, count: usize) -> Result<Vec<u32>, Error> {
+        (0..count).map(|index| self.region.read_reg(offset + 4 * index)).collect()
+    }
+}
+
+impl Drop for DmaMemory {
+    fn drop(&mut self) {
+        let _ = self.mailbox.unlock(self.handle);
+        let _ = self.mailbox.release(self.handle);
+    }
+}
+
+
+/// The registers of one DMA channel.
+pub(crate) struct DmaChannel {
+    region: MappedRegion,
+}
+
+impl DmaChannel {
+
+    pub(crate) fn open(peripheral_base: i64, channel: u32) -> Result<Self, Error> {
+        if channel >= DMA_CHANNELS {
+            return Err(Error::Other(format!("DMA channel {} can't be used", channel)));
+        }
+        let base = peripheral_base + DMA_BASE_OFFSET + DMA_CHANNEL_STRIDE * channel as i64;
+        Ok(Self { region: MappedRegion::open(DEV_MEM_PATH, base, DMA_CHANNEL_LEN)? })
+    }
+
+    pub(crate) fn reset(&self) -> Result<(), Error> {
+        self.region.write_reg(DMA_CS, CS_ABORT)?;
+        self.region.write_reg(DMA_CS, CS_RESET)?;
+        self.region.write_reg(DMA_DEBUG, DEBUG_CLEAR_ERRORS)
+    }
+
+    /// Starts the chain of control blocks at `first`.
+    pub(crate) fn start(&self, first: u32) -> Result<(), Error> {
+        self.reset()?;
+        self.region.write_reg(DMA_CS, CS_END)?;
+        self.region.write_reg(DMA_CONBLK_AD, first)?;
+        self.region.write_reg(DMA_CS,
+            CS_ACTIVE | CS_WAIT_FOR_OUTSTANDING_WRITES | 8 << CS_PRIORITY_SHIFT | 8 << CS_PANIC_PRIORITY_SHIFT)
+    }
+
+    pub(crate) fn is_active(&self) -> Result<bool, Error> {
+        Ok(self.region.read_reg(DMA_CS)? & CS_ACTIVE != 0)
+    }
+
+    pub(crate) fn wait(&self, timeout: Option<Duration>) -> Result<(), Error> {
+        let started = Instant::now();
+        while self.is_active()? {
+            if let Some(timeout) = timeout {
+                if started.elapsed() >= timeout {
+                    return Err(Error::Timeout(format!("DMA transfer still running after {:?}", timeout)));
+                }
+            }
+            std::thread::sleep(Duration::from_micros(100));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for DmaChannel {
+    fn drop(&mut self) {
+        let _ = self.reset();
+    }
+}
@@ -0,0 +1,143 @@
+use crate::one_wire::crc8;
+use crate::{Error, OneWire};
+
+use std::time::Duration;
+
+
+const FAMILY_CODE: u8 = 0x28;
+
+const CONVERT_T: u8 = 0x44;
+const WRITE_SCRATCHPAD: u8 = 0x4e;
+const READ_SCRATCHPAD: u8 = 0xbe;
+
+// Conversion time at 9 bits; each extra bit doubles it.
+const CONVERSION_TIME_9_BIT: Duration = Duration::from_micros(93_750);
+
+
+// Reads the temperature out of a scratchpad, keeping only the bits the
+// resolution in its configuration byte makes valid.
+fn parse_scratchpad(scratchpad: &[u8; 9]) -> Result<f64, Error> {
+    if crc8(scratchpad) != 0 {
+        return Err(Error::Other("DS18B20 scratchpad fails its CRC".to_string()));
+    }
+    let resolution = 9 + (scratchpad[4] >> 5 & 0b11) as u32;
+    let raw = i16::from_le_bytes([scratchpad[0], scratchpad[1]]);
+    let raw = raw & !((1 << (12 - resolution)) - 1);
+    Ok(raw as f64 / 16.0)
+}
+
+
+/// A DS18B20 temperature sensor on a `OneWire` bus. The sensor needs its
+/// own power supply; parasite power isn't supported.
+pub struct Ds18b20<'b, 'a> {
+    bus: &'b OneWire<'a>,
+    rom: Option<u64>,
+    resolution: u8,
+}
+
+impl<'b, 'a> Ds18b20<'b, 'a> {
+
+    /// The sensor with ROM code `rom`, or the only device on the bus if
+    /// `rom` is `None`. Assumes the power-on resolution of 12 bits.
+    pub fn new(bus: &'b OneWire<'a>, rom: Option<u64>) -> Self {
+        Self { bus, rom, resolution: 12 }
+    }
+
+    /// Every DS18B20 on the bus.
+    pub fn find_all(bus: &'b OneWire<'a>) -> Result<Vec<Self>, Error> {
+        Ok(bus.search()?.into_iter()
+            .filter(|&rom| rom as u8 == FAMILY_CODE)
+            .map(|rom| Self::new(bus, Some(rom)))
+            .collect())
+    }
+
+    pub fn rom(&self) -> Option<u64> {
+        self.rom
+    }
+
+    /// Sets the resolution to 9 to 12 bits, trading precision (0.5 °C down
+    /// to 0.0625 °C) for conversion time (94 ms up to 750 ms). The alarm
+    /// thresholds are cleared.
+    pub fn set_resolution(&mut self, bits: u8) -> Result<(), Error> {
+        if !(9..=12).contains(&bits) {
+            return Err(Error::Other(format!("the DS18B20 resolution is 9 to 12 bits, not {}", bits)));
+        }
+        self.bus.select(self.rom)?;
+        self.bus.write(&[WRITE_SCRATCHPAD, 0, 0, (bits - 9) << 5 | 0x1f])?;
+        self.resolution = bits;
+        Ok(())
+    }
+
+    pub fn resolution(&self) -> u8 {
+        self.resolution
+    }
+
+    /// How long a conversion takes at the current resolution.
+    pub fn conversion_time(&self) -> Duration {
+        CONVERSION_TIME_9_BIT * (1 << (self.resolution - 9))
+    }
+
+    /// Starts a conversion, which is ready after `conversion_time`. With
+    /// `rom` as `None` every sensor on the bus converts at once.
+    pub fn start_conversion(&self) -> Result<(), Error> {
+        self.bus.select(self.rom)?;
+        self.bus.write_byte(CONVERT_T)
+    }
+
+    /// The result of the last conversion in °C. A sensor that hasn't
+    /// converted since power-on reads 85 °C.
+    pub fn read_temperature(&self) -> Result<f64, Error> {
+        self.bus.select(self.rom)?;
+        self.bus.write_byte(READ_SCRATCHPAD)?;
+        let mut scratchpad = [0u8; 9];
+        self.bus.read(&mut scratchpad)?;
+        parse_scratchpad(&scratchpad)
+    }
+
+    /// Converts, waits and returns the temperature in °C.
+    pub fn measure(&self) -> Result<f64, Error> {
+        self.start_conversion()?;
+        std::thread::sleep(self.conversion_time());
+        self.read_temperature()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GPIO;
+
+    fn scratchpad(raw: i16, config: u8) -> [u8; 9] {
+        let [low, high] = raw.to_le_bytes();
+        let mut scratchpad = [low, high, 0x4b, 0x46, config, 0xff, 0x0c, 0x10, 0];
+        scratchpad[8] = crc8(&scratchpad[..8]);
+        scratchpad
+    }
+
+    #[test]
+    fn test_parse_scratchpad() {
+        assert_eq!(parse_scratchpad(&scratchpad(0x0191, 0x7f)).ok(), Some(25.0625));
+        assert_eq!(parse_scratchpad(&scratchpad(-162, 0x7f)).ok(), Some(-10.125));
+        assert_eq!(parse_scratchpad(&scratchpad(0x0550, 0x7f)).ok(), Some(85.0));
+        // At 9 bits the low three bits are undefined.
+        assert_eq!(parse_scratchpad(&scratchpad(0x0197, 0x1f)).ok(), Some(25.0));
+        let mut corrupt = scratchpad(0x0191, 0x7f);
+        corrupt[0] ^= 1;
+        assert!(parse_scratchpad(&corrupt).is_err());
+    }
+
+    #[test]
+    fn test_ds18b20_settings() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        unsafe { gpio.write_raw(0x34, 1 << 4) };
+        let bus = OneWire::new(gpio.pin(4).unwrap()).unwrap();
+        let mut sensor = Ds18b20::new(&bus, None);
+        assert_eq!(sensor.conversion_time(), Duration::from_millis(750));
+        assert!(sensor.set_resolution(8).is_err());
+        // No device answers the reset.
+        assert!(sensor.set_resolution(9).is_err());
+        assert!(sensor.measure().is_err());
+        assert!(Ds18b20::find_all(&bus).unwrap().is_empty());
+    }
+}
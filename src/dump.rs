@@ -0,0 +1,148 @@
+use crate::alt_function::{alt_function_name, alt_index};
+use crate::{Error, PinFunction, Pull, Soc, GPIO};
+
+use std::fmt::{self, Display};
+
+
+// raspi-gpio splits the pins as the BCM2835 datasheet's power domains do.
+const BANK_STARTS: [u32; 3] = [0, 28, 46];
+
+
+/// One pin's entry in a `GpioDump`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PinState {
+    pub pin: u32,
+    /// `None` if the level couldn't be read, e.g. from a line held by
+    /// another driver.
+    pub level: Option<bool>,
+    pub function: PinFunction,
+    pub pull: Pull,
+    /// See `alt_function_name`.
+    pub alt_name: Option<&'static str>,
+    /// See `GPIO::line_name`.
+    pub line_name: Option<String>,
+}
+
+impl Display for PinState {
+    // One line of `raspi-gpio get`, followed by the line's name if it has
+    // one.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = match self.level {
+            Some(level) => (level as u8).to_string(),
+            None => "?".to_string(),
+        };
+        write!(f, "GPIO {}: level={} fsel={}", self.pin, level, self.function as u32 & 0b111)?;
+        match alt_index(self.function) {
+            Some(alt) => write!(f, " alt={} func={}", alt, self.alt_name.unwrap_or("-"))?,
+            None if self.function == PinFunction::Output => write!(f, " func=OUTPUT")?,
+            None => write!(f, " func=INPUT")?,
+        }
+        let pull = match self.pull {
+            Pull::None => "NONE",
+            Pull::Up => "UP",
+            Pull::Down => "DOWN",
+        };
+        write!(f, " pull={}", pull)?;
+        match &self.line_name {
+            Some(name) => write!(f, " // {}", name),
+            None => Ok(()),
+        }
+    }
+}
+
+
+/// The level, function and pull of every GPIO, as `GPIO::dump` found them.
+/// Displays like `raspi-gpio get`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GpioDump {
+    soc: Soc,
+    pins: Vec<PinState>,
+}
+
+impl GpioDump {
+
+    pub fn soc(&self) -> Soc {
+        self.soc
+    }
+
+    pub fn pins(&self) -> &[PinState] {
+        &self.pins
+    }
+
+    pub fn pin(&self, pin: u32) -> Option<&PinState> {
+        self.pins.get(pin as usize)
+    }
+}
+
+impl Display for GpioDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = self.pins.len() as u32;
+        for (bank, &start) in BANK_STARTS.iter().enumerate().filter(|&(_, &start)| start < count) {
+            let end = BANK_STARTS.get(bank + 1).map_or(count, |&next| next.min(count));
+            if bank > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "BANK{} (GPIO {} to {}):", bank, start, end - 1)?;
+            for state in &self.pins[start as usize..end as usize] {
+                writeln!(f, "{}", state)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+
+impl GPIO {
+
+    /// Reads the state of every GPIO on the SoC. Line backends only tell
+    /// inputs from outputs, and reading a level requests the line, so this
+    /// leaves every free line held by this `GPIO`.
+    pub fn dump(&self) -> Result<GpioDump, Error> {
+        let soc = self.soc();
+        let pins = (0..soc.gpio_count())
+            .map(|pin| {
+                let function = self.get_function(pin)?;
+                Ok(PinState {
+                    pin,
+                    level: self.read(pin).ok(),
+                    function,
+                    pull: self.get_pull(pin)?,
+                    alt_name: alt_function_name(soc, pin, function),
+                    line_name: self.line_name(pin)?.map(str::to_string),
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(GpioDump { soc, pins })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dump() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        gpio.switch_to_alt(&[14], PinFunction::Alt0).unwrap();
+        gpio.set_function(17, PinFunction::Output).unwrap();
+        gpio.set_high(17).unwrap();
+        gpio.set_pull(4, Pull::Up).unwrap();
+        unsafe { gpio.write_raw(0x34, 1 << 4) };
+        let mut names = vec![String::new(); 58];
+        names[17] = "RELAY".to_string();
+        gpio.line_names.set(names).unwrap();
+        let dump = gpio.dump().unwrap();
+        assert_eq!(dump.pins().len(), 58);
+        assert_eq!(dump.pin(14).map(|state| state.alt_name), Some(Some("TXD0")));
+
+        let text = dump.to_string();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "BANK0 (GPIO 0 to 27):");
+        assert_eq!(lines[1 + 4], "GPIO 4: level=1 fsel=0 func=INPUT pull=UP");
+        assert_eq!(lines[1 + 14], "GPIO 14: level=0 fsel=4 alt=0 func=TXD0 pull=NONE");
+        assert_eq!(lines[1 + 17], "GPIO 17: level=0 fsel=1 func=OUTPUT pull=NONE // RELAY");
+        assert!(text.contains("\n\nBANK1 (GPIO 28 to 45):\n") && text.contains("\n\nBANK2 (GPIO 46 to 57):\n"));
+        assert_eq!(lines.len(), 58 + 3 + 2);
+    }
+}
@@ -0,0 +1,256 @@
+use crate::{bit_in_bank, Error, Register, GPIO};
+
+use nix::time::{clock_gettime, ClockId};
+
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+
+// GPEDS latches events, so none are missed between polls; the interval
+// only bounds how late one is noticed.
+const EVENT_POLL_INTERVAL: Duration = Duration::from_micros(100);
+
+
+/// The edges `wait_for_edge` waits for.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Trigger {
+    Rising,
+    Falling,
+    Both,
+}
+
+impl Trigger {
+
+    fn registers(self) -> &'static [Register] {
+        match self {
+            Trigger::Rising => &[Register::GPREN],
+            Trigger::Falling => &[Register::GPFEN],
+            Trigger::Both => &[Register::GPREN, Register::GPFEN],
+        }
+    }
+}
+
+/// Which way the level went in a `PinEvent`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum EdgeKind {
+    Rising,
+    Falling,
+}
+
+/// An edge on a pin, as reported by every edge event API. The timestamp is
+/// on the monotonic clock: the kernel's own event timestamp for line
+/// backends, or when the event was noticed for register backends.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PinEvent {
+    pub pin: u32,
+    pub edge: EdgeKind,
+    pub timestamp: Instant,
+}
+
+impl PinEvent {
+
+    /// The time from `earlier` to this event, e.g. a pulse width when
+    /// `earlier` is the pulse's leading edge.
+    pub fn since(&self, earlier: &PinEvent) -> Duration {
+        self.timestamp.saturating_duration_since(earlier.timestamp)
+    }
+}
+
+
+/// Converts a `CLOCK_MONOTONIC` timestamp, as the kernel stamps line events
+/// with, to an `Instant`, which uses the same clock on Linux. Measured from
+/// one reference point taken on first use, so a timestamp read by several
+/// event APIs converts to the same `Instant` in each.
+pub(crate) fn instant_from_monotonic(timestamp_ns: u64) -> Instant {
+    static REFERENCE: OnceLock<Option<(Instant, u64)>> = OnceLock::new();
+    let Some((reference, reference_ns)) = *REFERENCE.get_or_init(|| Some((Instant::now(), monotonic_ns()?))) else {
+        return Instant::now();
+    };
+    if timestamp_ns >= reference_ns {
+        reference + Duration::from_nanos(timestamp_ns - reference_ns)
+    } else {
+        reference.checked_sub(Duration::from_nanos(reference_ns - timestamp_ns)).unwrap_or(reference)
+    }
+}
+
+/// The current `CLOCK_MONOTONIC` time in nanoseconds.
+pub(crate) fn monotonic_ns() -> Option<u64> {
+    let time = clock_gettime(ClockId::CLOCK_MONOTONIC).ok()?;
+    Some(time.tv_sec() as u64 * 1_000_000_000 + time.tv_nsec() as u64)
+}
+
+
+impl GPIO {
+
+    /// Blocks until `trigger` fires on `pin` and returns the edge as an
+    /// `PinEvent`. Line backends wait for a kernel line event; register
+    /// backends enable synchronous edge detection for the duration of the
+    /// call and poll GPEDS. With `Trigger::Both` on a register backend the
+    /// edge is inferred from the level read just after the event, so a
+    /// pulse shorter than the poll interval may be reported as the wrong
+    /// edge. Edges dropped by the pin's debounce don't end the wait. Fails
+    /// with `Error::Timeout` if `timeout` elapses first.
+    pub fn wait_for_edge(&self, pin: u32, trigger: Trigger, timeout: Option<Duration>) -> Result<PinEvent, Error> {
+        self.check_board_pin(pin)?;
+        let start = Instant::now();
+        loop {
+            let remaining = timeout.map(|timeout| timeout.saturating_sub(start.elapsed()));
+            let event = self.wait_for_any_edge(pin, trigger, remaining)?;
+            if self.debounce.accept(pin, event.timestamp) {
+                return Ok(event);
+            }
+        }
+    }
+
+    fn wait_for_any_edge(&self, pin: u32, trigger: Trigger, timeout: Option<Duration>) -> Result<PinEvent, Error> {
+        if self.buffer.is_null() {
+            return self.driver.wait_for_edge(pin, trigger, timeout);
+        }
+
+        let enabled = self.enable_wait_triggers(pin, trigger)?;
+        let result = self.poll_for_edge(pin, trigger, timeout);
+        self.disable_wait_triggers(pin, &enabled)?;
+        result
+    }
+
+    // Returns the enable registers that weren't already set for `pin`, so
+    // that only those are turned off again afterwards.
+    pub(crate) fn enable_wait_triggers(&self, pin: u32, trigger: Trigger) -> Result<Vec<Register>, Error> {
+        let _guard = self.config_lock.lock().unwrap();
+        let bit = 1 << bit_in_bank(pin);
+        let mut enabled = Vec::new();
+        for &register in trigger.registers() {
+            let value = self.read_register(register, pin)?;
+            if value & bit == 0 {
+                self.write_register(register, pin, value | bit)?;
+                enabled.push(register);
+            }
+        }
+        Ok(enabled)
+    }
+
+    pub(crate) fn disable_wait_triggers(&self, pin: u32, enabled: &[Register]) -> Result<(), Error> {
+        let _guard = self.config_lock.lock().unwrap();
+        for &register in enabled {
+            let value = self.read_register(register, pin)?;
+            self.write_register(register, pin, value & !(1 << bit_in_bank(pin)))?;
+        }
+        Ok(())
+    }
+
+    fn poll_for_edge(&self, pin: u32, trigger: Trigger, timeout: Option<Duration>) -> Result<PinEvent, Error> {
+        let start = Instant::now();
+        // Only edges after the call count.
+        if self.poll_event(pin)? {
+            self.clear_event(pin)?;
+        }
+        loop {
+            if self.poll_event(pin)? {
+                let seen = Instant::now();
+                let edge = match trigger {
+                    Trigger::Rising => EdgeKind::Rising,
+                    Trigger::Falling => EdgeKind::Falling,
+                    Trigger::Both => if self.read(pin)? { EdgeKind::Rising } else { EdgeKind::Falling },
+                };
+                self.clear_event(pin)?;
+                return Ok(PinEvent { pin, edge, timestamp: seen });
+            }
+            if let Some(timeout) = timeout {
+                if start.elapsed() >= timeout {
+                    return Err(Error::Timeout(format!("no {:?} edge on pin {} within {:?}", trigger, pin, timeout)));
+                }
+            }
+            std::thread::sleep(EVENT_POLL_INTERVAL);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EdgeTrigger, PinFunction};
+
+    #[test]
+    fn test_wait_for_edge_polls_gpeds() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        gpio.enable_edge_detect(17, EdgeTrigger::Rising).unwrap();
+        let button = gpio.pin(17).unwrap().into_input().unwrap();
+
+        let event = std::thread::scope(|scope| {
+            scope.spawn(|| {
+                std::thread::sleep(Duration::from_millis(20));
+                unsafe {
+                    gpio.write_raw(0x34, 1 << 17);
+                    gpio.write_raw(0x40, 1 << 17);
+                }
+            });
+            button.wait_for_edge(Trigger::Both, Some(Duration::from_secs(5))).unwrap()
+        });
+        assert_eq!(event.pin, 17);
+        assert_eq!(event.edge, EdgeKind::Rising);
+        assert!(event.timestamp.elapsed() < Duration::from_secs(5));
+
+        // The rising enable was already set and stays; the falling one was
+        // only enabled for the wait.
+        assert_eq!(gpio.read_register(Register::GPREN, 17).ok(), Some(1 << 17));
+        assert_eq!(gpio.read_register(Register::GPFEN, 17).ok(), Some(0));
+        assert_eq!(gpio.get_function(17).ok(), Some(PinFunction::Input));
+    }
+
+    #[test]
+    fn test_wait_for_edge_times_out() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let error = gpio.wait_for_edge(4, Trigger::Falling, Some(Duration::from_millis(10))).unwrap_err();
+        assert!(matches!(error, Error::Timeout(_)));
+        assert_eq!(gpio.read_register(Register::GPFEN, 4).ok(), Some(0));
+        assert!(gpio.wait_for_edge(58, Trigger::Falling, None).is_err());
+    }
+
+    #[test]
+    fn test_event_since() {
+        let start = Instant::now();
+        let rising = PinEvent { pin: 4, edge: EdgeKind::Rising, timestamp: start };
+        let falling = PinEvent { pin: 4, edge: EdgeKind::Falling, timestamp: start + Duration::from_micros(1500) };
+        assert_eq!(falling.since(&rising), Duration::from_micros(1500));
+        assert_eq!(rising.since(&falling), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_instant_from_monotonic() {
+        let now = clock_gettime(ClockId::CLOCK_MONOTONIC).unwrap();
+        let now_ns = now.tv_sec() as u64 * 1_000_000_000 + now.tv_nsec() as u64;
+        let instant = instant_from_monotonic(now_ns - 50_000_000);
+        let age = instant.elapsed();
+        assert!(age >= Duration::from_millis(50) && age < Duration::from_secs(1));
+        assert_eq!(instant_from_monotonic(now_ns), instant_from_monotonic(now_ns));
+    }
+
+    #[test]
+    fn test_event_apis_report_the_same_event() {
+        let mock = crate::MockGpio::new();
+        let gpio = mock.gpio().unwrap();
+        let (sender, callback_events) = std::sync::mpsc::channel();
+        gpio.on_edge(17, Trigger::Rising, move |event| sender.send(event).unwrap()).unwrap();
+        let mut watched = Vec::new();
+        let mut watcher = crate::Watcher::new(&gpio);
+        watcher.watch(17, Trigger::Rising, |event| watched.push(event)).unwrap();
+        #[cfg(feature = "async")]
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        #[cfg(feature = "async")]
+        let mut stream = runtime.block_on(async { gpio.edge_events(17, Trigger::Rising) }).unwrap();
+
+        // One scripted edge, which `wait_for_edge` is already waiting for.
+        let player = mock.play_inputs(17, &[(Duration::from_millis(50), true)]);
+        let waited = gpio.wait_for_edge(17, Trigger::Rising, Some(Duration::from_secs(5))).unwrap();
+        player.join().unwrap();
+        assert_eq!((waited.pin, waited.edge), (17, EdgeKind::Rising));
+
+        assert_eq!(callback_events.recv_timeout(Duration::from_secs(5)).ok(), Some(waited));
+        assert_eq!(watcher.poll(Some(Duration::from_secs(5))).unwrap(), 1);
+        drop(watcher);
+        assert_eq!(watched, [waited]);
+        #[cfg(feature = "async")]
+        assert_eq!(runtime.block_on(stream.next_edge()).ok(), Some(waited));
+    }
+}
@@ -0,0 +1,151 @@
+use crate::gpiochip::read_edge_event;
+use crate::{check_pin, Error, PinEvent, PinFunction, Trigger, GPIO};
+
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::libc;
+use nix::unistd::getpid;
+
+use std::fs::File;
+use std::io::ErrorKind;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+
+// Linux's own; glibc's bindings leave them out. The same on every
+// architecture a Pi runs.
+const F_SETSIG: libc::c_int = 10;
+#[cfg(test)]
+const F_GETSIG: libc::c_int = 11;
+
+
+// `fcntl` commands taking an int that nix doesn't wrap.
+fn fcntl_int(fd: RawFd, command: libc::c_int, argument: libc::c_int) -> nix::Result<libc::c_int> {
+    Errno::result(unsafe { libc::fcntl(fd, command, argument) })
+}
+
+
+/// Has signal `signo` sent to the process whenever an edge fires on one
+/// pin, through the line fd's asynchronous I/O, so a handler runs within
+/// microseconds without a thread polling for it. Set up with
+/// `GPIO::set_signal_on_edge`. Dropping it stops the signals and turns
+/// edge detection off again.
+///
+/// The handler runs on whichever thread the kernel picks, interrupting it
+/// wherever it is, so it may only call async-signal-safe functions: no
+/// allocation, no locks, no `println!`, and none of this crate's methods.
+/// Have it set an atomic flag or write to a pipe, and read the edges with
+/// `next_event` outside it. A real-time signal is queued once per edge and
+/// carries the fd in `siginfo_t::si_fd`, so one handler can serve several
+/// pins; should the queue fill, the kernel falls back to `SIGIO`.
+pub struct EdgeSignal<'a> {
+    gpio: &'a GPIO,
+    pin: u32,
+    signo: i32,
+    file: File,
+}
+
+impl<'a> EdgeSignal<'a> {
+
+    pub fn pin(&self) -> u32 {
+        self.pin
+    }
+
+    pub fn signal(&self) -> i32 {
+        self.signo
+    }
+
+    /// The next edge that's come in, or `None` once every queued one has
+    /// been read. Not for the signal handler itself.
+    pub fn next_event(&self) -> Result<Option<PinEvent>, Error> {
+        loop {
+            match read_edge_event(&self.file) {
+                Ok(Some(event)) if self.gpio.debounce.accept(self.pin, event.timestamp) => return Ok(Some(event)),
+                Ok(_) => continue,
+                Err(error) if error.kind() == ErrorKind::WouldBlock => return Ok(None),
+                Err(error) => return Err(Error::from_io(format!("failed to read an edge event for pin {}", self.pin), error)),
+            }
+        }
+    }
+}
+
+impl<'a> AsRawFd for EdgeSignal<'a> {
+    /// The fd a real-time signal's `si_fd` names for this pin.
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+impl<'a> Drop for EdgeSignal<'a> {
+    fn drop(&mut self) {
+        // Setting the function reconfigures the line without edge flags.
+        let _ = self.gpio.driver.set_function(self.pin, PinFunction::Input);
+    }
+}
+
+
+impl GPIO {
+
+    /// Sends the process signal `signo`, one of the real-time signals from
+    /// `libc::SIGRTMIN()` to `libc::SIGRTMAX()`, for each `trigger` edge on
+    /// `pin`, until the returned `EdgeSignal` is dropped. Install the
+    /// handler first, as the default action for a real-time signal ends
+    /// the process, and see `EdgeSignal` for what the handler may do. Only
+    /// line backends such as gpiochip have an fd to signal from.
+    pub fn set_signal_on_edge(&self, pin: u32, trigger: Trigger, signo: i32) -> Result<EdgeSignal<'_>, Error> {
+        check_pin(pin)?;
+        if !(libc::SIGRTMIN()..=libc::SIGRTMAX()).contains(&signo) {
+            return Err(Error::Other(format!(
+                "signal {} isn't a real-time signal ({} to {})", signo, libc::SIGRTMIN(), libc::SIGRTMAX())));
+        }
+        let signal = EdgeSignal { gpio: self, pin, signo, file: self.driver.edge_event_file(pin, trigger)? };
+        let fd = signal.file.as_raw_fd();
+        let context = |step: &str| format!("failed to {} for edge signals on pin {}", step, pin);
+        fcntl_int(fd, libc::F_SETOWN, getpid().as_raw()).map_err(|e| Error::from_nix(context("set the fd's owner"), e))?;
+        fcntl_int(fd, F_SETSIG, signo).map_err(|e| Error::from_nix(context("set the fd's signal"), e))?;
+        // The event file is already non-blocking, which `next_event` needs.
+        let flags = fcntl(fd, FcntlArg::F_GETFL).map_err(|e| Error::from_nix(context("read the fd's flags"), e))?;
+        fcntl(fd, FcntlArg::F_SETFL(OFlag::from_bits_truncate(flags) | OFlag::O_ASYNC | OFlag::O_NONBLOCK))
+            .map_err(|e| Error::from_nix(context("turn on async I/O"), e))?;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(pin, ?trigger, signo, "edge signal installed");
+        Ok(signal)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EdgeKind, MockGpio};
+
+    #[test]
+    fn test_signal_on_edge_setup() {
+        let mock = MockGpio::new();
+        let gpio = mock.gpio().unwrap();
+        let signo = libc::SIGRTMIN() + 2;
+        // Delivered by default, the signal would end the test run.
+        unsafe { libc::signal(signo, libc::SIG_IGN) };
+        assert!(gpio.set_signal_on_edge(17, Trigger::Rising, libc::SIGUSR1).is_err());
+
+        let signal = gpio.set_signal_on_edge(17, Trigger::Rising, signo).unwrap();
+        let fd = signal.as_raw_fd();
+        assert_eq!(fcntl_int(fd, libc::F_GETOWN, 0).ok(), Some(getpid().as_raw()));
+        assert_eq!(fcntl_int(fd, F_GETSIG, 0).ok(), Some(signo));
+        let flags = OFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFL).unwrap());
+        assert!(flags.contains(OFlag::O_ASYNC | OFlag::O_NONBLOCK));
+
+        // Delivery itself is the kernel's; the edges behind it still read.
+        assert!(signal.next_event().unwrap().is_none());
+        mock.set_input(17, true);
+        mock.set_input(17, false);
+        let event = signal.next_event().unwrap().unwrap();
+        assert_eq!((event.pin, event.edge), (17, EdgeKind::Rising));
+        assert!(signal.next_event().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_signal_on_edge_needs_event_file() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        assert!(matches!(gpio.set_signal_on_edge(17, Trigger::Both, libc::SIGRTMIN()), Err(Error::Unsupported(_))));
+    }
+}
@@ -0,0 +1,250 @@
+use crate::{CallbackId, Error, PinEvent, Trigger, GPIO};
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+
+/// What an `EventChannel` does with an event that arrives while its queue
+/// is full.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Overflow {
+    /// Discard the oldest queued event to make room, so the queue holds the
+    /// latest. The default.
+    DropOldest,
+    /// Discard the new event.
+    DropNewest,
+    /// Wait for room. This holds up every callback on the `GPIO`'s event
+    /// thread until the receiver catches up.
+    Block,
+}
+
+
+struct Queue {
+    events: Mutex<VecDeque<PinEvent>>,
+    // Signalled when an event is queued, and when one is taken.
+    changed: Condvar,
+    capacity: usize,
+    overflow: Overflow,
+    dropped: AtomicU64,
+    closed: AtomicBool,
+}
+
+impl Queue {
+
+    fn push(&self, event: PinEvent) {
+        let mut events = self.events.lock().unwrap();
+        while events.len() >= self.capacity {
+            match self.overflow {
+                Overflow::DropOldest => {
+                    events.pop_front();
+                    self.dropped.fetch_add(1, Ordering::SeqCst);
+                }
+                Overflow::DropNewest => {
+                    self.dropped.fetch_add(1, Ordering::SeqCst);
+                    return;
+                }
+                Overflow::Block => {
+                    if self.closed.load(Ordering::SeqCst) {
+                        return;
+                    }
+                    events = self.changed.wait(events).unwrap();
+                }
+            }
+        }
+        events.push_back(event);
+        self.changed.notify_all();
+    }
+
+    fn pop(&self, deadline: Option<Instant>) -> Option<PinEvent> {
+        let mut events = self.events.lock().unwrap();
+        loop {
+            if let Some(event) = events.pop_front() {
+                self.changed.notify_all();
+                return Some(event);
+            }
+            events = match deadline {
+                None => self.changed.wait(events).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return None;
+                    }
+                    self.changed.wait_timeout(events, deadline - now).unwrap().0
+                }
+            };
+        }
+    }
+}
+
+
+/// Edge events on a set of pins, queued by the `GPIO`'s event thread for
+/// whichever thread receives them, from `GPIO::events_channel`. The pins'
+/// callbacks are removed when the channel is dropped.
+pub struct EventChannel<'a> {
+    gpio: &'a GPIO,
+    pins: Vec<u32>,
+    callbacks: Vec<CallbackId>,
+    queue: Arc<Queue>,
+}
+
+impl<'a> EventChannel<'a> {
+
+    pub const DEFAULT_CAPACITY: usize = 256;
+
+    pub fn pins(&self) -> &[u32] {
+        &self.pins
+    }
+
+    /// Waits for the next event.
+    pub fn recv(&self) -> PinEvent {
+        // Without a deadline, `pop` only returns with an event.
+        loop {
+            if let Some(event) = self.queue.pop(None) {
+                return event;
+            }
+        }
+    }
+
+    /// Waits up to `timeout` for the next event.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<PinEvent> {
+        self.queue.pop(Some(Instant::now() + timeout))
+    }
+
+    pub fn try_recv(&self) -> Option<PinEvent> {
+        self.queue.pop(Some(Instant::now()))
+    }
+
+    /// The number of events waiting.
+    pub fn len(&self) -> usize {
+        self.queue.events.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of events discarded because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.queue.dropped.load(Ordering::SeqCst)
+    }
+}
+
+impl<'a> Drop for EventChannel<'a> {
+    fn drop(&mut self) {
+        // Let a callback blocked on a full queue go before removing it,
+        // which waits for the event thread. Under the lock, so the callback
+        // can't miss it between checking and waiting.
+        {
+            let _events = self.queue.events.lock().unwrap();
+            self.queue.closed.store(true, Ordering::SeqCst);
+            self.queue.changed.notify_all();
+        }
+        for &id in &self.callbacks {
+            let _ = self.gpio.remove_callback(id);
+        }
+    }
+}
+
+
+impl GPIO {
+
+    /// Queues `trigger` edges on each of `pins` for receiving on any
+    /// thread, keeping the latest `EventChannel::DEFAULT_CAPACITY` events.
+    /// The pins' callbacks are taken, as with `on_edge`.
+    pub fn events_channel(&self, pins: &[u32], trigger: Trigger) -> Result<EventChannel<'_>, Error> {
+        self.events_channel_with(pins, trigger, EventChannel::DEFAULT_CAPACITY, Overflow::DropOldest)
+    }
+
+    /// As `events_channel`, queueing up to `capacity` events and handling
+    /// any more as `overflow` says.
+    pub fn events_channel_with(&self, pins: &[u32], trigger: Trigger, capacity: usize, overflow: Overflow)
+        -> Result<EventChannel<'_>, Error>
+    {
+        if capacity == 0 {
+            return Err(Error::Other("an event channel needs room for at least one event".to_string()));
+        }
+        let queue = Arc::new(Queue {
+            events: Mutex::new(VecDeque::with_capacity(capacity)),
+            changed: Condvar::new(),
+            capacity,
+            overflow,
+            dropped: AtomicU64::new(0),
+            closed: AtomicBool::new(false),
+        });
+        // Dropping the channel part-built removes the callbacks installed
+        // so far.
+        let mut channel = EventChannel { gpio: self, pins: pins.to_vec(), callbacks: Vec::new(), queue };
+        for &pin in pins {
+            let queue = Arc::clone(&channel.queue);
+            channel.callbacks.push(self.on_edge(pin, trigger, move |event| queue.push(event))?);
+        }
+        Ok(channel)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EdgeKind, Register};
+
+    fn event(pin: u32) -> PinEvent {
+        PinEvent { pin, edge: EdgeKind::Rising, timestamp: Instant::now() }
+    }
+
+    fn queue(capacity: usize, overflow: Overflow) -> Queue {
+        Queue {
+            events: Mutex::new(VecDeque::new()),
+            changed: Condvar::new(),
+            capacity,
+            overflow,
+            dropped: AtomicU64::new(0),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    #[test]
+    fn test_overflow_policies() {
+        let oldest = queue(2, Overflow::DropOldest);
+        (1..=3).for_each(|pin| oldest.push(event(pin)));
+        assert_eq!(oldest.pop(Some(Instant::now())).map(|event| event.pin), Some(2));
+        assert_eq!(oldest.dropped.load(Ordering::SeqCst), 1);
+
+        let newest = queue(2, Overflow::DropNewest);
+        (1..=3).for_each(|pin| newest.push(event(pin)));
+        assert_eq!(newest.pop(Some(Instant::now())).map(|event| event.pin), Some(1));
+        assert_eq!(newest.dropped.load(Ordering::SeqCst), 1);
+
+        let blocking = Arc::new(queue(1, Overflow::Block));
+        blocking.push(event(1));
+        let pusher = {
+            let blocking = Arc::clone(&blocking);
+            std::thread::spawn(move || blocking.push(event(2)))
+        };
+        assert_eq!(blocking.pop(None).map(|event| event.pin), Some(1));
+        pusher.join().unwrap();
+        assert_eq!(blocking.pop(None).map(|event| event.pin), Some(2));
+        assert_eq!(blocking.dropped.load(Ordering::SeqCst), 0);
+        assert!(blocking.pop(Some(Instant::now() + Duration::from_millis(5))).is_none());
+    }
+
+    #[test]
+    fn test_events_channel() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let channel = gpio.events_channel(&[17, 40], Trigger::Rising).unwrap();
+        assert_eq!(channel.pins(), &[17, 40]);
+        assert!(channel.try_recv().is_none());
+        unsafe { gpio.write_raw(0x44, 1 << 8) };
+        let event = channel.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!((event.pin, event.edge), (40, EdgeKind::Rising));
+
+        // A pin that's taken fails the whole channel and frees the rest.
+        assert!(matches!(gpio.events_channel(&[4, 40], Trigger::Both).err(), Some(Error::PinInUse(40))));
+        assert!(gpio.on_edge(4, Trigger::Both, |_| {}).is_ok());
+        drop(channel);
+        assert_eq!(gpio.read_register(Register::GPREN, 17).ok(), Some(1 << 4));
+        assert!(gpio.events_channel_with(&[17], Trigger::Both, 0, Overflow::Block).is_err());
+    }
+}
@@ -0,0 +1,283 @@
+use crate::debounce::Debounce;
+use crate::gpiochip::read_edge_event;
+use crate::{bit_in_bank, EdgeKind, Error, PinEvent, PinFunction, Register, RegisterMap, Trigger, GPIO};
+
+use nix::poll::{poll, PollFd, PollFlags};
+
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+
+// How often GPEDS is polled while a register-backed callback is installed.
+const REGISTER_POLL_MS: i32 = 1;
+// How long the thread waits on line fds before checking for shutdown and
+// new registrations.
+const IDLE_POLL_MS: i32 = 20;
+
+
+type EdgeCallback = Box<dyn FnMut(PinEvent) + Send>;
+
+/// Identifies a callback installed with `GPIO::on_edge`.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct CallbackId(u64);
+
+enum Source {
+    // Edge detection enabled in the register block; holds the enable
+    // registers this callback turned on.
+    Registers(Vec<Register>),
+    // A line fd from the backend, readable with each edge.
+    Line(File),
+}
+
+struct Registration {
+    id: CallbackId,
+    pin: u32,
+    trigger: Trigger,
+    callback: EdgeCallback,
+    source: Source,
+}
+
+struct Shared {
+    registrations: Mutex<Vec<Registration>>,
+    running: AtomicBool,
+}
+
+/// The background thread behind `GPIO::on_edge`. It is started with the
+/// first callback and stopped when the `GPIO` is dropped.
+pub(crate) struct EventLoop {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+    next_id: AtomicU64,
+}
+
+impl EventLoop {
+
+    // `registers` is the address of the GPIO register block, laid out as
+    // `map` says, or 0 for line backends. The owning `GPIO` stops the
+    // thread before the block is unmapped.
+    fn start(registers: usize, map: RegisterMap, debounce: Arc<Debounce>) -> Result<Self, Error> {
+        let shared = Arc::new(Shared {
+            registrations: Mutex::new(Vec::new()),
+            running: AtomicBool::new(true),
+        });
+        let thread = {
+            let shared = Arc::clone(&shared);
+            std::thread::Builder::new()
+                .name("rustberrypi-events".to_string())
+                .spawn(move || run(&shared, registers, &map, &debounce))
+                .map_err(|e| Error::from_io("failed to start the event loop thread", e))?
+        };
+        Ok(Self { shared, thread: Some(thread), next_id: AtomicU64::new(0) })
+    }
+
+    fn stop(&mut self) {
+        self.shared.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            // A callback that drops the last handle to its GPIO ends up
+            // here on the loop thread itself, which can't join itself.
+            if thread.thread().id() != std::thread::current().id() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+
+fn run(shared: &Shared, registers: usize, map: &RegisterMap, debounce: &Debounce) {
+    while shared.running.load(Ordering::SeqCst) {
+        let mut registrations = shared.registrations.lock().unwrap();
+        let polls_registers = registrations.iter().any(|r| matches!(r.source, Source::Registers(_)));
+        let wait_ms = if polls_registers { REGISTER_POLL_MS } else { IDLE_POLL_MS };
+
+        let mut fds: Vec<PollFd> = registrations.iter()
+            .filter_map(|r| match &r.source {
+                Source::Line(file) => Some(PollFd::new(file.as_raw_fd(), PollFlags::POLLIN)),
+                Source::Registers(_) => None,
+            })
+            .collect();
+        if fds.is_empty() {
+            drop(registrations);
+            std::thread::sleep(std::time::Duration::from_millis(wait_ms as u64));
+            registrations = shared.registrations.lock().unwrap();
+        } else {
+            // EINTR and friends just mean another round.
+            let _ = poll(&mut fds, wait_ms);
+        }
+
+        let mut ready = fds.iter().map(|fd| fd.revents().is_some_and(|events| events.contains(PollFlags::POLLIN)));
+        for registration in registrations.iter_mut() {
+            if let Source::Line(file) = &registration.source {
+                if ready.next() == Some(true) {
+                    // The fd is non-blocking, so this drains what's queued.
+                    loop {
+                        match read_edge_event(file) {
+                            Ok(Some(event)) => dispatch(&mut registration.callback, registration.pin, event, debounce),
+                            Ok(None) => continue,
+                            Err(_) => break,
+                        }
+                    }
+                }
+            }
+        }
+        if polls_registers && registers != 0 {
+            dispatch_register_events(&mut registrations, registers, map, debounce);
+        }
+    }
+}
+
+// Offsets were checked when the pin's callback was installed.
+fn register_word(registers: usize, map: &RegisterMap, register: Register, pin: u32) -> *mut u32 {
+    (registers + map.offset(register, pin).unwrap_or(0)) as *mut u32
+}
+
+fn dispatch_register_events(registrations: &mut [Registration], registers: usize, map: &RegisterMap, debounce: &Debounce) {
+    for registration in registrations.iter_mut() {
+        if !matches!(registration.source, Source::Registers(_)) {
+            continue;
+        }
+        let pin = registration.pin;
+        let bit = 1 << bit_in_bank(pin);
+        let events = unsafe { register_word(registers, map, Register::GPEDS, pin).read_volatile() };
+        if events & bit == 0 {
+            continue;
+        }
+        let seen = Instant::now();
+        let edge = match registration.trigger {
+            Trigger::Rising => EdgeKind::Rising,
+            Trigger::Falling => EdgeKind::Falling,
+            Trigger::Both => {
+                let level = unsafe { register_word(registers, map, Register::GPLEV, pin).read_volatile() };
+                if level & bit != 0 { EdgeKind::Rising } else { EdgeKind::Falling }
+            }
+        };
+        // Write-1-to-clear, leaving other pins' events alone.
+        unsafe { register_word(registers, map, Register::GPEDS, pin).write_volatile(bit) };
+        dispatch(&mut registration.callback, pin, PinEvent { pin, edge, timestamp: seen }, debounce);
+    }
+}
+
+// Runs `callback` unless `pin`'s debounce swallows the event.
+fn dispatch(callback: &mut EdgeCallback, pin: u32, event: PinEvent, debounce: &Debounce) {
+    if !debounce.accept(pin, event.timestamp) {
+        #[cfg(feature = "tracing")]
+        tracing::trace!(pin = event.pin, edge = ?event.edge, "edge dropped by debounce");
+        return;
+    }
+    #[cfg(feature = "tracing")]
+    let _span = tracing::trace_span!("edge_callback", pin = event.pin, edge = ?event.edge).entered();
+    callback(event);
+}
+
+
+impl GPIO {
+
+    /// Calls `callback` from a background thread with each `trigger` edge
+    /// on `pin`. Each pin can have one callback at a time. The thread is
+    /// started with the first callback and shut down when the `GPIO` is
+    /// dropped. Callbacks must not install or remove callbacks themselves.
+    pub fn on_edge(&self, pin: u32, trigger: Trigger, callback: impl FnMut(PinEvent) + Send + 'static)
+        -> Result<CallbackId, Error>
+    {
+        self.check_board_pin(pin)?;
+        self.check_writable()?;
+        let mut event_loop = self.event_loop.lock().unwrap();
+        if event_loop.is_none() {
+            *event_loop = Some(EventLoop::start(self.buffer as usize, self.register_map, Arc::clone(&self.debounce))?);
+        }
+        let event_loop = event_loop.as_ref().unwrap();
+        let mut registrations = event_loop.shared.registrations.lock().unwrap();
+        if registrations.iter().any(|registration| registration.pin == pin) {
+            return Err(Error::PinInUse(pin));
+        }
+
+        let source = if self.buffer.is_null() {
+            Source::Line(self.driver.edge_event_file(pin, trigger)?)
+        } else {
+            let enabled = self.enable_wait_triggers(pin, trigger)?;
+            // Only edges after registration count.
+            if self.poll_event(pin)? {
+                self.clear_event(pin)?;
+            }
+            Source::Registers(enabled)
+        };
+        let id = CallbackId(event_loop.next_id.fetch_add(1, Ordering::SeqCst));
+        #[cfg(feature = "tracing")]
+        tracing::debug!(pin, ?trigger, registers = !self.buffer.is_null(), "edge callback installed");
+        registrations.push(Registration { id, pin, trigger, callback: Box::new(callback), source });
+        Ok(id)
+    }
+
+    /// Removes a callback installed with `on_edge` and turns off the edge
+    /// detection it enabled. Returns whether the callback was still
+    /// installed.
+    pub fn remove_callback(&self, id: CallbackId) -> Result<bool, Error> {
+        let event_loop = self.event_loop.lock().unwrap();
+        let Some(event_loop) = event_loop.as_ref() else {
+            return Ok(false);
+        };
+        let mut registrations = event_loop.shared.registrations.lock().unwrap();
+        let Some(index) = registrations.iter().position(|registration| registration.id == id) else {
+            return Ok(false);
+        };
+        let registration = registrations.remove(index);
+        #[cfg(feature = "tracing")]
+        tracing::debug!(pin = registration.pin, "edge callback removed");
+        match registration.source {
+            Source::Registers(enabled) => self.disable_wait_triggers(registration.pin, &enabled)?,
+            // Setting the function reconfigures the line without edge flags.
+            Source::Line(_) => self.driver.set_function(registration.pin, PinFunction::Input)?,
+        }
+        Ok(true)
+    }
+
+    pub(crate) fn stop_event_loop(&mut self) {
+        if let Some(mut event_loop) = self.event_loop.get_mut().unwrap().take() {
+            event_loop.stop();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_on_edge_dispatches_register_events() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let id = gpio.on_edge(40, Trigger::Both, move |event| sender.lock().unwrap().send(event.edge).unwrap()).unwrap();
+        assert_eq!(gpio.read_register(Register::GPREN, 40).ok(), Some(1 << 8));
+        assert_eq!(gpio.read_register(Register::GPFEN, 40).ok(), Some(1 << 8));
+        assert!(matches!(gpio.on_edge(40, Trigger::Rising, |_| {}), Err(Error::PinInUse(40))));
+
+        unsafe {
+            gpio.write_raw(0x38, 1 << 8);
+            gpio.write_raw(0x44, 1 << 8);
+        }
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(EdgeKind::Rising));
+
+        assert_eq!(gpio.remove_callback(id).ok(), Some(true));
+        assert_eq!(gpio.remove_callback(id).ok(), Some(false));
+        assert_eq!(gpio.read_register(Register::GPREN, 40).ok(), Some(0));
+        assert_eq!(gpio.read_register(Register::GPFEN, 40).ok(), Some(0));
+    }
+
+    #[test]
+    fn test_event_loop_stops_on_drop() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        gpio.on_edge(4, Trigger::Falling, |_| {}).unwrap();
+        let shared = Arc::clone(&gpio.event_loop.lock().unwrap().as_ref().unwrap().shared);
+        drop(gpio);
+        assert!(!shared.running.load(Ordering::SeqCst));
+        // Only the test's reference is left once the thread has exited.
+        assert_eq!(Arc::strong_count(&shared), 1);
+    }
+}
@@ -0,0 +1,254 @@
+//! A C interface, declared in `include/rustberrypi.h`, for C and C++
+//! programs moving off bcm2835 or wiringPi. Pins are BCM GPIO numbers, as
+//! in bcm2835. Functions returning `int` return a non-negative value on
+//! success and -1 on failure, with `rbp_last_error` describing it; waiting
+//! for an edge returns -2 on timeout. `librustberrypi.so` is built from
+//! the `rustberrypi-ffi` crate in `ffi/`, with
+//! `cargo build --release -p rustberrypi-ffi`; this crate's own manifest
+//! only builds an rlib, which `no_std` dependents need.
+
+use crate::{EdgeKind, Error, PinFunction, Pull, Trigger, GPIO};
+
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::time::Duration;
+
+
+const FAILED: c_int = -1;
+const TIMED_OUT: c_int = -2;
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn set_last_error(message: String) {
+    // Messages are formatted by this crate and never contain NULs.
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+}
+
+// Runs `call` on the GPIO behind `gpio`, turning its error or panic into
+// `FAILED` and the last error.
+fn with_gpio(gpio: *const GPIO, call: impl FnOnce(&GPIO) -> Result<c_int, Error>) -> c_int {
+    let gpio = match unsafe { gpio.as_ref() } {
+        Some(gpio) => gpio,
+        None => {
+            set_last_error("the GPIO handle is NULL".to_string());
+            return FAILED;
+        }
+    };
+    match catch_unwind(AssertUnwindSafe(|| call(gpio))) {
+        Ok(Ok(result)) => result,
+        Ok(Err(Error::Timeout(message))) => {
+            set_last_error(message);
+            TIMED_OUT
+        }
+        Ok(Err(error)) => {
+            set_last_error(error.to_string());
+            FAILED
+        }
+        Err(_) => {
+            set_last_error("rustberrypi panicked".to_string());
+            FAILED
+        }
+    }
+}
+
+
+/// Opens the GPIOs as `GPIO::new` does. Returns NULL on failure.
+#[no_mangle]
+pub extern "C" fn rbp_open() -> *mut GPIO {
+    match catch_unwind(GPIO::new) {
+        Ok(Ok(gpio)) => Box::into_raw(Box::new(gpio)),
+        Ok(Err(error)) => {
+            set_last_error(error.to_string());
+            std::ptr::null_mut()
+        }
+        Err(_) => {
+            set_last_error("rustberrypi panicked".to_string());
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Releases a handle from `rbp_open`. NULL is ignored.
+///
+/// # Safety
+///
+/// `gpio` must come from `rbp_open` and not have been closed already.
+#[no_mangle]
+pub unsafe extern "C" fn rbp_close(gpio: *mut GPIO) {
+    if !gpio.is_null() {
+        drop(Box::from_raw(gpio));
+    }
+}
+
+/// The message for the last failure on this thread, valid until the next
+/// failing call on it. Empty if nothing has failed.
+#[no_mangle]
+pub extern "C" fn rbp_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ptr())
+}
+
+/// The pin's level, 0 or 1.
+#[no_mangle]
+pub extern "C" fn rbp_read(gpio: *const GPIO, pin: u32) -> c_int {
+    with_gpio(gpio, |gpio| Ok(gpio.read(pin)? as c_int))
+}
+
+/// Drives the pin low if `level` is 0 and high otherwise.
+#[no_mangle]
+pub extern "C" fn rbp_write(gpio: *const GPIO, pin: u32, level: c_int) -> c_int {
+    with_gpio(gpio, |gpio| {
+        if level != 0 { gpio.set_high(pin)? } else { gpio.set_low(pin)? }
+        Ok(0)
+    })
+}
+
+/// Sets the pin's function from its 3-bit GPFSEL value, as bcm2835's
+/// `bcm2835_gpio_fsel` takes it: 0 for input, 1 for output, 4 for ALT0.
+#[no_mangle]
+pub extern "C" fn rbp_set_function(gpio: *const GPIO, pin: u32, function: c_int) -> c_int {
+    with_gpio(gpio, |gpio| {
+        if !(0..=0b111).contains(&function) {
+            return Err(Error::Other(format!("{} is not a GPFSEL function", function)));
+        }
+        gpio.set_function(pin, PinFunction::from_bits(0, function as u32))?;
+        Ok(0)
+    })
+}
+
+/// The pin's GPFSEL value, as for `rbp_set_function`.
+#[no_mangle]
+pub extern "C" fn rbp_get_function(gpio: *const GPIO, pin: u32) -> c_int {
+    with_gpio(gpio, |gpio| match gpio.get_function(pin)? {
+        PinFunction::Error => Err(Error::Other(format!("pin {} has no GPFSEL function", pin))),
+        function => Ok(function as c_int),
+    })
+}
+
+/// Sets the pin's pull: 0 for none, 1 for up, 2 for down, the BCM2711's
+/// encoding.
+#[no_mangle]
+pub extern "C" fn rbp_set_pull(gpio: *const GPIO, pin: u32, pull: c_int) -> c_int {
+    with_gpio(gpio, |gpio| {
+        let pull = match pull {
+            0 => Pull::None,
+            1 => Pull::Up,
+            2 => Pull::Down,
+            _ => return Err(Error::Other(format!("{} is not a pull", pull))),
+        };
+        gpio.set_pull(pin, pull)?;
+        Ok(0)
+    })
+}
+
+/// The pin's pull, as for `rbp_set_pull`.
+#[no_mangle]
+pub extern "C" fn rbp_get_pull(gpio: *const GPIO, pin: u32) -> c_int {
+    with_gpio(gpio, |gpio| Ok(gpio.get_pull(pin)? as c_int))
+}
+
+/// Waits for an edge on the pin: `trigger` 1 for rising, 2 for falling, 3
+/// for either. A negative `timeout_ms` waits forever. Returns 1 for a
+/// rising edge and 0 for a falling one.
+#[no_mangle]
+pub extern "C" fn rbp_wait_for_edge(gpio: *const GPIO, pin: u32, trigger: c_int, timeout_ms: i64) -> c_int {
+    with_gpio(gpio, |gpio| {
+        let trigger = match trigger {
+            1 => Trigger::Rising,
+            2 => Trigger::Falling,
+            3 => Trigger::Both,
+            _ => return Err(Error::Other(format!("{} is not an edge trigger", trigger))),
+        };
+        let timeout = u64::try_from(timeout_ms).ok().map(Duration::from_millis);
+        Ok((gpio.wait_for_edge(pin, trigger, timeout)?.edge == EdgeKind::Rising) as c_int)
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(rbp_last_error()) }.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_c_interface() {
+        let gpio = Box::into_raw(Box::new(GPIO::open_for_testing_on(Vec::new())));
+        assert_eq!(rbp_set_function(gpio, 17, 1), 0);
+        assert_eq!(rbp_get_function(gpio, 17), 1);
+        assert_eq!(rbp_set_function(gpio, 14, 4), 0);
+        assert_eq!(unsafe { &*gpio }.get_function(14).ok(), Some(PinFunction::Alt0));
+        assert_eq!(rbp_write(gpio, 17, 1), 0);
+        assert_eq!(unsafe { &*gpio }.output_state(17).ok(), Some(Some(true)));
+        assert_eq!(rbp_read(gpio, 17), 0);
+        assert_eq!(rbp_set_pull(gpio, 4, 2), 0);
+        assert_eq!(rbp_get_pull(gpio, 4), 2);
+
+        assert_eq!(rbp_set_function(gpio, 17, 8), -1);
+        assert!(last_error().contains("not a GPFSEL function"));
+        assert_eq!(rbp_read(gpio, 58), -1);
+        assert_eq!(rbp_wait_for_edge(gpio, 4, 4, 0), -1);
+        assert_eq!(rbp_read(std::ptr::null(), 4), -1);
+        assert_eq!(last_error(), "the GPIO handle is NULL");
+        unsafe { rbp_close(gpio) };
+    }
+
+    // The C spelling of a Rust type in an exported signature, ready for a
+    // name to follow.
+    fn c_type(rust: &str) -> &'static str {
+        match rust {
+            "*const GPIO" => "const rbp_gpio *",
+            "*mut GPIO" => "rbp_gpio *",
+            "*const c_char" => "const char *",
+            "c_int" => "int ",
+            "u32" => "uint32_t ",
+            "i64" => "int64_t ",
+            _ => panic!("no C type for {} in rustberrypi.h", rust),
+        }
+    }
+
+    // The header's declaration of an exported function, from its Rust
+    // signature, e.g. `int rbp_read(const rbp_gpio *gpio, uint32_t pin);`.
+    fn c_declaration(signature: &str) -> String {
+        let (name, rest) = signature.split_once('(').unwrap();
+        let (params, ret) = rest.split_once(')').unwrap();
+        let ret = match ret.trim_end_matches('{').trim().strip_prefix("-> ") {
+            Some(ret) => c_type(ret),
+            None => "void ",
+        };
+        let params: Vec<String> = params.split(", ")
+            .filter(|param| !param.is_empty())
+            .map(|param| {
+                let (name, rust) = param.split_once(": ").unwrap();
+                format!("{}{}", c_type(rust), name)
+            })
+            .collect();
+        let params = if params.is_empty() { "void".to_string() } else { params.join(", ") };
+        format!("{}{}({});", ret, name, params)
+    }
+
+    #[test]
+    fn test_header_matches_exported_signatures() {
+        let header = include_str!("../include/rustberrypi.h");
+        let source = include_str!("ffi.rs");
+        let mut expected: Vec<String> = source.lines()
+            .filter_map(|line| line.split("extern \"C\" fn ").nth(1))
+            .map(c_declaration)
+            .collect();
+        let mut declared: Vec<String> = header.lines()
+            .filter(|line| line.starts_with(|c: char| c.is_ascii_alphabetic()) && line.ends_with(");"))
+            .map(str::to_string)
+            .collect();
+        expected.sort();
+        declared.sort();
+        assert_eq!(declared, expected);
+    }
+}
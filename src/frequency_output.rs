@@ -0,0 +1,207 @@
+use crate::{Clock, ClockManager, ClockSource, Error, Mash, PinFunction, Soc, GPIO};
+
+
+// The sources whose rates are fixed, so can be divided to a known
+// frequency. The oscillator comes first, as the steadier of the two.
+const SOURCES: [ClockSource; 2] = [ClockSource::Oscillator, ClockSource::PllD];
+
+const DIVISOR_MAX: u32 = 0xfff;
+const FRACTION_STEPS: f64 = 4096.0;
+
+
+/// How a general-purpose clock is set up to get close to a frequency, and
+/// what comes out.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FrequencyPlan {
+    pub source: ClockSource,
+    pub integer: u32,
+    pub fraction: u32,
+    pub mash: Mash,
+    /// The average frequency of the output.
+    pub frequency_hz: f64,
+    /// The lowest and highest frequency of any one cycle. A fractional
+    /// divisor is met on average by mixing cycles of neighbouring whole
+    /// divisors, so these differ unless the divisor is whole.
+    pub min_hz: f64,
+    pub max_hz: f64,
+}
+
+impl FrequencyPlan {
+
+    /// The closest `soc`'s fixed-rate sources can get to `freq_hz`. A
+    /// whole divisor is chosen over a fractional one that's no closer, and
+    /// the oscillator over PLLD, so the output is as steady as it can be.
+    pub fn new(soc: Soc, freq_hz: f64) -> Result<Self, Error> {
+        if !(freq_hz.is_finite() && freq_hz > 0.0) {
+            return Err(Error::Other(format!("{} Hz isn't a frequency a clock can run at", freq_hz)));
+        }
+        let mut best: Option<FrequencyPlan> = None;
+        for source in SOURCES.iter().copied() {
+            let Some(source_hz) = source.frequency_hz(soc) else { continue };
+            let divisor = source_hz as f64 / freq_hz;
+            let whole = (divisor.round() as u32).clamp(1, DIVISOR_MAX);
+            let mut candidates = vec![Self::with_divisor(source, source_hz, whole, 0, Mash::Integer)];
+            if divisor >= 2.0 && divisor < (DIVISOR_MAX + 1) as f64 {
+                let steps = (divisor * FRACTION_STEPS).round() as u32;
+                let (integer, fraction) = (steps / FRACTION_STEPS as u32, steps % FRACTION_STEPS as u32);
+                if fraction != 0 && integer <= DIVISOR_MAX {
+                    candidates.push(Self::with_divisor(source, source_hz, integer, fraction, Mash::Stage1));
+                }
+            }
+            for candidate in candidates {
+                // Strictly closer only, so earlier, steadier plans win ties.
+                if best.is_none_or(|best| candidate.error_hz(freq_hz) < best.error_hz(freq_hz)) {
+                    best = Some(candidate);
+                }
+            }
+        }
+        best.ok_or_else(|| Error::Unsupported(format!("the {:?} has no fixed-rate clock source", soc)))
+    }
+
+    fn with_divisor(source: ClockSource, source_hz: u32, integer: u32, fraction: u32, mash: Mash) -> Self {
+        let source_hz = source_hz as f64;
+        // The range of whole divisors each MASH stage mixes, from the
+        // datasheet.
+        let (below, above) = match mash {
+            _ if fraction == 0 => (0, 0),
+            Mash::Integer => (0, 0),
+            Mash::Stage1 => (0, 1),
+            Mash::Stage2 => (1, 2),
+            Mash::Stage3 => (3, 4),
+        };
+        FrequencyPlan {
+            source,
+            integer,
+            fraction,
+            mash,
+            frequency_hz: source_hz / (integer as f64 + fraction as f64 / FRACTION_STEPS),
+            min_hz: source_hz / (integer + above) as f64,
+            max_hz: source_hz / (integer - below) as f64,
+        }
+    }
+
+    /// How far the average frequency is from `freq_hz`.
+    pub fn error_hz(&self, freq_hz: f64) -> f64 {
+        (self.frequency_hz - freq_hz).abs()
+    }
+
+    /// The difference between the longest and shortest cycle, in
+    /// nanoseconds. 0 for a whole divisor.
+    pub fn jitter_ns(&self) -> f64 {
+        (1.0 / self.min_hz - 1.0 / self.max_hz) * 1e9
+    }
+}
+
+
+/// A steady square wave from a general-purpose clock on one of its pins,
+/// e.g. GPIO 4, 5 or 6 in ALT0, for clocking external chips. The clock is
+/// stopped and the pin made an input when it's dropped.
+pub struct FrequencyOutput<'a> {
+    clocks: ClockManager,
+    gpio: &'a GPIO,
+    clock: Clock,
+    pin: u32,
+    plan: FrequencyPlan,
+}
+
+impl<'a> FrequencyOutput<'a> {
+
+    /// Starts `pin` at the closest frequency to `freq_hz` its clock can
+    /// make. Maps the clock manager through `/dev/mem`.
+    pub fn new(gpio: &'a GPIO, pin: u32, freq_hz: f64) -> Result<Self, Error> {
+        Self::with_clock_manager(ClockManager::new()?, gpio, pin, freq_hz)
+    }
+
+    pub fn with_clock_manager(clocks: ClockManager, gpio: &'a GPIO, pin: u32, freq_hz: f64) -> Result<Self, Error> {
+        let clock = Clock::for_pin(pin).ok_or_else(
+            || Error::Other(format!("pin {} can't output a general-purpose clock", pin)))?;
+        let plan = FrequencyPlan::new(clocks.soc(), freq_hz)?;
+        clocks.configure(clock, plan.source, plan.integer, plan.fraction, plan.mash)?;
+        clocks.route(gpio, clock, pin)?;
+        Ok(Self { clocks, gpio, clock, pin, plan })
+    }
+
+    pub fn pin(&self) -> u32 {
+        self.pin
+    }
+
+    pub fn clock(&self) -> Clock {
+        self.clock
+    }
+
+    pub fn plan(&self) -> &FrequencyPlan {
+        &self.plan
+    }
+
+    /// The average frequency being output.
+    pub fn frequency_hz(&self) -> f64 {
+        self.plan.frequency_hz
+    }
+
+    /// Moves to the closest frequency to `freq_hz`, and returns how. The
+    /// clock stops briefly while its divisor changes.
+    pub fn set_frequency(&mut self, freq_hz: f64) -> Result<FrequencyPlan, Error> {
+        let plan = FrequencyPlan::new(self.clocks.soc(), freq_hz)?;
+        self.clocks.configure(self.clock, plan.source, plan.integer, plan.fraction, plan.mash)?;
+        self.plan = plan;
+        Ok(plan)
+    }
+}
+
+impl<'a> Drop for FrequencyOutput<'a> {
+    fn drop(&mut self) {
+        let _ = self.clocks.disable(self.clock);
+        let _ = self.gpio.set_function(self.pin, PinFunction::Input);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frequency_plans() {
+        // 19.2 MHz / 10 is whole, on the oscillator.
+        let plan = FrequencyPlan::new(Soc::Bcm2837, 1_920_000.0).unwrap();
+        assert_eq!((plan.source, plan.integer, plan.fraction, plan.mash), (ClockSource::Oscillator, 10, 0, Mash::Integer));
+        assert_eq!((plan.frequency_hz, plan.jitter_ns()), (1_920_000.0, 0.0));
+
+        // 25 MHz is whole only from PLLD.
+        let plan = FrequencyPlan::new(Soc::Bcm2837, 25_000_000.0).unwrap();
+        assert_eq!((plan.source, plan.integer, plan.mash), (ClockSource::PllD, 20, Mash::Integer));
+
+        let plan = FrequencyPlan::new(Soc::Bcm2711, 32_768.0).unwrap();
+        assert_eq!(plan.mash, Mash::Stage1);
+        assert!(plan.error_hz(32_768.0) < 0.01);
+        assert!(plan.min_hz < plan.frequency_hz && plan.frequency_hz < plan.max_hz);
+        assert!(plan.jitter_ns() > 0.0);
+
+        assert!(FrequencyPlan::new(Soc::Bcm2711, 0.0).is_err());
+        assert!(FrequencyPlan::new(Soc::Bcm2711, f64::NAN).is_err());
+    }
+
+    #[test]
+    fn test_frequency_output() {
+        let path = std::env::temp_dir().join(format!("rustberrypi-frequency-{}", std::process::id()));
+        std::fs::write(&path, vec![0u8; 4096]).unwrap();
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let clocks = || ClockManager::open(&path, 0, Soc::Bcm2837).ok().unwrap();
+        assert!(FrequencyOutput::with_clock_manager(clocks(), &gpio, 7, 1e6).is_err());
+
+        let mut output = FrequencyOutput::with_clock_manager(clocks(), &gpio, 4, 1_920_000.0).unwrap();
+        assert_eq!(output.clock(), Clock::Gp0);
+        assert_eq!(gpio.get_function(4).ok(), Some(PinFunction::Alt0));
+        let check = clocks();
+        assert!(check.is_enabled(Clock::Gp0).unwrap());
+        let plan = output.set_frequency(100_000.0).unwrap();
+        assert_eq!(output.frequency_hz(), plan.frequency_hz);
+        assert_eq!(plan.integer, 192);
+
+        drop(output);
+        assert!(!check.is_enabled(Clock::Gp0).unwrap());
+        assert_eq!(gpio.get_function(4).ok(), Some(PinFunction::Input));
+        drop(check);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,1897 @@
+use nix::errno::Errno;
+
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::c_void;
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::backend::RegisterBackend;
+use crate::registers::{GPIO_PINS_PER_BANK, GPIO_PIN_COUNT, REGISTER_SIZE};
+#[cfg(any(test, feature = "mock"))]
+use crate::registers::GPIO_BLOCK_SIZE;
+use crate::{
+    bit_in_bank, memory_barrier, CalibrationData, GpioBackend, GpioBuilder, PinFunction, Pull, Register,
+    RegisterMap, Soc, WritePolicy,
+};
+use crate::board::Board;
+use crate::debounce::Debounce;
+use crate::event_loop::EventLoop;
+#[cfg(feature = "trace")]
+use crate::trace::RegisterTrace;
+#[cfg(feature = "trace")]
+use crate::RegisterAccess;
+
+const GPIO_PIN_MASK: u64 = (1 << GPIO_PIN_COUNT) - 1;
+
+pub(crate) fn check_pin(pin: u32) -> Result<(), Error> {
+    if pin >= GPIO_PIN_COUNT {
+        return Err(Error::InvalidPin { pin, register: None });
+    }
+    Ok(())
+}
+
+// A pin bitmap may only have bits for pins 0 to 57 set.
+pub(crate) fn check_pin_mask(mask: u64) -> Result<(), Error> {
+    match mask & !GPIO_PIN_MASK {
+        0 => Ok(()),
+        invalid => Err(Error::InvalidPin { pin: invalid.trailing_zeros(), register: None }),
+    }
+}
+
+pub(crate) fn pins_in(mask: u64) -> impl Iterator<Item = u32> {
+    (0..GPIO_PIN_COUNT).filter(move |pin| mask & (1 << pin) != 0)
+}
+
+impl Register {
+
+    pub fn to_offset(self, pin: u32) -> Result<usize, Error> {
+        check_pin(pin).map_err(|error| error.with_register(self))?;
+        Ok(self.offset_of(pin))
+    }
+}
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum EdgeTrigger {
+    Rising,
+    Falling,
+    High,
+    Low,
+    AsyncRising,
+    AsyncFalling,
+}
+
+impl EdgeTrigger {
+
+    const ALL: [EdgeTrigger; 6] = [
+        EdgeTrigger::Rising, EdgeTrigger::Falling, EdgeTrigger::High,
+        EdgeTrigger::Low, EdgeTrigger::AsyncRising, EdgeTrigger::AsyncFalling,
+    ];
+
+    pub fn register(self) -> Register {
+        match self {
+            EdgeTrigger::Rising => Register::GPREN,
+            EdgeTrigger::Falling => Register::GPFEN,
+            EdgeTrigger::High => Register::GPHEN,
+            EdgeTrigger::Low => Register::GPLEN,
+            EdgeTrigger::AsyncRising => Register::GPAREN,
+            EdgeTrigger::AsyncFalling => Register::GPAFEN,
+        }
+    }
+}
+
+pub(crate) fn group_edge_masks(configs: &[(u32, EdgeTrigger)]) -> Result<BTreeMap<usize, (Register, u32, u32)>, Error> {
+    let mut groups: BTreeMap<usize, (Register, u32, u32)> = BTreeMap::new();
+    for &(pin, trigger) in configs {
+        let register = trigger.register();
+        let entry = groups.entry(register.to_offset(pin)?).or_insert((register, pin, 0));
+        entry.2 |= 1 << bit_in_bank(pin);
+    }
+    Ok(groups)
+}
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PinSetup {
+    pub pin: u32,
+    pub function: PinFunction,
+    pub pull: Pull,
+    pub initial_level: Option<bool>,
+    pub edge: Option<EdgeTrigger>,
+}
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PinReport {
+    pub pin: u32,
+    pub function: PinFunction,
+    pub pull: Pull,
+    pub level: bool,
+}
+
+impl PinReport {
+
+    pub fn is_default(&self) -> bool {
+        self.function == PinFunction::Input && self.pull == Pull::None
+    }
+}
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PinChange {
+    Function { pin: u32, old: PinFunction, new: PinFunction },
+    Level { pin: u32, old: Option<bool>, new: bool },
+    Pull { pin: u32, old: Pull, new: Pull },
+}
+
+impl PinChange {
+
+    pub fn pin(&self) -> u32 {
+        match *self {
+            PinChange::Function { pin, .. } => pin,
+            PinChange::Level { pin, .. } => pin,
+            PinChange::Pull { pin, .. } => pin,
+        }
+    }
+}
+
+// Level writes aren't audited, as bit-banged protocols make far too many,
+// so only writes that change what a pin drives are logged at debug level.
+#[cfg(feature = "tracing")]
+pub(crate) fn log_level_change(change: &PinChange) {
+    match *change {
+        PinChange::Level { pin, old, new } if old != Some(new) => tracing::debug!(pin, "{}", change),
+        _ => tracing::trace!(pin = change.pin(), "{}", change),
+    }
+}
+
+pub(crate) fn level_name(level: Option<bool>) -> &'static str {
+    match level {
+        None => "undriven",
+        Some(true) => "high",
+        Some(false) => "low",
+    }
+}
+
+impl Display for PinChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PinChange::Function { pin, old, new } => write!(f, "pin {}: {:?} -> {:?}", pin, old, new),
+            PinChange::Level { pin, old, new } =>
+                write!(f, "pin {}: {} -> {}", pin, level_name(*old), level_name(Some(*new))),
+            PinChange::Pull { pin, old, new } => write!(f, "pin {}: pull {:?} -> {:?}", pin, old, new),
+        }
+    }
+}
+
+
+pub(crate) const GPIO_BASE_OFFSET: i64 = 0x200000;
+
+pub(crate) fn detect_peripheral_base() -> Result<i64, Error> {
+    if let Some(base) = crate::device_tree::peripheral_base() {
+        return Ok(base as i64);
+    }
+    match Soc::from_cpuinfo() {
+        Some(soc) => Ok(soc.peripheral_base()),
+        None => Err(Error::UnsupportedBoard(
+            "unable to detect the peripheral base from /proc/device-tree/soc/ranges or /proc/cpuinfo".to_string())),
+    }
+}
+
+
+pub(crate) fn open_file(path: impl Into<PathBuf>) -> Result<std::fs::File, Error> {
+    open_file_with(path, true)
+}
+
+pub(crate) fn open_file_with(path: impl Into<PathBuf>, write: bool) -> Result<std::fs::File, Error> {
+    let path = path.into();
+    let file = OpenOptions::new()
+                .create(false)
+                .read(true)
+                .write(write)
+                .open(&path)
+                .map_err(|e| match e.kind() {
+                    std::io::ErrorKind::PermissionDenied => Error::PermissionDenied { path: path.clone(), source: e },
+                    _ => Error::from_io(format!("failed to open {}", path.display()), e),
+                })?;
+    Ok(file)
+}
+
+
+
+#[derive(Debug)]
+pub enum Error {
+    /// A device node exists but the process may not open it, usually
+    /// because it isn't root or in the `gpio` group.
+    PermissionDenied { path: PathBuf, source: std::io::Error },
+    /// Mapping the register block into memory failed.
+    MmapFailed { context: String, source: nix::Error },
+    /// A pin number outside `0..58`.
+    InvalidPin { pin: u32, register: Option<Register> },
+    /// The SoC or its peripheral base address could not be determined.
+    UnsupportedBoard(String),
+    /// Any other failed open, read or write of a file.
+    Io { context: String, source: std::io::Error },
+    /// A failed system call such as an ioctl.
+    Sys { context: String, source: nix::Error },
+    /// A register access outside the register block, or a register that
+    /// holds an unexpected value.
+    Register { message: String, register: Option<Register>, offset: Option<usize> },
+    /// The backend can't perform the operation.
+    Unsupported(String),
+    /// The write was refused, either because the `GPIO` is read-only, the
+    /// pin belongs to a kernel driver, or by its `WritePolicy`.
+    WriteRejected(String),
+    /// Another `Pin` handle holds the pin.
+    PinInUse(u32),
+    /// `GPIO::take` was called while a taken `GPIO` is still alive.
+    AlreadyTaken,
+    /// A wait ran out of time.
+    Timeout(String),
+    /// An I2C device didn't acknowledge its address or a byte written to it.
+    NoAcknowledge(String),
+    Other(String),
+}
+
+impl Error {
+
+    pub(crate) fn from_nix(context: impl std::string::ToString, source: nix::Error) -> Self {
+        Error::Sys { context: context.to_string(), source }
+    }
+
+    pub(crate) fn from_io(context: impl std::string::ToString, source: std::io::Error) -> Self {
+        Error::Io { context: context.to_string(), source }
+    }
+
+    pub(crate) fn register_error(message: impl std::string::ToString) -> Self {
+        Error::Register { message: message.to_string(), register: None, offset: None }
+    }
+
+    /// Attaches the register being accessed, for errors that carry one.
+    pub fn with_register(mut self, with: Register) -> Self {
+        match &mut self {
+            Error::InvalidPin { register, .. } | Error::Register { register, .. } => *register = Some(with),
+            _ => {}
+        }
+        self
+    }
+
+    /// Attaches the byte offset being accessed, for register errors.
+    pub fn with_offset(mut self, with: usize) -> Self {
+        if let Error::Register { offset, .. } = &mut self {
+            *offset = Some(with);
+        }
+        self
+    }
+
+    pub fn register(&self) -> Option<Register> {
+        match *self {
+            Error::InvalidPin { register, .. } | Error::Register { register, .. } => register,
+            _ => None,
+        }
+    }
+
+    pub fn offset(&self) -> Option<usize> {
+        match *self {
+            Error::Register { offset, .. } => offset,
+            _ => None,
+        }
+    }
+
+    /// The OS error behind this error, if there is one.
+    pub fn errno(&self) -> Option<Errno> {
+        match self {
+            Error::PermissionDenied { source, .. } | Error::Io { source, .. } =>
+                source.raw_os_error().map(Errno::from_i32),
+            Error::MmapFailed { source, .. } | Error::Sys { source, .. } => source.as_errno(),
+            Error::Timeout(_) => Some(Errno::ETIMEDOUT),
+            _ => None,
+        }
+    }
+}
+
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::PermissionDenied { path, .. } => write!(f, "permission denied opening {}", path.display())?,
+            Error::MmapFailed { context, .. } | Error::Io { context, .. } | Error::Sys { context, .. } =>
+                write!(f, "{}", context)?,
+            Error::InvalidPin { pin, .. } =>
+                write!(f, "invalid pin {}, pins are numbered 0 to {}", pin, GPIO_PIN_COUNT - 1)?,
+            Error::PinInUse(pin) => write!(f, "pin {} is already claimed", pin)?,
+            Error::AlreadyTaken => write!(f, "the GPIO peripheral has already been taken")?,
+            Error::UnsupportedBoard(message) | Error::Register { message, .. } | Error::Unsupported(message)
+                | Error::WriteRejected(message) | Error::Timeout(message) | Error::NoAcknowledge(message)
+                | Error::Other(message) =>
+                write!(f, "{}", message)?,
+        }
+        match (self.register(), self.offset()) {
+            (Some(register), Some(offset)) => write!(f, " ({:?} at offset {:#x})", register, offset)?,
+            (Some(register), None) => write!(f, " ({:?})", register)?,
+            (None, Some(offset)) => write!(f, " (offset {:#x})", offset)?,
+            (None, None) => {}
+        }
+        match self.errno() {
+            Some(errno) if !matches!(self, Error::Timeout(_)) => write!(f, ": {}", errno.desc()),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::PermissionDenied { source, .. } | Error::Io { source, .. } => Some(source),
+            Error::MmapFailed { source, .. } | Error::Sys { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) const DEV_MEM_PATH: &str = "/dev/mem";
+pub(crate) const DEV_GPIOMEM_PATH: &str = "/dev/gpiomem";
+pub(crate) const DEV_GPIOMEM0_PATH: &str = "/dev/gpiomem0";
+pub(crate) const DEV_GPIOCHIP_PATH: &str = "/dev/gpiochip0";
+pub(crate) const SYSFS_GPIO_PATH: &str = "/sys/class/gpio";
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Backend {
+    /// The first of `/dev/gpiomem`, `/dev/mem`, `/dev/gpiochip0` and
+    /// `/sys/class/gpio` that can be opened. On a Pi 5, `Rp1` takes the
+    /// place of the first two.
+    Auto,
+    /// `/dev/gpiomem` exposes only the GPIO block at offset 0 and is
+    /// accessible to the `gpio` group, so root isn't required.
+    GpioMem,
+    /// `/dev/mem` maps the GPIO block at its physical address and needs root.
+    DevMem,
+    /// Line requests on `/dev/gpiochip0`, for systems where the registers
+    /// can't be mapped. Only the pin-level API (function, pull, level) is
+    /// available; register-level operations return an error.
+    GpioChip,
+    /// The `/sys/class/gpio` interface, for containers and older kernels.
+    /// Like `GpioChip` it is pin-level only, and it has no pull control.
+    Sysfs,
+    /// The Pi 5's RP1 GPIO registers through `/dev/gpiomem0`. Pin-level
+    /// only like `GpioChip`, whose line events it uses for edges.
+    Rp1,
+}
+
+impl Backend {
+
+    pub(crate) fn path(self) -> Option<&'static str> {
+        match self {
+            Backend::Auto => None,
+            Backend::GpioMem => Some(DEV_GPIOMEM_PATH),
+            Backend::DevMem => Some(DEV_MEM_PATH),
+            Backend::GpioChip => Some(DEV_GPIOCHIP_PATH),
+            Backend::Sysfs => Some(SYSFS_GPIO_PATH),
+            Backend::Rp1 => Some(DEV_GPIOMEM0_PATH),
+        }
+    }
+
+    // /dev/mem is always tried since its absence is reported better by the
+    // open than by skipping it. A Pi 5 has no BCM GPIO block to map, and
+    // its /dev/gpiomem may well be the RP1's.
+    pub(crate) fn candidates(self, soc: Option<Soc>, exists: impl Fn(&str) -> bool) -> Vec<Backend> {
+        match self {
+            Backend::Auto if soc == Some(Soc::Bcm2712) => [Backend::Rp1, Backend::GpioChip, Backend::Sysfs].iter()
+                .copied()
+                .filter(|backend| backend.path().is_some_and(&exists))
+                .collect(),
+            Backend::Auto => [Backend::GpioMem, Backend::DevMem, Backend::GpioChip, Backend::Sysfs].iter()
+                .copied()
+                .filter(|&backend| backend == Backend::DevMem || backend.path().is_some_and(&exists))
+                .collect(),
+            backend => vec![backend],
+        }
+    }
+}
+
+pub(crate) fn check_offset(offset: usize, block_size: usize) -> Result<usize, Error> {
+    let size = REGISTER_SIZE as usize;
+    if !offset.is_multiple_of(size) || offset + size > block_size {
+        return Err(Error::register_error(
+            format!("register offset outside the {:#x} byte register block", block_size))
+            .with_offset(offset));
+    }
+    Ok(offset)
+}
+
+pub(crate) type AuditHook = Box<dyn FnMut(PinChange) + Send>;
+
+// GPFSEL offset -> (first pin seen in that register, new register value).
+pub(crate) type FunctionWords = BTreeMap<usize, (u32, u32)>;
+
+pub struct GPIO {
+    pub(crate) driver: Box<dyn GpioBackend>,
+    // The driver's register block, or null for pin-level backends.
+    pub(crate) buffer: *mut c_void,
+    pub(crate) backend: Option<Backend>,
+    pub(crate) soc: Soc,
+    pub(crate) register_map: RegisterMap,
+    pub(crate) read_only: bool,
+    pub(crate) audit_hook: Mutex<Option<AuditHook>>,
+    pub(crate) write_policy: Mutex<Option<Box<dyn WritePolicy + Send>>>,
+    // Held across every read-modify-write of the function, pull and
+    // edge-detect registers, and across vetting a write with the write
+    // policy and making it, so the state the policy saw is still the
+    // state when the write lands.
+    pub(crate) config_lock: Mutex<()>,
+    // GPSET/GPCLR are write-only, so the levels we last drove are kept here.
+    pub(crate) driven_pins: AtomicU64,
+    pub(crate) driven_levels: AtomicU64,
+    pub(crate) claimed_pins: AtomicU64,
+    pub(crate) calibration: OnceLock<CalibrationData>,
+    pub(crate) line_names: OnceLock<Vec<String>>,
+    pub(crate) board: OnceLock<Board>,
+    pub(crate) kernel_consumers: OnceLock<Vec<Option<String>>>,
+    // Kernel-claimed pins the caller has taken over with `force`.
+    pub(crate) forced_pins: AtomicU64,
+    pub(crate) event_loop: Mutex<Option<EventLoop>>,
+    pub(crate) debounce: Arc<Debounce>,
+    #[cfg(feature = "trace")]
+    pub(crate) trace: Arc<RegisterTrace>,
+    // Whether this is the process's `take`n GPIO.
+    pub(crate) taken: bool,
+}
+
+// `buffer` is the driver's register block, which is Send and Sync along
+// with the driver itself.
+unsafe impl Send for GPIO {}
+unsafe impl Sync for GPIO {}
+
+impl GPIO {
+
+    pub(crate) fn from_backend(driver: Box<dyn GpioBackend>, soc: Soc) -> Self {
+        Self {
+            buffer: driver.registers().unwrap_or(std::ptr::null_mut()),
+            driver,
+            backend: None,
+            soc,
+            register_map: RegisterMap::default(),
+            read_only: false,
+            audit_hook: Mutex::new(None),
+            write_policy: Mutex::new(None),
+            config_lock: Mutex::new(()),
+            driven_pins: AtomicU64::new(0),
+            driven_levels: AtomicU64::new(0),
+            claimed_pins: AtomicU64::new(0),
+            calibration: OnceLock::new(),
+            line_names: OnceLock::new(),
+            board: OnceLock::new(),
+            kernel_consumers: OnceLock::new(),
+            forced_pins: AtomicU64::new(0),
+            event_loop: Mutex::new(None),
+            debounce: Arc::new(Debounce::new()),
+            #[cfg(feature = "trace")]
+            trace: Arc::default(),
+            taken: false,
+        }
+    }
+
+    // Register backends share their trace with the `GPIO`, whose own
+    // register accessors reach the block directly.
+    pub(crate) fn from_registers(driver: RegisterBackend, soc: Soc) -> Self {
+        #[cfg(feature = "trace")]
+        let trace = Arc::clone(&driver.trace);
+        let register_map = driver.register_map;
+        let mut gpio = Self::from_backend(Box::new(driver), soc);
+        gpio.register_map = register_map;
+        #[cfg(feature = "trace")]
+        {
+            gpio.trace = trace;
+        }
+        gpio
+    }
+
+    #[cfg(any(test, feature = "mock"))]
+    pub fn open_for_testing_on(buffer: Vec<u8>) -> Self {
+        Self::from_registers(RegisterBackend::owned(buffer, Soc::Bcm2711), Soc::Bcm2711)
+    }
+
+    #[cfg(any(test, feature = "mock"))]
+    pub fn testing_buffer(&self) -> Vec<u8> {
+        (0..GPIO_BLOCK_SIZE / REGISTER_SIZE as usize)
+            .flat_map(|index| {
+                let word = unsafe { self.read_raw(index * REGISTER_SIZE as usize) };
+                word.to_ne_bytes()
+            })
+            .collect()
+    }
+
+ 	pub fn new() -> Result<Self, Error> {
+        GpioBuilder::new().build()
+    }
+
+    pub fn open(backend: Backend) -> Result<Self, Error> {
+        GpioBuilder::new().backend(backend).build()
+    }
+
+    /// Opens the register backend over `file` rather than `/dev/mem`, with
+    /// the GPIO block `offset` bytes in. The mapping is shared, so a test
+    /// can lay out registers in a scratch file and read back exactly what
+    /// was written. `soc` picks the pull-up/down register scheme.
+    pub fn with_mapping(file: &File, offset: i64, soc: Soc) -> Result<Self, Error> {
+        let driver = RegisterBackend::map_file(file, "the mapped file", offset, soc, false, RegisterMap::default())?;
+        Ok(Self::from_registers(driver, soc))
+    }
+
+    /// Maps the GPIO block at physical address `gpio_base` through
+    /// `/dev/mem`, for BCM-like SoCs and machine models that put it
+    /// elsewhere. `GpioBuilder` also takes a `RegisterMap` for blocks laid
+    /// out differently.
+    pub fn with_base(gpio_base: i64) -> Result<Self, Error> {
+        GpioBuilder::new().backend(Backend::DevMem).gpio_base(gpio_base).build()
+    }
+
+    /// Opens a specific GPIO character device, e.g. `/dev/gpiochip4` for
+    /// the RP1 header pins on a Pi 5 running an older kernel.
+    pub fn open_chip(path: impl Into<PathBuf>) -> Result<Self, Error> {
+        GpioBuilder::new().backend(Backend::GpioChip).chip_path(path).build()
+    }
+
+    /// The backend this `GPIO` was opened with, or `None` for a GPIO that
+    /// isn't backed by a device.
+    pub fn backend(&self) -> Option<Backend> {
+        self.backend
+    }
+
+    pub fn soc(&self) -> Soc {
+        self.soc
+    }
+
+    pub fn set_audit_hook(&self, hook: impl FnMut(PinChange) + Send + 'static) {
+        *self.audit_hook.lock().unwrap() = Some(Box::new(hook));
+    }
+
+    pub fn clear_audit_hook(&self) {
+        *self.audit_hook.lock().unwrap() = None;
+    }
+
+    pub fn is_mapped(&self) -> bool {
+        !self.buffer.is_null() && self.buffer != nix::libc::MAP_FAILED
+    }
+
+    /// Closes the device file the backend keeps open beside its mapping,
+    /// for a process short of descriptors, and returns whether it had one.
+    /// Register access carries on through the mapping, but edge events
+    /// that come from the file, such as the Pi 5's `wait_for_edge`, fail
+    /// from then on. `/dev/mem` and `/dev/gpiomem` are closed once mapped
+    /// anyway, and line backends need theirs, so only the Pi 5's
+    /// `/dev/gpiomem0` backend has one to release.
+    pub fn release_fd(&mut self) -> bool {
+        self.driver.release_fd()
+    }
+
+    /// Probes the backend: a register read when the block is mapped, else a
+    /// query of a pin's function through the line backend.
+    pub fn health_check(&self) -> Result<(), Error> {
+        if self.buffer.is_null() {
+            return self.driver.function(self.board_pins().start).map(|_| ());
+        }
+        if !self.is_mapped() {
+            return Err(Error::Unsupported("GPIO register block is not mapped".to_string()));
+        }
+        // Bits 30-31 of every GPFSEL register are reserved and read as zero.
+        let value = self.read_register(Register::GPFSEL, 0)?;
+        if value & 0xc000_0000 != 0 {
+            return Err(Error::register_error(
+                format!("GPFSEL0 read back {:#010x} with reserved bits set", value))
+                .with_register(Register::GPFSEL)
+                .with_offset(0));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn register_ptr(&self, register: Register, pin: u32) -> Result<*mut u32, Error> {
+        if self.buffer.is_null() {
+            return Err(Error::Unsupported(format!(
+                "{:?} is not accessible through a pin-level backend", register)));
+        }
+        Ok(self.buffer.wrapping_add(self.register_map.offset(register, pin)?) as *mut u32)
+    }
+
+    /// Reads the word of `register` that holds `pin`'s bits.
+    pub fn read_register(&self, register: Register, pin: u32) -> Result<u32, Error> {
+        let ptr = self.register_ptr(register, pin)?;
+        let value = unsafe { ptr.read_volatile() };
+        #[cfg(feature = "trace")]
+        self.trace.record(RegisterAccess::Read, ptr as usize - self.buffer as usize, value);
+        Ok(value)
+    }
+
+    /// `read_register` followed by a memory barrier, so that the value is
+    /// not reordered with accesses to another peripheral made afterwards.
+    pub fn read_register_fenced(&self, register: Register, pin: u32) -> Result<u32, Error> {
+        let value = self.read_register(register, pin)?;
+        memory_barrier();
+        Ok(value)
+    }
+
+    /// `write_register` preceded by a memory barrier, so that accesses to
+    /// another peripheral made before it complete first.
+    pub fn write_register_fenced(&self, register: Register, pin: u32, value: u32) -> Result<(), Error> {
+        memory_barrier();
+        self.write_register(register, pin, value)
+    }
+
+    /// Writes the word of `register` that holds `pin`'s bits. The write
+    /// bypasses the write policy and audit hook, and replaces the bits of
+    /// every other pin in the word.
+    pub fn write_register(&self, register: Register, pin: u32, value: u32) -> Result<(), Error> {
+        self.check_writable()?;
+        let ptr = self.register_ptr(register, pin)?;
+        unsafe { ptr.write_volatile(value) };
+        #[cfg(feature = "trace")]
+        self.trace.record(RegisterAccess::Write, ptr as usize - self.buffer as usize, value);
+        Ok(())
+    }
+
+    /// Reads the word at byte `offset` into the GPIO register block, for
+    /// registers this crate does not model.
+    ///
+    /// # Safety
+    ///
+    /// The block must be mapped (see `is_mapped`) and `offset` must be
+    /// 4-byte aligned and less than 0x100. Neither is checked.
+    #[inline]
+    pub unsafe fn read_raw(&self, offset: usize) -> u32 {
+        (self.buffer.wrapping_add(offset) as *const u32).read_volatile()
+    }
+
+    /// Writes the word at byte `offset` into the GPIO register block,
+    /// bypassing the read-only flag, write policy and audit hook.
+    ///
+    /// # Safety
+    ///
+    /// As for `read_raw`, and the block must be mapped writable.
+    #[inline]
+    pub unsafe fn write_raw(&self, offset: usize, value: u32) {
+        (self.buffer.wrapping_add(offset) as *mut u32).write_volatile(value)
+    }
+
+    // The word at byte `offset` into the register block, checked against
+    // the block.
+    fn checked_raw_ptr(&self, offset: usize) -> Result<*mut u32, Error> {
+        if !self.is_mapped() {
+            return Err(Error::Unsupported("the GPIO register block isn't mapped by this backend".to_string()));
+        }
+        check_offset(offset, self.register_map.block_size())?;
+        Ok(self.buffer.wrapping_add(offset) as *mut u32)
+    }
+
+    /// `read_raw` with its conditions checked: fails unless the block is
+    /// mapped and `offset` is an aligned word within it, per the
+    /// `RegisterMap`'s block size.
+    ///
+    /// # Safety
+    ///
+    /// The word must be safe to read on the hardware at hand. The GPIO
+    /// registers have no read side effects, but a custom block size can
+    /// take in words that aren't GPIO registers.
+    pub unsafe fn read_register_at(&self, offset: usize) -> Result<u32, Error> {
+        let ptr = self.checked_raw_ptr(offset)?;
+        let value = ptr.read_volatile();
+        #[cfg(feature = "trace")]
+        self.trace.record(RegisterAccess::Read, offset, value);
+        Ok(value)
+    }
+
+    /// `write_raw` with the same checks as `read_register_at`, which also
+    /// refuses to write through a read-only `GPIO`. The write policy and
+    /// audit hook are still bypassed, and the levels `output_state`
+    /// reports aren't updated.
+    ///
+    /// # Safety
+    ///
+    /// As for `read_register_at`. The write may also reconfigure pins that
+    /// `Pin` handles, drivers or the kernel are using behind their backs.
+    pub unsafe fn write_register_at(&self, offset: usize, value: u32) -> Result<(), Error> {
+        self.check_writable()?;
+        let ptr = self.checked_raw_ptr(offset)?;
+        ptr.write_volatile(value);
+        #[cfg(feature = "trace")]
+        self.trace.record(RegisterAccess::Write, offset, value);
+        Ok(())
+    }
+
+    pub(crate) fn audit(&self, change: PinChange) {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(pin = change.pin(), "{}", change);
+        if let Some(hook) = self.audit_hook.lock().unwrap().as_mut() {
+            hook(change);
+        }
+    }
+
+    pub fn set_function(&self, pin: u32, function: PinFunction) -> Result<(), Error> {
+        if function == PinFunction::Error {
+            // Its 0b1000 value would spill into the next pin's field
+            return Err(Error::register_error(format!("cannot set pin {} to PinFunction::Error", pin))
+                .with_register(Register::GPFSEL));
+        }
+        let guard = self.config_lock.lock().unwrap();
+        let change = PinChange::Function { pin, old: self.get_function(pin)?, new: function };
+        self.check_policy(&change)?;
+        self.driver.set_function(pin, function)?;
+        drop(guard);
+        self.audit(change);
+        Ok(())
+    }
+
+    pub(crate) fn plan_function_words(&self, pins: &[u32], function: PinFunction) -> Result<(FunctionWords, Vec<PinChange>), Error> {
+        let mut words: BTreeMap<usize, (u32, u32)> = BTreeMap::new();
+        let mut changes = Vec::with_capacity(pins.len());
+        for &pin in pins {
+            let current = self.read_register(Register::GPFSEL, pin)?;
+            let (_, value) = words.entry(Register::GPFSEL.to_offset(pin)?).or_insert((pin, current));
+            changes.push(PinChange::Function { pin, old: PinFunction::from_bits(pin, *value), new: function });
+            *value = *value & PinFunction::clear_mask(pin) | function.to_bits(pin);
+        }
+        Ok((words, changes))
+    }
+
+    /// Moves a block of pins to `function` with as small a window as
+    /// possible between the first and last pin switching. Every affected
+    /// GPFSEL word is read and recomputed up front, then the words are
+    /// written back-to-back. Pins sharing a GPFSEL register switch
+    /// together; pins in different registers can't be switched atomically.
+    pub fn switch_to_alt(&self, pins: &[u32], function: PinFunction) -> Result<(), Error> {
+        let guard = self.config_lock.lock().unwrap();
+        let (words, changes) = self.plan_function_words(pins, function)?;
+        self.check_policies(&changes)?;
+        for &(pin, value) in words.values() {
+            self.write_register(Register::GPFSEL, pin, value)?;
+        }
+        drop(guard);
+        for change in changes {
+            self.audit(change);
+        }
+        Ok(())
+    }
+
+    pub fn get_function(&self, pin: u32) -> Result<PinFunction, Error> {
+        self.check_board_pin(pin)?;
+        self.driver.function(pin)
+    }
+
+    pub fn set_pull(&self, pin: u32, pull: Pull) -> Result<(), Error> {
+        let guard = self.config_lock.lock().unwrap();
+        let change = PinChange::Pull { pin, old: self.get_pull(pin)?, new: pull };
+        self.check_policy(&change)?;
+        self.driver.set_pull(pin, pull)?;
+        drop(guard);
+        self.audit(change);
+        Ok(())
+    }
+
+    /// On the BCM2835-BCM2837 the pull latches can't be read, so this
+    /// returns the last pull set through this `GPIO` (`Pull::None` if none).
+    pub fn get_pull(&self, pin: u32) -> Result<Pull, Error> {
+        self.check_board_pin(pin)?;
+        self.driver.pull(pin)
+    }
+
+    /// Puts `pin` back to its power-on state: an input with no pull.
+    pub fn reset_pin(&self, pin: u32) -> Result<(), Error> {
+        self.set_function(pin, PinFunction::Input)?;
+        self.set_pull(pin, Pull::None)
+    }
+
+    /// Runs `f` with `pin` pulled `pull`, to read a jumper say, then puts
+    /// back the pull it had, even if `f` panics. A failure to restore it
+    /// after a panic goes unreported.
+    ///
+    /// This returns a `Result` rather than `f`'s value alone because
+    /// applying the pull can fail, and then `f` isn't run, since it would
+    /// read the pin with the wrong pull. A failure to restore the pull is
+    /// reported the same way, as the pin would be left pulled.
+    pub fn with_pull<R>(&self, pin: u32, pull: Pull, f: impl FnOnce() -> R) -> Result<R, Error> {
+        struct Restore<'a> {
+            gpio: &'a GPIO,
+            pin: u32,
+            pull: Option<Pull>,
+        }
+
+        impl Drop for Restore<'_> {
+            fn drop(&mut self) {
+                if let Some(pull) = self.pull.take() {
+                    let _ = self.gpio.set_pull(self.pin, pull);
+                }
+            }
+        }
+
+        let previous = self.get_pull(pin)?;
+        self.set_pull(pin, pull)?;
+        let mut restore = Restore { gpio: self, pin, pull: Some(previous) };
+        let result = f();
+        restore.pull = None;
+        self.set_pull(pin, previous)?;
+        Ok(result)
+    }
+
+    /// Makes `pin` an input with `expected` pull, waits `settle` for the
+    /// line to move, then reports whether the pull took: it reads back, and
+    /// the pin sits at the level the pull leaves it at. This assumes
+    /// nothing else drives the pin; one wired to a device reads whatever
+    /// that drives. `Pull::None` leaves the pin floating, with no level to
+    /// check, so only the read-back counts.
+    pub fn verify_pull(&self, pin: u32, expected: Pull, settle: Duration) -> Result<bool, Error> {
+        self.set_function(pin, PinFunction::Input)?;
+        self.set_pull(pin, expected)?;
+        std::thread::sleep(settle);
+        if self.get_pull(pin)? != expected {
+            return Ok(false);
+        }
+        Ok(match expected {
+            Pull::Up => self.read(pin)?,
+            Pull::Down => !self.read(pin)?,
+            Pull::None => true,
+        })
+    }
+
+    pub fn set_high(&self, pin: u32) -> Result<(), Error> {
+        let _guard = self.config_lock.lock().unwrap();
+        self.drive(pin, true)
+    }
+
+    pub fn set_low(&self, pin: u32) -> Result<(), Error> {
+        let _guard = self.config_lock.lock().unwrap();
+        self.drive(pin, false)
+    }
+
+    // `set_high` or `set_low`, for a caller holding `config_lock`.
+    fn drive(&self, pin: u32, level: bool) -> Result<(), Error> {
+        let change = PinChange::Level { pin, old: self.output_state(pin)?, new: level };
+        self.check_policy(&change)?;
+        self.driver.write(pin, level)?;
+        #[cfg(feature = "tracing")]
+        log_level_change(&change);
+        if level {
+            self.driven_levels.fetch_or(1 << pin, Ordering::SeqCst);
+        } else {
+            self.driven_levels.fetch_and(!(1 << pin), Ordering::SeqCst);
+        }
+        self.driven_pins.fetch_or(1 << pin, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Drives every pin in the `set` bitmap high and every pin in `clear`
+    /// low. On register backends each GPSET/GPCLR bank is a single write, so
+    /// pins in the same bank change together. Every change is vetted by the
+    /// write policy, against the board as the rest of the batch leaves it,
+    /// before anything is written.
+    pub fn write_mask(&self, set: u64, clear: u64) -> Result<(), Error> {
+        check_pin_mask(set)?;
+        check_pin_mask(clear)?;
+        if set & clear != 0 {
+            return Err(Error::Other(format!("pins {:#x} are in both the set and clear masks", set & clear)));
+        }
+        let _guard = self.config_lock.lock().unwrap();
+        let changes = pins_in(set | clear)
+            .map(|pin| Ok(PinChange::Level { pin, old: self.output_state(pin)?, new: set & (1 << pin) != 0 }))
+            .collect::<Result<Vec<_>, Error>>()?;
+        self.check_policies(&changes)?;
+
+        if self.buffer.is_null() {
+            for pin in pins_in(set | clear) {
+                self.driver.write(pin, set & (1 << pin) != 0)?;
+            }
+        } else {
+            memory_barrier();
+            for &(register, mask) in [(Register::GPSET, set), (Register::GPCLR, clear)].iter() {
+                for &bank_pin in [0, GPIO_PINS_PER_BANK].iter() {
+                    let word = (mask >> bank_pin) as u32;
+                    if word != 0 {
+                        self.write_register(register, bank_pin, word)?;
+                    }
+                }
+            }
+        }
+        #[cfg(feature = "tracing")]
+        for change in &changes {
+            log_level_change(change);
+        }
+        self.driven_levels.fetch_or(set, Ordering::SeqCst);
+        self.driven_levels.fetch_and(!clear, Ordering::SeqCst);
+        self.driven_pins.fetch_or(set | clear, Ordering::SeqCst);
+        Ok(())
+    }
+
+    pub fn set_mask(&self, mask: u64) -> Result<(), Error> {
+        self.write_mask(mask, 0)
+    }
+
+    pub fn clear_mask(&self, mask: u64) -> Result<(), Error> {
+        self.write_mask(0, mask)
+    }
+
+    /// Inverts `pin`'s level. The level is read and written under one lock,
+    /// so toggles from several threads can't cancel each other out.
+    pub fn toggle(&self, pin: u32) -> Result<(), Error> {
+        let _guard = self.config_lock.lock().unwrap();
+        let high = match self.output_state(pin)? {
+            Some(level) => level,
+            None => self.read(pin)?,
+        };
+        self.drive(pin, !high)
+    }
+
+    pub fn output_state(&self, pin: u32) -> Result<Option<bool>, Error> {
+        self.check_board_pin(pin)?;
+        if self.driven_pins.load(Ordering::SeqCst) & (1 << pin) == 0 {
+            return Ok(None);
+        }
+        Ok(Some(self.driven_levels.load(Ordering::SeqCst) & (1 << pin) != 0))
+    }
+
+
+    pub fn read(&self, pin: u32) -> Result<bool, Error> {
+        self.check_board_pin(pin)?;
+        self.driver.read(pin)
+    }
+
+    /// The levels of all the SoC's pins as a bitmap, bit `n` for pin `n`.
+    /// Register backends read both GPLEV banks back-to-back; line backends
+    /// fall back to reading the pins one at a time.
+    pub fn read_all(&self) -> Result<u64, Error> {
+        if self.buffer.is_null() {
+            let mut levels = 0;
+            for pin in self.board_pins() {
+                levels |= (self.driver.read(pin)? as u64) << pin;
+            }
+            return Ok(levels);
+        }
+        let low = self.read_register(Register::GPLEV, 0)? as u64;
+        let high = self.read_register(Register::GPLEV, GPIO_PINS_PER_BANK)? as u64;
+        memory_barrier();
+        Ok((high << GPIO_PINS_PER_BANK | low) & GPIO_PIN_MASK)
+    }
+
+    /// The levels of `pins`, in order, from a single `read_all`.
+    pub fn levels_for(&self, pins: &[u32]) -> Result<Vec<bool>, Error> {
+        for &pin in pins {
+            self.check_board_pin(pin)?;
+        }
+        let levels = self.read_all()?;
+        Ok(pins.iter().map(|&pin| levels & (1 << pin) != 0).collect())
+    }
+
+    pub fn pin_report(&self, pin: u32) -> Result<PinReport, Error> {
+        Ok(PinReport {
+            pin,
+            function: self.get_function(pin)?,
+            pull: self.get_pull(pin)?,
+            level: self.read(pin)?,
+        })
+    }
+
+    pub fn diff_against_default(&self) -> Result<Vec<PinReport>, Error> {
+        let mut reports = Vec::new();
+        for pin in self.board_pins() {
+            let report = self.pin_report(pin)?;
+            if !report.is_default() {
+                reports.push(report);
+            }
+        }
+        Ok(reports)
+    }
+
+    pub fn function_histogram(&self) -> Result<HashMap<PinFunction, u32>, Error> {
+        let mut histogram = HashMap::new();
+        for pin in self.board_pins() {
+            *histogram.entry(self.get_function(pin)?).or_insert(0) += 1;
+        }
+        Ok(histogram)
+    }
+
+    pub fn metrics(&self) -> Result<String, Error> {
+        use std::fmt::Write;
+
+        let mut out = String::new();
+        out.push_str("# HELP gpio_pin_level Current level of the GPIO pin (GPLEV).\n");
+        out.push_str("# TYPE gpio_pin_level gauge\n");
+        for pin in self.board_pins() {
+            let _ = writeln!(out, "gpio_pin_level{{pin=\"{}\"}} {}", pin, self.read(pin)? as u8);
+        }
+        out.push_str("# HELP gpio_pin_function Function select bits of the GPIO pin (GPFSEL).\n");
+        out.push_str("# TYPE gpio_pin_function gauge\n");
+        for pin in self.board_pins() {
+            let function = self.get_function(pin)?;
+            let _ = writeln!(out, "gpio_pin_function{{pin=\"{}\",function=\"{:?}\"}} {}", pin, function, function as u32);
+        }
+        Ok(out)
+    }
+
+    pub(crate) fn write_verified(&self, register: Register, pin: u32, value: u32) -> Result<(), Error> {
+        self.write_register(register, pin, value)?;
+        let readback = self.read_register(register, pin)?;
+        if readback != value {
+            return Err(Error::register_error(
+                format!("register read back {:#010x} after writing {:#010x}", readback, value))
+                .with_register(register)
+                .with_offset(register.to_offset(pin)?));
+        }
+        Ok(())
+    }
+
+    pub fn enable_edge_detect_bulk(&self, configs: &[(u32, EdgeTrigger)]) -> Result<(), Error> {
+        let _guard = self.config_lock.lock().unwrap();
+        for (register, pin, mask) in group_edge_masks(configs)?.into_values() {
+            let value = self.read_register(register, pin)?;
+            self.write_verified(register, pin, value | mask)?;
+        }
+        Ok(())
+    }
+
+    pub fn enable_edge_detect(&self, pin: u32, trigger: EdgeTrigger) -> Result<(), Error> {
+        self.enable_edge_detect_bulk(&[(pin, trigger)])
+    }
+
+    /// Turns off every trigger on `pin`. Events already latched in GPEDS
+    /// stay set until `clear_event`.
+    pub fn disable_edge_detect(&self, pin: u32) -> Result<(), Error> {
+        let _guard = self.config_lock.lock().unwrap();
+        for trigger in EdgeTrigger::ALL.iter() {
+            let register = trigger.register();
+            let value = self.read_register(register, pin)?;
+            let bit = 1 << bit_in_bank(pin);
+            if value & bit != 0 {
+                self.write_verified(register, pin, value & !bit)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether an enabled trigger has fired on `pin` since its event was
+    /// last cleared.
+    pub fn poll_event(&self, pin: u32) -> Result<bool, Error> {
+        let value = self.read_register(Register::GPEDS, pin)?;
+        Ok((value >> bit_in_bank(pin)) & 1 == 1)
+    }
+
+    // GPEDS bits are cleared by writing 1, so only the pin's own bit is
+    // written and the other pins' pending events are left alone.
+    pub fn clear_event(&self, pin: u32) -> Result<(), Error> {
+        self.write_register(Register::GPEDS, pin, 1 << bit_in_bank(pin))
+    }
+
+    /// Applies each setup in the order that minimises glitches on the
+    /// line: the pull is settled and the output level set before the pin
+    /// becomes an output, and edge detection is enabled last so
+    /// reconfiguration doesn't raise spurious events. Register backends
+    /// latch the level; line backends hold it as pending and request the
+    /// output at it. `Pull::None` leaves the pin's pull as it is.
+    pub fn configure(&self, setups: &[PinSetup]) -> Result<(), Error> {
+        for setup in setups.iter().filter(|setup| setup.pull != Pull::None) {
+            self.set_pull(setup.pin, setup.pull)?;
+        }
+        for setup in setups {
+            match setup.initial_level {
+                Some(true) => self.set_high(setup.pin)?,
+                Some(false) => self.set_low(setup.pin)?,
+                None => {}
+            }
+            self.set_function(setup.pin, setup.function)?;
+        }
+        let edges: Vec<(u32, EdgeTrigger)> = setups.iter()
+            .filter_map(|setup| setup.edge.map(|edge| (setup.pin, edge)))
+            .collect();
+        self.enable_edge_detect_bulk(&edges)
+    }
+
+    /// Variant of `read` for tight bit-banging loops: a single load from
+    /// GPLEV, without the register trace or `tracing` events. Only works
+    /// with the register block mapped; line backends return
+    /// `Error::Unsupported`.
+    #[inline]
+    pub fn read_pin_fast(&self, pin: u32) -> Result<bool, Error> {
+        if !self.is_mapped() {
+            return Err(Error::Unsupported("read_pin_fast needs the GPIO register block mapped".to_string()));
+        }
+        self.check_board_pin(pin)?;
+        let offset = self.register_map.word_offset(Register::GPLEV, pin)
+            .ok_or(Error::InvalidPin { pin, register: Some(Register::GPLEV) })?;
+        // Mapped, and the offset is aligned and inside the block.
+        let value = unsafe { self.read_raw(offset) };
+        Ok((value >> bit_in_bank(pin)) & 1 == 1)
+    }
+
+    pub fn wait_for_level(&self, pin: u32, level: bool, timeout: Duration) -> Result<Duration, Error> {
+        let start = Instant::now();
+        loop {
+            if self.read(pin)? == level {
+                return Ok(start.elapsed());
+            }
+            if start.elapsed() >= timeout {
+                return Err(Error::Timeout(
+                    format!("pin {} did not go {} within {:?}", pin, if level { "high" } else { "low" }, timeout)));
+            }
+            std::thread::yield_now();
+        }
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::identity_op)]
+mod tests {
+    use super::*;
+    use crate::{bank_of, BoardState, MockGpio, MockOperation, WritePolicy, BANK_COUNT};
+    use std::sync::Arc;
+
+    fn test_gpio() -> GPIO {
+        GPIO::open_for_testing_on(Vec::new())
+    }
+
+    fn write_word(gpio: &GPIO, offset: usize, value: u32) {
+        unsafe { gpio.write_raw(offset, value) }
+    }
+
+    fn read_word(gpio: &GPIO, offset: usize) -> u32 {
+        unsafe { gpio.read_raw(offset) }
+    }
+
+    #[test]
+    fn test_register_gpfsel_to_offset() {
+        let gpfsel0 = Register::GPFSEL;
+        let gpfsel1 = Register::GPFSEL;
+        let gpfsel2 = Register::GPFSEL;
+        let gpfsel3 = Register::GPFSEL;
+        let gpfsel4 = Register::GPFSEL;
+        let gpfsel5 = Register::GPFSEL;
+        
+        assert_eq!(gpfsel0.to_offset(5).unwrap(),  0x00);
+        assert_eq!(gpfsel1.to_offset(15).unwrap(), 0x04);
+        assert_eq!(gpfsel2.to_offset(25).unwrap(), 0x08);
+        assert_eq!(gpfsel3.to_offset(35).unwrap(), 0x0c);
+        assert_eq!(gpfsel4.to_offset(45).unwrap(), 0x10);
+        assert_eq!(gpfsel5.to_offset(55).unwrap(), 0x14);
+    }
+
+
+    #[test]
+    fn test_register_2regcntrl_to_offset() {
+        let gpset0 = Register::GPSET;
+        let gpset1 = Register::GPSET;
+
+        assert_eq!(gpset0.to_offset(5).unwrap(),  0x1c + 0x00);
+        assert_eq!(gpset1.to_offset(45).unwrap(), 0x1c + 0x04);
+    }
+
+
+    #[test]
+    fn test_pullup_pulldown_control_to_offset() {
+        let gp_pup_pud0 = Register::GPPUPPDNCNTRL;
+        let gp_pup_pud1 = Register::GPPUPPDNCNTRL;
+        let gp_pup_pud2 = Register::GPPUPPDNCNTRL;
+        let gp_pup_pud3 = Register::GPPUPPDNCNTRL;
+    
+        assert_eq!(gp_pup_pud0.to_offset(8).unwrap(),    0xe4 + 0x00);
+        assert_eq!(gp_pup_pud1.to_offset(8+16).unwrap(), 0xe4 + 0x04);
+        assert_eq!(gp_pup_pud2.to_offset(8+32).unwrap(), 0xe4 + 0x08);
+        assert_eq!(gp_pup_pud3.to_offset(8+48).unwrap(), 0xe4 + 0x0c);
+
+    }
+
+
+    #[test]
+    fn test_pinfunction_to_bits() {
+        let pin32: u32 = 32;
+        let pin5: u32 = 5;
+        let output = PinFunction::Output;
+
+        assert_eq!(output.to_bits(pin32),         0b001000000);
+        assert_eq!(output.to_bits(pin5), 0b001000000000000000);
+    }
+
+    #[test]
+    fn test_pinfunction_clear_mask() {
+        let pin32: u32 = 32;
+        let pin5: u32 = 5;
+
+        let mask32: u32 = !(0b111000000);
+        let mask5: u32 = !(0b111000000000000000); 
+        assert_eq!(PinFunction::clear_mask(pin32), mask32);
+        assert_eq!(PinFunction::clear_mask(pin5), mask5);
+
+    }
+
+
+    #[test]
+    fn test_pinfunction_from_bits() {
+        let bits = PinFunction::Output.to_bits(17) | PinFunction::Alt0.to_bits(12);
+        assert_eq!(PinFunction::from_bits(17, bits), PinFunction::Output);
+        assert_eq!(PinFunction::from_bits(12, bits), PinFunction::Alt0);
+        assert_eq!(PinFunction::from_bits(10, bits), PinFunction::Input);
+    }
+
+
+    #[test]
+    fn test_audit_hook_records_function_change() {
+        let gpio = test_gpio();
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&changes);
+        gpio.set_audit_hook(move |change| sink.lock().unwrap().push(change));
+
+        gpio.set_function(17, PinFunction::Output).unwrap();
+        gpio.set_function(17, PinFunction::Alt5).unwrap();
+
+        let changes = changes.lock().unwrap();
+        assert_eq!(changes[0], PinChange::Function { pin: 17, old: PinFunction::Input, new: PinFunction::Output });
+        assert_eq!(changes[1], PinChange::Function { pin: 17, old: PinFunction::Output, new: PinFunction::Alt5 });
+        assert_eq!(changes[0].to_string(), "pin 17: Input -> Output");
+    }
+
+
+    #[test]
+    fn test_check_offset_error_carries_offset() {
+        assert_eq!(check_offset(0x34, GPIO_BLOCK_SIZE).ok(), Some(0x34));
+
+        let error = check_offset(GPIO_BLOCK_SIZE, GPIO_BLOCK_SIZE).unwrap_err();
+        assert_eq!(error.offset(), Some(GPIO_BLOCK_SIZE));
+        assert!(error.to_string().contains("offset 0x100"));
+
+        let error = check_offset(0x1e, GPIO_BLOCK_SIZE).unwrap_err().with_register(Register::GPSET);
+        assert_eq!(error.register(), Some(Register::GPSET));
+        assert!(error.to_string().contains("GPSET at offset 0x1e"));
+    }
+
+
+    #[test]
+    fn test_wait_for_level_reached_before_timeout() {
+        let gpio = test_gpio();
+        let level_register = gpio.buffer.wrapping_add(0x34) as usize;
+        let driver = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            unsafe { (level_register as *mut u32).write_volatile(1 << 17) }
+        });
+
+        let waited = gpio.wait_for_level(17, true, Duration::from_secs(5)).ok().unwrap();
+        driver.join().unwrap();
+        assert!(waited >= Duration::from_millis(20));
+    }
+
+
+    #[test]
+    fn test_wait_for_level_times_out() {
+        let gpio = test_gpio();
+        write_word(&gpio, 0x34, 1 << 17);
+
+        let error = gpio.wait_for_level(17, false, Duration::from_millis(20)).unwrap_err();
+        assert!(matches!(error, Error::Timeout(_)));
+        assert_eq!(error.errno(), Some(Errno::ETIMEDOUT));
+
+        write_word(&gpio, 0x34, 0);
+        assert!(gpio.wait_for_level(17, false, Duration::from_millis(20)).is_ok());
+    }
+
+
+    #[test]
+    fn test_metrics_format() {
+        let gpio = test_gpio();
+        gpio.set_function(17, PinFunction::Output).unwrap();
+        gpio.set_function(18, PinFunction::Alt5).unwrap();
+        write_word(&gpio, 0x34, 1 << 17);
+        write_word(&gpio, 0x38, 1 << (40 - 32));
+
+        let metrics = gpio.metrics().unwrap();
+        let lines: Vec<&str> = metrics.lines().collect();
+        assert_eq!(lines[0], "# HELP gpio_pin_level Current level of the GPIO pin (GPLEV).");
+        assert_eq!(lines[1], "# TYPE gpio_pin_level gauge");
+        assert!(lines.contains(&"gpio_pin_level{pin=\"17\"} 1"));
+        assert!(lines.contains(&"gpio_pin_level{pin=\"18\"} 0"));
+        assert!(lines.contains(&"gpio_pin_level{pin=\"40\"} 1"));
+        assert!(lines.contains(&"# TYPE gpio_pin_function gauge"));
+        assert!(lines.contains(&"gpio_pin_function{pin=\"16\",function=\"Input\"} 0"));
+        assert!(lines.contains(&"gpio_pin_function{pin=\"17\",function=\"Output\"} 1"));
+        assert!(lines.contains(&"gpio_pin_function{pin=\"18\",function=\"Alt5\"} 2"));
+        assert_eq!(lines.len(), 4 + 2 * GPIO_PIN_COUNT as usize);
+    }
+
+
+    #[test]
+    fn test_enable_edge_detect_bulk_groups_by_register() {
+        let keypad = [(4, EdgeTrigger::Rising), (17, EdgeTrigger::Rising), (22, EdgeTrigger::Rising), (27, EdgeTrigger::Rising)];
+        let groups = group_edge_masks(&keypad).unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[&0x4c], (Register::GPREN, 4, (1 << 4) | (1 << 17) | (1 << 22) | (1 << 27)));
+
+        let mixed = [(5, EdgeTrigger::Falling), (40, EdgeTrigger::Falling), (6, EdgeTrigger::High)];
+        assert_eq!(group_edge_masks(&mixed).unwrap().len(), 3);
+
+        let gpio = test_gpio();
+        write_word(&gpio, 0x4c, 1 << 2);
+        assert!(gpio.enable_edge_detect_bulk(&keypad).is_ok());
+        assert!(gpio.enable_edge_detect_bulk(&mixed).is_ok());
+        assert_eq!(read_word(&gpio, 0x4c), (1 << 2) | (1 << 4) | (1 << 17) | (1 << 22) | (1 << 27));
+        assert_eq!(read_word(&gpio, 0x58), 1 << 5);
+        assert_eq!(read_word(&gpio, 0x5c), 1 << 8);
+        assert_eq!(read_word(&gpio, 0x64), 1 << 6);
+    }
+
+
+    #[test]
+    fn test_edge_detect_and_events() {
+        let gpio = test_gpio();
+        gpio.enable_edge_detect(17, EdgeTrigger::Falling).unwrap();
+        gpio.enable_edge_detect(17, EdgeTrigger::AsyncRising).unwrap();
+        gpio.enable_edge_detect(18, EdgeTrigger::Falling).unwrap();
+        assert_eq!(read_word(&gpio, 0x58), (1 << 17) | (1 << 18));
+        assert_eq!(read_word(&gpio, 0x7c), 1 << 17);
+
+        gpio.disable_edge_detect(17).unwrap();
+        assert_eq!(read_word(&gpio, 0x58), 1 << 18);
+        assert_eq!(read_word(&gpio, 0x7c), 0);
+
+        write_word(&gpio, 0x40, (1 << 4) | (1 << 17));
+        assert_eq!(gpio.poll_event(17).ok(), Some(true));
+        assert_eq!(gpio.poll_event(18).ok(), Some(false));
+        gpio.clear_event(17).unwrap();
+        // Write-1-to-clear: only pin 17's bit is written.
+        assert_eq!(read_word(&gpio, 0x40), 1 << 17);
+        assert!(gpio.poll_event(58).is_err());
+    }
+
+
+    #[test]
+    fn test_open_for_testing_on_seeded_state() {
+        let mut seed = vec![0u8; 8];
+        seed[4..8].copy_from_slice(&PinFunction::Output.to_bits(17).to_ne_bytes());
+        let gpio = GPIO::open_for_testing_on(seed);
+
+        assert_eq!(gpio.get_function(17).unwrap(), PinFunction::Output);
+        assert_eq!(gpio.get_function(16).unwrap(), PinFunction::Input);
+
+        gpio.set_function(16, PinFunction::Alt0).unwrap();
+        let buffer = gpio.testing_buffer();
+        assert_eq!(buffer.len(), GPIO_BLOCK_SIZE);
+        let gpfsel1 = u32::from_ne_bytes([buffer[4], buffer[5], buffer[6], buffer[7]]);
+        assert_eq!(gpfsel1, PinFunction::Output.to_bits(17) | PinFunction::Alt0.to_bits(16));
+    }
+
+
+    #[test]
+    fn test_health_check() {
+        let gpio = test_gpio();
+        assert!(gpio.is_mapped());
+        assert!(gpio.health_check().is_ok());
+
+        write_word(&gpio, 0x00, 0xc000_0000);
+        assert_eq!(gpio.health_check().unwrap_err().register(), Some(Register::GPFSEL));
+
+        let mut gpio = test_gpio();
+        gpio.buffer = nix::libc::MAP_FAILED;
+        assert!(!gpio.is_mapped());
+        assert!(gpio.health_check().is_err());
+
+        gpio.buffer = std::ptr::null_mut();
+        assert!(!gpio.is_mapped());
+
+        assert!(MockGpio::new().gpio().unwrap().health_check().is_ok());
+    }
+
+
+    #[test]
+    fn test_configure_applies_all_register_groups() {
+        let gpio = test_gpio();
+        let setups = [
+            PinSetup { pin: 17, function: PinFunction::Output, pull: Pull::None, initial_level: Some(true), edge: None },
+            PinSetup { pin: 40, function: PinFunction::Output, pull: Pull::Down, initial_level: Some(false), edge: None },
+            PinSetup { pin: 22, function: PinFunction::Input, pull: Pull::Up, initial_level: None, edge: Some(EdgeTrigger::Falling) },
+        ];
+        assert!(gpio.configure(&setups).is_ok());
+
+        assert_eq!(gpio.get_function(17).unwrap(), PinFunction::Output);
+        assert_eq!(gpio.get_function(40).unwrap(), PinFunction::Output);
+        assert_eq!(gpio.get_function(22).unwrap(), PinFunction::Input);
+        assert_eq!(read_word(&gpio, 0x1c), 1 << 17);
+        assert_eq!(read_word(&gpio, 0x2c), 1 << (40 - 32));
+        assert_eq!(read_word(&gpio, 0x58), 1 << 22);
+        assert_eq!(gpio.get_pull(17).unwrap(), Pull::None);
+        assert_eq!(gpio.get_pull(40).unwrap(), Pull::Down);
+        assert_eq!(gpio.get_pull(22).unwrap(), Pull::Up);
+    }
+
+
+    #[test]
+    fn test_bank_helpers() {
+        assert_eq!((bank_of(0), bit_in_bank(0)), (0, 0));
+        assert_eq!((bank_of(31), bit_in_bank(31)), (0, 31));
+        assert_eq!((bank_of(32), bit_in_bank(32)), (1, 0));
+        assert_eq!((bank_of(GPIO_PIN_COUNT - 1), bit_in_bank(GPIO_PIN_COUNT - 1)), (1, 25));
+        assert_eq!(bank_of(GPIO_PIN_COUNT - 1), BANK_COUNT - 1);
+
+        assert_eq!(Register::GPLEV.to_offset(31).unwrap(), 0x34);
+        assert_eq!(Register::GPLEV.to_offset(32).unwrap(), 0x38);
+    }
+
+
+    #[test]
+    fn test_output_state_shadow() {
+        let gpio = test_gpio();
+        assert_eq!(gpio.output_state(17).unwrap(), None);
+
+        gpio.set_high(17).unwrap();
+        assert_eq!(gpio.output_state(17).unwrap(), Some(true));
+        assert!(!gpio.read(17).unwrap());
+
+        gpio.set_low(17).unwrap();
+        assert_eq!(gpio.output_state(17).unwrap(), Some(false));
+
+        gpio.set_high(45).unwrap();
+        assert_eq!(gpio.output_state(45).unwrap(), Some(true));
+        assert_eq!(gpio.output_state(13).unwrap(), None);
+    }
+
+
+    #[test]
+    fn test_diff_against_default() {
+        let gpio = test_gpio();
+        assert!(gpio.diff_against_default().unwrap().is_empty());
+
+        gpio.set_function(4, PinFunction::Output).unwrap();
+        gpio.set_function(41, PinFunction::Alt0).unwrap();
+        gpio.set_pull(30, Pull::Up).unwrap();
+        write_word(&gpio, 0x34, (1 << 4) | (1 << 5));
+
+        assert_eq!(gpio.diff_against_default().unwrap(), vec![
+            PinReport { pin: 4, function: PinFunction::Output, pull: Pull::None, level: true },
+            PinReport { pin: 30, function: PinFunction::Input, pull: Pull::Up, level: false },
+            PinReport { pin: 41, function: PinFunction::Alt0, pull: Pull::None, level: false },
+        ]);
+    }
+
+
+    #[test]
+    fn test_switch_to_alt_spanning_two_registers() {
+        let gpio = test_gpio();
+        gpio.set_function(11, PinFunction::Output).unwrap();
+        let spi_pins = [7, 8, 9, 10, 11];
+
+        let (words, changes) = gpio.plan_function_words(&spi_pins, PinFunction::Alt0).unwrap();
+        let alt0 = |pins: &[u32]| pins.iter().fold(0, |word, &pin| word | PinFunction::Alt0.to_bits(pin));
+        assert_eq!(words.len(), 2);
+        assert_eq!(words[&0x00], (7, alt0(&[7, 8, 9])));
+        assert_eq!(words[&0x04], (10, alt0(&[10, 11])));
+        assert_eq!(changes[4], PinChange::Function { pin: 11, old: PinFunction::Output, new: PinFunction::Alt0 });
+
+        gpio.switch_to_alt(&spi_pins, PinFunction::Alt0).unwrap();
+        assert_eq!(read_word(&gpio, 0x00), alt0(&[7, 8, 9]));
+        assert_eq!(read_word(&gpio, 0x04), alt0(&[10, 11]));
+    }
+
+
+    #[test]
+    fn test_read_pin_fast_matches_level() {
+        let gpio = test_gpio();
+        write_word(&gpio, 0x34, (1 << 0) | (1 << 17) | (1 << 31));
+        write_word(&gpio, 0x38, (1 << 0) | (1 << 25));
+        for pin in 0..GPIO_PIN_COUNT {
+            assert_eq!(gpio.read_pin_fast(pin).unwrap(), gpio.read(pin).unwrap(), "pin {}", pin);
+        }
+        assert!(matches!(gpio.read_pin_fast(GPIO_PIN_COUNT), Err(Error::InvalidPin { .. })));
+        assert!(matches!(crate::MockGpio::new().gpio().unwrap().read_pin_fast(17), Err(Error::Unsupported(_))));
+    }
+
+
+    #[test]
+    fn test_read_pin_fast_tight_loop() {
+        const ROUNDS: u32 = 100_000;
+        let gpio = test_gpio();
+        write_word(&gpio, 0x38, 1 << (40 - 32));
+
+        let fast = (0..ROUNDS).filter(|_| gpio.read_pin_fast(40).unwrap()).count();
+        let checked = (0..ROUNDS).filter(|_| gpio.read(40).unwrap()).count();
+        assert_eq!(fast, ROUNDS as usize);
+        assert_eq!(checked, ROUNDS as usize);
+    }
+
+
+    // (register, pin, BCM2711 datasheet offset) covering both banks
+    const DATASHEET_OFFSETS: [(Register, u32, usize); 39] = [
+        (Register::GPPUD, 57, 0x94), (Register::GPPUDCLK, 31, 0x98), (Register::GPPUDCLK, 32, 0x9c),
+        (Register::GPFSEL, 0, 0x00), (Register::GPFSEL, 19, 0x04), (Register::GPFSEL, 20, 0x08),
+        (Register::GPFSEL, 39, 0x0c), (Register::GPFSEL, 40, 0x10), (Register::GPFSEL, 57, 0x14),
+        (Register::GPSET, 31, 0x1c), (Register::GPSET, 32, 0x20),
+        (Register::GPCLR, 31, 0x28), (Register::GPCLR, 32, 0x2c),
+        (Register::GPLEV, 31, 0x34), (Register::GPLEV, 32, 0x38),
+        (Register::GPEDS, 31, 0x40), (Register::GPEDS, 32, 0x44),
+        (Register::GPREN, 31, 0x4c), (Register::GPREN, 32, 0x50),
+        (Register::GPFEN, 31, 0x58), (Register::GPFEN, 32, 0x5c),
+        (Register::GPHEN, 31, 0x64), (Register::GPHEN, 32, 0x68),
+        (Register::GPLEN, 31, 0x70), (Register::GPLEN, 32, 0x74),
+        (Register::GPAREN, 31, 0x7c), (Register::GPAREN, 32, 0x80),
+        (Register::GPAFEN, 31, 0x88), (Register::GPAFEN, 32, 0x8c),
+        (Register::GPPUPPDNCNTRL, 0, 0xe4), (Register::GPPUPPDNCNTRL, 15, 0xe4),
+        (Register::GPPUPPDNCNTRL, 16, 0xe8), (Register::GPPUPPDNCNTRL, 31, 0xe8),
+        (Register::GPPUPPDNCNTRL, 32, 0xec), (Register::GPPUPPDNCNTRL, 47, 0xec),
+        (Register::GPPUPPDNCNTRL, 48, 0xf0), (Register::GPPUPPDNCNTRL, 57, 0xf0),
+        (Register::GPLEV, 0, 0x34), (Register::GPLEV, 57, 0x38),
+    ];
+
+    fn validate_offsets() {
+        for &(register, pin, offset) in DATASHEET_OFFSETS.iter() {
+            assert_eq!(register.to_offset(pin).unwrap(), offset, "{:?} for pin {}", register, pin);
+        }
+
+        let registers = [
+            Register::GPFSEL, Register::GPSET, Register::GPCLR, Register::GPLEV,
+            Register::GPEDS, Register::GPREN, Register::GPFEN, Register::GPHEN,
+            Register::GPLEN, Register::GPAREN, Register::GPAFEN, Register::GPPUD,
+            Register::GPPUDCLK, Register::GPPUPPDNCNTRL,
+        ];
+        let mut owners: BTreeMap<usize, Register> = BTreeMap::new();
+        for &register in registers.iter() {
+            for pin in 0..GPIO_PIN_COUNT {
+                let offset = register.to_offset(pin).unwrap();
+                assert!(check_offset(offset, GPIO_BLOCK_SIZE).is_ok(), "{:?} for pin {}", register, pin);
+                let owner = *owners.entry(offset).or_insert(register);
+                assert_eq!(owner, register, "{:?} and {:?} collide at {:#x}", owner, register, offset);
+            }
+        }
+        assert_eq!(owners.len(), 6 + 10 * 2 + 1 + 2 + 4);
+    }
+
+
+    #[test]
+    fn test_validate_datasheet_offsets() {
+        validate_offsets();
+    }
+
+
+    #[test]
+    fn test_function_histogram() {
+        let gpio = test_gpio();
+        gpio.switch_to_alt(&[7, 8, 9, 10, 11], PinFunction::Alt0).unwrap();
+        gpio.set_function(17, PinFunction::Output).unwrap();
+        gpio.set_function(27, PinFunction::Output).unwrap();
+        gpio.set_function(18, PinFunction::Alt5).unwrap();
+
+        let histogram = gpio.function_histogram().unwrap();
+        assert_eq!(histogram[&PinFunction::Input], GPIO_PIN_COUNT - 8);
+        assert_eq!(histogram[&PinFunction::Output], 2);
+        assert_eq!(histogram[&PinFunction::Alt0], 5);
+        assert_eq!(histogram[&PinFunction::Alt5], 1);
+        assert_eq!(histogram.get(&PinFunction::Alt3), None);
+    }
+
+
+    struct AllowAll;
+
+    impl WritePolicy for AllowAll {
+        fn check(&self, _: &PinChange, _: &BoardState) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_whole_board_queries_on_54_pin_soc() {
+        let gpio = GPIO::from_registers(RegisterBackend::owned(Vec::new(), Soc::Bcm2837), Soc::Bcm2837);
+        gpio.set_write_policy(AllowAll);
+        gpio.set_function(17, PinFunction::Output).unwrap();
+        gpio.set_high(17).unwrap();
+        let state = gpio.board_state().unwrap();
+        assert_eq!(state.function(17).ok(), Some(PinFunction::Output));
+        assert!(state.function(54).is_err());
+
+        assert_eq!(gpio.function_histogram().unwrap().values().sum::<u32>(), 54);
+        assert_eq!(gpio.diff_against_default().unwrap().len(), 1);
+        assert_eq!(gpio.metrics().unwrap().matches("gpio_pin_level{").count(), 54);
+    }
+
+
+    #[test]
+    fn test_set_high_set_low_write_only_their_bit() {
+        let gpio = test_gpio();
+        gpio.set_high(17).unwrap();
+        gpio.set_high(22).unwrap();
+        assert_eq!(read_word(&gpio, 0x1c), 1 << 22);
+        assert_eq!(read_word(&gpio, 0x28), 0);
+
+        gpio.set_low(40).unwrap();
+        assert_eq!(read_word(&gpio, 0x2c), 1 << 8);
+        assert_eq!(read_word(&gpio, 0x28), 0);
+    }
+
+
+    #[test]
+    fn test_toggle_and_read() {
+        let gpio = test_gpio();
+        write_word(&gpio, 0x34, 1 << 4);
+        assert!(gpio.read(4).unwrap());
+        assert!(!gpio.read(5).unwrap());
+
+        gpio.toggle(4).unwrap();
+        assert_eq!(read_word(&gpio, 0x28), 1 << 4);
+        assert_eq!(gpio.output_state(4).unwrap(), Some(false));
+
+        gpio.toggle(4).unwrap();
+        assert_eq!(read_word(&gpio, 0x1c), 1 << 4);
+        assert_eq!(gpio.output_state(4).unwrap(), Some(true));
+
+        gpio.toggle(5).unwrap();
+        assert_eq!(gpio.output_state(5).unwrap(), Some(true));
+    }
+
+
+    #[test]
+    fn test_concurrent_toggles() {
+        let gpio = test_gpio();
+        gpio.set_low(6).unwrap();
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| for _ in 0..250 { gpio.toggle(6).unwrap() });
+            }
+        });
+        assert_eq!(gpio.output_state(6).unwrap(), Some(false));
+    }
+
+
+    #[test]
+    fn test_set_function_read_modify_write_per_register() {
+        let gpio = test_gpio();
+        for pin in 0..GPIO_PIN_COUNT {
+            gpio.set_function(pin, PinFunction::Alt3).unwrap();
+        }
+        for pin in (0..GPIO_PIN_COUNT).step_by(3) {
+            gpio.set_function(pin, PinFunction::Output).unwrap();
+        }
+        for pin in 0..GPIO_PIN_COUNT {
+            let expected = if pin % 3 == 0 { PinFunction::Output } else { PinFunction::Alt3 };
+            assert_eq!(gpio.get_function(pin).unwrap(), expected, "pin {}", pin);
+        }
+        // Only 30 bits of each GPFSEL word hold fields; GPFSEL5 only 24.
+        assert_eq!(read_word(&gpio, 0x00) >> 30, 0);
+        assert_eq!(read_word(&gpio, 0x14) >> 24, 0);
+    }
+
+
+    #[test]
+    fn test_set_function_rejects_error_variant() {
+        let gpio = test_gpio();
+        let error = gpio.set_function(3, PinFunction::Error).unwrap_err();
+        assert_eq!(error.register(), Some(Register::GPFSEL));
+        assert_eq!(read_word(&gpio, 0x00), 0);
+    }
+
+
+    #[test]
+    fn test_pull_bits() {
+        assert_eq!(Pull::Up.to_bits(0), 0b01);
+        assert_eq!(Pull::Down.to_bits(17), 0b10 << 2);
+        assert_eq!(Pull::Down.to_bits(31), 0b10 << 30);
+        assert_eq!(Pull::clear_mask(15), !(0b11 << 30));
+        assert_eq!(Pull::from_bits(33, 0b10 << 2), Pull::Down);
+        assert_eq!(Pull::from_bits(33, 0b11 << 2), Pull::None);
+    }
+
+
+    #[test]
+    fn test_set_pull_and_audit() {
+        let gpio = test_gpio();
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&changes);
+        gpio.set_audit_hook(move |change| sink.lock().unwrap().push(change));
+
+        gpio.set_pull(16, Pull::Up).unwrap();
+        gpio.set_pull(17, Pull::Down).unwrap();
+        gpio.set_pull(57, Pull::Up).unwrap();
+        gpio.set_pull(16, Pull::None).unwrap();
+
+        assert_eq!(read_word(&gpio, 0xe8), 0b10 << 2);
+        assert_eq!(read_word(&gpio, 0xf0), 0b01 << 18);
+        assert_eq!(gpio.get_pull(16).unwrap(), Pull::None);
+        assert_eq!(gpio.get_pull(17).unwrap(), Pull::Down);
+        assert_eq!(gpio.get_pull(57).unwrap(), Pull::Up);
+
+        let changes = changes.lock().unwrap();
+        assert_eq!(changes[3], PinChange::Pull { pin: 16, old: Pull::Up, new: Pull::None });
+        assert_eq!(changes[3].to_string(), "pin 16: pull Up -> None");
+    }
+
+
+    #[test]
+    fn test_backend_candidates() {
+        assert_eq!(Backend::Auto.candidates(None, |_| true),
+            vec![Backend::GpioMem, Backend::DevMem, Backend::GpioChip, Backend::Sysfs]);
+        assert_eq!(Backend::Auto.candidates(Some(Soc::Bcm2711), |_| false), vec![Backend::DevMem]);
+        assert_eq!(Backend::Auto.candidates(None, |path| path == DEV_GPIOCHIP_PATH), vec![Backend::DevMem, Backend::GpioChip]);
+        assert_eq!(Backend::GpioMem.candidates(None, |_| false), vec![Backend::GpioMem]);
+        assert_eq!(Backend::DevMem.candidates(None, |_| true), vec![Backend::DevMem]);
+        assert_eq!(Backend::Auto.candidates(Some(Soc::Bcm2712), |_| true),
+            vec![Backend::Rp1, Backend::GpioChip, Backend::Sysfs]);
+        assert_eq!(Backend::Auto.candidates(Some(Soc::Bcm2712), |path| path != DEV_GPIOMEM0_PATH),
+            vec![Backend::GpioChip, Backend::Sysfs]);
+        assert_eq!(Backend::Rp1.candidates(Some(Soc::Bcm2711), |_| true), vec![Backend::Rp1]);
+        assert_eq!(test_gpio().backend(), None);
+    }
+
+
+    #[test]
+    fn test_invalid_pin_is_an_error() {
+        let gpio = test_gpio();
+        let error = Register::GPLEV.to_offset(58).unwrap_err();
+        assert!(matches!(error, Error::InvalidPin { pin: 58, register: Some(Register::GPLEV) }));
+        assert!(matches!(gpio.read(58).unwrap_err(), Error::InvalidPin { pin: 58, .. }));
+        assert!(matches!(gpio.set_high(64).unwrap_err(), Error::InvalidPin { pin: 64, .. }));
+        assert!(matches!(gpio.set_function(100, PinFunction::Output).unwrap_err(), Error::InvalidPin { pin: 100, .. }));
+        assert!(gpio.switch_to_alt(&[4, 58], PinFunction::Alt0).is_err());
+        assert_eq!(gpio.get_function(4).ok(), Some(PinFunction::Input));
+        assert!(gpio.enable_edge_detect_bulk(&[(58, EdgeTrigger::Rising)]).is_err());
+    }
+
+
+    #[test]
+    fn test_register_access() {
+        let gpio = test_gpio();
+        gpio.write_register(Register::GPFSEL, 12, 0x0000_0248).unwrap();
+        assert_eq!(read_word(&gpio, 0x04), 0x0000_0248);
+        assert_eq!(gpio.read_register(Register::GPFSEL, 19).ok(), Some(0x0000_0248));
+        assert_eq!(gpio.get_function(11).ok(), Some(PinFunction::Output));
+        assert!(matches!(gpio.read_register(Register::GPLEV, 58), Err(Error::InvalidPin { .. })));
+
+        unsafe { gpio.write_raw(0x34, 1 << 3) };
+        assert_eq!(unsafe { gpio.read_raw(0x34) }, 1 << 3);
+        assert_eq!(gpio.read(3).ok(), Some(true));
+
+        unsafe {
+            gpio.write_register_at(0x38, 1 << 8).unwrap();
+            assert_eq!(gpio.read_register_at(0x38).ok(), Some(1 << 8));
+            assert!(matches!(gpio.read_register_at(0x36), Err(Error::Register { offset: Some(0x36), .. })));
+            assert!(gpio.write_register_at(GPIO_BLOCK_SIZE, 0).is_err());
+        }
+        assert_eq!(gpio.read(40).ok(), Some(true));
+
+        let mut gpio = test_gpio();
+        gpio.read_only = true;
+        assert!(matches!(gpio.write_register(Register::GPSET, 0, 1), Err(Error::WriteRejected(_))));
+        assert!(matches!(unsafe { gpio.write_register_at(0x1c, 1) }, Err(Error::WriteRejected(_))));
+    }
+
+
+    #[test]
+    fn test_fenced_register_access() {
+        let gpio = test_gpio();
+        gpio.write_register_fenced(Register::GPLEV, 40, 1 << 8).unwrap();
+        assert_eq!(gpio.read_register_fenced(Register::GPLEV, 40).ok(), Some(1 << 8));
+        assert_eq!(gpio.read(40).ok(), Some(true));
+        assert!(gpio.read_register_fenced(Register::GPLEV, 58).is_err());
+    }
+
+
+    #[test]
+    fn test_gpio_is_shared_across_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<GPIO>();
+
+        // All ten pins share GPFSEL1, so unserialised read-modify-writes
+        // would lose each other's fields.
+        let gpio = Arc::new(test_gpio());
+        let workers: Vec<_> = (10..20u32).map(|pin| {
+            let gpio = Arc::clone(&gpio);
+            std::thread::spawn(move || {
+                for round in 0..200 {
+                    let function = if round % 2 == 0 { PinFunction::Output } else { PinFunction::Alt3 };
+                    gpio.set_function(pin, function).unwrap();
+                }
+                gpio.set_function(pin, PinFunction::Output).unwrap();
+                gpio.set_high(pin).unwrap();
+            })
+        }).collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        for pin in 10..20 {
+            assert_eq!(gpio.get_function(pin).ok(), Some(PinFunction::Output));
+            assert_eq!(gpio.output_state(pin).ok(), Some(Some(true)));
+        }
+    }
+
+
+    #[test]
+    fn test_read_all() {
+        let gpio = test_gpio();
+        write_word(&gpio, 0x34, (1 << 0) | (1 << 17) | (1 << 31));
+        write_word(&gpio, 0x38, (1 << 8) | (1 << 25) | 0xfc00_0000);
+        assert_eq!(gpio.read_all().ok(), Some((1 << 0) | (1 << 17) | (1 << 31) | (1 << 40) | (1 << 57)));
+        assert_eq!(gpio.levels_for(&[40, 1, 17, 57]).ok(), Some(vec![true, false, true, true]));
+        assert!(gpio.levels_for(&[4, 58]).is_err());
+    }
+
+
+    #[test]
+    fn test_verify_pull() {
+        let mock = MockGpio::new();
+        let gpio = mock.gpio().unwrap();
+        gpio.set_function(4, PinFunction::Output).unwrap();
+        assert!(gpio.verify_pull(4, Pull::Up, Duration::ZERO).unwrap());
+        assert_eq!(mock.operations(), [
+            MockOperation::SetFunction { pin: 4, function: PinFunction::Output },
+            MockOperation::SetFunction { pin: 4, function: PinFunction::Input },
+            MockOperation::SetPull { pin: 4, pull: Pull::Up },
+        ]);
+        assert!(gpio.verify_pull(4, Pull::Down, Duration::ZERO).unwrap());
+
+        // Something outside holding the line high defeats the pull-down.
+        mock.set_input(4, true);
+        assert!(!gpio.verify_pull(4, Pull::Down, Duration::ZERO).unwrap());
+        assert!(gpio.verify_pull(4, Pull::None, Duration::ZERO).unwrap());
+    }
+
+
+    #[test]
+    fn test_with_pull() {
+        let mock = MockGpio::new();
+        let gpio = mock.gpio().unwrap();
+        gpio.set_pull(4, Pull::Down).unwrap();
+        assert_eq!(gpio.with_pull(4, Pull::Up, || gpio.read(4)).unwrap().ok(), Some(true));
+        assert_eq!(gpio.get_pull(4).ok(), Some(Pull::Down));
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            gpio.with_pull(4, Pull::Up, || panic!("jumper read failed"))
+        }));
+        assert!(result.is_err());
+        assert_eq!(gpio.get_pull(4).ok(), Some(Pull::Down));
+        assert_eq!(mock.operations()[3..], [
+            MockOperation::SetPull { pin: 4, pull: Pull::Up },
+            MockOperation::SetPull { pin: 4, pull: Pull::Down },
+        ]);
+    }
+
+
+    #[test]
+    fn test_read_all_on_54_line_backend() {
+        let mock = MockGpio::new();
+        let gpio = GpioBuilder::new().soc(Soc::Bcm2712).custom(mock.clone()).build().unwrap();
+        mock.set_input(53, true);
+        mock.set_input(55, true);
+        assert_eq!(gpio.read_all().ok(), Some(1 << 53));
+        assert!(gpio.levels_for(&[55]).is_err());
+    }
+
+
+    #[test]
+    fn test_write_mask() {
+        let gpio = test_gpio();
+        gpio.write_mask((1 << 4) | (1 << 40), (1 << 5) | (1 << 41)).unwrap();
+        assert_eq!(read_word(&gpio, 0x1c), 1 << 4);
+        assert_eq!(read_word(&gpio, 0x20), 1 << 8);
+        assert_eq!(read_word(&gpio, 0x28), 1 << 5);
+        assert_eq!(read_word(&gpio, 0x2c), 1 << 9);
+        assert_eq!(gpio.output_state(40).ok(), Some(Some(true)));
+        assert_eq!(gpio.output_state(41).ok(), Some(Some(false)));
+
+        gpio.clear_mask(1 << 40).unwrap();
+        assert_eq!(read_word(&gpio, 0x2c), 1 << 8);
+        assert_eq!(gpio.output_state(40).ok(), Some(Some(false)));
+        gpio.set_mask(1 << 6).unwrap();
+        assert_eq!(gpio.output_state(6).ok(), Some(Some(true)));
+
+        assert!(matches!(gpio.set_mask(1 << 58), Err(Error::InvalidPin { pin: 58, .. })));
+        assert!(gpio.write_mask(1 << 7, 1 << 7).is_err());
+        assert_eq!(gpio.output_state(7).ok(), Some(None));
+    }
+
+
+    #[test]
+    fn test_error_source() {
+        let missing = std::env::temp_dir().join(format!("rustberrypi-missing-{}", std::process::id()));
+        let error = open_file(&missing).unwrap_err();
+        assert!(matches!(error, Error::Io { .. }));
+        assert_eq!(error.errno(), Some(Errno::ENOENT));
+        let source = std::error::Error::source(&error).unwrap();
+        assert_eq!(source.downcast_ref::<std::io::Error>().unwrap().kind(), std::io::ErrorKind::NotFound);
+
+        let error = Register::GPSET.to_offset(60).unwrap_err();
+        assert!(std::error::Error::source(&error).is_none());
+        assert_eq!(error.to_string(), "invalid pin 60, pins are numbered 0 to 57 (GPSET)");
+    }
+}
@@ -0,0 +1,198 @@
+use crate::registers::REGISTER_SIZE;
+use crate::{bit_in_bank, memory_barrier, PinFunction, Pull, Register, RegisterMap};
+
+
+/// The GPIO register block at a known address, for code running without
+/// Linux, such as a kernel or bootloader, that can't open a `GPIO`. It
+/// needs only `core`, so it's available without the `std` feature, and
+/// works from the same `RegisterMap` and offsets as `GPIO`.
+///
+/// Every access is one volatile word access. Nothing is locked: changes
+/// of function and pull read, modify and write a word shared with other
+/// pins, so callers on more than one core must serialise them.
+///
+/// Methods taking a pin panic if there's no such pin, as a bad pin number
+/// is a bug rather than something to recover from.
+pub struct GpioBlock {
+    base: *mut u32,
+    register_map: RegisterMap,
+}
+
+impl GpioBlock {
+
+    /// The block at `base` with the datasheet layout, e.g. at 0x3f20_0000
+    /// on a BCM2837 or 0xfe20_0000 on a BCM2711 in low peripheral mode.
+    ///
+    /// # Safety
+    ///
+    /// `base` must point to the GPIO block, mapped for reads and writes as
+    /// device memory, for as long as the `GpioBlock` is used.
+    pub unsafe fn new(base: *mut u32) -> Self {
+        Self::with_register_map(base, RegisterMap::default())
+    }
+
+    /// The block at `base`, laid out as `register_map` says.
+    ///
+    /// # Safety
+    ///
+    /// As for `new`, for a block of `register_map.block_size()` bytes.
+    pub unsafe fn with_register_map(base: *mut u32, register_map: RegisterMap) -> Self {
+        Self { base, register_map }
+    }
+
+    pub fn base(&self) -> *mut u32 {
+        self.base
+    }
+
+    pub fn register_map(&self) -> &RegisterMap {
+        &self.register_map
+    }
+
+    fn offset(&self, register: Register, pin: u32) -> usize {
+        match self.register_map.word_offset(register, pin) {
+            Some(offset) => offset,
+            None => panic!("{:?} has no word for pin {} in this block", register, pin),
+        }
+    }
+
+    fn check_word(&self, offset: usize) {
+        let size = REGISTER_SIZE as usize;
+        assert!(offset.is_multiple_of(size) && offset + size <= self.register_map.block_size(),
+            "{:#x} isn't a word offset into the {:#x} byte block", offset, self.register_map.block_size());
+    }
+
+    /// Reads the word at byte `offset` into the block. Panics if it isn't
+    /// a word inside the block.
+    #[inline]
+    pub fn read_word(&self, offset: usize) -> u32 {
+        self.check_word(offset);
+        unsafe { self.base.wrapping_byte_add(offset).read_volatile() }
+    }
+
+    /// Writes the word at byte `offset` into the block. Panics if it isn't
+    /// a word inside the block.
+    #[inline]
+    pub fn write_word(&self, offset: usize, value: u32) {
+        self.check_word(offset);
+        unsafe { self.base.wrapping_byte_add(offset).write_volatile(value) }
+    }
+
+    /// Reads the word of `register` that holds `pin`'s bits.
+    pub fn read(&self, register: Register, pin: u32) -> u32 {
+        self.read_word(self.offset(register, pin))
+    }
+
+    /// Writes the word of `register` that holds `pin`'s bits, replacing
+    /// the bits of every other pin in it.
+    pub fn write(&self, register: Register, pin: u32, value: u32) {
+        self.write_word(self.offset(register, pin), value)
+    }
+
+    pub fn function(&self, pin: u32) -> PinFunction {
+        PinFunction::from_bits(pin, self.read(Register::GPFSEL, pin))
+    }
+
+    pub fn set_function(&self, pin: u32, function: PinFunction) {
+        let value = self.read(Register::GPFSEL, pin);
+        self.write(Register::GPFSEL, pin, value & PinFunction::clear_mask(pin) | function.to_bits(pin))
+    }
+
+    // GPSET/GPCLR ignore zero bits, so writing the pin's bit alone leaves
+    // the others be. Level accesses are fenced, as they're the ones most
+    // often interleaved with other peripherals'.
+    pub fn write_level(&self, pin: u32, high: bool) {
+        memory_barrier();
+        self.write(if high { Register::GPSET } else { Register::GPCLR }, pin, 1 << bit_in_bank(pin))
+    }
+
+    pub fn set_high(&self, pin: u32) {
+        self.write_level(pin, true)
+    }
+
+    pub fn set_low(&self, pin: u32) {
+        self.write_level(pin, false)
+    }
+
+    pub fn is_high(&self, pin: u32) -> bool {
+        let value = self.read(Register::GPLEV, pin);
+        memory_barrier();
+        value & (1 << bit_in_bank(pin)) != 0
+    }
+
+    /// The pull of `pin`, from the BCM2711's pull control registers. The
+    /// older chips' pulls can't be read back.
+    pub fn pull(&self, pin: u32) -> Pull {
+        Pull::from_bits(pin, self.read(Register::GPPUPPDNCNTRL, pin))
+    }
+
+    /// Sets the pull of `pin` through the BCM2711's pull control registers.
+    pub fn set_pull(&self, pin: u32, pull: Pull) {
+        let value = self.read(Register::GPPUPPDNCNTRL, pin);
+        self.write(Register::GPPUPPDNCNTRL, pin, value & Pull::clear_mask(pin) | pull.to_bits(pin))
+    }
+
+    /// Sets the pull of `pin` with the BCM2835-BCM2837 sequence through
+    /// GPPUD and GPPUDCLK. `wait` is called where the control signal needs
+    /// 150 core cycles to settle, twice per call.
+    pub fn set_pull_legacy(&self, pin: u32, pull: Pull, mut wait: impl FnMut()) {
+        self.write(Register::GPPUD, pin, pull as u32);
+        wait();
+        self.write(Register::GPPUDCLK, pin, 1 << bit_in_bank(pin));
+        wait();
+        self.write(Register::GPPUD, pin, 0);
+        self.write(Register::GPPUDCLK, pin, 0);
+    }
+
+    /// Whether an enabled edge or level has been detected on `pin` since
+    /// its event was last cleared.
+    pub fn event_detected(&self, pin: u32) -> bool {
+        self.read(Register::GPEDS, pin) & (1 << bit_in_bank(pin)) != 0
+    }
+
+    pub fn clear_event(&self, pin: u32) {
+        self.write(Register::GPEDS, pin, 1 << bit_in_bank(pin))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gpio_block() {
+        let mut words = [0u32; 64];
+        let block = unsafe { GpioBlock::new(words.as_mut_ptr()) };
+        block.set_function(17, PinFunction::Output);
+        block.set_function(12, PinFunction::Alt0);
+        assert_eq!(block.function(17), PinFunction::Output);
+        assert_eq!(block.read_word(0x04), 0b001 << 21 | 0b100 << 6);
+        block.set_high(40);
+        assert_eq!(block.read_word(0x20), 1 << 8);
+        block.set_pull(33, Pull::Down);
+        assert_eq!(block.pull(33), Pull::Down);
+
+        let mut waits = 0;
+        block.set_pull_legacy(4, Pull::Up, || waits += 1);
+        assert_eq!(waits, 2);
+        block.write_word(0x34, 1 << 5);
+        assert!(block.is_high(5) && !block.is_high(4));
+        assert_eq!(words[0x04 / 4], 0b001 << 21 | 0b100 << 6);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_gpio_block_rejects_bad_pins() {
+        let mut words = [0u32; 64];
+        let block = unsafe { GpioBlock::new(words.as_mut_ptr()) };
+        block.read(Register::GPLEV, 58);
+    }
+
+    #[test]
+    fn test_register_map_word_offsets() {
+        let map = RegisterMap::default().with_offset(Register::GPSET, 0x200).with_word_stride(8).with_block_size(0x400);
+        assert_eq!(map.word_offset(Register::GPSET, 40), Some(0x208));
+        assert_eq!(map.word_offset(Register::GPSET, 58), None);
+        assert_eq!(RegisterMap::default().with_block_size(0x38).word_offset(Register::GPLEV, 40), None);
+    }
+}
@@ -0,0 +1,356 @@
+use crate::{ConstPin, Error, Input, Mcp23017, Mcp23017Pin, Output, Pcf8574, Pcf8574Pin, Pin, PinGroup, ShiftRegisterPin, Sr74hc595};
+
+
+/// A line whose level can be read, whether a native pin or an expander's.
+/// Drivers that only sense, like buttons and switches, take this so they
+/// work with any of them.
+pub trait GpioInput {
+    fn is_high(&self) -> Result<bool, Error>;
+
+    fn is_low(&self) -> Result<bool, Error> {
+        Ok(!self.is_high()?)
+    }
+}
+
+/// A line that can be driven, whether a native output pin, an expander's
+/// or a shift register's. Drivers of LCDs, relays and the like take this
+/// so they work with any of them.
+pub trait GpioPin {
+    fn set_high(&self) -> Result<(), Error>;
+
+    fn set_low(&self) -> Result<(), Error>;
+
+    /// The level the line is being driven to.
+    fn is_set_high(&self) -> Result<bool, Error>;
+
+    fn set(&self, high: bool) -> Result<(), Error> {
+        if high { self.set_high() } else { self.set_low() }
+    }
+
+    fn toggle(&self) -> Result<(), Error> {
+        self.set(!self.is_set_high()?)
+    }
+}
+
+/// A set of lines written and read together as one value, line 0 in bit
+/// 0. Lines that can't be read back report the level they were set to.
+pub trait GpioPort {
+    /// The number of lines, at most 64.
+    fn width(&self) -> usize;
+
+    /// Sets every line. Fails if `value` has bits set beyond the width.
+    fn write_port(&self, value: u64) -> Result<(), Error>;
+
+    fn read_port(&self) -> Result<u64, Error>;
+}
+
+
+impl<T: GpioInput + ?Sized> GpioInput for &T {
+    fn is_high(&self) -> Result<bool, Error> {
+        (**self).is_high()
+    }
+}
+
+impl<T: GpioInput + ?Sized> GpioInput for Box<T> {
+    fn is_high(&self) -> Result<bool, Error> {
+        (**self).is_high()
+    }
+}
+
+impl<T: GpioPin + ?Sized> GpioPin for &T {
+    fn set_high(&self) -> Result<(), Error> {
+        (**self).set_high()
+    }
+
+    fn set_low(&self) -> Result<(), Error> {
+        (**self).set_low()
+    }
+
+    fn is_set_high(&self) -> Result<bool, Error> {
+        (**self).is_set_high()
+    }
+}
+
+impl<T: GpioPin + ?Sized> GpioPin for Box<T> {
+    fn set_high(&self) -> Result<(), Error> {
+        (**self).set_high()
+    }
+
+    fn set_low(&self) -> Result<(), Error> {
+        (**self).set_low()
+    }
+
+    fn is_set_high(&self) -> Result<bool, Error> {
+        (**self).is_set_high()
+    }
+}
+
+
+impl<'a> GpioInput for Pin<'a, Input> {
+    fn is_high(&self) -> Result<bool, Error> {
+        self.read()
+    }
+}
+
+impl<'a> GpioInput for Pin<'a, Output> {
+    fn is_high(&self) -> Result<bool, Error> {
+        self.read()
+    }
+}
+
+impl<'a> GpioPin for Pin<'a, Output> {
+    fn set_high(&self) -> Result<(), Error> {
+        Pin::set_high(self)
+    }
+
+    fn set_low(&self) -> Result<(), Error> {
+        Pin::set_low(self)
+    }
+
+    // Falls back to the pin's level if nothing has been driven through
+    // this GPIO yet.
+    fn is_set_high(&self) -> Result<bool, Error> {
+        match self.output_state() {
+            Some(level) => Ok(level),
+            None => self.read(),
+        }
+    }
+
+    fn toggle(&self) -> Result<(), Error> {
+        Pin::toggle(self)
+    }
+}
+
+impl<'a, const N: u32> GpioInput for ConstPin<'a, N, Input> {
+    fn is_high(&self) -> Result<bool, Error> {
+        self.read()
+    }
+}
+
+impl<'a, const N: u32> GpioInput for ConstPin<'a, N, Output> {
+    fn is_high(&self) -> Result<bool, Error> {
+        self.read()
+    }
+}
+
+impl<'a, const N: u32> GpioPin for ConstPin<'a, N, Output> {
+    fn set_high(&self) -> Result<(), Error> {
+        ConstPin::set_high(self)
+    }
+
+    fn set_low(&self) -> Result<(), Error> {
+        ConstPin::set_low(self)
+    }
+
+    fn is_set_high(&self) -> Result<bool, Error> {
+        GpioPin::is_set_high(self.as_pin())
+    }
+
+    fn toggle(&self) -> Result<(), Error> {
+        ConstPin::toggle(self)
+    }
+}
+
+impl<'e, Mode> GpioInput for Mcp23017Pin<'e, Mode> {
+    fn is_high(&self) -> Result<bool, Error> {
+        self.read()
+    }
+}
+
+impl<'e> GpioPin for Mcp23017Pin<'e, Output> {
+    fn set_high(&self) -> Result<(), Error> {
+        Mcp23017Pin::set_high(self)
+    }
+
+    fn set_low(&self) -> Result<(), Error> {
+        Mcp23017Pin::set_low(self)
+    }
+
+    fn is_set_high(&self) -> Result<bool, Error> {
+        Mcp23017Pin::is_set_high(self)
+    }
+
+    fn toggle(&self) -> Result<(), Error> {
+        Mcp23017Pin::toggle(self)
+    }
+}
+
+impl<'e> GpioInput for Pcf8574Pin<'e> {
+    fn is_high(&self) -> Result<bool, Error> {
+        self.read()
+    }
+}
+
+impl<'e> GpioPin for Pcf8574Pin<'e> {
+    fn set_high(&self) -> Result<(), Error> {
+        Pcf8574Pin::set_high(self)
+    }
+
+    fn set_low(&self) -> Result<(), Error> {
+        Pcf8574Pin::set_low(self)
+    }
+
+    fn is_set_high(&self) -> Result<bool, Error> {
+        Ok(Pcf8574Pin::is_set_high(self))
+    }
+}
+
+// The chips can't be read back, so this is the level last shifted out.
+impl<'r, 'a> GpioInput for ShiftRegisterPin<'r, 'a> {
+    fn is_high(&self) -> Result<bool, Error> {
+        ShiftRegisterPin::is_set_high(self)
+    }
+}
+
+impl<'r, 'a> GpioPin for ShiftRegisterPin<'r, 'a> {
+    fn set_high(&self) -> Result<(), Error> {
+        ShiftRegisterPin::set_high(self)
+    }
+
+    fn set_low(&self) -> Result<(), Error> {
+        ShiftRegisterPin::set_low(self)
+    }
+
+    fn is_set_high(&self) -> Result<bool, Error> {
+        ShiftRegisterPin::is_set_high(self)
+    }
+}
+
+
+fn check_fits(value: u64, width: usize) -> Result<(), Error> {
+    if width < 64 && value >> width != 0 {
+        return Err(Error::Other(format!("{:#x} doesn't fit in a {}-line port", value, width)));
+    }
+    Ok(())
+}
+
+impl<'a> GpioPort for PinGroup<'a> {
+    fn width(&self) -> usize {
+        self.len()
+    }
+
+    fn write_port(&self, value: u64) -> Result<(), Error> {
+        self.write(value)
+    }
+
+    fn read_port(&self) -> Result<u64, Error> {
+        self.read()
+    }
+}
+
+impl GpioPort for Mcp23017 {
+    fn width(&self) -> usize {
+        16
+    }
+
+    // Lines that are inputs only take their bit once made outputs.
+    fn write_port(&self, value: u64) -> Result<(), Error> {
+        check_fits(value, 16)?;
+        self.write_all(value as u16)
+    }
+
+    fn read_port(&self) -> Result<u64, Error> {
+        Ok(self.read_all()? as u64)
+    }
+}
+
+impl GpioPort for Pcf8574 {
+    fn width(&self) -> usize {
+        8
+    }
+
+    fn write_port(&self, value: u64) -> Result<(), Error> {
+        check_fits(value, 8)?;
+        self.write(value as u8)
+    }
+
+    fn read_port(&self) -> Result<u64, Error> {
+        Ok(self.read()? as u64)
+    }
+}
+
+// Chains longer than eight chips don't fit a port, and report an error.
+impl<'a> GpioPort for Sr74hc595<'a> {
+    fn width(&self) -> usize {
+        self.len().min(64)
+    }
+
+    fn write_port(&self, value: u64) -> Result<(), Error> {
+        if self.chips() > 8 {
+            return Err(Error::Other(format!("a chain of {} chips is too long for a 64-line port", self.chips())));
+        }
+        check_fits(value, self.len())?;
+        self.write(&value.to_le_bytes()[..self.chips()])
+    }
+
+    fn read_port(&self) -> Result<u64, Error> {
+        if self.chips() > 8 {
+            return Err(Error::Other(format!("a chain of {} chips is too long for a 64-line port", self.chips())));
+        }
+        Ok(self.outputs().iter().rev().fold(0, |value, &byte| value << 8 | byte as u64))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp23017::tests::expander;
+    use crate::GPIO;
+
+    // Blinks any pin, as a driver generic over pin sources would.
+    fn blink(pin: &dyn GpioPin) -> Result<Vec<bool>, Error> {
+        let mut levels = Vec::new();
+        pin.set_low()?;
+        for _ in 0..3 {
+            pin.toggle()?;
+            levels.push(pin.is_set_high()?);
+        }
+        Ok(levels)
+    }
+
+    #[test]
+    fn test_pins_are_interchangeable() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let native = gpio.pin(17).unwrap().into_output().unwrap();
+        let (mcp, _, levels) = expander();
+        let mcp_pin = mcp.pin(4).unwrap().into_output().unwrap();
+        let output = |pin| gpio.pin(pin).unwrap().into_output().unwrap();
+        let register = Sr74hc595::new(output(22), output(23), output(24), 1).unwrap();
+        let shifted = register.output(3).unwrap();
+
+        let pins: Vec<Box<dyn GpioPin + '_>> = vec![Box::new(native), Box::new(mcp_pin), Box::new(&shifted)];
+        for pin in &pins {
+            assert_eq!(blink(pin).ok(), Some(vec![true, false, true]));
+        }
+        assert_eq!(register.outputs(), vec![0x08]);
+        assert!(shifted.is_high().unwrap());
+
+        let button = mcp.pin(9).unwrap().into_input().unwrap();
+        *levels.lock().unwrap() = 1 << 9;
+        assert!(button.is_high().unwrap());
+        assert_eq!(mcp.read_port().ok(), Some(1 << 9 | 1 << 4));
+    }
+
+    #[test]
+    fn test_ports() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let output = |pin| gpio.pin(pin).unwrap().into_output().unwrap();
+        let register = Sr74hc595::new(output(22), output(23), output(24), 2).unwrap();
+        assert_eq!(register.width(), 16);
+        register.write_port(0x8001).unwrap();
+        assert_eq!(register.outputs(), vec![0x01, 0x80]);
+        assert_eq!(register.read_port().ok(), Some(0x8001));
+        assert!(register.write_port(0x1_0000).is_err());
+
+        let (mcp, registers, _) = expander();
+        mcp.write_port(0x0201).unwrap();
+        assert_eq!(registers.lock().unwrap()[0x14..0x16], [0x01, 0x02]);
+        assert!(mcp.write_port(0x1_0000).is_err());
+
+        let group = gpio.pin_group(&[5, 6]).unwrap();
+        let port: &dyn GpioPort = &group;
+        assert_eq!(port.width(), 2);
+        assert!(port.write_port(0b100).is_err());
+    }
+}
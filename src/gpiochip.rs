@@ -0,0 +1,576 @@
+use crate::edge::instant_from_monotonic;
+use crate::{open_file, EdgeKind, Error, GpioBackend, PinEvent, PinFunction, Pull, Trigger, GPIO_PIN_COUNT};
+
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::poll::{poll, PollFd, PollFlags};
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+
+// Layouts and flags from the GPIO v2 uAPI in <linux/gpio.h>.
+const GPIO_MAX_NAME_SIZE: usize = 32;
+const GPIO_V2_LINES_MAX: usize = 64;
+const GPIO_V2_LINE_NUM_ATTRS_MAX: usize = 10;
+
+const LINE_FLAG_USED: u64 = 1 << 0;
+const LINE_FLAG_INPUT: u64 = 1 << 2;
+const LINE_FLAG_OUTPUT: u64 = 1 << 3;
+const LINE_FLAG_EDGE_RISING: u64 = 1 << 4;
+const LINE_FLAG_EDGE_FALLING: u64 = 1 << 5;
+const LINE_FLAG_BIAS_PULL_UP: u64 = 1 << 8;
+const LINE_FLAG_BIAS_PULL_DOWN: u64 = 1 << 9;
+const LINE_FLAG_BIAS_DISABLED: u64 = 1 << 10;
+
+const LINE_DIRECTION_FLAGS: u64 = LINE_FLAG_INPUT | LINE_FLAG_OUTPUT;
+const LINE_BIAS_FLAGS: u64 = LINE_FLAG_BIAS_PULL_UP | LINE_FLAG_BIAS_PULL_DOWN | LINE_FLAG_BIAS_DISABLED;
+
+const LINE_ATTR_ID_OUTPUT_VALUES: u32 = 2;
+
+const LINE_EVENT_RISING_EDGE: u32 = 1;
+const LINE_EVENT_FALLING_EDGE: u32 = 2;
+
+const CONSUMER: &[u8] = b"rustberrypi";
+
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct LineAttribute {
+    id: u32,
+    padding: u32,
+    // Union of flags, output values and debounce period in the uAPI.
+    value: u64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct LineConfigAttribute {
+    attr: LineAttribute,
+    mask: u64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct LineConfig {
+    flags: u64,
+    num_attrs: u32,
+    padding: [u32; 5],
+    attrs: [LineConfigAttribute; GPIO_V2_LINE_NUM_ATTRS_MAX],
+}
+
+#[repr(C)]
+struct LineRequest {
+    offsets: [u32; GPIO_V2_LINES_MAX],
+    consumer: [u8; GPIO_MAX_NAME_SIZE],
+    config: LineConfig,
+    num_lines: u32,
+    event_buffer_size: u32,
+    padding: [u32; 5],
+    fd: i32,
+}
+
+#[repr(C)]
+struct LineValues {
+    bits: u64,
+    mask: u64,
+}
+
+#[repr(C)]
+struct LineInfo {
+    name: [u8; GPIO_MAX_NAME_SIZE],
+    consumer: [u8; GPIO_MAX_NAME_SIZE],
+    offset: u32,
+    num_attrs: u32,
+    flags: u64,
+    attrs: [LineAttribute; GPIO_V2_LINE_NUM_ATTRS_MAX],
+    padding: [u32; 4],
+}
+
+#[repr(C)]
+struct ChipInfo {
+    name: [u8; GPIO_MAX_NAME_SIZE],
+    label: [u8; GPIO_MAX_NAME_SIZE],
+    lines: u32,
+}
+
+#[repr(C)]
+struct LineEvent {
+    timestamp_ns: u64,
+    id: u32,
+    offset: u32,
+    seqno: u32,
+    line_seqno: u32,
+    padding: [u32; 6],
+}
+
+nix::ioctl_read!(gpio_get_chipinfo, 0xB4, 0x01, ChipInfo);
+nix::ioctl_readwrite!(gpio_v2_get_lineinfo, 0xB4, 0x05, LineInfo);
+nix::ioctl_readwrite!(gpio_v2_get_line, 0xB4, 0x07, LineRequest);
+nix::ioctl_readwrite!(gpio_v2_line_set_config, 0xB4, 0x0D, LineConfig);
+nix::ioctl_readwrite!(gpio_v2_line_get_values, 0xB4, 0x0E, LineValues);
+nix::ioctl_readwrite!(gpio_v2_line_set_values, 0xB4, 0x0F, LineValues);
+
+
+fn nul_terminated(bytes: &[u8]) -> &[u8] {
+    &bytes[..bytes.iter().position(|&byte| byte == 0).unwrap_or(bytes.len())]
+}
+
+// Who holds the line if it's in use by anyone other than us. Pins a
+// pinctrl function has taken are marked used with no consumer name.
+fn kernel_consumer(info: &LineInfo) -> Option<String> {
+    let consumer = nul_terminated(&info.consumer);
+    if info.flags & LINE_FLAG_USED == 0 || consumer == CONSUMER {
+        return None;
+    }
+    match consumer {
+        b"" => Some("kernel".to_string()),
+        name => Some(String::from_utf8_lossy(name).into_owned()),
+    }
+}
+
+// Every line's `kernel_consumer`, stopping at the end of the chip.
+fn kernel_consumers_from(file: &File) -> Vec<Option<String>> {
+    let mut consumers = Vec::new();
+    for pin in 0..GPIO_PIN_COUNT {
+        let mut info: LineInfo = unsafe { std::mem::zeroed() };
+        info.offset = pin;
+        if unsafe { gpio_v2_get_lineinfo(file.as_raw_fd(), &mut info) }.is_err() {
+            break;
+        }
+        consumers.push(kernel_consumer(&info));
+    }
+    consumers
+}
+
+/// The first `/dev/gpiochipN`, by `N`, whose driver labels it `label`,
+/// e.g. `"pinctrl-rp1"`. Chip numbers depend on probe order, so this is
+/// how a particular controller is found.
+pub(crate) fn find_chip_by_label(dev: impl AsRef<Path>, label: &str) -> Option<PathBuf> {
+    let mut chips: Vec<(u32, PathBuf)> = std::fs::read_dir(dev).ok()?
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let number = path.file_name()?.to_str()?.strip_prefix("gpiochip")?.parse().ok()?;
+            Some((number, path))
+        })
+        .collect();
+    chips.sort();
+    chips.into_iter().map(|(_, path)| path).find(|path| {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(_) => return false,
+        };
+        let mut info: ChipInfo = unsafe { std::mem::zeroed() };
+        unsafe { gpio_get_chipinfo(file.as_raw_fd(), &mut info) }.is_ok()
+            && nul_terminated(&info.label) == label.as_bytes()
+    })
+}
+
+/// The lines of the chip at `path` held by kernel drivers, for backends
+/// that reach the pins some other way. Empty if the chip can't be opened.
+pub(crate) fn kernel_consumers_at(path: impl AsRef<Path>) -> Vec<Option<String>> {
+    match File::open(path) {
+        Ok(file) => kernel_consumers_from(&file),
+        Err(_) => Vec::new(),
+    }
+}
+
+
+// A single-line config. Output lines also carry their initial level, since
+// the kernel otherwise drives them low when the direction changes.
+fn line_config(flags: u64, output: Option<bool>) -> LineConfig {
+    let mut config: LineConfig = unsafe { std::mem::zeroed() };
+    config.flags = flags;
+    if let Some(level) = output {
+        config.num_attrs = 1;
+        config.attrs[0] = LineConfigAttribute {
+            attr: LineAttribute { id: LINE_ATTR_ID_OUTPUT_VALUES, padding: 0, value: level as u64 },
+            mask: 1,
+        };
+    }
+    config
+}
+
+fn direction_flags(pin: u32, function: PinFunction) -> Result<u64, Error> {
+    match function {
+        PinFunction::Input => Ok(LINE_FLAG_INPUT),
+        PinFunction::Output => Ok(LINE_FLAG_OUTPUT),
+        _ => Err(Error::Unsupported(format!(
+            "cannot set pin {} to {:?}: the gpiochip backend only supports Input and Output", pin, function))),
+    }
+}
+
+fn bias_flags(pull: Pull) -> u64 {
+    match pull {
+        Pull::None => LINE_FLAG_BIAS_DISABLED,
+        Pull::Up => LINE_FLAG_BIAS_PULL_UP,
+        Pull::Down => LINE_FLAG_BIAS_PULL_DOWN,
+    }
+}
+
+fn edge_flags(trigger: Trigger) -> u64 {
+    match trigger {
+        Trigger::Rising => LINE_FLAG_EDGE_RISING,
+        Trigger::Falling => LINE_FLAG_EDGE_FALLING,
+        Trigger::Both => LINE_FLAG_EDGE_RISING | LINE_FLAG_EDGE_FALLING,
+    }
+}
+
+fn edge_from_event_id(id: u32) -> Option<EdgeKind> {
+    match id {
+        LINE_EVENT_RISING_EDGE => Some(EdgeKind::Rising),
+        LINE_EVENT_FALLING_EDGE => Some(EdgeKind::Falling),
+        _ => None,
+    }
+}
+
+fn function_from_flags(flags: u64) -> PinFunction {
+    if flags & LINE_FLAG_OUTPUT != 0 { PinFunction::Output } else { PinFunction::Input }
+}
+
+fn pull_from_flags(flags: u64) -> Pull {
+    if flags & LINE_FLAG_BIAS_PULL_UP != 0 {
+        Pull::Up
+    } else if flags & LINE_FLAG_BIAS_PULL_DOWN != 0 {
+        Pull::Down
+    } else {
+        Pull::None
+    }
+}
+
+
+struct Line {
+    file: File,
+    flags: u64,
+    // The level last driven or left pending on an input, or read back when
+    // the line was first held, kept through every reconfiguration.
+    level: bool,
+}
+
+// The config for giving a line `flags`. An output keeps the line's level.
+fn line_config_for(line: &Line, flags: u64) -> LineConfig {
+    line_config(flags, (flags & LINE_FLAG_OUTPUT != 0).then_some(line.level))
+}
+
+// The config a line is first requested with: no direction, so the kernel
+// leaves it as it is and an output isn't driven.
+fn as_is_config() -> LineConfig {
+    line_config(0, None)
+}
+
+/// Pin access through a `/dev/gpiochipN` character device. Each pin is
+/// requested as its own line the first time it's used and held until the
+/// chip is dropped. Line offsets are the BCM GPIO numbers.
+pub(crate) struct GpioChip {
+    file: File,
+    path: PathBuf,
+    lines: Mutex<HashMap<u32, Line>>,
+}
+
+impl GpioChip {
+
+    pub(crate) fn open(path: impl Into<PathBuf>) -> Result<Self, Error> {
+        let path = path.into();
+        Ok(Self {
+            file: open_file(&path)?,
+            path,
+            lines: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn line_flags(&self, pin: u32) -> Result<u64, Error> {
+        if let Some(line) = self.lines.lock().unwrap().get(&pin) {
+            return Ok(line.flags);
+        }
+        self.line_info_flags(pin)
+    }
+
+    fn line_info_flags(&self, pin: u32) -> Result<u64, Error> {
+        let mut info: LineInfo = unsafe { std::mem::zeroed() };
+        info.offset = pin;
+        unsafe { gpio_v2_get_lineinfo(self.file.as_raw_fd(), &mut info) }
+            .map_err(|e| Error::from_nix(format!(
+                "failed to read line info for pin {} from {}", pin, self.path.display()), e))?;
+        Ok(info.flags & (LINE_DIRECTION_FLAGS | LINE_BIAS_FLAGS))
+    }
+
+    // The pin's line, requested as it is on first use, with the level it
+    // was at so that reconfiguring an output keeps it there.
+    fn hold<'a>(&self, lines: &'a mut HashMap<u32, Line>, pin: u32) -> Result<&'a mut Line, Error> {
+        match lines.entry(pin) {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => {
+                let flags = self.line_info_flags(pin)?;
+                let mut request: LineRequest = unsafe { std::mem::zeroed() };
+                request.offsets[0] = pin;
+                request.consumer[..CONSUMER.len()].copy_from_slice(CONSUMER);
+                request.config = as_is_config();
+                request.num_lines = 1;
+                unsafe { gpio_v2_get_line(self.file.as_raw_fd(), &mut request) }
+                    .map_err(|e| Error::from_nix(format!(
+                        "failed to request pin {} from {}", pin, self.path.display()), e))?;
+                let file = unsafe { File::from_raw_fd(request.fd) };
+                let level = read_line(&file, pin)?;
+                Ok(entry.insert(Line { file, flags, level }))
+            }
+        }
+    }
+
+    // Applies `flags` to the pin's line.
+    fn configure(&self, pin: u32, flags: u64) -> Result<(), Error> {
+        let mut lines = self.lines.lock().unwrap();
+        let line = self.hold(&mut lines, pin)?;
+        let mut config = line_config_for(line, flags);
+        unsafe { gpio_v2_line_set_config(line.file.as_raw_fd(), &mut config) }
+            .map_err(|e| Error::from_nix(format!("failed to reconfigure pin {}", pin), e))?;
+        line.flags = flags;
+        Ok(())
+    }
+
+    fn read_line_event(file: &File, pin: u32, timeout: Option<Duration>) -> Result<PinEvent, Error> {
+        let start = Instant::now();
+        loop {
+            let remaining = match timeout {
+                None => -1,
+                Some(timeout) => match timeout.checked_sub(start.elapsed()) {
+                    Some(remaining) => remaining.as_millis().min(i32::MAX as u128) as i32,
+                    None => 0,
+                },
+            };
+            let mut fds = [PollFd::new(file.as_raw_fd(), PollFlags::POLLIN)];
+            match poll(&mut fds, remaining) {
+                Ok(0) => return Err(Error::Timeout(format!("no edge on pin {} within {:?}", pin, timeout.unwrap_or_default()))),
+                Ok(_) => {}
+                Err(e) if e.as_errno() == Some(Errno::EINTR) => continue,
+                Err(e) => return Err(Error::from_nix(format!("failed to wait for an edge on pin {}", pin), e)),
+            }
+
+            let event = read_edge_event(file)
+                .map_err(|e| Error::from_io(format!("failed to read an edge event for pin {}", pin), e))?;
+            if let Some(event) = event {
+                return Ok(event);
+            }
+        }
+    }
+
+    // Puts the line into edge detection mode and returns a duplicate of its
+    // fd for waiting on.
+    fn enable_line_events(&self, pin: u32, trigger: Trigger) -> Result<File, Error> {
+        let flags = self.line_flags(pin)?;
+        if flags & LINE_FLAG_OUTPUT != 0 {
+            return Err(Error::Unsupported(format!("cannot wait for an edge on pin {}: it is an output", pin)));
+        }
+        self.configure(pin, flags | LINE_FLAG_INPUT | edge_flags(trigger))?;
+        self.lines.lock().unwrap()[&pin].file.try_clone()
+            .map_err(|e| Error::from_io(format!("failed to duplicate the line for pin {}", pin), e))
+    }
+}
+
+
+fn read_line(file: &File, pin: u32) -> Result<bool, Error> {
+    let mut values = LineValues { bits: 0, mask: 1 };
+    unsafe { gpio_v2_line_get_values(file.as_raw_fd(), &mut values) }
+        .map_err(|e| Error::from_nix(format!("failed to read pin {}", pin), e))?;
+    Ok(values.bits & 1 != 0)
+}
+
+/// Reads one event from a line fd. Events other than edges give `None`.
+pub(crate) fn read_edge_event(mut file: &File) -> std::io::Result<Option<PinEvent>> {
+    let mut event: LineEvent = unsafe { std::mem::zeroed() };
+    let bytes = unsafe {
+        std::slice::from_raw_parts_mut(&mut event as *mut LineEvent as *mut u8, std::mem::size_of::<LineEvent>())
+    };
+    file.read_exact(bytes)?;
+    Ok(edge_from_event_id(event.id).map(|edge| PinEvent {
+        pin: event.offset,
+        edge,
+        timestamp: instant_from_monotonic(event.timestamp_ns),
+    }))
+}
+
+/// Encodes an edge the way a line fd delivers it, for backends that stand
+/// in for one.
+#[cfg(any(test, feature = "mock"))]
+pub(crate) fn edge_event_bytes(pin: u32, edge: EdgeKind, timestamp_ns: u64) -> [u8; 48] {
+    let id = match edge {
+        EdgeKind::Rising => LINE_EVENT_RISING_EDGE,
+        EdgeKind::Falling => LINE_EVENT_FALLING_EDGE,
+    };
+    let mut bytes = [0u8; 48];
+    bytes[..8].copy_from_slice(&timestamp_ns.to_ne_bytes());
+    bytes[8..12].copy_from_slice(&id.to_ne_bytes());
+    bytes[12..16].copy_from_slice(&pin.to_ne_bytes());
+    bytes
+}
+
+impl GpioBackend for GpioChip {
+
+    fn function(&self, pin: u32) -> Result<PinFunction, Error> {
+        Ok(function_from_flags(self.line_flags(pin)?))
+    }
+
+    fn set_function(&self, pin: u32, function: PinFunction) -> Result<(), Error> {
+        let direction = direction_flags(pin, function)?;
+        let flags = self.line_flags(pin)? & LINE_BIAS_FLAGS | direction;
+        self.configure(pin, flags)
+    }
+
+    fn pull(&self, pin: u32) -> Result<Pull, Error> {
+        Ok(pull_from_flags(self.line_flags(pin)?))
+    }
+
+    fn line_name(&self, pin: u32) -> Option<String> {
+        let mut info: LineInfo = unsafe { std::mem::zeroed() };
+        info.offset = pin;
+        unsafe { gpio_v2_get_lineinfo(self.file.as_raw_fd(), &mut info) }.ok()?;
+        let name = nul_terminated(&info.name);
+        (!name.is_empty()).then(|| String::from_utf8_lossy(name).into_owned())
+    }
+
+    fn kernel_consumers(&self) -> Vec<Option<String>> {
+        kernel_consumers_from(&self.file)
+    }
+
+    // The kernel only accepts bias flags alongside a direction, so the
+    // line's current direction is kept.
+    fn set_pull(&self, pin: u32, pull: Pull) -> Result<(), Error> {
+        let flags = self.line_flags(pin)? & LINE_DIRECTION_FLAGS | bias_flags(pull);
+        self.configure(pin, flags)
+    }
+
+    // An input can't be driven, so the level is kept for the output
+    // request when it's next made an output.
+    fn write(&self, pin: u32, level: bool) -> Result<(), Error> {
+        let mut lines = self.lines.lock().unwrap();
+        let line = self.hold(&mut lines, pin)?;
+        if line.flags & LINE_FLAG_OUTPUT != 0 {
+            let mut values = LineValues { bits: level as u64, mask: 1 };
+            unsafe { gpio_v2_line_set_values(line.file.as_raw_fd(), &mut values) }
+                .map_err(|e| Error::from_nix(format!("failed to drive pin {}", pin), e))?;
+        }
+        line.level = level;
+        Ok(())
+    }
+
+    fn read(&self, pin: u32) -> Result<bool, Error> {
+        let mut lines = self.lines.lock().unwrap();
+        let line = self.hold(&mut lines, pin)?;
+        read_line(&line.file, pin)
+    }
+
+    // The line is given edge detection for the duration of the wait, then
+    // put back to its previous configuration.
+    fn wait_for_edge(&self, pin: u32, trigger: Trigger, timeout: Option<Duration>) -> Result<PinEvent, Error> {
+        let flags = self.line_flags(pin)?;
+        let result = self.enable_line_events(pin, trigger)
+            .and_then(|file| Self::read_line_event(&file, pin, timeout));
+        self.configure(pin, flags)?;
+        result
+    }
+
+    // An event loop needs O_NONBLOCK so a spurious wakeup can't stall it.
+    // The flag is shared with the held line's fd, whose ioctls ignore it.
+    fn edge_event_file(&self, pin: u32, trigger: Trigger) -> Result<File, Error> {
+        let file = self.enable_line_events(pin, trigger)?;
+        fcntl(file.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK))
+            .map_err(|e| Error::from_nix(format!("failed to make the line for pin {} non-blocking", pin), e))?;
+        Ok(file)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uapi_struct_sizes() {
+        assert_eq!(std::mem::size_of::<LineAttribute>(), 16);
+        assert_eq!(std::mem::size_of::<LineConfig>(), 272);
+        assert_eq!(std::mem::size_of::<LineRequest>(), 592);
+        assert_eq!(std::mem::size_of::<LineValues>(), 16);
+        assert_eq!(std::mem::size_of::<LineInfo>(), 256);
+        assert_eq!(std::mem::size_of::<ChipInfo>(), 68);
+        assert_eq!(std::mem::size_of::<LineEvent>(), 48);
+    }
+
+    #[test]
+    fn test_line_config_output_value() {
+        let config = line_config(LINE_FLAG_OUTPUT | LINE_FLAG_BIAS_DISABLED, Some(true));
+        assert_eq!(config.flags, LINE_FLAG_OUTPUT | LINE_FLAG_BIAS_DISABLED);
+        assert_eq!(config.num_attrs, 1);
+        assert_eq!(config.attrs[0].attr.id, LINE_ATTR_ID_OUTPUT_VALUES);
+        assert_eq!(config.attrs[0].attr.value, 1);
+        assert_eq!(config.attrs[0].mask, 1);
+        assert_eq!(line_config(LINE_FLAG_INPUT, None).num_attrs, 0);
+    }
+
+    #[test]
+    fn test_reconfigured_output_keeps_level() {
+        let path = std::env::temp_dir().join(format!("rustberrypi-line-{}", std::process::id()));
+        let line = Line { file: File::create(&path).unwrap(), flags: LINE_FLAG_OUTPUT | LINE_FLAG_BIAS_DISABLED, level: true };
+        // What `set_pull` asks of a high output.
+        let config = line_config_for(&line, line.flags & LINE_DIRECTION_FLAGS | bias_flags(Pull::Up));
+        assert_eq!((config.num_attrs, config.attrs[0].attr.value), (1, 1));
+        assert_eq!(line_config_for(&line, LINE_FLAG_INPUT).num_attrs, 0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_read_leaves_output_level() {
+        // A line is first held with no direction and no output value, which
+        // the kernel takes as leaving it alone, so a read can't drive it.
+        let config = as_is_config();
+        assert_eq!(config.flags & LINE_DIRECTION_FLAGS, 0);
+        assert_eq!(config.num_attrs, 0);
+        // It keeps the level read back when it's next configured.
+        let path = std::env::temp_dir().join(format!("rustberrypi-held-line-{}", std::process::id()));
+        let held = Line { file: File::create(&path).unwrap(), flags: LINE_FLAG_OUTPUT, level: true };
+        assert_eq!(line_config_for(&held, LINE_FLAG_OUTPUT | bias_flags(Pull::Down)).attrs[0].attr.value, 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_flag_mapping() {
+        assert_eq!(direction_flags(4, PinFunction::Output).ok(), Some(LINE_FLAG_OUTPUT));
+        assert!(direction_flags(4, PinFunction::Alt0).is_err());
+        for &pull in [Pull::None, Pull::Up, Pull::Down].iter() {
+            assert_eq!(pull_from_flags(LINE_FLAG_INPUT | bias_flags(pull)), pull);
+        }
+        assert_eq!(function_from_flags(LINE_FLAG_OUTPUT | LINE_FLAG_BIAS_PULL_UP), PinFunction::Output);
+        assert_eq!(function_from_flags(0), PinFunction::Input);
+        assert_eq!(edge_flags(Trigger::Both), LINE_FLAG_EDGE_RISING | LINE_FLAG_EDGE_FALLING);
+        assert_eq!(edge_from_event_id(LINE_EVENT_FALLING_EDGE), Some(EdgeKind::Falling));
+        assert_eq!(edge_from_event_id(0), None);
+    }
+
+    #[test]
+    fn test_find_chip_by_label() {
+        let dev = std::env::temp_dir().join(format!("rustberrypi-chips-{}", std::process::id()));
+        std::fs::create_dir_all(&dev).unwrap();
+        std::fs::write(dev.join("gpiochip0"), b"").unwrap();
+        // Regular files have no chip info to match.
+        assert_eq!(find_chip_by_label(&dev, "pinctrl-rp1"), None);
+        assert_eq!(find_chip_by_label(dev.join("missing"), "pinctrl-rp1"), None);
+        std::fs::remove_dir_all(&dev).unwrap();
+    }
+
+    #[test]
+    fn test_kernel_consumer() {
+        let mut info: LineInfo = unsafe { std::mem::zeroed() };
+        assert_eq!(kernel_consumer(&info), None);
+        info.flags = LINE_FLAG_USED | LINE_FLAG_OUTPUT;
+        assert_eq!(kernel_consumer(&info).as_deref(), Some("kernel"));
+        info.consumer[..8].copy_from_slice(b"spi0 CS0");
+        assert_eq!(kernel_consumer(&info).as_deref(), Some("spi0 CS0"));
+        info.consumer = [0; GPIO_MAX_NAME_SIZE];
+        info.consumer[..CONSUMER.len()].copy_from_slice(CONSUMER);
+        assert_eq!(kernel_consumer(&info), None);
+    }
+}
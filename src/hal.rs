@@ -0,0 +1,382 @@
+use crate::{Delay, Error, HwPwm, I2c, I2cAddress, Input, Mcp23017Pin, Output, Pin, SoftPwmChannel, Spi};
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::{self, ErrorKind, ErrorType, InputPin, OutputPin, StatefulOutputPin};
+use embedded_hal::i2c::{self, NoAcknowledgeSource, Operation, SevenBitAddress, TenBitAddress};
+use embedded_hal::pwm::{self, SetDutyCycle};
+use embedded_hal::spi::{self, SpiBus};
+
+
+impl digital::Error for Error {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+impl pwm::Error for Error {
+    fn kind(&self) -> pwm::ErrorKind {
+        pwm::ErrorKind::Other
+    }
+}
+
+impl spi::Error for Error {
+    fn kind(&self) -> spi::ErrorKind {
+        spi::ErrorKind::Other
+    }
+}
+
+impl i2c::Error for Error {
+    fn kind(&self) -> i2c::ErrorKind {
+        match self {
+            Error::NoAcknowledge(_) => i2c::ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown),
+            _ => i2c::ErrorKind::Other,
+        }
+    }
+}
+
+impl<'a, Mode> ErrorType for Pin<'a, Mode> {
+    type Error = Error;
+}
+
+impl<'a> InputPin for Pin<'a, Input> {
+    fn is_high(&mut self) -> Result<bool, Error> {
+        self.read()
+    }
+
+    fn is_low(&mut self) -> Result<bool, Error> {
+        Ok(!self.read()?)
+    }
+}
+
+impl<'a> OutputPin for Pin<'a, Output> {
+    fn set_low(&mut self) -> Result<(), Error> {
+        Pin::set_low(self)
+    }
+
+    fn set_high(&mut self) -> Result<(), Error> {
+        Pin::set_high(self)
+    }
+}
+
+impl<'a> StatefulOutputPin for Pin<'a, Output> {
+    // Falls back to the pin's level if nothing has been driven through
+    // this GPIO yet.
+    fn is_set_high(&mut self) -> Result<bool, Error> {
+        match self.output_state() {
+            Some(level) => Ok(level),
+            None => self.read(),
+        }
+    }
+
+    fn is_set_low(&mut self) -> Result<bool, Error> {
+        Ok(!self.is_set_high()?)
+    }
+
+    fn toggle(&mut self) -> Result<(), Error> {
+        Pin::toggle(self)
+    }
+}
+
+
+impl<'e, Mode> ErrorType for Mcp23017Pin<'e, Mode> {
+    type Error = Error;
+}
+
+impl<'e> InputPin for Mcp23017Pin<'e, Input> {
+    fn is_high(&mut self) -> Result<bool, Error> {
+        self.read()
+    }
+
+    fn is_low(&mut self) -> Result<bool, Error> {
+        Ok(!self.read()?)
+    }
+}
+
+impl<'e> OutputPin for Mcp23017Pin<'e, Output> {
+    fn set_low(&mut self) -> Result<(), Error> {
+        Mcp23017Pin::set_low(self)
+    }
+
+    fn set_high(&mut self) -> Result<(), Error> {
+        Mcp23017Pin::set_high(self)
+    }
+}
+
+impl<'e> StatefulOutputPin for Mcp23017Pin<'e, Output> {
+    fn is_set_high(&mut self) -> Result<bool, Error> {
+        Mcp23017Pin::is_set_high(self)
+    }
+
+    fn is_set_low(&mut self) -> Result<bool, Error> {
+        Ok(!Mcp23017Pin::is_set_high(self)?)
+    }
+
+    fn toggle(&mut self) -> Result<(), Error> {
+        Mcp23017Pin::toggle(self)
+    }
+}
+
+
+// Duty cycles are fractions here, so the full u16 range is used and
+// scaled.
+fn duty_fraction(duty: u16) -> f64 {
+    duty as f64 / u16::MAX as f64
+}
+
+impl pwm::ErrorType for HwPwm {
+    type Error = Error;
+}
+
+impl SetDutyCycle for HwPwm {
+    fn max_duty_cycle(&self) -> u16 {
+        u16::MAX
+    }
+
+    fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Error> {
+        HwPwm::set_duty_cycle(self, duty_fraction(duty))
+    }
+}
+
+impl<'p, 'a> pwm::ErrorType for SoftPwmChannel<'p, 'a> {
+    type Error = Error;
+}
+
+impl<'p, 'a> SetDutyCycle for SoftPwmChannel<'p, 'a> {
+    fn max_duty_cycle(&self) -> u16 {
+        u16::MAX
+    }
+
+    fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Error> {
+        SoftPwmChannel::set_duty_cycle(self, duty_fraction(duty))
+    }
+}
+
+
+impl DelayNs for Delay {
+    fn delay_ns(&mut self, ns: u32) {
+        Delay::delay_ns(self, ns)
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        Delay::delay_us(self, us)
+    }
+
+    fn delay_ms(&mut self, ms: u32) {
+        Delay::delay_ms(self, ms)
+    }
+}
+
+
+impl spi::ErrorType for Spi {
+    type Error = Error;
+}
+
+// The controller asserts its chip select for each call by itself.
+impl SpiBus for Spi {
+    fn read(&mut self, words: &mut [u8]) -> Result<(), Error> {
+        Spi::read(self, words)
+    }
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Error> {
+        Spi::write(self, words)
+    }
+
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Error> {
+        let mut buffer = vec![0; read.len().max(write.len())];
+        buffer[..write.len()].copy_from_slice(write);
+        Spi::transfer(self, &mut buffer)?;
+        read.copy_from_slice(&buffer[..read.len()]);
+        Ok(())
+    }
+
+    fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Error> {
+        Spi::transfer(self, words)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+
+impl i2c::ErrorType for I2c {
+    type Error = Error;
+}
+
+// The controllers can only do a write followed by a read, so consecutive
+// operations of the same kind are merged and anything else is refused.
+fn transaction(bus: &mut I2c, address: I2cAddress, operations: &mut [Operation<'_>]) -> Result<(), Error> {
+    let split = operations.iter().position(|op| matches!(op, Operation::Read(_))).unwrap_or(operations.len());
+    let (writes, reads) = operations.split_at_mut(split);
+    let mut data = Vec::new();
+    for op in writes.iter() {
+        if let Operation::Write(bytes) = op {
+            data.extend_from_slice(bytes);
+        }
+    }
+    let mut buffers = Vec::with_capacity(reads.len());
+    for op in reads.iter_mut() {
+        match op {
+            Operation::Read(buffer) => buffers.push(buffer),
+            Operation::Write(_) => return Err(Error::Unsupported("I2C transactions can't write after reading".to_string())),
+        }
+    }
+    let mut input = vec![0; buffers.iter().map(|buffer| buffer.len()).sum()];
+    match (writes.is_empty(), buffers.is_empty()) {
+        (_, true) => bus.write(address, &data)?,
+        (true, false) => bus.read(address, &mut input)?,
+        (false, false) => bus.write_read(address, &data, &mut input)?,
+    }
+    let mut offset = 0;
+    for buffer in buffers {
+        buffer.copy_from_slice(&input[offset..offset + buffer.len()]);
+        offset += buffer.len();
+    }
+    Ok(())
+}
+
+impl i2c::I2c<SevenBitAddress> for I2c {
+    fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Error> {
+        transaction(self, I2cAddress::SevenBit(address), operations)
+    }
+}
+
+impl i2c::I2c<TenBitAddress> for I2c {
+    fn transaction(&mut self, address: u16, operations: &mut [Operation<'_>]) -> Result<(), Error> {
+        transaction(self, I2cAddress::TenBit(address), operations)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{I2cBackend, PwmChannel, SoftPwm, SpiBackend, SpiMode, GPIO};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    fn blink(pin: &mut impl StatefulOutputPin) -> Result<bool, ErrorKind> {
+        use embedded_hal::digital::Error as _;
+        pin.set_high().map_err(|e| e.kind())?;
+        pin.toggle().map_err(|e| e.kind())?;
+        pin.is_set_low().map_err(|e| e.kind())
+    }
+
+    #[test]
+    fn test_embedded_hal_pins() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let mut led = gpio.pin(17).unwrap().into_output().unwrap();
+        assert!(!led.is_set_high().unwrap());
+        assert_eq!(blink(&mut led), Ok(true));
+        assert_eq!(led.output_state(), Some(false));
+
+        let mut input = gpio.pin(27).unwrap().into_input().unwrap();
+        assert!(input.is_low().unwrap());
+        unsafe { gpio.write_raw(0x34, 1 << 27) };
+        assert!(input.is_high().unwrap());
+    }
+
+    #[test]
+    fn test_embedded_hal_expander_pins() {
+        let (expander, _, pins) = crate::mcp23017::tests::expander();
+        let mut led = expander.pin(0).unwrap().into_output().unwrap();
+        assert_eq!(blink(&mut led), Ok(true));
+        let mut input = expander.pin(15).unwrap().into_input().unwrap();
+        assert!(input.is_low().unwrap());
+        *pins.lock().unwrap() = 0x8000;
+        assert!(input.is_high().unwrap());
+    }
+
+    struct Loopback;
+
+    impl SpiBackend for Loopback {
+        fn transfer(&mut self, _buffer: &mut [u8]) -> Result<(), Error> { Ok(()) }
+        fn set_mode(&mut self, _mode: SpiMode) -> Result<(), Error> { Ok(()) }
+        fn set_frequency(&mut self, freq_hz: u32) -> Result<u32, Error> { Ok(freq_hz) }
+        fn set_cs_active_high(&mut self, _active_high: bool) -> Result<(), Error> { Ok(()) }
+    }
+
+    #[test]
+    fn test_embedded_hal_spi_bus() {
+        let mut spi = Spi::custom(Loopback);
+        let mut read = [0u8; 3];
+        SpiBus::transfer(&mut spi, &mut read, &[1, 2]).unwrap();
+        assert_eq!(read, [1, 2, 0]);
+        let mut read = [0u8; 1];
+        SpiBus::transfer(&mut spi, &mut read, &[7, 8, 9]).unwrap();
+        assert_eq!(read, [7]);
+    }
+
+    // The address, bytes written and bytes read of one call.
+    type I2cCall = (I2cAddress, Vec<u8>, usize);
+
+    // Records each call and answers reads with a count up from 1.
+    struct I2cLog(Arc<Mutex<Vec<I2cCall>>>);
+
+    impl I2cBackend for I2cLog {
+        fn write(&mut self, address: I2cAddress, data: &[u8]) -> Result<(), Error> {
+            self.write_read(address, data, &mut [])
+        }
+        fn read(&mut self, address: I2cAddress, buffer: &mut [u8]) -> Result<(), Error> {
+            self.write_read(address, &[], buffer)
+        }
+        fn write_read(&mut self, address: I2cAddress, data: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+            self.0.lock().unwrap().push((address, data.to_vec(), buffer.len()));
+            buffer.iter_mut().zip(1..).for_each(|(byte, value)| *byte = value);
+            Ok(())
+        }
+        fn set_frequency(&mut self, freq_hz: u32) -> Result<u32, Error> { Ok(freq_hz) }
+        fn set_timeout(&mut self, _timeout: Duration) -> Result<(), Error> { Ok(()) }
+    }
+
+    #[test]
+    fn test_embedded_hal_i2c() {
+        use embedded_hal::i2c::{Error as _, I2c as _};
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut bus = I2c::custom(I2cLog(Arc::clone(&log)));
+        let (mut first, mut second) = ([0u8; 1], [0u8; 2]);
+        bus.transaction(0x50u8, &mut [
+            Operation::Write(&[1]), Operation::Write(&[2]),
+            Operation::Read(&mut first), Operation::Read(&mut second),
+        ]).unwrap();
+        assert_eq!((first, second), ([1], [2, 3]));
+        bus.transaction(0x2a5u16, &mut [Operation::Read(&mut first)]).unwrap();
+        assert!(bus.transaction(0x50u8, &mut [Operation::Read(&mut first), Operation::Write(&[1])]).is_err());
+        assert_eq!(*log.lock().unwrap(), vec![
+            (I2cAddress::SevenBit(0x50), vec![1, 2], 3),
+            (I2cAddress::TenBit(0x2a5), vec![], 1),
+        ]);
+        let error = Error::NoAcknowledge(String::new());
+        assert_eq!(error.kind(), i2c::ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown));
+    }
+
+    #[test]
+    fn test_embedded_hal_delay() {
+        fn settle(delay: &mut impl DelayNs) {
+            delay.delay_us(200);
+        }
+        let started = Instant::now();
+        settle(&mut Delay::new());
+        assert!(started.elapsed() >= Duration::from_micros(200));
+    }
+
+    #[test]
+    fn test_embedded_hal_pwm() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let soft = SoftPwm::new(&gpio).unwrap();
+        soft.start(20, 100.0, 0.0).unwrap();
+        let mut channel = soft.channel(20).unwrap();
+        channel.set_duty_cycle_percent(50).unwrap();
+        assert!((channel.duty_cycle().unwrap() - 0.5).abs() < 1e-4);
+        SetDutyCycle::set_duty_cycle_fully_on(&mut channel).unwrap();
+        assert_eq!(channel.duty_cycle().ok(), Some(1.0));
+
+        let path = std::env::temp_dir().join(format!("rustberrypi-hal-pwm-{}", std::process::id()));
+        std::fs::write(&path, vec![0u8; 4096]).unwrap();
+        let mut pwm = HwPwm::open(&path, 0, PwmChannel::Pwm0).ok().unwrap();
+        pwm.set_duty_cycle_fraction(1, 4).unwrap();
+        assert!((pwm.duty_cycle() - 0.25).abs() < 1e-4);
+        drop(pwm);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
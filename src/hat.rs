@@ -0,0 +1,320 @@
+use crate::{Error, PinConfig, PinConfigSet, PinFunction, Pull, SoftI2c, GPIO};
+
+
+// Every HAT's ID EEPROM sits at this address on ID_SD (GPIO 0) and ID_SC
+// (GPIO 1), which the firmware reads at boot and then leaves alone.
+const EEPROM_ADDRESS: u8 = 0x50;
+const ID_SD: u32 = 0;
+const ID_SC: u32 = 1;
+
+const SIGNATURE: &[u8; 4] = b"R-Pi";
+const HEADER_LEN: usize = 12;
+const ATOM_HEADER_LEN: usize = 8;
+// 24C32s are the usual part; nothing valid is bigger than a 24C512.
+const MAX_EEPROM_LEN: usize = 64 * 1024;
+
+const ATOM_VENDOR_INFO: u16 = 0x0001;
+const ATOM_GPIO_MAP: u16 = 0x0002;
+const ATOM_DEVICE_TREE: u16 = 0x0003;
+const ATOM_CUSTOM: u16 = 0x0004;
+
+// Bank drive, power and a byte for each of GPIO 0-27.
+const GPIO_MAP_LEN: usize = 30;
+
+
+/// The vendor info atom: who made the HAT and what it is.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HatVendor {
+    /// Unique to each board made, as stored.
+    pub uuid: [u8; 16],
+    pub product_id: u16,
+    pub product_version: u16,
+    pub vendor: String,
+    pub product: String,
+}
+
+/// How the HAT wants one of GPIO 0-27 set up.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct HatPin {
+    pub pin: u32,
+    pub function: PinFunction,
+    /// `None` if the pin's pull is left at its default.
+    pub pull: Option<Pull>,
+}
+
+/// The GPIO map atom: the pins the HAT uses and the bank settings it
+/// needs. Settings it leaves at the default are `None`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HatGpioMap {
+    pub drive_ma: Option<u8>,
+    pub slew_rate_limited: Option<bool>,
+    pub hysteresis: Option<bool>,
+    /// The current the HAT can supply the Pi through the header, if it
+    /// back-powers it.
+    pub back_power_ma: Option<u32>,
+    /// The pins marked as used, in order.
+    pub pins: Vec<HatPin>,
+}
+
+impl HatGpioMap {
+
+    /// The used pins as a configuration for `GPIO::apply_config`, each
+    /// named `"GPIO n"`. Pulls left at their default are given as none.
+    pub fn pin_config(&self) -> PinConfigSet {
+        self.pins.iter().fold(PinConfigSet::new(), |set, pin| {
+            set.with_pin(PinConfig::new(pin.pin, pin.function)
+                .with_pull(pin.pull.unwrap_or(Pull::None))
+                .with_name(format!("GPIO {}", pin.pin)))
+        })
+    }
+}
+
+
+/// The contents of a HAT's ID EEPROM, in the format of the HAT
+/// specification: a header then atoms, each checked against its CRC.
+/// Atoms of types this doesn't know are skipped.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HatEeprom {
+    version: u8,
+    vendor: Option<HatVendor>,
+    gpio_map: Option<HatGpioMap>,
+    device_tree: Option<Vec<u8>>,
+    custom: Vec<Vec<u8>>,
+}
+
+impl HatEeprom {
+
+    /// Reads the EEPROM of the attached HAT by bit-banging I2C on ID_SD and
+    /// ID_SC, GPIO 0 and 1, which are left as inputs. Fails with
+    /// `Error::NoAcknowledge` if no HAT (or one without an EEPROM) is
+    /// attached.
+    pub fn read(gpio: &GPIO) -> Result<Self, Error> {
+        let i2c = SoftI2c::new(gpio.pin(ID_SD)?, gpio.pin(ID_SC)?)?;
+        let mut header = [0u8; HEADER_LEN];
+        i2c.write_read(EEPROM_ADDRESS, &[0, 0], &mut header)?;
+        let length = Self::check_header(&header)?;
+        let mut image = vec![0u8; length];
+        i2c.write_read(EEPROM_ADDRESS, &[0, 0], &mut image)?;
+        Self::parse(&image)
+    }
+
+    // Returns the length of the whole image.
+    fn check_header(header: &[u8]) -> Result<usize, Error> {
+        if header.len() < HEADER_LEN || &header[..4] != SIGNATURE {
+            return Err(Error::Other("the EEPROM doesn't hold a HAT ID (no \"R-Pi\" signature)".to_string()));
+        }
+        let length = u32::from_le_bytes([header[8], header[9], header[10], header[11]]) as usize;
+        if !(HEADER_LEN..=MAX_EEPROM_LEN).contains(&length) {
+            return Err(Error::Other(format!("the HAT ID header gives an impossible length of {} bytes", length)));
+        }
+        Ok(length)
+    }
+
+    /// Parses an EEPROM image, e.g. a `.eep` file made with `eepmake`.
+    pub fn parse(image: &[u8]) -> Result<Self, Error> {
+        let length = Self::check_header(image)?;
+        if image.len() < length {
+            return Err(Error::Other(format!("the HAT ID is {} bytes, but only {} were given", length, image.len())));
+        }
+        let atoms = u16::from_le_bytes([image[6], image[7]]);
+        let mut eeprom = Self { version: image[4], vendor: None, gpio_map: None, device_tree: None, custom: Vec::new() };
+        let mut offset = HEADER_LEN;
+        for index in 0..atoms {
+            let (kind, data, next) = atom(&image[..length], offset)
+                .map_err(|message| Error::Other(format!("HAT ID atom {}: {}", index, message)))?;
+            match kind {
+                ATOM_VENDOR_INFO => eeprom.vendor = Some(vendor_info(data)?),
+                ATOM_GPIO_MAP => eeprom.gpio_map = Some(gpio_map(data)?),
+                ATOM_DEVICE_TREE => eeprom.device_tree = Some(data.to_vec()),
+                ATOM_CUSTOM => eeprom.custom.push(data.to_vec()),
+                _ => {}
+            }
+            offset = next;
+        }
+        Ok(eeprom)
+    }
+
+    /// The format version, 1 for the original HAT specification.
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn vendor(&self) -> Option<&HatVendor> {
+        self.vendor.as_ref()
+    }
+
+    pub fn gpio_map(&self) -> Option<&HatGpioMap> {
+        self.gpio_map.as_ref()
+    }
+
+    /// The device tree overlay the firmware loads for the HAT, as a blob.
+    pub fn device_tree(&self) -> Option<&[u8]> {
+        self.device_tree.as_deref()
+    }
+
+    /// The vendor's own atoms, in order.
+    pub fn custom(&self) -> &[Vec<u8>] {
+        &self.custom
+    }
+}
+
+
+// CRC-16/ARC, as `eepmake` computes over each atom's header and data.
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u16, |crc, _| if crc & 1 != 0 { crc >> 1 ^ 0xa001 } else { crc >> 1 })
+    })
+}
+
+// Splits off the atom at `offset`, checking its CRC. Returns its type, its
+// data and where the next one starts.
+fn atom(image: &[u8], offset: usize) -> Result<(u16, &[u8], usize), String> {
+    let header = image.get(offset..offset + ATOM_HEADER_LEN).ok_or("the image ends in its header")?;
+    let kind = u16::from_le_bytes([header[0], header[1]]);
+    let length = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    if length < 2 {
+        return Err(format!("a length of {} leaves no room for the CRC", length));
+    }
+    let end = offset + ATOM_HEADER_LEN + length;
+    let atom = image.get(offset..end).ok_or("the image ends in its data")?;
+    let (covered, crc) = atom.split_at(atom.len() - 2);
+    let expected = u16::from_le_bytes([crc[0], crc[1]]);
+    if crc16(covered) != expected {
+        return Err(format!("CRC {:#06x} doesn't match the stored {:#06x}", crc16(covered), expected));
+    }
+    Ok((kind, &covered[ATOM_HEADER_LEN..], end))
+}
+
+fn vendor_info(data: &[u8]) -> Result<HatVendor, Error> {
+    let error = || Error::Other("the HAT vendor info atom is truncated".to_string());
+    let fixed = data.get(..22).ok_or_else(error)?;
+    let (vendor_len, product_len) = (fixed[20] as usize, fixed[21] as usize);
+    let strings = data.get(22..22 + vendor_len + product_len).ok_or_else(error)?;
+    let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).trim_end_matches('\0').to_string();
+    let mut uuid = [0u8; 16];
+    uuid.copy_from_slice(&fixed[..16]);
+    Ok(HatVendor {
+        uuid,
+        product_id: u16::from_le_bytes([fixed[16], fixed[17]]),
+        product_version: u16::from_le_bytes([fixed[18], fixed[19]]),
+        vendor: text(&strings[..vendor_len]),
+        product: text(&strings[vendor_len..]),
+    })
+}
+
+fn gpio_map(data: &[u8]) -> Result<HatGpioMap, Error> {
+    if data.len() < GPIO_MAP_LEN {
+        return Err(Error::Other(format!("the HAT GPIO map atom is {} bytes, not {}", data.len(), GPIO_MAP_LEN)));
+    }
+    let (bank, power) = (data[0], data[1]);
+    let setting = |bits: u8| match bits {
+        1 => Some(true),
+        2 => Some(false),
+        _ => None,
+    };
+    let pins = data[2..GPIO_MAP_LEN].iter().enumerate()
+        .filter(|&(_, &byte)| byte & 0x80 != 0)
+        .map(|(pin, &byte)| HatPin {
+            pin: pin as u32,
+            function: PinFunction::from_bits(0, (byte & 0b111) as u32),
+            pull: match byte >> 5 & 0b11 {
+                1 => Some(Pull::Up),
+                2 => Some(Pull::Down),
+                3 => Some(Pull::None),
+                _ => None,
+            },
+        })
+        .collect();
+    Ok(HatGpioMap {
+        drive_ma: match bank & 0x0f {
+            0 => None,
+            level => Some(level * 2),
+        },
+        slew_rate_limited: setting(bank >> 4 & 0b11),
+        hysteresis: setting(bank >> 6 & 0b11),
+        back_power_ma: match power & 0b11 {
+            1 => Some(1300),
+            2 => Some(2000),
+            _ => None,
+        },
+        pins,
+    })
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn atom(kind: u16, count: u16, data: &[u8]) -> Vec<u8> {
+        let mut atom = Vec::new();
+        atom.extend_from_slice(&kind.to_le_bytes());
+        atom.extend_from_slice(&count.to_le_bytes());
+        atom.extend_from_slice(&(data.len() as u32 + 2).to_le_bytes());
+        atom.extend_from_slice(data);
+        let crc = crc16(&atom);
+        atom.extend_from_slice(&crc.to_le_bytes());
+        atom
+    }
+
+    /// An image with vendor info for "Relay HAT" by "Acme" and GPIO 5 and
+    /// 6 used as outputs.
+    pub(crate) fn image() -> Vec<u8> {
+        let mut vendor = vec![0xaa; 16];
+        vendor.extend_from_slice(&0x1234u16.to_le_bytes());
+        vendor.extend_from_slice(&2u16.to_le_bytes());
+        vendor.extend_from_slice(&[4, 9]);
+        vendor.extend_from_slice(b"AcmeRelay HAT");
+        let mut map = vec![0x13, 0x01];
+        map.extend_from_slice(&[0; 28]);
+        map[2 + 5] = 0x80 | 0b001;
+        map[2 + 6] = 0x80 | 1 << 5 | 0b001;
+        map[2 + 26] = 1 << 5;
+
+        let atoms = [atom(ATOM_VENDOR_INFO, 0, &vendor), atom(ATOM_GPIO_MAP, 1, &map), atom(0x1234, 2, &[7])].concat();
+        let mut image = SIGNATURE.to_vec();
+        image.extend_from_slice(&[1, 0]);
+        image.extend_from_slice(&3u16.to_le_bytes());
+        image.extend_from_slice(&((HEADER_LEN + atoms.len()) as u32).to_le_bytes());
+        image.extend(atoms);
+        image
+    }
+
+    #[test]
+    fn test_crc16() {
+        assert_eq!(crc16(b"123456789"), 0xbb3d);
+    }
+
+    #[test]
+    fn test_parse_hat_eeprom() {
+        let eeprom = HatEeprom::parse(&image()).unwrap();
+        assert_eq!(eeprom.version(), 1);
+        let vendor = eeprom.vendor().unwrap();
+        assert_eq!((vendor.vendor.as_str(), vendor.product.as_str()), ("Acme", "Relay HAT"));
+        assert_eq!((vendor.product_id, vendor.product_version), (0x1234, 2));
+        let map = eeprom.gpio_map().unwrap();
+        assert_eq!((map.drive_ma, map.slew_rate_limited, map.hysteresis, map.back_power_ma), (Some(6), Some(true), None, Some(1300)));
+        assert_eq!(map.pins, vec![
+            HatPin { pin: 5, function: PinFunction::Output, pull: None },
+            HatPin { pin: 6, function: PinFunction::Output, pull: Some(Pull::Up) },
+        ]);
+        assert_eq!(map.pin_config().get("GPIO 6").map(|config| config.pull), Some(Pull::Up));
+        assert!(eeprom.device_tree().is_none() && eeprom.custom().is_empty());
+
+        let mut corrupt = image();
+        corrupt[HEADER_LEN + ATOM_HEADER_LEN] ^= 1;
+        let error = HatEeprom::parse(&corrupt).unwrap_err();
+        assert!(error.to_string().contains("atom 0: CRC"));
+        assert!(HatEeprom::parse(&image()[..40]).is_err());
+        assert!(HatEeprom::parse(b"\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff").is_err());
+    }
+
+    #[test]
+    fn test_read_without_a_hat() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        // Both lines float high, so nothing acknowledges.
+        unsafe { gpio.write_raw(0x34, 1 << ID_SD | 1 << ID_SC) };
+        assert!(matches!(HatEeprom::read(&gpio), Err(Error::NoAcknowledge(_))));
+        assert!(gpio.pin(ID_SD).is_ok());
+    }
+}
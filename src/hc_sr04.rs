@@ -0,0 +1,121 @@
+use crate::timing::wait_until;
+use crate::{Error, Input, Output, Pin, Trigger};
+
+use std::time::{Duration, Instant};
+
+
+const TRIGGER_PULSE: Duration = Duration::from_micros(10);
+
+// The sensor raises the echo line within about half a millisecond of the
+// trigger, after sending its burst.
+const ECHO_START_TIMEOUT: Duration = Duration::from_millis(10);
+
+
+/// The speed of sound in dry air at `temperature_c`, in m/s.
+fn speed_of_sound(temperature_c: f64) -> f64 {
+    331.3 + 0.606 * temperature_c
+}
+
+// The echo covers the distance there and back.
+fn distance_m(echo: Duration, temperature_c: f64) -> f64 {
+    echo.as_secs_f64() * speed_of_sound(temperature_c) / 2.0
+}
+
+
+/// An HC-SR04 ultrasonic rangefinder. The echo pin needs a divider down to
+/// 3.3 V, as the sensor drives it at 5 V. The echo pulse is timed from its
+/// edge event timestamps, so readings are as precise as the backend's
+/// timestamps: line backends use the kernel's, register backends when the
+/// edge was noticed, which is within about 100 µs (1.7 cm).
+pub struct HcSr04<'a> {
+    trigger: Pin<'a, Output>,
+    echo: Pin<'a, Input>,
+    temperature_c: f64,
+    timeout: Duration,
+}
+
+impl<'a> HcSr04<'a> {
+
+    /// Assumed until `set_temperature` says otherwise.
+    pub const DEFAULT_TEMPERATURE_C: f64 = 20.0;
+
+    /// Long enough for the sensor's 4 m range.
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_millis(30);
+
+    pub fn new(trigger: Pin<'a, Output>, echo: Pin<'a, Input>) -> Result<Self, Error> {
+        trigger.set_low()?;
+        Ok(Self { trigger, echo, temperature_c: Self::DEFAULT_TEMPERATURE_C, timeout: Self::DEFAULT_TIMEOUT })
+    }
+
+    /// Sets the air temperature used to work out the speed of sound, which
+    /// changes by about 0.18 % per °C.
+    pub fn set_temperature(&mut self, temperature_c: f64) {
+        self.temperature_c = temperature_c;
+    }
+
+    pub fn temperature(&self) -> f64 {
+        self.temperature_c
+    }
+
+    /// How long to wait for the echo to come back before deciding nothing
+    /// is in range.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Triggers a measurement and returns the width of the echo pulse.
+    /// Fails with `Error::Timeout` if the sensor doesn't answer or nothing
+    /// is in range.
+    pub fn echo_time(&self) -> Result<Duration, Error> {
+        let started = Instant::now();
+        self.trigger.set_high()?;
+        wait_until(started + TRIGGER_PULSE);
+        self.trigger.set_low()?;
+        let rise = self.echo.wait_for_edge(Trigger::Rising, Some(ECHO_START_TIMEOUT)).map_err(|e| match e {
+            Error::Timeout(_) => Error::Timeout(format!("no echo pulse on pin {}", self.echo.number())),
+            e => e,
+        })?;
+        let fall = self.echo.wait_for_edge(Trigger::Falling, Some(self.timeout)).map_err(|e| match e {
+            Error::Timeout(_) => Error::Timeout(format!("no echo returned within {:?}", self.timeout)),
+            e => e,
+        })?;
+        Ok(fall.since(&rise))
+    }
+
+    /// Measures the distance to the nearest object in metres.
+    pub fn distance(&self) -> Result<f64, Error> {
+        Ok(distance_m(self.echo_time()?, self.temperature_c))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GPIO;
+
+    #[test]
+    fn test_distance() {
+        assert!((speed_of_sound(20.0) - 343.42).abs() < 1e-9);
+        assert!((distance_m(Duration::from_micros(5824), 20.0) - 1.0).abs() < 1e-3);
+        // Colder air is slower, so the same echo means a shorter distance.
+        assert!(distance_m(Duration::from_millis(5), -10.0) < distance_m(Duration::from_millis(5), 30.0));
+    }
+
+    #[test]
+    fn test_hc_sr04_without_an_echo() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let mut sensor = HcSr04::new(
+            gpio.pin(23).unwrap().into_output().unwrap(),
+            gpio.pin(24).unwrap().into_input().unwrap(),
+        ).unwrap();
+        sensor.set_temperature(25.0);
+        assert_eq!(sensor.temperature(), 25.0);
+        assert!(matches!(sensor.distance(), Err(Error::Timeout(_))));
+        assert_eq!(gpio.output_state(23).ok(), Some(Some(false)));
+    }
+}
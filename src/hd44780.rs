@@ -0,0 +1,369 @@
+use crate::timing::wait_until;
+use crate::{Error, GpioPin, I2c, Output, Pin, PinGroup};
+
+use std::time::{Duration, Instant};
+
+
+const CLEAR_DISPLAY: u8 = 0x01;
+const RETURN_HOME: u8 = 0x02;
+const ENTRY_MODE_SET: u8 = 0x04;
+const DISPLAY_CONTROL: u8 = 0x08;
+const CURSOR_SHIFT: u8 = 0x10;
+const FUNCTION_SET: u8 = 0x20;
+const SET_CGRAM_ADDRESS: u8 = 0x40;
+const SET_DDRAM_ADDRESS: u8 = 0x80;
+
+const ENTRY_INCREMENT: u8 = 0x02;
+const DISPLAY_ON: u8 = 0x04;
+const CURSOR_ON: u8 = 0x02;
+const BLINK_ON: u8 = 0x01;
+const SHIFT_DISPLAY: u8 = 0x08;
+const SHIFT_RIGHT: u8 = 0x04;
+const TWO_LINES: u8 = 0x08;
+
+// Most instructions take 37 µs; clear and home take 1.52 ms.
+const INSTRUCTION_TIME: Duration = Duration::from_micros(50);
+const SLOW_INSTRUCTION_TIME: Duration = Duration::from_millis(2);
+
+// How the usual PCF8574 backpack wires the expander: RS, RW, E and the
+// backlight on P0 to P3 and D4 to D7 on P4 to P7.
+const PCF8574_RS: u8 = 0x01;
+const PCF8574_E: u8 = 0x04;
+const PCF8574_BACKLIGHT: u8 = 0x08;
+
+
+// A way of clocking 4-bit halves of bytes into the controller. RW is
+// assumed tied low, so nothing is ever read back.
+trait LcdBus {
+    fn write_nibble(&mut self, nibble: u8, data: bool) -> Result<(), Error>;
+
+    fn set_backlight(&mut self, _on: bool) -> Result<(), Error> {
+        Err(Error::Unsupported("this display's backlight isn't switchable".to_string()))
+    }
+}
+
+
+struct GpioBus<'a> {
+    data: PinGroup<'a>,
+    rs: Pin<'a, Output>,
+    enable: Pin<'a, Output>,
+}
+
+impl<'a> LcdBus for GpioBus<'a> {
+    fn write_nibble(&mut self, nibble: u8, data: bool) -> Result<(), Error> {
+        if data { self.rs.set_high()? } else { self.rs.set_low()? }
+        self.data.write(nibble as u64)?;
+        // The controller latches on the falling edge of E, which must have
+        // been high for at least 450 ns.
+        let started = Instant::now();
+        self.enable.set_high()?;
+        wait_until(started + Duration::from_micros(1));
+        self.enable.set_low()
+    }
+}
+
+
+// D4 to D7, RS and E on separate lines of any kind, e.g. an expander's.
+struct PinBus<P> {
+    data: [P; 4],
+    rs: P,
+    enable: P,
+}
+
+impl<P: GpioPin> LcdBus for PinBus<P> {
+    fn write_nibble(&mut self, nibble: u8, data: bool) -> Result<(), Error> {
+        self.rs.set(data)?;
+        for (bit, pin) in self.data.iter().enumerate() {
+            pin.set(nibble & (1 << bit) != 0)?;
+        }
+        let started = Instant::now();
+        self.enable.set_high()?;
+        wait_until(started + Duration::from_micros(1));
+        self.enable.set_low()
+    }
+}
+
+
+struct Pcf8574Bus {
+    i2c: I2c,
+    address: u8,
+    backlight: u8,
+}
+
+impl LcdBus for Pcf8574Bus {
+    fn write_nibble(&mut self, nibble: u8, data: bool) -> Result<(), Error> {
+        let byte = nibble << 4 | self.backlight | if data { PCF8574_RS } else { 0 };
+        // Each I2C byte takes longer than E needs to be high.
+        self.i2c.write(self.address, &[byte | PCF8574_E, byte])
+    }
+
+    fn set_backlight(&mut self, on: bool) -> Result<(), Error> {
+        self.backlight = if on { PCF8574_BACKLIGHT } else { 0 };
+        self.i2c.write(self.address, &[self.backlight])
+    }
+}
+
+
+/// An HD44780 character LCD, or one of its many clones, driven in 4-bit
+/// mode either straight from pins or through a PCF8574 I2C backpack.
+/// Characters are written to the cursor position, which moves right after
+/// each one.
+pub struct Hd44780<'a> {
+    bus: Box<dyn LcdBus + 'a>,
+    columns: u8,
+    rows: u8,
+    display_control: u8,
+}
+
+impl<'a> Hd44780<'a> {
+
+    /// A display with D4 to D7 as bits 0 to 3 of `data`, which are made
+    /// outputs, and its RW line tied to ground.
+    pub fn new(data: PinGroup<'a>, rs: Pin<'a, Output>, enable: Pin<'a, Output>, columns: u8, rows: u8) -> Result<Self, Error> {
+        if data.len() != 4 {
+            return Err(Error::Other(format!("the HD44780 takes 4 data pins, not {}", data.len())));
+        }
+        data.set_output()?;
+        enable.set_low()?;
+        Self::init(Box::new(GpioBus { data, rs, enable }), columns, rows)
+    }
+
+    /// A display on output lines from any source, such as an MCP23017 or
+    /// a shift register, with D4 to D7 as `data[0]` to `data[3]`. Pins of
+    /// different kinds can be mixed as `Box<dyn GpioPin>`.
+    pub fn with_pins<P: GpioPin + 'a>(data: [P; 4], rs: P, enable: P, columns: u8, rows: u8) -> Result<Self, Error> {
+        enable.set_low()?;
+        Self::init(Box::new(PinBus { data, rs, enable }), columns, rows)
+    }
+
+    /// A display behind a PCF8574 backpack at `address`, usually 0x27 or
+    /// 0x3f. The backlight is switched on.
+    pub fn pcf8574(i2c: I2c, address: u8, columns: u8, rows: u8) -> Result<Self, Error> {
+        Self::init(Box::new(Pcf8574Bus { i2c, address, backlight: PCF8574_BACKLIGHT }), columns, rows)
+    }
+
+    // The power-on reset may not have run, so the controller is first
+    // forced into 8-bit mode by three function sets, then switched to
+    // 4-bit mode, as the datasheet lays out.
+    fn init(mut bus: Box<dyn LcdBus + 'a>, columns: u8, rows: u8) -> Result<Self, Error> {
+        if columns == 0 || !(1..=4).contains(&rows) {
+            return Err(Error::Other(format!("unsupported HD44780 size {}x{}", columns, rows)));
+        }
+        std::thread::sleep(Duration::from_millis(50));
+        for delay in [Duration::from_micros(4100), Duration::from_micros(100), INSTRUCTION_TIME].iter() {
+            bus.write_nibble(0x3, false)?;
+            std::thread::sleep(*delay);
+        }
+        bus.write_nibble(0x2, false)?;
+        std::thread::sleep(INSTRUCTION_TIME);
+
+        let mut lcd = Self { bus, columns, rows, display_control: DISPLAY_ON };
+        lcd.command(FUNCTION_SET | if rows > 1 { TWO_LINES } else { 0 })?;
+        lcd.command(DISPLAY_CONTROL | lcd.display_control)?;
+        lcd.clear()?;
+        lcd.command(ENTRY_MODE_SET | ENTRY_INCREMENT)?;
+        Ok(lcd)
+    }
+
+    fn send(&mut self, byte: u8, data: bool) -> Result<(), Error> {
+        self.bus.write_nibble(byte >> 4, data)?;
+        self.bus.write_nibble(byte & 0xf, data)?;
+        std::thread::sleep(INSTRUCTION_TIME);
+        Ok(())
+    }
+
+    fn command(&mut self, command: u8) -> Result<(), Error> {
+        self.send(command, false)
+    }
+
+    pub fn columns(&self) -> u8 {
+        self.columns
+    }
+
+    pub fn rows(&self) -> u8 {
+        self.rows
+    }
+
+    /// Blanks the display and moves the cursor to the top left.
+    pub fn clear(&mut self) -> Result<(), Error> {
+        self.command(CLEAR_DISPLAY)?;
+        std::thread::sleep(SLOW_INSTRUCTION_TIME);
+        Ok(())
+    }
+
+    /// Moves the cursor to the top left and undoes any scrolling.
+    pub fn home(&mut self) -> Result<(), Error> {
+        self.command(RETURN_HOME)?;
+        std::thread::sleep(SLOW_INSTRUCTION_TIME);
+        Ok(())
+    }
+
+    /// Moves the cursor to `column` of `row`, both counted from 0.
+    pub fn set_cursor(&mut self, column: u8, row: u8) -> Result<(), Error> {
+        if column >= self.columns || row >= self.rows {
+            return Err(Error::Other(format!(
+                "({}, {}) is off a {}x{} display", column, row, self.columns, self.rows)));
+        }
+        // Rows 2 and 3 carry on from the ends of rows 0 and 1.
+        let row_start = [0x00, 0x40, self.columns, 0x40 + self.columns][row as usize];
+        self.command(SET_DDRAM_ADDRESS | (row_start + column))
+    }
+
+    fn set_display_control(&mut self, flag: u8, on: bool) -> Result<(), Error> {
+        self.display_control = if on { self.display_control | flag } else { self.display_control & !flag };
+        self.command(DISPLAY_CONTROL | self.display_control)
+    }
+
+    /// Turns the characters on or off without losing them.
+    pub fn set_display(&mut self, on: bool) -> Result<(), Error> {
+        self.set_display_control(DISPLAY_ON, on)
+    }
+
+    /// Shows or hides the underline cursor.
+    pub fn set_cursor_visible(&mut self, visible: bool) -> Result<(), Error> {
+        self.set_display_control(CURSOR_ON, visible)
+    }
+
+    /// Blinks the character at the cursor or stops blinking it.
+    pub fn set_blink(&mut self, blink: bool) -> Result<(), Error> {
+        self.set_display_control(BLINK_ON, blink)
+    }
+
+    /// Scrolls the whole display one column left.
+    pub fn scroll_left(&mut self) -> Result<(), Error> {
+        self.command(CURSOR_SHIFT | SHIFT_DISPLAY)
+    }
+
+    /// Scrolls the whole display one column right.
+    pub fn scroll_right(&mut self) -> Result<(), Error> {
+        self.command(CURSOR_SHIFT | SHIFT_DISPLAY | SHIFT_RIGHT)
+    }
+
+    /// Defines character code `code`, 0 to 7, from eight rows of five
+    /// pixels, top first with the leftmost pixel in bit 4. The cursor is
+    /// sent home afterwards.
+    pub fn create_char(&mut self, code: u8, rows: [u8; 8]) -> Result<(), Error> {
+        if code > 7 {
+            return Err(Error::Other(format!("custom characters are numbered 0 to 7, not {}", code)));
+        }
+        self.command(SET_CGRAM_ADDRESS | code << 3)?;
+        for row in rows.iter() {
+            self.send(row & 0x1f, true)?;
+        }
+        self.command(SET_DDRAM_ADDRESS)
+    }
+
+    /// Writes one character code at the cursor, e.g. 0 to 7 for the custom
+    /// characters.
+    pub fn write_byte(&mut self, code: u8) -> Result<(), Error> {
+        self.send(code, true)
+    }
+
+    /// Writes `text` at the cursor. The character ROM only matches ASCII,
+    /// so anything else is shown as `?`.
+    pub fn write_str(&mut self, text: &str) -> Result<(), Error> {
+        for c in text.chars() {
+            self.write_byte(if c.is_ascii() && !c.is_ascii_control() { c as u8 } else { b'?' })?;
+        }
+        Ok(())
+    }
+
+    /// Switches the backlight, which only the PCF8574 backpack can do.
+    pub fn set_backlight(&mut self, on: bool) -> Result<(), Error> {
+        self.bus.set_backlight(on)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{I2cAddress, I2cBackend, Pcf8574, GPIO};
+    use std::sync::{Arc, Mutex};
+
+    // Records the bytes written to the expander.
+    struct Expander(Arc<Mutex<Vec<u8>>>);
+
+    impl I2cBackend for Expander {
+        fn write(&mut self, address: I2cAddress, data: &[u8]) -> Result<(), Error> {
+            assert_eq!(address, I2cAddress::SevenBit(0x27));
+            self.0.lock().unwrap().extend_from_slice(data);
+            Ok(())
+        }
+        fn read(&mut self, _address: I2cAddress, _buffer: &mut [u8]) -> Result<(), Error> { Ok(()) }
+        fn write_read(&mut self, _address: I2cAddress, _data: &[u8], _buffer: &mut [u8]) -> Result<(), Error> { Ok(()) }
+        fn set_frequency(&mut self, freq_hz: u32) -> Result<u32, Error> { Ok(freq_hz) }
+        fn set_timeout(&mut self, _timeout: Duration) -> Result<(), Error> { Ok(()) }
+    }
+
+    // The nibbles latched by the falling edges of E, with RS.
+    fn latched(bytes: &[u8]) -> Vec<(u8, bool)> {
+        bytes.chunks(2).map(|pair| (pair[1] >> 4, pair[1] & PCF8574_RS != 0)).collect()
+    }
+
+    #[test]
+    fn test_hd44780_over_pcf8574() {
+        let bytes = Arc::new(Mutex::new(Vec::new()));
+        let mut lcd = Hd44780::pcf8574(I2c::custom(Expander(Arc::clone(&bytes))), 0x27, 16, 2).unwrap();
+        assert_eq!(latched(&bytes.lock().unwrap()), vec![
+            (0x3, false), (0x3, false), (0x3, false), (0x2, false),
+            (0x2, false), (0x8, false), (0x0, false), (0xc, false),
+            (0x0, false), (0x1, false), (0x0, false), (0x6, false),
+        ]);
+        assert!(bytes.lock().unwrap().iter().all(|byte| byte & PCF8574_BACKLIGHT != 0));
+        bytes.lock().unwrap().clear();
+
+        lcd.set_cursor(3, 1).unwrap();
+        lcd.write_str("Hé").unwrap();
+        assert_eq!(latched(&bytes.lock().unwrap()), vec![
+            (0xc, false), (0x3, false), (0x4, true), (0x8, true), (0x3, true), (0xf, true),
+        ]);
+        assert!(lcd.set_cursor(16, 0).is_err());
+        assert!(lcd.create_char(8, [0; 8]).is_err());
+
+        bytes.lock().unwrap().clear();
+        lcd.set_backlight(false).unwrap();
+        lcd.set_blink(true).unwrap();
+        assert_eq!(latched(&bytes.lock().unwrap()[1..]), vec![(0x0, false), (0xd, false)]);
+        assert!(bytes.lock().unwrap().iter().all(|byte| byte & PCF8574_BACKLIGHT == 0));
+    }
+
+    #[test]
+    fn test_hd44780_over_any_pins() {
+        // The backpack's wiring, but line by line through `GpioPin`.
+        let bytes = Arc::new(Mutex::new(Vec::new()));
+        let expander = Pcf8574::new(I2c::custom(Expander(Arc::clone(&bytes))), 0x27).unwrap();
+        let pin = |line| expander.pin(line).unwrap();
+        Hd44780::with_pins([pin(4), pin(5), pin(6), pin(7)], pin(0), pin(2), 16, 2).unwrap();
+        // Skip the expander's reset and E being pulled low.
+        let bytes = bytes.lock().unwrap()[1..].to_vec();
+        let falling: Vec<(u8, bool)> = bytes.windows(2)
+            .filter(|pair| pair[0] & PCF8574_E != 0 && pair[1] & PCF8574_E == 0)
+            .map(|pair| (pair[1] >> 4, pair[1] & PCF8574_RS != 0))
+            .collect();
+        assert_eq!(falling, vec![
+            (0x3, false), (0x3, false), (0x3, false), (0x2, false),
+            (0x2, false), (0x8, false), (0x0, false), (0xc, false),
+            (0x0, false), (0x1, false), (0x0, false), (0x6, false),
+        ]);
+    }
+
+    #[test]
+    fn test_hd44780_over_pins() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let mut lcd = Hd44780::new(
+            gpio.pin_group(&[22, 23, 24, 25]).unwrap(),
+            gpio.pin(26).unwrap().into_output().unwrap(),
+            gpio.pin(19).unwrap().into_output().unwrap(),
+            20, 4,
+        ).unwrap();
+        lcd.set_cursor(0, 3).unwrap();
+        lcd.write_str("A").unwrap();
+        // The last nibble out was the low half of 'A', as data.
+        let levels: Vec<_> = [22, 23, 24, 25, 26, 19].iter().map(|&pin| gpio.output_state(pin).unwrap()).collect();
+        assert_eq!(levels, vec![Some(true), Some(false), Some(false), Some(false), Some(true), Some(false)]);
+        assert!(lcd.set_backlight(true).is_err());
+        assert!(Hd44780::new(gpio.pin_group(&[5, 6]).unwrap(),
+            gpio.pin(7).unwrap().into_output().unwrap(), gpio.pin(8).unwrap().into_output().unwrap(), 16, 2).is_err());
+    }
+}
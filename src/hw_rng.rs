@@ -0,0 +1,165 @@
+use crate::region::MappedRegion;
+use crate::{detect_peripheral_base, Error, Soc, DEV_MEM_PATH};
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+
+const RNG_BASE_OFFSET: i64 = 0x104000;
+const RNG_BLOCK_LEN: usize = 0x28;
+
+// The BCM2835-BCM2837's generator.
+const RNG_CTRL: usize = 0x0;
+const RNG_STATUS: usize = 0x4;
+const RNG_DATA: usize = 0x8;
+const RNG_INT_MASK: usize = 0x10;
+const RNG_RBGEN: u32 = 1;
+const RNG_INT_OFF: u32 = 1;
+// The number of bits to throw away after enabling it; the first are less
+// random.
+const RNG_WARMUP_COUNT: u32 = 0x40000;
+const RNG_STATUS_WORDS_SHIFT: u32 = 24;
+
+// The BCM2711's RNG200.
+const RNG200_TOTAL_BIT_COUNT_THRESHOLD: usize = 0x10;
+const RNG200_FIFO_DATA: usize = 0x20;
+const RNG200_FIFO_COUNT: usize = 0x24;
+const RNG200_RBGEN_MASK: u32 = 0x1fff;
+// Sample at 1 MHz.
+const RNG200_DIV_CTRL: u32 = 0x3 << 13;
+const RNG200_FIFO_COUNT_MASK: u32 = 0xff;
+const RNG200_FIFO_THRESHOLD: u32 = 2 << 24;
+
+// Long enough for the warm-up bits to be thrown away.
+const WORD_TIMEOUT: Duration = Duration::from_secs(1);
+
+
+/// The SoC's hardware random number generator, which the kernel's
+/// `bcm2835-rng` driver also reads. Words come from a small FIFO the
+/// generator refills at about a megabit per second, so reading many
+/// blocks until it catches up. Prefer `/dev/hwrng` where the driver is
+/// loaded; reading both at once splits the stream between them.
+pub struct HwRng {
+    region: MappedRegion,
+    soc: Soc,
+}
+
+impl HwRng {
+
+    /// Maps the generator through `/dev/mem` and starts it if the
+    /// firmware or kernel hasn't.
+    pub fn new() -> Result<Self, Error> {
+        let base = detect_peripheral_base()?;
+        let soc = Soc::detect(Some(base)).unwrap_or(Soc::Bcm2711);
+        Self::open(DEV_MEM_PATH, base + RNG_BASE_OFFSET, soc)
+    }
+
+    pub(crate) fn open(path: impl AsRef<Path>, phys_base: i64, soc: Soc) -> Result<Self, Error> {
+        if soc == Soc::Bcm2712 {
+            return Err(Error::Unsupported("the Pi 5's random number generator isn't supported".to_string()));
+        }
+        let rng = Self { region: MappedRegion::open(path.as_ref(), phys_base, RNG_BLOCK_LEN)?, soc };
+        rng.start()?;
+        Ok(rng)
+    }
+
+    // The generator is left running once started, as the kernel's driver
+    // leaves it, and its warm-up isn't repeated.
+    fn start(&self) -> Result<(), Error> {
+        let ctrl = self.region.read_reg(RNG_CTRL)?;
+        if self.soc == Soc::Bcm2711 {
+            if ctrl & RNG200_RBGEN_MASK == 0 {
+                self.region.write_reg(RNG200_TOTAL_BIT_COUNT_THRESHOLD, RNG_WARMUP_COUNT)?;
+                self.region.write_reg(RNG200_FIFO_COUNT, RNG200_FIFO_THRESHOLD)?;
+                self.region.write_reg(RNG_CTRL, RNG200_DIV_CTRL | RNG_RBGEN)?;
+            }
+        } else if ctrl & RNG_RBGEN == 0 {
+            let mask = self.region.read_reg(RNG_INT_MASK)?;
+            self.region.write_reg(RNG_INT_MASK, mask | RNG_INT_OFF)?;
+            self.region.write_reg(RNG_STATUS, RNG_WARMUP_COUNT)?;
+            self.region.write_reg(RNG_CTRL, RNG_RBGEN)?;
+        }
+        Ok(())
+    }
+
+    /// The number of words ready to read.
+    pub fn available(&self) -> Result<u32, Error> {
+        Ok(if self.soc == Soc::Bcm2711 {
+            self.region.read_reg(RNG200_FIFO_COUNT)? & RNG200_FIFO_COUNT_MASK
+        } else {
+            self.region.read_reg(RNG_STATUS)? >> RNG_STATUS_WORDS_SHIFT
+        })
+    }
+
+    /// The next word of entropy, waiting for one if the FIFO is empty,
+    /// e.g. during warm-up.
+    pub fn next_u32(&self) -> Result<u32, Error> {
+        let deadline = Instant::now() + WORD_TIMEOUT;
+        while self.available()? == 0 {
+            if Instant::now() >= deadline {
+                return Err(Error::Timeout(format!("the random number generator had no data after {:?}", WORD_TIMEOUT)));
+            }
+            std::thread::sleep(Duration::from_micros(100));
+        }
+        self.region.read_reg(if self.soc == Soc::Bcm2711 { RNG200_FIFO_DATA } else { RNG_DATA })
+    }
+
+    pub fn next_u64(&self) -> Result<u64, Error> {
+        Ok((self.next_u32()? as u64) << 32 | self.next_u32()? as u64)
+    }
+
+    /// Fills `bytes` with entropy, in the manner of `rand_core`'s
+    /// `try_fill_bytes`, so a `RngCore` can be built on it.
+    pub fn fill_bytes(&self, bytes: &mut [u8]) -> Result<(), Error> {
+        for chunk in bytes.chunks_mut(4) {
+            let word = self.next_u32()?.to_ne_bytes();
+            chunk.copy_from_slice(&word[..chunk.len()]);
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_rng(name: &str, soc: Soc, check: impl FnOnce(&HwRng)) {
+        let path = std::env::temp_dir().join(format!("rustberrypi-rng-{}-{}", name, std::process::id()));
+        std::fs::write(&path, vec![0u8; 4096]).unwrap();
+        let rng = HwRng::open(&path, 0, soc).ok().unwrap();
+        check(&rng);
+        drop(rng);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_bcm2835_rng() {
+        with_rng("2835", Soc::Bcm2837, |rng| {
+            assert_eq!(rng.region.read_reg(RNG_CTRL).ok(), Some(RNG_RBGEN));
+            assert_eq!(rng.region.read_reg(RNG_STATUS).ok(), Some(RNG_WARMUP_COUNT));
+            assert_eq!(rng.region.read_reg(RNG_INT_MASK).ok(), Some(RNG_INT_OFF));
+            rng.region.write_reg(RNG_STATUS, 2 << 24).unwrap();
+            rng.region.write_reg(RNG_DATA, 0x1234_5678).unwrap();
+            assert_eq!(rng.available().ok(), Some(2));
+            assert_eq!(rng.next_u32().ok(), Some(0x1234_5678));
+            let mut bytes = [0u8; 6];
+            rng.fill_bytes(&mut bytes).unwrap();
+            assert_eq!(&bytes[..4], &0x1234_5678u32.to_ne_bytes());
+        });
+    }
+
+    #[test]
+    fn test_rng200() {
+        with_rng("2711", Soc::Bcm2711, |rng| {
+            assert_eq!(rng.region.read_reg(RNG_CTRL).ok(), Some(RNG200_DIV_CTRL | RNG_RBGEN));
+            assert_eq!(rng.region.read_reg(RNG200_TOTAL_BIT_COUNT_THRESHOLD).ok(), Some(RNG_WARMUP_COUNT));
+            assert_eq!(rng.available().ok(), Some(0));
+            rng.region.write_reg(RNG200_FIFO_COUNT, RNG200_FIFO_THRESHOLD | 1).unwrap();
+            rng.region.write_reg(RNG200_FIFO_DATA, 0xcafe_f00d).unwrap();
+            assert_eq!(rng.next_u64().ok(), Some(0xcafe_f00d_cafe_f00d));
+        });
+        let path = std::env::temp_dir();
+        assert!(matches!(HwRng::open(&path, 0, Soc::Bcm2712), Err(Error::Unsupported(_))));
+    }
+}
@@ -0,0 +1,398 @@
+use crate::i2cdev::I2cDev;
+use crate::region::MappedRegion;
+use crate::{detect_peripheral_base, Error, PinFunction, Soc, GPIO, DEV_MEM_PATH};
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+
+const BSC0_BASE_OFFSET: i64 = 0x205000;
+const BSC1_BASE_OFFSET: i64 = 0x804000;
+const BSC_BLOCK_LEN: usize = 0x20;
+
+const BSC_C: usize = 0x00;
+const BSC_S: usize = 0x04;
+const BSC_DLEN: usize = 0x08;
+const BSC_A: usize = 0x0c;
+const BSC_FIFO: usize = 0x10;
+const BSC_DIV: usize = 0x14;
+
+const C_I2CEN: u32 = 1 << 15;
+const C_ST: u32 = 1 << 7;
+const C_CLEAR: u32 = 0b11 << 4;
+const C_READ: u32 = 1;
+
+const S_TA: u32 = 1;
+const S_DONE: u32 = 1 << 1;
+const S_TXD: u32 = 1 << 4;
+const S_RXD: u32 = 1 << 5;
+const S_ERR: u32 = 1 << 8;
+const S_CLKT: u32 = 1 << 9;
+
+const FIFO_DEPTH: usize = 16;
+const MAX_TRANSFER_LEN: usize = 0xffff;
+
+// SDA and SCL of BSC0 and BSC1, both on Alt0.
+const BSC_PINS: [[u32; 2]; 2] = [[0, 1], [2, 3]];
+
+// A 10-bit address goes out as 0b11110 followed by its top two bits, then
+// its low byte as the first data byte.
+const TEN_BIT_PREFIX: u32 = 0x78;
+
+
+/// A 7-bit or 10-bit I2C device address. Plain `u8`s convert to 7-bit ones.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum I2cAddress {
+    SevenBit(u8),
+    TenBit(u16),
+}
+
+impl I2cAddress {
+
+    fn check(self) -> Result<Self, Error> {
+        match self {
+            I2cAddress::SevenBit(address) if address > 0x7f =>
+                Err(Error::Other(format!("I2C address {:#x} doesn't fit in 7 bits", address))),
+            I2cAddress::TenBit(address) if address > 0x3ff =>
+                Err(Error::Other(format!("I2C address {:#x} doesn't fit in 10 bits", address))),
+            _ => Ok(self),
+        }
+    }
+}
+
+impl From<u8> for I2cAddress {
+    fn from(address: u8) -> Self {
+        I2cAddress::SevenBit(address)
+    }
+}
+
+
+/// A way of driving an I2C bus. `I2c` works the same over any of them.
+/// Addresses are checked before they reach the backend.
+pub trait I2cBackend: Send {
+    fn write(&mut self, address: I2cAddress, data: &[u8]) -> Result<(), Error>;
+    fn read(&mut self, address: I2cAddress, buffer: &mut [u8]) -> Result<(), Error>;
+    /// Writes `data` then reads into `buffer` after a repeated start.
+    fn write_read(&mut self, address: I2cAddress, data: &[u8], buffer: &mut [u8]) -> Result<(), Error>;
+    /// Sets the clock to at most `freq_hz` and returns the rate achieved.
+    fn set_frequency(&mut self, freq_hz: u32) -> Result<u32, Error>;
+    /// How long a transfer may go without progress before failing with
+    /// `Error::Timeout`.
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), Error>;
+}
+
+
+/// An I2C master. Devices that don't acknowledge fail with
+/// `Error::NoAcknowledge`.
+pub struct I2c {
+    backend: Box<dyn I2cBackend>,
+}
+
+impl I2c {
+
+    pub const DEFAULT_FREQUENCY_HZ: u32 = 100_000;
+
+    /// Drives BSC controller `bus` (0 on GPIO 0/1, 1 on GPIO 2/3) through
+    /// its registers at `DEFAULT_FREQUENCY_HZ`, switching its pins over.
+    /// The kernel's i2c driver must not be using the controller at the
+    /// same time.
+    pub fn new(gpio: &GPIO, bus: u8) -> Result<Self, Error> {
+        let offset = match bus {
+            0 => BSC0_BASE_OFFSET,
+            1 => BSC1_BASE_OFFSET,
+            _ => return Err(Error::Other(format!("there is no BSC controller {}", bus))),
+        };
+        let base = detect_peripheral_base()?;
+        let mut backend = Bsc::open(DEV_MEM_PATH, base + offset, gpio.soc())?;
+        backend.set_frequency(Self::DEFAULT_FREQUENCY_HZ)?;
+        gpio.switch_to_alt(&BSC_PINS[bus as usize], PinFunction::Alt0)?;
+        Ok(Self::custom(backend))
+    }
+
+    /// Goes through the kernel's i2c-dev driver for `bus`, i.e.
+    /// `/dev/i2c-N`, leaving the controller to the kernel's own driver.
+    /// The bus speed is then fixed by the device tree.
+    pub fn i2cdev(bus: u8) -> Result<Self, Error> {
+        Self::i2cdev_at(format!("/dev/i2c-{}", bus))
+    }
+
+    pub fn i2cdev_at(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Ok(Self::custom(I2cDev::open(path)?))
+    }
+
+    /// Uses `backend` instead of any of the built-in ones.
+    pub fn custom(backend: impl I2cBackend + 'static) -> Self {
+        Self { backend: Box::new(backend) }
+    }
+
+    pub fn write(&mut self, address: impl Into<I2cAddress>, data: &[u8]) -> Result<(), Error> {
+        self.backend.write(address.into().check()?, data)
+    }
+
+    pub fn read(&mut self, address: impl Into<I2cAddress>, buffer: &mut [u8]) -> Result<(), Error> {
+        self.backend.read(address.into().check()?, buffer)
+    }
+
+    /// Writes `data` then reads into `buffer` after a repeated start, as
+    /// used to read a device register.
+    pub fn write_read(&mut self, address: impl Into<I2cAddress>, data: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+        self.backend.write_read(address.into().check()?, data, buffer)
+    }
+
+    pub fn set_frequency(&mut self, freq_hz: u32) -> Result<u32, Error> {
+        self.backend.set_frequency(freq_hz)
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) -> Result<(), Error> {
+        self.backend.set_timeout(timeout)
+    }
+}
+
+
+// The I2C clock is the core clock divided by an even number.
+fn clock_divider(core_clock_hz: u32, freq_hz: u32) -> Result<u32, Error> {
+    if freq_hz == 0 {
+        return Err(Error::Other("I2C clock frequency must be positive".to_string()));
+    }
+    let divider = core_clock_hz.div_ceil(freq_hz).max(2);
+    let divider = divider + divider % 2;
+    if divider > 32768 {
+        return Err(Error::Other(format!("{} Hz is below the slowest I2C clock", freq_hz)));
+    }
+    Ok(divider)
+}
+
+// The A register value and, for 10-bit addresses, the byte that has to
+// lead the data.
+fn address_bytes(address: I2cAddress) -> (u32, Option<u8>) {
+    match address {
+        I2cAddress::SevenBit(address) => (address as u32, None),
+        I2cAddress::TenBit(address) => (TEN_BIT_PREFIX | (address >> 8) as u32, Some(address as u8)),
+    }
+}
+
+fn with_prefix(prefix: Option<u8>, data: &[u8]) -> Vec<u8> {
+    prefix.iter().chain(data).copied().collect()
+}
+
+
+// One of the Broadcom Serial Controllers.
+struct Bsc {
+    region: MappedRegion,
+    core_clock_hz: u32,
+    timeout: Duration,
+}
+
+impl Bsc {
+
+    fn open(path: impl AsRef<Path>, phys_base: i64, soc: Soc) -> Result<Self, Error> {
+        let core_clock_hz = if soc == Soc::Bcm2711 { 500_000_000 } else { 250_000_000 };
+        let bsc = Self {
+            region: MappedRegion::open(path.as_ref(), phys_base, BSC_BLOCK_LEN)?,
+            core_clock_hz,
+            timeout: Duration::from_millis(100),
+        };
+        bsc.finish()?;
+        Ok(bsc)
+    }
+
+    // Clears the sticky status bits and the FIFO, leaving the controller
+    // idle.
+    fn finish(&self) -> Result<(), Error> {
+        self.region.write_reg(BSC_S, S_CLKT | S_ERR | S_DONE)?;
+        self.region.write_reg(BSC_C, C_I2CEN | C_CLEAR)
+    }
+
+    fn check_len(len: usize) -> Result<u32, Error> {
+        if len > MAX_TRANSFER_LEN {
+            return Err(Error::Other(format!("I2C transfers are limited to {} bytes, not {}", MAX_TRANSFER_LEN, len)));
+        }
+        Ok(len as u32)
+    }
+
+    // Queues as much of `data` as the FIFO will take.
+    fn fill_fifo(&self, data: &[u8]) -> Result<usize, Error> {
+        let mut sent = 0;
+        while sent < data.len() && self.region.read_reg(BSC_S)? & S_TXD != 0 {
+            self.region.write_reg(BSC_FIFO, data[sent] as u32)?;
+            sent += 1;
+        }
+        Ok(sent)
+    }
+
+    // Feeds the FIFO from `out` and drains it into `input` until the
+    // controller reports the transfer done.
+    fn pump(&self, out: &[u8], mut sent: usize, input: &mut [u8]) -> Result<(), Error> {
+        let mut received = 0;
+        let mut progress = Instant::now();
+        loop {
+            let status = self.region.read_reg(BSC_S)?;
+            if status & S_ERR != 0 {
+                return Err(Error::NoAcknowledge("I2C device didn't acknowledge".to_string()));
+            }
+            if status & S_CLKT != 0 {
+                return Err(Error::Timeout("I2C device held the clock low for too long".to_string()));
+            }
+            if status & S_TXD != 0 && sent < out.len() {
+                self.region.write_reg(BSC_FIFO, out[sent] as u32)?;
+                sent += 1;
+                progress = Instant::now();
+            } else if status & S_RXD != 0 && received < input.len() {
+                input[received] = self.region.read_reg(BSC_FIFO)? as u8;
+                received += 1;
+                progress = Instant::now();
+            } else if status & S_DONE != 0 {
+                if sent < out.len() || received < input.len() {
+                    return Err(Error::Other(format!(
+                        "I2C transfer ended after {} of {} bytes", sent + received, out.len() + input.len())));
+                }
+                return Ok(());
+            } else if progress.elapsed() > self.timeout {
+                return Err(Error::Timeout(format!(
+                    "I2C transfer stalled after {} of {} bytes", sent + received, out.len() + input.len())));
+            } else {
+                std::hint::spin_loop();
+            }
+        }
+    }
+
+    fn transfer(&self, address: u32, out: &[u8], input: &mut [u8]) -> Result<(), Error> {
+        let read = !input.is_empty();
+        let len = Self::check_len(if read { input.len() } else { out.len() })?;
+        self.region.write_reg(BSC_A, address)?;
+        self.region.write_reg(BSC_DLEN, len)?;
+        let sent = self.fill_fifo(out)?;
+        self.region.write_reg(BSC_C, C_I2CEN | C_ST | if read { C_READ } else { 0 })?;
+        let result = self.pump(out, sent, input);
+        self.finish()?;
+        result
+    }
+
+    // The controller has no repeated-start command. Instead the whole write
+    // is queued, and the read is started as soon as the write is under way,
+    // which replaces the write's stop with a start.
+    fn combined(&self, address: u32, out: &[u8], input: &mut [u8]) -> Result<(), Error> {
+        if out.len() > FIFO_DEPTH {
+            return Err(Error::Unsupported(format!(
+                "the write half of a combined I2C transfer is limited to {} bytes", FIFO_DEPTH)));
+        }
+        let read_len = Self::check_len(input.len())?;
+        self.region.write_reg(BSC_A, address)?;
+        self.region.write_reg(BSC_DLEN, out.len() as u32)?;
+        self.fill_fifo(out)?;
+        self.region.write_reg(BSC_C, C_I2CEN | C_ST)?;
+        let started = Instant::now();
+        let result = loop {
+            let status = self.region.read_reg(BSC_S)?;
+            if status & (S_TA | S_DONE) != 0 {
+                self.region.write_reg(BSC_DLEN, read_len)?;
+                self.region.write_reg(BSC_C, C_I2CEN | C_ST | C_READ)?;
+                break self.pump(&[], 0, input);
+            }
+            if started.elapsed() > self.timeout {
+                break Err(Error::Timeout("I2C write never started".to_string()));
+            }
+            std::hint::spin_loop();
+        };
+        self.finish()?;
+        result
+    }
+}
+
+impl I2cBackend for Bsc {
+    fn write(&mut self, address: I2cAddress, data: &[u8]) -> Result<(), Error> {
+        let (address, prefix) = address_bytes(address);
+        self.transfer(address, &with_prefix(prefix, data), &mut [])
+    }
+
+    fn read(&mut self, address: I2cAddress, buffer: &mut [u8]) -> Result<(), Error> {
+        match address_bytes(address) {
+            (address, Some(low)) => self.combined(address, &[low], buffer),
+            (address, None) => self.transfer(address, &[], buffer),
+        }
+    }
+
+    fn write_read(&mut self, address: I2cAddress, data: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+        let (address, prefix) = address_bytes(address);
+        let out = with_prefix(prefix, data);
+        if buffer.is_empty() {
+            return self.transfer(address, &out, &mut []);
+        }
+        self.combined(address, &out, buffer)
+    }
+
+    fn set_frequency(&mut self, freq_hz: u32) -> Result<u32, Error> {
+        let divider = clock_divider(self.core_clock_hz, freq_hz)?;
+        // 32768 is written as 0.
+        self.region.write_reg(BSC_DIV, divider & 0x7fff)?;
+        Ok(self.core_clock_hz / divider)
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), Error> {
+        self.timeout = timeout;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open_bsc(name: &str) -> (Bsc, std::path::PathBuf) {
+        let path = std::env::temp_dir().join(format!("rustberrypi-i2c-{}-{}", name, std::process::id()));
+        std::fs::write(&path, vec![0u8; 4096]).unwrap();
+        (Bsc::open(&path, 0, Soc::Bcm2837).ok().unwrap(), path)
+    }
+
+    #[test]
+    fn test_addresses() {
+        assert_eq!(I2cAddress::from(0x50).check().ok(), Some(I2cAddress::SevenBit(0x50)));
+        assert!(I2cAddress::SevenBit(0x80).check().is_err());
+        assert!(I2cAddress::TenBit(0x400).check().is_err());
+        assert_eq!(address_bytes(I2cAddress::TenBit(0x2a5)), (0x7a, Some(0xa5)));
+        assert_eq!(clock_divider(250_000_000, 100_000).ok(), Some(2500));
+        assert_eq!(clock_divider(250_000_000, 400_000).ok(), Some(626));
+        assert!(clock_divider(250_000_000, 1_000).is_err());
+    }
+
+    #[test]
+    fn test_bsc_write() {
+        let (mut bsc, path) = open_bsc("write");
+        assert_eq!(bsc.set_frequency(400_000).ok(), Some(399_361));
+        assert_eq!(bsc.region.read_reg(BSC_DIV).ok(), Some(626));
+
+        bsc.region.write_reg(BSC_S, S_TXD | S_DONE).unwrap();
+        bsc.write(I2cAddress::TenBit(0x2a5), &[0x10, 0x20]).unwrap();
+        assert_eq!(bsc.region.read_reg(BSC_A).ok(), Some(0x7a));
+        assert_eq!(bsc.region.read_reg(BSC_DLEN).ok(), Some(3));
+        assert_eq!(bsc.region.read_reg(BSC_FIFO).ok(), Some(0x20));
+        assert_eq!(bsc.region.read_reg(BSC_C).ok(), Some(C_I2CEN | C_CLEAR));
+
+        bsc.region.write_reg(BSC_S, S_TXD | S_ERR).unwrap();
+        let error = bsc.write(I2cAddress::SevenBit(0x50), &[1]).unwrap_err();
+        assert!(matches!(error, Error::NoAcknowledge(_)));
+        drop(bsc);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_bsc_read() {
+        let (mut bsc, path) = open_bsc("read");
+        // The FIFO register reads back the last byte written to it.
+        bsc.region.write_reg(BSC_S, S_TA | S_TXD | S_RXD | S_DONE).unwrap();
+        let mut buffer = [0u8; 2];
+        bsc.write_read(I2cAddress::SevenBit(0x50), &[0x42], &mut buffer).unwrap();
+        assert_eq!(buffer, [0x42, 0x42]);
+        assert_eq!(bsc.region.read_reg(BSC_A).ok(), Some(0x50));
+        assert_eq!(bsc.region.read_reg(BSC_DLEN).ok(), Some(2));
+        assert!(bsc.write_read(I2cAddress::SevenBit(0x50), &[0; 17], &mut buffer).is_err());
+
+        bsc.region.write_reg(BSC_S, 0).unwrap();
+        bsc.set_timeout(Duration::from_millis(1)).unwrap();
+        let error = bsc.read(I2cAddress::SevenBit(0x50), &mut buffer).unwrap_err();
+        assert!(matches!(error, Error::Timeout(_)));
+        drop(bsc);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
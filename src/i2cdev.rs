@@ -0,0 +1,132 @@
+use crate::{open_file, Error, I2cAddress, I2cBackend};
+
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+
+const I2C_M_RD: u16 = 0x0001;
+const I2C_M_TEN: u16 = 0x0010;
+
+
+// struct i2c_msg from linux/i2c.h.
+#[repr(C)]
+struct I2cMsg {
+    addr: u16,
+    flags: u16,
+    len: u16,
+    buf: *mut u8,
+}
+
+// struct i2c_rdwr_ioctl_data from linux/i2c-dev.h.
+#[repr(C)]
+struct I2cRdwrIoctlData {
+    msgs: *mut I2cMsg,
+    nmsgs: u32,
+}
+
+nix::ioctl_write_ptr_bad!(i2c_rdwr, 0x0707, I2cRdwrIoctlData);
+// The argument is in units of 10 ms.
+nix::ioctl_write_int_bad!(i2c_timeout, 0x0702);
+
+
+fn message(address: I2cAddress, read: bool, buffer: *mut u8, len: usize) -> Result<I2cMsg, Error> {
+    if len > u16::MAX as usize {
+        return Err(Error::Other(format!("i2c-dev messages are limited to {} bytes, not {}", u16::MAX, len)));
+    }
+    let (addr, flags) = match address {
+        I2cAddress::SevenBit(address) => (address as u16, 0),
+        I2cAddress::TenBit(address) => (address, I2C_M_TEN),
+    };
+    Ok(I2cMsg { addr, flags: if read { flags | I2C_M_RD } else { flags }, len: len as u16, buf: buffer })
+}
+
+
+/// I2C through the kernel's i2c-dev driver, e.g. `/dev/i2c-1`. Every call
+/// is a single I2C_RDWR ioctl, so a write and read are joined by a repeated
+/// start and nothing else on the bus can come between them.
+pub(crate) struct I2cDev {
+    file: File,
+    path: PathBuf,
+}
+
+impl I2cDev {
+
+    pub(crate) fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        Ok(Self { file: open_file(&path)?, path })
+    }
+
+    fn transfer(&self, messages: &mut [I2cMsg]) -> Result<(), Error> {
+        let data = I2cRdwrIoctlData { msgs: messages.as_mut_ptr(), nmsgs: messages.len() as u32 };
+        unsafe { i2c_rdwr(self.file.as_raw_fd(), &data) }.map_err(|e| {
+            let context = format!("I2C transfer on {} failed", self.path.display());
+            // The bcm2835 driver reports a missing acknowledge as EREMOTEIO.
+            match e.as_errno() {
+                Some(nix::errno::Errno::EREMOTEIO) => Error::NoAcknowledge(context),
+                _ => Error::from_nix(context, e),
+            }
+        })?;
+        Ok(())
+    }
+}
+
+impl I2cBackend for I2cDev {
+    fn write(&mut self, address: I2cAddress, data: &[u8]) -> Result<(), Error> {
+        // The kernel only reads from a write message's buffer.
+        self.transfer(&mut [message(address, false, data.as_ptr() as *mut u8, data.len())?])
+    }
+
+    fn read(&mut self, address: I2cAddress, buffer: &mut [u8]) -> Result<(), Error> {
+        self.transfer(&mut [message(address, true, buffer.as_mut_ptr(), buffer.len())?])
+    }
+
+    fn write_read(&mut self, address: I2cAddress, data: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+        self.transfer(&mut [
+            message(address, false, data.as_ptr() as *mut u8, data.len())?,
+            message(address, true, buffer.as_mut_ptr(), buffer.len())?,
+        ])
+    }
+
+    fn set_frequency(&mut self, _freq_hz: u32) -> Result<u32, Error> {
+        Err(Error::Unsupported(format!(
+            "the clock of {} is set by the device tree, not through i2c-dev", self.path.display())))
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), Error> {
+        let ticks = timeout.as_millis().div_ceil(10).min(i32::MAX as u128) as i32;
+        unsafe { i2c_timeout(self.file.as_raw_fd(), ticks) }
+            .map_err(|e| Error::from_nix(format!("failed to set the I2C timeout of {}", self.path.display()), e))?;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_i2c_messages() {
+        let mut buffer = [0u8; 4];
+        let msg = message(I2cAddress::TenBit(0x2a5), true, buffer.as_mut_ptr(), buffer.len()).ok().unwrap();
+        assert_eq!((msg.addr, msg.flags, msg.len), (0x2a5, I2C_M_TEN | I2C_M_RD, 4));
+        let msg = message(I2cAddress::SevenBit(0x50), false, buffer.as_mut_ptr(), 0).ok().unwrap();
+        assert_eq!((msg.addr, msg.flags, msg.len), (0x50, 0, 0));
+        assert!(message(I2cAddress::SevenBit(0x50), false, buffer.as_mut_ptr(), 0x10000).is_err());
+    }
+
+    #[test]
+    fn test_i2cdev_needs_an_i2c_node() {
+        let path = std::env::temp_dir().join(format!("rustberrypi-i2cdev-{}", std::process::id()));
+        std::fs::write(&path, b"").unwrap();
+        let mut i2c = I2cDev::open(&path).ok().unwrap();
+        let error = i2c.write(I2cAddress::SevenBit(0x50), &[1]).unwrap_err();
+        assert_eq!(error.errno(), Some(nix::errno::Errno::ENOTTY));
+        assert!(i2c.set_frequency(100_000).is_err());
+        drop(i2c);
+        std::fs::remove_file(&path).unwrap();
+        assert!(I2cDev::open("/dev/i2c-does-not-exist").is_err());
+    }
+}
@@ -0,0 +1,224 @@
+use std::time::Duration;
+
+
+// NEC timings in microseconds. Each bit is a burst followed by a short
+// space for 0 or a long one for 1.
+pub(crate) const NEC_LEADER_MARK_US: u64 = 9000;
+pub(crate) const NEC_LEADER_SPACE_US: u64 = 4500;
+pub(crate) const NEC_REPEAT_SPACE_US: u64 = 2250;
+pub(crate) const NEC_BIT_MARK_US: u64 = 562;
+pub(crate) const NEC_ZERO_SPACE_US: u64 = 562;
+pub(crate) const NEC_ONE_SPACE_US: u64 = 1687;
+
+// Each RC-5 bit is two halves of this, one burst and one space.
+pub(crate) const RC5_HALF_BIT_US: u64 = 889;
+const RC5_BITS: usize = 14;
+
+// Measured timings are accepted within this fraction of the nominal ones.
+const TOLERANCE: f64 = 0.25;
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum IrProtocol {
+    /// 38 kHz, pulse distance coded, 8-bit commands.
+    Nec,
+    /// Philips RC-5: 36 kHz, biphase coded, 5-bit addresses and 6- or
+    /// 7-bit (RC-5X) commands.
+    Rc5,
+}
+
+/// One decoded remote control frame.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct IrFrame {
+    pub protocol: IrProtocol,
+    /// 8 bits for standard NEC, or 16 for extended NEC, whose address
+    /// byte isn't followed by its complement.
+    pub address: u16,
+    pub command: u8,
+    /// An NEC repeat code, sent while a key is held. Its address and
+    /// command are left at 0.
+    pub repeat: bool,
+    /// The RC-5 toggle bit, which flips with each new key press.
+    pub toggle: bool,
+}
+
+
+fn within(measured: Duration, nominal_us: u64) -> bool {
+    let measured = measured.as_secs_f64() * 1e6;
+    let nominal = nominal_us as f64;
+    (measured - nominal).abs() <= nominal * TOLERANCE
+}
+
+/// Decodes an NEC frame or repeat code from the burst and space lengths,
+/// starting and ending with a burst.
+pub(crate) fn decode_nec(timings: &[Duration]) -> Option<IrFrame> {
+    if timings.len() < 3 || !within(timings[0], NEC_LEADER_MARK_US) {
+        return None;
+    }
+    if timings.len() == 3 && within(timings[1], NEC_REPEAT_SPACE_US) && within(timings[2], NEC_BIT_MARK_US) {
+        return Some(IrFrame { protocol: IrProtocol::Nec, address: 0, command: 0, repeat: true, toggle: false });
+    }
+    if timings.len() != 67 || !within(timings[1], NEC_LEADER_SPACE_US) {
+        return None;
+    }
+    let mut bits = 0u32;
+    for (index, pair) in timings[2..66].chunks(2).enumerate() {
+        if !within(pair[0], NEC_BIT_MARK_US) {
+            return None;
+        }
+        if within(pair[1], NEC_ONE_SPACE_US) {
+            bits |= 1 << index;
+        } else if !within(pair[1], NEC_ZERO_SPACE_US) {
+            return None;
+        }
+    }
+    let [address, address_check, command, command_check] = bits.to_le_bytes();
+    if command != !command_check {
+        return None;
+    }
+    let address = if address == !address_check {
+        address as u16
+    } else {
+        u16::from_le_bytes([address, address_check])
+    };
+    Some(IrFrame { protocol: IrProtocol::Nec, address, command, repeat: false, toggle: false })
+}
+
+/// Decodes an RC-5 frame from the burst and space lengths, starting and
+/// ending with a burst. The first half of the first start bit is a space,
+/// and so is the last half of a frame ending in a 0, neither of which can
+/// be seen.
+pub(crate) fn decode_rc5(timings: &[Duration]) -> Option<IrFrame> {
+    // true for a half bit with a burst.
+    let mut halves = vec![false];
+    for (index, &timing) in timings.iter().enumerate() {
+        let count = if within(timing, RC5_HALF_BIT_US) {
+            1
+        } else if within(timing, 2 * RC5_HALF_BIT_US) {
+            2
+        } else {
+            return None;
+        };
+        halves.extend(std::iter::repeat_n(index % 2 == 0, count));
+    }
+    if halves.len() == 2 * RC5_BITS - 1 {
+        halves.push(false);
+    }
+    if halves.len() != 2 * RC5_BITS {
+        return None;
+    }
+    let mut bits = 0u16;
+    for pair in halves.chunks(2) {
+        bits = bits << 1 | match (pair[0], pair[1]) {
+            (false, true) => 1,
+            (true, false) => 0,
+            _ => return None,
+        };
+    }
+    // The second start bit is the inverted seventh command bit in RC-5X.
+    let field = bits >> 12 & 1;
+    Some(IrFrame {
+        protocol: IrProtocol::Rc5,
+        address: bits >> 6 & 0x1f,
+        command: ((bits & 0x3f) | (field ^ 1) << 6) as u8,
+        repeat: false,
+        toggle: bits >> 11 & 1 != 0,
+    })
+}
+
+
+fn us(values: &[u64]) -> Vec<Duration> {
+    values.iter().map(|&value| Duration::from_micros(value)).collect()
+}
+
+/// The burst and space lengths of an NEC frame. Addresses over 0xff are
+/// sent as extended NEC.
+pub(crate) fn encode_nec(address: u16, command: u8) -> Vec<Duration> {
+    let [low, high] = address.to_le_bytes();
+    let address_check = if address > 0xff { high } else { !low };
+    let bits = u32::from_le_bytes([low, address_check, command, !command]);
+    let mut timings = vec![NEC_LEADER_MARK_US, NEC_LEADER_SPACE_US];
+    for bit in 0..32 {
+        timings.push(NEC_BIT_MARK_US);
+        timings.push(if bits & (1 << bit) != 0 { NEC_ONE_SPACE_US } else { NEC_ZERO_SPACE_US });
+    }
+    timings.push(NEC_BIT_MARK_US);
+    us(&timings)
+}
+
+/// The burst and space lengths of an NEC repeat code.
+pub(crate) fn encode_nec_repeat() -> Vec<Duration> {
+    us(&[NEC_LEADER_MARK_US, NEC_REPEAT_SPACE_US, NEC_BIT_MARK_US])
+}
+
+/// The burst and space lengths of an RC-5 frame, or RC-5X for commands
+/// over 63.
+pub(crate) fn encode_rc5(address: u8, command: u8, toggle: bool) -> Vec<Duration> {
+    let field = (command >> 6 & 1) ^ 1;
+    let bits = 1 << 13 | (field as u16) << 12 | (toggle as u16) << 11
+        | ((address & 0x1f) as u16) << 6 | (command & 0x3f) as u16;
+    let mut timings: Vec<u64> = Vec::new();
+    let mut previous = None;
+    for bit in (0..RC5_BITS).rev() {
+        let halves = if bits & (1 << bit) != 0 { [false, true] } else { [true, false] };
+        for &burst in halves.iter() {
+            if previous == Some(burst) {
+                *timings.last_mut().unwrap() += RC5_HALF_BIT_US;
+            } else if previous.is_some() || burst {
+                timings.push(RC5_HALF_BIT_US);
+            }
+            previous = Some(burst);
+        }
+    }
+    // A trailing space is just the line going idle.
+    if previous == Some(false) {
+        timings.pop();
+    }
+    us(&timings)
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn nec_timings(bytes: [u8; 4]) -> Vec<Duration> {
+        let mut timings = vec![NEC_LEADER_MARK_US, NEC_LEADER_SPACE_US];
+        for index in 0..32 {
+            let one = bytes[index / 8] & (1 << (index % 8)) != 0;
+            timings.push(NEC_BIT_MARK_US);
+            timings.push(if one { NEC_ONE_SPACE_US } else { NEC_ZERO_SPACE_US });
+        }
+        timings.push(NEC_BIT_MARK_US);
+        us(&timings)
+    }
+
+    #[test]
+    fn test_decode_nec() {
+        let frame = decode_nec(&nec_timings([0x04, 0xfb, 0x08, 0xf7])).unwrap();
+        assert_eq!((frame.address, frame.command, frame.repeat), (0x04, 0x08, false));
+        assert_eq!(decode_nec(&nec_timings([0x34, 0x12, 0x08, 0xf7])).map(|frame| frame.address), Some(0x1234));
+        assert_eq!(decode_nec(&nec_timings([0x04, 0xfb, 0x08, 0xf6])), None);
+        assert!(decode_nec(&us(&[9100, 2200, 600])).unwrap().repeat);
+        assert_eq!(decode_nec(&us(&[4500, 4500, 600])), None);
+    }
+
+    #[test]
+    fn test_decode_rc5() {
+        let timings = encode_rc5(5, 0x35, false);
+        let frame = decode_rc5(&timings).unwrap();
+        assert_eq!((frame.address, frame.command, frame.toggle), (5, 0x35, false));
+        // Both ways a frame can end: in a burst, and in a space.
+        let frame = decode_rc5(&encode_rc5(0x1f, 0x7e, true)).unwrap();
+        assert_eq!((frame.address, frame.command, frame.toggle), (0x1f, 0x7e, true));
+        assert_eq!(decode_rc5(&timings[..timings.len() - 2]), None);
+        assert_eq!(decode_rc5(&nec_timings([0; 4])), None);
+    }
+
+    #[test]
+    fn test_encode_nec() {
+        assert_eq!(encode_nec(0x04, 0x08), nec_timings([0x04, 0xfb, 0x08, 0xf7]));
+        assert_eq!(decode_nec(&encode_nec(0x1234, 0x08)).map(|frame| frame.address), Some(0x1234));
+        assert!(decode_nec(&encode_nec_repeat()).unwrap().repeat);
+    }
+}
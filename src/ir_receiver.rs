@@ -0,0 +1,159 @@
+use crate::ir::{decode_nec, decode_rc5, IrFrame};
+use crate::{CallbackId, EdgeKind, Error, Input, Pin, PinEvent, Pull, Trigger, GPIO};
+
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+
+// A space longer than any within a frame; the next burst starts a new one.
+const FRAME_GAP: Duration = Duration::from_millis(12);
+
+// Longer than an NEC frame, the longest decoded.
+const MAX_TIMINGS: usize = 67;
+
+type FrameCallback = Box<dyn FnMut(IrFrame) + Send>;
+
+
+struct Shared {
+    timings: Vec<Duration>,
+    last_edge: Option<Instant>,
+    on_frame: Option<FrameCallback>,
+    subscribers: Vec<Sender<IrFrame>>,
+}
+
+impl Shared {
+    // The demodulator's output is low during a burst.
+    fn edge(&mut self, event: PinEvent) {
+        let since = self.last_edge.map(|last| event.timestamp.saturating_duration_since(last));
+        self.last_edge = Some(event.timestamp);
+        match (event.edge, since) {
+            (EdgeKind::Falling, Some(space)) if space < FRAME_GAP && !self.timings.is_empty() => self.timings.push(space),
+            (EdgeKind::Falling, _) => self.timings.clear(),
+            (EdgeKind::Rising, Some(burst)) => {
+                self.timings.push(burst);
+                if let Some(frame) = decode_nec(&self.timings).or_else(|| decode_rc5(&self.timings)) {
+                    self.timings.clear();
+                    self.deliver(frame);
+                } else if self.timings.len() >= MAX_TIMINGS {
+                    self.timings.clear();
+                }
+            }
+            (EdgeKind::Rising, None) => {}
+        }
+    }
+
+    fn deliver(&mut self, frame: IrFrame) {
+        if let Some(callback) = self.on_frame.as_mut() {
+            callback(frame);
+        }
+        self.subscribers.retain(|sender| sender.send(frame).is_ok());
+    }
+}
+
+
+/// An infrared receiver on a TSOP38238-style demodulator, whose output
+/// idles high and goes low while it sees the carrier. Bursts and spaces
+/// are timed from the pin's edge events on the event loop thread and
+/// decoded as NEC or RC-5 frames.
+///
+/// The timings need the kernel's edge timestamps, so use a line backend;
+/// a register backend only notices edges once a millisecond and will drop
+/// most frames.
+pub struct IrReceiver<'a> {
+    gpio: &'a GPIO,
+    pin: Pin<'a, Input>,
+    shared: Arc<Mutex<Shared>>,
+    callback: CallbackId,
+}
+
+impl<'a> IrReceiver<'a> {
+
+    pub fn new(pin: Pin<'a, Input>) -> Result<Self, Error> {
+        pin.set_pull(Pull::Up)?;
+        let shared = Arc::new(Mutex::new(Shared {
+            timings: Vec::with_capacity(MAX_TIMINGS),
+            last_edge: None,
+            on_frame: None,
+            subscribers: Vec::new(),
+        }));
+        let gpio = pin.gpio();
+        let callback = {
+            let shared = Arc::clone(&shared);
+            gpio.on_edge(pin.number(), Trigger::Both, move |event| shared.lock().unwrap().edge(event))?
+        };
+        Ok(Self { gpio, pin, shared, callback })
+    }
+
+    pub fn pin(&self) -> u32 {
+        self.pin.number()
+    }
+
+    /// Calls `callback` from the event loop thread with each frame.
+    pub fn on_frame(&self, callback: impl FnMut(IrFrame) + Send + 'static) {
+        self.shared.lock().unwrap().on_frame = Some(Box::new(callback));
+    }
+
+    pub fn clear_on_frame(&self) {
+        self.shared.lock().unwrap().on_frame = None;
+    }
+
+    /// A channel that receives every frame decoded from now on, for as long
+    /// as it's kept.
+    pub fn subscribe(&self) -> Receiver<IrFrame> {
+        let (sender, receiver) = mpsc::channel();
+        self.shared.lock().unwrap().subscribers.push(sender);
+        receiver
+    }
+}
+
+impl<'a> Drop for IrReceiver<'a> {
+    fn drop(&mut self) {
+        let _ = self.gpio.remove_callback(self.callback);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::tests::nec_timings;
+    use crate::IrProtocol;
+
+    // Plays `timings`, starting with a burst, into `shared` as edges.
+    fn play(shared: &Mutex<Shared>, start: Instant, timings: &[Duration]) -> Instant {
+        let mut at = start;
+        let mut edge = EdgeKind::Falling;
+        shared.lock().unwrap().edge(PinEvent { pin: 17, edge, timestamp: at });
+        for &timing in timings {
+            at += timing;
+            edge = if edge == EdgeKind::Falling { EdgeKind::Rising } else { EdgeKind::Falling };
+            shared.lock().unwrap().edge(PinEvent { pin: 17, edge, timestamp: at });
+        }
+        at
+    }
+
+    #[test]
+    fn test_ir_receiver_decodes_edges() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let receiver = IrReceiver::new(gpio.pin(17).unwrap().into_input().unwrap()).unwrap();
+        assert_eq!(gpio.get_pull(17).unwrap(), Pull::Up);
+        let frames = receiver.subscribe();
+        let seen = Arc::new(Mutex::new(0));
+        let count = Arc::clone(&seen);
+        receiver.on_frame(move |_| *count.lock().unwrap() += 1);
+
+        let start = Instant::now();
+        let end = play(&receiver.shared, start, &nec_timings([0x00, 0xff, 0x45, 0xba]));
+        let frame = frames.try_recv().unwrap();
+        assert_eq!((frame.protocol, frame.address, frame.command), (IrProtocol::Nec, 0, 0x45));
+        // A repeat code 40 ms later is a frame of its own.
+        let repeat = [9000, 2250, 562].map(Duration::from_micros);
+        play(&receiver.shared, end + Duration::from_millis(40), &repeat);
+        assert!(frames.try_recv().unwrap().repeat);
+        assert_eq!(*seen.lock().unwrap(), 2);
+        // Noise doesn't decode, and is dropped at the next gap.
+        play(&receiver.shared, end + Duration::from_millis(100), &[Duration::from_micros(300); 5]);
+        assert!(frames.try_recv().is_err());
+    }
+}
@@ -0,0 +1,176 @@
+use crate::clock::{Clock, ClockManager, ClockSource};
+use crate::ir::{encode_nec, encode_nec_repeat, encode_rc5};
+use crate::timing::wait_until;
+use crate::{Error, HwPwm, Pin, PinFunction, PwmMode, Unconfigured, GPIO};
+
+use std::time::{Duration, Instant};
+
+
+// IR LEDs are usually driven at a third duty cycle, which saves power
+// and is what most receivers are tuned for.
+const PWM_DUTY: f64 = 1.0 / 3.0;
+
+
+enum Carrier {
+    Pwm(HwPwm),
+    Clock(ClockManager, Clock),
+}
+
+
+/// An infrared LED, driven through a transistor, sending remote control
+/// frames on a carrier from the PWM controller or a general-purpose clock.
+/// The carrier runs continuously and is gated by switching the pin
+/// between its carrier function and a plain output held low, so bursts
+/// start and stop within a carrier cycle of where software puts them.
+///
+/// Frames are timed by spinning, so a badly timed context switch can
+/// stretch a burst or space; receivers allow about 25 %.
+pub struct IrTransmitter<'a> {
+    pin: Pin<'a, Unconfigured>,
+    function: PinFunction,
+    carrier: Carrier,
+}
+
+impl<'a> IrTransmitter<'a> {
+
+    pub const NEC_CARRIER_HZ: f64 = 38_000.0;
+
+    pub const RC5_CARRIER_HZ: f64 = 36_000.0;
+
+    /// Runs the carrier on `pwm`, which must be able to drive `pin` (12,
+    /// 13, 18 or 19). The PWM clock must already be running, as for any
+    /// `HwPwm`.
+    pub fn pwm(pin: Pin<'a, Unconfigured>, mut pwm: HwPwm, carrier_hz: f64) -> Result<Self, Error> {
+        let function = pwm.channel().pins().iter().find(|&&(candidate, _)| candidate == pin.number())
+            .map(|&(_, function)| function)
+            .ok_or_else(|| Error::Other(format!("{:?} can't be routed to pin {}", pwm.channel(), pin.number())))?;
+        pwm.set_frequency(carrier_hz)?;
+        pwm.set_duty_cycle(PWM_DUTY)?;
+        pwm.set_mode(PwmMode::MarkSpace)?;
+        pwm.enable()?;
+        Self::start(pin, function, Carrier::Pwm(pwm))
+    }
+
+    /// Runs the carrier on the general-purpose clock that can be output on
+    /// `pin` (4, 5, 6, 20 or 21), from the crystal oscillator. Its duty
+    /// cycle is a half.
+    pub fn clock(pin: Pin<'a, Unconfigured>, clocks: ClockManager, carrier_hz: f64) -> Result<Self, Error> {
+        let clock = Clock::for_pin(pin.number())
+            .ok_or_else(|| Error::Other(format!("no general-purpose clock can be output on pin {}", pin.number())))?;
+        let function = clock.pins().iter().find(|&&(candidate, _)| candidate == pin.number()).unwrap().1;
+        clocks.set_frequency(clock, ClockSource::Oscillator, carrier_hz)?;
+        Self::start(pin, function, Carrier::Clock(clocks, clock))
+    }
+
+    fn start(pin: Pin<'a, Unconfigured>, function: PinFunction, carrier: Carrier) -> Result<Self, Error> {
+        let transmitter = Self { pin, function, carrier };
+        transmitter.gpio().set_low(transmitter.pin.number())?;
+        transmitter.carrier_off()?;
+        Ok(transmitter)
+    }
+
+    fn gpio(&self) -> &'a GPIO {
+        self.pin.gpio()
+    }
+
+    fn carrier_on(&self) -> Result<(), Error> {
+        self.gpio().set_function(self.pin.number(), self.function)
+    }
+
+    fn carrier_off(&self) -> Result<(), Error> {
+        self.gpio().set_function(self.pin.number(), PinFunction::Output)
+    }
+
+    /// Changes the carrier frequency, e.g. to `RC5_CARRIER_HZ`.
+    pub fn set_carrier_frequency(&mut self, carrier_hz: f64) -> Result<(), Error> {
+        match &mut self.carrier {
+            Carrier::Pwm(pwm) => pwm.set_frequency(carrier_hz),
+            Carrier::Clock(clocks, clock) => clocks.set_frequency(*clock, ClockSource::Oscillator, carrier_hz).map(|_| ()),
+        }
+    }
+
+    /// Sends alternate bursts and spaces, starting with a burst. Each edge
+    /// is timed from the start of the train, so errors don't add up.
+    pub fn send_raw(&self, timings: &[Duration]) -> Result<(), Error> {
+        let started = Instant::now();
+        let mut edge = started;
+        let result = timings.iter().enumerate().try_for_each(|(index, &timing)| {
+            if index % 2 == 0 { self.carrier_on() } else { self.carrier_off() }?;
+            edge += timing;
+            wait_until(edge);
+            Ok(())
+        });
+        self.carrier_off()?;
+        result
+    }
+
+    /// Sends an NEC frame; addresses over 0xff are sent as extended NEC.
+    pub fn send_nec(&self, address: u16, command: u8) -> Result<(), Error> {
+        self.send_raw(&encode_nec(address, command))
+    }
+
+    /// Sends the code that says the last key is still held, which remotes
+    /// repeat every 108 ms.
+    pub fn send_nec_repeat(&self) -> Result<(), Error> {
+        self.send_raw(&encode_nec_repeat())
+    }
+
+    /// Sends an RC-5 frame, or RC-5X for commands over 63. Flip `toggle`
+    /// for each new key press.
+    pub fn send_rc5(&self, address: u8, command: u8, toggle: bool) -> Result<(), Error> {
+        if address > 0x1f || command > 0x7f {
+            return Err(Error::Other(format!("RC-5 address {} or command {} is out of range", address, command)));
+        }
+        self.send_raw(&encode_rc5(address, command, toggle))
+    }
+}
+
+impl<'a> Drop for IrTransmitter<'a> {
+    fn drop(&mut self) {
+        let _ = self.carrier_off();
+        if let Carrier::Clock(clocks, clock) = &self.carrier {
+            let _ = clocks.disable(*clock);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MockGpio, MockOperation, PwmChannel, Soc};
+
+    fn block_file(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("rustberrypi-ir-{}-{}", name, std::process::id()));
+        std::fs::write(&path, vec![0u8; 4096]).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_ir_transmitter_gates_the_carrier() {
+        let mock = MockGpio::new();
+        let gpio = mock.gpio().unwrap();
+        let path = block_file("pwm");
+        let pwm = HwPwm::open(&path, 0, PwmChannel::Pwm0).ok().unwrap();
+        assert!(IrTransmitter::pwm(gpio.pin(13).unwrap(), HwPwm::open(&path, 0, PwmChannel::Pwm0).ok().unwrap(), 38e3).is_err());
+        let transmitter = IrTransmitter::pwm(gpio.pin(18).unwrap(), pwm, IrTransmitter::NEC_CARRIER_HZ).unwrap();
+        mock.take_operations();
+
+        transmitter.send_raw(&[Duration::from_micros(100); 3]).unwrap();
+        let functions: Vec<_> = mock.operations().into_iter().filter_map(|operation| match operation {
+            MockOperation::SetFunction { pin: 18, function } => Some(function),
+            _ => None,
+        }).collect();
+        assert_eq!(functions, vec![PinFunction::Alt5, PinFunction::Output, PinFunction::Alt5, PinFunction::Output]);
+        assert!(transmitter.send_rc5(0x20, 1, false).is_err());
+        drop(transmitter);
+        std::fs::remove_file(&path).unwrap();
+
+        let path = block_file("clock");
+        let clocks = ClockManager::open(&path, 0, Soc::Bcm2837).ok().unwrap();
+        let transmitter = IrTransmitter::clock(gpio.pin(4).unwrap(), clocks, IrTransmitter::RC5_CARRIER_HZ).unwrap();
+        assert_eq!(gpio.get_function(4).unwrap(), PinFunction::Output);
+        drop(transmitter);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
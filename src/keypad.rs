@@ -0,0 +1,244 @@
+use crate::timing::wait_until;
+use crate::{Error, Input, Pin, PinFunction, Pull, Unconfigured, GPIO};
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+
+// How long a driven row is given to pull the columns down through the
+// pull-ups before they're read.
+const SETTLE_TIME: Duration = Duration::from_micros(10);
+
+
+/// A key changing state, once it has been stable for the debounce period.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct KeyEvent {
+    pub key: char,
+    pub row: usize,
+    pub column: usize,
+    pub pressed: bool,
+    pub timestamp: Instant,
+}
+
+type KeyCallback = Box<dyn FnMut(KeyEvent) + Send>;
+
+
+// Per-key debounce: a key's reported state follows its raw state only
+// after the raw state has held for `period`.
+struct KeyStates {
+    period: Duration,
+    reported: Vec<bool>,
+    raw: Vec<bool>,
+    since: Vec<Instant>,
+}
+
+impl KeyStates {
+    fn new(keys: usize, period: Duration) -> Self {
+        let now = Instant::now();
+        Self { period, reported: vec![false; keys], raw: vec![false; keys], since: vec![now; keys] }
+    }
+
+    // Takes a scan made at `now` and returns the keys whose reported state
+    // changed.
+    fn update(&mut self, scan: &[bool], now: Instant) -> Vec<usize> {
+        let mut changed = Vec::new();
+        for (key, &down) in scan.iter().enumerate() {
+            if down != self.raw[key] {
+                self.raw[key] = down;
+                self.since[key] = now;
+            }
+            if self.raw[key] != self.reported[key] && now.saturating_duration_since(self.since[key]) >= self.period {
+                self.reported[key] = self.raw[key];
+                changed.push(key);
+            }
+        }
+        changed
+    }
+}
+
+
+/// A matrix keypad, such as the common 4x4 and 4x3 membrane ones. Columns
+/// are inputs held high by their pull-ups; each row in turn is pulled low,
+/// open-drain style, so that pressing several keys at once can't short two
+/// driven pins together. Keys are read by polling, with `pressed_keys` or
+/// with `scan_until` delivering `KeyEvent`s to a callback.
+pub struct Keypad<'a> {
+    rows: Vec<Pin<'a, Unconfigured>>,
+    columns: Vec<Pin<'a, Input>>,
+    keymap: Vec<Vec<char>>,
+    scan_interval: Duration,
+    states: KeyStates,
+    on_key: Option<KeyCallback>,
+}
+
+impl<'a> Keypad<'a> {
+
+    pub const DEFAULT_SCAN_INTERVAL: Duration = Duration::from_millis(10);
+
+    pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(20);
+
+    /// `keymap` has one string per row, giving the key in each column, e.g.
+    /// `&["123A", "456B", "789C", "*0#D"]`.
+    pub fn new(rows: Vec<Pin<'a, Unconfigured>>, columns: Vec<Pin<'a, Input>>, keymap: &[&str]) -> Result<Self, Error> {
+        let keymap: Vec<Vec<char>> = keymap.iter().map(|row| row.chars().collect()).collect();
+        if rows.is_empty() || columns.is_empty() || keymap.len() != rows.len()
+            || keymap.iter().any(|keys| keys.len() != columns.len())
+        {
+            return Err(Error::Other(format!("the keymap doesn't fit a {}x{} keypad", rows.len(), columns.len())));
+        }
+        for column in &columns {
+            column.set_pull(Pull::Up)?;
+        }
+        let gpio = rows[0].gpio();
+        for row in &rows {
+            gpio.set_pull(row.number(), Pull::None)?;
+            gpio.set_low(row.number())?;
+            gpio.set_function(row.number(), PinFunction::Input)?;
+        }
+        let states = KeyStates::new(rows.len() * columns.len(), Self::DEFAULT_DEBOUNCE);
+        Ok(Self { rows, columns, keymap, scan_interval: Self::DEFAULT_SCAN_INTERVAL, states, on_key: None })
+    }
+
+    fn gpio(&self) -> &'a GPIO {
+        self.rows[0].gpio()
+    }
+
+    /// How long a key must stay pressed or released before it counts.
+    pub fn set_debounce(&mut self, period: Duration) {
+        self.states.period = period;
+    }
+
+    /// How often `scan_until` scans the matrix.
+    pub fn set_scan_interval(&mut self, interval: Duration) {
+        self.scan_interval = interval;
+    }
+
+    pub fn scan_interval(&self) -> Duration {
+        self.scan_interval
+    }
+
+    /// Reads the whole matrix once without debouncing, row by row, with
+    /// `true` for each key held down.
+    pub fn scan(&self) -> Result<Vec<bool>, Error> {
+        let gpio = self.gpio();
+        let mut keys = Vec::with_capacity(self.rows.len() * self.columns.len());
+        for row in &self.rows {
+            let started = Instant::now();
+            gpio.set_function(row.number(), PinFunction::Output)?;
+            wait_until(started + SETTLE_TIME);
+            let levels: Result<Vec<bool>, Error> = self.columns.iter().map(|column| column.read()).collect();
+            gpio.set_function(row.number(), PinFunction::Input)?;
+            keys.extend(levels?.into_iter().map(|high| !high));
+        }
+        Ok(keys)
+    }
+
+    /// Calls `callback` with each press and release found by `poll`.
+    pub fn on_key(&mut self, callback: impl FnMut(KeyEvent) + Send + 'static) {
+        self.on_key = Some(Box::new(callback));
+    }
+
+    pub fn clear_on_key(&mut self) {
+        self.on_key = None;
+    }
+
+    /// Scans once and returns the presses and releases that have passed
+    /// the debounce period, also handing each to the `on_key` callback.
+    pub fn poll(&mut self) -> Result<Vec<KeyEvent>, Error> {
+        let scan = self.scan()?;
+        let now = Instant::now();
+        let columns = self.columns.len();
+        let events: Vec<KeyEvent> = self.states.update(&scan, now).into_iter().map(|index| {
+            let (row, column) = (index / columns, index % columns);
+            KeyEvent { key: self.keymap[row][column], row, column, pressed: self.states.reported[index], timestamp: now }
+        }).collect();
+        if let Some(callback) = self.on_key.as_mut() {
+            events.iter().for_each(|&event| callback(event));
+        }
+        Ok(events)
+    }
+
+    /// Scans once and returns the keys held down, after debouncing.
+    pub fn pressed_keys(&mut self) -> Result<Vec<char>, Error> {
+        self.poll()?;
+        let columns = self.columns.len();
+        Ok(self.states.reported.iter().enumerate()
+            .filter(|(_, &down)| down)
+            .map(|(index, _)| self.keymap[index / columns][index % columns])
+            .collect())
+    }
+
+    /// Polls every scan interval until `stop` is set, e.g. from a scoped
+    /// thread, delivering events to the `on_key` callback.
+    pub fn scan_until(&mut self, stop: &AtomicBool) -> Result<(), Error> {
+        let mut next = Instant::now();
+        while !stop.load(Ordering::Relaxed) {
+            self.poll()?;
+            next += self.scan_interval;
+            let now = Instant::now();
+            if next > now {
+                std::thread::sleep(next - now);
+            } else {
+                next = now;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Drop for Keypad<'a> {
+    fn drop(&mut self) {
+        let gpio = self.gpio();
+        for row in &self.rows {
+            let _ = gpio.set_function(row.number(), PinFunction::Input);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_key_debounce() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut states = KeyStates::new(2, Duration::from_millis(20));
+        assert!(states.update(&[true, false], at(0)).is_empty());
+        // A bounce restarts the wait.
+        assert!(states.update(&[false, false], at(5)).is_empty());
+        assert!(states.update(&[true, false], at(10)).is_empty());
+        assert!(states.update(&[true, false], at(25)).is_empty());
+        assert_eq!(states.update(&[true, true], at(30)), vec![0]);
+        assert_eq!(states.update(&[true, true], at(50)), vec![1]);
+        assert!(states.update(&[true, true], at(60)).is_empty());
+    }
+
+    #[test]
+    fn test_keypad_scan() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let rows = vec![gpio.pin(5).unwrap(), gpio.pin(6).unwrap()];
+        let columns = (20..23).map(|pin| gpio.pin(pin).unwrap().into_input().unwrap()).collect();
+        assert!(Keypad::new(Vec::new(), Vec::new(), &[]).is_err());
+        let mut keypad = Keypad::new(rows, columns, &["123", "456"]).unwrap();
+        assert_eq!(gpio.get_pull(21).unwrap(), Pull::Up);
+        assert_eq!(gpio.get_function(5).unwrap(), PinFunction::Input);
+        keypad.set_debounce(Duration::ZERO);
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&events);
+        keypad.on_key(move |event| log.lock().unwrap().push((event.key, event.pressed)));
+        // The mock's levels don't follow the rows, so column 1 reading low
+        // shows as its key on every row.
+        unsafe { gpio.write_raw(0x34, 1 << 20 | 1 << 22) };
+        assert_eq!(keypad.pressed_keys().unwrap(), vec!['2', '5']);
+        unsafe { gpio.write_raw(0x34, 0b111 << 20) };
+        assert!(keypad.pressed_keys().unwrap().is_empty());
+        assert_eq!(*events.lock().unwrap(), vec![('2', true), ('5', true), ('2', false), ('5', false)]);
+
+        let stop = AtomicBool::new(true);
+        keypad.scan_until(&stop).unwrap();
+    }
+}
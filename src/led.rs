@@ -0,0 +1,169 @@
+use crate::pwm_output::PwmOutput;
+use crate::{Error, HwPwm, GPIO};
+
+use std::time::{Duration, Instant};
+
+
+// How often `fade_to` updates the duty cycle.
+const FADE_STEP: Duration = Duration::from_millis(10);
+
+
+/// An LED dimmed with PWM. Brightness runs from 0 to 1 and is gamma
+/// corrected, so equal steps in brightness look like equal steps to the
+/// eye. Blocking helpers fade and blink it from the calling thread.
+pub struct Led<'a> {
+    output: PwmOutput<'a>,
+    gamma: f32,
+    brightness: f32,
+}
+
+impl<'a> Led<'a> {
+
+    /// High enough to avoid visible flicker, low enough for software PWM.
+    pub const DEFAULT_FREQUENCY_HZ: f64 = 500.0;
+    /// The usual approximation of the eye's response.
+    pub const DEFAULT_GAMMA: f32 = 2.2;
+
+    /// An LED on `pin`, driven by the hardware PWM controller if the pin
+    /// has a PWM channel and `/dev/mem` can be mapped, and by software PWM
+    /// otherwise. It starts off.
+    pub fn new(gpio: &'a GPIO, pin: u32) -> Result<Self, Error> {
+        Ok(Self::with_output(PwmOutput::open(gpio, pin, Self::DEFAULT_FREQUENCY_HZ)?))
+    }
+
+    /// An LED on a hardware PWM channel that is already routed to its pin.
+    pub fn hardware(pwm: HwPwm) -> Result<Self, Error> {
+        Ok(Self::with_output(PwmOutput::hardware(pwm, Self::DEFAULT_FREQUENCY_HZ)?))
+    }
+
+    /// An LED driven by software PWM on `pin`, which is made an output.
+    pub fn software(gpio: &'a GPIO, pin: u32) -> Result<Self, Error> {
+        Ok(Self::with_output(PwmOutput::software(gpio, pin, Self::DEFAULT_FREQUENCY_HZ)?))
+    }
+
+    fn with_output(output: PwmOutput<'a>) -> Self {
+        Self { output, gamma: Self::DEFAULT_GAMMA, brightness: 0.0 }
+    }
+
+    pub fn is_hardware(&self) -> bool {
+        self.output.is_hardware()
+    }
+
+    /// Sets the exponent mapping brightness to duty cycle. 1 turns the
+    /// correction off. Applies from the next brightness change.
+    pub fn set_gamma(&mut self, gamma: f32) -> Result<(), Error> {
+        if !(gamma.is_finite() && gamma > 0.0) {
+            return Err(Error::Other(format!("gamma must be positive, not {}", gamma)));
+        }
+        self.gamma = gamma;
+        Ok(())
+    }
+
+    pub fn gamma(&self) -> f32 {
+        self.gamma
+    }
+
+    pub fn set_brightness(&mut self, brightness: f32) -> Result<(), Error> {
+        if !(0.0..=1.0).contains(&brightness) {
+            return Err(Error::Other(format!("brightness {} is outside 0 to 1", brightness)));
+        }
+        self.output.set_duty_cycle(brightness.powf(self.gamma) as f64)?;
+        self.brightness = brightness;
+        Ok(())
+    }
+
+    pub fn brightness(&self) -> f32 {
+        self.brightness
+    }
+
+    /// The duty cycle the current brightness was corrected to.
+    pub fn duty_cycle(&self) -> f64 {
+        self.output.duty_cycle()
+    }
+
+    pub fn on(&mut self) -> Result<(), Error> {
+        self.set_brightness(1.0)
+    }
+
+    pub fn off(&mut self) -> Result<(), Error> {
+        self.set_brightness(0.0)
+    }
+
+    /// Ramps the brightness linearly to `brightness` over `duration`,
+    /// returning once it's reached.
+    pub fn fade_to(&mut self, brightness: f32, duration: Duration) -> Result<(), Error> {
+        if !(0.0..=1.0).contains(&brightness) {
+            return Err(Error::Other(format!("brightness {} is outside 0 to 1", brightness)));
+        }
+        let from = self.brightness;
+        let started = Instant::now();
+        loop {
+            let elapsed = started.elapsed();
+            if elapsed >= duration {
+                return self.set_brightness(brightness);
+            }
+            let progress = elapsed.as_secs_f32() / duration.as_secs_f32();
+            self.set_brightness(from + (brightness - from) * progress)?;
+            std::thread::sleep(FADE_STEP.min(duration - elapsed));
+        }
+    }
+
+    /// Blinks fully on and off `times` times, ending off.
+    pub fn blink(&mut self, on_time: Duration, off_time: Duration, times: usize) -> Result<(), Error> {
+        for _ in 0..times {
+            self.play(&[(1.0, on_time), (0.0, off_time)])?;
+        }
+        Ok(())
+    }
+
+    /// Steps through `pattern`, holding each brightness for its duration.
+    /// The LED is left at the last step's brightness.
+    pub fn play(&mut self, pattern: &[(f32, Duration)]) -> Result<(), Error> {
+        for &(brightness, hold) in pattern {
+            self.set_brightness(brightness)?;
+            std::thread::sleep(hold);
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_led_brightness_is_gamma_corrected() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let mut led = Led::software(&gpio, 22).unwrap();
+        assert!(!led.is_hardware());
+        assert_eq!(led.duty_cycle(), 0.0);
+
+        led.set_brightness(0.5).unwrap();
+        assert!((led.duty_cycle() - 0.5f64.powf(2.2)).abs() < 1e-6);
+        led.set_gamma(1.0).unwrap();
+        led.set_brightness(0.5).unwrap();
+        assert_eq!(led.duty_cycle(), 0.5);
+        led.on().unwrap();
+        assert_eq!(led.duty_cycle(), 1.0);
+
+        assert!(led.set_brightness(1.5).is_err());
+        assert!(led.set_gamma(0.0).is_err());
+        assert_eq!(led.brightness(), 1.0);
+    }
+
+    #[test]
+    fn test_led_fade_and_blink() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let mut led = Led::software(&gpio, 23).unwrap();
+        let started = Instant::now();
+        led.fade_to(0.8, Duration::from_millis(30)).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(30));
+        assert_eq!(led.brightness(), 0.8);
+
+        led.blink(Duration::from_millis(1), Duration::from_millis(1), 2).unwrap();
+        assert_eq!(led.brightness(), 0.0);
+        led.play(&[(0.3, Duration::from_millis(1)), (0.6, Duration::from_millis(1))]).unwrap();
+        assert_eq!(led.brightness(), 0.6);
+    }
+}
@@ -0,0 +1,98 @@
+use crate::timing::wait_until;
+use crate::backend::RegisterBackend;
+use crate::{bit_in_bank, Error, Pull, Register};
+
+use std::time::{Duration, Instant};
+
+
+// The datasheet asks for 150 cycles of the 250 MHz core clock (0.6 us)
+// between steps; a few microseconds leaves margin for slower clocks.
+const LEGACY_PULL_SETUP: Duration = Duration::from_micros(5);
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum PullStep {
+    Write(Register, u32),
+    Wait(Duration),
+}
+
+// GPPUD uses a different encoding to the BCM2711 control registers.
+fn gppud_bits(pull: Pull) -> u32 {
+    match pull {
+        Pull::None => 0b00,
+        Pull::Down => 0b01,
+        Pull::Up => 0b10,
+    }
+}
+
+/// The BCM2835 pull sequence: set the control signal in GPPUD, clock it
+/// into the pin with GPPUDCLK, then remove both.
+pub(crate) fn legacy_pull_sequence(pin: u32, pull: Pull) -> [PullStep; 6] {
+    [
+        PullStep::Write(Register::GPPUD, gppud_bits(pull)),
+        PullStep::Wait(LEGACY_PULL_SETUP),
+        PullStep::Write(Register::GPPUDCLK, 1 << bit_in_bank(pin)),
+        PullStep::Wait(LEGACY_PULL_SETUP),
+        PullStep::Write(Register::GPPUD, 0),
+        PullStep::Write(Register::GPPUDCLK, 0),
+    ]
+}
+
+
+impl RegisterBackend {
+
+    pub(crate) fn set_pull_legacy(&self, pin: u32, pull: Pull) -> Result<(), Error> {
+        for step in legacy_pull_sequence(pin, pull).iter() {
+            match *step {
+                PullStep::Write(register, value) => self.write_register(register, pin, value)?,
+                PullStep::Wait(duration) => wait_until(Instant::now() + duration),
+            }
+        }
+        self.legacy_pulls.lock().unwrap()[pin as usize] = pull;
+        Ok(())
+    }
+
+    // The legacy pull latches can't be read back, so report what we set.
+    pub(crate) fn get_pull_legacy(&self, pin: u32) -> Pull {
+        self.legacy_pulls.lock().unwrap()[pin as usize]
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Soc, GPIO};
+
+    #[test]
+    fn test_legacy_pull_sequence() {
+        assert_eq!(legacy_pull_sequence(17, Pull::Up), [
+            PullStep::Write(Register::GPPUD, 0b10),
+            PullStep::Wait(LEGACY_PULL_SETUP),
+            PullStep::Write(Register::GPPUDCLK, 1 << 17),
+            PullStep::Wait(LEGACY_PULL_SETUP),
+            PullStep::Write(Register::GPPUD, 0),
+            PullStep::Write(Register::GPPUDCLK, 0),
+        ]);
+        assert_eq!(legacy_pull_sequence(40, Pull::Down)[0], PullStep::Write(Register::GPPUD, 0b01));
+        assert_eq!(legacy_pull_sequence(40, Pull::Down)[2], PullStep::Write(Register::GPPUDCLK, 1 << 8));
+        assert_eq!(Register::GPPUD.to_offset(40).ok(), Some(0x94));
+        assert_eq!(Register::GPPUDCLK.to_offset(40).ok(), Some(0x9c));
+    }
+
+    #[test]
+    fn test_set_pull_on_legacy_soc() {
+        let registers = RegisterBackend::owned(Vec::new(), Soc::Bcm2837);
+        let gpio = GPIO::from_backend(Box::new(registers), Soc::Bcm2837);
+
+        gpio.set_pull(4, Pull::Up).unwrap();
+        assert_eq!(gpio.get_pull(4).ok(), Some(Pull::Up));
+        assert_eq!(gpio.get_pull(5).ok(), Some(Pull::None));
+
+        let buffer = gpio.testing_buffer();
+        let word = |offset: usize| u32::from_ne_bytes([buffer[offset], buffer[offset + 1], buffer[offset + 2], buffer[offset + 3]]);
+        assert_eq!(word(0x94), 0);
+        assert_eq!(word(0x98), 0);
+        assert_eq!(word(0xe4), 0);
+    }
+}
@@ -1,321 +1,218 @@
-use nix::sys::mman;
-use nix::errno::Errno;
-
-use std::ffi::c_void;
-use std::fmt::Display;
-use std::fs::OpenOptions;
-use std::os::unix::io::{AsRawFd, RawFd};
-use std::path::PathBuf;
-
-
-pub enum Register {
-    GPFSEL = 0x00,
-    
-    GPSET =  0x1c,
-    GPCLR =  0x28,
-    GPLEV =  0x34,
-    GPEDS =  0x40,
-    GPREN =  0x4c,
-    GPFEN =  0x58,
-    GPHEN =  0x64,
-    GPLEN =  0x70,
-    GPAREN = 0x7c,
-    GPAFEN = 0x88,
-
-    GPPUPPDNCNTRL = 0xe4,
-}
-
-
-const REGISTER_SIZE: u32 = 4;
-const GPIO_PIN_COUNT: u32 = 58;
-const GPIO_FUNCS_PER_REGISTER: u32 = 10;
-const GPIO_PUPPUD_PER_REGISTER: u32 = 16;
-
-fn assert_pin_index(pin: u32) {
-    assert!(
-        pin < GPIO_PIN_COUNT,
-        "Illegal pin value. Pin must be in [0,{pin_count}) - Paniced on pin = {pin}",
-        pin_count = GPIO_PIN_COUNT, pin = pin
-    );
-}
-
-macro_rules! register_offset {
-    ($pin:expr) => {
-        if $pin > 31 { REGISTER_SIZE } else { 0 }
+#![cfg_attr(not(feature = "std"), no_std)]
+
+// Everything but the register layer needs std, and Linux to run on.
+macro_rules! std_only {
+    ($($item:item)*) => {
+        $(
+            #[cfg(feature = "std")]
+            $item
+        )*
     };
 }
 
-impl Register {
-
-    pub fn to_offset(self, pin: u32) -> usize {
-        assert_pin_index(pin);
-
-        match self {
-            Register::GPFSEL => Register::gpfsel_offset_for(pin),
-            Register::GPPUPPDNCNTRL => Register::gp_pullup_pulldown(pin),
-            _ => Register::gp2reg_offset_for(self as u32, pin),
-        }
-    }
-
-    fn gpfsel_offset_for(pin: u32) -> usize {
-        ((pin / GPIO_FUNCS_PER_REGISTER) * REGISTER_SIZE) as usize
-    }
-
-    fn gp_pullup_pulldown(pin: u32) -> usize {
-        (Register::GPPUPPDNCNTRL as usize) + 
-            ((pin / GPIO_PUPPUD_PER_REGISTER) * REGISTER_SIZE) as usize
-    }
-
-    fn gp2reg_offset_for(offset: u32, pin: u32) -> usize {
-        (offset + register_offset!(pin)) as usize
-    }
-
-}
-
-
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
-pub enum PinFunction {
-	Input = 0b000,
-	Output = 0b001,
-	Alt0 = 0b100,
-	Alt1 = 0b101,
-	Alt2 = 0b110,
-	Alt3 = 0b111,
-	Alt4 = 0b011,
-	Alt5 = 0b010,
-    Error = 0b1000,
+mod barrier;
+mod gpio_block;
+mod register_map;
+mod registers;
+
+std_only! {
+    mod alt_function;
+    #[cfg(feature = "async")]
+    mod async_edge;
+    mod aux_spi;
+    mod auxiliary;
+    mod backend;
+    pub mod bench;
+    pub mod board;
+    mod button;
+    mod capabilities;
+    mod buzzer;
+    mod cleanup;
+    mod clock;
+    mod const_pin;
+    mod counter;
+    mod dc_motor;
+    mod debounce;
+    mod delay;
+    mod device_tree;
+    mod dht;
+    mod dma;
+    mod ds18b20;
+    mod dump;
+    mod edge;
+    mod edge_signal;
+    mod event_channel;
+    mod event_loop;
+    mod frequency_output;
+    #[cfg(feature = "ffi")]
+    pub mod ffi;
+    mod gpio;
+    mod gpio_pin;
+    mod gpiochip;
+    #[cfg(feature = "embedded-hal")]
+    mod hal;
+    mod hat;
+    mod hc_sr04;
+    mod hd44780;
+    mod hw_rng;
+    mod i2c;
+    mod i2cdev;
+    mod ir;
+    mod ir_receiver;
+    mod ir_transmitter;
+    mod keypad;
+    mod led;
+    mod legacy_pull;
+    mod line_names;
+    mod mailbox;
+    mod mcp23017;
+    mod mcp3008;
+    mod mini_uart;
+    mod multi_pwm;
+    #[cfg(any(test, feature = "mock"))]
+    mod mock;
+    mod one_wire;
+    mod pads;
+    mod parallel_display;
+    mod pcf8574;
+    mod pcm;
+    mod pin;
+    mod pin_config;
+    mod pin_group;
+    mod pl011;
+    mod policy;
+    mod profile;
+    mod pulse_meter;
+    mod pwm;
+    mod pwm_output;
+    #[cfg(feature = "python")]
+    mod python;
+    pub mod realtime;
+    mod region;
+    mod relay;
+    mod reserved;
+    mod rotary_encoder;
+    mod rgb_led;
+    mod rp1;
+    pub mod selftest;
+    mod servo;
+    mod singleton;
+    mod snapshot;
+    mod soc;
+    mod soft_i2c;
+    mod soft_pwm;
+    mod soft_serial;
+    mod soft_spi;
+    mod spi;
+    mod spidev;
+    mod split;
+    mod square_wave;
+    mod sr74hc165;
+    mod sr74hc595;
+    mod stepper;
+    mod sysfs;
+    mod system_timer;
+    mod timing;
+    #[cfg(feature = "trace")]
+    mod trace;
+    mod watchdog;
+    mod watcher;
+    mod wave;
+    mod ws2812;
 }
 
-impl PinFunction {
-
-	pub fn to_bits(&self, pin: u32) -> u32 {
-		let fval = *self as u32;
-		fval <<	((pin % 10) * 3)
-	}
-
-	pub fn clear_mask(pin: u32) -> u32 {
-		!(0b111 << ((pin % 10) * 3))
-	}
-
-    pub fn mask(pin: u32) -> u32 {
-        0b111 << ((pin % 10)* 3)
-    }
-
-    pub fn from_bits(pin: u32, bits: u32) -> PinFunction {
-        let bits = bits & (0b111 << ((pin % 10) * 3));
-        match bits {
-            0b000 => PinFunction::Input,
-            0b001 => PinFunction::Output,
-            0b100 => PinFunction::Alt0,
-            0b101 => PinFunction::Alt1,
-            0b110 => PinFunction::Alt2,
-            0b111 => PinFunction::Alt3,
-            0b011 => PinFunction::Alt4,
-            0b010 => PinFunction::Alt5,
-            _ => PinFunction::Error
-        }
-    }
-}
-
-
-fn detect_peripheral_base() -> Result<i64, Error> {
-    // Stub that works for the Pi4
-    let start: i64 = 0xfe200000;
-    return Ok(start);
-}
-
-
-fn open_file(path: impl Into<PathBuf>) -> Result<std::fs::File, Error> {
-    let path = path.into();
-    let file = OpenOptions::new()
-                .create(false)
-                .read(true)
-                .write(true)
-                .open(&path)
-                .map_err(|e| Error::from_io(format!("failed to open {}", path.display()), e))?;
-    Ok(file)
-}
-
-
-
-pub struct Error {
-    pub message: String,
-    pub errno: Option<Errno>,
-}
-
-impl Error {
-    pub fn new (message: impl std::string::ToString, errno: Option<Errno>) -> Self {
-        Self {
-            message: message.to_string(),
-            errno,
-        }
-    }
-
-    pub fn from_nix(message: impl std::string::ToString, error: nix::Error) -> Self {
-        Self::new(message, error.as_errno())
-    }
-
-    pub fn from_io(message: impl std::string::ToString, error: std::io::Error) -> Self {
-        Self::new(message, error.raw_os_error().map(Errno::from_i32))
-    }
-}
-
-
-impl Display for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self.errno {
-            None => write!(f, "{}", self.message),
-            Some(errno) => write!(f, "{}: {}", self.message, errno.desc())
-        }
-    }    
-}
-
-const GPIO_BLOCK_SIZE: usize = 0x100;
-
-pub struct GPIO {
-    buffer: *mut c_void,
-}
-
-impl GPIO {
-
- 	pub fn new() -> Result<Self, Error> {
-        let fp: std::fs::File  = open_file("/dev/mem")?;
-        let fd: RawFd = fp.as_raw_fd();
-        let gpio_offset:i64 = detect_peripheral_base()?;
-        let ptr = unsafe {
-            mman::mmap(std::ptr::null_mut(), GPIO_BLOCK_SIZE, 
-                mman::ProtFlags::PROT_READ | mman::ProtFlags::PROT_WRITE,
-                mman::MapFlags::MAP_SHARED, fd, gpio_offset)
-                .map_err(
-                    |e| Error::from_nix(format!(
-                    "failed to map the GPIO ({:#X}) from /dev/mem ", gpio_offset), e))?
-        };
-        Ok(Self{buffer: ptr})
-    }
-
-    pub fn set_function(&self, pin: u32, function: PinFunction) {
-        let offset: usize = Register::GPFSEL.to_offset(pin);
-        let clear_mask: u32 = PinFunction::clear_mask(pin);
-        let function_mask: u32 =  function.to_bits(pin);
-
-        let ptr = self.buffer.wrapping_add(offset) as *mut u32;
-        unsafe {
-            let value: u32 = ptr.read_volatile();
-            ptr.write_volatile(value & clear_mask | function_mask);
-        }
-    }
-
-    pub fn get_function(&self, pin: u32) -> PinFunction {
-        let offset: usize = Register::GPFSEL.to_offset(pin);
-        let ptr = self.buffer.wrapping_add(offset) as *const u32;
-        let bits: u32 = unsafe { ptr.read_volatile() };
-        PinFunction::from_bits(pin, bits)
-    }
-
-
-    pub fn set(&self, pin: u32) {
-        let offset: usize = Register::GPSET.to_offset(pin);
-        let ptr = self.buffer.wrapping_add(offset) as *mut u32;
-        unsafe {
-            let value: u32 = ptr.read_volatile();
-            ptr.write_volatile(value | (1 << pin));
-        }
-    }
-
-    pub fn clear(&self, pin: u32) {
-        let offset: usize = Register::GPCLR.to_offset(pin);
-        let ptr = self.buffer.wrapping_add(offset) as *mut u32;
-        unsafe {
-            let value: u32 = ptr.read_volatile();
-            ptr.write_volatile(value | (1 << pin));
-        }
-    }
-
-
-    pub fn level(&self, pin: u32) -> bool {
-        let offset: usize = Register::GPLEV.to_offset(pin);
-        let ptr = self.buffer.wrapping_add(offset) as *const u32;
-        let value: u32 = unsafe { ptr.read_volatile() };
-        ((value >> (pin % 32)) & 1) == 1
-    }    
-}
-
-
-impl Drop for GPIO {
-    fn drop(&mut self) {
-        unsafe {
-            drop(mman::munmap(self.buffer, GPIO_BLOCK_SIZE))
-        }
-    }
-}
-
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_register_gpfsel_to_offset() {
-        let gpfsel0 = Register::GPFSEL;
-        let gpfsel1 = Register::GPFSEL;
-        let gpfsel2 = Register::GPFSEL;
-        let gpfsel3 = Register::GPFSEL;
-        let gpfsel4 = Register::GPFSEL;
-        let gpfsel5 = Register::GPFSEL;
-        
-        assert_eq!(gpfsel0.to_offset(5),  0x00);
-        assert_eq!(gpfsel1.to_offset(15), 0x04);
-        assert_eq!(gpfsel2.to_offset(25), 0x08);
-        assert_eq!(gpfsel3.to_offset(35), 0x0c);
-        assert_eq!(gpfsel4.to_offset(45), 0x10);
-        assert_eq!(gpfsel5.to_offset(55), 0x14);
-    }
-
-
-    #[test]
-    fn test_register_2regcntrl_to_offset() {
-        let gpset0 = Register::GPSET;
-        let gpset1 = Register::GPSET;
-
-        assert_eq!(gpset0.to_offset(5),  0x1c + 0x00);
-        assert_eq!(gpset1.to_offset(45), 0x1c + 0x04);
-    }
-
-
-    #[test]
-    fn test_pullup_pulldown_control_to_offset() {
-        let gp_pup_pud0 = Register::GPPUPPDNCNTRL;
-        let gp_pup_pud1 = Register::GPPUPPDNCNTRL;
-        let gp_pup_pud2 = Register::GPPUPPDNCNTRL;
-        let gp_pup_pud3 = Register::GPPUPPDNCNTRL;
-    
-        assert_eq!(gp_pup_pud0.to_offset(8),    0xe4 + 0x00);
-        assert_eq!(gp_pup_pud1.to_offset(8+16), 0xe4 + 0x04);
-        assert_eq!(gp_pup_pud2.to_offset(8+32), 0xe4 + 0x08);
-        assert_eq!(gp_pup_pud3.to_offset(8+48), 0xe4 + 0x0c);
-
-    }
-
-
-    #[test]
-    fn test_pinfunction_to_bits() {
-        let pin32: u32 = 32;
-        let pin5: u32 = 5;
-        let output = PinFunction::Output;
-
-        assert_eq!(output.to_bits(pin32),         0b001000000);
-        assert_eq!(output.to_bits(pin5), 0b001000000000000000);
-    }
-
-    #[test]
-    fn test_pinfunction_clear_mask() {
-        let pin32: u32 = 32;
-        let pin5: u32 = 5;
-
-        let mask32: u32 = !(0b111000000);
-        let mask5: u32 = !(0b111000000000000000); 
-        assert_eq!(PinFunction::clear_mask(pin32), mask32);
-        assert_eq!(PinFunction::clear_mask(pin5), mask5);
-
-    }
+pub use barrier::memory_barrier;
+pub use gpio_block::GpioBlock;
+pub use register_map::RegisterMap;
+pub use registers::{bank_of, bit_in_bank, PinFunction, Pull, Register, BANK_COUNT};
+
+std_only! {
+    pub use alt_function::{alt_function_name, alt_functions, pins_with_function};
+    #[cfg(feature = "async")]
+    pub use async_edge::EdgeEvents;
+    pub use aux_spi::AuxSpi;
+    pub use auxiliary::{Aux, AuxPeripheral};
+    pub use backend::{GpioBackend, GpioBuilder};
+    pub use button::{Button, ButtonAction, ButtonEvent};
+    #[cfg(feature = "async")]
+    pub use button::ButtonEvents;
+    pub use buzzer::Buzzer;
+    pub use capabilities::{Interface, PinCapabilities};
+    pub use cleanup::{add_cleanup, install_signal_cleanup, remove_cleanup, run_cleanup, CleanupId};
+    pub use clock::{Clock, ClockManager, ClockSource, Mash};
+    pub use const_pin::ConstPin;
+    pub use counter::Counter;
+    pub use dc_motor::{DcMotor, DifferentialDrive};
+    pub use delay::{delay_ns, delay_us, Delay};
+    #[cfg(feature = "async")]
+    pub use delay::{delay_ns_async, delay_us_async};
+    pub use dht::{Dht, DhtKind, DhtReading};
+    pub use ds18b20::Ds18b20;
+    pub use dump::{GpioDump, PinState};
+    pub use edge::{EdgeKind, PinEvent, Trigger};
+    pub use edge_signal::EdgeSignal;
+    pub use event_channel::{EventChannel, Overflow};
+    pub use event_loop::CallbackId;
+    pub use frequency_output::{FrequencyOutput, FrequencyPlan};
+    pub use gpio::{Backend, EdgeTrigger, Error, PinChange, PinReport, PinSetup, GPIO};
+    pub use gpio_pin::{GpioInput, GpioPin, GpioPort};
+    pub use hat::{HatEeprom, HatGpioMap, HatPin, HatVendor};
+    pub use hc_sr04::HcSr04;
+    pub use hd44780::Hd44780;
+    pub use hw_rng::HwRng;
+    pub use i2c::{I2c, I2cAddress, I2cBackend};
+    pub use ir::{IrFrame, IrProtocol};
+    pub use ir_receiver::IrReceiver;
+    pub use ir_transmitter::IrTransmitter;
+    pub use keypad::{KeyEvent, Keypad};
+    pub use led::Led;
+    pub use mailbox::{FirmwareClock, Mailbox, ThrottleFlags};
+    pub use mcp23017::{Mcp23017, Mcp23017Pin};
+    pub use mcp3008::Mcp3008;
+    pub use mini_uart::MiniUart;
+    pub use multi_pwm::MultiPwm;
+    #[cfg(any(test, feature = "mock"))]
+    pub use mock::{MockGpio, MockOperation};
+    pub use one_wire::OneWire;
+    pub use pads::{PadBank, Pads};
+    pub use parallel_display::{ParallelBus, ParallelDisplay, ParallelTiming};
+    pub use pcf8574::{Pcf8574, Pcf8574Pin};
+    pub use pcm::{I2sConfig, Pcm, PcmDreq, PcmErrors};
+    pub use pin::{Alt, DropPolicy, Input, Output, OutputMode, Pin, Unconfigured};
+    pub use pin_config::{PinConfig, PinConfigSet};
+    pub use pin_group::PinGroup;
+    pub use pl011::{Parity, Pl011, RxErrors, StopBits};
+    pub use policy::{BoardState, WritePolicy};
+    pub use profile::{BoardProfile, BoardProfiles};
+    pub use pulse_meter::PulseMeter;
+    pub use pwm::{HwPwm, PwmChannel, PwmMode};
+    pub use region::MappedRegion;
+    pub use relay::{Relay, RelayBank};
+    pub use rgb_led::{Animation, Polarity, Rgb, RgbLed};
+    pub use rotary_encoder::{Direction, RotaryEncoder};
+    pub use servo::Servo;
+    pub use snapshot::{GpioSnapshot, PinSnapshot};
+    pub use soc::Soc;
+    pub use soft_i2c::SoftI2c;
+    pub use soft_pwm::{SoftPwm, SoftPwmChannel};
+    pub use soft_serial::SoftSerial;
+    pub use soft_spi::{BitOrder, SoftSpi, SpiMode};
+    pub use spi::{Spi, SpiBackend};
+    pub use split::{OwnedPin, Pins};
+    pub use square_wave::SquareWaveHandle;
+    pub use sr74hc165::Sr74hc165;
+    pub use sr74hc595::{ShiftRegisterPin, Sr74hc595};
+    pub use stepper::{StepMode, Stepper};
+    pub use system_timer::SystemTimer;
+    pub use timing::CalibrationData;
+    #[cfg(feature = "trace")]
+    pub use trace::{read_trace, RegisterAccess, TraceEntry};
+    pub use watchdog::Watchdog;
+    pub use watcher::Watcher;
+    pub use wave::{Pacing, Pulse, Wave, WaveEngine};
+    pub use ws2812::Ws2812;
+
+    // The helpers the drivers share.
+    use gpio::*;
+    use registers::GPIO_PIN_COUNT;
 }
@@ -0,0 +1,75 @@
+use crate::{check_pin, device_tree, Error, Pin, Unconfigured, GPIO, GPIO_PIN_COUNT};
+
+
+impl GPIO {
+
+    // Looked up once: the backend's own names first (the gpiochip line
+    // info), then the device tree's `gpio-line-names`.
+    fn line_names(&self) -> &[String] {
+        self.line_names.get_or_init(|| {
+            let tree = device_tree::gpio_line_names().unwrap_or_default();
+            (0..GPIO_PIN_COUNT)
+                .map(|pin| {
+                    self.driver.line_name(pin)
+                        .or_else(|| tree.get(pin as usize).cloned())
+                        .unwrap_or_default()
+                })
+                .collect()
+        })
+    }
+
+    /// The name the board gives `pin`, e.g. `"ID_SDA"` or `"GPIO18"`, if
+    /// the kernel or device tree has one.
+    pub fn line_name(&self, pin: u32) -> Result<Option<&str>, Error> {
+        check_pin(pin)?;
+        let name = self.line_names()[pin as usize].as_str();
+        Ok((!name.is_empty()).then_some(name))
+    }
+
+    /// The GPIO number of the line called `name`. A name of the form
+    /// `GPIO<n>` that no line has falls back to GPIO `n`, so code written
+    /// against one board's names still runs where there are none.
+    pub fn pin_number_by_name(&self, name: &str) -> Result<u32, Error> {
+        if let Some(pin) = self.line_names().iter().position(|line| line == name) {
+            return Ok(pin as u32);
+        }
+        match name.strip_prefix("GPIO").and_then(|number| number.parse().ok()) {
+            Some(pin) => check_pin(pin).map(|()| pin),
+            None => Err(Error::Other(format!("no GPIO line is named {:?}", name))),
+        }
+    }
+
+    /// A handle to the pin whose line is called `name`; see
+    /// `pin_number_by_name`.
+    pub fn by_name(&self, name: &str) -> Result<Pin<'_, Unconfigured>, Error> {
+        self.pin(self.pin_number_by_name(name)?)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pins_by_name() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let mut names = vec![String::new(); GPIO_PIN_COUNT as usize];
+        names[0] = "ID_SDA".to_string();
+        names[18] = "PWM0".to_string();
+        gpio.line_names.set(names).unwrap();
+
+        assert_eq!(gpio.line_name(0).unwrap(), Some("ID_SDA"));
+        assert_eq!(gpio.line_name(1).unwrap(), None);
+        assert!(gpio.line_name(GPIO_PIN_COUNT).is_err());
+        assert_eq!(gpio.pin_number_by_name("PWM0").ok(), Some(18));
+        assert_eq!(gpio.pin_number_by_name("GPIO21").ok(), Some(21));
+        assert!(gpio.pin_number_by_name("GPIO99").is_err());
+        assert!(gpio.pin_number_by_name("SDA1").is_err());
+
+        let pin = gpio.by_name("ID_SDA").unwrap();
+        assert_eq!(pin.number(), 0);
+        assert!(matches!(gpio.by_name("ID_SDA"), Err(Error::PinInUse(0))));
+        assert!(matches!(gpio.by_name("SDA1"), Err(Error::Other(message)) if message.contains("SDA1")));
+    }
+}
@@ -0,0 +1,263 @@
+use crate::{open_file, Error};
+
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+
+
+const DEV_VCIO_PATH: &str = "/dev/vcio";
+
+const TAG_GET_BOARD_MODEL: u32 = 0x10001;
+const TAG_GET_BOARD_REVISION: u32 = 0x10002;
+const TAG_GET_BOARD_SERIAL: u32 = 0x10004;
+const TAG_GET_TEMPERATURE: u32 = 0x30006;
+const TAG_GET_MAX_TEMPERATURE: u32 = 0x3000a;
+const TAG_GET_MAX_CLOCK_RATE: u32 = 0x30004;
+const TAG_GET_THROTTLED: u32 = 0x30046;
+const TAG_ALLOCATE_MEMORY: u32 = 0x3000c;
+const TAG_LOCK_MEMORY: u32 = 0x3000d;
+const TAG_UNLOCK_MEMORY: u32 = 0x3000e;
+const TAG_RELEASE_MEMORY: u32 = 0x3000f;
+const TAG_GET_CLOCK_RATE: u32 = 0x30002;
+
+// The SoC's only sensor, for TAG_GET_TEMPERATURE.
+const TEMPERATURE_ID_SOC: u32 = 0;
+
+const RESPONSE_SUCCESS: u32 = 0x8000_0000;
+
+// The ioctl takes the message buffer's address itself as its argument.
+nix::ioctl_readwrite_bad!(
+    mbox_property, nix::request_code_readwrite!(100, 0, std::mem::size_of::<*mut u8>()), u32);
+
+
+// The firmware wants the buffer 16-byte aligned.
+#[repr(C, align(16))]
+struct Message([u32; 16]);
+
+// A property message carrying one tag: the buffer size, a request code, the
+// tag, its value buffer size, its request/response code, the values, and
+// the end tag. The value buffer is sized for whichever of the arguments and
+// the `response_len` words of response is longer.
+fn property_message(tag: u32, args: &[u32], response_len: usize) -> Message {
+    let mut words = [0u32; 16];
+    let values = args.len().max(response_len).max(1);
+    words[0] = ((6 + values) * 4) as u32;
+    words[2] = tag;
+    words[3] = (values * 4) as u32;
+    words[5..5 + args.len()].copy_from_slice(args);
+    Message(words)
+}
+
+
+/// A clock the firmware manages, by its mailbox clock id.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum FirmwareClock {
+    Emmc = 1,
+    Uart = 2,
+    Arm = 3,
+    Core = 4,
+    V3d = 5,
+    H264 = 6,
+    Isp = 7,
+    Sdram = 8,
+    Pixel = 9,
+    Pwm = 10,
+    Hevc = 11,
+    Emmc2 = 12,
+}
+
+
+/// Why and whether the firmware has slowed the SoC down, as `vcgencmd
+/// get_throttled` reports it. Each condition is flagged both while it
+/// holds and, sticky until reboot, once it has ever held.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ThrottleFlags(pub u32);
+
+impl ThrottleFlags {
+
+    // The sticky flags are the current ones shifted up 16.
+    const OCCURRED_SHIFT: u32 = 16;
+    const UNDER_VOLTAGE: u32 = 1 << 0;
+    const FREQUENCY_CAPPED: u32 = 1 << 1;
+    const THROTTLED: u32 = 1 << 2;
+    const SOFT_TEMPERATURE_LIMIT: u32 = 1 << 3;
+
+    fn now(self, flag: u32) -> bool {
+        self.0 & flag != 0
+    }
+
+    fn occurred(self, flag: u32) -> bool {
+        self.0 & flag << Self::OCCURRED_SHIFT != 0
+    }
+
+    /// The supply is below about 4.63 V.
+    pub fn is_under_voltage(self) -> bool {
+        self.now(Self::UNDER_VOLTAGE)
+    }
+
+    pub fn is_frequency_capped(self) -> bool {
+        self.now(Self::FREQUENCY_CAPPED)
+    }
+
+    pub fn is_throttled(self) -> bool {
+        self.now(Self::THROTTLED)
+    }
+
+    /// The SoC is hot enough that the ARM clock has been lowered.
+    pub fn is_soft_temperature_limited(self) -> bool {
+        self.now(Self::SOFT_TEMPERATURE_LIMIT)
+    }
+
+    pub fn under_voltage_occurred(self) -> bool {
+        self.occurred(Self::UNDER_VOLTAGE)
+    }
+
+    pub fn frequency_capping_occurred(self) -> bool {
+        self.occurred(Self::FREQUENCY_CAPPED)
+    }
+
+    pub fn throttling_occurred(self) -> bool {
+        self.occurred(Self::THROTTLED)
+    }
+
+    pub fn soft_temperature_limit_occurred(self) -> bool {
+        self.occurred(Self::SOFT_TEMPERATURE_LIMIT)
+    }
+}
+
+
+/// The VideoCore firmware's property mailbox, through `/dev/vcio`: board
+/// identity, clock rates, the SoC's temperature and whether it's being
+/// throttled. Inside the crate it also allocates memory the DMA controller
+/// can reach.
+pub struct Mailbox {
+    file: File,
+}
+
+impl Mailbox {
+
+    pub fn open() -> Result<Self, Error> {
+        Ok(Self { file: open_file(DEV_VCIO_PATH)? })
+    }
+
+    fn property(&self, tag: u32, args: &[u32]) -> Result<u32, Error> {
+        Ok(self.property_response(tag, args, 1)?[0])
+    }
+
+    fn property_response(&self, tag: u32, args: &[u32], response_len: usize) -> Result<Vec<u32>, Error> {
+        let mut message = property_message(tag, args, response_len);
+        unsafe { mbox_property(self.file.as_raw_fd(), message.0.as_mut_ptr()) }
+            .map_err(|e| Error::from_nix(format!("mailbox property {:#x} failed", tag), e))?;
+        if message.0[1] != RESPONSE_SUCCESS {
+            return Err(Error::Other(format!("the firmware rejected mailbox property {:#x}", tag)));
+        }
+        Ok(message.0[5..5 + response_len].to_vec())
+    }
+
+    /// Returns a handle to `size` bytes of GPU memory.
+    pub(crate) fn allocate(&self, size: u32, align: u32, flags: u32) -> Result<u32, Error> {
+        match self.property(TAG_ALLOCATE_MEMORY, &[size, align, flags])? {
+            0 => Err(Error::Other(format!("the firmware couldn't allocate {} bytes", size))),
+            handle => Ok(handle),
+        }
+    }
+
+    /// Pins the memory in place and returns its bus address.
+    pub(crate) fn lock(&self, handle: u32) -> Result<u32, Error> {
+        self.property(TAG_LOCK_MEMORY, &[handle])
+    }
+
+    pub(crate) fn unlock(&self, handle: u32) -> Result<(), Error> {
+        self.property(TAG_UNLOCK_MEMORY, &[handle]).map(|_| ())
+    }
+
+    pub(crate) fn release(&self, handle: u32) -> Result<(), Error> {
+        self.property(TAG_RELEASE_MEMORY, &[handle]).map(|_| ())
+    }
+
+    fn clock_property(&self, tag: u32, clock: FirmwareClock) -> Result<u32, Error> {
+        match self.property_response(tag, &[clock as u32], 2)?[1] {
+            0 => Err(Error::Unsupported(format!("the firmware has no {:?} clock", clock))),
+            rate => Ok(rate),
+        }
+    }
+
+    /// The clock's current rate in Hz.
+    pub fn clock_rate(&self, clock: FirmwareClock) -> Result<u32, Error> {
+        self.clock_property(TAG_GET_CLOCK_RATE, clock)
+    }
+
+    /// The highest rate in Hz the firmware will run the clock at.
+    pub fn max_clock_rate(&self, clock: FirmwareClock) -> Result<u32, Error> {
+        self.clock_property(TAG_GET_MAX_CLOCK_RATE, clock)
+    }
+
+    /// The firmware's board model number, 0 on most boards.
+    pub fn board_model(&self) -> Result<u32, Error> {
+        self.property(TAG_GET_BOARD_MODEL, &[])
+    }
+
+    /// The revision code, as in `/proc/cpuinfo`; see `Board::from_revision`.
+    pub fn board_revision(&self) -> Result<u32, Error> {
+        self.property(TAG_GET_BOARD_REVISION, &[])
+    }
+
+    pub fn board_serial(&self) -> Result<u64, Error> {
+        let words = self.property_response(TAG_GET_BOARD_SERIAL, &[], 2)?;
+        Ok(join_words(words[0], words[1]))
+    }
+
+    /// The SoC's temperature in degrees Celsius.
+    pub fn temperature(&self) -> Result<f64, Error> {
+        let words = self.property_response(TAG_GET_TEMPERATURE, &[TEMPERATURE_ID_SOC], 2)?;
+        Ok(words[1] as f64 / 1000.0)
+    }
+
+    /// The temperature in degrees Celsius at which the firmware starts
+    /// throttling.
+    pub fn max_temperature(&self) -> Result<f64, Error> {
+        let words = self.property_response(TAG_GET_MAX_TEMPERATURE, &[TEMPERATURE_ID_SOC], 2)?;
+        Ok(words[1] as f64 / 1000.0)
+    }
+
+    pub fn throttled(&self) -> Result<ThrottleFlags, Error> {
+        // The argument asks the firmware not to clear the sticky flags.
+        self.property(TAG_GET_THROTTLED, &[0xffff]).map(ThrottleFlags)
+    }
+}
+
+// The firmware returns 64-bit values low word first.
+fn join_words(low: u32, high: u32) -> u64 {
+    (high as u64) << 32 | low as u64
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_property_message_layout() {
+        let message = property_message(TAG_ALLOCATE_MEMORY, &[4096, 4096, 4], 1);
+        assert_eq!(&message.0[..10], &[36, 0, 0x3000c, 12, 0, 4096, 4096, 4, 0, 0]);
+        let message = property_message(TAG_LOCK_MEMORY, &[7], 1);
+        assert_eq!(&message.0[..8], &[28, 0, 0x3000d, 4, 0, 7, 0, 0]);
+        let message = property_message(TAG_GET_CLOCK_RATE, &[2], 2);
+        assert_eq!(&message.0[..9], &[32, 0, 0x30002, 8, 0, 2, 0, 0, 0]);
+        assert_eq!(std::mem::align_of::<Message>(), 16);
+        let message = property_message(TAG_GET_BOARD_SERIAL, &[], 2);
+        assert_eq!(&message.0[..8], &[32, 0, 0x10004, 8, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_firmware_values() {
+        assert_eq!(join_words(0x89ab_cdef, 0x0123_4567), 0x0123_4567_89ab_cdef);
+
+        let flags = ThrottleFlags(0x50005);
+        assert!(flags.is_under_voltage() && flags.is_throttled());
+        assert!(!flags.is_frequency_capped() && !flags.is_soft_temperature_limited());
+        assert!(flags.under_voltage_occurred() && flags.throttling_occurred());
+        assert!(!flags.frequency_capping_occurred());
+        let flags = ThrottleFlags(0x80000);
+        assert!(flags.soft_temperature_limit_occurred() && !flags.is_soft_temperature_limited());
+    }
+}
@@ -0,0 +1,312 @@
+use crate::{Error, I2c, Input, Output, Pull, Unconfigured};
+
+use std::marker::PhantomData;
+use std::sync::Mutex;
+
+
+// Register addresses with IOCON.BANK = 0, where each A register is
+// followed by its B twin, so a two-byte access covers all 16 lines.
+const IODIR: u8 = 0x00;
+const GPINTEN: u8 = 0x04;
+const INTCON: u8 = 0x08;
+const IOCON: u8 = 0x0a;
+const GPPU: u8 = 0x0c;
+const INTF: u8 = 0x0e;
+const INTCAP: u8 = 0x10;
+const GPIO: u8 = 0x12;
+const OLAT: u8 = 0x14;
+
+// INTA and INTB both report changes on either port.
+const IOCON_MIRROR: u8 = 0x40;
+
+const LINES: u32 = 16;
+
+
+struct Inner {
+    i2c: I2c,
+    claimed: u16,
+}
+
+impl Inner {
+    fn read(&mut self, address: u8, register: u8) -> Result<u16, Error> {
+        let mut buffer = [0u8; 2];
+        self.i2c.write_read(address, &[register], &mut buffer)?;
+        Ok(u16::from_le_bytes(buffer))
+    }
+
+    fn write(&mut self, address: u8, register: u8, value: u16) -> Result<(), Error> {
+        let [a, b] = value.to_le_bytes();
+        self.i2c.write(address, &[register, a, b])
+    }
+
+    fn set_bit(&mut self, address: u8, register: u8, line: u32, set: bool) -> Result<(), Error> {
+        let value = self.read(address, register)?;
+        let mask = 1 << line;
+        self.write(address, register, if set { value | mask } else { value & !mask })
+    }
+}
+
+
+/// An MCP23017 16-line I2C GPIO expander. Lines 0-7 are GPA0-7 and 8-15
+/// are GPB0-7. Each line is claimed as an `Mcp23017Pin`, which has the
+/// same modes and methods as a native `Pin` and, with the `embedded-hal`
+/// feature, implements the same traits.
+///
+/// Interrupt-on-change is reported on INTA and INTB, which are mirrored so
+/// either one covers all 16 lines; wire one to a native input and wait for
+/// its falling edge, then call `take_interrupts`.
+pub struct Mcp23017 {
+    address: u8,
+    inner: Mutex<Inner>,
+}
+
+impl Mcp23017 {
+
+    /// The address with A0-A2 tied low.
+    pub const DEFAULT_ADDRESS: u8 = 0x20;
+
+    /// Every line starts as an input without a pull-up, as at power-on.
+    pub fn new(i2c: I2c, address: u8) -> Result<Self, Error> {
+        if !(0x20..=0x27).contains(&address) {
+            return Err(Error::Other(format!("{:#04x} isn't an MCP23017 address (0x20-0x27)", address)));
+        }
+        let mut inner = Inner { i2c, claimed: 0 };
+        inner.i2c.write(address, &[IOCON, IOCON_MIRROR])?;
+        inner.write(address, IODIR, 0xffff)?;
+        inner.write(address, GPPU, 0)?;
+        inner.write(address, GPINTEN, 0)?;
+        inner.write(address, INTCON, 0)?;
+        Ok(Self { address, inner: Mutex::new(inner) })
+    }
+
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    fn with<T>(&self, f: impl FnOnce(&mut Inner, u8) -> Result<T, Error>) -> Result<T, Error> {
+        f(&mut self.inner.lock().unwrap(), self.address)
+    }
+
+    /// Claims line `line`, failing with `Error::PinInUse` if it already
+    /// has a handle.
+    pub fn pin(&self, line: u32) -> Result<Mcp23017Pin<'_, Unconfigured>, Error> {
+        if line >= LINES {
+            return Err(Error::Other(format!("the MCP23017 has no line {}", line)));
+        }
+        let mut inner = self.inner.lock().unwrap();
+        if inner.claimed & (1 << line) != 0 {
+            return Err(Error::PinInUse(line));
+        }
+        inner.claimed |= 1 << line;
+        Ok(Mcp23017Pin { expander: self, line, mode: PhantomData })
+    }
+
+    /// The levels of all 16 lines, GPA0 in bit 0.
+    pub fn read_all(&self) -> Result<u16, Error> {
+        self.with(|inner, address| inner.read(address, GPIO))
+    }
+
+    /// Sets the output latches of all 16 lines, GPA0 in bit 0. Lines that
+    /// are inputs keep their bit for when they're made outputs.
+    pub fn write_all(&self, value: u16) -> Result<(), Error> {
+        self.with(|inner, address| inner.write(address, OLAT, value))
+    }
+
+    /// Which lines raised the interrupt since the last call, and their
+    /// levels when it was raised. Reading them clears the interrupt.
+    pub fn take_interrupts(&self) -> Result<(u16, u16), Error> {
+        self.with(|inner, address| {
+            let flags = inner.read(address, INTF)?;
+            Ok((flags, inner.read(address, INTCAP)?))
+        })
+    }
+}
+
+
+/// One line of an `Mcp23017`, in the same modes as a native `Pin`.
+pub struct Mcp23017Pin<'e, Mode> {
+    expander: &'e Mcp23017,
+    line: u32,
+    mode: PhantomData<Mode>,
+}
+
+impl<'e, Mode> Mcp23017Pin<'e, Mode> {
+
+    fn into_mode<NewMode>(self, input: bool) -> Result<Mcp23017Pin<'e, NewMode>, Error> {
+        let line = self.line;
+        self.expander.with(|inner, address| inner.set_bit(address, IODIR, line, input))?;
+        let pin = Mcp23017Pin { expander: self.expander, line, mode: PhantomData };
+        // The claim moves to the new handle.
+        std::mem::forget(self);
+        Ok(pin)
+    }
+
+    pub fn number(&self) -> u32 {
+        self.line
+    }
+
+    pub fn into_input(self) -> Result<Mcp23017Pin<'e, Input>, Error> {
+        self.into_mode(true)
+    }
+
+    pub fn into_output(self) -> Result<Mcp23017Pin<'e, Output>, Error> {
+        self.into_mode(false)
+    }
+
+    pub fn read(&self) -> Result<bool, Error> {
+        Ok(self.expander.read_all()? & (1 << self.line) != 0)
+    }
+}
+
+impl<'e, Mode> Drop for Mcp23017Pin<'e, Mode> {
+    fn drop(&mut self) {
+        self.expander.inner.lock().unwrap().claimed &= !(1 << self.line);
+    }
+}
+
+impl<'e> Mcp23017Pin<'e, Input> {
+
+    /// The MCP23017 only has pull-ups, of about 100 kΩ.
+    pub fn set_pull(&self, pull: Pull) -> Result<(), Error> {
+        let up = match pull {
+            Pull::None => false,
+            Pull::Up => true,
+            Pull::Down => return Err(Error::Unsupported("the MCP23017 has no pull-downs".to_string())),
+        };
+        let line = self.line;
+        self.expander.with(|inner, address| inner.set_bit(address, GPPU, line, up))
+    }
+
+    /// Enables or disables the interrupt on any change of this line.
+    pub fn set_interrupt_on_change(&self, enabled: bool) -> Result<(), Error> {
+        let line = self.line;
+        self.expander.with(|inner, address| inner.set_bit(address, GPINTEN, line, enabled))
+    }
+}
+
+impl<'e> Mcp23017Pin<'e, Output> {
+
+    fn drive(&self, high: bool) -> Result<(), Error> {
+        let line = self.line;
+        self.expander.with(|inner, address| inner.set_bit(address, OLAT, line, high))
+    }
+
+    pub fn set_high(&self) -> Result<(), Error> {
+        self.drive(true)
+    }
+
+    pub fn set_low(&self) -> Result<(), Error> {
+        self.drive(false)
+    }
+
+    pub fn toggle(&self) -> Result<(), Error> {
+        let line = self.line;
+        self.expander.with(|inner, address| {
+            let latch = inner.read(address, OLAT)?;
+            inner.write(address, OLAT, latch ^ (1 << line))
+        })
+    }
+
+    /// The level this line is being driven to.
+    pub fn is_set_high(&self) -> Result<bool, Error> {
+        let latch = self.expander.with(|inner, address| inner.read(address, OLAT))?;
+        Ok(latch & (1 << self.line) != 0)
+    }
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{I2cAddress, I2cBackend};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    // A register file with sequential addressing, as the chip has with
+    // IOCON.SEQOP clear. GPIO reads back OLAT on outputs and `pins` on
+    // inputs.
+    struct Chip {
+        registers: Registers,
+        pins: Arc<Mutex<u16>>,
+    }
+
+    type Registers = Arc<Mutex<[u8; 0x16]>>;
+
+    impl I2cBackend for Chip {
+        fn write(&mut self, address: I2cAddress, data: &[u8]) -> Result<(), Error> {
+            assert_eq!(address, I2cAddress::SevenBit(0x20));
+            let mut registers = self.registers.lock().unwrap();
+            for (offset, &byte) in data[1..].iter().enumerate() {
+                registers[data[0] as usize + offset] = byte;
+            }
+            Ok(())
+        }
+        fn read(&mut self, _address: I2cAddress, _buffer: &mut [u8]) -> Result<(), Error> {
+            Err(Error::Unsupported("the test chip only reads through write_read".to_string()))
+        }
+        fn write_read(&mut self, _address: I2cAddress, data: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+            let registers = self.registers.lock().unwrap();
+            let pins = self.pins.lock().unwrap().to_le_bytes();
+            for (offset, byte) in buffer.iter_mut().enumerate() {
+                let register = data[0] as usize + offset;
+                *byte = match register as u8 {
+                    GPIO | 0x13 => {
+                        let port = register - GPIO as usize;
+                        let direction = registers[IODIR as usize + port];
+                        (pins[port] & direction) | (registers[OLAT as usize + port] & !direction)
+                    }
+                    _ => registers[register],
+                };
+            }
+            Ok(())
+        }
+        fn set_frequency(&mut self, freq_hz: u32) -> Result<u32, Error> { Ok(freq_hz) }
+        fn set_timeout(&mut self, _timeout: Duration) -> Result<(), Error> { Ok(()) }
+    }
+
+    pub(crate) fn expander() -> (Mcp23017, Registers, Arc<Mutex<u16>>) {
+        let registers = Arc::new(Mutex::new([0u8; 0x16]));
+        let pins = Arc::new(Mutex::new(0));
+        let chip = Chip { registers: Arc::clone(&registers), pins: Arc::clone(&pins) };
+        (Mcp23017::new(I2c::custom(chip), 0x20).unwrap(), registers, pins)
+    }
+
+    #[test]
+    fn test_mcp23017_pins() {
+        let (expander, registers, pins) = expander();
+        assert_eq!(registers.lock().unwrap()[..2], [0xff, 0xff]);
+        assert_eq!(registers.lock().unwrap()[IOCON as usize], IOCON_MIRROR);
+        assert!(Mcp23017::new(I2c::custom(Chip { registers: Arc::clone(&registers), pins }), 0x30).is_err());
+
+        let led = expander.pin(9).unwrap().into_output().unwrap();
+        assert!(matches!(expander.pin(9), Err(Error::PinInUse(9))));
+        assert!(expander.pin(16).is_err());
+        assert_eq!(registers.lock().unwrap()[IODIR as usize + 1], 0xfd);
+        led.set_high().unwrap();
+        assert_eq!(registers.lock().unwrap()[OLAT as usize + 1], 0x02);
+        assert!(led.read().unwrap());
+        led.toggle().unwrap();
+        assert!(!led.is_set_high().unwrap());
+        drop(led);
+        assert!(expander.pin(9).is_ok());
+    }
+
+    #[test]
+    fn test_mcp23017_inputs_and_interrupts() {
+        let (expander, registers, pins) = expander();
+        let button = expander.pin(3).unwrap().into_input().unwrap();
+        button.set_pull(Pull::Up).unwrap();
+        assert!(button.set_pull(Pull::Down).is_err());
+        button.set_interrupt_on_change(true).unwrap();
+        assert_eq!(registers.lock().unwrap()[GPPU as usize], 0x08);
+        assert_eq!(registers.lock().unwrap()[GPINTEN as usize], 0x08);
+        assert!(!button.read().unwrap());
+        *pins.lock().unwrap() = 0x0008;
+        assert!(button.read().unwrap());
+        assert_eq!(expander.read_all().unwrap(), 0x0008);
+
+        registers.lock().unwrap()[INTF as usize] = 0x08;
+        registers.lock().unwrap()[INTCAP as usize] = 0x08;
+        assert_eq!(expander.take_interrupts().unwrap(), (0x0008, 0x0008));
+    }
+}
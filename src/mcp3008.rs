@@ -0,0 +1,118 @@
+use crate::{Error, Spi, SpiMode};
+
+
+// The fastest clock at 2.7 V; at 5 V the chip manages 3.6 MHz.
+const MAX_FREQUENCY_HZ: u32 = 1_350_000;
+
+const FULL_SCALE: f64 = 1024.0;
+
+
+/// An MCP3008 (eight channels) or MCP3004 (four) 10-bit ADC on an SPI
+/// chip select. Readings are 0 to 1023, with 1023 just under Vref.
+pub struct Mcp3008 {
+    spi: Spi,
+    channels: u8,
+}
+
+impl Mcp3008 {
+
+    /// Sets the bus to mode 0 and a clock the chip manages at any supply.
+    pub fn new(spi: Spi) -> Result<Self, Error> {
+        Self::with_channels(spi, 8)
+    }
+
+    pub fn mcp3004(spi: Spi) -> Result<Self, Error> {
+        Self::with_channels(spi, 4)
+    }
+
+    fn with_channels(mut spi: Spi, channels: u8) -> Result<Self, Error> {
+        spi.set_mode(SpiMode::Mode0)?;
+        spi.set_frequency(MAX_FREQUENCY_HZ)?;
+        Ok(Self { spi, channels })
+    }
+
+    pub fn channels(&self) -> u8 {
+        self.channels
+    }
+
+    fn check_channel(&self, channel: u8) -> Result<(), Error> {
+        if channel >= self.channels {
+            return Err(Error::Other(format!("there is no channel {} on a {}-channel ADC", channel, self.channels)));
+        }
+        Ok(())
+    }
+
+    // The start bit ends the first byte, so the result's two top bits end
+    // the second and the rest fill the third.
+    fn convert(&mut self, single_ended: bool, channel: u8) -> Result<u16, Error> {
+        let mut buffer = [0x01, (single_ended as u8) << 7 | channel << 4, 0];
+        self.spi.transfer(&mut buffer)?;
+        Ok(u16::from(buffer[1] & 0x03) << 8 | u16::from(buffer[2]))
+    }
+
+    /// Reads `channel` against ground.
+    pub fn read(&mut self, channel: u8) -> Result<u16, Error> {
+        self.check_channel(channel)?;
+        self.convert(true, channel)
+    }
+
+    /// Reads `positive` against `negative`, which must be the two channels
+    /// of one pair (0 and 1, 2 and 3, ...). Reads 0 whenever `positive` is
+    /// the lower of the two.
+    pub fn read_differential(&mut self, positive: u8, negative: u8) -> Result<u16, Error> {
+        self.check_channel(positive)?;
+        if positive / 2 != negative / 2 || positive == negative {
+            return Err(Error::Other(format!("channels {} and {} aren't a differential pair", positive, negative)));
+        }
+        // The pair's code is its positive input.
+        self.convert(false, positive)
+    }
+
+    /// Reads `channel` and scales it to volts given the reference voltage.
+    pub fn voltage(&mut self, channel: u8, vref: f64) -> Result<f64, Error> {
+        Ok(f64::from(self.read(channel)?) * vref / FULL_SCALE)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SpiBackend;
+    use std::sync::{Arc, Mutex};
+
+    // Answers every conversion with 0x2ab, recording the command bytes.
+    struct Adc(Arc<Mutex<Vec<[u8; 3]>>>);
+
+    impl SpiBackend for Adc {
+        fn transfer(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+            let mut command = [0u8; 3];
+            command.copy_from_slice(buffer);
+            self.0.lock().unwrap().push(command);
+            buffer.copy_from_slice(&[0xff, 0xfa, 0xab]);
+            Ok(())
+        }
+        fn set_mode(&mut self, mode: SpiMode) -> Result<(), Error> {
+            assert_eq!(mode, SpiMode::Mode0);
+            Ok(())
+        }
+        fn set_frequency(&mut self, freq_hz: u32) -> Result<u32, Error> { Ok(freq_hz) }
+        fn set_cs_active_high(&mut self, _active_high: bool) -> Result<(), Error> { Ok(()) }
+    }
+
+    #[test]
+    fn test_mcp3008_reads() {
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let mut adc = Mcp3008::new(Spi::custom(Adc(Arc::clone(&commands)))).unwrap();
+        assert_eq!(adc.read(5).unwrap(), 0x2ab);
+        assert_eq!(adc.read_differential(3, 2).unwrap(), 0x2ab);
+        assert!((adc.voltage(0, 3.3).unwrap() - 0x2ab as f64 * 3.3 / 1024.0).abs() < 1e-9);
+        assert_eq!(*commands.lock().unwrap(), vec![[0x01, 0xd0, 0], [0x01, 0x30, 0], [0x01, 0x80, 0]]);
+        assert!(adc.read(8).is_err());
+        assert!(adc.read_differential(1, 2).is_err());
+
+        let mut adc = Mcp3008::mcp3004(Spi::custom(Adc(commands))).unwrap();
+        assert_eq!(adc.channels(), 4);
+        assert!(adc.read(4).is_err());
+    }
+}
@@ -0,0 +1,255 @@
+use crate::auxiliary::{AuxPeripheral, AUX_BASE_OFFSET, AUX_ENABLES};
+use crate::region::MappedRegion;
+use crate::{detect_peripheral_base, Error, PinFunction, Soc, GPIO, DEV_MEM_PATH};
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+
+const AUX_BLOCK_LEN: usize = 0x6c;
+
+const AUX_MU_IO: usize = 0x40;
+const AUX_MU_IER: usize = 0x44;
+const AUX_MU_IIR: usize = 0x48;
+const AUX_MU_LCR: usize = 0x4c;
+const AUX_MU_MCR: usize = 0x50;
+const AUX_MU_LSR: usize = 0x54;
+const AUX_MU_CNTL: usize = 0x60;
+const AUX_MU_STAT: usize = 0x64;
+const AUX_MU_BAUD: usize = 0x68;
+
+const IIR_CLEAR_FIFOS: u32 = 0b11 << 1;
+const LCR_8_BIT: u32 = 0b11;
+const LSR_DATA_READY: u32 = 1;
+const LSR_TX_EMPTY: u32 = 1 << 5;
+const LSR_TX_IDLE: u32 = 1 << 6;
+const CNTL_RX_ENABLE: u32 = 1;
+const CNTL_TX_ENABLE: u32 = 1 << 1;
+const STAT_RX_LEVEL_SHIFT: u32 = 16;
+const STAT_TX_LEVEL_SHIFT: u32 = 24;
+const STAT_LEVEL_MASK: u32 = 0xf;
+
+// TXD1 and RXD1.
+const MINI_UART_PINS: [u32; 2] = [14, 15];
+
+
+// The baud rate is the core clock over 8 × (BAUD + 1).
+fn baud_register(core_clock_hz: u32, baud: u32) -> Result<u32, Error> {
+    if baud == 0 {
+        return Err(Error::Other("baud rate must be positive".to_string()));
+    }
+    let divisor = (core_clock_hz as f64 / (8.0 * baud as f64)).round() as u64;
+    if divisor == 0 || divisor > 0x10000 {
+        return Err(Error::Other(format!("{} baud is out of the mini UART's range", baud)));
+    }
+    Ok(divisor as u32 - 1)
+}
+
+
+/// The auxiliary mini UART on GPIO 14 (TX) and 15 (RX), 8N1 only. Its
+/// clock is the VPU core clock, so the baud rate only holds if the core
+/// clock is fixed, e.g. with `core_freq` or `enable_uart=1` in config.txt.
+/// Both FIFOs are eight bytes deep.
+pub struct MiniUart {
+    region: MappedRegion,
+    core_clock_hz: u32,
+    baud: u32,
+    read_timeout: Option<Duration>,
+}
+
+impl MiniUart {
+
+    /// Enables the mini UART at `baud` and switches GPIO 14 and 15 to it.
+    pub fn new(gpio: &GPIO, baud: u32) -> Result<Self, Error> {
+        let base = detect_peripheral_base()?;
+        let uart = Self::open(DEV_MEM_PATH, base + AUX_BASE_OFFSET, gpio.soc(), baud)?;
+        gpio.switch_to_alt(&MINI_UART_PINS, PinFunction::Alt5)?;
+        Ok(uart)
+    }
+
+    pub(crate) fn open(path: impl AsRef<Path>, phys_base: i64, soc: Soc, baud: u32) -> Result<Self, Error> {
+        let core_clock_hz = if soc == Soc::Bcm2711 { 500_000_000 } else { 250_000_000 };
+        let baud_reg = baud_register(core_clock_hz, baud)?;
+        let uart = Self {
+            region: MappedRegion::open(path.as_ref(), phys_base, AUX_BLOCK_LEN)?,
+            core_clock_hz,
+            baud: core_clock_hz / (8 * (baud_reg + 1)),
+            read_timeout: None,
+        };
+        // AUX_ENABLES also holds the two SPI controllers' enables; see
+        // `Aux`.
+        let enables = uart.region.read_reg(AUX_ENABLES)?;
+        uart.region.write_reg(AUX_ENABLES, enables | AuxPeripheral::MiniUart.mask())?;
+        uart.region.write_reg(AUX_MU_CNTL, 0)?;
+        uart.region.write_reg(AUX_MU_IER, 0)?;
+        uart.region.write_reg(AUX_MU_LCR, LCR_8_BIT)?;
+        uart.region.write_reg(AUX_MU_MCR, 0)?;
+        uart.region.write_reg(AUX_MU_IIR, IIR_CLEAR_FIFOS)?;
+        uart.region.write_reg(AUX_MU_BAUD, baud_reg)?;
+        uart.region.write_reg(AUX_MU_CNTL, CNTL_RX_ENABLE | CNTL_TX_ENABLE)?;
+        Ok(uart)
+    }
+
+    /// Sets the baud rate as close to `baud` as the divider allows and
+    /// returns the rate achieved.
+    pub fn set_baud_rate(&mut self, baud: u32) -> Result<u32, Error> {
+        let baud_reg = baud_register(self.core_clock_hz, baud)?;
+        self.region.write_reg(AUX_MU_BAUD, baud_reg)?;
+        self.baud = self.core_clock_hz / (8 * (baud_reg + 1));
+        Ok(self.baud)
+    }
+
+    pub fn baud_rate(&self) -> u32 {
+        self.baud
+    }
+
+    /// How long `read` waits for each byte; `None` waits forever.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout
+    }
+
+    fn line_status(&self) -> Result<u32, Error> {
+        self.region.read_reg(AUX_MU_LSR)
+    }
+
+    /// Whether a received byte is waiting.
+    pub fn can_read(&self) -> Result<bool, Error> {
+        Ok(self.line_status()? & LSR_DATA_READY != 0)
+    }
+
+    /// Whether the transmit FIFO has room for a byte.
+    pub fn can_write(&self) -> Result<bool, Error> {
+        Ok(self.line_status()? & LSR_TX_EMPTY != 0)
+    }
+
+    /// Whether everything written has left the transmitter.
+    pub fn is_tx_idle(&self) -> Result<bool, Error> {
+        Ok(self.line_status()? & LSR_TX_IDLE != 0)
+    }
+
+    /// The number of bytes in the receive FIFO.
+    pub fn rx_fifo_level(&self) -> Result<usize, Error> {
+        Ok((self.region.read_reg(AUX_MU_STAT)? >> STAT_RX_LEVEL_SHIFT & STAT_LEVEL_MASK) as usize)
+    }
+
+    /// The number of bytes in the transmit FIFO.
+    pub fn tx_fifo_level(&self) -> Result<usize, Error> {
+        Ok((self.region.read_reg(AUX_MU_STAT)? >> STAT_TX_LEVEL_SHIFT & STAT_LEVEL_MASK) as usize)
+    }
+
+    /// Queues as much of `data` as fits in the transmit FIFO without
+    /// waiting and returns how many bytes that was.
+    pub fn try_write(&mut self, data: &[u8]) -> Result<usize, Error> {
+        let mut written = 0;
+        while written < data.len() && self.can_write()? {
+            self.region.write_reg(AUX_MU_IO, data[written] as u32)?;
+            written += 1;
+        }
+        Ok(written)
+    }
+
+    /// Writes all of `data`, waiting for room in the FIFO as needed. The
+    /// last bytes may still be going out when this returns; see `flush`.
+    pub fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        let mut written = 0;
+        while written < data.len() {
+            let queued = self.try_write(&data[written..])?;
+            if queued == 0 {
+                std::hint::spin_loop();
+            }
+            written += queued;
+        }
+        Ok(())
+    }
+
+    /// Waits until the transmitter has sent everything written.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        while !self.is_tx_idle()? {
+            std::hint::spin_loop();
+        }
+        Ok(())
+    }
+
+    /// Takes whatever received bytes are waiting, up to the length of
+    /// `buffer`, and returns how many there were.
+    pub fn try_read(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        let mut read = 0;
+        while read < buffer.len() && self.can_read()? {
+            buffer[read] = self.region.read_reg(AUX_MU_IO)? as u8;
+            read += 1;
+        }
+        Ok(read)
+    }
+
+    /// Fills `buffer`, waiting for each byte. Fails with `Error::Timeout`
+    /// if a byte takes longer than the read timeout to arrive; the bytes
+    /// read by then are kept in `buffer`.
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        let mut read = 0;
+        let mut progress = Instant::now();
+        while read < buffer.len() {
+            let received = self.try_read(&mut buffer[read..])?;
+            if received > 0 {
+                read += received;
+                progress = Instant::now();
+                continue;
+            }
+            if let Some(timeout) = self.read_timeout {
+                if progress.elapsed() > timeout {
+                    return Err(Error::Timeout(format!("mini UART read {} of {} bytes", read, buffer.len())));
+                }
+            }
+            std::hint::spin_loop();
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_baud_register() {
+        assert_eq!(baud_register(250_000_000, 115_200).ok(), Some(270));
+        assert_eq!(baud_register(500_000_000, 9_600).ok(), Some(6509));
+        assert!(baud_register(250_000_000, 300).is_err());
+        assert!(baud_register(250_000_000, 0).is_err());
+    }
+
+    #[test]
+    fn test_mini_uart() {
+        let path = std::env::temp_dir().join(format!("rustberrypi-mini-uart-{}", std::process::id()));
+        std::fs::write(&path, vec![0u8; 4096]).unwrap();
+        let mut uart = MiniUart::open(&path, 0, Soc::Bcm2837, 115_200).ok().unwrap();
+        assert_eq!(uart.region.read_reg(AUX_ENABLES).ok(), Some(AuxPeripheral::MiniUart.mask()));
+        assert_eq!(uart.region.read_reg(AUX_MU_CNTL).ok(), Some(CNTL_RX_ENABLE | CNTL_TX_ENABLE));
+        assert_eq!(uart.region.read_reg(AUX_MU_BAUD).ok(), Some(270));
+        assert_eq!(uart.baud_rate(), 115_313);
+        assert_eq!(uart.set_baud_rate(9_600).ok(), Some(9_600));
+
+        // Nothing received and a full transmit FIFO.
+        assert_eq!(uart.try_write(b"hi").ok(), Some(0));
+        assert_eq!(uart.try_read(&mut [0; 4]).ok(), Some(0));
+        uart.set_read_timeout(Some(Duration::from_millis(1)));
+        assert!(matches!(uart.read(&mut [0; 1]), Err(Error::Timeout(_))));
+
+        uart.region.write_reg(AUX_MU_LSR, LSR_DATA_READY | LSR_TX_EMPTY | LSR_TX_IDLE).unwrap();
+        uart.region.write_reg(AUX_MU_STAT, 3 << STAT_RX_LEVEL_SHIFT | 5 << STAT_TX_LEVEL_SHIFT).unwrap();
+        uart.write(b"hi").unwrap();
+        uart.flush().unwrap();
+        // The IO register reads back the last byte written to it.
+        let mut buffer = [0u8; 2];
+        uart.read(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"ii");
+        assert_eq!(uart.rx_fifo_level().ok(), Some(3));
+        assert_eq!(uart.tx_fifo_level().ok(), Some(5));
+        drop(uart);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
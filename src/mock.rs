@@ -0,0 +1,478 @@
+use crate::edge::{instant_from_monotonic, monotonic_ns};
+use crate::gpiochip::edge_event_bytes;
+use crate::{EdgeKind, Error, GpioBackend, GpioBuilder, PinEvent, PinFunction, Pull, Trigger, GPIO};
+#[cfg(feature = "trace")]
+use crate::timing::wait_until;
+#[cfg(feature = "trace")]
+use crate::{Register, RegisterAccess, TraceEntry, GPIO_PIN_COUNT};
+
+use nix::fcntl::OFlag;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::io::FromRawFd;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+
+/// A call made on a `MockGpio` by the code under test.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MockOperation {
+    SetFunction { pin: u32, function: PinFunction },
+    SetPull { pin: u32, pull: Pull },
+    Write { pin: u32, level: bool },
+}
+
+
+#[derive(Copy, Clone)]
+struct MockPin {
+    function: PinFunction,
+    pull: Pull,
+    latch: bool,
+    input: Option<bool>,
+}
+
+impl Default for MockPin {
+    fn default() -> Self {
+        Self { function: PinFunction::Input, pull: Pull::None, latch: false, input: None }
+    }
+}
+
+impl MockPin {
+
+    // An output drives its latch onto the line. Otherwise the line is
+    // whatever the test set, or where the pull leaves it.
+    fn level(&self) -> bool {
+        match (self.function, self.input) {
+            (PinFunction::Output, _) => self.latch,
+            (_, Some(level)) => level,
+            (_, None) => self.pull == Pull::Up,
+        }
+    }
+}
+
+fn fires(trigger: Trigger, edge: EdgeKind) -> bool {
+    matches!((trigger, edge), (Trigger::Both, _) | (Trigger::Rising, EdgeKind::Rising) | (Trigger::Falling, EdgeKind::Falling))
+}
+
+#[derive(Default)]
+struct State {
+    pins: HashMap<u32, MockPin>,
+    operations: Vec<MockOperation>,
+    edges: Vec<PinEvent>,
+    // The write ends of the pipes handed out by `edge_event_file`.
+    watchers: Vec<(u32, Trigger, File)>,
+    // (from, to): `to` reads what `from` drives or pulls.
+    jumpers: Vec<(u32, u32)>,
+}
+
+impl State {
+
+    fn pin(&self, pin: u32) -> MockPin {
+        self.pins.get(&pin).copied().unwrap_or_default()
+    }
+
+    // Applies `change` to `pin`, recording an edge on it and on any pin
+    // jumpered from it if their lines moved.
+    fn update(&mut self, pin: u32, change: impl FnOnce(&mut MockPin)) -> bool {
+        let entry = self.pins.entry(pin).or_default();
+        let before = entry.level();
+        change(entry);
+        self.settle(pin, before)
+    }
+
+    // Follows a change to `pin`, whose level was `before`.
+    fn settle(&mut self, pin: u32, before: bool) -> bool {
+        let entry = self.pin(pin);
+        let after = entry.level();
+        let driven = match (entry.function, entry.pull) {
+            (PinFunction::Output, _) => Some(entry.latch),
+            (_, Pull::None) => None,
+            (_, pull) => Some(pull == Pull::Up),
+        };
+        let targets: Vec<u32> = self.jumpers.iter().filter(|&&(from, _)| from == pin).map(|&(_, to)| to).collect();
+        let mut moved = false;
+        for target in targets {
+            let entry = self.pins.entry(target).or_default();
+            // Also ends the walk round a jumper wired both ways.
+            if entry.input == driven {
+                continue;
+            }
+            let before = entry.level();
+            entry.input = driven;
+            moved |= self.settle(target, before);
+        }
+        if before == after {
+            return moved;
+        }
+        let edge = if after { EdgeKind::Rising } else { EdgeKind::Falling };
+        // One timestamp for every API the edge reaches.
+        let timestamp_ns = monotonic_ns().unwrap_or(0);
+        let event = PinEvent { pin, edge, timestamp: instant_from_monotonic(timestamp_ns) };
+        let bytes = edge_event_bytes(pin, edge, timestamp_ns);
+        // Pipe writes this small are atomic. A full pipe drops the event,
+        // as a full kernel buffer would; a closed one means its file was
+        // dropped.
+        self.watchers.retain_mut(|(watched, trigger, file)| {
+            if *watched != pin || !fires(*trigger, edge) {
+                return true;
+            }
+            match file.write_all(&bytes) {
+                Err(e) => e.kind() == std::io::ErrorKind::WouldBlock,
+                Ok(()) => true,
+            }
+        });
+        self.edges.push(event);
+        true
+    }
+}
+
+
+/// A `GpioBackend` that keeps every pin in memory, for testing code that
+/// drives GPIO without a Pi. Input levels are set by the test, every
+/// function, pull and level change is recorded, and edges are reported
+/// to `wait_for_edge`, `on_edge` and `edge_events` like a line backend's.
+///
+/// Clones share the same pins, so a test keeps one to script inputs and
+/// inspect what happened while the `GPIO` from `gpio` owns another. An
+/// unconfigured pin is an input with no pull, reading low.
+#[derive(Clone, Default)]
+pub struct MockGpio {
+    state: Arc<(Mutex<State>, Condvar)>,
+}
+
+impl MockGpio {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A `GPIO` backed by this mock.
+    pub fn gpio(&self) -> Result<GPIO, Error> {
+        GpioBuilder::new().custom(self.clone()).build()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.0.lock().unwrap()
+    }
+
+    /// Sets the level an external circuit puts on `pin`. It reads back
+    /// whenever the pin isn't an output.
+    pub fn set_input(&self, pin: u32, level: bool) {
+        if self.lock().update(pin, |entry| entry.input = Some(level)) {
+            self.state.1.notify_all();
+        }
+    }
+
+    /// Stops driving `pin` from outside, leaving it to its pull.
+    pub fn release_input(&self, pin: u32) {
+        if self.lock().update(pin, |entry| entry.input = None) {
+            self.state.1.notify_all();
+        }
+    }
+
+    /// Wires `from` to `to`, as a jumper between two header pins would:
+    /// while `from` is an output, `to` reads the level it drives, while
+    /// it's a pulled input its pull moves `to` too, and otherwise `to` is
+    /// left to its own pull. Levels set on `to` with `set_input` are
+    /// replaced whenever `from` changes. Connect both ways for a jumper
+    /// either end may drive.
+    pub fn connect(&self, from: u32, to: u32) {
+        assert_ne!(from, to, "a pin can't be jumpered to itself");
+        let mut state = self.lock();
+        state.jumpers.push((from, to));
+        // Applies the jumper to `to` without changing `from`.
+        if state.update(from, |_| ()) {
+            self.state.1.notify_all();
+        }
+    }
+
+    /// Plays `steps` on `pin` from a background thread: each level is set
+    /// once its delay has passed since the one before.
+    pub fn play_inputs(&self, pin: u32, steps: &[(Duration, bool)]) -> JoinHandle<()> {
+        let mock = self.clone();
+        let steps = steps.to_vec();
+        std::thread::spawn(move || {
+            for (delay, level) in steps {
+                std::thread::sleep(delay);
+                mock.set_input(pin, level);
+            }
+        })
+    }
+
+    /// The level on `pin` as the code under test would read it.
+    pub fn level(&self, pin: u32) -> bool {
+        self.lock().pin(pin).level()
+    }
+
+    /// The level `pin` is driving, or `None` if it isn't an output.
+    pub fn output(&self, pin: u32) -> Option<bool> {
+        let entry = self.lock().pin(pin);
+        (entry.function == PinFunction::Output).then_some(entry.latch)
+    }
+
+    /// Every operation made so far, oldest first.
+    pub fn operations(&self) -> Vec<MockOperation> {
+        self.lock().operations.clone()
+    }
+
+    /// Returns the operations made so far and forgets them.
+    pub fn take_operations(&self) -> Vec<MockOperation> {
+        std::mem::take(&mut self.lock().operations)
+    }
+
+    /// The levels written to `pin` so far, oldest first.
+    pub fn writes(&self, pin: u32) -> Vec<bool> {
+        self.lock().operations.iter().filter_map(|operation| match *operation {
+            MockOperation::Write { pin: written, level } if written == pin => Some(level),
+            _ => None,
+        }).collect()
+    }
+
+    /// Every edge seen on `pin` so far, whatever caused it.
+    pub fn edges(&self, pin: u32) -> Vec<PinEvent> {
+        self.lock().edges.iter().filter(|event| event.pin == pin).copied().collect()
+    }
+
+    /// Plays a register trace back as pin operations: function, BCM2711
+    /// pull and GPSET/GPCLR writes are made as the code under test would
+    /// have made them, and each GPLEV read sets the levels it saw on pins
+    /// that aren't outputs. Other registers are skipped. With `timed`, each
+    /// entry waits for its timestamp, measured from the call.
+    #[cfg(feature = "trace")]
+    pub fn replay(&self, trace: &[TraceEntry], timed: bool) -> Result<(), Error> {
+        let started = Instant::now();
+        for entry in trace {
+            if timed {
+                wait_until(started + entry.timestamp);
+            }
+            self.replay_entry(entry)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "trace")]
+    fn replay_entry(&self, entry: &TraceEntry) -> Result<(), Error> {
+        let word = |register: Register, count: u32| {
+            let start = register as usize;
+            let words = GPIO_PIN_COUNT.div_ceil(count) as usize;
+            (start..start + words * 4).contains(&entry.offset)
+                .then(|| ((entry.offset - start) as u32 / 4) * count)
+        };
+        let pins = |first: u32, count: u32| first..(first + count).min(GPIO_PIN_COUNT);
+        match entry.access {
+            RegisterAccess::Write => {
+                if let Some(first) = word(Register::GPFSEL, 10) {
+                    for pin in pins(first, 10) {
+                        let function = PinFunction::from_bits(pin, entry.value);
+                        if self.function(pin)? != function {
+                            self.set_function(pin, function)?;
+                        }
+                    }
+                } else if let Some(first) = word(Register::GPPUPPDNCNTRL, 16) {
+                    for pin in pins(first, 16) {
+                        let pull = Pull::from_bits(pin, entry.value);
+                        if self.pull(pin)? != pull {
+                            self.set_pull(pin, pull)?;
+                        }
+                    }
+                } else if let Some((first, level)) = word(Register::GPSET, 32).map(|first| (first, true))
+                    .or_else(|| word(Register::GPCLR, 32).map(|first| (first, false))) {
+                    for pin in pins(first, 32).filter(|pin| entry.value & (1 << (pin - first)) != 0) {
+                        self.write(pin, level)?;
+                    }
+                }
+            }
+            RegisterAccess::Read => {
+                if let Some(first) = word(Register::GPLEV, 32) {
+                    for pin in pins(first, 32) {
+                        let level = entry.value & (1 << (pin - first)) != 0;
+                        if self.output(pin).is_none() && self.level(pin) != level {
+                            self.set_input(pin, level);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl GpioBackend for MockGpio {
+
+    fn function(&self, pin: u32) -> Result<PinFunction, Error> {
+        Ok(self.lock().pin(pin).function)
+    }
+
+    fn set_function(&self, pin: u32, function: PinFunction) -> Result<(), Error> {
+        let mut state = self.lock();
+        state.operations.push(MockOperation::SetFunction { pin, function });
+        state.watchers.retain(|(watched, _, _)| *watched != pin);
+        if state.update(pin, |entry| entry.function = function) {
+            self.state.1.notify_all();
+        }
+        Ok(())
+    }
+
+    fn pull(&self, pin: u32) -> Result<Pull, Error> {
+        Ok(self.lock().pin(pin).pull)
+    }
+
+    fn set_pull(&self, pin: u32, pull: Pull) -> Result<(), Error> {
+        let mut state = self.lock();
+        state.operations.push(MockOperation::SetPull { pin, pull });
+        if state.update(pin, |entry| entry.pull = pull) {
+            self.state.1.notify_all();
+        }
+        Ok(())
+    }
+
+    fn write(&self, pin: u32, level: bool) -> Result<(), Error> {
+        let mut state = self.lock();
+        state.operations.push(MockOperation::Write { pin, level });
+        if state.update(pin, |entry| entry.latch = level) {
+            self.state.1.notify_all();
+        }
+        Ok(())
+    }
+
+    fn read(&self, pin: u32) -> Result<bool, Error> {
+        Ok(self.level(pin))
+    }
+
+    fn wait_for_edge(&self, pin: u32, trigger: Trigger, timeout: Option<Duration>) -> Result<PinEvent, Error> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut state = self.lock();
+        // Only edges after the call count.
+        let mut seen = state.edges.len();
+        loop {
+            if let Some(event) = state.edges[seen..].iter().find(|event| event.pin == pin && fires(trigger, event.edge)) {
+                return Ok(*event);
+            }
+            seen = state.edges.len();
+            state = match deadline {
+                None => self.state.1.wait(state).unwrap(),
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return Err(Error::Timeout(format!("no edge on pin {} within {:?}", pin, timeout.unwrap())));
+                    }
+                    self.state.1.wait_timeout(state, remaining).unwrap().0
+                }
+            };
+        }
+    }
+
+    fn edge_event_file(&self, pin: u32, trigger: Trigger) -> Result<File, Error> {
+        let (read, write) = nix::unistd::pipe2(OFlag::O_NONBLOCK | OFlag::O_CLOEXEC)
+            .map_err(|e| Error::from_nix(format!("failed to create an event pipe for pin {}", pin), e))?;
+        let (read, write) = unsafe { (File::from_raw_fd(read), File::from_raw_fd(write)) };
+        self.lock().watchers.push((pin, trigger, write));
+        Ok(read)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Counter;
+
+    #[test]
+    fn test_mock_gpio_records_operations() {
+        let mock = MockGpio::new();
+        let gpio = mock.gpio().unwrap();
+        let led = gpio.pin(17).unwrap().into_output().unwrap();
+        led.set_high().unwrap();
+        assert_eq!(mock.output(17), Some(true));
+        assert_eq!(mock.output(18), None);
+        let operations = mock.take_operations();
+        assert!(operations.contains(&MockOperation::SetFunction { pin: 17, function: PinFunction::Output }));
+        assert_eq!(operations.last(), Some(&MockOperation::Write { pin: 17, level: true }));
+        assert!(mock.operations().is_empty());
+
+        let button = gpio.pin(4).unwrap().into_input().unwrap();
+        button.set_pull(Pull::Up).unwrap();
+        assert!(button.read().unwrap());
+        mock.set_input(4, false);
+        assert!(!button.read().unwrap());
+        mock.release_input(4);
+        assert!(button.read().unwrap());
+        assert_eq!(mock.edges(4).iter().map(|event| event.edge).collect::<Vec<_>>(),
+            vec![EdgeKind::Rising, EdgeKind::Falling, EdgeKind::Rising]);
+    }
+
+    #[test]
+    fn test_mock_gpio_edges() {
+        let mock = MockGpio::new();
+        let gpio = mock.gpio().unwrap();
+        let pin = gpio.pin(22).unwrap().into_input().unwrap();
+        let player = mock.play_inputs(22, &[(Duration::from_millis(20), true), (Duration::from_millis(20), false)]);
+        assert_eq!(pin.wait_for_edge(Trigger::Falling, Some(Duration::from_secs(5))).unwrap().edge, EdgeKind::Falling);
+        player.join().unwrap();
+        assert!(matches!(pin.wait_for_edge(Trigger::Both, Some(Duration::from_millis(10))), Err(Error::Timeout(_))));
+
+        let counter = Counter::new(pin, Trigger::Rising).unwrap();
+        for _ in 0..3 {
+            mock.set_input(22, true);
+            mock.set_input(22, false);
+        }
+        let start = Instant::now();
+        while counter.count() < 3 && start.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(counter.count(), 3);
+    }
+
+    #[test]
+    fn test_mock_gpio_jumper() {
+        let mock = MockGpio::new();
+        let gpio = mock.gpio().unwrap();
+        mock.connect(5, 6);
+        gpio.set_pull(6, Pull::Up).unwrap();
+        assert!(gpio.read(6).unwrap());
+        gpio.set_function(5, PinFunction::Output).unwrap();
+        assert!(!gpio.read(6).unwrap());
+        gpio.set_high(5).unwrap();
+        assert!(gpio.read(6).unwrap());
+        assert_eq!(mock.edges(6).iter().map(|event| event.edge).collect::<Vec<_>>(), [EdgeKind::Rising, EdgeKind::Falling, EdgeKind::Rising]);
+
+        gpio.set_low(5).unwrap();
+        gpio.set_function(5, PinFunction::Input).unwrap();
+        assert!(gpio.read(6).unwrap());
+
+        mock.connect(6, 5);
+        gpio.set_pull(6, Pull::None).unwrap();
+        gpio.set_pull(5, Pull::Down).unwrap();
+        assert!(!gpio.read(6).unwrap());
+        gpio.set_function(6, PinFunction::Output).unwrap();
+        gpio.set_high(6).unwrap();
+        assert!(gpio.read(5).unwrap());
+    }
+
+    #[cfg(feature = "trace")]
+    #[test]
+    fn test_replay_register_trace() {
+        let recorded = GPIO::open_for_testing_on(Vec::new());
+        recorded.start_trace(64);
+        recorded.set_function(17, PinFunction::Output).unwrap();
+        recorded.set_pull(4, Pull::Up).unwrap();
+        recorded.set_high(17).unwrap();
+        recorded.set_low(17).unwrap();
+        unsafe { recorded.write_raw(0x38, 1 << 2) };
+        assert!(recorded.read(34).unwrap());
+        let trace = recorded.stop_trace().unwrap();
+
+        let mock = MockGpio::new();
+        mock.replay(&trace, false).unwrap();
+        assert_eq!(mock.operations(), vec![
+            MockOperation::SetFunction { pin: 17, function: PinFunction::Output },
+            MockOperation::SetPull { pin: 4, pull: Pull::Up },
+            MockOperation::Write { pin: 17, level: true },
+            MockOperation::Write { pin: 17, level: false },
+        ]);
+        assert!(mock.level(34) && mock.level(4) && !mock.level(5));
+    }
+}
@@ -0,0 +1,175 @@
+use crate::{check_pin, Error, PinFunction, Wave, WaveEngine, GPIO};
+
+use std::collections::BTreeMap;
+
+
+// The set and clear masks of each step of the period, for pins with the
+// given pulse widths. Every pin rises at the start of the period, unless
+// its width is 0, and falls at the step its width ends on, unless that's
+// the whole period.
+fn step_masks(widths: &BTreeMap<u32, u32>, steps: u32, step_us: u32) -> Vec<(u64, u64)> {
+    let mut masks = vec![(0, 0); steps as usize];
+    for (&pin, &width_us) in widths {
+        let fall = (width_us + step_us / 2) / step_us;
+        if fall > 0 {
+            masks[0].0 |= 1 << pin;
+        }
+        if let Some(step) = masks.get_mut(fall as usize) {
+            step.1 |= 1 << pin;
+        }
+    }
+    masks
+}
+
+
+/// PWM on any number of pins at once, timed by the DMA controller through
+/// a `WaveEngine`, as pigpio does, for driving a robot's worth of servos
+/// without a hardware channel each. Every pin shares one period, 20 ms by
+/// default, and pulse widths are rounded to a step, 10 µs by default.
+/// Widths change in place in the DMA controller's memory, so the output
+/// never stops; the pass under way when a width changes may see the old
+/// width or the new.
+///
+/// The engine plays for as long as the `MultiPwm` lives, with the same
+/// requirements. When it's dropped, the pins are driven low.
+pub struct MultiPwm<'a> {
+    gpio: &'a GPIO,
+    engine: WaveEngine<'a>,
+    period_us: u32,
+    step_us: u32,
+    widths: BTreeMap<u32, u32>,
+    masks: Vec<(u64, u64)>,
+}
+
+impl<'a> MultiPwm<'a> {
+
+    pub const DEFAULT_PERIOD_US: u32 = 20_000;
+    pub const DEFAULT_STEP_US: u32 = 10;
+
+    pub fn new(gpio: &'a GPIO) -> Result<Self, Error> {
+        Self::with_engine(gpio, WaveEngine::new(gpio)?, Self::DEFAULT_PERIOD_US, Self::DEFAULT_STEP_US)
+    }
+
+    /// Starts `engine` on a period of `period_us`, which must be a whole
+    /// number of `step_us` steps. Shorter steps give finer widths, at the
+    /// cost of more DMA memory.
+    pub fn with_engine(gpio: &'a GPIO, mut engine: WaveEngine<'a>, period_us: u32, step_us: u32)
+        -> Result<Self, Error>
+    {
+        if step_us == 0 || period_us < 2 * step_us || !period_us.is_multiple_of(step_us) {
+            return Err(Error::Other(format!(
+                "a {} us period isn't a whole number of {} us steps", period_us, step_us)));
+        }
+        let steps = period_us / step_us;
+        let wave = (0..steps).fold(Wave::new(), |wave, _| wave.pulse(0, 0, step_us)).with_fixed_masks();
+        engine.play(&wave, true)?;
+        Ok(Self { gpio, engine, period_us, step_us, widths: BTreeMap::new(), masks: vec![(0, 0); steps as usize] })
+    }
+
+    pub fn period_us(&self) -> u32 {
+        self.period_us
+    }
+
+    pub fn step_us(&self) -> u32 {
+        self.step_us
+    }
+
+    pub fn frequency(&self) -> f64 {
+        1e6 / self.period_us as f64
+    }
+
+    /// The pins being driven, in order.
+    pub fn pins(&self) -> Vec<u32> {
+        self.widths.keys().copied().collect()
+    }
+
+    // Writes the steps whose masks differ from those playing. A pin moving
+    // its fall is first cleared at both steps, so the pass under way can
+    // only cut a pulse short, never leave it high for a whole period.
+    fn update(&mut self, widths: BTreeMap<u32, u32>) -> Result<(), Error> {
+        let masks = step_masks(&widths, self.masks.len() as u32, self.step_us);
+        for (index, (&new, old)) in masks.iter().zip(self.masks.iter_mut()).enumerate() {
+            if new != *old {
+                *old = (old.0 & new.0, old.1 | new.1);
+                self.engine.rewrite_pulse(index, old.0, old.1)?;
+            }
+        }
+        for (index, (&new, old)) in masks.iter().zip(self.masks.iter_mut()).enumerate() {
+            if new != *old {
+                self.engine.rewrite_pulse(index, new.0, new.1)?;
+                *old = new;
+            }
+        }
+        self.widths = widths;
+        Ok(())
+    }
+
+    /// Sets the width of `pin`'s pulses, from 0 for always low to the
+    /// period for always high. A pin not yet driven is made an output.
+    pub fn set_pulse_width(&mut self, pin: u32, width_us: u32) -> Result<(), Error> {
+        check_pin(pin)?;
+        if width_us > self.period_us {
+            return Err(Error::Other(format!(
+                "a {} us pulse doesn't fit the {} us period", width_us, self.period_us)));
+        }
+        if !self.widths.contains_key(&pin) {
+            self.gpio.set_function(pin, PinFunction::Output)?;
+        }
+        let mut widths = self.widths.clone();
+        widths.insert(pin, width_us);
+        self.update(widths)
+    }
+
+    /// Sets `pin`'s duty cycle, from 0 to 1.
+    pub fn set_duty_cycle(&mut self, pin: u32, duty: f64) -> Result<(), Error> {
+        if !(0.0..=1.0).contains(&duty) {
+            return Err(Error::Other(format!("duty cycle {} is outside 0 to 1", duty)));
+        }
+        self.set_pulse_width(pin, (duty * self.period_us as f64).round() as u32)
+    }
+
+    /// The width last set for `pin`, before rounding to a step.
+    pub fn pulse_width(&self, pin: u32) -> Option<u32> {
+        self.widths.get(&pin).copied()
+    }
+
+    /// Stops driving `pin`, leaving it low. Returns whether it was driven.
+    pub fn remove(&mut self, pin: u32) -> Result<bool, Error> {
+        if !self.widths.contains_key(&pin) {
+            return Ok(false);
+        }
+        let mut widths = self.widths.clone();
+        widths.remove(&pin);
+        self.update(widths)?;
+        // Ends a pulse the pass under way had started.
+        self.gpio.set_low(pin)?;
+        Ok(true)
+    }
+}
+
+impl<'a> Drop for MultiPwm<'a> {
+    fn drop(&mut self) {
+        let _ = self.engine.stop();
+        for pin in self.pins() {
+            let _ = self.gpio.set_low(pin);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_step_masks() {
+        let widths: BTreeMap<u32, u32> = [(4, 1500), (17, 0), (40, 20_000), (22, 1504), (23, 1506)].iter().copied().collect();
+        let masks = step_masks(&widths, 2000, 10);
+        assert_eq!(masks.len(), 2000);
+        assert_eq!(masks[0], (1 << 4 | 1 << 40 | 1 << 22 | 1 << 23, 1 << 17));
+        assert_eq!(masks[150], (0, 1 << 4 | 1 << 22));
+        assert_eq!(masks[151], (0, 1 << 23));
+        assert_eq!(masks.iter().filter(|&&mask| mask != (0, 0)).count(), 3);
+        assert!(step_masks(&BTreeMap::new(), 4, 10).iter().all(|&mask| mask == (0, 0)));
+    }
+}
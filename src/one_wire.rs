@@ -0,0 +1,311 @@
+use crate::timing::wait_until;
+use crate::{Error, Pin, PinFunction, Pull, Unconfigured, GPIO};
+
+use std::time::{Duration, Instant};
+
+
+const SEARCH_ROM: u8 = 0xf0;
+const MATCH_ROM: u8 = 0x55;
+const SKIP_ROM: u8 = 0xcc;
+
+// Standard-speed slot timings, in microseconds.
+const RESET_LOW_US: u64 = 480;
+const PRESENCE_SAMPLE_US: u64 = 70;
+const RESET_RECOVERY_US: u64 = 410;
+const WRITE_ONE_LOW_US: u64 = 6;
+const WRITE_ONE_RELEASE_US: u64 = 64;
+const WRITE_ZERO_LOW_US: u64 = 60;
+const WRITE_ZERO_RELEASE_US: u64 = 10;
+const READ_LOW_US: u64 = 6;
+const READ_SAMPLE_US: u64 = 9;
+const READ_RELEASE_US: u64 = 55;
+
+
+/// The Dallas/Maxim CRC-8 (polynomial x⁸ + x⁵ + x⁴ + 1) used for ROM codes
+/// and scratchpads. Data followed by its CRC comes out as 0.
+pub(crate) fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |crc, &byte| {
+        (0..8).fold((crc, byte), |(crc, byte), _| {
+            let mix = (crc ^ byte) & 1;
+            let crc = crc >> 1;
+            (if mix != 0 { crc ^ 0x8c } else { crc }, byte >> 1)
+        }).0
+    })
+}
+
+fn check_rom(rom: u64) -> Result<u64, Error> {
+    if crc8(&rom.to_le_bytes()) != 0 {
+        return Err(Error::Other(format!("1-Wire ROM code {:016x} fails its CRC", rom)));
+    }
+    Ok(rom)
+}
+
+// One pass of the ROM search. Bits are numbered 0 to 63 from the family
+// code's LSB. `triplet` reads a bit and its complement from the devices
+// still taking part, writes the chosen direction and returns the pair.
+// Returns the ROM found and the position of the last branch where 0 was
+// taken, which the next pass takes 1 at instead.
+fn search_pass(
+    previous: u64,
+    last_discrepancy: Option<u32>,
+    mut triplet: impl FnMut(Option<bool>) -> Result<(bool, bool, bool), Error>,
+) -> Result<Option<(u64, Option<u32>)>, Error> {
+    let mut rom = 0u64;
+    let mut last_zero = None;
+    for bit in 0..64 {
+        let forced = match last_discrepancy {
+            Some(last) if bit < last => Some(previous & (1 << bit) != 0),
+            Some(last) if bit == last => Some(true),
+            _ => None,
+        };
+        let (id, complement, direction) = triplet(forced)?;
+        if id && complement {
+            return Ok(None);
+        }
+        if !id && !complement && !direction {
+            last_zero = Some(bit);
+        }
+        if direction {
+            rom |= 1 << bit;
+        }
+    }
+    Ok(Some((rom, last_zero)))
+}
+
+
+/// A 1-Wire bus master on one pin, at standard speed. The line is driven
+/// open-drain: the pin's latch stays low and the pin is switched to an
+/// output to pull the line down, or to an input to let it float up. The
+/// internal pull-up is enabled but is too weak for more than a short bus;
+/// fit the usual 4.7 kΩ. Parasite power isn't supported.
+///
+/// ROM codes are 64-bit numbers with the family code in the low byte.
+pub struct OneWire<'a> {
+    pin: Pin<'a, Unconfigured>,
+}
+
+impl<'a> OneWire<'a> {
+
+    pub fn new(pin: Pin<'a, Unconfigured>) -> Result<Self, Error> {
+        let bus = Self { pin };
+        let gpio = bus.gpio();
+        gpio.set_pull(bus.pin.number(), Pull::Up)?;
+        gpio.set_low(bus.pin.number())?;
+        bus.release()?;
+        Ok(bus)
+    }
+
+    fn gpio(&self) -> &'a GPIO {
+        self.pin.gpio()
+    }
+
+    fn pull_low(&self) -> Result<(), Error> {
+        self.gpio().set_function(self.pin.number(), PinFunction::Output)
+    }
+
+    fn release(&self) -> Result<(), Error> {
+        self.gpio().set_function(self.pin.number(), PinFunction::Input)
+    }
+
+    fn sample(&self) -> Result<bool, Error> {
+        self.gpio().read(self.pin.number())
+    }
+
+    // Holds the line low for `low_us`, then lets it go, returning when the
+    // release happened.
+    fn pulse(&self, low_us: u64) -> Result<Instant, Error> {
+        let started = Instant::now();
+        self.pull_low()?;
+        wait_until(started + Duration::from_micros(low_us));
+        self.release()?;
+        Ok(Instant::now())
+    }
+
+    /// Sends a reset pulse and returns whether any device answered with a
+    /// presence pulse. Fails if the line stays low, e.g. from a short.
+    pub fn reset(&self) -> Result<bool, Error> {
+        let released = self.pulse(RESET_LOW_US)?;
+        wait_until(released + Duration::from_micros(PRESENCE_SAMPLE_US));
+        let present = !self.sample()?;
+        wait_until(released + Duration::from_micros(PRESENCE_SAMPLE_US + RESET_RECOVERY_US));
+        if !self.sample()? {
+            return Err(Error::Other(format!("the 1-Wire bus on pin {} is held low", self.pin.number())));
+        }
+        Ok(present)
+    }
+
+    pub fn write_bit(&self, bit: bool) -> Result<(), Error> {
+        let (low, release) = if bit {
+            (WRITE_ONE_LOW_US, WRITE_ONE_RELEASE_US)
+        } else {
+            (WRITE_ZERO_LOW_US, WRITE_ZERO_RELEASE_US)
+        };
+        let released = self.pulse(low)?;
+        wait_until(released + Duration::from_micros(release));
+        Ok(())
+    }
+
+    pub fn read_bit(&self) -> Result<bool, Error> {
+        let released = self.pulse(READ_LOW_US)?;
+        wait_until(released + Duration::from_micros(READ_SAMPLE_US));
+        let bit = self.sample()?;
+        wait_until(released + Duration::from_micros(READ_SAMPLE_US + READ_RELEASE_US));
+        Ok(bit)
+    }
+
+    pub fn write_byte(&self, byte: u8) -> Result<(), Error> {
+        (0..8).try_for_each(|bit| self.write_bit(byte & (1 << bit) != 0))
+    }
+
+    pub fn read_byte(&self) -> Result<u8, Error> {
+        (0..8).try_fold(0u8, |byte, bit| Ok(byte | (self.read_bit()? as u8) << bit))
+    }
+
+    pub fn write(&self, data: &[u8]) -> Result<(), Error> {
+        data.iter().try_for_each(|&byte| self.write_byte(byte))
+    }
+
+    pub fn read(&self, buffer: &mut [u8]) -> Result<(), Error> {
+        for byte in buffer.iter_mut() {
+            *byte = self.read_byte()?;
+        }
+        Ok(())
+    }
+
+    /// Resets the bus and addresses the device with ROM code `rom`, or
+    /// every device if `rom` is `None`, ready for a function command.
+    pub fn select(&self, rom: Option<u64>) -> Result<(), Error> {
+        if !self.reset()? {
+            return Err(Error::Other(format!("no 1-Wire device answered on pin {}", self.pin.number())));
+        }
+        match rom {
+            Some(rom) => {
+                self.write_byte(MATCH_ROM)?;
+                self.write(&rom.to_le_bytes())
+            }
+            None => self.write_byte(SKIP_ROM),
+        }
+    }
+
+    /// The ROM codes of every device on the bus.
+    pub fn search(&self) -> Result<Vec<u64>, Error> {
+        let mut roms = Vec::new();
+        let mut previous = 0;
+        let mut last_discrepancy = None;
+        loop {
+            if !self.reset()? {
+                return Ok(roms);
+            }
+            self.write_byte(SEARCH_ROM)?;
+            let pass = search_pass(previous, last_discrepancy, |forced| {
+                let id = self.read_bit()?;
+                let complement = self.read_bit()?;
+                // With only one kind of bit present, follow it.
+                let direction = match (id, complement) {
+                    (true, false) => true,
+                    (false, true) => false,
+                    _ => forced.unwrap_or(false),
+                };
+                self.write_bit(direction)?;
+                Ok((id, complement, direction))
+            })?;
+            match pass {
+                Some((rom, next)) => {
+                    roms.push(check_rom(rom)?);
+                    previous = rom;
+                    last_discrepancy = next;
+                    if next.is_none() {
+                        return Ok(roms);
+                    }
+                }
+                None => return Ok(roms),
+            }
+        }
+    }
+}
+
+impl<'a> Drop for OneWire<'a> {
+    fn drop(&mut self) {
+        let _ = self.release();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc8() {
+        assert_eq!(crc8(&[]), 0);
+        let rom = [0x28, 0xff, 0x4c, 0x6e, 0x91, 0x16, 0x04, 0x00];
+        let crc = crc8(&rom[..7]);
+        let mut with_crc = rom;
+        with_crc[7] = crc;
+        assert_eq!(crc8(&with_crc), 0);
+        assert_eq!(crc8(&[0x02, 0x1c, 0xb8, 0x01, 0, 0, 0]), 0xa2);
+        assert!(check_rom(u64::from_le_bytes(with_crc)).is_ok());
+        assert!(check_rom(u64::from_le_bytes(rom)).is_err());
+    }
+
+    // Plays the devices' side of the search: each bit position answers
+    // with the wired-AND of the bit and its complement among the devices
+    // still taking part.
+    fn search_all(devices: &[u64]) -> Vec<u64> {
+        let mut found = Vec::new();
+        let (mut previous, mut last_discrepancy) = (0, None);
+        loop {
+            let mut active: Vec<u64> = devices.to_vec();
+            let mut bit = 0;
+            let pass = search_pass(previous, last_discrepancy, |forced| {
+                let id = active.iter().all(|rom| rom & (1 << bit) != 0);
+                let complement = active.iter().all(|rom| rom & (1 << bit) == 0);
+                let direction = match (id, complement) {
+                    (true, false) => true,
+                    (false, true) => false,
+                    _ => forced.unwrap_or(false),
+                };
+                active.retain(|rom| (rom & (1 << bit) != 0) == direction);
+                bit += 1;
+                Ok((id, complement, direction))
+            }).unwrap();
+            match pass {
+                Some((rom, next)) => {
+                    found.push(rom);
+                    previous = rom;
+                    last_discrepancy = next;
+                    if next.is_none() {
+                        return found;
+                    }
+                }
+                None => return found,
+            }
+        }
+    }
+
+    #[test]
+    fn test_search_finds_every_device() {
+        let devices = [0x28_0000_0000_0001, 0x28_0000_0000_0003, 0x10_8000_0000_0028, 0x28];
+        let mut found = search_all(&devices);
+        found.sort();
+        let mut expected = devices.to_vec();
+        expected.sort();
+        assert_eq!(found, expected);
+        assert_eq!(search_all(&[0x1234]), vec![0x1234]);
+        assert!(search_all(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_reset() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let bus = OneWire::new(gpio.pin(4).unwrap()).unwrap();
+        assert_eq!(gpio.get_pull(4).unwrap(), Pull::Up);
+        assert_eq!(gpio.get_function(4).unwrap(), PinFunction::Input);
+        // A line that never comes back up.
+        assert!(bus.reset().is_err());
+        unsafe { gpio.write_raw(0x34, 1 << 4) };
+        assert_eq!(bus.reset().ok(), Some(false));
+        assert!(bus.search().unwrap().is_empty());
+        assert!(bus.select(None).is_err());
+    }
+}
@@ -0,0 +1,144 @@
+use crate::region::MappedRegion;
+use crate::{detect_peripheral_base, Error, DEV_MEM_PATH};
+
+use std::path::Path;
+
+
+const PADS_BASE_OFFSET: i64 = 0x100000;
+const PADS_BLOCK_LEN: usize = 0x38;
+
+// As with the clock manager, writes without this in the top byte are
+// ignored.
+const PADS_PASSWORD: u32 = 0x5a << 24;
+
+// Set when the slew rate is *not* limited, the reset state.
+const PADS_SLEW: u32 = 1 << 4;
+const PADS_HYST: u32 = 1 << 3;
+const PADS_DRIVE_MASK: u32 = 0b111;
+
+
+/// A group of pins sharing one pad control register, as the banks of
+/// `GPIO::dump` are.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum PadBank {
+    /// GPIO 0 to 27, which includes the header.
+    Bank0,
+    /// GPIO 28 to 45.
+    Bank1,
+    /// GPIO 46 and up.
+    Bank2,
+}
+
+impl PadBank {
+
+    fn register(self) -> usize {
+        match self {
+            PadBank::Bank0 => 0x2c,
+            PadBank::Bank1 => 0x30,
+            PadBank::Bank2 => 0x34,
+        }
+    }
+
+    pub fn for_pin(pin: u32) -> Result<PadBank, Error> {
+        match pin {
+            0..=27 => Ok(PadBank::Bank0),
+            28..=45 => Ok(PadBank::Bank1),
+            46..=57 => Ok(PadBank::Bank2),
+            _ => Err(Error::InvalidPin { pin, register: None }),
+        }
+    }
+}
+
+
+/// The GPIO pad controls: output drive strength, input hysteresis and
+/// output slew rate, each set per bank. Stronger drive and an unlimited
+/// slew rate give sharper edges for fast bit-banging; weaker drive and a
+/// limited slew rate ring less on long wires and LED strip data lines.
+pub struct Pads {
+    region: MappedRegion,
+}
+
+impl Pads {
+
+    /// Maps the pad controls through `/dev/mem`.
+    pub fn new() -> Result<Self, Error> {
+        Self::open(DEV_MEM_PATH, detect_peripheral_base()? + PADS_BASE_OFFSET)
+    }
+
+    pub(crate) fn open(path: impl AsRef<Path>, phys_base: i64) -> Result<Self, Error> {
+        Ok(Self { region: MappedRegion::open(path.as_ref(), phys_base, PADS_BLOCK_LEN)? })
+    }
+
+    fn modify(&self, bank: PadBank, clear: u32, set: u32) -> Result<(), Error> {
+        let value = self.region.read_reg(bank.register())? & 0xffffff;
+        self.region.write_reg(bank.register(), PADS_PASSWORD | (value & !clear | set))
+    }
+
+    /// The bank's drive strength, from 2 to 16 mA. The firmware leaves it
+    /// at 8 mA.
+    pub fn drive_strength(&self, bank: PadBank) -> Result<u32, Error> {
+        Ok(2 + 2 * (self.region.read_reg(bank.register())? & PADS_DRIVE_MASK))
+    }
+
+    /// Sets the current the bank's outputs can source or sink while
+    /// holding their level, in steps of 2 mA from 2 to 16. It isn't a
+    /// current limit.
+    pub fn set_drive_strength(&self, bank: PadBank, milliamps: u32) -> Result<(), Error> {
+        if !(2..=16).contains(&milliamps) || !milliamps.is_multiple_of(2) {
+            return Err(Error::Other(format!("drive strength must be 2 to 16 mA in steps of 2, not {} mA", milliamps)));
+        }
+        self.modify(bank, PADS_DRIVE_MASK, milliamps / 2 - 1)
+    }
+
+    pub fn hysteresis(&self, bank: PadBank) -> Result<bool, Error> {
+        Ok(self.region.read_reg(bank.register())? & PADS_HYST != 0)
+    }
+
+    /// Enables the Schmitt trigger on the bank's inputs, which is on by
+    /// default and keeps slow or noisy edges from reading as several.
+    pub fn set_hysteresis(&self, bank: PadBank, enabled: bool) -> Result<(), Error> {
+        self.modify(bank, PADS_HYST, if enabled { PADS_HYST } else { 0 })
+    }
+
+    pub fn is_slew_limited(&self, bank: PadBank) -> Result<bool, Error> {
+        Ok(self.region.read_reg(bank.register())? & PADS_SLEW == 0)
+    }
+
+    /// Limits how fast the bank's outputs change level. Off by default.
+    pub fn set_slew_limited(&self, bank: PadBank, limited: bool) -> Result<(), Error> {
+        self.modify(bank, PADS_SLEW, if limited { 0 } else { PADS_SLEW })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pad_controls() {
+        let path = std::env::temp_dir().join(format!("rustberrypi-pads-{}", std::process::id()));
+        std::fs::write(&path, vec![0u8; 4096]).unwrap();
+        let pads = Pads::open(&path, 0).ok().unwrap();
+        // The reset state: 8 mA, hysteresis on, slew rate unlimited.
+        pads.region.write_reg(0x2c, 0x1b).unwrap();
+
+        assert_eq!(pads.drive_strength(PadBank::Bank0).ok(), Some(8));
+        pads.set_drive_strength(PadBank::Bank0, 16).unwrap();
+        assert_eq!(pads.region.read_reg(0x2c).ok(), Some(0x5a << 24 | 0x1f));
+        pads.set_slew_limited(PadBank::Bank0, true).unwrap();
+        pads.set_hysteresis(PadBank::Bank0, false).unwrap();
+        assert_eq!(pads.region.read_reg(0x2c).ok(), Some(0x5a << 24 | 0x07));
+        assert!(pads.is_slew_limited(PadBank::Bank0).unwrap());
+        assert!(!pads.hysteresis(PadBank::Bank0).unwrap());
+
+        pads.set_drive_strength(PadBank::Bank2, 2).unwrap();
+        assert_eq!(pads.region.read_reg(0x34).ok(), Some(0x5a << 24));
+        assert!(pads.set_drive_strength(PadBank::Bank1, 5).is_err());
+        assert!(pads.set_drive_strength(PadBank::Bank1, 18).is_err());
+        assert_eq!(PadBank::for_pin(28).ok(), Some(PadBank::Bank1));
+        assert!(PadBank::for_pin(58).is_err());
+        drop(pads);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
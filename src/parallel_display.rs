@@ -0,0 +1,215 @@
+use crate::timing::wait_until;
+use crate::{Error, Output, Pin, PinGroup};
+
+use std::time::{Duration, Instant};
+
+
+/// The handshake a parallel display controller speaks.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum ParallelBus {
+    /// Intel 8080: an active-low WR strobe, latched on its rising edge,
+    /// and an active-low RD that's held high.
+    I8080,
+    /// Motorola 6800: an active-high E strobe, latched on its falling
+    /// edge, and RW held low for writes.
+    M6800,
+}
+
+/// How long each phase of a write lasts. The defaults suit the slow
+/// end of common controllers; most accept shorter.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ParallelTiming {
+    /// From the bus and RS settling to the strobe going active.
+    pub setup: Duration,
+    /// How long the strobe stays active.
+    pub strobe: Duration,
+    /// From the strobe going inactive to the bus being released.
+    pub hold: Duration,
+}
+
+impl Default for ParallelTiming {
+    fn default() -> Self {
+        Self { setup: Duration::from_nanos(100), strobe: Duration::from_nanos(500), hold: Duration::from_nanos(100) }
+    }
+}
+
+
+/// A display controller on an 8-bit 8080 or 6800 parallel bus, such as an
+/// ILI9341 or ST7789 in parallel mode, or an HD44780 in 8-bit mode. The
+/// data byte goes out through a `PinGroup`, so on register backends pins
+/// in the same bank change together, then the strobe is pulsed with the
+/// configured timing. Only writes are supported: RD or RW, if given, is
+/// held at its write level.
+pub struct ParallelDisplay<'a> {
+    bus: ParallelBus,
+    data: PinGroup<'a>,
+    rs: Pin<'a, Output>,
+    strobe: Pin<'a, Output>,
+    // Only held, at its write level, so it stays claimed.
+    read: Option<Pin<'a, Output>>,
+    chip_select: Option<Pin<'a, Output>>,
+    timing: ParallelTiming,
+}
+
+impl<'a> ParallelDisplay<'a> {
+
+    /// A display with D0 to D7 as bits 0 to 7 of `data`, which are made
+    /// outputs, RS (D/C on some controllers) on `rs` and WR or E on
+    /// `strobe`, which is left inactive.
+    pub fn new(bus: ParallelBus, data: PinGroup<'a>, rs: Pin<'a, Output>, strobe: Pin<'a, Output>) -> Result<Self, Error> {
+        if data.len() != 8 {
+            return Err(Error::Other(format!("a parallel display takes 8 data pins, not {}", data.len())));
+        }
+        data.set_output()?;
+        let display = Self { bus, data, rs, strobe, read: None, chip_select: None, timing: ParallelTiming::default() };
+        display.release_strobe()?;
+        Ok(display)
+    }
+
+    /// Drives RD (8080) or RW (6800) from `pin`, holding it at its write
+    /// level, for displays where it isn't tied.
+    pub fn with_read(mut self, pin: Pin<'a, Output>) -> Result<Self, Error> {
+        match self.bus {
+            ParallelBus::I8080 => pin.set_high()?,
+            ParallelBus::M6800 => pin.set_low()?,
+        }
+        self.read = Some(pin);
+        Ok(self)
+    }
+
+    /// Drives the active-low chip select from `pin`, asserted around
+    /// each write.
+    pub fn with_chip_select(mut self, pin: Pin<'a, Output>) -> Result<Self, Error> {
+        pin.set_high()?;
+        self.chip_select = Some(pin);
+        Ok(self)
+    }
+
+    pub fn with_timing(mut self, timing: ParallelTiming) -> Self {
+        self.timing = timing;
+        self
+    }
+
+    pub fn bus(&self) -> ParallelBus {
+        self.bus
+    }
+
+    pub fn timing(&self) -> ParallelTiming {
+        self.timing
+    }
+
+    fn assert_strobe(&self) -> Result<(), Error> {
+        match self.bus {
+            ParallelBus::I8080 => self.strobe.set_low(),
+            ParallelBus::M6800 => self.strobe.set_high(),
+        }
+    }
+
+    // The controller latches the bus as the strobe goes inactive.
+    fn release_strobe(&self) -> Result<(), Error> {
+        match self.bus {
+            ParallelBus::I8080 => self.strobe.set_high(),
+            ParallelBus::M6800 => self.strobe.set_low(),
+        }
+    }
+
+    fn write(&mut self, byte: u8, data: bool) -> Result<(), Error> {
+        if let Some(chip_select) = &self.chip_select {
+            chip_select.set_low()?;
+        }
+        if data { self.rs.set_high()? } else { self.rs.set_low()? }
+        self.data.write(byte as u64)?;
+        wait_until(Instant::now() + self.timing.setup);
+        let started = Instant::now();
+        self.assert_strobe()?;
+        wait_until(started + self.timing.strobe);
+        self.release_strobe()?;
+        wait_until(Instant::now() + self.timing.hold);
+        if let Some(chip_select) = &self.chip_select {
+            chip_select.set_high()?;
+        }
+        Ok(())
+    }
+
+    /// Sends `command` with RS low.
+    pub fn write_command(&mut self, command: u8) -> Result<(), Error> {
+        self.write(command, false)
+    }
+
+    /// Sends `data` with RS high.
+    pub fn write_data(&mut self, data: u8) -> Result<(), Error> {
+        self.write(data, true)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MockGpio, MockOperation};
+
+    const DATA_PINS: [u32; 8] = [16, 17, 18, 19, 20, 21, 22, 23];
+
+    fn bus_writes(byte: u8) -> Vec<MockOperation> {
+        DATA_PINS.iter().enumerate()
+            .map(|(bit, &pin)| MockOperation::Write { pin, level: byte & (1 << bit) != 0 })
+            .collect()
+    }
+
+    #[test]
+    fn test_8080_write_sequence() {
+        let mock = MockGpio::new();
+        let gpio = mock.gpio().unwrap();
+        let mut display = ParallelDisplay::new(
+            ParallelBus::I8080,
+            gpio.pin_group(&DATA_PINS).unwrap(),
+            gpio.pin(24).unwrap().into_output().unwrap(),
+            gpio.pin(25).unwrap().into_output().unwrap(),
+        ).unwrap()
+            .with_read(gpio.pin(26).unwrap().into_output().unwrap()).unwrap()
+            .with_chip_select(gpio.pin(27).unwrap().into_output().unwrap()).unwrap()
+            .with_timing(ParallelTiming { setup: Duration::ZERO, strobe: Duration::ZERO, hold: Duration::ZERO });
+        assert_eq!((mock.level(25), mock.level(26), mock.level(27)), (true, true, true));
+
+        let before = mock.operations().len();
+        display.write_command(0x2c).unwrap();
+        let mut expected = vec![
+            MockOperation::Write { pin: 27, level: false },
+            MockOperation::Write { pin: 24, level: false },
+        ];
+        expected.extend(bus_writes(0x2c));
+        expected.extend_from_slice(&[
+            MockOperation::Write { pin: 25, level: false },
+            MockOperation::Write { pin: 25, level: true },
+            MockOperation::Write { pin: 27, level: true },
+        ]);
+        assert_eq!(mock.operations()[before..], expected[..]);
+        assert!(mock.level(26));
+    }
+
+    #[test]
+    fn test_6800_write_sequence() {
+        let mock = MockGpio::new();
+        let gpio = mock.gpio().unwrap();
+        let mut display = ParallelDisplay::new(
+            ParallelBus::M6800,
+            gpio.pin_group(&DATA_PINS).unwrap(),
+            gpio.pin(24).unwrap().into_output().unwrap(),
+            gpio.pin(25).unwrap().into_output().unwrap(),
+        ).unwrap().with_read(gpio.pin(26).unwrap().into_output().unwrap()).unwrap();
+        assert_eq!((mock.level(25), mock.level(26)), (false, false));
+
+        let before = mock.operations().len();
+        display.write_data(0xa5).unwrap();
+        let mut expected = vec![MockOperation::Write { pin: 24, level: true }];
+        expected.extend(bus_writes(0xa5));
+        expected.extend_from_slice(&[
+            MockOperation::Write { pin: 25, level: true },
+            MockOperation::Write { pin: 25, level: false },
+        ]);
+        assert_eq!(mock.operations()[before..], expected[..]);
+
+        assert!(ParallelDisplay::new(ParallelBus::M6800, gpio.pin_group(&[5, 6, 7, 8]).unwrap(),
+            gpio.pin(9).unwrap().into_output().unwrap(), gpio.pin(10).unwrap().into_output().unwrap()).is_err());
+    }
+}
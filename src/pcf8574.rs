@@ -0,0 +1,223 @@
+use crate::{Error, I2c, Input, Pin, Trigger};
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+
+/// A PCF8574 or PCF8574A 8-line I2C expander. Its lines are
+/// quasi-bidirectional: writing 0 drives a line low, while writing 1 only
+/// holds it up weakly, so it can be read as an input that anything may
+/// pull down. All lines start high, i.e. as inputs.
+///
+/// The chip pulls its open-drain INT line low when an input changes, until
+/// the port is next read or written.
+///
+/// Lines can be set through the expander, or claimed one at a time as
+/// `Pcf8574Pin`s, which share it.
+pub struct Pcf8574 {
+    address: u8,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    i2c: I2c,
+    latch: u8,
+    claimed: u8,
+}
+
+impl Pcf8574 {
+
+    /// `address` is 0x20-0x27 for a PCF8574 or 0x38-0x3f for a PCF8574A,
+    /// depending on A0-A2.
+    pub fn new(i2c: I2c, address: u8) -> Result<Self, Error> {
+        if !(0x20..=0x27).contains(&address) && !(0x38..=0x3f).contains(&address) {
+            return Err(Error::Other(format!("{:#04x} isn't a PCF8574 or PCF8574A address", address)));
+        }
+        let expander = Self { address, inner: Mutex::new(Inner { i2c, latch: 0, claimed: 0 }) };
+        expander.write(0xff)?;
+        Ok(expander)
+    }
+
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    /// Sets all eight lines, P0 in bit 0.
+    pub fn write(&self, byte: u8) -> Result<(), Error> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.i2c.write(self.address, &[byte])?;
+        inner.latch = byte;
+        Ok(())
+    }
+
+    /// The levels of all eight lines. Lines written low read low.
+    pub fn read(&self) -> Result<u8, Error> {
+        let mut byte = [0u8];
+        self.inner.lock().unwrap().i2c.read(self.address, &mut byte)?;
+        Ok(byte[0])
+    }
+
+    /// The byte last written.
+    pub fn latch(&self) -> u8 {
+        self.inner.lock().unwrap().latch
+    }
+
+    fn check_line(line: u8) -> Result<(), Error> {
+        if line > 7 {
+            return Err(Error::Other(format!("the PCF8574 has no line {}", line)));
+        }
+        Ok(())
+    }
+
+    /// Drives `line` low, or releases it high, leaving the others alone.
+    pub fn set(&self, line: u8, high: bool) -> Result<(), Error> {
+        Self::check_line(line)?;
+        let mut inner = self.inner.lock().unwrap();
+        let byte = if high { inner.latch | 1 << line } else { inner.latch & !(1 << line) };
+        inner.i2c.write(self.address, &[byte])?;
+        inner.latch = byte;
+        Ok(())
+    }
+
+    /// Releases `line` so it can be read as an input.
+    pub fn set_input(&self, line: u8) -> Result<(), Error> {
+        self.set(line, true)
+    }
+
+    pub fn get(&self, line: u8) -> Result<bool, Error> {
+        Self::check_line(line)?;
+        Ok(self.read()? & 1 << line != 0)
+    }
+
+    /// Waits for the chip to signal a change on `interrupt`, a native pin
+    /// wired to INT with a pull-up, then reads the port, which clears the
+    /// interrupt. Returns at once if INT is already low.
+    pub fn wait_for_change(&self, interrupt: &Pin<'_, Input>, timeout: Option<Duration>) -> Result<u8, Error> {
+        if interrupt.read()? {
+            interrupt.wait_for_edge(Trigger::Falling, timeout)?;
+        }
+        self.read()
+    }
+
+    /// Claims `line`, failing with `Error::PinInUse` if it already has a
+    /// handle.
+    pub fn pin(&self, line: u8) -> Result<Pcf8574Pin<'_>, Error> {
+        Self::check_line(line)?;
+        let mut inner = self.inner.lock().unwrap();
+        if inner.claimed & (1 << line) != 0 {
+            return Err(Error::PinInUse(line as u32));
+        }
+        inner.claimed |= 1 << line;
+        Ok(Pcf8574Pin { expander: self, line })
+    }
+}
+
+
+/// One line of a `Pcf8574`. Like the chip's lines it's both an input and
+/// an output: set it high to release it, then read it.
+pub struct Pcf8574Pin<'e> {
+    expander: &'e Pcf8574,
+    line: u8,
+}
+
+impl<'e> Pcf8574Pin<'e> {
+
+    pub fn number(&self) -> u8 {
+        self.line
+    }
+
+    /// Drives the line low.
+    pub fn set_low(&self) -> Result<(), Error> {
+        self.expander.set(self.line, false)
+    }
+
+    /// Releases the line to its weak pull-up.
+    pub fn set_high(&self) -> Result<(), Error> {
+        self.expander.set(self.line, true)
+    }
+
+    pub fn toggle(&self) -> Result<(), Error> {
+        self.expander.set(self.line, !self.is_set_high())
+    }
+
+    /// Whether the line was last set high, i.e. released.
+    pub fn is_set_high(&self) -> bool {
+        self.expander.latch() & (1 << self.line) != 0
+    }
+
+    /// The line's level, which is low while something outside pulls it
+    /// down even if it was set high.
+    pub fn read(&self) -> Result<bool, Error> {
+        self.expander.get(self.line)
+    }
+}
+
+impl<'e> Drop for Pcf8574Pin<'e> {
+    fn drop(&mut self) {
+        self.expander.inner.lock().unwrap().claimed &= !(1 << self.line);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{I2cAddress, I2cBackend, GPIO};
+    use std::sync::{Arc, Mutex};
+
+    // Reads back the last byte written, with line 7 pulled low outside.
+    struct Port(Arc<Mutex<Vec<u8>>>);
+
+    impl I2cBackend for Port {
+        fn write(&mut self, address: I2cAddress, data: &[u8]) -> Result<(), Error> {
+            assert_eq!(address, I2cAddress::SevenBit(0x38));
+            self.0.lock().unwrap().extend_from_slice(data);
+            Ok(())
+        }
+        fn read(&mut self, _address: I2cAddress, buffer: &mut [u8]) -> Result<(), Error> {
+            buffer[0] = self.0.lock().unwrap().last().copied().unwrap_or(0xff) & 0x7f;
+            Ok(())
+        }
+        fn write_read(&mut self, _address: I2cAddress, _data: &[u8], _buffer: &mut [u8]) -> Result<(), Error> {
+            Err(Error::Unsupported("the test port has no registers to address".to_string()))
+        }
+        fn set_frequency(&mut self, freq_hz: u32) -> Result<u32, Error> { Ok(freq_hz) }
+        fn set_timeout(&mut self, _timeout: Duration) -> Result<(), Error> { Ok(()) }
+    }
+
+    #[test]
+    fn test_pcf8574() {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        assert!(Pcf8574::new(I2c::custom(Port(Arc::clone(&written))), 0x30).is_err());
+        let expander = Pcf8574::new(I2c::custom(Port(Arc::clone(&written))), 0x38).unwrap();
+        expander.set(2, false).unwrap();
+        expander.set(2, true).unwrap();
+        expander.set(0, false).unwrap();
+        assert_eq!(*written.lock().unwrap(), vec![0xff, 0xfb, 0xff, 0xfe]);
+        assert_eq!(expander.latch(), 0xfe);
+        assert_eq!(expander.read().unwrap(), 0x7e);
+        assert!(!expander.get(7).unwrap() && expander.get(6).unwrap());
+        assert!(expander.set(8, true).is_err());
+
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let interrupt = gpio.pin(4).unwrap().into_input().unwrap();
+        assert_eq!(expander.wait_for_change(&interrupt, Some(Duration::from_millis(1))).unwrap(), 0x7e);
+    }
+
+    #[test]
+    fn test_pcf8574_pins() {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let expander = Pcf8574::new(I2c::custom(Port(Arc::clone(&written))), 0x38).unwrap();
+        let led = expander.pin(1).unwrap();
+        assert!(matches!(expander.pin(1), Err(Error::PinInUse(1))));
+        assert!(expander.pin(8).is_err());
+        let switch = expander.pin(7).unwrap();
+        led.set_low().unwrap();
+        assert!(!led.is_set_high() && !led.read().unwrap());
+        led.toggle().unwrap();
+        assert_eq!(*written.lock().unwrap(), vec![0xff, 0xfd, 0xff]);
+        assert!(switch.is_set_high() && !switch.read().unwrap());
+        drop(led);
+        assert!(expander.pin(1).is_ok());
+    }
+}
@@ -0,0 +1,484 @@
+use crate::clock::{Clock, ClockManager, ClockSource};
+use crate::region::MappedRegion;
+use crate::{detect_peripheral_base, Error, PinFunction, GPIO, DEV_MEM_PATH};
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+
+pub(crate) const PCM_BASE_OFFSET: i64 = 0x203000;
+const PCM_BLOCK_LEN: usize = 0x24;
+
+const PCM_CS: usize = 0x00;
+pub(crate) const PCM_FIFO: usize = 0x04;
+const PCM_MODE: usize = 0x08;
+const PCM_RXC: usize = 0x0c;
+const PCM_TXC: usize = 0x10;
+const PCM_DREQ: usize = 0x14;
+const PCM_INTEN: usize = 0x18;
+const PCM_INTSTC: usize = 0x1c;
+
+const CS_EN: u32 = 1 << 0;
+const CS_RXON: u32 = 1 << 1;
+const CS_TXON: u32 = 1 << 2;
+const CS_TXCLR: u32 = 1 << 3;
+const CS_RXCLR: u32 = 1 << 4;
+const CS_DMAEN: u32 = 1 << 9;
+// Write-1-to-clear, so kept out of read-modify-writes.
+const CS_TXERR: u32 = 1 << 15;
+const CS_RXERR: u32 = 1 << 16;
+const CS_TXD: u32 = 1 << 19;
+const CS_RXD: u32 = 1 << 20;
+const CS_TXE: u32 = 1 << 21;
+const CS_RXF: u32 = 1 << 22;
+const CS_RXSEX: u32 = 1 << 23;
+// Takes the FIFO RAM out of standby.
+const CS_STBY: u32 = 1 << 25;
+
+const MODE_FSLEN_SHIFT: u32 = 0;
+const MODE_FLEN_SHIFT: u32 = 10;
+const MODE_FSI: u32 = 1 << 20;
+const MODE_FSM: u32 = 1 << 21;
+const MODE_CLKI: u32 = 1 << 22;
+const MODE_CLKM: u32 = 1 << 23;
+const MODE_LEN_MAX: u32 = 0x3ff;
+
+// TXC and RXC hold the same 16-bit layout for each channel, channel 1 in
+// the top half. A channel is 8 + WID bits wide, plus 16 with WEX.
+const CH_WID_SHIFT: u32 = 0;
+const CH_POS_SHIFT: u32 = 4;
+const CH_EN: u32 = 1 << 14;
+const CH_WEX: u32 = 1 << 15;
+const CH1_SHIFT: u32 = 16;
+
+const DREQ_RX_SHIFT: u32 = 0;
+const DREQ_TX_SHIFT: u32 = 8;
+const DREQ_RX_PANIC_SHIFT: u32 = 16;
+const DREQ_TX_PANIC_SHIFT: u32 = 24;
+const DREQ_LEVEL_MAX: u32 = 0x7f;
+
+const INTSTC_ALL: u32 = 0xf;
+
+// PCM_CLK, PCM_FS, PCM_DIN and PCM_DOUT.
+const PCM_PINS: [u32; 4] = [18, 19, 20, 21];
+
+// How long a blocking read or write waits for the FIFO to move, which it
+// won't if the bit clock isn't running.
+const FIFO_TIMEOUT: Duration = Duration::from_millis(100);
+
+
+/// The I2S frame: two slots per frame, left then right, with the frame
+/// sync low for the left. Data starts one bit clock after the frame sync
+/// changes and goes out MSB first, as I2S DACs and MEMS microphones expect.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct I2sConfig {
+    /// Bits in each sample, 8 to 32.
+    pub sample_bits: u32,
+    /// Bit clocks in each slot, at least `sample_bits`. Many devices want
+    /// 32 whatever the sample size.
+    pub slot_bits: u32,
+    /// 1 for mono, which uses the left slot only, or 2 for stereo.
+    pub channels: u32,
+    /// Whether the Pi drives the bit clock, rather than the device.
+    pub clock_master: bool,
+    /// Whether the Pi drives the frame sync, rather than the device.
+    pub frame_sync_master: bool,
+}
+
+impl I2sConfig {
+
+    /// Stereo `sample_bits` samples in slots of the same size, with the Pi
+    /// driving both clocks.
+    pub fn new(sample_bits: u32) -> Self {
+        Self { sample_bits, slot_bits: sample_bits, channels: 2, clock_master: true, frame_sync_master: true }
+    }
+
+    pub fn with_slot_bits(mut self, slot_bits: u32) -> Self {
+        self.slot_bits = slot_bits;
+        self
+    }
+
+    pub fn mono(mut self) -> Self {
+        self.channels = 1;
+        self
+    }
+
+    /// Takes both clocks from the device, e.g. a codec with its own
+    /// crystal.
+    pub fn as_slave(mut self) -> Self {
+        self.clock_master = false;
+        self.frame_sync_master = false;
+        self
+    }
+
+    /// Bit clocks in each frame.
+    pub fn frame_bits(&self) -> u32 {
+        2 * self.slot_bits
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if !(8..=32).contains(&self.sample_bits) {
+            return Err(Error::Other(format!("I2S samples are 8 to 32 bits, not {}", self.sample_bits)));
+        }
+        if self.slot_bits < self.sample_bits || self.frame_bits() > MODE_LEN_MAX + 1 {
+            return Err(Error::Other(format!(
+                "a {}-bit slot can't hold {}-bit samples", self.slot_bits, self.sample_bits)));
+        }
+        if !(1..=2).contains(&self.channels) {
+            return Err(Error::Other(format!("I2S carries 1 or 2 channels, not {}", self.channels)));
+        }
+        Ok(())
+    }
+
+    fn mode(&self) -> u32 {
+        // The bit clock is inverted so data changes on its falling edge
+        // and is sampled on its rising edge.
+        let mut mode = (self.frame_bits() - 1) << MODE_FLEN_SHIFT | self.slot_bits << MODE_FSLEN_SHIFT
+            | MODE_CLKI | MODE_FSI;
+        if !self.clock_master {
+            mode |= MODE_CLKM;
+        }
+        if !self.frame_sync_master {
+            mode |= MODE_FSM;
+        }
+        mode
+    }
+
+    fn channels_config(&self) -> u32 {
+        let left = channel_config(self.sample_bits, 1);
+        let right = if self.channels == 2 { channel_config(self.sample_bits, self.slot_bits + 1) } else { 0 };
+        left << CH1_SHIFT | right
+    }
+}
+
+
+fn channel_config(width: u32, position: u32) -> u32 {
+    let wide = if width >= 24 { CH_WEX } else { 0 };
+    let extra = if width >= 24 { width - 24 } else { width - 8 };
+    CH_EN | wide | position << CH_POS_SHIFT | extra << CH_WID_SHIFT
+}
+
+
+/// FIFO levels at which the PCM block asks the DMA controller for data,
+/// each 0 to 127 words. The panic levels raise the DMA's priority.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct PcmDreq {
+    /// Request data while the transmit FIFO holds fewer words than this.
+    pub tx: u32,
+    /// Request a read while the receive FIFO holds more words than this.
+    pub rx: u32,
+    pub tx_panic: u32,
+    pub rx_panic: u32,
+}
+
+impl Default for PcmDreq {
+    /// The levels the kernel's I2S driver uses.
+    fn default() -> Self {
+        Self { tx: 0x30, rx: 0x20, tx_panic: 0x10, rx_panic: 0x30 }
+    }
+}
+
+
+/// FIFO errors seen since they were last taken.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct PcmErrors {
+    /// The transmit FIFO ran dry while transmitting.
+    pub tx_underrun: bool,
+    /// The receive FIFO filled up and samples were lost.
+    pub rx_overrun: bool,
+}
+
+
+/// The PCM/I2S controller on GPIO 18 (bit clock), 19 (frame sync), 20
+/// (data in) and 21 (data out), for I2S DACs and MEMS microphones. Samples
+/// go through 64-word FIFOs, one word per channel, left first; received
+/// samples are sign extended. The bit clock comes from `Clock::Pcm`, set
+/// with `set_sample_rate` when the Pi is the clock master.
+///
+/// The kernel's I2S driver uses the same block, so it mustn't be loaded
+/// (`dtparam=i2s=off`).
+pub struct Pcm {
+    region: MappedRegion,
+    config: I2sConfig,
+}
+
+impl Pcm {
+
+    /// Sets the controller up for `config` and switches GPIO 18 to 21 to
+    /// it.
+    pub fn new(gpio: &GPIO, config: I2sConfig) -> Result<Self, Error> {
+        let base = detect_peripheral_base()?;
+        let pcm = Self::open(DEV_MEM_PATH, base + PCM_BASE_OFFSET, config)?;
+        gpio.switch_to_alt(&PCM_PINS, PinFunction::Alt0)?;
+        Ok(pcm)
+    }
+
+    pub(crate) fn open(path: impl AsRef<Path>, phys_base: i64, config: I2sConfig) -> Result<Self, Error> {
+        config.validate()?;
+        let mut pcm = Self { region: MappedRegion::open(path.as_ref(), phys_base, PCM_BLOCK_LEN)?, config };
+        pcm.region.write_reg(PCM_INTEN, 0)?;
+        pcm.region.write_reg(PCM_INTSTC, INTSTC_ALL)?;
+        pcm.configure(config)?;
+        Ok(pcm)
+    }
+
+    /// Sets the controller up as a pacing source for DMA: one 32-bit word
+    /// leaves the transmit FIFO every `bits_per_word` bit clocks, and the
+    /// FIFO raises its DREQ whenever it has room. Nothing is routed to the
+    /// pins.
+    pub(crate) fn open_pacer(path: impl AsRef<Path>, phys_base: i64, bits_per_word: u32) -> Result<Self, Error> {
+        let region = MappedRegion::open(path.as_ref(), phys_base, PCM_BLOCK_LEN)?;
+        region.write_reg(PCM_CS, CS_EN | CS_STBY)?;
+        region.write_reg(PCM_MODE, (bits_per_word - 1) << MODE_FLEN_SHIFT)?;
+        region.write_reg(PCM_TXC, channel_config(32, 0) << CH1_SHIFT)?;
+        region.write_reg(PCM_RXC, 0)?;
+        region.write_reg(PCM_CS, CS_EN | CS_STBY | CS_TXCLR | CS_RXCLR)?;
+        let pcm = Self { region, config: I2sConfig::new(32).mono() };
+        pcm.enable_dma(PcmDreq { tx: 0x40, rx: 0x40, tx_panic: 0x10, rx_panic: 0x40 })?;
+        pcm.update_cs(CS_TXON, 0)?;
+        Ok(pcm)
+    }
+
+    // Sets and clears bits of CS without clearing the error flags.
+    fn update_cs(&self, set: u32, clear: u32) -> Result<(), Error> {
+        let cs = self.region.read_reg(PCM_CS)?;
+        self.region.write_reg(PCM_CS, cs & !(CS_TXERR | CS_RXERR | CS_TXCLR | CS_RXCLR | clear) | set)
+    }
+
+    /// Stops any transfer and changes the frame format. Both FIFOs are
+    /// emptied.
+    pub fn configure(&mut self, config: I2sConfig) -> Result<(), Error> {
+        config.validate()?;
+        self.update_cs(CS_EN | CS_STBY, CS_TXON | CS_RXON)?;
+        self.region.write_reg(PCM_MODE, config.mode())?;
+        self.region.write_reg(PCM_TXC, config.channels_config())?;
+        self.region.write_reg(PCM_RXC, config.channels_config())?;
+        self.update_cs(CS_RXSEX, 0)?;
+        self.config = config;
+        self.clear_fifos()
+    }
+
+    pub fn config(&self) -> I2sConfig {
+        self.config
+    }
+
+    /// Runs the bit clock for `rate_hz` frames a second and returns the
+    /// rate achieved. Only matters when the Pi is the clock master.
+    pub fn set_sample_rate(&self, clocks: &ClockManager, rate_hz: u32) -> Result<f64, Error> {
+        let bit_clock = rate_hz as f64 * self.config.frame_bits() as f64;
+        Ok(clocks.set_frequency(Clock::Pcm, ClockSource::PllD, bit_clock)? / self.config.frame_bits() as f64)
+    }
+
+    /// Empties both FIFOs. This takes a couple of bit clocks to happen.
+    pub fn clear_fifos(&self) -> Result<(), Error> {
+        self.update_cs(CS_TXCLR | CS_RXCLR, 0)
+    }
+
+    /// Starts transmitting. Fill the FIFO with `try_write` first, or it
+    /// underruns straight away.
+    pub fn start_tx(&self) -> Result<(), Error> {
+        self.update_cs(CS_TXON, 0)
+    }
+
+    pub fn start_rx(&self) -> Result<(), Error> {
+        self.update_cs(CS_RXON, 0)
+    }
+
+    /// Stops transmitting and receiving, leaving the FIFOs as they are.
+    pub fn stop(&self) -> Result<(), Error> {
+        self.update_cs(0, CS_TXON | CS_RXON)
+    }
+
+    /// Has the FIFOs request DMA at `levels`.
+    pub fn enable_dma(&self, levels: PcmDreq) -> Result<(), Error> {
+        for &level in [levels.tx, levels.rx, levels.tx_panic, levels.rx_panic].iter() {
+            if level > DREQ_LEVEL_MAX {
+                return Err(Error::Other(format!("DREQ levels are 0 to {}, not {}", DREQ_LEVEL_MAX, level)));
+            }
+        }
+        self.region.write_reg(PCM_DREQ, levels.tx_panic << DREQ_TX_PANIC_SHIFT | levels.rx_panic << DREQ_RX_PANIC_SHIFT
+            | levels.tx << DREQ_TX_SHIFT | levels.rx << DREQ_RX_SHIFT)?;
+        self.update_cs(CS_DMAEN, 0)
+    }
+
+    pub fn disable_dma(&self) -> Result<(), Error> {
+        self.update_cs(0, CS_DMAEN)
+    }
+
+    fn status(&self) -> Result<u32, Error> {
+        self.region.read_reg(PCM_CS)
+    }
+
+    /// Whether the transmit FIFO has room for a sample.
+    pub fn can_write(&self) -> Result<bool, Error> {
+        Ok(self.status()? & CS_TXD != 0)
+    }
+
+    /// Whether a received sample is waiting.
+    pub fn can_read(&self) -> Result<bool, Error> {
+        Ok(self.status()? & CS_RXD != 0)
+    }
+
+    pub fn is_tx_empty(&self) -> Result<bool, Error> {
+        Ok(self.status()? & CS_TXE != 0)
+    }
+
+    pub fn is_rx_full(&self) -> Result<bool, Error> {
+        Ok(self.status()? & CS_RXF != 0)
+    }
+
+    /// Returns the FIFO errors flagged since the last call and clears them.
+    pub fn take_errors(&self) -> Result<PcmErrors, Error> {
+        let cs = self.status()?;
+        let errors = PcmErrors { tx_underrun: cs & CS_TXERR != 0, rx_overrun: cs & CS_RXERR != 0 };
+        self.update_cs(cs & (CS_TXERR | CS_RXERR), 0)?;
+        Ok(errors)
+    }
+
+    /// Queues as many of `samples` as fit in the transmit FIFO without
+    /// waiting and returns how many that was.
+    pub fn try_write(&self, samples: &[i32]) -> Result<usize, Error> {
+        let mut written = 0;
+        while written < samples.len() && self.can_write()? {
+            self.region.write_reg(PCM_FIFO, samples[written] as u32)?;
+            written += 1;
+        }
+        Ok(written)
+    }
+
+    /// Writes all of `samples`, waiting for room in the FIFO as needed.
+    /// Fails with `Error::Timeout` if the FIFO stops draining.
+    pub fn write(&self, samples: &[i32]) -> Result<(), Error> {
+        let mut written = 0;
+        let mut progress = Instant::now();
+        while written < samples.len() {
+            let queued = self.try_write(&samples[written..])?;
+            if queued > 0 {
+                written += queued;
+                progress = Instant::now();
+            } else if progress.elapsed() > FIFO_TIMEOUT {
+                return Err(Error::Timeout(format!(
+                    "the PCM transmit FIFO stopped draining after {} of {} samples", written, samples.len())));
+            } else {
+                std::hint::spin_loop();
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes whatever received samples are waiting, up to the length of
+    /// `samples`, and returns how many there were.
+    pub fn try_read(&self, samples: &mut [i32]) -> Result<usize, Error> {
+        let mut read = 0;
+        while read < samples.len() && self.can_read()? {
+            samples[read] = self.region.read_reg(PCM_FIFO)? as i32;
+            read += 1;
+        }
+        Ok(read)
+    }
+
+    /// Fills `samples`, waiting for each. Fails with `Error::Timeout` if
+    /// samples stop arriving.
+    pub fn read(&self, samples: &mut [i32]) -> Result<(), Error> {
+        let mut read = 0;
+        let mut progress = Instant::now();
+        while read < samples.len() {
+            let received = self.try_read(&mut samples[read..])?;
+            if received > 0 {
+                read += received;
+                progress = Instant::now();
+            } else if progress.elapsed() > FIFO_TIMEOUT {
+                return Err(Error::Timeout(format!("PCM read {} of {} samples", read, samples.len())));
+            } else {
+                std::hint::spin_loop();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Pcm {
+    fn drop(&mut self) {
+        let _ = self.region.write_reg(PCM_CS, 0);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_pcm(name: &str, config: I2sConfig, check: impl FnOnce(&mut Pcm, &MappedRegion)) {
+        let path = std::env::temp_dir().join(format!("rustberrypi-pcm-{}-{}", name, std::process::id()));
+        std::fs::write(&path, vec![0u8; 4096]).unwrap();
+        let mut pcm = Pcm::open(&path, 0, config).ok().unwrap();
+        // A second mapping of the file sees the controller's writes.
+        let region = MappedRegion::open(&path, 0, PCM_BLOCK_LEN).ok().unwrap();
+        check(&mut pcm, &region);
+        drop(pcm);
+        assert_eq!(region.read_reg(PCM_CS).ok(), Some(0));
+        drop(region);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_i2s_config() {
+        with_pcm("config", I2sConfig::new(16).with_slot_bits(32), |pcm, region| {
+            assert_eq!(region.read_reg(PCM_MODE).ok(), Some(63 << 10 | 32 | MODE_CLKI | MODE_FSI));
+            // Left at bit 1, right at bit 33, both 16 bits wide.
+            let left = CH_EN | 1 << 4 | 8;
+            let right = CH_EN | 33 << 4 | 8;
+            assert_eq!(region.read_reg(PCM_TXC).ok(), Some(left << 16 | right));
+            assert_eq!(region.read_reg(PCM_RXC).ok(), Some(left << 16 | right));
+            let cs = region.read_reg(PCM_CS).unwrap();
+            assert_eq!(cs & (CS_EN | CS_STBY | CS_RXSEX | CS_TXON), CS_EN | CS_STBY | CS_RXSEX);
+
+            pcm.configure(I2sConfig::new(24).mono().as_slave()).unwrap();
+            assert_eq!(region.read_reg(PCM_MODE).ok(), Some(47 << 10 | 24 | MODE_CLKI | MODE_FSI | MODE_CLKM | MODE_FSM));
+            assert_eq!(region.read_reg(PCM_TXC).ok(), Some((CH_EN | CH_WEX | 1 << 4) << 16));
+            assert!(pcm.configure(I2sConfig::new(33)).is_err());
+            assert!(pcm.configure(I2sConfig::new(16).with_slot_bits(8)).is_err());
+            assert_eq!(pcm.config().sample_bits, 24);
+        });
+    }
+
+    #[test]
+    fn test_fifo_and_dma() {
+        with_pcm("fifo", I2sConfig::new(32), |pcm, region| {
+            pcm.enable_dma(PcmDreq::default()).unwrap();
+            assert_eq!(region.read_reg(PCM_DREQ).ok(), Some(0x10 << 24 | 0x30 << 16 | 0x30 << 8 | 0x20));
+            assert_ne!(region.read_reg(PCM_CS).unwrap() & CS_DMAEN, 0);
+            assert!(pcm.enable_dma(PcmDreq { tx: 0x80, ..PcmDreq::default() }).is_err());
+
+            // With TXD clear the FIFO is full, and nothing moves.
+            assert_eq!(pcm.try_write(&[1, 2]).ok(), Some(0));
+            assert!(matches!(pcm.write(&[1]), Err(Error::Timeout(_))));
+            region.write_reg(PCM_CS, region.read_reg(PCM_CS).unwrap() | CS_TXD).unwrap();
+            pcm.write(&[-5]).unwrap();
+            assert_eq!(region.read_reg(PCM_FIFO).ok(), Some(-5i32 as u32));
+            pcm.start_tx().unwrap();
+            assert_ne!(region.read_reg(PCM_CS).unwrap() & CS_TXON, 0);
+            pcm.stop().unwrap();
+            assert_eq!(region.read_reg(PCM_CS).unwrap() & CS_TXON, 0);
+
+            region.write_reg(PCM_CS, CS_EN | CS_RXD | CS_TXERR).unwrap();
+            let mut samples = [0; 2];
+            pcm.read(&mut samples).unwrap();
+            assert_eq!(samples, [-5, -5]);
+            assert_eq!(pcm.take_errors().ok(), Some(PcmErrors { tx_underrun: true, rx_overrun: false }));
+        });
+    }
+
+    #[test]
+    fn test_pacer() {
+        let path = std::env::temp_dir().join(format!("rustberrypi-pcm-pacer-{}", std::process::id()));
+        std::fs::write(&path, vec![0u8; 4096]).unwrap();
+        let pacer = Pcm::open_pacer(&path, 0, 10).ok().unwrap();
+        assert_eq!(pacer.region.read_reg(PCM_MODE).ok(), Some(9 << 10));
+        assert_eq!(pacer.region.read_reg(PCM_TXC).ok(), Some((CH_EN | CH_WEX | 8) << 16));
+        let cs = pacer.region.read_reg(PCM_CS).unwrap();
+        assert_eq!(cs & (CS_EN | CS_TXON | CS_DMAEN), CS_EN | CS_TXON | CS_DMAEN);
+        drop(pacer);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
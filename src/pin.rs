@@ -0,0 +1,569 @@
+use crate::{Error, PinEvent, PinFunction, PinSnapshot, Pull, Register, Trigger, GPIO};
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+
+/// Mode of a pin whose function hasn't been chosen through its handle yet.
+pub struct Unconfigured;
+
+pub struct Input;
+
+pub struct Output;
+
+/// Alternate function `N` (0-5). Other values fail to compile when the pin
+/// is moved into the mode.
+pub struct Alt<const N: u8>;
+
+impl<const N: u8> Alt<N> {
+    const FUNCTION: PinFunction = match N {
+        0 => PinFunction::Alt0,
+        1 => PinFunction::Alt1,
+        2 => PinFunction::Alt2,
+        3 => PinFunction::Alt3,
+        4 => PinFunction::Alt4,
+        5 => PinFunction::Alt5,
+        _ => panic!("alternate functions are numbered 0 to 5"),
+    };
+}
+
+
+/// What a `Pin` does to its pin when the handle is dropped, including
+/// while unwinding from a panic.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum DropPolicy {
+    /// Leave the pin as the handle last set it. The default.
+    Keep,
+    /// Make the pin an input, so it stops driving whatever it's wired to.
+    SetInput,
+    /// Make the pin an input with no pull, as `GPIO::reset_pin` does.
+    Reset,
+    /// Put back the function, pull and level the pin had when the policy
+    /// was chosen.
+    RestorePrevious,
+}
+
+/// How a `Pin<Output>` drives its pin. The open modes are emulated by
+/// leaving the output latch at the driven level and switching the pin
+/// between output and input, so a released pin is only held by its pull.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum OutputMode {
+    /// Drive the pin both high and low. The default.
+    PushPull,
+    /// Drive the pin low, and for high release it to its pull-up, as on a
+    /// bus shared with other open-drain outputs such as I2C or 1-Wire, or
+    /// with an external pull-up to a higher voltage.
+    OpenDrain,
+    /// Drive the pin high, and for low release it to its pull-down.
+    OpenSource,
+}
+
+
+enum DropAction {
+    Keep,
+    SetInput,
+    Reset,
+    Restore(PinSnapshot),
+}
+
+// The words of GPSET, GPCLR and GPLEV holding an output's bit, and the bit,
+// worked out once by `Pin::fast_path`.
+struct FastPath {
+    set: *mut u32,
+    clear: *mut u32,
+    level: *const u32,
+    mask: u32,
+}
+
+// The pointers are into the `GPIO`'s register block, which the handle
+// borrows, and only reached through volatile word accesses.
+unsafe impl Send for FastPath {}
+unsafe impl Sync for FastPath {}
+
+
+/// A handle to one pin whose mode is tracked in its type, so that e.g.
+/// `set_high` only exists on `Pin<Output>`. Changing mode consumes the
+/// handle and writes the pin's GPFSEL field. The pin stays claimed until
+/// the handle is dropped, when its `DropPolicy` is applied.
+pub struct Pin<'a, Mode> {
+    gpio: &'a GPIO,
+    pin: u32,
+    mode: PhantomData<Mode>,
+    on_drop: DropAction,
+    fast: Option<FastPath>,
+    output_mode: OutputMode,
+}
+
+impl<'a, Mode> Pin<'a, Mode> {
+
+    fn into_mode<NewMode>(mut self, function: PinFunction) -> Result<Pin<'a, NewMode>, Error> {
+        self.leave_fast_path();
+        self.gpio.set_function(self.pin, function)?;
+        let on_drop = std::mem::replace(&mut self.on_drop, DropAction::Keep);
+        let pin = Pin {
+            gpio: self.gpio, pin: self.pin, mode: PhantomData, on_drop, fast: None, output_mode: OutputMode::PushPull,
+        };
+        // The claim and drop policy move to the new handle.
+        std::mem::forget(self);
+        Ok(pin)
+    }
+
+    /// Chooses what happens to the pin when this handle, or the handle it
+    /// turns into, is dropped, e.g. `SetInput` so a crashed control loop
+    /// doesn't leave a heater relay switched on. For `RestorePrevious`,
+    /// choose it before changing the pin's mode. Failures while dropping
+    /// are ignored.
+    pub fn on_drop(mut self, policy: DropPolicy) -> Result<Self, Error> {
+        self.on_drop = match policy {
+            DropPolicy::Keep => DropAction::Keep,
+            DropPolicy::SetInput => DropAction::SetInput,
+            DropPolicy::Reset => DropAction::Reset,
+            DropPolicy::RestorePrevious => DropAction::Restore(self.gpio.snapshot_pins(&[self.pin])?),
+        };
+        Ok(self)
+    }
+
+    /// Whether dropping the handle resets the pin to an input with no
+    /// pull, the `DropPolicy::Reset` policy, rather than leaving it as it
+    /// was last set. Off by default, for pins meant to stay configured
+    /// after their handle is gone. Turning it off only undoes a `Reset`
+    /// policy; any other drop policy is left in place.
+    pub fn auto_reset(mut self, enabled: bool) -> Self {
+        if enabled {
+            self.on_drop = DropAction::Reset;
+        } else if let DropAction::Reset = self.on_drop {
+            self.on_drop = DropAction::Keep;
+        }
+        self
+    }
+
+    // Brings the `GPIO`'s record of driven levels up to date with what the
+    // fast path drove, which it doesn't track.
+    fn leave_fast_path(&mut self) {
+        if let Some(fast) = self.fast.take() {
+            let bit = 1 << self.pin;
+            if unsafe { fast.level.read_volatile() } & fast.mask != 0 {
+                self.gpio.driven_levels.fetch_or(bit, Ordering::SeqCst);
+            } else {
+                self.gpio.driven_levels.fetch_and(!bit, Ordering::SeqCst);
+            }
+            self.gpio.driven_pins.fetch_or(bit, Ordering::SeqCst);
+        }
+    }
+
+    pub fn number(&self) -> u32 {
+        self.pin
+    }
+
+    pub(crate) fn gpio(&self) -> &'a GPIO {
+        self.gpio
+    }
+
+    pub fn into_input(self) -> Result<Pin<'a, Input>, Error> {
+        self.into_mode(PinFunction::Input)
+    }
+
+    pub fn into_output(self) -> Result<Pin<'a, Output>, Error> {
+        self.into_mode(PinFunction::Output)
+    }
+
+    pub fn into_alt<const N: u8>(self) -> Result<Pin<'a, Alt<N>>, Error> {
+        self.into_mode(Alt::<N>::FUNCTION)
+    }
+}
+
+impl<'a, Mode> Drop for Pin<'a, Mode> {
+    fn drop(&mut self) {
+        self.leave_fast_path();
+        let _ = match &self.on_drop {
+            DropAction::Keep => Ok(()),
+            DropAction::SetInput => self.gpio.set_function(self.pin, PinFunction::Input),
+            DropAction::Reset => self.gpio.reset_pin(self.pin),
+            DropAction::Restore(snapshot) => self.gpio.restore(snapshot),
+        };
+        self.gpio.claimed_pins.fetch_and(!(1 << self.pin), Ordering::SeqCst);
+    }
+}
+
+impl<'a, Mode> fmt::Display for Pin<'a, Mode> {
+    // The pin's function and level as they read now, e.g. "GPIO17 (output,
+    // high)", with "?" for either if it can't be read.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let function = match self.gpio.get_function(self.pin) {
+            Ok(function) => format!("{:?}", function).to_lowercase(),
+            Err(_) => "?".to_string(),
+        };
+        let level = match self.gpio.read(self.pin) {
+            Ok(true) => "high",
+            Ok(false) => "low",
+            Err(_) => "?",
+        };
+        write!(f, "GPIO{} ({}, {})", self.pin, function, level)
+    }
+}
+
+impl<'a> Pin<'a, Input> {
+
+    pub fn read(&self) -> Result<bool, Error> {
+        self.gpio.read(self.pin)
+    }
+
+    pub fn set_pull(&self, pull: Pull) -> Result<(), Error> {
+        self.gpio.set_pull(self.pin, pull)
+    }
+
+    /// See `GPIO::set_debounce`.
+    pub fn set_debounce(&self, period: Duration) -> Result<(), Error> {
+        self.gpio.set_debounce(self.pin, period)
+    }
+
+    /// See `GPIO::wait_for_edge`.
+    pub fn wait_for_edge(&self, trigger: Trigger, timeout: Option<Duration>) -> Result<PinEvent, Error> {
+        self.gpio.wait_for_edge(self.pin, trigger, timeout)
+    }
+
+    #[cfg(feature = "async")]
+    pub fn edge_events(&self, trigger: Trigger) -> Result<crate::EdgeEvents<'a>, Error> {
+        self.gpio.edge_events(self.pin, trigger)
+    }
+
+    #[cfg(feature = "async")]
+    pub async fn wait_for_edge_async(&self, trigger: Trigger) -> Result<PinEvent, Error> {
+        self.gpio.wait_for_edge_async(self.pin, trigger).await
+    }
+}
+
+impl<'a> Pin<'a, Output> {
+
+    /// Works out the pin's GPSET and GPCLR words and bit once, so that
+    /// from then on `set_high` and `set_low` are each a single store, for
+    /// bit-banging at the highest rate the bus allows. Those stores skip
+    /// the write policy, the register trace and the `tracing` events, and
+    /// aren't recorded as driven levels: `output_state` and `toggle` read
+    /// the level back from GPLEV instead.
+    ///
+    /// Fails on pin-level backends, on a read-only `GPIO`, on an
+    /// open-drain or open-source pin, and while a write policy is
+    /// installed. A policy installed after this call isn't consulted.
+    pub fn fast_path(mut self) -> Result<Self, Error> {
+        if self.output_mode != OutputMode::PushPull {
+            return Err(Error::Other(format!("pin {} is {:?}, which has no fast path", self.pin, self.output_mode)));
+        }
+        self.gpio.check_writable()?;
+        self.gpio.check_not_reserved(self.pin)?;
+        if self.gpio.write_policy.lock().unwrap().is_some() {
+            return Err(Error::WriteRejected(format!(
+                "pin {} can't bypass the write policy with a fast path", self.pin)));
+        }
+        let ptr = |register| self.gpio.register_ptr(register, self.pin);
+        self.fast = Some(FastPath {
+            set: ptr(Register::GPSET)?,
+            clear: ptr(Register::GPCLR)?,
+            level: ptr(Register::GPLEV)?,
+            mask: 1 << (self.pin % 32),
+        });
+        Ok(self)
+    }
+
+    /// Switches how the pin is driven. An open-drain pin gets its pull-up
+    /// and an open-source pin its pull-down, and either starts released
+    /// unless it was already driving its active level. Fails on a fast
+    /// path.
+    pub fn with_output_mode(mut self, mode: OutputMode) -> Result<Self, Error> {
+        if self.fast.is_some() {
+            return Err(Error::Other(format!("pin {} is on a fast path, which is push-pull only", self.pin)));
+        }
+        let (pull, active) = match mode {
+            OutputMode::PushPull => {
+                self.gpio.set_function(self.pin, PinFunction::Output)?;
+                self.output_mode = mode;
+                return Ok(self);
+            }
+            OutputMode::OpenDrain => (Pull::Up, false),
+            OutputMode::OpenSource => (Pull::Down, true),
+        };
+        // Release the pin before loading the latch, so it never drives the
+        // inactive level.
+        if self.output_state() != Some(active) {
+            self.gpio.set_function(self.pin, PinFunction::Input)?;
+        }
+        self.gpio.set_pull(self.pin, pull)?;
+        if active { self.gpio.set_high(self.pin)? } else { self.gpio.set_low(self.pin)? }
+        self.output_mode = mode;
+        Ok(self)
+    }
+
+    pub fn output_mode(&self) -> OutputMode {
+        self.output_mode
+    }
+
+    // Drives an open pin to its active level, or releases it.
+    fn drive_open(&self, drive: bool) -> Result<(), Error> {
+        let function = if drive { PinFunction::Output } else { PinFunction::Input };
+        self.gpio.set_function(self.pin, function)
+    }
+
+    #[inline]
+    pub fn set_high(&self) -> Result<(), Error> {
+        match (&self.fast, self.output_mode) {
+            (Some(fast), _) => {
+                unsafe { fast.set.write_volatile(fast.mask) };
+                Ok(())
+            }
+            (None, OutputMode::PushPull) => self.gpio.set_high(self.pin),
+            (None, OutputMode::OpenDrain) => self.drive_open(false),
+            (None, OutputMode::OpenSource) => self.drive_open(true),
+        }
+    }
+
+    #[inline]
+    pub fn set_low(&self) -> Result<(), Error> {
+        match (&self.fast, self.output_mode) {
+            (Some(fast), _) => {
+                unsafe { fast.clear.write_volatile(fast.mask) };
+                Ok(())
+            }
+            (None, OutputMode::PushPull) => self.gpio.set_low(self.pin),
+            (None, OutputMode::OpenDrain) => self.drive_open(true),
+            (None, OutputMode::OpenSource) => self.drive_open(false),
+        }
+    }
+
+    pub fn toggle(&self) -> Result<(), Error> {
+        match (&self.fast, self.output_mode) {
+            (Some(fast), _) => {
+                let register = if unsafe { fast.level.read_volatile() } & fast.mask != 0 { fast.clear } else { fast.set };
+                unsafe { register.write_volatile(fast.mask) };
+                Ok(())
+            }
+            (None, OutputMode::PushPull) => self.gpio.toggle(self.pin),
+            (None, _) => if self.output_state() == Some(true) { self.set_low() } else { self.set_high() },
+        }
+    }
+
+    /// The level last driven through this `GPIO`, if any, or on a fast path
+    /// the level the pin reads. An open pin that's released reports the
+    /// level of its pull, whatever else may be driving the line; `read`
+    /// gives the line's actual level.
+    pub fn output_state(&self) -> Option<bool> {
+        if let Some(fast) = &self.fast {
+            return Some(unsafe { fast.level.read_volatile() } & fast.mask != 0);
+        }
+        // The pin was validated when the handle was claimed.
+        let driving = || self.gpio.get_function(self.pin).ok().map(|function| function == PinFunction::Output);
+        match self.output_mode {
+            OutputMode::PushPull => self.gpio.output_state(self.pin).ok().flatten(),
+            OutputMode::OpenDrain => driving().map(|driving| !driving),
+            OutputMode::OpenSource => driving(),
+        }
+    }
+
+    pub fn read(&self) -> Result<bool, Error> {
+        self.gpio.read(self.pin)
+    }
+}
+
+
+impl GPIO {
+
+    /// A handle to `pin` that leaves its current function alone until one
+    /// of the `into_*` methods is called. Fails if another handle to the
+    /// same pin is still alive.
+    pub fn pin(&self, pin: u32) -> Result<Pin<'_, Unconfigured>, Error> {
+        self.check_board_pin(pin)?;
+        if self.claimed_pins.fetch_or(1 << pin, Ordering::SeqCst) & (1 << pin) != 0 {
+            return Err(Error::PinInUse(pin));
+        }
+        Ok(Pin { gpio: self, pin, mode: PhantomData, on_drop: DropAction::Keep, fast: None, output_mode: OutputMode::PushPull })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pin_mode_transitions() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+
+        let led = gpio.pin(17).unwrap().into_output().unwrap();
+        assert_eq!(gpio.get_function(17).ok(), Some(PinFunction::Output));
+        led.set_high().unwrap();
+        assert_eq!(led.output_state(), Some(true));
+        led.toggle().unwrap();
+        assert_eq!(led.output_state(), Some(false));
+
+        let button = led.into_input().unwrap();
+        assert_eq!(gpio.get_function(17).ok(), Some(PinFunction::Input));
+        button.set_pull(Pull::Up).unwrap();
+        assert_eq!(gpio.get_pull(17).ok(), Some(Pull::Up));
+        assert_eq!(button.read().ok(), Some(false));
+
+        let uart = gpio.pin(14).unwrap().into_alt::<0>().unwrap();
+        assert_eq!(uart.number(), 14);
+        assert_eq!(gpio.get_function(14).ok(), Some(PinFunction::Alt0));
+        gpio.pin(15).unwrap().into_alt::<5>().unwrap();
+        assert_eq!(gpio.get_function(15).ok(), Some(PinFunction::Alt5));
+    }
+
+    #[test]
+    fn test_pin_out_of_range() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        assert!(matches!(gpio.pin(58).err(), Some(Error::InvalidPin { pin: 58, .. })));
+    }
+
+    #[test]
+    fn test_pin_claims() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let led = gpio.pin(17).unwrap().into_output().unwrap();
+        assert!(matches!(gpio.pin(17).err(), Some(Error::PinInUse(17))));
+        assert!(gpio.pin(18).is_ok());
+
+        let led = led.into_input().unwrap();
+        assert!(gpio.pin(17).is_err());
+        drop(led);
+        assert!(gpio.pin(17).is_ok());
+
+        // A failed mode change releases the claim with the consumed handle.
+        let pin = gpio.pin(20).unwrap();
+        gpio.set_write_policy(DenyAll);
+        assert!(pin.into_output().is_err());
+        assert!(gpio.pin(20).is_ok());
+    }
+
+    #[test]
+    fn test_drop_policies() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let relay = gpio.pin(17).unwrap().on_drop(DropPolicy::SetInput).unwrap().into_output().unwrap();
+        relay.set_high().unwrap();
+        drop(relay);
+        assert_eq!(gpio.get_function(17).ok(), Some(PinFunction::Input));
+
+        gpio.set_function(22, PinFunction::Output).unwrap();
+        gpio.set_low(22).unwrap();
+        gpio.set_pull(22, Pull::Down).unwrap();
+        let pin = gpio.pin(22).unwrap().on_drop(DropPolicy::RestorePrevious).unwrap().into_input().unwrap();
+        pin.set_pull(Pull::Up).unwrap();
+        drop(pin);
+        assert_eq!(gpio.get_function(22).ok(), Some(PinFunction::Output));
+        assert_eq!(gpio.get_pull(22).ok(), Some(Pull::Down));
+        assert_eq!(gpio.output_state(22).ok(), Some(Some(false)));
+
+        let kept = gpio.pin(23).unwrap().into_output().unwrap();
+        drop(kept);
+        assert_eq!(gpio.get_function(23).ok(), Some(PinFunction::Output));
+
+        // The policy is applied while unwinding too.
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _heater = gpio.pin(24).unwrap().on_drop(DropPolicy::SetInput).unwrap().into_output().unwrap();
+            panic!("control loop crashed");
+        }));
+        assert!(result.is_err());
+        assert_eq!(gpio.get_function(24).ok(), Some(PinFunction::Input));
+        assert!(gpio.pin(24).is_ok());
+    }
+
+    #[test]
+    fn test_auto_reset() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let sensor = gpio.pin(17).unwrap().auto_reset(true).into_input().unwrap();
+        sensor.set_pull(Pull::Up).unwrap();
+        drop(sensor);
+        assert_eq!(gpio.get_function(17).ok(), Some(PinFunction::Input));
+        assert_eq!(gpio.get_pull(17).ok(), Some(Pull::None));
+
+        let led = gpio.pin(18).unwrap().auto_reset(true).auto_reset(false).into_output().unwrap();
+        gpio.set_pull(18, Pull::Down).unwrap();
+        drop(led);
+        assert_eq!(gpio.get_function(18).ok(), Some(PinFunction::Output));
+        assert_eq!(gpio.get_pull(18).ok(), Some(Pull::Down));
+
+        let relay = gpio.pin(19).unwrap().on_drop(DropPolicy::SetInput).unwrap().auto_reset(false).into_output().unwrap();
+        drop(relay);
+        assert_eq!(gpio.get_function(19).ok(), Some(PinFunction::Input));
+    }
+
+    #[test]
+    fn test_display() {
+        let mock = crate::MockGpio::new();
+        let gpio = mock.gpio().unwrap();
+        let led = gpio.pin(17).unwrap().into_output().unwrap();
+        led.set_high().unwrap();
+        assert_eq!(led.to_string(), "GPIO17 (output, high)");
+        led.set_low().unwrap();
+        assert_eq!(led.to_string(), "GPIO17 (output, low)");
+
+        mock.set_input(4, true);
+        let button = gpio.pin(4).unwrap().into_input().unwrap();
+        assert_eq!(button.to_string(), "GPIO4 (input, high)");
+        let uart = gpio.pin(14).unwrap().into_alt::<0>().unwrap();
+        assert_eq!(uart.to_string(), "GPIO14 (alt0, low)");
+    }
+
+    #[test]
+    fn test_fast_path() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let clock = gpio.pin(40).unwrap().into_output().unwrap().fast_path().unwrap();
+        clock.set_high().unwrap();
+        assert_eq!(unsafe { gpio.read_raw(0x20) }, 1 << 8);
+        clock.set_low().unwrap();
+        assert_eq!(unsafe { gpio.read_raw(0x2c) }, 1 << 8);
+        // The test block's GPLEV doesn't follow GPSET and GPCLR.
+        unsafe { gpio.write_raw(0x38, 1 << 8) };
+        assert_eq!(clock.output_state(), Some(true));
+        unsafe { gpio.write_raw(0x2c, 0) };
+        clock.toggle().unwrap();
+        assert_eq!(unsafe { gpio.read_raw(0x2c) }, 1 << 8);
+        drop(clock);
+        assert_eq!(gpio.output_state(40).ok(), Some(Some(true)));
+
+        let pin = gpio.pin(17).unwrap().into_output().unwrap();
+        gpio.set_write_policy(DenyAll);
+        assert!(matches!(pin.fast_path().err(), Some(Error::WriteRejected(_))));
+    }
+
+    #[test]
+    fn test_open_drain_and_open_source() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let data = gpio.pin(4).unwrap().into_output().unwrap().with_output_mode(OutputMode::OpenDrain).unwrap();
+        assert_eq!(gpio.get_pull(4).ok(), Some(Pull::Up));
+        assert_eq!(gpio.get_function(4).ok(), Some(PinFunction::Input));
+        assert_eq!(gpio.output_state(4).ok(), Some(Some(false)));
+        assert_eq!(data.output_state(), Some(true));
+        data.set_low().unwrap();
+        assert_eq!(gpio.get_function(4).ok(), Some(PinFunction::Output));
+        assert_eq!(data.output_state(), Some(false));
+        data.toggle().unwrap();
+        assert_eq!(gpio.get_function(4).ok(), Some(PinFunction::Input));
+        assert!(data.fast_path().is_err());
+
+        // A pin already driving its active level keeps driving it.
+        let enable = gpio.pin(5).unwrap().into_output().unwrap();
+        enable.set_high().unwrap();
+        let enable = enable.with_output_mode(OutputMode::OpenSource).unwrap();
+        assert_eq!(gpio.get_function(5).ok(), Some(PinFunction::Output));
+        assert_eq!(gpio.get_pull(5).ok(), Some(Pull::Down));
+        assert_eq!(enable.output_state(), Some(true));
+        enable.set_low().unwrap();
+        assert_eq!(gpio.get_function(5).ok(), Some(PinFunction::Input));
+        assert_eq!(enable.output_state(), Some(false));
+        enable.set_high().unwrap();
+        assert_eq!(gpio.output_state(5).ok(), Some(Some(true)));
+
+        let enable = enable.with_output_mode(OutputMode::PushPull).unwrap();
+        enable.set_low().unwrap();
+        assert_eq!(enable.output_state(), Some(false));
+        assert!(enable.fast_path().unwrap().with_output_mode(OutputMode::OpenDrain).is_err());
+    }
+
+    struct DenyAll;
+
+    impl crate::WritePolicy for DenyAll {
+        fn check(&self, _intended: &crate::PinChange, _state: &crate::BoardState) -> Result<(), Error> {
+            Err(Error::WriteRejected("denied".to_string()))
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use crate::{EdgeTrigger, Error, PinFunction, PinSetup, Pull, GPIO};
+
+use std::collections::HashSet;
+#[cfg(feature = "serde")]
+use std::path::Path;
+
+
+/// How one pin should be set up, for a `PinConfigSet`. With the `serde`
+/// feature only `pin` and `function` are required; the pull defaults to
+/// none.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(deny_unknown_fields))]
+pub struct PinConfig {
+    pub pin: u32,
+    /// What the pin is used for, e.g. `"motor enable"`, so the set can be
+    /// looked up by role rather than number.
+    pub name: Option<String>,
+    pub function: PinFunction,
+    #[cfg_attr(feature = "serde", serde(default = "no_pull"))]
+    pub pull: Pull,
+    /// The level an output starts at. Only outputs may have one.
+    pub initial_level: Option<bool>,
+    pub edge: Option<EdgeTrigger>,
+}
+
+impl PinConfig {
+
+    /// `pin` as `function`, with no pull, level or edge detection.
+    pub fn new(pin: u32, function: PinFunction) -> Self {
+        Self { pin, name: None, function, pull: Pull::None, initial_level: None, edge: None }
+    }
+
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn with_pull(mut self, pull: Pull) -> Self {
+        self.pull = pull;
+        self
+    }
+
+    pub fn with_initial_level(mut self, level: bool) -> Self {
+        self.initial_level = Some(level);
+        self
+    }
+
+    pub fn with_edge(mut self, edge: EdgeTrigger) -> Self {
+        self.edge = Some(edge);
+        self
+    }
+
+    fn setup(&self) -> PinSetup {
+        PinSetup {
+            pin: self.pin,
+            function: self.function,
+            pull: self.pull,
+            initial_level: self.initial_level,
+            edge: self.edge,
+        }
+    }
+}
+
+
+#[cfg(feature = "serde")]
+fn no_pull() -> Pull {
+    Pull::None
+}
+
+
+/// The setup of a board's pins as data, e.g. built from a deployment's
+/// configuration file, for `GPIO::apply_config`. With the `serde` feature
+/// it's read from TOML as an array of `[[pins]]` tables, or from JSON as
+/// `{"pins": [...]}`, with functions, pulls and edges in snake case:
+///
+/// ```toml
+/// [[pins]]
+/// pin = 17
+/// name = "motor enable"
+/// function = "output"
+/// initial_level = false
+///
+/// [[pins]]
+/// pin = 22
+/// function = "input"
+/// pull = "up"
+/// edge = "falling"
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(deny_unknown_fields))]
+pub struct PinConfigSet {
+    pins: Vec<PinConfig>,
+}
+
+impl PinConfigSet {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_pin(mut self, config: PinConfig) -> Self {
+        self.pins.push(config);
+        self
+    }
+
+    pub fn pins(&self) -> &[PinConfig] {
+        &self.pins
+    }
+
+    /// The pin configured under `name`.
+    pub fn get(&self, name: &str) -> Option<&PinConfig> {
+        self.pins.iter().find(|config| config.name.as_deref() == Some(name))
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn from_toml(text: &str) -> Result<Self, Error> {
+        toml::from_str(text).map_err(|e| Error::Other(format!("invalid pin configuration: {}", e)))
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn from_json(text: &str) -> Result<Self, Error> {
+        serde_json::from_str(text).map_err(|e| Error::Other(format!("invalid pin configuration: {}", e)))
+    }
+
+    /// Reads a set from a `.toml` or `.json` file, by its extension.
+    #[cfg(feature = "serde")]
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| Error::from_io(format!("failed to read {}", path.display()), e))?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => Self::from_toml(&text),
+            Some("json") => Self::from_json(&text),
+            _ => Err(Error::Unsupported(format!("{} is neither a .toml nor a .json file", path.display()))),
+        }
+    }
+
+    /// Checks the set is consistent and every pin exists on `gpio`'s SoC,
+    /// so nothing is written for a set that can't be applied in full.
+    fn check(&self, gpio: &GPIO) -> Result<(), Error> {
+        let mut pins = HashSet::new();
+        let mut names = HashSet::new();
+        for config in &self.pins {
+            gpio.check_board_pin(config.pin)?;
+            if !pins.insert(config.pin) {
+                return Err(Error::Other(format!("pin {} is configured more than once", config.pin)));
+            }
+            if let Some(name) = &config.name {
+                if !names.insert(name) {
+                    return Err(Error::Other(format!("more than one pin is named {:?}", name)));
+                }
+            }
+            if config.initial_level.is_some() && config.function != PinFunction::Output {
+                return Err(Error::Other(format!(
+                    "pin {} has an initial level but is configured as {:?}", config.pin, config.function)));
+            }
+        }
+        Ok(())
+    }
+}
+
+
+impl GPIO {
+
+    /// Sets up every pin in `config` as `configure` does, after checking
+    /// the whole set: no pin or name may appear twice, and only outputs may
+    /// have an initial level.
+    pub fn apply_config(&self, config: &PinConfigSet) -> Result<(), Error> {
+        config.check(self)?;
+        let setups: Vec<PinSetup> = config.pins.iter().map(PinConfig::setup).collect();
+        self.configure(&setups)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_config() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let config = PinConfigSet::new()
+            .with_pin(PinConfig::new(17, PinFunction::Output).with_name("motor enable").with_initial_level(false))
+            .with_pin(PinConfig::new(22, PinFunction::Input).with_name("limit switch").with_pull(Pull::Up)
+                .with_edge(EdgeTrigger::Falling))
+            .with_pin(PinConfig::new(14, PinFunction::Alt0));
+        gpio.apply_config(&config).unwrap();
+        assert_eq!(gpio.get_function(17).ok(), Some(PinFunction::Output));
+        assert_eq!(gpio.output_state(17).ok(), Some(Some(false)));
+        assert_eq!(gpio.get_pull(22).ok(), Some(Pull::Up));
+        assert_eq!(gpio.get_function(14).ok(), Some(PinFunction::Alt0));
+        assert_eq!(config.get("limit switch").map(|config| config.pin), Some(22));
+
+        let twice = config.clone().with_pin(PinConfig::new(17, PinFunction::Input));
+        assert!(gpio.apply_config(&twice).is_err());
+        let input_level = PinConfigSet::new()
+            .with_pin(PinConfig::new(5, PinFunction::Output))
+            .with_pin(PinConfig::new(6, PinFunction::Input).with_initial_level(true));
+        assert!(gpio.apply_config(&input_level).is_err());
+        assert_eq!(gpio.get_function(5).ok(), Some(PinFunction::Input));
+        assert!(gpio.apply_config(&PinConfigSet::new().with_pin(PinConfig::new(58, PinFunction::Input))).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_load_config() {
+        let expected = PinConfigSet::new()
+            .with_pin(PinConfig::new(17, PinFunction::Output).with_name("motor enable").with_initial_level(false))
+            .with_pin(PinConfig::new(22, PinFunction::Input).with_pull(Pull::Up).with_edge(EdgeTrigger::Falling))
+            .with_pin(PinConfig::new(14, PinFunction::Alt0));
+        let toml = r#"
+            [[pins]]
+            pin = 17
+            name = "motor enable"
+            function = "output"
+            initial_level = false
+
+            [[pins]]
+            pin = 22
+            function = "input"
+            pull = "up"
+            edge = "falling"
+
+            [[pins]]
+            pin = 14
+            function = "alt0"
+        "#;
+        assert_eq!(PinConfigSet::from_toml(toml).ok(), Some(expected.clone()));
+        let json = serde_json::to_string(&expected).unwrap();
+        assert!(json.contains(r#""function":"alt0""#));
+        assert_eq!(PinConfigSet::from_json(&json).ok(), Some(expected.clone()));
+        assert!(PinConfigSet::from_toml("[[pins]]\npin = 4\nfunction = \"sideways\"").is_err());
+        assert!(PinConfigSet::from_json(r#"{"pins": [{"pin": 4, "function": "input", "pul": "up"}]}"#).is_err());
+
+        let load = |extension: &str| {
+            let path = std::env::temp_dir().join(format!("rustberrypi-pins-{}.{}", std::process::id(), extension));
+            std::fs::write(&path, toml).unwrap();
+            let loaded = PinConfigSet::load(&path);
+            std::fs::remove_file(&path).unwrap();
+            loaded
+        };
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        gpio.apply_config(&load("toml").unwrap()).unwrap();
+        assert_eq!(gpio.get_pull(22).ok(), Some(Pull::Up));
+        assert!(matches!(load("yaml"), Err(Error::Unsupported(_))));
+        assert!(matches!(PinConfigSet::load("/nonexistent/pins.json"), Err(Error::Io { .. })));
+    }
+}
@@ -0,0 +1,140 @@
+use crate::{Error, PinFunction, GPIO};
+
+use std::sync::atomic::Ordering;
+
+
+/// An ordered set of pins read and written as one integer, e.g. the data
+/// lines of a parallel bus. Bit `i` of a value maps to the `i`th pin.
+/// Writes go through `GPIO::write_mask`, so pins in the same bank change
+/// together. The pins stay claimed until the group is dropped.
+pub struct PinGroup<'a> {
+    gpio: &'a GPIO,
+    pins: Vec<u32>,
+}
+
+impl<'a> PinGroup<'a> {
+
+    pub fn pins(&self) -> &[u32] {
+        &self.pins
+    }
+
+    pub fn len(&self) -> usize {
+        self.pins.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pins.is_empty()
+    }
+
+    fn set_function(&self, function: PinFunction) -> Result<(), Error> {
+        for &pin in &self.pins {
+            self.gpio.set_function(pin, function)?;
+        }
+        Ok(())
+    }
+
+    pub fn set_output(&self) -> Result<(), Error> {
+        self.set_function(PinFunction::Output)
+    }
+
+    pub fn set_input(&self) -> Result<(), Error> {
+        self.set_function(PinFunction::Input)
+    }
+
+    /// Drives the group to `value`. Fails if `value` has bits set beyond
+    /// the group's width.
+    pub fn write(&self, value: u64) -> Result<(), Error> {
+        if self.pins.len() < 64 && value >> self.pins.len() != 0 {
+            return Err(Error::Other(format!("{:#x} doesn't fit in a {}-pin group", value, self.pins.len())));
+        }
+        let (mut set, mut clear) = (0, 0);
+        for (bit, &pin) in self.pins.iter().enumerate() {
+            if value & (1 << bit) != 0 {
+                set |= 1 << pin;
+            } else {
+                clear |= 1 << pin;
+            }
+        }
+        self.gpio.write_mask(set, clear)
+    }
+
+    /// Samples every pin in the group at once.
+    pub fn read(&self) -> Result<u64, Error> {
+        let levels = self.gpio.read_all()?;
+        Ok(self.pins.iter().enumerate()
+            .filter(|&(_, &pin)| levels & (1 << pin) != 0)
+            .fold(0, |value, (bit, _)| value | (1 << bit)))
+    }
+}
+
+impl<'a> Drop for PinGroup<'a> {
+    fn drop(&mut self) {
+        let mask = self.pins.iter().fold(0, |mask, &pin| mask | (1 << pin));
+        self.gpio.claimed_pins.fetch_and(!mask, Ordering::SeqCst);
+    }
+}
+
+
+impl GPIO {
+
+    /// Claims `pins`, in bit order, as a `PinGroup`. Their functions are
+    /// left alone until `set_output` or `set_input` is called. Fails if a
+    /// pin is listed twice or is claimed elsewhere.
+    pub fn pin_group(&self, pins: &[u32]) -> Result<PinGroup<'_>, Error> {
+        let mut mask = 0u64;
+        for &pin in pins {
+            self.check_board_pin(pin)?;
+            if mask & (1 << pin) != 0 {
+                return Err(Error::PinInUse(pin));
+            }
+            mask |= 1 << pin;
+        }
+        let claimed = self.claimed_pins.fetch_or(mask, Ordering::SeqCst);
+        if claimed & mask != 0 {
+            // Only give back the pins this call claimed.
+            self.claimed_pins.fetch_and(!(mask & !claimed), Ordering::SeqCst);
+            return Err(Error::PinInUse((claimed & mask).trailing_zeros()));
+        }
+        Ok(PinGroup { gpio: self, pins: pins.to_vec() })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pin_group_write_and_read() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let bus = gpio.pin_group(&[9, 3, 40]).unwrap();
+        bus.set_output().unwrap();
+        assert_eq!(gpio.get_function(40).ok(), Some(PinFunction::Output));
+
+        bus.write(0b101).unwrap();
+        assert_eq!(gpio.output_state(9).ok(), Some(Some(true)));
+        assert_eq!(gpio.output_state(3).ok(), Some(Some(false)));
+        assert_eq!(gpio.output_state(40).ok(), Some(Some(true)));
+        assert!(bus.write(0b1000).is_err());
+
+        unsafe {
+            gpio.write_raw(0x34, 1 << 3);
+            gpio.write_raw(0x38, 1 << 8);
+        }
+        assert_eq!(bus.read().ok(), Some(0b110));
+    }
+
+    #[test]
+    fn test_pin_group_claims_pins() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let pin = gpio.pin(5).unwrap();
+        assert!(matches!(gpio.pin_group(&[4, 5]), Err(Error::PinInUse(5))));
+        assert!(matches!(gpio.pin_group(&[6, 6]), Err(Error::PinInUse(6))));
+        // The failed call didn't keep pin 4.
+        let group = gpio.pin_group(&[4]).unwrap();
+        assert!(gpio.pin(4).is_err());
+        drop(group);
+        drop(pin);
+        assert!(gpio.pin_group(&[4, 5]).is_ok());
+    }
+}
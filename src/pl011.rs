@@ -0,0 +1,372 @@
+use crate::mailbox::{FirmwareClock, Mailbox};
+use crate::region::MappedRegion;
+use crate::{detect_peripheral_base, Error, PinFunction, GPIO, DEV_MEM_PATH};
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+
+const UART0_BASE_OFFSET: i64 = 0x201000;
+const UART_BLOCK_LEN: usize = 0x48;
+
+const UART_DR: usize = 0x00;
+const UART_FR: usize = 0x18;
+const UART_IBRD: usize = 0x24;
+const UART_FBRD: usize = 0x28;
+const UART_LCRH: usize = 0x2c;
+const UART_CR: usize = 0x30;
+const UART_IMSC: usize = 0x38;
+const UART_ICR: usize = 0x44;
+
+const DR_DATA_MASK: u32 = 0xff;
+const DR_FE: u32 = 1 << 8;
+const DR_PE: u32 = 1 << 9;
+const DR_BE: u32 = 1 << 10;
+const DR_OE: u32 = 1 << 11;
+
+const FR_BUSY: u32 = 1 << 3;
+const FR_RXFE: u32 = 1 << 4;
+const FR_TXFF: u32 = 1 << 5;
+
+const LCRH_PEN: u32 = 1 << 1;
+const LCRH_EPS: u32 = 1 << 2;
+const LCRH_STP2: u32 = 1 << 3;
+const LCRH_FEN: u32 = 1 << 4;
+const LCRH_WLEN_SHIFT: u32 = 5;
+
+const CR_UARTEN: u32 = 1;
+const CR_TXE: u32 = 1 << 8;
+const CR_RXE: u32 = 1 << 9;
+
+const ICR_ALL: u32 = 0x7ff;
+
+// TXD0 and RXD0.
+const UART0_PINS: [u32; 2] = [14, 15];
+
+// How long reconfiguring waits for a character in flight to go out.
+const IDLE_TIMEOUT: Duration = Duration::from_millis(100);
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+
+/// Counts of the receive errors seen since they were last taken.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct RxErrors {
+    /// Characters without a valid stop bit, usually from a baud rate
+    /// mismatch.
+    pub framing: u32,
+    pub parity: u32,
+    /// Break conditions, where the line was held low for a whole character.
+    pub breaks: u32,
+    /// Times the receive FIFO was full and characters were lost.
+    pub overruns: u32,
+}
+
+impl RxErrors {
+
+    pub fn is_empty(&self) -> bool {
+        *self == RxErrors::default()
+    }
+}
+
+
+// The baud rate divisor is the UART clock over 16 × baud, in 16.6 fixed
+// point: the top bits go in IBRD and the bottom six in FBRD.
+fn baud_divisor(clock_hz: u32, baud: u32) -> Result<u32, Error> {
+    if baud == 0 {
+        return Err(Error::Other("baud rate must be positive".to_string()));
+    }
+    let divisor = ((clock_hz as u64 * 4) as f64 / baud as f64).round() as u64;
+    if divisor >> 6 == 0 || divisor >> 6 > 0xffff {
+        return Err(Error::Other(format!("{} baud is out of the UART's range", baud)));
+    }
+    Ok(divisor as u32)
+}
+
+
+/// The PL011 UART (UART0) on GPIO 14 (TX) and 15 (RX). Unlike the mini
+/// UART its clock doesn't follow the core clock, and it handles 5 to 8 data
+/// bits, parity and two stop bits. Both FIFOs are 16 characters deep.
+///
+/// On boards with Bluetooth, UART0 drives the Bluetooth chip unless it's
+/// moved off with the `disable-bt` or `miniuart-bt` overlay.
+pub struct Pl011 {
+    region: MappedRegion,
+    clock_hz: u32,
+    divisor: u32,
+    data_bits: u8,
+    parity: Parity,
+    stop_bits: StopBits,
+    read_timeout: Option<Duration>,
+    errors: RxErrors,
+}
+
+impl Pl011 {
+
+    /// Sets UART0 up for `baud` 8N1 and switches GPIO 14 and 15 to it. The
+    /// UART clock rate is asked of the firmware.
+    pub fn new(gpio: &GPIO, baud: u32) -> Result<Self, Error> {
+        let clock_hz = Mailbox::open()?.clock_rate(FirmwareClock::Uart)?;
+        let base = detect_peripheral_base()?;
+        let uart = Self::open(DEV_MEM_PATH, base + UART0_BASE_OFFSET, clock_hz, baud)?;
+        gpio.switch_to_alt(&UART0_PINS, PinFunction::Alt0)?;
+        Ok(uart)
+    }
+
+    pub(crate) fn open(path: impl AsRef<Path>, phys_base: i64, clock_hz: u32, baud: u32) -> Result<Self, Error> {
+        let mut uart = Self {
+            region: MappedRegion::open(path.as_ref(), phys_base, UART_BLOCK_LEN)?,
+            clock_hz,
+            divisor: baud_divisor(clock_hz, baud)?,
+            data_bits: 8,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            read_timeout: None,
+            errors: RxErrors::default(),
+        };
+        uart.region.write_reg(UART_IMSC, 0)?;
+        uart.region.write_reg(UART_ICR, ICR_ALL)?;
+        uart.configure()?;
+        Ok(uart)
+    }
+
+    // The line settings can only change with the UART disabled and idle.
+    // Clearing FEN flushes the transmit FIFO, and the divisor only takes
+    // effect on the LCRH write that follows it.
+    fn configure(&mut self) -> Result<(), Error> {
+        self.region.write_reg(UART_CR, 0)?;
+        let started = Instant::now();
+        while self.region.read_reg(UART_FR)? & FR_BUSY != 0 {
+            if started.elapsed() > IDLE_TIMEOUT {
+                return Err(Error::Timeout("UART0 stayed busy".to_string()));
+            }
+            std::hint::spin_loop();
+        }
+        self.region.write_reg(UART_LCRH, 0)?;
+        self.region.write_reg(UART_IBRD, self.divisor >> 6)?;
+        self.region.write_reg(UART_FBRD, self.divisor & 0x3f)?;
+        let parity = match self.parity {
+            Parity::None => 0,
+            Parity::Even => LCRH_PEN | LCRH_EPS,
+            Parity::Odd => LCRH_PEN,
+        };
+        let stop = if self.stop_bits == StopBits::Two { LCRH_STP2 } else { 0 };
+        let word_length = (self.data_bits as u32 - 5) << LCRH_WLEN_SHIFT;
+        self.region.write_reg(UART_LCRH, word_length | LCRH_FEN | parity | stop)?;
+        self.region.write_reg(UART_CR, CR_UARTEN | CR_TXE | CR_RXE)
+    }
+
+    /// Sets the baud rate as close to `baud` as the divisor allows and
+    /// returns the rate achieved.
+    pub fn set_baud_rate(&mut self, baud: u32) -> Result<u32, Error> {
+        self.divisor = baud_divisor(self.clock_hz, baud)?;
+        self.configure()?;
+        Ok(self.baud_rate())
+    }
+
+    pub fn baud_rate(&self) -> u32 {
+        (self.clock_hz as u64 * 4 / self.divisor as u64) as u32
+    }
+
+    /// Sets the number of data bits, 5 to 8.
+    pub fn set_data_bits(&mut self, data_bits: u8) -> Result<(), Error> {
+        if !(5..=8).contains(&data_bits) {
+            return Err(Error::Other(format!("UART0 takes 5 to 8 data bits, not {}", data_bits)));
+        }
+        self.data_bits = data_bits;
+        self.configure()
+    }
+
+    pub fn data_bits(&self) -> u8 {
+        self.data_bits
+    }
+
+    pub fn set_parity(&mut self, parity: Parity) -> Result<(), Error> {
+        self.parity = parity;
+        self.configure()
+    }
+
+    pub fn parity(&self) -> Parity {
+        self.parity
+    }
+
+    pub fn set_stop_bits(&mut self, stop_bits: StopBits) -> Result<(), Error> {
+        self.stop_bits = stop_bits;
+        self.configure()
+    }
+
+    pub fn stop_bits(&self) -> StopBits {
+        self.stop_bits
+    }
+
+    /// How long `read` waits for each character; `None` waits forever.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout
+    }
+
+    fn flags(&self) -> Result<u32, Error> {
+        self.region.read_reg(UART_FR)
+    }
+
+    /// Whether a received character is waiting.
+    pub fn can_read(&self) -> Result<bool, Error> {
+        Ok(self.flags()? & FR_RXFE == 0)
+    }
+
+    /// Whether the transmit FIFO has room for a character.
+    pub fn can_write(&self) -> Result<bool, Error> {
+        Ok(self.flags()? & FR_TXFF == 0)
+    }
+
+    /// Whether everything written has left the transmitter.
+    pub fn is_tx_idle(&self) -> Result<bool, Error> {
+        Ok(self.flags()? & FR_BUSY == 0)
+    }
+
+    /// Returns the receive errors counted since the last call and resets
+    /// the counts.
+    pub fn take_rx_errors(&mut self) -> RxErrors {
+        std::mem::take(&mut self.errors)
+    }
+
+    /// Queues as much of `data` as fits in the transmit FIFO without
+    /// waiting and returns how many characters that was.
+    pub fn try_write(&mut self, data: &[u8]) -> Result<usize, Error> {
+        let mut written = 0;
+        while written < data.len() && self.can_write()? {
+            self.region.write_reg(UART_DR, data[written] as u32)?;
+            written += 1;
+        }
+        Ok(written)
+    }
+
+    /// Writes all of `data`, waiting for room in the FIFO as needed. The
+    /// last characters may still be going out when this returns; see
+    /// `flush`.
+    pub fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        let mut written = 0;
+        while written < data.len() {
+            let queued = self.try_write(&data[written..])?;
+            if queued == 0 {
+                std::hint::spin_loop();
+            }
+            written += queued;
+        }
+        Ok(())
+    }
+
+    /// Waits until the transmitter has sent everything written.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        while !self.is_tx_idle()? {
+            std::hint::spin_loop();
+        }
+        Ok(())
+    }
+
+    /// Takes whatever received characters are waiting, up to the length of
+    /// `buffer`, and returns how many there were. Characters received with
+    /// an error are still returned; the error is counted in `RxErrors`.
+    pub fn try_read(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        let mut read = 0;
+        while read < buffer.len() && self.can_read()? {
+            let data = self.region.read_reg(UART_DR)?;
+            self.errors.framing += (data & DR_FE != 0) as u32;
+            self.errors.parity += (data & DR_PE != 0) as u32;
+            self.errors.breaks += (data & DR_BE != 0) as u32;
+            self.errors.overruns += (data & DR_OE != 0) as u32;
+            buffer[read] = (data & DR_DATA_MASK) as u8;
+            read += 1;
+        }
+        Ok(read)
+    }
+
+    /// Fills `buffer`, waiting for each character. Fails with
+    /// `Error::Timeout` if a character takes longer than the read timeout
+    /// to arrive; the characters read by then are kept in `buffer`.
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        let mut read = 0;
+        let mut progress = Instant::now();
+        while read < buffer.len() {
+            let received = self.try_read(&mut buffer[read..])?;
+            if received > 0 {
+                read += received;
+                progress = Instant::now();
+                continue;
+            }
+            if let Some(timeout) = self.read_timeout {
+                if progress.elapsed() > timeout {
+                    return Err(Error::Timeout(format!("UART0 read {} of {} bytes", read, buffer.len())));
+                }
+            }
+            std::hint::spin_loop();
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_baud_divisor() {
+        assert_eq!(baud_divisor(48_000_000, 115_200).ok(), Some(26 << 6 | 3));
+        assert_eq!(baud_divisor(3_000_000, 9_600).ok(), Some(19 << 6 | 34));
+        assert!(baud_divisor(48_000_000, 4_000_000).is_err());
+        assert!(baud_divisor(48_000_000, 0).is_err());
+    }
+
+    #[test]
+    fn test_pl011() {
+        let path = std::env::temp_dir().join(format!("rustberrypi-pl011-{}", std::process::id()));
+        std::fs::write(&path, vec![0u8; 4096]).unwrap();
+        let mut uart = Pl011::open(&path, 0, 48_000_000, 115_200).ok().unwrap();
+        assert_eq!(uart.region.read_reg(UART_IBRD).ok(), Some(26));
+        assert_eq!(uart.region.read_reg(UART_LCRH).ok(), Some(3 << LCRH_WLEN_SHIFT | LCRH_FEN));
+        assert_eq!(uart.region.read_reg(UART_CR).ok(), Some(CR_UARTEN | CR_TXE | CR_RXE));
+        assert_eq!(uart.region.read_reg(UART_FBRD).ok(), Some(3));
+        assert_eq!(uart.baud_rate(), 115_176);
+        assert_eq!(uart.set_baud_rate(9_600).ok(), Some(9_600));
+
+        uart.set_data_bits(7).unwrap();
+        uart.set_parity(Parity::Even).unwrap();
+        uart.set_stop_bits(StopBits::Two).unwrap();
+        assert_eq!(uart.region.read_reg(UART_LCRH).ok(), Some(0x5e));
+        assert!(uart.set_data_bits(9).is_err());
+
+        // An empty FR: room to write and a character waiting.
+        uart.write(b"ok").unwrap();
+        uart.flush().unwrap();
+        uart.region.write_reg(UART_DR, DR_FE | DR_OE | 0x41).unwrap();
+        let mut buffer = [0u8; 2];
+        uart.read(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"AA");
+        assert_eq!(uart.take_rx_errors(), RxErrors { framing: 2, overruns: 2, ..RxErrors::default() });
+        assert!(uart.take_rx_errors().is_empty());
+
+        uart.region.write_reg(UART_FR, FR_RXFE | FR_TXFF).unwrap();
+        uart.set_read_timeout(Some(Duration::from_millis(1)));
+        assert_eq!(uart.try_write(b"x").ok(), Some(0));
+        assert!(matches!(uart.read(&mut buffer), Err(Error::Timeout(_))));
+        drop(uart);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
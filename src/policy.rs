@@ -0,0 +1,198 @@
+use crate::{Error, PinChange, PinFunction, GPIO};
+
+use std::sync::atomic::Ordering;
+
+
+/// The board state a `WritePolicy` sees when vetting a write: every pin's
+/// function and the output levels this `GPIO` has driven.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BoardState {
+    functions: Vec<PinFunction>,
+    driven_pins: u64,
+    driven_levels: u64,
+}
+
+impl BoardState {
+
+    // Fails for pins the board doesn't have.
+    fn check_pin(&self, pin: u32) -> Result<(), Error> {
+        if pin as usize >= self.functions.len() {
+            return Err(Error::InvalidPin { pin, register: None });
+        }
+        Ok(())
+    }
+
+    pub fn function(&self, pin: u32) -> Result<PinFunction, Error> {
+        self.check_pin(pin)?;
+        Ok(self.functions[pin as usize])
+    }
+
+    /// The level this `GPIO` last drove `pin` to, or `None` if it hasn't.
+    pub fn output_state(&self, pin: u32) -> Result<Option<bool>, Error> {
+        self.check_pin(pin)?;
+        if self.driven_pins & (1 << pin) == 0 {
+            return Ok(None);
+        }
+        Ok(Some(self.driven_levels & (1 << pin) != 0))
+    }
+
+    // Updates the state as if `change` had been made. Changes to pins the
+    // board doesn't have are left out; they're refused on their own.
+    fn apply(&mut self, change: &PinChange) {
+        if self.check_pin(change.pin()).is_err() {
+            return;
+        }
+        match *change {
+            PinChange::Function { pin, new, .. } => self.functions[pin as usize] = new,
+            PinChange::Level { pin, new, .. } => {
+                self.driven_pins |= 1 << pin;
+                if new { self.driven_levels |= 1 << pin } else { self.driven_levels &= !(1 << pin) }
+            }
+            PinChange::Pull { .. } => {}
+        }
+    }
+}
+
+
+/// A safety interlock consulted before every level or function write once
+/// installed with `GPIO::set_write_policy`. Returning an error vetoes the
+/// write and the error is passed back to the caller. A change made as part
+/// of a batch, such as `GPIO::write_mask`, is checked against the board as
+/// the rest of the batch leaves it, and the whole batch is refused if any
+/// of it is.
+pub trait WritePolicy {
+    fn check(&self, intended: &PinChange, current_state: &BoardState) -> Result<(), Error>;
+}
+
+
+impl GPIO {
+
+    pub fn set_write_policy(&self, policy: impl WritePolicy + Send + 'static) {
+        *self.write_policy.lock().unwrap() = Some(Box::new(policy));
+    }
+
+    pub fn clear_write_policy(&self) {
+        *self.write_policy.lock().unwrap() = None;
+    }
+
+    pub fn board_state(&self) -> Result<BoardState, Error> {
+        Ok(BoardState {
+            functions: self.board_pins().map(|pin| self.get_function(pin)).collect::<Result<_, _>>()?,
+            driven_pins: self.driven_pins.load(Ordering::SeqCst),
+            driven_levels: self.driven_levels.load(Ordering::SeqCst),
+        })
+    }
+
+    pub(crate) fn check_writable(&self) -> Result<(), Error> {
+        if self.read_only {
+            return Err(Error::WriteRejected("GPIO was opened read-only".to_string()));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_policy(&self, change: &PinChange) -> Result<(), Error> {
+        self.check_policies(std::slice::from_ref(change))
+    }
+
+    // Vets `changes`, to be made together. The caller holds `config_lock`
+    // until they've been written.
+    pub(crate) fn check_policies(&self, changes: &[PinChange]) -> Result<(), Error> {
+        let policy = self.write_policy.lock().unwrap();
+        let current = match policy.as_ref() {
+            Some(_) => Some(self.board_state()?),
+            None => None,
+        };
+        for (index, change) in changes.iter().enumerate() {
+            let result = self.check_writable()
+                .and_then(|()| self.check_not_reserved(change.pin()))
+                .and_then(|()| match (policy.as_ref(), &current) {
+                    (Some(policy), Some(current)) => {
+                        let mut state = current.clone();
+                        for (_, other) in changes.iter().enumerate().filter(|&(other, _)| other != index) {
+                            state.apply(other);
+                        }
+                        policy.check(change, &state)
+                    }
+                    _ => Ok(()),
+                });
+            #[cfg(feature = "tracing")]
+            if let Err(e) = &result {
+                tracing::warn!(pin = change.pin(), "{} refused: {}", change, e);
+            }
+            result?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct ShootThroughGuard {
+        high_side: u32,
+        low_side: u32,
+    }
+
+    impl WritePolicy for ShootThroughGuard {
+        fn check(&self, intended: &PinChange, current_state: &BoardState) -> Result<(), Error> {
+            let other = match *intended {
+                PinChange::Level { pin, new: true, .. } if pin == self.high_side => self.low_side,
+                PinChange::Level { pin, new: true, .. } if pin == self.low_side => self.high_side,
+                _ => return Ok(()),
+            };
+            match current_state.output_state(other)? {
+                Some(true) => Err(Error::WriteRejected(format!("shoot-through: {} while pin {} is high", intended, other))),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_shoot_through_guard_rejects_both_high() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        gpio.set_write_policy(ShootThroughGuard { high_side: 5, low_side: 6 });
+
+        gpio.set_high(5).unwrap();
+        let error = gpio.set_high(6).unwrap_err();
+        assert_eq!(error.to_string(), "shoot-through: pin 6: undriven -> high while pin 5 is high");
+        assert_eq!(gpio.output_state(6).ok(), Some(None));
+
+        gpio.set_low(5).unwrap();
+        gpio.set_high(6).unwrap();
+        assert!(gpio.set_high(5).is_err());
+        assert_eq!(gpio.output_state(5).ok(), Some(Some(false)));
+
+        gpio.clear_write_policy();
+        gpio.set_high(5).unwrap();
+        assert_eq!(gpio.output_state(5).ok(), Some(Some(true)));
+    }
+
+    #[test]
+    fn test_shoot_through_guard_rejects_batch() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        gpio.set_write_policy(ShootThroughGuard { high_side: 5, low_side: 6 });
+
+        assert!(gpio.set_mask((1 << 5) | (1 << 6)).is_err());
+        assert_eq!((gpio.output_state(5).ok(), gpio.output_state(6).ok()), (Some(None), Some(None)));
+        gpio.set_high(5).unwrap();
+        gpio.write_mask(1 << 6, 1 << 5).unwrap();
+        assert_eq!((gpio.output_state(5).ok(), gpio.output_state(6).ok()), (Some(Some(false)), Some(Some(true))));
+    }
+
+    #[test]
+    fn test_board_state_reflects_gpio() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        gpio.set_function(5, PinFunction::Output).unwrap();
+        gpio.set_high(5).unwrap();
+
+        let state = gpio.board_state().unwrap();
+        assert_eq!(state.function(5).ok(), Some(PinFunction::Output));
+        assert_eq!(state.function(6).ok(), Some(PinFunction::Input));
+        assert_eq!(state.output_state(5).ok(), Some(Some(true)));
+        assert_eq!(state.output_state(6).ok(), Some(None));
+        assert!(matches!(state.function(58), Err(Error::InvalidPin { pin: 58, .. })));
+        assert!(matches!(state.output_state(64), Err(Error::InvalidPin { pin: 64, .. })));
+    }
+}
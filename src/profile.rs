@@ -0,0 +1,357 @@
+use crate::{EdgeTrigger, Error, HatVendor, PinConfig, PinConfigSet, PinFunction, Pull, GPIO};
+
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+
+/// A named setup of the header for one HAT or wiring, loaded from a
+/// profiles file by `BoardProfiles`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BoardProfile {
+    name: String,
+    // The board model, as `BoardModel` displays it, that the profile is
+    // for, if it's only for one.
+    board: Option<String>,
+    // The product string of the HAT the profile is for, as its ID EEPROM
+    // gives it.
+    hat: Option<String>,
+    config: PinConfigSet,
+}
+
+impl BoardProfile {
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn board(&self) -> Option<&str> {
+        self.board.as_deref()
+    }
+
+    /// The product name of the HAT the profile is for, if it's for one.
+    pub fn hat(&self) -> Option<&str> {
+        self.hat.as_deref()
+    }
+
+    /// The pins, each named by its role.
+    pub fn config(&self) -> &PinConfigSet {
+        &self.config
+    }
+}
+
+
+/// Board profiles read from a TOML file with a table per profile and an
+/// inline table per pin, keyed by the pin's role:
+///
+/// ```toml
+/// [relay-hat-v2]
+/// board = "Raspberry Pi 4 Model B"  # optional
+/// hat = "Relay HAT v2"               # optional
+/// "relay 1" = { pin = 5, function = "output", level = "low" }
+/// stop = { pin = 6, function = "input", pull = "up", edge = "falling" }
+/// ```
+///
+/// Functions are `input`, `output` and `alt0` to `alt5`; pulls `up`,
+/// `down` and `none`; levels `high` and `low`; edges `rising`, `falling`,
+/// `high`, `low`, `async-rising` and `async-falling`. Only that much of
+/// TOML is understood. A profile with a `hat` is picked by `for_hat` when
+/// that HAT's ID EEPROM names it as its product.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct BoardProfiles {
+    profiles: Vec<BoardProfile>,
+}
+
+impl BoardProfiles {
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| Error::from_io(format!("failed to read board profiles from {}", path.display()), e))?;
+        Self::parse(&text).map_err(|e| Error::Other(format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut profiles: Vec<BoardProfile> = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let error = |message: String| Error::Other(format!("line {}: {}", number + 1, message));
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+                let name = unquote(name.trim()).map_err(error)?;
+                if profiles.iter().any(|profile| profile.name == name) {
+                    return Err(error(format!("profile {:?} is defined twice", name)));
+                }
+                profiles.push(BoardProfile { name, board: None, hat: None, config: PinConfigSet::new() });
+                continue;
+            }
+            let profile = profiles.last_mut()
+                .ok_or_else(|| error("pins must follow a [profile] header".to_string()))?;
+            let (key, value) = split_pair(line).map_err(error)?;
+            match value {
+                Value::String(board) if key == "board" => profile.board = Some(board),
+                Value::String(hat) if key == "hat" => profile.hat = Some(hat),
+                Value::Table(fields) => {
+                    let config = pin_config(key, &fields).map_err(error)?;
+                    profile.config = std::mem::take(&mut profile.config).with_pin(config);
+                }
+                _ => return Err(error(format!("{:?} should be a pin's inline table", key))),
+            }
+        }
+        Ok(Self { profiles })
+    }
+
+    pub fn profiles(&self) -> &[BoardProfile] {
+        &self.profiles
+    }
+
+    pub fn get(&self, name: &str) -> Option<&BoardProfile> {
+        self.profiles.iter().find(|profile| profile.name == name)
+    }
+
+    /// The profile for the HAT described by `vendor`, read with
+    /// `HatEeprom::read`.
+    pub fn for_hat(&self, vendor: &HatVendor) -> Option<&BoardProfile> {
+        self.profiles.iter().find(|profile| profile.hat.as_deref() == Some(vendor.product.as_str()))
+    }
+}
+
+
+enum Value {
+    String(String),
+    Integer(u32),
+    Table(Vec<(String, Value)>),
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn unquote(text: &str) -> Result<String, String> {
+    match text.strip_prefix('"').and_then(|rest| rest.strip_suffix('"')) {
+        Some(inner) if !inner.contains('"') && !inner.contains('\\') => Ok(inner.to_string()),
+        Some(_) => Err(format!("escapes aren't supported in {}", text)),
+        None if !text.is_empty() && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') => {
+            Ok(text.to_string())
+        }
+        None => Err(format!("{:?} is not a valid key", text)),
+    }
+}
+
+// Splits `text` at each `separator` outside quotes and braces.
+fn split_outside(text: &str, separator: char) -> Vec<&str> {
+    let (mut parts, mut start, mut quoted, mut depth) = (Vec::new(), 0, false, 0);
+    for (index, c) in text.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '{' if !quoted => depth += 1,
+            '}' if !quoted => depth -= 1,
+            c if c == separator && !quoted && depth == 0 => {
+                parts.push(&text[start..index]);
+                start = index + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+fn split_pair(text: &str) -> Result<(String, Value), String> {
+    let parts = split_outside(text, '=');
+    let (key, value) = match parts.as_slice() {
+        [key, value] => (key.trim(), value.trim()),
+        _ => return Err(format!("expected key = value, not {:?}", text)),
+    };
+    Ok((unquote(key)?, parse_value(value)?))
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+    if let Some(inner) = text.strip_prefix('{').and_then(|rest| rest.strip_suffix('}')) {
+        return split_outside(inner, ',').into_iter()
+            .filter(|field| !field.trim().is_empty())
+            .map(split_pair)
+            .collect::<Result<_, _>>()
+            .map(Value::Table);
+    }
+    if text.starts_with('"') {
+        return unquote(text).map(Value::String);
+    }
+    text.parse().map(Value::Integer).map_err(|_| format!("{:?} is not a string, number or inline table", text))
+}
+
+fn pin_config(role: String, fields: &[(String, Value)]) -> Result<PinConfig, String> {
+    let field = |name: &str| fields.iter().find(|(key, _)| key == name).map(|(_, value)| value);
+    let text = |name: &str| match field(name) {
+        None => Ok(None),
+        Some(Value::String(text)) => Ok(Some(text.as_str())),
+        Some(_) => Err(format!("{} of {:?} should be a string", name, role)),
+    };
+    if let Some((key, _)) = fields.iter()
+        .find(|(key, _)| !["pin", "function", "pull", "level", "edge"].contains(&key.as_str()))
+    {
+        return Err(format!("{:?} has an unknown setting {:?}", role, key));
+    }
+
+    let pin = match field("pin") {
+        Some(&Value::Integer(pin)) => pin,
+        _ => return Err(format!("{:?} needs a pin number", role)),
+    };
+    let function = match text("function")?.unwrap_or("input") {
+        "input" => PinFunction::Input,
+        "output" => PinFunction::Output,
+        "alt0" => PinFunction::Alt0,
+        "alt1" => PinFunction::Alt1,
+        "alt2" => PinFunction::Alt2,
+        "alt3" => PinFunction::Alt3,
+        "alt4" => PinFunction::Alt4,
+        "alt5" => PinFunction::Alt5,
+        other => return Err(format!("{:?} has an unknown function {:?}", role, other)),
+    };
+    let pull = match text("pull")?.unwrap_or("none") {
+        "none" => Pull::None,
+        "up" => Pull::Up,
+        "down" => Pull::Down,
+        other => return Err(format!("{:?} has an unknown pull {:?}", role, other)),
+    };
+    let mut config = PinConfig::new(pin, function).with_pull(pull);
+    match text("level")? {
+        None => {}
+        Some("high") => config = config.with_initial_level(true),
+        Some("low") => config = config.with_initial_level(false),
+        Some(other) => return Err(format!("{:?} has an unknown level {:?}", role, other)),
+    }
+    let edge = match text("edge")? {
+        None => None,
+        Some("rising") => Some(EdgeTrigger::Rising),
+        Some("falling") => Some(EdgeTrigger::Falling),
+        Some("high") => Some(EdgeTrigger::High),
+        Some("low") => Some(EdgeTrigger::Low),
+        Some("async-rising") => Some(EdgeTrigger::AsyncRising),
+        Some("async-falling") => Some(EdgeTrigger::AsyncFalling),
+        Some(other) => return Err(format!("{:?} has an unknown edge {:?}", role, other)),
+    };
+    if let Some(edge) = edge {
+        config = config.with_edge(edge);
+    }
+    Ok(config.with_name(role))
+}
+
+
+impl GPIO {
+
+    /// Checks `profile` against this board and applies it with
+    /// `apply_config`. Nothing is written if the profile is for another
+    /// model, or wants a pin that a kernel driver (often a HAT's overlay)
+    /// or a `Pin` handle already holds.
+    pub fn apply_profile(&self, profile: &BoardProfile) -> Result<(), Error> {
+        if let Some(board) = profile.board() {
+            let detected = self.board().model().to_string();
+            if board != detected {
+                return Err(Error::Unsupported(format!(
+                    "profile {:?} is for a {}, but this is a {}", profile.name, board, detected)));
+            }
+        }
+        for config in profile.config.pins() {
+            let role = config.name.as_deref().unwrap_or("?");
+            self.check_board_pin(config.pin)?;
+            if let Some(consumer) = self.kernel_consumer(config.pin)? {
+                return Err(Error::WriteRejected(format!(
+                    "profile {:?} wants GPIO {} for {:?}, but {:?} holds it; is a HAT or overlay using it?",
+                    profile.name, config.pin, role, consumer)));
+            }
+            if self.claimed_pins.load(Ordering::SeqCst) & (1 << config.pin) != 0 {
+                return Err(Error::WriteRejected(format!(
+                    "profile {:?} wants GPIO {} for {:?}, but a Pin handle holds it",
+                    profile.name, config.pin, role)));
+            }
+        }
+        self.apply_config(&profile.config)
+    }
+
+    /// Loads the profile `name` from the profiles file at `path` and applies
+    /// it, e.g. at startup with the name from the deployment's settings.
+    pub fn apply_profile_from(&self, path: impl AsRef<Path>, name: &str) -> Result<BoardProfile, Error> {
+        let path = path.as_ref();
+        let profiles = BoardProfiles::load(path)?;
+        let profile = profiles.get(name).ok_or_else(|| Error::Other(format!(
+            "{} has no profile {:?}; it has {:?}", path.display(), name,
+            profiles.profiles().iter().map(BoardProfile::name).collect::<Vec<_>>())))?;
+        self.apply_profile(profile)?;
+        Ok(profile.clone())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Board;
+    use crate::{hat, HatEeprom, Soc};
+
+    const PROFILES: &str = r#"
+        # Relays on 5 and 6, with a stop button.
+        [relay-hat-v2]
+        hat = "Relay HAT"
+        "relay 1" = { pin = 5, function = "output", level = "low" }
+        relay2 = { pin = 6, function = "output", level = "low" }   # second relay
+        stop = { pin = 26, pull = "up", edge = "falling" }
+
+        ["spi-display"]
+        board = "Raspberry Pi 4 Model B"
+        cs = { pin = 8, function = "output", level = "high" }
+    "#;
+
+    #[test]
+    fn test_parse_profiles() {
+        let profiles = BoardProfiles::parse(PROFILES).unwrap();
+        assert_eq!(profiles.profiles().len(), 2);
+        let relays = profiles.get("relay-hat-v2").unwrap();
+        assert_eq!(relays.config().pins().len(), 3);
+        assert_eq!(relays.config().get("relay 1").map(|config| (config.pin, config.initial_level)), Some((5, Some(false))));
+        let stop = relays.config().get("stop").unwrap();
+        assert_eq!((stop.function, stop.pull, stop.edge), (PinFunction::Input, Pull::Up, Some(EdgeTrigger::Falling)));
+        assert_eq!(profiles.get("spi-display").and_then(BoardProfile::board), Some("Raspberry Pi 4 Model B"));
+
+        let eeprom = HatEeprom::parse(&hat::tests::image()).unwrap();
+        assert_eq!(profiles.for_hat(eeprom.vendor().unwrap()).map(BoardProfile::name), Some("relay-hat-v2"));
+        assert_eq!(relays.hat(), Some("Relay HAT"));
+
+        let error = BoardProfiles::parse("[a]\nled = { pin = 5, function = \"outptu\" }").unwrap_err();
+        assert!(error.to_string().contains("line 2") && error.to_string().contains("outptu"));
+        assert!(BoardProfiles::parse("led = { pin = 5 }").is_err());
+        assert!(BoardProfiles::parse("[a]\nled = { function = \"output\" }").is_err());
+        assert!(BoardProfiles::parse("[a]\nled = { pin = 5, colour = \"red\" }").is_err());
+        assert!(BoardProfiles::parse("[a]\n[a]").is_err());
+    }
+
+    #[test]
+    fn test_apply_profile() {
+        let profiles = BoardProfiles::parse(PROFILES).unwrap();
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        gpio.board.set(Board::from_revision(0xc03111).unwrap_or_else(|| Board::unknown(Soc::Bcm2711))).unwrap();
+        let mut consumers = vec![None; 28];
+        consumers[8] = Some("spi0 CS0".to_string());
+        gpio.kernel_consumers.set(consumers).unwrap();
+
+        let error = gpio.apply_profile(profiles.get("spi-display").unwrap()).unwrap_err();
+        assert!(matches!(&error, Error::WriteRejected(message) if message.contains("\"spi0 CS0\" holds it")));
+        assert_eq!(gpio.get_function(8).ok(), Some(PinFunction::Input));
+
+        let relay = gpio.pin(6).unwrap();
+        assert!(matches!(gpio.apply_profile(profiles.get("relay-hat-v2").unwrap()), Err(Error::WriteRejected(_))));
+        drop(relay);
+        gpio.apply_profile(profiles.get("relay-hat-v2").unwrap()).unwrap();
+        assert_eq!(gpio.get_function(5).ok(), Some(PinFunction::Output));
+        assert_eq!(gpio.get_pull(26).ok(), Some(Pull::Up));
+    }
+}
@@ -0,0 +1,194 @@
+use crate::{CallbackId, EdgeKind, Error, Input, Pin, PinEvent, Trigger, GPIO};
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+
+// The pulse widths seen most recently, up to `window` of each level.
+struct Pulses {
+    window: usize,
+    highs: VecDeque<Duration>,
+    lows: VecDeque<Duration>,
+    last_edge: Option<PinEvent>,
+}
+
+impl Pulses {
+    fn new(window: usize) -> Self {
+        Self { window, highs: VecDeque::new(), lows: VecDeque::new(), last_edge: None }
+    }
+
+    fn edge(&mut self, event: PinEvent) {
+        if let Some(last) = self.last_edge {
+            // Two edges the same way mean one in between was missed, so
+            // the time between them is no pulse width.
+            if last.edge != event.edge {
+                let pulses = if last.edge == EdgeKind::Rising { &mut self.highs } else { &mut self.lows };
+                pulses.push_back(event.since(&last));
+                while pulses.len() > self.window {
+                    pulses.pop_front();
+                }
+            }
+        }
+        self.last_edge = Some(event);
+    }
+
+    fn clear(&mut self) {
+        self.highs.clear();
+        self.lows.clear();
+        self.last_edge = None;
+    }
+
+    fn set_window(&mut self, window: usize) {
+        self.window = window;
+        for pulses in [&mut self.highs, &mut self.lows] {
+            while pulses.len() > window {
+                pulses.pop_front();
+            }
+        }
+    }
+}
+
+fn average(pulses: &VecDeque<Duration>) -> Option<Duration> {
+    if pulses.is_empty() {
+        return None;
+    }
+    Some(pulses.iter().sum::<Duration>() / pulses.len() as u32)
+}
+
+
+/// Measures the pulses on an input pin from its edge events: high and low
+/// times, period, frequency and duty cycle, each averaged over the last
+/// few pulses. For RC receiver channels, fan tachometers, flow sensors
+/// and the like.
+///
+/// The edges are timed by the kernel on line backends. Register backends
+/// only notice edges once a millisecond, which limits them to slow
+/// signals, and miss pulses shorter than that.
+pub struct PulseMeter<'a> {
+    gpio: &'a GPIO,
+    pin: Pin<'a, Input>,
+    pulses: Arc<Mutex<Pulses>>,
+    timeout: Duration,
+    callback: CallbackId,
+}
+
+impl<'a> PulseMeter<'a> {
+
+    pub const DEFAULT_WINDOW: usize = 8;
+
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+    pub fn new(pin: Pin<'a, Input>) -> Result<Self, Error> {
+        let pulses = Arc::new(Mutex::new(Pulses::new(Self::DEFAULT_WINDOW)));
+        let gpio = pin.gpio();
+        let callback = {
+            let pulses = Arc::clone(&pulses);
+            gpio.on_edge(pin.number(), Trigger::Both, move |event| pulses.lock().unwrap().edge(event))?
+        };
+        Ok(Self { gpio, pin, pulses, timeout: Self::DEFAULT_TIMEOUT, callback })
+    }
+
+    pub fn pin(&self) -> u32 {
+        self.pin.number()
+    }
+
+    /// How many of the latest pulses of each level are averaged.
+    pub fn set_window(&self, window: usize) -> Result<(), Error> {
+        if window == 0 {
+            return Err(Error::Other("the averaging window needs at least one pulse".to_string()));
+        }
+        self.pulses.lock().unwrap().set_window(window);
+        Ok(())
+    }
+
+    /// How long without an edge before the signal counts as stopped and
+    /// the readings as stale.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Forgets every pulse seen so far.
+    pub fn reset(&self) {
+        self.pulses.lock().unwrap().clear();
+    }
+
+    fn read<T>(&self, f: impl FnOnce(&Pulses) -> Option<T>) -> Option<T> {
+        let pulses = self.pulses.lock().unwrap();
+        let last = pulses.last_edge?;
+        if Instant::now().saturating_duration_since(last.timestamp) > self.timeout {
+            return None;
+        }
+        f(&pulses)
+    }
+
+    /// The average high time, or `None` if there's no recent signal.
+    pub fn high_time(&self) -> Option<Duration> {
+        self.read(|pulses| average(&pulses.highs))
+    }
+
+    pub fn low_time(&self) -> Option<Duration> {
+        self.read(|pulses| average(&pulses.lows))
+    }
+
+    pub fn period(&self) -> Option<Duration> {
+        self.read(|pulses| Some(average(&pulses.highs)? + average(&pulses.lows)?))
+    }
+
+    /// In hertz.
+    pub fn frequency(&self) -> Option<f64> {
+        self.period().map(|period| 1.0 / period.as_secs_f64())
+    }
+
+    /// The fraction of each period spent high.
+    pub fn duty_cycle(&self) -> Option<f64> {
+        self.read(|pulses| {
+            let (high, low) = (average(&pulses.highs)?, average(&pulses.lows)?);
+            Some(high.as_secs_f64() / (high + low).as_secs_f64())
+        })
+    }
+}
+
+impl<'a> Drop for PulseMeter<'a> {
+    fn drop(&mut self) {
+        let _ = self.gpio.remove_callback(self.callback);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pulse_meter_averages() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let meter = PulseMeter::new(gpio.pin(22).unwrap().into_input().unwrap()).unwrap();
+        assert_eq!(meter.frequency(), None);
+        meter.set_window(2).unwrap();
+        assert!(meter.set_window(0).is_err());
+
+        // A 1 kHz square wave at 25 %, then one stretched pulse that falls
+        // out of the window.
+        let start = Instant::now();
+        let mut at = start;
+        let widths = [400, 600, 250, 750, 250, 750, 250];
+        let mut edge = EdgeKind::Rising;
+        for &width in widths.iter() {
+            meter.pulses.lock().unwrap().edge(PinEvent { pin: 22, edge, timestamp: at });
+            at += Duration::from_micros(width);
+            edge = if edge == EdgeKind::Rising { EdgeKind::Falling } else { EdgeKind::Rising };
+        }
+        // The repeated edge is ignored.
+        meter.pulses.lock().unwrap().edge(PinEvent { pin: 22, edge: EdgeKind::Rising, timestamp: at });
+        meter.pulses.lock().unwrap().edge(PinEvent { pin: 22, edge: EdgeKind::Rising, timestamp: at });
+
+        assert_eq!(meter.high_time(), Some(Duration::from_micros(250)));
+        assert_eq!(meter.period(), Some(Duration::from_millis(1)));
+        assert!((meter.frequency().unwrap() - 1000.0).abs() < 1e-6);
+        assert!((meter.duty_cycle().unwrap() - 0.25).abs() < 1e-9);
+
+        meter.reset();
+        assert_eq!(meter.low_time(), None);
+    }
+}
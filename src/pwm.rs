@@ -0,0 +1,273 @@
+use crate::region::MappedRegion;
+use crate::{detect_peripheral_base, Error, PinFunction, GPIO, DEV_MEM_PATH};
+
+use std::path::Path;
+use std::sync::Mutex;
+
+
+pub(crate) const PWM_BASE_OFFSET: i64 = 0x20c000;
+pub(crate) const PWM_BLOCK_LEN: usize = 0x28;
+
+pub(crate) const PWM_CTL: usize = 0x00;
+pub(crate) const PWM_DMAC: usize = 0x08;
+pub(crate) const PWM_RNG1: usize = 0x10;
+const PWM_DAT1: usize = 0x14;
+pub(crate) const PWM_FIF1: usize = 0x18;
+const PWM_RNG2: usize = 0x20;
+const PWM_DAT2: usize = 0x24;
+
+// Per-channel CTL bits; channel 1's are the same shifted up by 8.
+pub(crate) const CTL_PWEN: u32 = 1 << 0;
+pub(crate) const CTL_MODE_SERIALISER: u32 = 1 << 1;
+pub(crate) const CTL_USEF: u32 = 1 << 5;
+pub(crate) const CTL_CLRF: u32 = 1 << 6;
+const CTL_MSEN: u32 = 1 << 7;
+const CTL_CHANNEL_SHIFT: u32 = 8;
+
+// DMA requests are raised while the FIFO has fewer than DREQ words.
+pub(crate) const DMAC_ENAB: u32 = 1 << 31;
+pub(crate) const DMAC_PANIC_SHIFT: u32 = 8;
+
+// The firmware leaves the PWM clock at the 19.2 MHz oscillator when the
+// pwm overlay is loaded.
+const DEFAULT_CLOCK_HZ: u32 = 19_200_000;
+
+// Both channels share CTL, so updates to it are serialised process-wide.
+static CTL_LOCK: Mutex<()> = Mutex::new(());
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum PwmChannel {
+    Pwm0,
+    Pwm1,
+}
+
+impl PwmChannel {
+
+    /// The pins this channel can be routed to, with the alternate function
+    /// that does it.
+    pub fn pins(self) -> [(u32, PinFunction); 2] {
+        match self {
+            PwmChannel::Pwm0 => [(12, PinFunction::Alt0), (18, PinFunction::Alt5)],
+            PwmChannel::Pwm1 => [(13, PinFunction::Alt0), (19, PinFunction::Alt5)],
+        }
+    }
+
+    /// The channel that can be routed to `pin`, if any.
+    pub fn for_pin(pin: u32) -> Option<PwmChannel> {
+        [PwmChannel::Pwm0, PwmChannel::Pwm1].iter().copied()
+            .find(|channel| channel.pins().iter().any(|&(candidate, _)| candidate == pin))
+    }
+
+    fn ctl_shift(self) -> u32 {
+        match self {
+            PwmChannel::Pwm0 => 0,
+            PwmChannel::Pwm1 => CTL_CHANNEL_SHIFT,
+        }
+    }
+
+    fn range_register(self) -> usize {
+        match self {
+            PwmChannel::Pwm0 => PWM_RNG1,
+            PwmChannel::Pwm1 => PWM_RNG2,
+        }
+    }
+
+    fn data_register(self) -> usize {
+        match self {
+            PwmChannel::Pwm0 => PWM_DAT1,
+            PwmChannel::Pwm1 => PWM_DAT2,
+        }
+    }
+}
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum PwmMode {
+    /// The output is high for the first `duty` of each period.
+    MarkSpace,
+    /// High and low time are spread evenly across the period, which suits
+    /// filtering to an analogue level better.
+    Balanced,
+}
+
+
+/// One channel of the hardware PWM controller. The controller's clock
+/// must already be running, through the pwm overlay or `ClockManager`
+/// with `Clock::Pwm`; `set_clock_hz` tells the channel its rate so
+/// frequencies can be converted to ranges.
+/// The channel is disabled when dropped.
+pub struct HwPwm {
+    region: MappedRegion,
+    channel: PwmChannel,
+    clock_hz: u32,
+    range: u32,
+    duty: f64,
+}
+
+impl HwPwm {
+
+    /// Maps the PWM controller through `/dev/mem`.
+    pub fn new(channel: PwmChannel) -> Result<Self, Error> {
+        let base = detect_peripheral_base()?;
+        Self::open(DEV_MEM_PATH, base + PWM_BASE_OFFSET, channel)
+    }
+
+    pub(crate) fn open(path: impl AsRef<Path>, phys_base: i64, channel: PwmChannel) -> Result<Self, Error> {
+        let region = MappedRegion::open(path.as_ref(), phys_base, PWM_BLOCK_LEN)?;
+        let range = region.read_reg(channel.range_register())?;
+        let data = region.read_reg(channel.data_register())?;
+        let duty = if range == 0 { 0.0 } else { (data as f64 / range as f64).min(1.0) };
+        Ok(Self { region, channel, clock_hz: DEFAULT_CLOCK_HZ, range, duty })
+    }
+
+    pub fn channel(&self) -> PwmChannel {
+        self.channel
+    }
+
+    /// Switches `pin` to this channel's alternate function. Fails if the
+    /// channel can't drive `pin`.
+    pub fn route(&self, gpio: &GPIO, pin: u32) -> Result<(), Error> {
+        match self.channel.pins().iter().find(|&&(candidate, _)| candidate == pin) {
+            Some(&(_, function)) => gpio.set_function(pin, function),
+            None => Err(Error::Other(format!("{:?} can't be routed to pin {}", self.channel, pin))),
+        }
+    }
+
+    pub fn clock_hz(&self) -> u32 {
+        self.clock_hz
+    }
+
+    /// Sets the rate the PWM clock is running at. The next `set_frequency`
+    /// uses it.
+    pub fn set_clock_hz(&mut self, clock_hz: u32) {
+        self.clock_hz = clock_hz;
+    }
+
+    /// Sets the output frequency, keeping the current duty cycle. The
+    /// achievable frequencies are the clock rate divided by a whole number
+    /// of at least 2.
+    pub fn set_frequency(&mut self, freq_hz: f64) -> Result<(), Error> {
+        let range = self.clock_hz as f64 / freq_hz;
+        if !range.is_finite() || range < 2.0 || range > u32::MAX as f64 {
+            return Err(Error::Other(format!(
+                "{} Hz is out of reach of a {} Hz PWM clock", freq_hz, self.clock_hz)));
+        }
+        self.range = range.round() as u32;
+        self.region.write_reg(self.channel.range_register(), self.range)?;
+        self.write_data()
+    }
+
+    /// The frequency the channel is set to, after rounding to a whole range.
+    pub fn frequency(&self) -> f64 {
+        if self.range == 0 { 0.0 } else { self.clock_hz as f64 / self.range as f64 }
+    }
+
+    /// Sets the fraction of each period the output is high, from 0 to 1.
+    pub fn set_duty_cycle(&mut self, duty: f64) -> Result<(), Error> {
+        if !(0.0..=1.0).contains(&duty) {
+            return Err(Error::Other(format!("duty cycle {} is outside 0 to 1", duty)));
+        }
+        self.duty = duty;
+        self.write_data()
+    }
+
+    pub fn duty_cycle(&self) -> f64 {
+        self.duty
+    }
+
+    fn write_data(&self) -> Result<(), Error> {
+        let data = (self.range as f64 * self.duty).round() as u32;
+        self.region.write_reg(self.channel.data_register(), data)
+    }
+
+    fn update_ctl(&self, clear: u32, set: u32) -> Result<(), Error> {
+        let shift = self.channel.ctl_shift();
+        let _guard = CTL_LOCK.lock().unwrap();
+        let ctl = self.region.read_reg(PWM_CTL)?;
+        self.region.write_reg(PWM_CTL, (ctl & !(clear << shift)) | (set << shift))
+    }
+
+    pub fn set_mode(&self, mode: PwmMode) -> Result<(), Error> {
+        match mode {
+            PwmMode::MarkSpace => self.update_ctl(0, CTL_MSEN),
+            PwmMode::Balanced => self.update_ctl(CTL_MSEN, 0),
+        }
+    }
+
+    pub fn mode(&self) -> Result<PwmMode, Error> {
+        let ctl = self.region.read_reg(PWM_CTL)? >> self.channel.ctl_shift();
+        Ok(if ctl & CTL_MSEN != 0 { PwmMode::MarkSpace } else { PwmMode::Balanced })
+    }
+
+    pub fn enable(&self) -> Result<(), Error> {
+        self.update_ctl(0, CTL_PWEN)
+    }
+
+    pub fn disable(&self) -> Result<(), Error> {
+        self.update_ctl(CTL_PWEN, 0)
+    }
+
+    pub fn is_enabled(&self) -> Result<bool, Error> {
+        Ok(self.region.read_reg(PWM_CTL)? >> self.channel.ctl_shift() & CTL_PWEN != 0)
+    }
+}
+
+impl Drop for HwPwm {
+    fn drop(&mut self) {
+        let _ = self.disable();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block_file(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("rustberrypi-pwm-{}-{}", name, std::process::id()));
+        std::fs::write(&path, vec![0u8; 4096]).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_hw_pwm_registers() {
+        let path = block_file("registers");
+        let mut pwm1 = HwPwm::open(&path, 0, PwmChannel::Pwm1).ok().unwrap();
+        pwm1.set_duty_cycle(0.25).unwrap();
+        pwm1.set_frequency(1_000.0).unwrap();
+        assert_eq!(pwm1.frequency(), 1_000.0);
+        pwm1.set_mode(PwmMode::MarkSpace).unwrap();
+        pwm1.enable().unwrap();
+        assert!(pwm1.is_enabled().unwrap());
+        assert_eq!(pwm1.mode().ok(), Some(PwmMode::MarkSpace));
+
+        let pwm0 = HwPwm::open(&path, 0, PwmChannel::Pwm0).ok().unwrap();
+        assert!(!pwm0.is_enabled().unwrap());
+        pwm0.enable().unwrap();
+        assert_eq!(pwm1.region.read_reg(PWM_CTL).ok(), Some(CTL_PWEN | (CTL_PWEN | CTL_MSEN) << 8));
+        assert_eq!(pwm1.region.read_reg(PWM_RNG2).ok(), Some(19_200));
+        assert_eq!(pwm1.region.read_reg(PWM_DAT2).ok(), Some(4_800));
+
+        assert!(pwm1.set_frequency(20_000_000.0).is_err());
+        assert!(pwm1.set_duty_cycle(1.5).is_err());
+        drop(pwm1);
+        assert_eq!(pwm0.region.read_reg(PWM_CTL).ok(), Some(CTL_PWEN | CTL_MSEN << 8));
+        drop(pwm0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_hw_pwm_routing() {
+        let path = block_file("routing");
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let pwm = HwPwm::open(&path, 0, PwmChannel::Pwm0).ok().unwrap();
+        assert_eq!(PwmChannel::for_pin(19), Some(PwmChannel::Pwm1));
+        assert_eq!(PwmChannel::for_pin(17), None);
+        pwm.route(&gpio, 18).unwrap();
+        assert_eq!(gpio.get_function(18).ok(), Some(PinFunction::Alt5));
+        assert!(pwm.route(&gpio, 13).is_err());
+        drop(pwm);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,64 @@
+use crate::{Error, HwPwm, PinFunction, PwmChannel, PwmMode, SoftPwm, GPIO};
+
+
+/// A PWM signal on one pin from whichever generator could drive it, for
+/// the helpers built on PWM.
+pub(crate) enum PwmOutput<'a> {
+    Hardware(HwPwm),
+    Software(SoftPwm<'a>, u32),
+}
+
+impl<'a> PwmOutput<'a> {
+
+    /// Uses the hardware PWM controller if `pin` has a PWM channel and
+    /// `/dev/mem` can be mapped, and software PWM otherwise.
+    pub(crate) fn open(gpio: &'a GPIO, pin: u32, freq_hz: f64) -> Result<Self, Error> {
+        if let Some(Ok(pwm)) = PwmChannel::for_pin(pin).map(HwPwm::new) {
+            pwm.route(gpio, pin)?;
+            return Self::hardware(pwm, freq_hz);
+        }
+        Self::software(gpio, pin, freq_hz)
+    }
+
+    /// Starts `pwm`, which must already be routed to its pin, low.
+    pub(crate) fn hardware(mut pwm: HwPwm, freq_hz: f64) -> Result<Self, Error> {
+        pwm.set_mode(PwmMode::MarkSpace)?;
+        pwm.set_duty_cycle(0.0)?;
+        pwm.set_frequency(freq_hz)?;
+        pwm.enable()?;
+        Ok(PwmOutput::Hardware(pwm))
+    }
+
+    /// Makes `pin` an output and starts software PWM on it, low.
+    pub(crate) fn software(gpio: &'a GPIO, pin: u32, freq_hz: f64) -> Result<Self, Error> {
+        gpio.set_function(pin, PinFunction::Output)?;
+        let pwm = SoftPwm::new(gpio)?;
+        pwm.start(pin, freq_hz, 0.0)?;
+        Ok(PwmOutput::Software(pwm, pin))
+    }
+
+    pub(crate) fn is_hardware(&self) -> bool {
+        matches!(self, PwmOutput::Hardware(_))
+    }
+
+    pub(crate) fn set_frequency(&mut self, freq_hz: f64) -> Result<(), Error> {
+        match self {
+            PwmOutput::Hardware(pwm) => pwm.set_frequency(freq_hz),
+            PwmOutput::Software(pwm, pin) => pwm.set_frequency(*pin, freq_hz),
+        }
+    }
+
+    pub(crate) fn set_duty_cycle(&mut self, duty: f64) -> Result<(), Error> {
+        match self {
+            PwmOutput::Hardware(pwm) => pwm.set_duty_cycle(duty),
+            PwmOutput::Software(pwm, pin) => pwm.set_duty_cycle(*pin, duty),
+        }
+    }
+
+    pub(crate) fn duty_cycle(&self) -> f64 {
+        match self {
+            PwmOutput::Hardware(pwm) => pwm.duty_cycle(),
+            PwmOutput::Software(pwm, pin) => pwm.duty_cycle(*pin).unwrap_or(0.0),
+        }
+    }
+}
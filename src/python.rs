@@ -0,0 +1,494 @@
+//! Python bindings, built as the `rustberrypi` extension module by
+//! `maturin build --release`, which `pyproject.toml` points at the `python`
+//! feature. They follow RPi.GPIO where they can: BCM pin numbers, `setup`,
+//! `input`, `output`, `wait_for_edge`, `add_event_detect` and `PWM`, and
+//! module constants for functions, pulls and edges, with the C interface's
+//! values. The state RPi.GPIO keeps module-wide lives in a `GPIO` object.
+//!
+//! Waits release the GIL. Edge callbacks run on the event loop thread,
+//! taking the GIL for each call, and get a `PinEvent` whose timestamp is
+//! on `time.monotonic()`'s clock.
+
+use crate::edge::monotonic_ns;
+use crate::{CallbackId, EdgeKind, Error, HwPwm, PinEvent, PinFunction, PinSetup, Pull, PwmChannel, Trigger, GPIO};
+
+use pyo3::exceptions::{PyOSError, PyPermissionError, PyRuntimeError, PyTimeoutError, PyValueError};
+use pyo3::prelude::*;
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+
+const LOW: u32 = 0;
+const HIGH: u32 = 1;
+const RISING: u32 = 1;
+const FALLING: u32 = 2;
+const BOTH: u32 = 3;
+
+impl From<Error> for PyErr {
+    fn from(error: Error) -> PyErr {
+        let message = error.to_string();
+        match error {
+            Error::InvalidPin { .. } => PyValueError::new_err(message),
+            Error::PermissionDenied { .. } => PyPermissionError::new_err(message),
+            Error::Timeout(_) => PyTimeoutError::new_err(message),
+            Error::Io { .. } | Error::Sys { .. } | Error::MmapFailed { .. } => PyOSError::new_err(message),
+            _ => PyRuntimeError::new_err(message),
+        }
+    }
+}
+
+fn function_from(function: u32) -> PyResult<PinFunction> {
+    if function > 0b111 {
+        return Err(PyValueError::new_err(format!("{} is not a GPFSEL function", function)));
+    }
+    Ok(PinFunction::from_bits(0, function))
+}
+
+fn pull_from(pull: u32) -> PyResult<Pull> {
+    match pull {
+        0 => Ok(Pull::None),
+        1 => Ok(Pull::Up),
+        2 => Ok(Pull::Down),
+        _ => Err(PyValueError::new_err(format!("{} is not a pull", pull))),
+    }
+}
+
+fn trigger_from(edge: u32) -> PyResult<Trigger> {
+    match edge {
+        RISING => Ok(Trigger::Rising),
+        FALLING => Ok(Trigger::Falling),
+        BOTH => Ok(Trigger::Both),
+        _ => Err(PyValueError::new_err(format!("{} is not an edge", edge))),
+    }
+}
+
+fn timeout_from(timeout_ms: Option<u64>) -> Option<Duration> {
+    timeout_ms.map(Duration::from_millis)
+}
+
+
+/// An edge, as `wait_for_edge` returns it and `add_event_detect` callbacks
+/// receive it.
+#[pyclass(name = "PinEvent", module = "rustberrypi", frozen)]
+struct PyPinEvent {
+    #[pyo3(get)]
+    pin: u32,
+    /// `RISING` or `FALLING`.
+    #[pyo3(get)]
+    edge: u32,
+    /// Seconds on `time.monotonic()`'s clock.
+    #[pyo3(get)]
+    timestamp: f64,
+}
+
+impl From<PinEvent> for PyPinEvent {
+    fn from(event: PinEvent) -> Self {
+        // CLOCK_MONOTONIC, which `time.monotonic()` reads on Linux.
+        let now_ns = monotonic_ns().unwrap_or(0) as f64;
+        let timestamp = (now_ns - event.timestamp.elapsed().as_nanos() as f64) / 1e9;
+        let edge = match event.edge {
+            EdgeKind::Rising => RISING,
+            EdgeKind::Falling => FALLING,
+        };
+        Self { pin: event.pin, edge, timestamp }
+    }
+}
+
+#[pymethods]
+impl PyPinEvent {
+
+    fn __repr__(&self) -> String {
+        let edge = if self.edge == RISING { "RISING" } else { "FALLING" };
+        format!("PinEvent(pin={}, edge={}, timestamp={:.6})", self.pin, edge, self.timestamp)
+    }
+}
+
+
+/// The GPIO block, opened as `GPIO::new` does.
+#[pyclass(name = "GPIO", module = "rustberrypi", frozen)]
+struct PyGpio {
+    gpio: GPIO,
+    // The callbacks `add_event_detect` installed, by pin.
+    callbacks: Mutex<HashMap<u32, CallbackId>>,
+}
+
+impl From<GPIO> for PyGpio {
+    fn from(gpio: GPIO) -> Self {
+        Self { gpio, callbacks: Mutex::new(HashMap::new()) }
+    }
+}
+
+impl PyGpio {
+
+    fn wait_for_edge_on(&self, py: Python<'_>, pin: u32, edge: u32, timeout_ms: Option<u64>)
+        -> PyResult<Option<PyPinEvent>>
+    {
+        let trigger = trigger_from(edge)?;
+        match py.detach(|| self.gpio.wait_for_edge(pin, trigger, timeout_from(timeout_ms))) {
+            Ok(event) => Ok(Some(event.into())),
+            Err(Error::Timeout(_)) => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+}
+
+#[pymethods]
+impl PyGpio {
+
+    #[new]
+    fn new() -> PyResult<Self> {
+        Ok(GPIO::new()?.into())
+    }
+
+    /// Sets `pin`'s function, and its pull and starting level if given,
+    /// as RPi.GPIO's `setup` does.
+    #[pyo3(signature = (pin, function, pull_up_down = 0, initial = None))]
+    fn setup(&self, pin: u32, function: u32, pull_up_down: u32, initial: Option<u32>) -> PyResult<()> {
+        let setup = PinSetup {
+            pin,
+            function: function_from(function)?,
+            pull: pull_from(pull_up_down)?,
+            initial_level: initial.map(|level| level != LOW),
+            edge: None,
+        };
+        Ok(self.gpio.configure(&[setup])?)
+    }
+
+    fn input(&self, pin: u32) -> PyResult<u32> {
+        Ok(if self.gpio.read(pin)? { HIGH } else { LOW })
+    }
+
+    fn output(&self, pin: u32, value: u32) -> PyResult<()> {
+        if value != LOW { self.gpio.set_high(pin)? } else { self.gpio.set_low(pin)? }
+        Ok(())
+    }
+
+    fn gpio_function(&self, pin: u32) -> PyResult<u32> {
+        Ok(self.gpio.get_function(pin)? as u32)
+    }
+
+    fn set_function(&self, pin: u32, function: u32) -> PyResult<()> {
+        Ok(self.gpio.set_function(pin, function_from(function)?)?)
+    }
+
+    fn set_pull(&self, pin: u32, pull: u32) -> PyResult<()> {
+        Ok(self.gpio.set_pull(pin, pull_from(pull)?)?)
+    }
+
+    /// `pin` as a `Pin` object.
+    fn pin(slf: Py<Self>, pin: u32) -> PyResult<PyPin> {
+        slf.get().gpio.check_board_pin(pin)?;
+        Ok(PyPin { gpio: slf, pin })
+    }
+
+    /// Waits up to `timeout` milliseconds, or forever if `None`, for an
+    /// `edge` on `pin`. Returns the `PinEvent`, or `None` on timeout.
+    #[pyo3(signature = (pin, edge, timeout = None))]
+    fn wait_for_edge(&self, py: Python<'_>, pin: u32, edge: u32, timeout: Option<u64>) -> PyResult<Option<PyPinEvent>> {
+        self.wait_for_edge_on(py, pin, edge, timeout)
+    }
+
+    /// Calls `callback` with a `PinEvent` for each `edge` on `pin`.
+    fn add_event_detect(&self, py: Python<'_>, pin: u32, edge: u32, callback: Py<PyAny>) -> PyResult<()> {
+        let trigger = trigger_from(edge)?;
+        let mut callbacks = self.callbacks.lock().unwrap();
+        // The event loop may be waiting for the GIL to run a callback while
+        // it holds the registrations.
+        let id = py.detach(|| self.gpio.on_edge(pin, trigger, move |event| {
+            Python::attach(|py| {
+                if let Err(error) = callback.call1(py, (PyPinEvent::from(event),)) {
+                    error.print(py);
+                }
+            })
+        }))?;
+        callbacks.insert(pin, id);
+        Ok(())
+    }
+
+    /// Removes `pin`'s `add_event_detect` callback. Returns whether it
+    /// had one.
+    fn remove_event_detect(&self, py: Python<'_>, pin: u32) -> PyResult<bool> {
+        let Some(id) = self.callbacks.lock().unwrap().remove(&pin) else {
+            return Ok(false);
+        };
+        Ok(py.detach(|| self.gpio.remove_callback(id))?)
+    }
+
+    /// Routes `pin` to its hardware PWM channel and sets the channel to
+    /// `frequency` Hz. The channel stays off until `start`.
+    #[pyo3(name = "PWM")]
+    fn pwm(&self, pin: u32, frequency: f64) -> PyResult<PyPwm> {
+        let channel = PwmChannel::for_pin(pin)
+            .ok_or_else(|| PyValueError::new_err(format!("pin {} has no hardware PWM channel", pin)))?;
+        PyPwm::routed(HwPwm::new(channel)?, &self.gpio, pin, frequency)
+    }
+}
+
+impl Drop for PyGpio {
+    fn drop(&mut self) {
+        // As in `add_event_detect`, the event loop is stopped without the
+        // GIL, which a callback may be waiting for.
+        let gpio = &mut self.gpio;
+        Python::attach(|py| py.detach(|| gpio.stop_event_loop()));
+    }
+}
+
+
+/// One pin of a `GPIO`.
+#[pyclass(name = "Pin", module = "rustberrypi", frozen)]
+struct PyPin {
+    gpio: Py<PyGpio>,
+    pin: u32,
+}
+
+#[pymethods]
+impl PyPin {
+
+    #[getter]
+    fn number(&self) -> u32 {
+        self.pin
+    }
+
+    fn read(&self) -> PyResult<u32> {
+        self.gpio.get().input(self.pin)
+    }
+
+    fn write(&self, value: u32) -> PyResult<()> {
+        self.gpio.get().output(self.pin, value)
+    }
+
+    fn function(&self) -> PyResult<u32> {
+        self.gpio.get().gpio_function(self.pin)
+    }
+
+    fn set_function(&self, function: u32) -> PyResult<()> {
+        self.gpio.get().set_function(self.pin, function)
+    }
+
+    fn set_pull(&self, pull: u32) -> PyResult<()> {
+        self.gpio.get().set_pull(self.pin, pull)
+    }
+
+    #[pyo3(signature = (edge, timeout = None))]
+    fn wait_for_edge(&self, py: Python<'_>, edge: u32, timeout: Option<u64>) -> PyResult<Option<PyPinEvent>> {
+        self.gpio.get().wait_for_edge_on(py, self.pin, edge, timeout)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Pin({})", self.pin)
+    }
+}
+
+
+/// A hardware PWM channel, with RPi.GPIO's `PWM` methods. Duty cycles are
+/// percentages. The channel is turned off when the object is collected.
+#[pyclass(name = "PWM", module = "rustberrypi", frozen)]
+struct PyPwm {
+    pwm: Mutex<HwPwm>,
+}
+
+impl PyPwm {
+
+    fn routed(mut pwm: HwPwm, gpio: &GPIO, pin: u32, frequency: f64) -> PyResult<Self> {
+        pwm.set_frequency(frequency)?;
+        pwm.route(gpio, pin)?;
+        Ok(Self { pwm: Mutex::new(pwm) })
+    }
+}
+
+#[pymethods]
+impl PyPwm {
+
+    fn start(&self, duty_cycle: f64) -> PyResult<()> {
+        let mut pwm = self.pwm.lock().unwrap();
+        pwm.set_duty_cycle(duty_cycle / 100.0)?;
+        Ok(pwm.enable()?)
+    }
+
+    #[pyo3(name = "ChangeDutyCycle")]
+    fn change_duty_cycle(&self, duty_cycle: f64) -> PyResult<()> {
+        Ok(self.pwm.lock().unwrap().set_duty_cycle(duty_cycle / 100.0)?)
+    }
+
+    #[pyo3(name = "ChangeFrequency")]
+    fn change_frequency(&self, frequency: f64) -> PyResult<()> {
+        Ok(self.pwm.lock().unwrap().set_frequency(frequency)?)
+    }
+
+    fn stop(&self) -> PyResult<()> {
+        Ok(self.pwm.lock().unwrap().disable()?)
+    }
+
+    #[getter]
+    fn frequency(&self) -> f64 {
+        self.pwm.lock().unwrap().frequency()
+    }
+
+    #[getter]
+    fn duty_cycle(&self) -> f64 {
+        self.pwm.lock().unwrap().duty_cycle() * 100.0
+    }
+}
+
+
+#[pymodule]
+fn rustberrypi(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyGpio>()?;
+    m.add_class::<PyPin>()?;
+    m.add_class::<PyPinEvent>()?;
+    m.add_class::<PyPwm>()?;
+    let functions = [
+        ("IN", PinFunction::Input), ("OUT", PinFunction::Output),
+        ("ALT0", PinFunction::Alt0), ("ALT1", PinFunction::Alt1), ("ALT2", PinFunction::Alt2),
+        ("ALT3", PinFunction::Alt3), ("ALT4", PinFunction::Alt4), ("ALT5", PinFunction::Alt5),
+    ];
+    for &(name, function) in functions.iter() {
+        m.add(name, function as u32)?;
+    }
+    for &(name, pull) in [("PUD_OFF", Pull::None), ("PUD_UP", Pull::Up), ("PUD_DOWN", Pull::Down)].iter() {
+        m.add(name, pull as u32)?;
+    }
+    for &(name, value) in [("LOW", LOW), ("HIGH", HIGH), ("RISING", RISING), ("FALLING", FALLING), ("BOTH", BOTH)].iter() {
+        m.add(name, value)?;
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockGpio;
+
+    use pyo3::types::PyDict;
+
+    use std::ffi::CString;
+
+    // A namespace for scripts, with the module as `rbp` and `names` bound.
+    fn namespace<'py>(py: Python<'py>, names: &[(&str, Bound<'py, PyAny>)]) -> Bound<'py, PyDict> {
+        let rbp = PyModule::new(py, "rustberrypi").unwrap();
+        rustberrypi(&rbp).unwrap();
+        let namespace = PyDict::new(py);
+        namespace.set_item("rbp", rbp).unwrap();
+        for (name, value) in names {
+            namespace.set_item(name, value).unwrap();
+        }
+        namespace
+    }
+
+    fn run(py: Python<'_>, script: &str, namespace: &Bound<'_, PyDict>) {
+        let script = CString::new(script).unwrap();
+        if let Err(error) = py.run(&script, Some(namespace), None) {
+            error.print(py);
+            panic!("the script failed");
+        }
+    }
+
+    #[test]
+    fn test_gpio_and_pin_methods() {
+        Python::initialize();
+        let mock = MockGpio::new();
+        let gpio = mock.gpio().unwrap();
+        Python::attach(|py| {
+            let gpio = Bound::new(py, PyGpio::from(gpio)).unwrap().into_any();
+            run(py, r#"
+gpio.setup(17, rbp.OUT, initial=rbp.HIGH)
+assert gpio.gpio_function(17) == rbp.OUT
+assert gpio.input(17) == rbp.HIGH
+gpio.setup(4, rbp.IN, pull_up_down=rbp.PUD_UP)
+button = gpio.pin(4)
+assert (button.number, button.read(), button.function()) == (4, rbp.HIGH, rbp.IN)
+button.set_pull(rbp.PUD_DOWN)
+assert button.read() == rbp.LOW
+assert repr(button) == "Pin(4)"
+for call in (lambda: gpio.input(58), lambda: gpio.pin(58), lambda: gpio.set_function(4, 8)):
+    try:
+        call()
+    except ValueError:
+        pass
+    else:
+        raise AssertionError("no ValueError")
+try:
+    gpio.PWM(17, 1000.0)
+except ValueError:
+    pass
+else:
+    raise AssertionError("pin 17 has no PWM")
+"#, &namespace(py, &[("gpio", gpio)]));
+        });
+        assert_eq!(mock.output(17), Some(true));
+    }
+
+    #[test]
+    fn test_edges() {
+        Python::initialize();
+        let mock = MockGpio::new();
+        let gpio = mock.gpio().unwrap();
+        Python::attach(|py| {
+            let gpio = Bound::new(py, PyGpio::from(gpio)).unwrap().into_any();
+            let namespace = namespace(py, &[("gpio", gpio)]);
+            let player = mock.play_inputs(5, &[(Duration::from_millis(50), true)]);
+            run(py, r#"
+import time
+assert gpio.wait_for_edge(6, rbp.BOTH, 10) is None
+event = gpio.pin(5).wait_for_edge(rbp.RISING, 5000)
+assert (event.pin, event.edge) == (5, rbp.RISING)
+assert abs(time.monotonic() - event.timestamp) < 5
+
+events = []
+gpio.add_event_detect(5, rbp.FALLING, events.append)
+"#, &namespace);
+            player.join().unwrap();
+            py.detach(|| mock.set_input(5, false));
+            run(py, r#"
+deadline = time.monotonic() + 5
+while not events and time.monotonic() < deadline:
+    time.sleep(0.01)
+assert [(event.pin, event.edge) for event in events] == [(5, rbp.FALLING)]
+assert gpio.remove_event_detect(5)
+assert not gpio.remove_event_detect(5)
+"#, &namespace);
+        });
+    }
+
+    #[test]
+    fn test_pwm() {
+        let path = std::env::temp_dir().join(format!("rustberrypi-python-pwm-{}", std::process::id()));
+        std::fs::write(&path, vec![0u8; 4096]).unwrap();
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let pwm = PyPwm::routed(HwPwm::open(&path, 0, PwmChannel::Pwm0).ok().unwrap(), &gpio, 18, 1_000.0).unwrap();
+        assert_eq!(gpio.get_function(18).ok(), Some(PinFunction::Alt5));
+        Python::initialize();
+        Python::attach(|py| {
+            let pwm = Bound::new(py, pwm).unwrap().into_any();
+            run(py, r#"
+pwm.start(25)
+assert (pwm.frequency, pwm.duty_cycle) == (1000.0, 25.0)
+pwm.ChangeDutyCycle(50)
+pwm.ChangeFrequency(2000)
+assert (pwm.frequency, pwm.duty_cycle) == (2000.0, 50.0)
+pwm.stop()
+try:
+    pwm.ChangeDutyCycle(150)
+except RuntimeError:
+    pass
+else:
+    raise AssertionError("150% accepted")
+"#, &namespace(py, &[("pwm", pwm)]));
+        });
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_errors_map_to_exceptions() {
+        Python::initialize();
+        Python::attach(|py| {
+            let error = PyErr::from(Error::InvalidPin { pin: 58, register: None });
+            assert!(error.is_instance_of::<PyValueError>(py));
+            assert!(PyErr::from(Error::Timeout("late".to_string())).is_instance_of::<PyTimeoutError>(py));
+            assert!(PyErr::from(Error::PinInUse(4)).is_instance_of::<PyRuntimeError>(py));
+        });
+    }
+}
@@ -0,0 +1,158 @@
+//! Settings for timing-critical threads, such as a `SoftPwm`'s or one
+//! bit-banging a protocol: locking the process's memory so no access to it
+//! waits on a page fault, running a thread under `SCHED_FIFO` so ordinary
+//! threads can't preempt it, and pinning it to one CPU. Each needs
+//! `CAP_SYS_NICE` or `CAP_IPC_LOCK` (or root) or a high enough
+//! `RLIMIT_RTPRIO`/`RLIMIT_MEMLOCK`.
+//!
+//! `Realtime::apply` asks for several at once and carries on past those
+//! the process isn't allowed, so a program can run unprivileged with
+//! worse timing rather than not at all.
+
+use crate::Error;
+
+use nix::errno::Errno;
+use nix::libc;
+use nix::sched::{sched_setaffinity, CpuSet};
+use nix::sys::mman::{mlockall, MlockAllFlags};
+use nix::unistd::Pid;
+
+
+/// Locks every page the process has mapped, and every page it maps from
+/// now on, into memory, which covers the heap, the stacks and the
+/// register mappings.
+pub fn lock_memory() -> Result<(), Error> {
+    mlockall(MlockAllFlags::MCL_CURRENT | MlockAllFlags::MCL_FUTURE)
+        .map_err(|e| Error::from_nix("failed to lock the process's memory", e))
+}
+
+/// The `SCHED_FIFO` priorities, 1 to 99 on Linux.
+pub fn fifo_priorities() -> (i32, i32) {
+    unsafe { (libc::sched_get_priority_min(libc::SCHED_FIFO), libc::sched_get_priority_max(libc::SCHED_FIFO)) }
+}
+
+/// Runs the calling thread under `SCHED_FIFO` at `priority`, above every
+/// ordinary thread. A thread that never sleeps at a high priority can
+/// starve the rest of the system, kernel threads included.
+pub fn set_fifo_priority(priority: i32) -> Result<(), Error> {
+    let (min, max) = fifo_priorities();
+    if !(min..=max).contains(&priority) {
+        return Err(Error::Other(format!("SCHED_FIFO priority must be {} to {}, not {}", min, max, priority)));
+    }
+    let param = libc::sched_param { sched_priority: priority };
+    match unsafe { libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_FIFO, &param) } {
+        0 => Ok(()),
+        errno => Err(Error::from_nix(
+            format!("failed to set SCHED_FIFO priority {}", priority), nix::Error::Sys(Errno::from_i32(errno)))),
+    }
+}
+
+/// Keeps the calling thread on `cpu`. Pinning it to a core that
+/// `isolcpus` keeps other tasks off gives the steadiest timing.
+pub fn pin_to_cpu(cpu: usize) -> Result<(), Error> {
+    let mut set = CpuSet::new();
+    set.set(cpu).map_err(|e| Error::from_nix(format!("there is no CPU {}", cpu), e))?;
+    // A pid of 0 is the calling thread.
+    sched_setaffinity(Pid::from_raw(0), &set)
+        .map_err(|e| Error::from_nix(format!("failed to pin the thread to CPU {}", cpu), e))
+}
+
+
+/// The real-time settings to ask for, built up and then applied to the
+/// calling thread with `apply`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Realtime {
+    lock_memory: bool,
+    priority: Option<i32>,
+    cpu: Option<usize>,
+}
+
+impl Realtime {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// See `lock_memory`.
+    pub fn lock_memory(mut self) -> Self {
+        self.lock_memory = true;
+        self
+    }
+
+    /// See `set_fifo_priority`.
+    pub fn fifo_priority(mut self, priority: i32) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    /// See `pin_to_cpu`.
+    pub fn cpu(mut self, cpu: usize) -> Self {
+        self.cpu = Some(cpu);
+        self
+    }
+
+    /// Applies each setting that was asked for, carrying on past any that
+    /// fail, and reports which took effect.
+    pub fn apply(&self) -> RealtimeStatus {
+        let mut status = RealtimeStatus::default();
+        if self.lock_memory {
+            match lock_memory() {
+                Ok(()) => status.memory_locked = true,
+                Err(error) => status.failures.push(error),
+            }
+        }
+        if let Some(priority) = self.priority {
+            match set_fifo_priority(priority) {
+                Ok(()) => status.priority = Some(priority),
+                Err(error) => status.failures.push(error),
+            }
+        }
+        if let Some(cpu) = self.cpu {
+            match pin_to_cpu(cpu) {
+                Ok(()) => status.cpu = Some(cpu),
+                Err(error) => status.failures.push(error),
+            }
+        }
+        status
+    }
+}
+
+
+/// What `Realtime::apply` managed to set.
+#[derive(Debug, Default)]
+pub struct RealtimeStatus {
+    pub memory_locked: bool,
+    /// The `SCHED_FIFO` priority the thread now runs at.
+    pub priority: Option<i32>,
+    /// The CPU the thread is now pinned to.
+    pub cpu: Option<usize>,
+    /// Why each of the other settings couldn't be applied, usually
+    /// `EPERM`.
+    pub failures: Vec<Error>,
+}
+
+impl RealtimeStatus {
+
+    /// Whether every setting asked for was applied.
+    pub fn is_complete(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_realtime_degrades() {
+        assert!(Realtime::new().apply().is_complete());
+        assert!(set_fifo_priority(0).is_err());
+        assert!(pin_to_cpu(CpuSet::count()).is_err());
+
+        let status = Realtime::new().fifo_priority(100).cpu(CpuSet::count()).apply();
+        assert!(!status.is_complete());
+        assert_eq!(status.failures.len(), 2);
+        assert_eq!((status.priority, status.cpu), (None, None));
+    }
+}
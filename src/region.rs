@@ -0,0 +1,140 @@
+use crate::{check_offset, memory_barrier, open_file, Error};
+
+use nix::sys::mman;
+use nix::unistd::{sysconf, SysconfVar};
+
+use std::ffi::c_void;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+
+
+// mmap offsets must be page aligned, so the mapping starts at the page
+// containing `phys_base` and is long enough to cover `len` bytes past it.
+// Returns the aligned base, the distance from it to `phys_base`, and the
+// length to map.
+fn page_span(phys_base: i64, len: usize, page_size: usize) -> (i64, usize, usize) {
+    let aligned_base = phys_base & !(page_size as i64 - 1);
+    let delta = (phys_base - aligned_base) as usize;
+    let map_len = (delta + len).div_ceil(page_size) * page_size;
+    (aligned_base, delta, map_len)
+}
+
+fn page_size() -> Result<usize, Error> {
+    match sysconf(SysconfVar::PAGE_SIZE) {
+        Ok(Some(size)) => Ok(size as usize),
+        Ok(None) => Err(Error::Other("page size is not available".to_string())),
+        Err(e) => Err(Error::from_nix("failed to query the page size", e)),
+    }
+}
+
+
+/// A mapping of an arbitrary physical register block, for peripherals this
+/// crate does not model. Accesses are bounds-checked against `len` and must
+/// be 32-bit aligned. Each access is fenced with `memory_barrier`.
+pub struct MappedRegion {
+    mapping: *mut c_void,
+    map_len: usize,
+    registers: *mut c_void,
+    len: usize,
+}
+
+impl MappedRegion {
+
+    pub fn open(path: impl Into<PathBuf>, phys_base: i64, len: usize) -> Result<Self, Error> {
+        let path = path.into();
+        let fp: std::fs::File = open_file(&path)?;
+        let (aligned_base, delta, map_len) = page_span(phys_base, len, page_size()?);
+        let mapping = unsafe {
+            mman::mmap(std::ptr::null_mut(), map_len,
+                mman::ProtFlags::PROT_READ | mman::ProtFlags::PROT_WRITE,
+                mman::MapFlags::MAP_SHARED, fp.as_raw_fd(), aligned_base)
+                .map_err(
+                    |e| Error::MmapFailed {
+                        context: format!("failed to map {:#X} from {}", phys_base, path.display()),
+                        source: e,
+                    })?
+        };
+        Ok(Self {
+            mapping,
+            map_len,
+            registers: mapping.wrapping_add(delta),
+            len,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn read_reg(&self, offset: usize) -> Result<u32, Error> {
+        let offset = check_offset(offset, self.len)?;
+        let ptr = self.registers.wrapping_add(offset) as *const u32;
+        let value = unsafe { ptr.read_volatile() };
+        memory_barrier();
+        Ok(value)
+    }
+
+    pub fn write_reg(&self, offset: usize, value: u32) -> Result<(), Error> {
+        let offset = check_offset(offset, self.len)?;
+        let ptr = self.registers.wrapping_add(offset) as *mut u32;
+        memory_barrier();
+        unsafe { ptr.write_volatile(value) };
+        Ok(())
+    }
+
+    /// Locks the mapping into memory, so no access waits on a page fault;
+    /// see also `realtime::lock_memory`.
+    pub fn lock(&self) -> Result<(), Error> {
+        unsafe { mman::mlock(self.mapping, self.map_len) }
+            .map_err(|e| Error::from_nix("failed to lock the mapped region", e))
+    }
+}
+
+// The mapping is owned by the region and only reached through volatile
+// word accesses, like the GPIO register block.
+unsafe impl Send for MappedRegion {}
+unsafe impl Sync for MappedRegion {}
+
+impl Drop for MappedRegion {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = mman::munmap(self.mapping, self.map_len);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_span_alignment() {
+        assert_eq!(page_span(0xfe200000, 0xf4, 4096), (0xfe200000, 0, 4096));
+        assert_eq!(page_span(0xfe201004, 0x10, 4096), (0xfe201000, 4, 4096));
+        assert_eq!(page_span(0xfe200ff8, 0x10, 4096), (0xfe200000, 0xff8, 8192));
+        assert_eq!(page_span(0x3f003000, 0x1c, 65536), (0x3f000000, 0x3000, 65536));
+    }
+
+    #[test]
+    fn test_mapped_region_on_file() {
+        let path = std::env::temp_dir().join(format!("rustberrypi-region-{}", std::process::id()));
+        std::fs::write(&path, vec![0u8; 3 * 4096]).unwrap();
+
+        let region = MappedRegion::open(&path, 4096 + 8, 16).ok().unwrap();
+        assert_eq!(region.len(), 16);
+        assert!(region.write_reg(4, 0xdead_beef).is_ok());
+        assert_eq!(region.read_reg(4).ok(), Some(0xdead_beef));
+        assert_eq!(region.read_reg(16).unwrap_err().offset(), Some(16));
+        assert!(region.write_reg(2, 0).is_err());
+        drop(region);
+
+        let contents = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(&contents[4096 + 12..4096 + 16], &0xdead_beef_u32.to_ne_bytes());
+    }
+}
@@ -0,0 +1,138 @@
+use crate::registers::{GPIO_BLOCK_SIZE, REGISTER_SIZE};
+use crate::Register;
+#[cfg(feature = "std")]
+use crate::{check_offset, Error};
+
+
+const REGISTERS: [Register; 14] = [
+    Register::GPFSEL,
+    Register::GPSET,
+    Register::GPCLR,
+    Register::GPLEV,
+    Register::GPEDS,
+    Register::GPREN,
+    Register::GPFEN,
+    Register::GPHEN,
+    Register::GPLEN,
+    Register::GPAREN,
+    Register::GPAFEN,
+    Register::GPPUD,
+    Register::GPPUDCLK,
+    Register::GPPUPPDNCNTRL,
+];
+
+fn index(register: Register) -> usize {
+    REGISTERS.iter().position(|&candidate| candidate == register).unwrap_or(0)
+}
+
+
+/// Where each register of a BCM-style GPIO block lives, for SoCs and
+/// emulators that lay the same registers out differently. The packing of
+/// pins into words is the BCM one (ten function fields or 32 level bits to
+/// a word); the first word of each register, the distance between its
+/// words and the size of the block can be changed. `Default` is the
+/// datasheet layout every Pi before the 5 uses.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct RegisterMap {
+    offsets: [usize; REGISTERS.len()],
+    word_stride: usize,
+    block_size: usize,
+}
+
+impl Default for RegisterMap {
+    fn default() -> Self {
+        let mut offsets = [0; REGISTERS.len()];
+        for (offset, &register) in offsets.iter_mut().zip(REGISTERS.iter()) {
+            *offset = register as usize;
+        }
+        Self { offsets, word_stride: REGISTER_SIZE as usize, block_size: GPIO_BLOCK_SIZE }
+    }
+}
+
+impl RegisterMap {
+
+    /// Moves the first word of `register` to byte `offset` into the block.
+    pub fn with_offset(mut self, register: Register, offset: usize) -> Self {
+        self.offsets[index(register)] = offset;
+        self
+    }
+
+    /// The bytes between consecutive words of a register, 4 on the BCM
+    /// chips.
+    pub fn with_word_stride(mut self, stride: usize) -> Self {
+        self.word_stride = stride;
+        self
+    }
+
+    /// The length of the block to map, which every register must fit in.
+    pub fn with_block_size(mut self, size: usize) -> Self {
+        self.block_size = size;
+        self
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    // The unchecked byte offset of the word of `register` that holds
+    // `pin`'s bits, for a pin known to be in range.
+    fn offset_of(&self, register: Register, pin: u32) -> usize {
+        let word = (register.offset_of(pin) - register as usize) / REGISTER_SIZE as usize;
+        self.offsets[index(register)] + word * self.word_stride
+    }
+
+    /// `offset` without `std`: `None` if there's no pin `pin` or the word
+    /// doesn't fit in the block.
+    pub fn word_offset(&self, register: Register, pin: u32) -> Option<usize> {
+        register.word_offset(pin)?;
+        let offset = self.offset_of(register, pin);
+        let size = REGISTER_SIZE as usize;
+        (offset.is_multiple_of(size) && offset + size <= self.block_size).then_some(offset)
+    }
+
+    /// The byte offset of the word of `register` that holds `pin`'s bits,
+    /// checked against the block size.
+    #[cfg(feature = "std")]
+    pub fn offset(&self, register: Register, pin: u32) -> Result<usize, Error> {
+        register.to_offset(pin)?;
+        check_offset(self.offset_of(register, pin), self.block_size)
+            .map_err(|error| error.with_register(register))
+    }
+}
+
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{PinFunction, GPIO};
+
+    #[test]
+    fn test_default_map_matches_datasheet() {
+        let map = RegisterMap::default();
+        for &register in REGISTERS.iter() {
+            for pin in [0, 17, 31, 32, 57].iter().copied() {
+                assert_eq!(map.offset(register, pin).ok(), register.to_offset(pin).ok(), "{:?} {}", register, pin);
+            }
+        }
+        assert_eq!(map.block_size(), GPIO_BLOCK_SIZE);
+    }
+
+    #[test]
+    fn test_custom_register_map() {
+        let map = RegisterMap::default()
+            .with_offset(Register::GPSET, 0x200)
+            .with_word_stride(8)
+            .with_block_size(0x400);
+        assert_eq!(map.offset(Register::GPSET, 40).ok(), Some(0x208));
+        assert_eq!(map.offset(Register::GPFSEL, 25).ok(), Some(0x10));
+        assert!(map.offset(Register::GPSET, 58).is_err());
+        assert_eq!(RegisterMap::default().with_block_size(0x38).offset(Register::GPLEV, 40).unwrap_err().offset(), Some(0x38));
+
+        let mut gpio = GPIO::open_for_testing_on(Vec::new());
+        gpio.register_map = RegisterMap::default().with_offset(Register::GPSET, 0x80);
+        gpio.set_function(4, PinFunction::Output).unwrap();
+        gpio.write_register(Register::GPSET, 4, 1 << 4).unwrap();
+        assert_eq!(unsafe { gpio.read_raw(0x80) }, 1 << 4);
+        assert_eq!(unsafe { gpio.read_raw(0x1c) }, 0);
+    }
+}
@@ -0,0 +1,158 @@
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Register {
+    GPFSEL = 0x00,
+    
+    GPSET =  0x1c,
+    GPCLR =  0x28,
+    GPLEV =  0x34,
+    GPEDS =  0x40,
+    GPREN =  0x4c,
+    GPFEN =  0x58,
+    GPHEN =  0x64,
+    GPLEN =  0x70,
+    GPAREN = 0x7c,
+    GPAFEN = 0x88,
+
+    // BCM2835-BCM2837 only
+    GPPUD = 0x94,
+    GPPUDCLK = 0x98,
+
+    // BCM2711 only
+    GPPUPPDNCNTRL = 0xe4,
+}
+
+
+pub(crate) const REGISTER_SIZE: u32 = 4;
+pub(crate) const GPIO_PIN_COUNT: u32 = 58;
+pub(crate) const GPIO_FUNCS_PER_REGISTER: u32 = 10;
+pub(crate) const GPIO_PUPPUD_PER_REGISTER: u32 = 16;
+pub(crate) const GPIO_PINS_PER_BANK: u32 = 32;
+
+pub(crate) const GPIO_BLOCK_SIZE: usize = 0x100;
+
+pub const BANK_COUNT: u32 = 2;
+
+pub const fn bank_of(pin: u32) -> u32 {
+    pin / GPIO_PINS_PER_BANK
+}
+
+pub const fn bit_in_bank(pin: u32) -> u32 {
+    pin % GPIO_PINS_PER_BANK
+}
+
+macro_rules! register_offset {
+    ($pin:expr) => {
+        bank_of($pin) * REGISTER_SIZE
+    };
+}
+
+impl Register {
+
+    /// The byte offset of the word of this register that holds `pin`'s
+    /// bits, or `None` if there's no pin `pin`.
+    pub const fn word_offset(self, pin: u32) -> Option<usize> {
+        if pin >= GPIO_PIN_COUNT {
+            return None;
+        }
+        Some(self.offset_of(pin))
+    }
+
+    // `to_offset` for a pin already known to be in range.
+    pub(crate) const fn offset_of(self, pin: u32) -> usize {
+        match self {
+            Register::GPFSEL => Register::gpfsel_offset_for(pin),
+            Register::GPPUD => Register::GPPUD as usize,
+            Register::GPPUPPDNCNTRL => Register::gp_pullup_pulldown(pin),
+            _ => Register::gp2reg_offset_for(self as u32, pin),
+        }
+    }
+
+    const fn gpfsel_offset_for(pin: u32) -> usize {
+        ((pin / GPIO_FUNCS_PER_REGISTER) * REGISTER_SIZE) as usize
+    }
+
+    const fn gp_pullup_pulldown(pin: u32) -> usize {
+        (Register::GPPUPPDNCNTRL as usize) + 
+            ((pin / GPIO_PUPPUD_PER_REGISTER) * REGISTER_SIZE) as usize
+    }
+
+    const fn gp2reg_offset_for(offset: u32, pin: u32) -> usize {
+        (offset + register_offset!(pin)) as usize
+    }
+
+}
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum PinFunction {
+	Input = 0b000,
+	Output = 0b001,
+	Alt0 = 0b100,
+	Alt1 = 0b101,
+	Alt2 = 0b110,
+	Alt3 = 0b111,
+	Alt4 = 0b011,
+	Alt5 = 0b010,
+    Error = 0b1000,
+}
+
+impl PinFunction {
+
+	pub fn to_bits(&self, pin: u32) -> u32 {
+		let fval = *self as u32;
+		fval <<	((pin % 10) * 3)
+	}
+
+	pub fn clear_mask(pin: u32) -> u32 {
+		!(0b111 << ((pin % 10) * 3))
+	}
+
+    pub fn mask(pin: u32) -> u32 {
+        0b111 << ((pin % 10)* 3)
+    }
+
+    pub fn from_bits(pin: u32, bits: u32) -> PinFunction {
+        let bits = (bits >> ((pin % 10) * 3)) & 0b111;
+        match bits {
+            0b000 => PinFunction::Input,
+            0b001 => PinFunction::Output,
+            0b100 => PinFunction::Alt0,
+            0b101 => PinFunction::Alt1,
+            0b110 => PinFunction::Alt2,
+            0b111 => PinFunction::Alt3,
+            0b011 => PinFunction::Alt4,
+            0b010 => PinFunction::Alt5,
+            _ => PinFunction::Error
+        }
+    }
+}
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum Pull {
+    None = 0b00,
+    Up = 0b01,
+    Down = 0b10,
+}
+
+impl Pull {
+
+    pub fn to_bits(&self, pin: u32) -> u32 {
+        (*self as u32) << ((pin % GPIO_PUPPUD_PER_REGISTER) * 2)
+    }
+
+    pub fn clear_mask(pin: u32) -> u32 {
+        !(0b11 << ((pin % GPIO_PUPPUD_PER_REGISTER) * 2))
+    }
+
+    // 0b11 is reserved on the BCM2711 and reads back as no pull.
+    pub fn from_bits(pin: u32, bits: u32) -> Pull {
+        match (bits >> ((pin % GPIO_PUPPUD_PER_REGISTER) * 2)) & 0b11 {
+            0b01 => Pull::Up,
+            0b10 => Pull::Down,
+            _ => Pull::None,
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use crate::{Error, GpioPin};
+
+use std::time::{Duration, Instant};
+
+
+/// A relay, or anything else switched on and off by one line, on any
+/// `GpioPin`: a native output, an expander line or a shift-register
+/// output. Many relay boards switch on when their input is pulled low, so
+/// the active level is configurable. The relay is switched off when
+/// dropped.
+pub struct Relay<P: GpioPin> {
+    pin: P,
+    active_low: bool,
+}
+
+impl<P: GpioPin> Relay<P> {
+
+    /// A relay that's on while `pin` is high. It starts off.
+    pub fn new(pin: P) -> Result<Self, Error> {
+        Self::with_active_low(pin, false)
+    }
+
+    /// A relay that's on while `pin` is low if `active_low`. It starts
+    /// off.
+    pub fn with_active_low(pin: P, active_low: bool) -> Result<Self, Error> {
+        let relay = Self { pin, active_low };
+        relay.off()?;
+        Ok(relay)
+    }
+
+    pub fn is_active_low(&self) -> bool {
+        self.active_low
+    }
+
+    pub fn set(&self, on: bool) -> Result<(), Error> {
+        self.pin.set(on != self.active_low)
+    }
+
+    pub fn on(&self) -> Result<(), Error> {
+        self.set(true)
+    }
+
+    pub fn off(&self) -> Result<(), Error> {
+        self.set(false)
+    }
+
+    pub fn toggle(&self) -> Result<(), Error> {
+        self.pin.toggle()
+    }
+
+    pub fn is_on(&self) -> Result<bool, Error> {
+        Ok(self.pin.is_set_high()? != self.active_low)
+    }
+}
+
+impl<P: GpioPin> Drop for Relay<P> {
+    fn drop(&mut self) {
+        let _ = self.off();
+    }
+}
+
+
+struct BankChannel<P: GpioPin> {
+    relay: Relay<P>,
+    min_interval: Duration,
+    switched: Option<Instant>,
+}
+
+
+/// Several relays, such as the channels of a relay HAT, numbered in the
+/// order they're added. Each channel has its own active level and starts
+/// off. A channel can be given a minimum time between switches, to spare
+/// its contacts and whatever it drives, and channels can be interlocked so
+/// that at most one of a group is on at once, e.g. the two directions of
+/// a motor. Switches that would break either rule fail with
+/// `WriteRejected`. Every channel is switched off when the bank is
+/// dropped.
+pub struct RelayBank<P: GpioPin> {
+    channels: Vec<BankChannel<P>>,
+    interlocks: Vec<Vec<usize>>,
+}
+
+impl<P: GpioPin> RelayBank<P> {
+
+    pub fn new() -> Self {
+        Self { channels: Vec::new(), interlocks: Vec::new() }
+    }
+
+    /// Adds a relay on `pin`, on while it's low if `active_low`, and
+    /// returns its channel number. It's switched off straight away, but
+    /// `pin` may already have been at the on level since it was made an
+    /// output; for native pins, `Pin::on_drop` and a pin setup that starts
+    /// at the off level close that gap.
+    pub fn add(&mut self, pin: P, active_low: bool) -> Result<usize, Error> {
+        let relay = Relay::with_active_low(pin, active_low)?;
+        self.channels.push(BankChannel { relay, min_interval: Duration::ZERO, switched: None });
+        Ok(self.channels.len() - 1)
+    }
+
+    pub fn len(&self) -> usize {
+        self.channels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_empty()
+    }
+
+    fn channel(&self, channel: usize) -> Result<&BankChannel<P>, Error> {
+        self.channels.get(channel)
+            .ok_or_else(|| Error::Other(format!("the relay bank has no channel {}", channel)))
+    }
+
+    /// Refuses to switch `channel` again until `interval` after its last
+    /// switch.
+    pub fn set_min_interval(&mut self, channel: usize, interval: Duration) -> Result<(), Error> {
+        self.channel(channel)?;
+        self.channels[channel].min_interval = interval;
+        Ok(())
+    }
+
+    pub fn min_interval(&self, channel: usize) -> Result<Duration, Error> {
+        Ok(self.channel(channel)?.min_interval)
+    }
+
+    /// Allows at most one of `channels` to be on at a time. Fails if more
+    /// than one already is.
+    pub fn add_interlock(&mut self, channels: &[usize]) -> Result<(), Error> {
+        let mut on = 0;
+        for &channel in channels {
+            if self.channel(channel)?.relay.is_on()? {
+                on += 1;
+            }
+        }
+        if on > 1 {
+            return Err(Error::Other(format!("more than one of relays {:?} is already on", channels)));
+        }
+        self.interlocks.push(channels.to_vec());
+        Ok(())
+    }
+
+    pub fn is_on(&self, channel: usize) -> Result<bool, Error> {
+        self.channel(channel)?.relay.is_on()
+    }
+
+    /// Whether each channel is on, in order.
+    pub fn states(&self) -> Result<Vec<bool>, Error> {
+        self.channels.iter().map(|channel| channel.relay.is_on()).collect()
+    }
+
+    /// Switches `channel` on or off. Leaving it as it is always succeeds
+    /// and doesn't count as a switch.
+    pub fn set(&mut self, channel: usize, on: bool) -> Result<(), Error> {
+        let current = self.channel(channel)?;
+        if current.relay.is_on()? == on {
+            return Ok(());
+        }
+        if let Some(since) = current.switched.map(|switched| switched.elapsed()) {
+            if since < current.min_interval {
+                return Err(Error::WriteRejected(format!(
+                    "relay {} switched {:?} ago, within its minimum interval of {:?}", channel, since, current.min_interval)));
+            }
+        }
+        if on {
+            for interlock in self.interlocks.iter().filter(|interlock| interlock.contains(&channel)) {
+                for &other in interlock.iter().filter(|&&other| other != channel) {
+                    if self.channels[other].relay.is_on()? {
+                        return Err(Error::WriteRejected(format!(
+                            "relay {} is interlocked with relay {}, which is on", channel, other)));
+                    }
+                }
+            }
+        }
+        let current = &mut self.channels[channel];
+        current.relay.set(on)?;
+        current.switched = Some(Instant::now());
+        Ok(())
+    }
+
+    pub fn on(&mut self, channel: usize) -> Result<(), Error> {
+        self.set(channel, true)
+    }
+
+    pub fn off(&mut self, channel: usize) -> Result<(), Error> {
+        self.set(channel, false)
+    }
+
+    pub fn toggle(&mut self, channel: usize) -> Result<(), Error> {
+        let on = self.is_on(channel)?;
+        self.set(channel, !on)
+    }
+
+    /// Switches every channel off, ignoring minimum intervals, as a safe
+    /// state has to be reachable at once. Tries every channel before
+    /// returning the first failure.
+    pub fn all_off(&mut self) -> Result<(), Error> {
+        let mut result = Ok(());
+        for channel in self.channels.iter_mut() {
+            let switched = match channel.relay.is_on() {
+                Ok(true) => channel.relay.off().map(|()| true),
+                other => other,
+            };
+            match switched {
+                Ok(true) => channel.switched = Some(Instant::now()),
+                Ok(false) => (),
+                Err(error) if result.is_ok() => result = Err(error),
+                Err(_) => (),
+            }
+        }
+        result
+    }
+}
+
+impl<P: GpioPin> Default for RelayBank<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp23017::tests::expander;
+    use crate::GPIO;
+
+    #[test]
+    fn test_relay() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let relay = Relay::with_active_low(gpio.pin(5).unwrap().into_output().unwrap(), true).unwrap();
+        assert!(!relay.is_on().unwrap());
+        assert_eq!(gpio.output_state(5).ok(), Some(Some(true)));
+        relay.on().unwrap();
+        assert_eq!(gpio.output_state(5).ok(), Some(Some(false)));
+        assert!(relay.is_on().unwrap());
+        drop(relay);
+        assert_eq!(gpio.output_state(5).ok(), Some(Some(true)));
+
+        let (mcp, registers, _) = expander();
+        let relay = Relay::new(mcp.pin(8).unwrap().into_output().unwrap()).unwrap();
+        relay.toggle().unwrap();
+        assert!(relay.is_on().unwrap());
+        assert_eq!(registers.lock().unwrap()[0x15], 0x01);
+    }
+
+    #[test]
+    fn test_relay_bank() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let mut bank = RelayBank::new();
+        let up = bank.add(gpio.pin(5).unwrap().into_output().unwrap(), true).unwrap();
+        let down = bank.add(gpio.pin(6).unwrap().into_output().unwrap(), false).unwrap();
+        let light = bank.add(gpio.pin(13).unwrap().into_output().unwrap(), true).unwrap();
+        assert_eq!(bank.states().unwrap(), [false; 3]);
+        assert_eq!((gpio.output_state(5).ok(), gpio.output_state(6).ok()), (Some(Some(true)), Some(Some(false))));
+
+        bank.add_interlock(&[up, down]).unwrap();
+        bank.on(up).unwrap();
+        assert_eq!(gpio.output_state(5).ok(), Some(Some(false)));
+        assert!(matches!(bank.on(down), Err(Error::WriteRejected(_))));
+        assert!(!bank.is_on(down).unwrap());
+        bank.off(up).unwrap();
+        bank.on(down).unwrap();
+        assert!(bank.add_interlock(&[up, 7]).is_err());
+
+        bank.set_min_interval(light, Duration::from_secs(60)).unwrap();
+        bank.toggle(light).unwrap();
+        assert!(matches!(bank.off(light), Err(Error::WriteRejected(_))));
+        bank.on(light).unwrap();
+        bank.all_off().unwrap();
+        assert_eq!(bank.states().unwrap(), [false; 3]);
+
+        bank.on(up).unwrap();
+        drop(bank);
+        assert_eq!(gpio.output_state(5).ok(), Some(Some(true)));
+    }
+}
@@ -0,0 +1,74 @@
+use crate::{check_pin, Error, GPIO};
+
+use std::sync::atomic::Ordering;
+
+
+impl GPIO {
+
+    // Looked up once, when a pin is first changed or asked about, so
+    // drivers bound later aren't seen.
+    fn kernel_consumers(&self) -> &[Option<String>] {
+        self.kernel_consumers.get_or_init(|| self.driver.kernel_consumers())
+    }
+
+    /// The kernel driver holding `pin`'s line, e.g. `"spi0 CS0"` for an SPI
+    /// chip select, or `"kernel"` for a pin taken by a pin function such as
+    /// the serial console's. `None` if the pin is free or the backend can't
+    /// tell.
+    pub fn kernel_consumer(&self, pin: u32) -> Result<Option<&str>, Error> {
+        check_pin(pin)?;
+        Ok(self.kernel_consumers().get(pin as usize).and_then(Option::as_deref))
+    }
+
+    /// Allows changes to `pin` even though a kernel driver holds it. Without
+    /// this, changing the function, pull or level of such a pin is refused
+    /// with `Error::WriteRejected`.
+    pub fn force(&self, pin: u32) -> Result<(), Error> {
+        check_pin(pin)?;
+        self.forced_pins.fetch_or(1 << pin, Ordering::SeqCst);
+        Ok(())
+    }
+
+    pub(crate) fn check_not_reserved(&self, pin: u32) -> Result<(), Error> {
+        if self.forced_pins.load(Ordering::SeqCst) & (1 << pin) != 0 {
+            return Ok(());
+        }
+        match self.kernel_consumer(pin)? {
+            Some(consumer) => Err(Error::WriteRejected(format!(
+                "pin {} is in use by {:?}; call GPIO::force({}) to change it anyway", pin, consumer, pin))),
+            None => Ok(()),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PinFunction, Pull};
+
+    #[test]
+    fn test_kernel_claimed_pins() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let mut consumers = vec![None; 16];
+        consumers[8] = Some("spi0 CS0".to_string());
+        consumers[14] = Some("kernel".to_string());
+        gpio.kernel_consumers.set(consumers).unwrap();
+
+        assert_eq!(gpio.kernel_consumer(8).unwrap(), Some("spi0 CS0"));
+        assert_eq!(gpio.kernel_consumer(17).unwrap(), None);
+        assert!(gpio.kernel_consumer(58).is_err());
+
+        let error = gpio.set_function(14, PinFunction::Output).unwrap_err();
+        assert!(matches!(&error, Error::WriteRejected(message) if message.contains("GPIO::force(14)")));
+        assert!(gpio.set_pull(8, Pull::Up).is_err());
+        assert!(gpio.set_high(8).is_err());
+        assert_eq!(gpio.get_function(14).ok(), Some(PinFunction::Input));
+        gpio.set_function(15, PinFunction::Output).unwrap();
+
+        gpio.force(14).unwrap();
+        gpio.set_function(14, PinFunction::Output).unwrap();
+        assert_eq!(gpio.get_function(14).ok(), Some(PinFunction::Output));
+        assert!(gpio.set_high(8).is_err());
+    }
+}
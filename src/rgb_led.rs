@@ -0,0 +1,382 @@
+use crate::pwm_output::PwmOutput;
+use crate::{Error, Led, PinFunction, GPIO};
+
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+
+// How often an animation updates the colour.
+const ANIMATION_STEP: Duration = Duration::from_millis(10);
+
+
+/// A colour, 0 to 255 per channel.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct Rgb {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Rgb {
+
+    pub const BLACK: Rgb = Rgb::new(0, 0, 0);
+    pub const WHITE: Rgb = Rgb::new(255, 255, 255);
+    pub const RED: Rgb = Rgb::new(255, 0, 0);
+    pub const GREEN: Rgb = Rgb::new(0, 255, 0);
+    pub const BLUE: Rgb = Rgb::new(0, 0, 255);
+
+    pub const fn new(red: u8, green: u8, blue: u8) -> Self {
+        Self { red, green, blue }
+    }
+
+    /// The colour with hue `hue` in degrees, wrapping outside 0 to 360,
+    /// and saturation and value from 0 to 1, clamped.
+    pub fn from_hsv(hue: f32, saturation: f32, value: f32) -> Self {
+        let (saturation, value) = (saturation.clamp(0.0, 1.0), value.clamp(0.0, 1.0));
+        let sector = hue.rem_euclid(360.0) / 60.0;
+        let chroma = value * saturation;
+        let second = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+        let (red, green, blue) = match sector as u32 {
+            0 => (chroma, second, 0.0),
+            1 => (second, chroma, 0.0),
+            2 => (0.0, chroma, second),
+            3 => (0.0, second, chroma),
+            4 => (second, 0.0, chroma),
+            _ => (chroma, 0.0, second),
+        };
+        let channel = |level: f32| ((level + value - chroma) * 255.0).round() as u8;
+        Self::new(channel(red), channel(green), channel(blue))
+    }
+
+    /// The colour's hue in degrees, saturation and value. Greys have hue 0.
+    pub fn to_hsv(self) -> (f32, f32, f32) {
+        let [red, green, blue] = self.levels();
+        let max = red.max(green).max(blue);
+        let chroma = max - red.min(green).min(blue);
+        let hue = if chroma == 0.0 {
+            0.0
+        } else if max == red {
+            60.0 * ((green - blue) / chroma).rem_euclid(6.0)
+        } else if max == green {
+            60.0 * ((blue - red) / chroma + 2.0)
+        } else {
+            60.0 * ((red - green) / chroma + 4.0)
+        };
+        let saturation = if max == 0.0 { 0.0 } else { chroma / max };
+        (hue, saturation, max)
+    }
+
+    /// The colour dimmed to `brightness` of itself, from 0 to 1, clamped.
+    pub fn scale(self, brightness: f32) -> Self {
+        let brightness = brightness.clamp(0.0, 1.0);
+        let channel = |level: u8| (level as f32 * brightness).round() as u8;
+        Self::new(channel(self.red), channel(self.green), channel(self.blue))
+    }
+
+    // Each channel from 0 to 1.
+    fn levels(self) -> [f32; 3] {
+        [self.red, self.green, self.blue].map(|level| level as f32 / 255.0)
+    }
+}
+
+impl From<(u8, u8, u8)> for Rgb {
+    fn from((red, green, blue): (u8, u8, u8)) -> Self {
+        Self::new(red, green, blue)
+    }
+}
+
+
+/// How an RGB LED is wired.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Polarity {
+    /// The LED's shared lead goes to ground, so a channel lights while its
+    /// pin is high.
+    CommonCathode,
+    /// The LED's shared lead goes to 3V3, so a channel lights while its
+    /// pin is low.
+    CommonAnode,
+}
+
+
+/// A colour that changes over time, played by `RgbLed::animate` until
+/// it's stopped.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Animation {
+    /// Fades smoothly from off up to `color` and back once per `period`.
+    Breathe { color: Rgb, period: Duration },
+    /// Shows `color` for `on_time`, then off for `off_time`.
+    Blink { color: Rgb, on_time: Duration, off_time: Duration },
+    /// Cycles through every hue at full saturation and `brightness` once
+    /// per `period`, starting from red.
+    Rainbow { period: Duration, brightness: f32 },
+}
+
+impl Animation {
+
+    /// The colour `elapsed` into the animation.
+    pub fn color_at(&self, elapsed: Duration) -> Rgb {
+        // Where in its period `elapsed` falls, from 0 to 1.
+        let phase = |period: Duration| {
+            if period.is_zero() { 0.0 } else { (elapsed.as_secs_f64() / period.as_secs_f64()).fract() as f32 }
+        };
+        match *self {
+            Animation::Breathe { color, period } => color.scale((1.0 - (2.0 * PI * phase(period)).cos()) / 2.0),
+            Animation::Blink { color, on_time, off_time } => {
+                if phase(on_time + off_time) * (on_time + off_time).as_secs_f32() < on_time.as_secs_f32() {
+                    color
+                } else {
+                    Rgb::BLACK
+                }
+            }
+            Animation::Rainbow { period, brightness } => Rgb::from_hsv(360.0 * phase(period), 1.0, brightness),
+        }
+    }
+}
+
+
+// The three channels' outputs, shared with the animation thread.
+struct Channels<'a> {
+    gpio: &'a GPIO,
+    pins: [u32; 3],
+    outputs: Vec<PwmOutput<'a>>,
+    polarity: Polarity,
+    gamma: f32,
+    color: Rgb,
+}
+
+impl<'a> Channels<'a> {
+    fn show(&mut self, color: Rgb) -> Result<(), Error> {
+        for (output, level) in self.outputs.iter_mut().zip(color.levels().iter()) {
+            let duty = level.powf(self.gamma) as f64;
+            output.set_duty_cycle(match self.polarity {
+                Polarity::CommonCathode => duty,
+                Polarity::CommonAnode => 1.0 - duty,
+            })?;
+        }
+        self.color = color;
+        Ok(())
+    }
+}
+
+impl<'a> Drop for Channels<'a> {
+    // A common-anode LED is lit by a pin left low, so its pins are driven
+    // high once their PWM has stopped.
+    fn drop(&mut self) {
+        for (output, &pin) in self.outputs.drain(..).zip(self.pins.iter()) {
+            drop(output);
+            if self.polarity == Polarity::CommonAnode {
+                let _ = self.gpio.set_function(pin, PinFunction::Output);
+                let _ = self.gpio.set_high(pin);
+            }
+        }
+    }
+}
+
+
+/// An RGB LED on three PWM outputs, red, green and blue, each on the
+/// hardware PWM controller where its pin has a channel and software PWM
+/// otherwise. Levels are gamma corrected as `Led`'s brightness is.
+/// Animations run on a background thread until another colour is set, and
+/// the LED is turned off when it's dropped.
+pub struct RgbLed<'a> {
+    channels: Arc<Mutex<Channels<'a>>>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<'a> RgbLed<'a> {
+
+    /// An LED on `pins`, in red, green, blue order, using hardware PWM
+    /// where it can. It starts off.
+    pub fn new(gpio: &'a GPIO, pins: [u32; 3], polarity: Polarity) -> Result<Self, Error> {
+        Self::with_outputs(gpio, pins, polarity, |pin| PwmOutput::open(gpio, pin, Led::DEFAULT_FREQUENCY_HZ))
+    }
+
+    /// An LED on `pins` driven by software PWM only, e.g. to leave the
+    /// hardware channels to something else.
+    pub fn software(gpio: &'a GPIO, pins: [u32; 3], polarity: Polarity) -> Result<Self, Error> {
+        Self::with_outputs(gpio, pins, polarity, |pin| PwmOutput::software(gpio, pin, Led::DEFAULT_FREQUENCY_HZ))
+    }
+
+    fn with_outputs(
+        gpio: &'a GPIO,
+        pins: [u32; 3],
+        polarity: Polarity,
+        open: impl Fn(u32) -> Result<PwmOutput<'a>, Error>,
+    ) -> Result<Self, Error> {
+        let mut channels = Channels {
+            gpio,
+            pins,
+            outputs: Vec::new(),
+            polarity,
+            gamma: Led::DEFAULT_GAMMA,
+            color: Rgb::BLACK,
+        };
+        for &pin in pins.iter() {
+            channels.outputs.push(open(pin)?);
+        }
+        channels.show(Rgb::BLACK)?;
+        Ok(Self { channels: Arc::new(Mutex::new(channels)), running: Arc::new(AtomicBool::new(false)), thread: None })
+    }
+
+    pub fn polarity(&self) -> Polarity {
+        self.channels.lock().unwrap().polarity
+    }
+
+    /// Sets the exponent mapping levels to duty cycles, as `Led::set_gamma`
+    /// does. Applies from the next colour change.
+    pub fn set_gamma(&mut self, gamma: f32) -> Result<(), Error> {
+        if !(gamma.is_finite() && gamma > 0.0) {
+            return Err(Error::Other(format!("gamma must be positive, not {}", gamma)));
+        }
+        self.channels.lock().unwrap().gamma = gamma;
+        Ok(())
+    }
+
+    /// Shows `color`, stopping any animation.
+    pub fn set_color(&mut self, color: impl Into<Rgb>) -> Result<(), Error> {
+        self.stop_animation();
+        self.channels.lock().unwrap().show(color.into())
+    }
+
+    /// Shows the colour with the given hue, saturation and value, as
+    /// `Rgb::from_hsv` takes them.
+    pub fn set_hsv(&mut self, hue: f32, saturation: f32, value: f32) -> Result<(), Error> {
+        self.set_color(Rgb::from_hsv(hue, saturation, value))
+    }
+
+    /// The colour being shown, which changes as an animation plays.
+    pub fn color(&self) -> Rgb {
+        self.channels.lock().unwrap().color
+    }
+
+    /// The red, green and blue duty cycles, after gamma correction and
+    /// polarity.
+    pub fn duty_cycles(&self) -> [f64; 3] {
+        let channels = self.channels.lock().unwrap();
+        [0, 1, 2].map(|channel| channels.outputs[channel].duty_cycle())
+    }
+
+    pub fn off(&mut self) -> Result<(), Error> {
+        self.set_color(Rgb::BLACK)
+    }
+
+    /// Plays `animation` from a background thread, replacing any already
+    /// playing, and returns at once.
+    pub fn animate(&mut self, animation: Animation) -> Result<(), Error> {
+        self.stop_animation();
+        self.running.store(true, Ordering::Relaxed);
+        let channels = Arc::clone(&self.channels);
+        let running = Arc::clone(&self.running);
+        let run = move || {
+            let started = Instant::now();
+            while running.load(Ordering::Relaxed) {
+                let color = animation.color_at(started.elapsed());
+                if channels.lock().unwrap().show(color).is_err() {
+                    break;
+                }
+                std::thread::sleep(ANIMATION_STEP);
+            }
+        };
+        // SAFETY: the thread borrows the GPIO through the channels, and is
+        // joined before the LED, and so that borrow, goes away.
+        let thread = unsafe {
+            std::thread::Builder::new()
+                .name("rustberrypi-rgb-led".to_string())
+                .spawn_unchecked(run)
+                .map_err(|e| Error::from_io("failed to start the RGB LED animation thread", e))?
+        };
+        self.thread = Some(thread);
+        Ok(())
+    }
+
+    pub fn is_animating(&self) -> bool {
+        self.thread.is_some()
+    }
+
+    /// Stops any animation, leaving the colour it had reached. Returns
+    /// whether one was playing.
+    pub fn stop_animation(&mut self) -> bool {
+        self.running.store(false, Ordering::Relaxed);
+        match self.thread.take() {
+            Some(thread) => {
+                let _ = thread.join();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl<'a> Drop for RgbLed<'a> {
+    fn drop(&mut self) {
+        let _ = self.off();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hsv() {
+        assert_eq!(Rgb::from_hsv(0.0, 1.0, 1.0), Rgb::RED);
+        assert_eq!(Rgb::from_hsv(120.0, 1.0, 1.0), Rgb::GREEN);
+        assert_eq!(Rgb::from_hsv(-120.0, 1.0, 1.0), Rgb::BLUE);
+        assert_eq!(Rgb::from_hsv(60.0, 1.0, 1.0), Rgb::new(255, 255, 0));
+        assert_eq!(Rgb::from_hsv(200.0, 0.0, 0.5), Rgb::new(128, 128, 128));
+        assert_eq!(Rgb::new(0, 255, 255).to_hsv(), (180.0, 1.0, 1.0));
+        assert_eq!(Rgb::new(64, 64, 64).to_hsv().0, 0.0);
+        let (hue, saturation, value) = Rgb::new(200, 40, 120).to_hsv();
+        assert_eq!(Rgb::from_hsv(hue, saturation, value), Rgb::new(200, 40, 120));
+        assert_eq!(Rgb::WHITE.scale(0.5), Rgb::new(128, 128, 128));
+    }
+
+    #[test]
+    fn test_animations() {
+        let ms = Duration::from_millis;
+        let breathe = Animation::Breathe { color: Rgb::WHITE, period: ms(1000) };
+        assert_eq!(breathe.color_at(ms(0)), Rgb::BLACK);
+        assert_eq!(breathe.color_at(ms(500)), Rgb::WHITE);
+        assert_eq!(breathe.color_at(ms(1250)), Rgb::new(128, 128, 128));
+
+        let blink = Animation::Blink { color: Rgb::RED, on_time: ms(100), off_time: ms(300) };
+        assert_eq!(blink.color_at(ms(50)), Rgb::RED);
+        assert_eq!(blink.color_at(ms(150)), Rgb::BLACK);
+        assert_eq!(blink.color_at(ms(450)), Rgb::RED);
+
+        let rainbow = Animation::Rainbow { period: ms(600), brightness: 1.0 };
+        assert_eq!(rainbow.color_at(ms(0)), Rgb::RED);
+        assert_eq!(rainbow.color_at(ms(200)), Rgb::GREEN);
+        assert_eq!(rainbow.color_at(ms(1000)), Rgb::BLUE);
+    }
+
+    #[test]
+    fn test_rgb_led() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let mut led = RgbLed::software(&gpio, [22, 23, 24], Polarity::CommonAnode).unwrap();
+        assert_eq!(led.duty_cycles(), [1.0; 3]);
+        led.set_gamma(1.0).unwrap();
+        led.set_color((255, 0, 51)).unwrap();
+        let duty = led.duty_cycles();
+        assert_eq!(duty[..2], [0.0, 1.0]);
+        assert!((duty[2] - 0.8).abs() < 1e-6);
+
+        led.animate(Animation::Blink { color: Rgb::GREEN, on_time: Duration::from_secs(5), off_time: Duration::ZERO })
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(led.is_animating());
+        assert_eq!(led.color(), Rgb::GREEN);
+        led.set_hsv(240.0, 1.0, 1.0).unwrap();
+        assert!(!led.is_animating());
+        assert_eq!(led.duty_cycles(), [1.0, 1.0, 0.0]);
+
+        drop(led);
+        assert_eq!(gpio.get_function(24).ok(), Some(PinFunction::Output));
+        assert_eq!(gpio.output_state(24).ok(), Some(Some(true)));
+    }
+}
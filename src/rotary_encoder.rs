@@ -0,0 +1,221 @@
+use crate::{Button, CallbackId, EdgeKind, Error, Input, Pin, Pull, Trigger, GPIO};
+
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Direction {
+    /// A leads B.
+    Clockwise,
+    CounterClockwise,
+}
+
+type TurnCallback = Box<dyn FnMut(Direction, i64) + Send>;
+
+
+// The step for each move from one (A, B) state to the next, indexed by
+// old << 2 | new. Moves where both lines changed at once can't be told
+// apart and count as nothing, along with staying put.
+const STEPS: [i8; 16] = [
+    0, -1, 1, 0,
+    1, 0, 0, -1,
+    -1, 0, 0, 1,
+    0, 1, -1, 0,
+];
+
+// Follows the quadrature state and counts whole detents. Bounce only
+// moves the state back and forth, which cancels out.
+struct Quadrature {
+    state: u8,
+    steps: i8,
+    steps_per_detent: i8,
+}
+
+impl Quadrature {
+    fn new(a: bool, b: bool) -> Self {
+        Self { state: (a as u8) << 1 | b as u8, steps: 0, steps_per_detent: RotaryEncoder::DEFAULT_STEPS_PER_DETENT }
+    }
+
+    fn update(&mut self, a: bool, b: bool) -> Option<Direction> {
+        let next = (a as u8) << 1 | b as u8;
+        self.steps += STEPS[(self.state << 2 | next) as usize];
+        self.state = next;
+        if self.steps >= self.steps_per_detent {
+            self.steps -= self.steps_per_detent;
+            Some(Direction::Clockwise)
+        } else if self.steps <= -self.steps_per_detent {
+            self.steps += self.steps_per_detent;
+            Some(Direction::CounterClockwise)
+        } else {
+            None
+        }
+    }
+
+    fn set_line(&mut self, line_a: bool, level: bool) -> Option<Direction> {
+        let (a, b) = (self.state & 0b10 != 0, self.state & 0b01 != 0);
+        if line_a { self.update(level, b) } else { self.update(a, level) }
+    }
+}
+
+struct Shared {
+    decoder: Mutex<Quadrature>,
+    position: AtomicI64,
+    on_turn: Mutex<Option<TurnCallback>>,
+}
+
+impl Shared {
+    fn edge(&self, line_a: bool, edge: EdgeKind) {
+        let turned = self.decoder.lock().unwrap().set_line(line_a, edge == EdgeKind::Rising);
+        if let Some(direction) = turned {
+            let step = if direction == Direction::Clockwise { 1 } else { -1 };
+            let position = self.position.fetch_add(step, Ordering::SeqCst) + step;
+            if let Some(callback) = self.on_turn.lock().unwrap().as_mut() {
+                callback(direction, position);
+            }
+        }
+    }
+}
+
+
+/// An incremental rotary encoder on two input pins, with the common pin to
+/// ground and the pins' pull-ups enabled. Edges on both lines are decoded
+/// from `GPIO::on_edge` callbacks, so the position keeps counting in the
+/// background; the pins can't have other callbacks meanwhile.
+pub struct RotaryEncoder<'a> {
+    gpio: &'a GPIO,
+    a: Pin<'a, Input>,
+    b: Pin<'a, Input>,
+    button: Option<Button<'a>>,
+    shared: Arc<Shared>,
+    callbacks: Vec<CallbackId>,
+}
+
+impl<'a> RotaryEncoder<'a> {
+
+    /// Most encoders go through all four states between detents.
+    pub const DEFAULT_STEPS_PER_DETENT: i8 = 4;
+
+    pub fn new(a: Pin<'a, Input>, b: Pin<'a, Input>) -> Result<Self, Error> {
+        a.set_pull(Pull::Up)?;
+        b.set_pull(Pull::Up)?;
+        let shared = Arc::new(Shared {
+            decoder: Mutex::new(Quadrature::new(a.read()?, b.read()?)),
+            position: AtomicI64::new(0),
+            on_turn: Mutex::new(None),
+        });
+        let mut encoder = Self { gpio: a.gpio(), a, b, button: None, shared, callbacks: Vec::new() };
+        for &(pin, line_a) in [(encoder.a.number(), true), (encoder.b.number(), false)].iter() {
+            let shared = Arc::clone(&encoder.shared);
+            let id = encoder.gpio.on_edge(pin, Trigger::Both, move |event| shared.edge(line_a, event.edge))?;
+            encoder.callbacks.push(id);
+        }
+        Ok(encoder)
+    }
+
+    /// Adds the encoder's push button, if it has one.
+    pub fn with_button(mut self, button: Button<'a>) -> Self {
+        self.button = Some(button);
+        self
+    }
+
+    pub fn button(&self) -> Option<&Button<'a>> {
+        self.button.as_ref()
+    }
+
+    /// How many quadrature steps make one detent: 4 for most encoders, 2
+    /// or 1 for those with a detent at every half or quarter cycle.
+    pub fn set_steps_per_detent(&self, steps: i8) -> Result<(), Error> {
+        if ![1, 2, 4].contains(&steps) {
+            return Err(Error::Other(format!("{} steps per detent isn't 1, 2 or 4", steps)));
+        }
+        let mut decoder = self.shared.decoder.lock().unwrap();
+        decoder.steps_per_detent = steps;
+        decoder.steps = 0;
+        Ok(())
+    }
+
+    /// Detents turned clockwise since the encoder was set up, less those
+    /// turned counter-clockwise.
+    pub fn position(&self) -> i64 {
+        self.shared.position.load(Ordering::SeqCst)
+    }
+
+    pub fn set_position(&self, position: i64) {
+        self.shared.position.store(position, Ordering::SeqCst);
+    }
+
+    /// Calls `callback` with the direction and new position after each
+    /// detent, from the event loop thread.
+    pub fn on_turn(&self, callback: impl FnMut(Direction, i64) + Send + 'static) {
+        *self.shared.on_turn.lock().unwrap() = Some(Box::new(callback));
+    }
+
+    pub fn clear_on_turn(&self) {
+        *self.shared.on_turn.lock().unwrap() = None;
+    }
+}
+
+impl<'a> Drop for RotaryEncoder<'a> {
+    fn drop(&mut self) {
+        for &id in &self.callbacks {
+            let _ = self.gpio.remove_callback(id);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Register;
+
+    // Steps `decoder` through `states`, as (A, B) pairs.
+    fn turn(decoder: &mut Quadrature, states: &[(bool, bool)]) -> Vec<Direction> {
+        states.iter().filter_map(|&(a, b)| decoder.update(a, b)).collect()
+    }
+
+    const CLOCKWISE: [(bool, bool); 4] = [(false, true), (false, false), (true, false), (true, true)];
+
+    #[test]
+    fn test_quadrature_decoding() {
+        let mut decoder = Quadrature::new(true, true);
+        assert_eq!(turn(&mut decoder, &CLOCKWISE), vec![Direction::Clockwise]);
+        let counter: Vec<_> = CLOCKWISE.iter().rev().skip(1).chain(&[(true, true)]).copied().collect();
+        assert_eq!(turn(&mut decoder, &counter), vec![Direction::CounterClockwise]);
+        // Bounce on one line goes back and forth and cancels out.
+        let bouncy = [(false, true), (true, true), (false, true), (false, false), (true, false), (true, true)];
+        assert_eq!(turn(&mut decoder, &bouncy), vec![Direction::Clockwise]);
+        // A jump over a state is ignored rather than guessed at.
+        assert!(turn(&mut decoder, &[(false, false), (true, true)]).is_empty());
+
+        decoder.steps_per_detent = 2;
+        assert_eq!(turn(&mut decoder, &CLOCKWISE), vec![Direction::Clockwise; 2]);
+        assert_eq!(decoder.set_line(false, false), None);
+        assert_eq!(decoder.state, 0b10);
+    }
+
+    #[test]
+    fn test_rotary_encoder_callbacks() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let encoder = RotaryEncoder::new(gpio.pin(5).unwrap().into_input().unwrap(), gpio.pin(6).unwrap().into_input().unwrap()).unwrap();
+        assert_eq!(gpio.get_pull(5).unwrap(), Pull::Up);
+        assert_eq!(gpio.read_register(Register::GPREN, 5).ok(), Some(0b11 << 5));
+        assert!(encoder.set_steps_per_detent(3).is_err());
+        encoder.set_steps_per_detent(1).unwrap();
+
+        let turns = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&turns);
+        encoder.on_turn(move |direction, position| log.lock().unwrap().push((direction, position)));
+        // Both lines read low at the start; A rising first is clockwise.
+        encoder.shared.edge(true, EdgeKind::Rising);
+        encoder.shared.edge(false, EdgeKind::Rising);
+        encoder.shared.edge(false, EdgeKind::Falling);
+        assert_eq!(*turns.lock().unwrap(), vec![(Direction::Clockwise, 1), (Direction::Clockwise, 2), (Direction::CounterClockwise, 1)]);
+        encoder.set_position(10);
+        assert_eq!(encoder.position(), 10);
+
+        drop(encoder);
+        assert_eq!(gpio.read_register(Register::GPREN, 5).ok(), Some(0));
+    }
+}
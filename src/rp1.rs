@@ -0,0 +1,260 @@
+use crate::gpiochip::GpioChip;
+use crate::region::MappedRegion;
+use crate::{Error, GpioBackend, PinEvent, PinFunction, Pull, Trigger};
+
+use std::fs::File;
+use std::path::Path;
+use std::time::Duration;
+
+
+// The RP1's GPIO blocks as /dev/gpiomem0 exposes them: the function
+// selects, the registered I/O (RIO) that reads and drives the pins, and
+// the pad controls, each with one sub-block per bank.
+const IO_BANK_OFFSET: usize = 0x00000;
+const RIO_OFFSET: usize = 0x10000;
+const PADS_OFFSET: usize = 0x20000;
+const BANK_STRIDE: usize = 0x4000;
+const RP1_BLOCK_LEN: usize = 0x30000;
+
+// The first GPIO of each bank; the RP1 has 54.
+const BANK_STARTS: [u32; 3] = [0, 28, 34];
+const RP1_GPIO_COUNT: u32 = 54;
+
+const CTRL_FUNCSEL_MASK: u32 = 0x1f;
+const FUNCSEL_RIO: u32 = 5;
+const FUNCSEL_NULL: u32 = 0x1f;
+
+const RIO_OUT: usize = 0x0;
+const RIO_OE: usize = 0x4;
+const RIO_SYNC_IN: usize = 0xc;
+// Atomic aliases of each RIO register.
+const RIO_SET: usize = 0x2000;
+const RIO_CLR: usize = 0x3000;
+
+const PAD_PULL_DOWN: u32 = 1 << 2;
+const PAD_PULL_UP: u32 = 1 << 3;
+const PAD_INPUT_ENABLE: u32 = 1 << 6;
+const PAD_OUTPUT_DISABLE: u32 = 1 << 7;
+
+
+/// The Pi 5's GPIOs, on the RP1 southbridge behind PCIe, through
+/// `/dev/gpiomem0`. Each pin has its own function select and pad control
+/// register, and is driven and read through the RIO block. Functions a0
+/// to a4 read and set as `Alt0` to `Alt4`; a5 is the RIO itself, so
+/// `Input` or `Output`, and a6 to a8 read as `PinFunction::Error`.
+pub(crate) struct Rp1Backend {
+    region: MappedRegion,
+    // The RP1's gpiochip, for edge events, which the RIO can't deliver.
+    chip: Option<GpioChip>,
+}
+
+impl Rp1Backend {
+
+    pub(crate) fn open(path: impl AsRef<Path>, chip: Option<GpioChip>) -> Result<Self, Error> {
+        Ok(Self { region: MappedRegion::open(path.as_ref(), 0, RP1_BLOCK_LEN)?, chip })
+    }
+
+    // The pin's bank and its index within it.
+    fn locate(pin: u32) -> Result<(usize, usize), Error> {
+        if pin >= RP1_GPIO_COUNT {
+            return Err(Error::InvalidPin { pin, register: None });
+        }
+        let bank = BANK_STARTS.iter().rposition(|&start| pin >= start).unwrap_or(0);
+        Ok((bank, (pin - BANK_STARTS[bank]) as usize))
+    }
+
+    fn ctrl_register(pin: u32) -> Result<usize, Error> {
+        let (bank, index) = Self::locate(pin)?;
+        Ok(IO_BANK_OFFSET + bank * BANK_STRIDE + index * 8 + 4)
+    }
+
+    // The bit to set or clear in one of the bank's RIO registers.
+    fn rio_register(pin: u32, register: usize) -> Result<(usize, u32), Error> {
+        let (bank, index) = Self::locate(pin)?;
+        Ok((RIO_OFFSET + bank * BANK_STRIDE + register, 1 << index))
+    }
+
+    // Each bank's pads start with its voltage select.
+    fn pad_register(pin: u32) -> Result<usize, Error> {
+        let (bank, index) = Self::locate(pin)?;
+        Ok(PADS_OFFSET + bank * BANK_STRIDE + 4 + index * 4)
+    }
+
+    fn modify(&self, offset: usize, clear: u32, set: u32) -> Result<(), Error> {
+        let value = self.region.read_reg(offset)?;
+        self.region.write_reg(offset, value & !clear | set)
+    }
+
+    fn set_output_enable(&self, pin: u32, enabled: bool) -> Result<(), Error> {
+        let (offset, bit) = Self::rio_register(pin, RIO_OE)?;
+        self.region.write_reg(offset + if enabled { RIO_SET } else { RIO_CLR }, bit)
+    }
+
+    fn edge_chip(&self, pin: u32) -> Result<&GpioChip, Error> {
+        self.chip.as_ref().ok_or_else(|| Error::Unsupported(format!(
+            "edge events on pin {} need the RP1's gpiochip, which couldn't be opened or was released", pin)))
+    }
+}
+
+impl GpioBackend for Rp1Backend {
+
+    fn function(&self, pin: u32) -> Result<PinFunction, Error> {
+        let function = match self.region.read_reg(Self::ctrl_register(pin)?)? & CTRL_FUNCSEL_MASK {
+            0 => PinFunction::Alt0,
+            1 => PinFunction::Alt1,
+            2 => PinFunction::Alt2,
+            3 => PinFunction::Alt3,
+            4 => PinFunction::Alt4,
+            FUNCSEL_RIO => {
+                let (offset, bit) = Self::rio_register(pin, RIO_OE)?;
+                if self.region.read_reg(offset)? & bit != 0 { PinFunction::Output } else { PinFunction::Input }
+            }
+            // An unclaimed pad is an undriven input.
+            FUNCSEL_NULL => PinFunction::Input,
+            _ => PinFunction::Error,
+        };
+        Ok(function)
+    }
+
+    fn set_function(&self, pin: u32, function: PinFunction) -> Result<(), Error> {
+        let funcsel = match function {
+            PinFunction::Input | PinFunction::Output => FUNCSEL_RIO,
+            PinFunction::Alt0 => 0,
+            PinFunction::Alt1 => 1,
+            PinFunction::Alt2 => 2,
+            PinFunction::Alt3 => 3,
+            PinFunction::Alt4 => 4,
+            PinFunction::Alt5 | PinFunction::Error => return Err(Error::Unsupported(format!(
+                "the RP1 has no {:?} for pin {}; a5 is its GPIO function", function, pin))),
+        };
+        if funcsel == FUNCSEL_RIO {
+            self.set_output_enable(pin, function == PinFunction::Output)?;
+        }
+        self.modify(Self::pad_register(pin)?, PAD_OUTPUT_DISABLE, PAD_INPUT_ENABLE)?;
+        self.modify(Self::ctrl_register(pin)?, CTRL_FUNCSEL_MASK, funcsel)
+    }
+
+    fn pull(&self, pin: u32) -> Result<Pull, Error> {
+        let pad = self.region.read_reg(Self::pad_register(pin)?)?;
+        Ok(if pad & PAD_PULL_UP != 0 {
+            Pull::Up
+        } else if pad & PAD_PULL_DOWN != 0 {
+            Pull::Down
+        } else {
+            Pull::None
+        })
+    }
+
+    fn set_pull(&self, pin: u32, pull: Pull) -> Result<(), Error> {
+        let set = match pull {
+            Pull::None => 0,
+            Pull::Up => PAD_PULL_UP,
+            Pull::Down => PAD_PULL_DOWN,
+        };
+        self.modify(Self::pad_register(pin)?, PAD_PULL_UP | PAD_PULL_DOWN, set)
+    }
+
+    fn write(&self, pin: u32, level: bool) -> Result<(), Error> {
+        let (offset, bit) = Self::rio_register(pin, RIO_OUT)?;
+        self.region.write_reg(offset + if level { RIO_SET } else { RIO_CLR }, bit)
+    }
+
+    fn read(&self, pin: u32) -> Result<bool, Error> {
+        let (offset, bit) = Self::rio_register(pin, RIO_SYNC_IN)?;
+        Ok(self.region.read_reg(offset)? & bit != 0)
+    }
+
+    fn wait_for_edge(&self, pin: u32, trigger: Trigger, timeout: Option<Duration>) -> Result<PinEvent, Error> {
+        self.edge_chip(pin)?.wait_for_edge(pin, trigger, timeout)
+    }
+
+    fn edge_event_file(&self, pin: u32, trigger: Trigger) -> Result<File, Error> {
+        self.edge_chip(pin)?.edge_event_file(pin, trigger)
+    }
+
+    fn line_name(&self, pin: u32) -> Option<String> {
+        self.chip.as_ref()?.line_name(pin)
+    }
+
+    fn kernel_consumers(&self) -> Vec<Option<String>> {
+        self.chip.as_ref().map(GpioChip::kernel_consumers).unwrap_or_default()
+    }
+
+    fn release_fd(&mut self) -> bool {
+        self.chip.take().is_some()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Soc, GPIO};
+
+    #[test]
+    fn test_rp1_register_layout() {
+        assert_eq!(Rp1Backend::locate(27).ok(), Some((0, 27)));
+        assert_eq!(Rp1Backend::locate(28).ok(), Some((1, 0)));
+        assert_eq!(Rp1Backend::locate(53).ok(), Some((2, 19)));
+        assert!(Rp1Backend::locate(54).is_err());
+        assert_eq!(Rp1Backend::ctrl_register(17).ok(), Some(0x8c));
+        assert_eq!(Rp1Backend::ctrl_register(34).ok(), Some(0x8004));
+        assert_eq!(Rp1Backend::rio_register(30, RIO_SYNC_IN).ok(), Some((0x1400c, 1 << 2)));
+        assert_eq!(Rp1Backend::pad_register(0).ok(), Some(0x20004));
+    }
+
+    #[test]
+    fn test_rp1_pin_access() {
+        let path = std::env::temp_dir().join(format!("rustberrypi-rp1-{}", std::process::id()));
+        std::fs::write(&path, vec![0u8; RP1_BLOCK_LEN]).unwrap();
+        let rp1 = Rp1Backend::open(&path, None).ok().unwrap();
+        rp1.region.write_reg(Rp1Backend::ctrl_register(17).unwrap(), FUNCSEL_NULL).unwrap();
+        assert_eq!(rp1.function(17).ok(), Some(PinFunction::Input));
+
+        rp1.set_function(17, PinFunction::Output).unwrap();
+        assert_eq!(rp1.region.read_reg(0x8c).ok(), Some(FUNCSEL_RIO));
+        assert_eq!(rp1.region.read_reg(0x20004 + 17 * 4).ok(), Some(PAD_INPUT_ENABLE));
+        // The file has no atomic aliases, so the SET and CLR writes land in
+        // registers of their own.
+        assert_eq!(rp1.region.read_reg(RIO_OFFSET + RIO_SET + RIO_OE).ok(), Some(1 << 17));
+        rp1.write(17, false).unwrap();
+        assert_eq!(rp1.region.read_reg(RIO_OFFSET + RIO_CLR + RIO_OUT).ok(), Some(1 << 17));
+
+        rp1.set_function(14, PinFunction::Alt4).unwrap();
+        assert_eq!(rp1.function(14).ok(), Some(PinFunction::Alt4));
+        assert!(rp1.set_function(14, PinFunction::Alt5).is_err());
+        rp1.region.write_reg(Rp1Backend::ctrl_register(14).unwrap(), 7).unwrap();
+        assert_eq!(rp1.function(14).ok(), Some(PinFunction::Error));
+
+        rp1.set_pull(4, Pull::Up).unwrap();
+        assert_eq!(rp1.pull(4).ok(), Some(Pull::Up));
+        rp1.set_pull(4, Pull::Down).unwrap();
+        assert_eq!(rp1.pull(4).ok(), Some(Pull::Down));
+        rp1.region.write_reg(RIO_OFFSET + BANK_STRIDE * 2 + RIO_SYNC_IN, 1 << 6).unwrap();
+        assert_eq!(rp1.read(40).ok(), Some(true));
+        assert_eq!(rp1.read(39).ok(), Some(false));
+        assert!(matches!(rp1.wait_for_edge(4, Trigger::Both, None), Err(Error::Unsupported(_))));
+        drop(rp1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_release_fd() {
+        let path = std::env::temp_dir().join(format!("rustberrypi-rp1-release-{}", std::process::id()));
+        std::fs::write(&path, vec![0u8; RP1_BLOCK_LEN]).unwrap();
+        // Any file stands in for the chip; its ioctls just fail.
+        let chip = GpioChip::open(&path).ok().unwrap();
+        let mut gpio = GPIO::from_backend(Box::new(Rp1Backend::open(&path, Some(chip)).ok().unwrap()), Soc::Bcm2712);
+        let error = gpio.wait_for_edge(4, Trigger::Both, Some(Duration::ZERO)).unwrap_err();
+        assert!(!matches!(error, Error::Unsupported(_)));
+
+        assert!(gpio.release_fd());
+        assert!(!gpio.release_fd());
+        gpio.set_function(14, PinFunction::Alt4).unwrap();
+        assert_eq!(gpio.get_function(14).ok(), Some(PinFunction::Alt4));
+        let error = gpio.wait_for_edge(4, Trigger::Both, Some(Duration::ZERO)).unwrap_err();
+        assert!(matches!(error, Error::Unsupported(_)));
+        drop(gpio);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
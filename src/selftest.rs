@@ -0,0 +1,320 @@
+//! Loopback self-tests, for bringing up a new board or backend, or for a
+//! CI rig on real hardware. Each pair of pins must be jumpered together:
+//! the first is driven and the second read, to check that levels arrive,
+//! that both pins' pulls work, that edges are detected and how long a
+//! level takes to come back. A check that fails is recorded in the report
+//! rather than returned, so one bad pair doesn't hide the rest, and the
+//! pins are put back as they were found. An input's debounce applies to
+//! the edge check, so is best left off.
+
+use crate::bench::{restoring, Measurement};
+use crate::{EdgeKind, Error, PinFunction, Pull, Trigger, GPIO};
+
+use std::fmt;
+use std::sync::{mpsc, Mutex};
+use std::time::{Duration, Instant};
+
+
+// How long a pull is given to move a floating wire.
+const PULL_SETTLE_TIME: Duration = Duration::from_millis(1);
+
+// How long a driven level is given to reach the input.
+const DRIVE_SETTLE_TIME: Duration = Duration::from_micros(10);
+
+
+/// How a single check went.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Outcome {
+    Passed,
+    Failed(String),
+    /// Not run, because an earlier check it depends on failed.
+    Skipped(String),
+}
+
+impl Outcome {
+
+    pub fn is_passed(&self) -> bool {
+        *self == Outcome::Passed
+    }
+
+    fn from_result(result: Result<(), String>) -> Self {
+        result.map_or_else(Outcome::Failed, |()| Outcome::Passed)
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Passed => write!(f, "ok"),
+            Outcome::Failed(reason) => write!(f, "FAILED ({})", reason),
+            Outcome::Skipped(reason) => write!(f, "skipped ({})", reason),
+        }
+    }
+}
+
+
+/// How long each check may wait, and how many times the latency is
+/// measured.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Options {
+    /// How long an edge or a level may take to arrive before its check
+    /// fails.
+    pub timeout: Duration,
+    pub latency_iterations: u32,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self { timeout: Duration::from_millis(100), latency_iterations: 1000 }
+    }
+}
+
+
+/// The checks on one pair of jumpered pins.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PairReport {
+    pub output: u32,
+    pub input: u32,
+    /// Whether the input reads high and low as the output drives it.
+    pub levels: Outcome,
+    /// Whether each pin's pull-up and pull-down move the wire while the
+    /// other pin floats.
+    pub pulls: Outcome,
+    /// Whether driving the output raises a rising and a falling edge on
+    /// the input.
+    pub edges: Outcome,
+    /// Whether the latency could be measured.
+    pub latency: Outcome,
+    /// The time from driving the output high to reading it on the input,
+    /// polling as fast as the backend allows.
+    pub toggle_latency: Option<Measurement>,
+}
+
+impl PairReport {
+
+    pub fn passed(&self) -> bool {
+        self.outcomes().iter().all(|(_, outcome)| outcome.is_passed())
+    }
+
+    /// Each check's name and outcome.
+    pub fn outcomes(&self) -> [(&'static str, &Outcome); 4] {
+        [("levels", &self.levels), ("pulls", &self.pulls), ("edges", &self.edges), ("latency", &self.latency)]
+    }
+}
+
+impl fmt::Display for PairReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "GPIO {} -> GPIO {}:", self.output, self.input)?;
+        for (name, outcome) in self.outcomes().iter() {
+            write!(f, " {} {},", name, outcome)?;
+        }
+        match &self.toggle_latency {
+            Some(latency) => write!(f, " read back in {:?} (min {:?}, max {:?})", latency.mean(), latency.min, latency.max),
+            None => write!(f, " no latency measured"),
+        }
+    }
+}
+
+
+/// The outcome of `run`, one line per pair when displayed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    pub pairs: Vec<PairReport>,
+}
+
+impl Report {
+
+    pub fn passed(&self) -> bool {
+        self.pairs.iter().all(PairReport::passed)
+    }
+
+    /// The pairs and names of the checks that failed.
+    pub fn failures(&self) -> Vec<(u32, u32, &'static str)> {
+        self.pairs.iter()
+            .flat_map(|pair| IntoIterator::into_iter(pair.outcomes())
+                .filter(|(_, outcome)| matches!(outcome, Outcome::Failed(_)))
+                .map(move |(name, _)| (pair.output, pair.input, name)))
+            .collect()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for pair in &self.pairs {
+            writeln!(f, "{}", pair)?;
+        }
+        let failures = self.failures().len();
+        if failures == 0 {
+            write!(f, "all checks passed")
+        } else {
+            write!(f, "{} check(s) failed", failures)
+        }
+    }
+}
+
+
+/// Runs every check on each `(output, input)` pair, in order. Fails only
+/// for pins the board doesn't have or a pin used twice, or if a pair
+/// can't be put back afterwards.
+pub fn run(gpio: &GPIO, pairs: &[(u32, u32)], options: &Options) -> Result<Report, Error> {
+    let mut pins: Vec<u32> = pairs.iter().flat_map(|&(output, input)| [output, input]).collect();
+    for &pin in &pins {
+        gpio.check_board_pin(pin)?;
+    }
+    pins.sort_unstable();
+    if let Some(pin) = pins.windows(2).find(|pins| pins[0] == pins[1]).map(|pins| pins[0]) {
+        return Err(Error::PinInUse(pin));
+    }
+    let pairs = pairs.iter()
+        .map(|&(output, input)| restoring(gpio, &[output, input], || Ok(check_pair(gpio, output, input, options))))
+        .collect::<Result<_, _>>()?;
+    Ok(Report { pairs })
+}
+
+fn check_pair(gpio: &GPIO, output: u32, input: u32, options: &Options) -> PairReport {
+    let levels = Outcome::from_result(check_levels(gpio, output, input));
+    let pulls = Outcome::from_result(check_pulls(gpio, output, input));
+    let (edges, latency, toggle_latency) = if levels.is_passed() {
+        let edges = Outcome::from_result(check_edges(gpio, output, input, options.timeout));
+        match measure_latency(gpio, output, input, options) {
+            Ok(measurement) => (edges, Outcome::Passed, Some(measurement)),
+            Err(reason) => (edges, Outcome::Failed(reason), None),
+        }
+    } else {
+        let skipped = || Outcome::Skipped("the input doesn't follow the output".to_string());
+        (skipped(), skipped(), None)
+    };
+    PairReport { output, input, levels, pulls, edges, latency, toggle_latency }
+}
+
+// Makes `input` an input with no pull, and `output` an output driving low.
+fn drive_low(gpio: &GPIO, output: u32, input: u32) -> Result<(), Error> {
+    gpio.set_function(input, PinFunction::Input)?;
+    gpio.set_pull(input, Pull::None)?;
+    gpio.set_function(output, PinFunction::Output)?;
+    gpio.set_low(output)
+}
+
+fn check_levels(gpio: &GPIO, output: u32, input: u32) -> Result<(), String> {
+    drive_low(gpio, output, input).map_err(|e| e.to_string())?;
+    for &level in [true, false, true, false].iter() {
+        if level { gpio.set_high(output) } else { gpio.set_low(output) }.map_err(|e| e.to_string())?;
+        std::thread::sleep(DRIVE_SETTLE_TIME);
+        let read = gpio.read(input).map_err(|e| e.to_string())?;
+        if read != level {
+            return Err(format!("GPIO {} read {} while GPIO {} drove {}", input, read as u8, output, level as u8));
+        }
+    }
+    Ok(())
+}
+
+// Floats both pins and pulls the wire each way from each end in turn.
+fn check_pulls(gpio: &GPIO, output: u32, input: u32) -> Result<(), String> {
+    let check = || -> Result<Option<String>, Error> {
+        gpio.set_function(output, PinFunction::Input)?;
+        gpio.set_function(input, PinFunction::Input)?;
+        for &(pulled, other) in [(input, output), (output, input)].iter() {
+            gpio.set_pull(other, Pull::None)?;
+            for &pull in [Pull::Up, Pull::Down].iter() {
+                gpio.set_pull(pulled, pull)?;
+                std::thread::sleep(PULL_SETTLE_TIME);
+                if gpio.read(input)? != (pull == Pull::Up) {
+                    return Ok(Some(format!("a {:?} pull on GPIO {} didn't move the wire", pull, pulled)));
+                }
+            }
+            gpio.set_pull(pulled, Pull::None)?;
+        }
+        Ok(None)
+    };
+    match check() {
+        Ok(None) => Ok(()),
+        Ok(Some(reason)) => Err(reason),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+fn check_edges(gpio: &GPIO, output: u32, input: u32, timeout: Duration) -> Result<(), String> {
+    drive_low(gpio, output, input).map_err(|e| e.to_string())?;
+    let (sender, receiver) = mpsc::channel();
+    let sender = Mutex::new(sender);
+    let id = gpio.on_edge(input, Trigger::Both, move |event| {
+        let _ = sender.lock().unwrap().send(event.edge);
+    }).map_err(|e| e.to_string())?;
+
+    let result = [(true, EdgeKind::Rising), (false, EdgeKind::Falling)].iter().try_for_each(|&(level, expected)| {
+        if level { gpio.set_high(output) } else { gpio.set_low(output) }.map_err(|e| e.to_string())?;
+        match receiver.recv_timeout(timeout) {
+            Ok(edge) if edge == expected => Ok(()),
+            Ok(edge) => Err(format!("a {:?} edge on GPIO {} where a {:?} one was expected", edge, input, expected)),
+            Err(_) => Err(format!("no {:?} edge on GPIO {} within {:?}", expected, input, timeout)),
+        }
+    });
+    gpio.remove_callback(id).map_err(|e| e.to_string())?;
+    result
+}
+
+// Polls `input` until it reads `level`, returning how long that took.
+fn wait_for_level(gpio: &GPIO, input: u32, level: bool, timeout: Duration) -> Result<Duration, String> {
+    let start = Instant::now();
+    loop {
+        if gpio.read(input).map_err(|e| e.to_string())? == level {
+            return Ok(start.elapsed());
+        }
+        if start.elapsed() > timeout {
+            return Err(format!("GPIO {} didn't read {} within {:?}", input, level as u8, timeout));
+        }
+    }
+}
+
+fn measure_latency(gpio: &GPIO, output: u32, input: u32, options: &Options) -> Result<Measurement, String> {
+    drive_low(gpio, output, input).map_err(|e| e.to_string())?;
+    let mut samples = Vec::with_capacity(options.latency_iterations as usize);
+    for _ in 0..options.latency_iterations {
+        wait_for_level(gpio, input, false, options.timeout)?;
+        let start = Instant::now();
+        gpio.set_high(output).map_err(|e| e.to_string())?;
+        wait_for_level(gpio, input, true, options.timeout)?;
+        samples.push(start.elapsed());
+        gpio.set_low(output).map_err(|e| e.to_string())?;
+    }
+    Ok(Measurement::from_samples(&samples))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockGpio;
+
+    #[test]
+    fn test_selftest_on_jumpered_pins() {
+        let mock = MockGpio::new();
+        let gpio = mock.gpio().unwrap();
+        mock.connect(17, 27);
+        mock.connect(27, 17);
+        gpio.set_function(17, PinFunction::Alt3).unwrap();
+        let options = Options { latency_iterations: 20, ..Options::default() };
+        let report = run(&gpio, &[(17, 27)], &options).unwrap();
+        assert!(report.passed(), "{}", report);
+        assert_eq!(report.pairs[0].toggle_latency.map(|latency| latency.iterations), Some(20));
+        assert!(report.to_string().ends_with("all checks passed"));
+        assert_eq!(gpio.get_function(17).ok(), Some(PinFunction::Alt3));
+    }
+
+    #[test]
+    fn test_selftest_reports_failures() {
+        let mock = MockGpio::new();
+        let gpio = mock.gpio().unwrap();
+        mock.connect(5, 6);
+        let options = Options { timeout: Duration::from_millis(10), latency_iterations: 1 };
+        let report = run(&gpio, &[(5, 6), (22, 23)], &options).unwrap();
+        assert!(!report.passed());
+        assert_eq!(report.failures(), [(22, 23, "levels"), (22, 23, "pulls")]);
+        assert!(matches!(report.pairs[1].edges, Outcome::Skipped(_)));
+        assert!(report.pairs[0].passed());
+        assert!(report.to_string().ends_with("2 check(s) failed"));
+
+        assert!(matches!(run(&gpio, &[(5, 6), (6, 7)], &options), Err(Error::PinInUse(6))));
+        assert!(run(&gpio, &[(5, 60)], &options).is_err());
+    }
+}
@@ -0,0 +1,171 @@
+use crate::pwm_output::PwmOutput;
+use crate::{Error, HwPwm, GPIO};
+
+
+/// A hobby servo whose position is set by the width of a pulse repeated at
+/// (by default) 50 Hz. Angles from 0 to 180 degrees map linearly onto the
+/// calibrated pulse range; pulses outside it are refused, since driving a
+/// servo past its end stops can damage it.
+pub struct Servo<'a> {
+    output: PwmOutput<'a>,
+    frequency_hz: f64,
+    min_pulse_us: u32,
+    max_pulse_us: u32,
+    pulse_us: Option<u32>,
+}
+
+impl<'a> Servo<'a> {
+
+    pub const DEFAULT_FREQUENCY_HZ: f64 = 50.0;
+    pub const DEFAULT_MIN_PULSE_US: u32 = 1000;
+    pub const DEFAULT_MAX_PULSE_US: u32 = 2000;
+    pub const MAX_ANGLE: f64 = 180.0;
+
+    /// A servo on `pin`, driven by the hardware PWM controller if the pin
+    /// has a PWM channel and `/dev/mem` can be mapped, and by software PWM
+    /// otherwise.
+    pub fn new(gpio: &'a GPIO, pin: u32) -> Result<Self, Error> {
+        Ok(Self::with_output(PwmOutput::open(gpio, pin, Self::DEFAULT_FREQUENCY_HZ)?))
+    }
+
+    /// A servo on a hardware PWM channel that is already routed to its pin.
+    pub fn hardware(pwm: HwPwm) -> Result<Self, Error> {
+        Ok(Self::with_output(PwmOutput::hardware(pwm, Self::DEFAULT_FREQUENCY_HZ)?))
+    }
+
+    /// A servo driven by software PWM on `pin`, which is made an output.
+    pub fn software(gpio: &'a GPIO, pin: u32) -> Result<Self, Error> {
+        Ok(Self::with_output(PwmOutput::software(gpio, pin, Self::DEFAULT_FREQUENCY_HZ)?))
+    }
+
+    fn with_output(output: PwmOutput<'a>) -> Self {
+        Self {
+            output,
+            frequency_hz: Self::DEFAULT_FREQUENCY_HZ,
+            min_pulse_us: Self::DEFAULT_MIN_PULSE_US,
+            max_pulse_us: Self::DEFAULT_MAX_PULSE_US,
+            pulse_us: None,
+        }
+    }
+
+    pub fn is_hardware(&self) -> bool {
+        self.output.is_hardware()
+    }
+
+    /// Sets the pulse widths for 0 and 180 degrees.
+    pub fn set_pulse_range(&mut self, min_us: u32, max_us: u32) -> Result<(), Error> {
+        if min_us >= max_us || max_us as f64 >= 1e6 / self.frequency_hz {
+            return Err(Error::Other(format!(
+                "pulse range {}-{} us doesn't fit a {} Hz period", min_us, max_us, self.frequency_hz)));
+        }
+        self.min_pulse_us = min_us;
+        self.max_pulse_us = max_us;
+        Ok(())
+    }
+
+    pub fn pulse_range(&self) -> (u32, u32) {
+        (self.min_pulse_us, self.max_pulse_us)
+    }
+
+    /// Changes how often the pulse repeats, keeping its width.
+    pub fn set_frequency(&mut self, freq_hz: f64) -> Result<(), Error> {
+        if !(freq_hz.is_finite() && freq_hz > 0.0) || self.max_pulse_us as f64 >= 1e6 / freq_hz {
+            return Err(Error::Other(format!(
+                "a {} Hz period is too short for {} us pulses", freq_hz, self.max_pulse_us)));
+        }
+        self.output.set_frequency(freq_hz)?;
+        self.frequency_hz = freq_hz;
+        match self.pulse_us {
+            Some(pulse_us) => self.write_pulse(pulse_us),
+            None => Ok(()),
+        }
+    }
+
+    pub fn frequency(&self) -> f64 {
+        self.frequency_hz
+    }
+
+    fn write_pulse(&mut self, pulse_us: u32) -> Result<(), Error> {
+        let duty = pulse_us as f64 * self.frequency_hz / 1e6;
+        self.output.set_duty_cycle(duty)
+    }
+
+    /// Sets the pulse width directly. It must lie within the pulse range.
+    pub fn set_pulse_width(&mut self, pulse_us: u32) -> Result<(), Error> {
+        if !(self.min_pulse_us..=self.max_pulse_us).contains(&pulse_us) {
+            return Err(Error::Other(format!(
+                "{} us is outside the servo's {}-{} us range", pulse_us, self.min_pulse_us, self.max_pulse_us)));
+        }
+        self.write_pulse(pulse_us)?;
+        self.pulse_us = Some(pulse_us);
+        Ok(())
+    }
+
+    /// The pulse width last set, or `None` before the first one, while the
+    /// output is held low.
+    pub fn pulse_width(&self) -> Option<u32> {
+        self.pulse_us
+    }
+
+    pub fn set_angle(&mut self, degrees: f64) -> Result<(), Error> {
+        if !(0.0..=Self::MAX_ANGLE).contains(&degrees) {
+            return Err(Error::Other(format!("servo angle {} is outside 0 to {}", degrees, Self::MAX_ANGLE)));
+        }
+        let span = (self.max_pulse_us - self.min_pulse_us) as f64;
+        self.set_pulse_width(self.min_pulse_us + (span * degrees / Self::MAX_ANGLE).round() as u32)
+    }
+
+    /// The angle of the pulse width last set.
+    pub fn angle(&self) -> Option<f64> {
+        let span = (self.max_pulse_us - self.min_pulse_us) as f64;
+        self.pulse_us.map(|pulse_us| (pulse_us - self.min_pulse_us) as f64 * Self::MAX_ANGLE / span)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PinFunction, PwmChannel, PwmMode};
+
+    #[test]
+    fn test_software_servo() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let mut servo = Servo::software(&gpio, 17).unwrap();
+        assert!(!servo.is_hardware());
+        assert_eq!(gpio.get_function(17).ok(), Some(PinFunction::Output));
+        assert_eq!(servo.pulse_width(), None);
+
+        servo.set_angle(90.0).unwrap();
+        assert_eq!(servo.pulse_width(), Some(1500));
+        assert_eq!(servo.angle(), Some(90.0));
+        assert!(servo.set_angle(200.0).is_err());
+        assert!(servo.set_pulse_width(2500).is_err());
+
+        servo.set_pulse_range(500, 2500).unwrap();
+        servo.set_angle(180.0).unwrap();
+        assert_eq!(servo.pulse_width(), Some(2500));
+        assert!(servo.set_pulse_range(500, 25_000).is_err());
+        assert!(servo.set_frequency(500.0).is_err());
+        servo.set_frequency(100.0).unwrap();
+        assert_eq!(servo.frequency(), 100.0);
+    }
+
+    #[test]
+    fn test_hardware_servo() {
+        let path = std::env::temp_dir().join(format!("rustberrypi-servo-{}", std::process::id()));
+        std::fs::write(&path, vec![0u8; 4096]).unwrap();
+        let pwm = HwPwm::open(&path, 0, PwmChannel::Pwm0).ok().unwrap();
+        let mut servo = Servo::hardware(pwm).unwrap();
+        assert!(servo.is_hardware());
+        servo.set_pulse_width(1000).unwrap();
+        if let PwmOutput::Hardware(pwm) = &servo.output {
+            assert_eq!(pwm.frequency(), 50.0);
+            assert_eq!(pwm.duty_cycle(), 0.05);
+            assert_eq!(pwm.mode().ok(), Some(PwmMode::MarkSpace));
+            assert!(pwm.is_enabled().unwrap());
+        }
+        drop(servo);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
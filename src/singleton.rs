@@ -0,0 +1,81 @@
+use crate::{Error, GPIO};
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+
+// Set while a `GPIO` from `take` is alive.
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+
+impl GPIO {
+
+    /// Opens the GPIO block the way `new` does, unless a `GPIO` from `take`
+    /// is still alive in this process, in which case it returns
+    /// `Error::AlreadyTaken`. Dropping the `GPIO` makes it available again.
+    /// `new`, `open` and `steal` don't take part in this and always open
+    /// another mapping.
+    pub fn take() -> Result<Self, Error> {
+        Self::take_with(GPIO::new)
+    }
+
+    /// Opens the GPIO block whether or not it has been taken. It leaves the
+    /// singleton as it was: a `GPIO` from `take` stays the one holding it,
+    /// and dropping the stolen one doesn't release it.
+    ///
+    /// # Safety
+    ///
+    /// Another `GPIO` may be configuring the same pins. The caller must
+    /// make sure the two don't interfere, e.g. because the other one is
+    /// never used again after a panic.
+    pub unsafe fn steal() -> Result<Self, Error> {
+        GPIO::new()
+    }
+
+    fn take_with(open: impl FnOnce() -> Result<Self, Error>) -> Result<Self, Error> {
+        if TAKEN.swap(true, Ordering::SeqCst) {
+            return Err(Error::AlreadyTaken);
+        }
+        match open() {
+            Ok(mut gpio) => {
+                gpio.taken = true;
+                Ok(gpio)
+            }
+            Err(error) => {
+                TAKEN.store(false, Ordering::SeqCst);
+                Err(error)
+            }
+        }
+    }
+}
+
+impl Drop for GPIO {
+    fn drop(&mut self) {
+        // The event loop reads the register block, so it goes first.
+        self.stop_event_loop();
+        if self.taken {
+            TAKEN.store(false, Ordering::SeqCst);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take_is_exclusive() {
+        let open = || Ok(GPIO::open_for_testing_on(Vec::new()));
+        let gpio = GPIO::take_with(open).unwrap();
+        assert!(matches!(GPIO::take_with(open), Err(Error::AlreadyTaken)));
+
+        // Untaken GPIOs, stolen ones included, don't release the singleton.
+        drop(GPIO::open_for_testing_on(Vec::new()));
+        assert!(matches!(GPIO::take_with(open), Err(Error::AlreadyTaken)));
+
+        drop(gpio);
+        let failed: Result<GPIO, Error> = GPIO::take_with(|| Err(Error::Other("no device".to_string())));
+        assert!(matches!(failed, Err(Error::Other(_))));
+        assert!(GPIO::take_with(open).is_ok());
+    }
+}
@@ -0,0 +1,257 @@
+use crate::registers::GPIO_PUPPUD_PER_REGISTER;
+use crate::{Error, PinChange, PinFunction, PinSetup, Pull, Register, GPIO, GPIO_PIN_COUNT};
+
+use std::collections::BTreeMap;
+
+
+// The read/write configuration registers. GPSET/GPCLR are write-only and
+// GPLEV/GPEDS reflect the pins rather than configuration, so they're left out.
+const SNAPSHOT_REGISTERS: [Register; 8] = [
+    Register::GPFSEL,
+    Register::GPREN,
+    Register::GPFEN,
+    Register::GPHEN,
+    Register::GPLEN,
+    Register::GPAREN,
+    Register::GPAFEN,
+    Register::GPPUPPDNCNTRL,
+];
+
+// Every configuration register word as (register, first pin it covers, offset).
+fn snapshot_words() -> Vec<(Register, u32, usize)> {
+    let mut words: Vec<(Register, u32, usize)> = Vec::new();
+    for &register in SNAPSHOT_REGISTERS.iter() {
+        for pin in 0..GPIO_PIN_COUNT {
+            let offset = register.offset_of(pin);
+            if words.last().map(|&(_, _, last)| last) != Some(offset) {
+                words.push((register, pin, offset));
+            }
+        }
+    }
+    words
+}
+
+
+/// The contents of every GPIO configuration register, usable as a target
+/// state for `GPIO::apply_delta`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GpioSnapshot {
+    words: BTreeMap<usize, u32>,
+}
+
+impl GpioSnapshot {
+
+    // Only the configuration registers are captured.
+    fn offset(&self, register: Register, pin: u32) -> Result<usize, Error> {
+        let offset = register.to_offset(pin)?;
+        if !self.words.contains_key(&offset) {
+            return Err(Error::register_error("register is not part of a snapshot").with_register(register));
+        }
+        Ok(offset)
+    }
+
+    pub fn word(&self, register: Register, pin: u32) -> Result<u32, Error> {
+        Ok(self.words[&self.offset(register, pin)?])
+    }
+
+    pub fn set_word(&mut self, register: Register, pin: u32, value: u32) -> Result<(), Error> {
+        let offset = self.offset(register, pin)?;
+        self.words.insert(offset, value);
+        Ok(())
+    }
+
+    pub fn function(&self, pin: u32) -> Result<PinFunction, Error> {
+        Ok(PinFunction::from_bits(pin, self.word(Register::GPFSEL, pin)?))
+    }
+
+    pub fn set_function(&mut self, pin: u32, function: PinFunction) -> Result<(), Error> {
+        let value = self.word(Register::GPFSEL, pin)? & PinFunction::clear_mask(pin) | function.to_bits(pin);
+        self.set_word(Register::GPFSEL, pin, value)
+    }
+
+    pub fn pull(&self, pin: u32) -> Result<Pull, Error> {
+        Ok(Pull::from_bits(pin, self.word(Register::GPPUPPDNCNTRL, pin)?))
+    }
+
+    pub fn set_pull(&mut self, pin: u32, pull: Pull) -> Result<(), Error> {
+        let value = self.word(Register::GPPUPPDNCNTRL, pin)? & Pull::clear_mask(pin) | pull.to_bits(pin);
+        self.set_word(Register::GPPUPPDNCNTRL, pin, value)
+    }
+}
+
+
+/// The function, pull and output level of chosen pins, as
+/// `GPIO::snapshot_pins` found them, for `GPIO::restore`. Unlike
+/// `GpioSnapshot` it works on every backend.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PinSnapshot {
+    setups: Vec<PinSetup>,
+}
+
+impl PinSnapshot {
+
+    /// Each pin's state as a setup `GPIO::configure` would apply. Only
+    /// outputs have an `initial_level`, and `edge` is always `None`.
+    pub fn pins(&self) -> &[PinSetup] {
+        &self.setups
+    }
+}
+
+
+impl GPIO {
+
+    /// Records `pins`' state so that `restore` can put it back, e.g. to
+    /// leave the board as it was found, or to return to a known safe state
+    /// after an error. An output's level is the one this `GPIO` last drove,
+    /// or the one read back from the pin if it hasn't driven it.
+    pub fn snapshot_pins(&self, pins: &[u32]) -> Result<PinSnapshot, Error> {
+        let setups = pins.iter()
+            .map(|&pin| {
+                let function = self.get_function(pin)?;
+                let initial_level = match function {
+                    PinFunction::Output => Some(match self.output_state(pin)? {
+                        Some(level) => level,
+                        None => self.read(pin)?,
+                    }),
+                    _ => None,
+                };
+                Ok(PinSetup { pin, function, pull: self.get_pull(pin)?, initial_level, edge: None })
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(PinSnapshot { setups })
+    }
+
+    /// Puts the pins of `snapshot` back as they were, through `configure`,
+    /// so outputs come back at their old level without a glitch and every
+    /// change passes the write policy and audit hook.
+    pub fn restore(&self, snapshot: &PinSnapshot) -> Result<(), Error> {
+        self.configure(&snapshot.setups)
+    }
+
+    pub fn snapshot(&self) -> Result<GpioSnapshot, Error> {
+        let mut words = BTreeMap::new();
+        for (register, pin, offset) in snapshot_words() {
+            let value = self.read_register(register, pin)?;
+            words.insert(offset, value);
+        }
+        Ok(GpioSnapshot { words })
+    }
+
+    /// Moves the configuration registers to `target`, writing only the words
+    /// that differ from the current contents. Function and pull changes are
+    /// vetted by the write policy before anything is written and reported to
+    /// the audit hook afterwards. Returns the number of register writes.
+    pub fn apply_delta(&self, target: &GpioSnapshot) -> Result<usize, Error> {
+        self.check_writable()?;
+        let guard = self.config_lock.lock().unwrap();
+        let mut writes: Vec<(Register, u32, u32)> = Vec::new();
+        let mut changes: Vec<PinChange> = Vec::new();
+        for (register, first_pin, offset) in snapshot_words() {
+            let wanted = target.words[&offset];
+            let current = self.read_register(register, first_pin)?;
+            if current == wanted {
+                continue;
+            }
+            if register == Register::GPFSEL {
+                let last_pin = (first_pin + 10).min(GPIO_PIN_COUNT);
+                for pin in first_pin..last_pin {
+                    let old = PinFunction::from_bits(pin, current);
+                    let new = PinFunction::from_bits(pin, wanted);
+                    if old != new {
+                        changes.push(PinChange::Function { pin, old, new });
+                    }
+                }
+            }
+            if register == Register::GPPUPPDNCNTRL {
+                let last_pin = (first_pin + GPIO_PUPPUD_PER_REGISTER).min(GPIO_PIN_COUNT);
+                for pin in first_pin..last_pin {
+                    let old = Pull::from_bits(pin, current);
+                    let new = Pull::from_bits(pin, wanted);
+                    if old != new {
+                        changes.push(PinChange::Pull { pin, old, new });
+                    }
+                }
+            }
+            writes.push((register, first_pin, wanted));
+        }
+
+        self.check_policies(&changes)?;
+        for &(register, pin, value) in writes.iter() {
+            self.write_register(register, pin, value)?;
+        }
+        drop(guard);
+        for change in changes {
+            self.audit(change);
+        }
+        Ok(writes.len())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_words_cover_configuration_registers() {
+        let words = snapshot_words();
+        assert_eq!(words.len(), 6 + 6 * 2 + 4);
+        assert_eq!(words[0], (Register::GPFSEL, 0, 0x00));
+        assert_eq!(words[5], (Register::GPFSEL, 50, 0x14));
+        assert_eq!(words[7], (Register::GPREN, 32, 0x50));
+        assert_eq!(words[21], (Register::GPPUPPDNCNTRL, 48, 0xf0));
+    }
+
+    #[test]
+    fn test_apply_delta_writes_only_changed_registers() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        gpio.set_function(4, PinFunction::Output).unwrap();
+
+        let mut target = gpio.snapshot().unwrap();
+        assert_eq!(gpio.apply_delta(&target).ok(), Some(0));
+
+        target.set_function(17, PinFunction::Output).unwrap();
+        target.set_function(18, PinFunction::Alt5).unwrap();
+        assert_eq!(gpio.apply_delta(&target).ok(), Some(1));
+        assert_eq!(gpio.get_function(4).ok(), Some(PinFunction::Output));
+        assert_eq!(gpio.get_function(17).ok(), Some(PinFunction::Output));
+        assert_eq!(gpio.get_function(18).ok(), Some(PinFunction::Alt5));
+        assert_eq!(gpio.snapshot().ok(), Some(target.clone()));
+
+        target.set_function(4, PinFunction::Input).unwrap();
+        target.set_word(Register::GPFEN, 40, 1 << 8).unwrap();
+        assert_eq!(gpio.apply_delta(&target).ok(), Some(2));
+        assert_eq!(gpio.snapshot().ok(), Some(target.clone()));
+
+        target.set_pull(20, Pull::Up).unwrap();
+        assert_eq!(gpio.apply_delta(&target).ok(), Some(1));
+        assert_eq!(gpio.get_pull(20).ok(), Some(Pull::Up));
+        assert!(target.set_word(Register::GPSET, 20, 1).is_err());
+        assert!(target.function(58).is_err());
+    }
+
+    #[test]
+    fn test_restore_pin_snapshot() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        gpio.set_function(17, PinFunction::Output).unwrap();
+        gpio.set_high(17).unwrap();
+        gpio.set_pull(22, Pull::Up).unwrap();
+        gpio.switch_to_alt(&[14], PinFunction::Alt0).unwrap();
+
+        let saved = gpio.snapshot_pins(&[17, 22, 14]).unwrap();
+        assert_eq!(saved.pins()[0].initial_level, Some(true));
+        assert_eq!(saved.pins()[1].initial_level, None);
+
+        gpio.set_low(17).unwrap();
+        gpio.set_function(17, PinFunction::Input).unwrap();
+        gpio.set_pull(22, Pull::Down).unwrap();
+        gpio.set_function(14, PinFunction::Output).unwrap();
+        gpio.restore(&saved).unwrap();
+        assert_eq!(gpio.get_function(17).ok(), Some(PinFunction::Output));
+        assert_eq!(gpio.output_state(17).ok(), Some(Some(true)));
+        assert_eq!(gpio.get_pull(22).ok(), Some(Pull::Up));
+        assert_eq!(gpio.get_function(14).ok(), Some(PinFunction::Alt0));
+        assert_eq!(gpio.snapshot_pins(&[17, 22, 14]).ok(), Some(saved));
+        assert!(gpio.snapshot_pins(&[58]).is_err());
+    }
+}
@@ -0,0 +1,171 @@
+use crate::{device_tree, Register};
+
+use std::path::Path;
+
+
+const CPUINFO_PATH: &str = "/proc/cpuinfo";
+
+// Set in new-style revision codes, which encode the SoC in bits 12-15.
+const REVISION_NEW_STYLE: u32 = 1 << 23;
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Soc {
+    Bcm2835,
+    Bcm2836,
+    Bcm2837,
+    Bcm2711,
+    /// The Pi 5's SoC, whose GPIOs are on the RP1 rather than the SoC.
+    Bcm2712,
+}
+
+impl Soc {
+
+    /// The BCM2711 replaced the clocked GPPUD/GPPUDCLK pull sequence with
+    /// directly readable GPIO_PUP_PDN_CNTRL registers.
+    pub fn has_pull_control_registers(self) -> bool {
+        self == Soc::Bcm2711
+    }
+
+    /// The number of GPIOs: 54 before the BCM2711, which added four. The
+    /// Pi 5's RP1 has 54 again.
+    pub fn gpio_count(self) -> u32 {
+        match self {
+            Soc::Bcm2711 => 58,
+            _ => 54,
+        }
+    }
+
+    /// Whether the GPIO block has `register`: the BCM2711 swapped
+    /// GPPUD/GPPUDCLK for GPIO_PUP_PDN_CNTRL, and the BCM2712 has no
+    /// block, its GPIOs being on the RP1.
+    pub fn has_register(self, register: Register) -> bool {
+        match (self, register) {
+            (Soc::Bcm2712, _) => false,
+            (_, Register::GPPUD) | (_, Register::GPPUDCLK) => !self.has_pull_control_registers(),
+            (_, Register::GPPUPPDNCNTRL) => self.has_pull_control_registers(),
+            _ => true,
+        }
+    }
+
+    pub fn peripheral_base(self) -> i64 {
+        match self {
+            Soc::Bcm2835 => 0x2000_0000,
+            Soc::Bcm2836 | Soc::Bcm2837 => 0x3f00_0000,
+            Soc::Bcm2711 => 0xfe00_0000,
+            Soc::Bcm2712 => 0x10_7c00_0000,
+        }
+    }
+
+    /// Decodes the board revision code from `/proc/cpuinfo`. Old-style codes
+    /// were only used on BCM2835 boards.
+    pub fn from_revision(revision: u32) -> Option<Soc> {
+        if revision & REVISION_NEW_STYLE == 0 {
+            return Some(Soc::Bcm2835);
+        }
+        match (revision >> 12) & 0xf {
+            0 => Some(Soc::Bcm2835),
+            1 => Some(Soc::Bcm2836),
+            2 => Some(Soc::Bcm2837),
+            3 => Some(Soc::Bcm2711),
+            4 => Some(Soc::Bcm2712),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn from_cpuinfo() -> Option<Soc> {
+        Soc::from_revision(cpuinfo_revision()?)
+    }
+
+    // `compatible` is a list of NUL-terminated strings, most specific first.
+    pub(crate) fn from_compatible(compatible: &[u8]) -> Option<Soc> {
+        compatible.split(|&byte| byte == 0)
+            .filter_map(|entry| match entry {
+                b"brcm,bcm2712" => Some(Soc::Bcm2712),
+                b"brcm,bcm2711" => Some(Soc::Bcm2711),
+                b"brcm,bcm2837" => Some(Soc::Bcm2837),
+                b"brcm,bcm2836" => Some(Soc::Bcm2836),
+                b"brcm,bcm2835" => Some(Soc::Bcm2835),
+                _ => None,
+            })
+            .next()
+    }
+
+    // The BCM2836 and BCM2837 share a peripheral base, so this can only
+    // narrow them down to the family; the pull mechanism is the same.
+    pub(crate) fn from_peripheral_base(base: i64) -> Soc {
+        match base {
+            0x2000_0000 => Soc::Bcm2835,
+            0x3f00_0000 => Soc::Bcm2837,
+            _ => Soc::Bcm2711,
+        }
+    }
+
+    pub(crate) fn detect(peripheral_base: Option<i64>) -> Option<Soc> {
+        device_tree::compatible()
+            .and_then(|compatible| Soc::from_compatible(&compatible))
+            .or_else(Soc::from_cpuinfo)
+            .or_else(|| peripheral_base.map(Soc::from_peripheral_base))
+    }
+}
+
+
+pub(crate) fn cpuinfo_revision() -> Option<u32> {
+    parse_cpuinfo_revision(&std::fs::read_to_string(Path::new(CPUINFO_PATH)).ok()?)
+}
+
+pub(crate) fn parse_cpuinfo_revision(cpuinfo: &str) -> Option<u32> {
+    cpuinfo.lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(key, _)| key.trim() == "Revision")
+        .and_then(|(_, value)| u32::from_str_radix(value.trim(), 16).ok())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_soc_from_compatible() {
+        assert_eq!(Soc::from_compatible(b"raspberrypi,4-model-b\0brcm,bcm2711\0"), Some(Soc::Bcm2711));
+        assert_eq!(Soc::from_compatible(b"raspberrypi,3-model-b-plus\0brcm,bcm2837\0"), Some(Soc::Bcm2837));
+        assert_eq!(Soc::from_compatible(b"raspberrypi,model-zero-w\0brcm,bcm2835\0"), Some(Soc::Bcm2835));
+        assert_eq!(Soc::from_compatible(b"raspberrypi,5-model-b\0brcm,bcm2712\0"), Some(Soc::Bcm2712));
+        assert_eq!(Soc::from_compatible(b"qemu,virt\0"), None);
+    }
+
+    #[test]
+    fn test_soc_from_revision() {
+        assert_eq!(Soc::from_revision(0x000e), Some(Soc::Bcm2835));
+        assert_eq!(Soc::from_revision(0x1000002), Some(Soc::Bcm2835));
+        assert_eq!(Soc::from_revision(0x9000c1), Some(Soc::Bcm2835));
+        assert_eq!(Soc::from_revision(0xa01041), Some(Soc::Bcm2836));
+        assert_eq!(Soc::from_revision(0xa02082), Some(Soc::Bcm2837));
+        assert_eq!(Soc::from_revision(0x902120), Some(Soc::Bcm2837));
+        assert_eq!(Soc::from_revision(0xc03114), Some(Soc::Bcm2711));
+        assert_eq!(Soc::from_revision(0xc03130), Some(Soc::Bcm2711));
+        assert_eq!(Soc::from_revision(0xd04170), Some(Soc::Bcm2712));
+        assert_eq!(Soc::from_revision(0xd05170), None);
+        assert_eq!(Soc::Bcm2837.peripheral_base(), 0x3f000000);
+    }
+
+    #[test]
+    fn test_parse_cpuinfo_revision() {
+        let cpuinfo = "processor\t: 3\nBogoMIPS\t: 108.00\n\nHardware\t: BCM2835\nRevision\t: c03114\nSerial\t\t: 10000000deadbeef\n";
+        assert_eq!(parse_cpuinfo_revision(cpuinfo), Some(0xc03114));
+        assert_eq!(parse_cpuinfo_revision("processor\t: 0\nmodel name\t: Intel\n"), None);
+    }
+
+    #[test]
+    fn test_soc_from_peripheral_base() {
+        assert_eq!(Soc::from_peripheral_base(0x20000000), Soc::Bcm2835);
+        assert_eq!(Soc::from_peripheral_base(0x3f000000), Soc::Bcm2837);
+        assert_eq!(Soc::from_peripheral_base(0xfe000000), Soc::Bcm2711);
+        assert!(!Soc::Bcm2837.has_pull_control_registers());
+        assert!(Soc::Bcm2711.has_pull_control_registers());
+        assert!(Soc::Bcm2837.has_register(Register::GPPUDCLK) && !Soc::Bcm2837.has_register(Register::GPPUPPDNCNTRL));
+        assert!(!Soc::Bcm2711.has_register(Register::GPPUD) && Soc::Bcm2711.has_register(Register::GPPUPPDNCNTRL));
+        assert!(Soc::Bcm2835.has_register(Register::GPLEV) && !Soc::Bcm2712.has_register(Register::GPLEV));
+    }
+}
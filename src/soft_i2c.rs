@@ -0,0 +1,323 @@
+use crate::timing::wait_until;
+use crate::{Error, Pin, PinFunction, Pull, Unconfigured, GPIO};
+
+use std::time::{Duration, Instant};
+
+
+/// An I2C master bit-banged on any two pins. The lines are driven
+/// open-drain: a pin's output latch is left low and the pin is switched to
+/// an output to pull its line low, or back to an input to let the pull-up
+/// raise it. The internal pull-ups are enabled, but they're weak, so
+/// anything faster than the default speed wants external ones too.
+///
+/// A device may stretch the clock by holding SCL low; the master waits for
+/// it up to the stretch timeout. Only 7-bit addresses are supported.
+pub struct SoftI2c<'a> {
+    sda: Pin<'a, Unconfigured>,
+    scl: Pin<'a, Unconfigured>,
+    half_period: Duration,
+    stretch_timeout: Duration,
+}
+
+impl<'a> SoftI2c<'a> {
+
+    pub const DEFAULT_FREQUENCY_HZ: f64 = 100_000.0;
+
+    /// The SMBus limit on how long a device may hold the clock low.
+    pub const DEFAULT_STRETCH_TIMEOUT: Duration = Duration::from_millis(25);
+
+    /// A master at `DEFAULT_FREQUENCY_HZ` with both lines released.
+    pub fn new(sda: Pin<'a, Unconfigured>, scl: Pin<'a, Unconfigured>) -> Result<Self, Error> {
+        let i2c = Self {
+            sda, scl,
+            half_period: Duration::from_secs_f64(0.5 / Self::DEFAULT_FREQUENCY_HZ),
+            stretch_timeout: Self::DEFAULT_STRETCH_TIMEOUT,
+        };
+        let gpio = i2c.gpio();
+        for &pin in [i2c.sda.number(), i2c.scl.number()].iter() {
+            gpio.set_pull(pin, Pull::Up)?;
+            gpio.set_low(pin)?;
+            i2c.release(pin)?;
+        }
+        Ok(i2c)
+    }
+
+    fn gpio(&self) -> &'a GPIO {
+        self.sda.gpio()
+    }
+
+    pub fn set_frequency(&mut self, freq_hz: f64) -> Result<(), Error> {
+        if !(freq_hz.is_finite() && freq_hz > 0.0) {
+            return Err(Error::Other(format!("I2C clock frequency must be positive, not {}", freq_hz)));
+        }
+        self.half_period = Duration::from_secs_f64(0.5 / freq_hz);
+        Ok(())
+    }
+
+    pub fn frequency(&self) -> f64 {
+        0.5 / self.half_period.as_secs_f64()
+    }
+
+    pub fn set_stretch_timeout(&mut self, timeout: Duration) {
+        self.stretch_timeout = timeout;
+    }
+
+    pub fn stretch_timeout(&self) -> Duration {
+        self.stretch_timeout
+    }
+
+    fn release(&self, pin: u32) -> Result<(), Error> {
+        self.gpio().set_function(pin, PinFunction::Input)
+    }
+
+    fn pull_low(&self, pin: u32) -> Result<(), Error> {
+        self.gpio().set_function(pin, PinFunction::Output)
+    }
+
+    fn set_sda(&self, high: bool) -> Result<(), Error> {
+        if high { self.release(self.sda.number()) } else { self.pull_low(self.sda.number()) }
+    }
+
+    fn pull_scl_low(&self) -> Result<(), Error> {
+        self.pull_low(self.scl.number())
+    }
+
+    // Releases SCL and waits for any device stretching the clock to let
+    // go of it. Bit timing restarts from the moment it does.
+    fn release_scl(&self, deadline: &mut Instant) -> Result<(), Error> {
+        let scl = self.scl.number();
+        self.release(scl)?;
+        if self.gpio().read(scl)? {
+            return Ok(());
+        }
+        let limit = Instant::now() + self.stretch_timeout;
+        while !self.gpio().read(scl)? {
+            if Instant::now() >= limit {
+                return Err(Error::Timeout(format!(
+                    "SCL (pin {}) was held low for more than {:?}", scl, self.stretch_timeout)));
+            }
+            std::thread::yield_now();
+        }
+        *deadline = Instant::now();
+        Ok(())
+    }
+
+    fn wait_half_period(&self, deadline: &mut Instant) {
+        *deadline += self.half_period;
+        wait_until(*deadline);
+    }
+
+    // Also serves as a repeated start, as it's entered with SCL low.
+    fn start(&self, deadline: &mut Instant) -> Result<(), Error> {
+        self.set_sda(true)?;
+        self.wait_half_period(deadline);
+        self.release_scl(deadline)?;
+        self.wait_half_period(deadline);
+        self.set_sda(false)?;
+        self.wait_half_period(deadline);
+        self.pull_scl_low()
+    }
+
+    fn stop(&self, deadline: &mut Instant) -> Result<(), Error> {
+        self.set_sda(false)?;
+        self.wait_half_period(deadline);
+        self.release_scl(deadline)?;
+        self.wait_half_period(deadline);
+        self.set_sda(true)?;
+        self.wait_half_period(deadline);
+        Ok(())
+    }
+
+    fn write_bit(&self, bit: bool, deadline: &mut Instant) -> Result<(), Error> {
+        self.set_sda(bit)?;
+        self.wait_half_period(deadline);
+        self.release_scl(deadline)?;
+        self.wait_half_period(deadline);
+        self.pull_scl_low()
+    }
+
+    fn read_bit(&self, deadline: &mut Instant) -> Result<bool, Error> {
+        self.set_sda(true)?;
+        self.wait_half_period(deadline);
+        self.release_scl(deadline)?;
+        self.wait_half_period(deadline);
+        let bit = self.gpio().read(self.sda.number())?;
+        self.pull_scl_low()?;
+        Ok(bit)
+    }
+
+    // Returns whether the device acknowledged the byte.
+    fn write_byte(&self, byte: u8, deadline: &mut Instant) -> Result<bool, Error> {
+        for bit in (0..8).rev() {
+            self.write_bit(byte & (1 << bit) != 0, deadline)?;
+        }
+        Ok(!self.read_bit(deadline)?)
+    }
+
+    fn read_byte(&self, ack: bool, deadline: &mut Instant) -> Result<u8, Error> {
+        let mut byte = 0u8;
+        for _ in 0..8 {
+            byte = byte << 1 | self.read_bit(deadline)? as u8;
+        }
+        self.write_bit(!ack, deadline)?;
+        Ok(byte)
+    }
+
+    fn send_address(&self, address: u8, read: bool, deadline: &mut Instant) -> Result<(), Error> {
+        if !self.write_byte(address << 1 | read as u8, deadline)? {
+            return Err(Error::NoAcknowledge(format!("no device acknowledged address {:#04x}", address)));
+        }
+        Ok(())
+    }
+
+    fn send(&self, address: u8, data: &[u8], deadline: &mut Instant) -> Result<(), Error> {
+        self.send_address(address, false, deadline)?;
+        for (index, &byte) in data.iter().enumerate() {
+            if !self.write_byte(byte, deadline)? {
+                return Err(Error::NoAcknowledge(format!(
+                    "device {:#04x} didn't acknowledge byte {} of {}", address, index, data.len())));
+            }
+        }
+        Ok(())
+    }
+
+    // Acknowledges every byte but the last, which tells the device to let
+    // go of SDA for the stop condition.
+    fn receive(&self, address: u8, buffer: &mut [u8], deadline: &mut Instant) -> Result<(), Error> {
+        self.send_address(address, true, deadline)?;
+        let count = buffer.len();
+        for (index, byte) in buffer.iter_mut().enumerate() {
+            *byte = self.read_byte(index + 1 < count, deadline)?;
+        }
+        Ok(())
+    }
+
+    // Runs `transfer` between a start and a stop condition, sending the
+    // stop even if the transfer fails.
+    fn transaction(&self, address: u8, transfer: impl FnOnce(&mut Instant) -> Result<(), Error>) -> Result<(), Error> {
+        if address > 0x7f {
+            return Err(Error::Other(format!("I2C address {:#x} doesn't fit in 7 bits", address)));
+        }
+        let mut deadline = Instant::now();
+        self.start(&mut deadline)?;
+        let result = transfer(&mut deadline);
+        let stopped = self.stop(&mut deadline);
+        result?;
+        stopped
+    }
+
+    pub fn write(&self, address: u8, data: &[u8]) -> Result<(), Error> {
+        self.transaction(address, |deadline| self.send(address, data, deadline))
+    }
+
+    pub fn read(&self, address: u8, buffer: &mut [u8]) -> Result<(), Error> {
+        self.transaction(address, |deadline| self.receive(address, buffer, deadline))
+    }
+
+    /// Writes `data` then reads into `buffer` after a repeated start, as
+    /// used to read a device register.
+    pub fn write_read(&self, address: u8, data: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+        self.transaction(address, |deadline| {
+            self.send(address, data, deadline)?;
+            self.start(deadline)?;
+            self.receive(address, buffer, deadline)
+        })
+    }
+}
+
+impl<'a> Drop for SoftI2c<'a> {
+    fn drop(&mut self) {
+        let _ = self.release(self.sda.number());
+        let _ = self.release(self.scl.number());
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MockGpio, MockOperation};
+
+    const SDA: u32 = 2;
+    const SCL: u32 = 3;
+
+    // The level left on SDA each time SCL is released.
+    fn sampled_bits(operations: &[MockOperation]) -> Vec<bool> {
+        let mut sda = true;
+        let mut bits = Vec::new();
+        for operation in operations {
+            match *operation {
+                MockOperation::SetFunction { pin: SDA, function } => sda = function == PinFunction::Input,
+                MockOperation::SetFunction { pin: SCL, function: PinFunction::Input } => bits.push(sda),
+                _ => {}
+            }
+        }
+        bits
+    }
+
+    fn byte_bits(byte: u8) -> Vec<bool> {
+        (0..8).rev().map(|bit| byte & (1 << bit) != 0).collect()
+    }
+
+    fn open(gpio: &GPIO) -> SoftI2c<'_> {
+        let mut i2c = SoftI2c::new(gpio.pin(SDA).unwrap(), gpio.pin(SCL).unwrap()).unwrap();
+        i2c.set_frequency(1_000_000.0).unwrap();
+        i2c
+    }
+
+    #[test]
+    fn test_soft_i2c_write() {
+        let mock = MockGpio::new();
+        let gpio = mock.gpio().unwrap();
+        // The device holds SDA low, acknowledging everything, and nothing
+        // stretches the clock.
+        mock.set_input(SDA, false);
+        mock.set_input(SCL, true);
+        let i2c = open(&gpio);
+        assert_eq!(gpio.get_pull(SDA).unwrap(), Pull::Up);
+        assert_eq!(gpio.output_state(SCL).ok(), Some(Some(false)));
+        mock.take_operations();
+
+        i2c.write(0x50, &[0xa5]).unwrap();
+        let mut expected = vec![true];
+        expected.extend(byte_bits(0xa0));
+        expected.push(true);
+        expected.extend(byte_bits(0xa5));
+        expected.extend(&[true, false]);
+        let operations = mock.operations();
+        assert_eq!(sampled_bits(&operations), expected);
+        // Stop condition: SDA rises while SCL is released.
+        assert_eq!(operations[operations.len() - 2..], [
+            MockOperation::SetFunction { pin: SCL, function: PinFunction::Input },
+            MockOperation::SetFunction { pin: SDA, function: PinFunction::Input },
+        ]);
+    }
+
+    #[test]
+    fn test_soft_i2c_read_and_nack() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        unsafe { gpio.write_raw(0x34, 1 << SCL) };
+        let i2c = open(&gpio);
+        let mut buffer = [0xff; 2];
+        i2c.write_read(0x50, &[0x10], &mut buffer).unwrap();
+        assert_eq!(buffer, [0, 0]);
+        assert!(i2c.read(0x80, &mut buffer).is_err());
+
+        // Nothing pulls SDA low, so the address goes unacknowledged.
+        unsafe { gpio.write_raw(0x34, 1 << SCL | 1 << SDA) };
+        let error = i2c.write(0x50, &[1]).unwrap_err();
+        assert!(matches!(error, Error::NoAcknowledge(_)));
+        assert_eq!(gpio.get_function(SDA).unwrap(), PinFunction::Input);
+        assert_eq!(gpio.get_function(SCL).unwrap(), PinFunction::Input);
+    }
+
+    #[test]
+    fn test_soft_i2c_clock_stretch_timeout() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let mut i2c = open(&gpio);
+        i2c.set_stretch_timeout(Duration::from_millis(1));
+        assert!(i2c.set_frequency(-1.0).is_err());
+        let error = i2c.write(0x50, &[]).unwrap_err();
+        assert!(matches!(error, Error::Timeout(_)));
+    }
+}
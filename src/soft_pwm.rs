@@ -0,0 +1,313 @@
+use crate::timing::{wait_until, CalibrationData};
+use crate::{bit_in_bank, check_pin, Error, Register, GPIO};
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+
+// The longest the thread waits before re-checking for new channels and
+// changed settings.
+const MAX_WAIT: Duration = Duration::from_millis(1);
+
+
+fn pwm_period(freq_hz: f64) -> Result<Duration, Error> {
+    if !(freq_hz.is_finite() && freq_hz > 0.0) {
+        return Err(Error::Other(format!("PWM frequency must be positive, not {}", freq_hz)));
+    }
+    Ok(Duration::from_secs_f64(1.0 / freq_hz))
+}
+
+fn check_duty(duty: f64) -> Result<(), Error> {
+    if !(0.0..=1.0).contains(&duty) {
+        return Err(Error::Other(format!("duty cycle {} is outside 0 to 1", duty)));
+    }
+    Ok(())
+}
+
+
+struct Channel {
+    pin: u32,
+    set: usize,
+    clear: usize,
+    bit: u32,
+    period: Duration,
+    duty: f64,
+    period_start: Instant,
+    high: bool,
+}
+
+impl Channel {
+
+    fn next_edge(&self) -> Instant {
+        if self.high && self.duty < 1.0 {
+            self.period_start + self.period.mul_f64(self.duty)
+        } else {
+            self.period_start + self.period
+        }
+    }
+
+    fn write(&mut self, high: bool) {
+        let register = if high { self.set } else { self.clear };
+        unsafe { (register as *mut u32).write_volatile(self.bit) };
+        self.high = high;
+    }
+
+    fn advance(&mut self, now: Instant) {
+        if self.high && self.duty < 1.0 {
+            self.write(false);
+            return;
+        }
+        // Periods are laid end to end from the first one, so sleep overruns
+        // don't accumulate. A channel that falls a whole period behind
+        // starts afresh instead of bursting to catch up.
+        self.period_start += self.period;
+        if self.period_start + self.period <= now {
+            self.period_start = now;
+        }
+        self.write(self.duty > 0.0);
+    }
+}
+
+struct Shared {
+    channels: Mutex<Vec<Channel>>,
+    running: AtomicBool,
+}
+
+
+fn run(shared: &Shared, calibration: CalibrationData) {
+    while shared.running.load(Ordering::Relaxed) {
+        let next = shared.channels.lock().unwrap().iter().map(Channel::next_edge).min();
+        let now = Instant::now();
+        match next {
+            Some(deadline) if deadline <= now + MAX_WAIT => wait_until(calibration.spin_target(deadline)),
+            _ => {
+                std::thread::sleep(MAX_WAIT);
+                continue;
+            }
+        }
+        let now = Instant::now();
+        for channel in shared.channels.lock().unwrap().iter_mut() {
+            if calibration.spin_target(channel.next_edge()) <= now {
+                channel.advance(now);
+            }
+        }
+    }
+}
+
+
+/// Software PWM on any output pins, driven from one background thread.
+/// Edges are timed against the start of the first period, so they don't
+/// drift, but scheduling jitter still shows on each edge; use `HwPwm`
+/// where the pin allows. Pins must already be outputs. Each pin is driven
+/// low when it's stopped or the driver is dropped.
+pub struct SoftPwm<'a> {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+    gpio: &'a GPIO,
+}
+
+impl<'a> SoftPwm<'a> {
+
+    pub fn new(gpio: &'a GPIO) -> Result<Self, Error> {
+        gpio.check_writable()?;
+        let shared = Arc::new(Shared { channels: Mutex::new(Vec::new()), running: AtomicBool::new(true) });
+        let calibration = gpio.calibration_or_none();
+        let thread = {
+            let shared = Arc::clone(&shared);
+            std::thread::Builder::new()
+                .name("rustberrypi-soft-pwm".to_string())
+                .spawn(move || run(&shared, calibration))
+                .map_err(|e| Error::from_io("failed to start the software PWM thread", e))?
+        };
+        Ok(Self { shared, thread: Some(thread), gpio })
+    }
+
+    /// Starts a PWM signal on `pin`. Fails with `PinInUse` if the pin is
+    /// already running.
+    pub fn start(&self, pin: u32, freq_hz: f64, duty: f64) -> Result<(), Error> {
+        check_pin(pin)?;
+        let period = pwm_period(freq_hz)?;
+        check_duty(duty)?;
+        // The thread only runs while the driver, and so its borrow of the
+        // GPIO, is alive.
+        let set = self.gpio.register_ptr(Register::GPSET, pin)? as usize;
+        let clear = self.gpio.register_ptr(Register::GPCLR, pin)? as usize;
+
+        let mut channels = self.shared.channels.lock().unwrap();
+        if channels.iter().any(|channel| channel.pin == pin) {
+            return Err(Error::PinInUse(pin));
+        }
+        let mut channel = Channel {
+            pin, set, clear, bit: 1 << bit_in_bank(pin), period, duty, period_start: Instant::now(), high: false,
+        };
+        channel.write(duty > 0.0);
+        channels.push(channel);
+        Ok(())
+    }
+
+    /// Stops the signal on `pin` and drives it low. Returns whether it was
+    /// running.
+    pub fn stop(&self, pin: u32) -> bool {
+        let mut channels = self.shared.channels.lock().unwrap();
+        match channels.iter().position(|channel| channel.pin == pin) {
+            Some(index) => {
+                channels.remove(index).write(false);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn update(&self, pin: u32, change: impl FnOnce(&mut Channel)) -> Result<(), Error> {
+        let mut channels = self.shared.channels.lock().unwrap();
+        match channels.iter_mut().find(|channel| channel.pin == pin) {
+            Some(channel) => {
+                change(channel);
+                Ok(())
+            }
+            None => Err(Error::Other(format!("software PWM isn't running on pin {}", pin))),
+        }
+    }
+
+    /// Takes effect from the next edge.
+    pub fn set_duty_cycle(&self, pin: u32, duty: f64) -> Result<(), Error> {
+        check_duty(duty)?;
+        self.update(pin, |channel| channel.duty = duty)
+    }
+
+    /// Takes effect from the next edge.
+    pub fn set_frequency(&self, pin: u32, freq_hz: f64) -> Result<(), Error> {
+        let period = pwm_period(freq_hz)?;
+        self.update(pin, |channel| channel.period = period)
+    }
+
+    /// The duty cycle `pin` is running at, if it's running.
+    pub fn duty_cycle(&self, pin: u32) -> Option<f64> {
+        self.shared.channels.lock().unwrap().iter().find(|channel| channel.pin == pin).map(|channel| channel.duty)
+    }
+
+    /// A handle to the signal on `pin`, for code that works with a single
+    /// PWM output. Fails if the pin isn't running.
+    pub fn channel(&self, pin: u32) -> Result<SoftPwmChannel<'_, 'a>, Error> {
+        self.update(pin, |_| ())?;
+        Ok(SoftPwmChannel { pwm: self, pin })
+    }
+
+    /// The pins currently running, in the order they were started.
+    pub fn pins(&self) -> Vec<u32> {
+        self.shared.channels.lock().unwrap().iter().map(|channel| channel.pin).collect()
+    }
+}
+
+/// One pin of a `SoftPwm`.
+pub struct SoftPwmChannel<'p, 'a> {
+    pwm: &'p SoftPwm<'a>,
+    pin: u32,
+}
+
+impl<'p, 'a> SoftPwmChannel<'p, 'a> {
+
+    pub fn pin(&self) -> u32 {
+        self.pin
+    }
+
+    pub fn set_duty_cycle(&self, duty: f64) -> Result<(), Error> {
+        self.pwm.set_duty_cycle(self.pin, duty)
+    }
+
+    /// Fails if the pin has since been stopped.
+    pub fn duty_cycle(&self) -> Result<f64, Error> {
+        self.pwm.duty_cycle(self.pin)
+            .ok_or_else(|| Error::Other(format!("software PWM isn't running on pin {}", self.pin)))
+    }
+
+    pub fn set_frequency(&self, freq_hz: f64) -> Result<(), Error> {
+        self.pwm.set_frequency(self.pin, freq_hz)
+    }
+}
+
+impl<'a> Drop for SoftPwm<'a> {
+    fn drop(&mut self) {
+        self.shared.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        for mut channel in self.shared.channels.lock().unwrap().drain(..) {
+            channel.write(false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_word(gpio: &GPIO, offset: usize) -> u32 {
+        unsafe { gpio.read_raw(offset) }
+    }
+
+    #[test]
+    fn test_soft_pwm_start_stop() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let pwm = SoftPwm::new(&gpio).unwrap();
+        pwm.start(21, 1000.0, 0.5).unwrap();
+        assert_eq!(read_word(&gpio, 0x1c), 1 << 21);
+        assert!(matches!(pwm.start(21, 500.0, 0.1), Err(Error::PinInUse(21))));
+        pwm.start(40, 200.0, 0.0).unwrap();
+        assert_eq!(read_word(&gpio, 0x2c), 1 << 8);
+        assert_eq!(pwm.pins(), vec![21, 40]);
+
+        std::thread::sleep(Duration::from_millis(5));
+        // The thread has since cleared pin 21 at least once.
+        assert_eq!(read_word(&gpio, 0x28), 1 << 21);
+
+        pwm.set_duty_cycle(21, 0.25).unwrap();
+        assert_eq!(pwm.duty_cycle(21), Some(0.25));
+        assert_eq!(pwm.duty_cycle(3), None);
+        pwm.set_frequency(40, 100.0).unwrap();
+        assert!(pwm.set_duty_cycle(21, 2.0).is_err());
+        assert!(pwm.set_frequency(3, 100.0).is_err());
+        assert!(pwm.start(5, 0.0, 0.5).is_err());
+
+        let channel = pwm.channel(40).unwrap();
+        channel.set_duty_cycle(0.75).unwrap();
+        assert_eq!(channel.duty_cycle().ok(), Some(0.75));
+        assert!(pwm.channel(3).is_err());
+
+        assert!(pwm.stop(21));
+        assert!(!pwm.stop(21));
+        assert_eq!(pwm.pins(), vec![40]);
+    }
+
+    #[test]
+    fn test_soft_pwm_drop_drives_pins_low() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let pwm = SoftPwm::new(&gpio).unwrap();
+        pwm.start(4, 50.0, 1.0).unwrap();
+        unsafe { gpio.write_raw(0x28, 0) };
+        drop(pwm);
+        assert_eq!(read_word(&gpio, 0x28), 1 << 4);
+    }
+
+    #[test]
+    fn test_channel_resyncs_after_falling_behind() {
+        let start = Instant::now();
+        let mut word = 0u32;
+        let address = &mut word as *mut u32 as usize;
+        let mut channel = Channel {
+            pin: 0, set: address, clear: address, bit: 1, period: Duration::from_millis(10), duty: 0.5,
+            period_start: start, high: true,
+        };
+        assert_eq!(channel.next_edge(), start + Duration::from_millis(5));
+        channel.advance(start + Duration::from_millis(5));
+        assert!(!channel.high);
+        channel.advance(start + Duration::from_millis(10));
+        assert_eq!(channel.period_start, start + Duration::from_millis(10));
+        channel.advance(start + Duration::from_millis(15));
+        let late = start + Duration::from_millis(50);
+        channel.advance(late);
+        assert_eq!(channel.period_start, late);
+    }
+}
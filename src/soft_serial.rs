@@ -0,0 +1,157 @@
+use crate::timing::wait_until;
+use crate::{Error, Input, Output, Pin};
+
+use std::time::{Duration, Instant};
+
+
+/// A UART bit-banged on ordinary pins, 8N1, for when both hardware UARTs
+/// are taken. Every bit is timed by busy-waiting, so it's only reliable at
+/// low baud rates and a busy system can still corrupt the odd character.
+/// Either direction is optional.
+pub struct SoftSerial<'a> {
+    tx: Option<Pin<'a, Output>>,
+    rx: Option<Pin<'a, Input>>,
+    bit_time: Duration,
+    read_timeout: Option<Duration>,
+}
+
+impl<'a> SoftSerial<'a> {
+
+    pub const MAX_BAUD_RATE: u32 = 38_400;
+
+    /// A port at `baud` with the TX line idling high.
+    pub fn new(tx: Option<Pin<'a, Output>>, rx: Option<Pin<'a, Input>>, baud: u32) -> Result<Self, Error> {
+        let mut serial = Self { tx, rx, bit_time: Duration::from_secs(1), read_timeout: None };
+        serial.set_baud_rate(baud)?;
+        if let Some(tx) = &serial.tx {
+            tx.set_high()?;
+        }
+        Ok(serial)
+    }
+
+    pub fn set_baud_rate(&mut self, baud: u32) -> Result<(), Error> {
+        if baud == 0 || baud > Self::MAX_BAUD_RATE {
+            return Err(Error::Other(format!(
+                "software serial runs at 1 to {} baud, not {}", Self::MAX_BAUD_RATE, baud)));
+        }
+        self.bit_time = Duration::from_secs_f64(1.0 / baud as f64);
+        Ok(())
+    }
+
+    pub fn baud_rate(&self) -> u32 {
+        (1.0 / self.bit_time.as_secs_f64()).round() as u32
+    }
+
+    /// How long `read` waits for each character to start; `None` waits
+    /// forever.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout
+    }
+
+    fn write_byte(&self, tx: &Pin<'a, Output>, byte: u8) -> Result<(), Error> {
+        let mut deadline = Instant::now();
+        // Start bit, eight data bits LSB first, stop bit.
+        let frame = (byte as u32) << 1 | 1 << 9;
+        for bit in 0..10 {
+            if frame & (1 << bit) != 0 { tx.set_high()? } else { tx.set_low()? }
+            deadline += self.bit_time;
+            wait_until(deadline);
+        }
+        Ok(())
+    }
+
+    pub fn write(&self, data: &[u8]) -> Result<(), Error> {
+        let tx = self.tx.as_ref().ok_or_else(|| Error::Unsupported("this SoftSerial has no TX pin".to_string()))?;
+        for &byte in data {
+            self.write_byte(tx, byte)?;
+        }
+        Ok(())
+    }
+
+    // Spins until the line drops for a start bit and returns when it did.
+    fn wait_for_start_bit(&self, rx: &Pin<'a, Input>) -> Result<Instant, Error> {
+        let started = Instant::now();
+        loop {
+            if !rx.read()? {
+                return Ok(Instant::now());
+            }
+            if let Some(timeout) = self.read_timeout {
+                if started.elapsed() > timeout {
+                    return Err(Error::Timeout("no character arrived on the software serial port".to_string()));
+                }
+            }
+            std::hint::spin_loop();
+        }
+    }
+
+    // Samples each bit in the middle of its slot, counted from the start
+    // bit's falling edge.
+    fn read_byte(&self, rx: &Pin<'a, Input>) -> Result<u8, Error> {
+        let edge = self.wait_for_start_bit(rx)?;
+        let mut deadline = edge + self.bit_time / 2;
+        let mut byte = 0u8;
+        for bit in 0..8 {
+            deadline += self.bit_time;
+            wait_until(deadline);
+            byte |= (rx.read()? as u8) << bit;
+        }
+        deadline += self.bit_time;
+        wait_until(deadline);
+        if !rx.read()? {
+            return Err(Error::Other(format!("framing error: no stop bit after {:#04x}", byte)));
+        }
+        Ok(byte)
+    }
+
+    /// Fills `buffer`, waiting for each character. Fails with
+    /// `Error::Timeout` if a character takes longer than the read timeout
+    /// to start, or on a character without a stop bit.
+    pub fn read(&self, buffer: &mut [u8]) -> Result<(), Error> {
+        let rx = self.rx.as_ref().ok_or_else(|| Error::Unsupported("this SoftSerial has no RX pin".to_string()))?;
+        for byte in buffer.iter_mut() {
+            *byte = self.read_byte(rx)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MockGpio, GPIO};
+
+    #[test]
+    fn test_soft_serial_write() {
+        let mock = MockGpio::new();
+        let gpio = mock.gpio().unwrap();
+        let serial = SoftSerial::new(Some(gpio.pin(4).unwrap().into_output().unwrap()), None, 38_400).unwrap();
+        assert_eq!(mock.writes(4), vec![true]);
+        mock.take_operations();
+
+        let started = Instant::now();
+        serial.write(&[0x31]).unwrap();
+        assert!(started.elapsed() >= Duration::from_micros(260));
+        assert_eq!(mock.writes(4), vec![false, true, false, false, false, true, true, false, false, true]);
+        assert!(matches!(serial.read(&mut [0]), Err(Error::Unsupported(_))));
+    }
+
+    #[test]
+    fn test_soft_serial_read() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let mut serial = SoftSerial::new(None, Some(gpio.pin(5).unwrap().into_input().unwrap()), 9_600).unwrap();
+        assert_eq!(serial.baud_rate(), 9_600);
+        assert!(serial.set_baud_rate(115_200).is_err());
+        serial.set_baud_rate(38_400).unwrap();
+        // A line held low reads as a break: zeros with no stop bit.
+        assert!(matches!(serial.read(&mut [0]), Err(Error::Other(_))));
+
+        unsafe { gpio.write_raw(0x34, 1 << 5) };
+        serial.set_read_timeout(Some(Duration::from_millis(1)));
+        assert!(matches!(serial.read(&mut [0]), Err(Error::Timeout(_))));
+    }
+}
@@ -0,0 +1,262 @@
+use crate::timing::wait_until;
+use crate::{Error, Input, Output, Pin};
+
+use std::time::{Duration, Instant};
+
+
+/// Clock polarity and phase, numbered as usual: CPOL is the high bit and
+/// CPHA the low one.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum SpiMode {
+    /// Clock idles low; data sampled on the rising edge.
+    Mode0,
+    /// Clock idles low; data sampled on the falling edge.
+    Mode1,
+    /// Clock idles high; data sampled on the falling edge.
+    Mode2,
+    /// Clock idles high; data sampled on the rising edge.
+    Mode3,
+}
+
+impl SpiMode {
+
+    fn idles_high(self) -> bool {
+        matches!(self, SpiMode::Mode2 | SpiMode::Mode3)
+    }
+
+    // Whether data is sampled on the second clock edge of each bit.
+    fn samples_trailing(self) -> bool {
+        matches!(self, SpiMode::Mode1 | SpiMode::Mode3)
+    }
+}
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum BitOrder {
+    MsbFirst,
+    LsbFirst,
+}
+
+
+/// An SPI master bit-banged on ordinary pins, for when the hardware SPI
+/// pins are taken. MOSI, MISO and chip select are optional. Chip select is
+/// active low and held for the length of each call. The clock rate is an
+/// upper bound; each edge also costs a register write.
+pub struct SoftSpi<'a> {
+    sclk: Pin<'a, Output>,
+    mosi: Option<Pin<'a, Output>>,
+    miso: Option<Pin<'a, Input>>,
+    cs: Option<Pin<'a, Output>>,
+    mode: SpiMode,
+    bit_order: BitOrder,
+    half_period: Duration,
+}
+
+impl<'a> SoftSpi<'a> {
+
+    pub const DEFAULT_FREQUENCY_HZ: f64 = 100_000.0;
+
+    /// A master in mode 0, MSB first, at `DEFAULT_FREQUENCY_HZ`. The clock
+    /// is put at its idle level and chip select released.
+    pub fn new(
+        sclk: Pin<'a, Output>,
+        mosi: Option<Pin<'a, Output>>,
+        miso: Option<Pin<'a, Input>>,
+        cs: Option<Pin<'a, Output>>,
+    ) -> Result<Self, Error> {
+        let mut spi = Self {
+            sclk, mosi, miso, cs,
+            mode: SpiMode::Mode0,
+            bit_order: BitOrder::MsbFirst,
+            half_period: Duration::from_secs_f64(0.5 / Self::DEFAULT_FREQUENCY_HZ),
+        };
+        spi.set_mode(SpiMode::Mode0)?;
+        if let Some(cs) = &spi.cs {
+            cs.set_high()?;
+        }
+        Ok(spi)
+    }
+
+    pub fn set_mode(&mut self, mode: SpiMode) -> Result<(), Error> {
+        self.mode = mode;
+        self.set_clock(false)
+    }
+
+    pub fn mode(&self) -> SpiMode {
+        self.mode
+    }
+
+    pub fn set_bit_order(&mut self, bit_order: BitOrder) {
+        self.bit_order = bit_order;
+    }
+
+    pub fn bit_order(&self) -> BitOrder {
+        self.bit_order
+    }
+
+    pub fn set_frequency(&mut self, freq_hz: f64) -> Result<(), Error> {
+        if !(freq_hz.is_finite() && freq_hz > 0.0) {
+            return Err(Error::Other(format!("SPI clock frequency must be positive, not {}", freq_hz)));
+        }
+        self.half_period = Duration::from_secs_f64(0.5 / freq_hz);
+        Ok(())
+    }
+
+    pub fn frequency(&self) -> f64 {
+        0.5 / self.half_period.as_secs_f64()
+    }
+
+    // Drives the clock to its active level if `active`, else to idle.
+    fn set_clock(&self, active: bool) -> Result<(), Error> {
+        if active != self.mode.idles_high() { self.sclk.set_high() } else { self.sclk.set_low() }
+    }
+
+    fn write_bit(&self, bit: bool) -> Result<(), Error> {
+        match &self.mosi {
+            Some(mosi) if bit => mosi.set_high(),
+            Some(mosi) => mosi.set_low(),
+            None => Ok(()),
+        }
+    }
+
+    fn read_bit(&self) -> Result<bool, Error> {
+        match &self.miso {
+            Some(miso) => miso.read(),
+            None => Ok(false),
+        }
+    }
+
+    // Deadlines run on from the start of the transfer, so the time spent
+    // writing pins doesn't stretch every bit.
+    fn wait_half_period(&self, deadline: &mut Instant) {
+        *deadline += self.half_period;
+        wait_until(*deadline);
+    }
+
+    fn transfer_byte(&self, out: u8, deadline: &mut Instant) -> Result<u8, Error> {
+        let mut input = 0u8;
+        for index in 0..8 {
+            let bit = match self.bit_order {
+                BitOrder::MsbFirst => 7 - index,
+                BitOrder::LsbFirst => index,
+            };
+            let out_bit = out & (1 << bit) != 0;
+            let sampled = if self.mode.samples_trailing() {
+                self.set_clock(true)?;
+                self.write_bit(out_bit)?;
+                self.wait_half_period(deadline);
+                self.set_clock(false)?;
+                let sampled = self.read_bit()?;
+                self.wait_half_period(deadline);
+                sampled
+            } else {
+                self.write_bit(out_bit)?;
+                self.wait_half_period(deadline);
+                self.set_clock(true)?;
+                let sampled = self.read_bit()?;
+                self.wait_half_period(deadline);
+                self.set_clock(false)?;
+                sampled
+            };
+            if sampled {
+                input |= 1 << bit;
+            }
+        }
+        Ok(input)
+    }
+
+    // Runs `transfer` with chip select asserted, releasing it even if the
+    // transfer fails.
+    fn selected<T>(&self, transfer: impl FnOnce(&Self) -> Result<T, Error>) -> Result<T, Error> {
+        if let Some(cs) = &self.cs {
+            cs.set_low()?;
+        }
+        let result = transfer(self);
+        let released = match &self.cs {
+            Some(cs) => cs.set_high(),
+            None => Ok(()),
+        };
+        let value = result?;
+        released?;
+        Ok(value)
+    }
+
+    /// Sends `buffer` and replaces it with the bytes clocked in at the same
+    /// time.
+    pub fn transfer(&self, buffer: &mut [u8]) -> Result<(), Error> {
+        self.selected(|spi| {
+            let mut deadline = Instant::now();
+            for byte in buffer.iter_mut() {
+                *byte = spi.transfer_byte(*byte, &mut deadline)?;
+            }
+            Ok(())
+        })
+    }
+
+    pub fn write(&self, data: &[u8]) -> Result<(), Error> {
+        self.selected(|spi| {
+            let mut deadline = Instant::now();
+            for &byte in data {
+                spi.transfer_byte(byte, &mut deadline)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Fills `buffer` from the device, sending zeros.
+    pub fn read(&self, buffer: &mut [u8]) -> Result<(), Error> {
+        buffer.iter_mut().for_each(|byte| *byte = 0);
+        self.transfer(buffer)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MockGpio, MockOperation, GPIO};
+
+    #[test]
+    fn test_soft_spi_write_mode0() {
+        let mock = MockGpio::new();
+        let gpio = mock.gpio().unwrap();
+        let spi = SoftSpi::new(
+            gpio.pin(11).unwrap().into_output().unwrap(),
+            Some(gpio.pin(10).unwrap().into_output().unwrap()),
+            None,
+            Some(gpio.pin(8).unwrap().into_output().unwrap()),
+        ).unwrap();
+        mock.take_operations();
+
+        spi.write(&[0b1010_0001]).unwrap();
+        assert_eq!(mock.writes(10), vec![true, false, true, false, false, false, false, true]);
+        assert_eq!(mock.writes(11), [true, false].repeat(8));
+        assert_eq!(mock.writes(8), vec![false, true]);
+        // Chip select goes low before the first clock edge.
+        assert_eq!(mock.operations()[0], MockOperation::Write { pin: 8, level: false });
+    }
+
+    #[test]
+    fn test_soft_spi_read_and_modes() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let mut spi = SoftSpi::new(
+            gpio.pin(11).unwrap().into_output().unwrap(),
+            None,
+            Some(gpio.pin(9).unwrap().into_input().unwrap()),
+            None,
+        ).unwrap();
+        spi.set_frequency(1_000_000.0).unwrap();
+        assert!(spi.set_frequency(0.0).is_err());
+
+        let mut buffer = [0xaa; 2];
+        spi.read(&mut buffer).unwrap();
+        assert_eq!(buffer, [0, 0]);
+        unsafe { gpio.write_raw(0x34, 1 << 9) };
+        spi.set_mode(SpiMode::Mode3).unwrap();
+        assert_eq!(gpio.output_state(11).ok(), Some(Some(true)));
+        spi.set_bit_order(BitOrder::LsbFirst);
+        spi.transfer(&mut buffer).unwrap();
+        assert_eq!(buffer, [0xff, 0xff]);
+        assert_eq!(gpio.output_state(11).ok(), Some(Some(true)));
+    }
+}
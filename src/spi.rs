@@ -0,0 +1,450 @@
+use crate::aux_spi::AuxSpi;
+use crate::dma::{
+    ControlBlock, DmaChannel, DmaMemory, CONTROL_BLOCK_SIZE, DREQ_SPI0_RX, DREQ_SPI0_TX, MAX_TRANSFER_LEN,
+    PERIPHERAL_BUS_BASE, TI_DEST_DREQ, TI_DEST_INC, TI_PERMAP_SHIFT, TI_SRC_DREQ, TI_SRC_INC, TI_WAIT_RESP,
+};
+use crate::region::MappedRegion;
+use crate::spidev::Spidev;
+use crate::{detect_peripheral_base, Error, PinFunction, Soc, SpiMode, GPIO, DEV_MEM_PATH};
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+
+const SPI0_BASE_OFFSET: i64 = 0x204000;
+const SPI0_BLOCK_LEN: usize = 0x18;
+
+const SPI_CS: usize = 0x00;
+const SPI_FIFO: usize = 0x04;
+const SPI_CLK: usize = 0x08;
+const SPI_DLEN: usize = 0x0c;
+
+const CS_CHIP_SELECT_MASK: u32 = 0b11;
+const CS_CPHA: u32 = 1 << 2;
+const CS_CPOL: u32 = 1 << 3;
+const CS_CLEAR_FIFOS: u32 = 0b11 << 4;
+const CS_TA: u32 = 1 << 7;
+const CS_DMAEN: u32 = 1 << 8;
+const CS_ADCS: u32 = 1 << 11;
+const CS_DONE: u32 = 1 << 16;
+const CS_RXD: u32 = 1 << 17;
+const CS_TXD: u32 = 1 << 18;
+// Per-line polarity bits; CSPOL0 is bit 21.
+const CS_CSPOL_SHIFT: u32 = 21;
+
+// SCLK, MOSI and MISO; the chip-select pins follow.
+const SPI0_DATA_PINS: [u32; 3] = [9, 10, 11];
+const SPI0_CHIP_SELECT_PINS: [u32; 2] = [8, 7];
+
+// How long a transfer may go without progress before giving up.
+const TRANSFER_TIMEOUT: Duration = Duration::from_millis(100);
+
+// DMA memory: the transmit and receive control blocks, then the transmit
+// words, then room for as many received words.
+const DMA_DATA_OFFSET: u32 = 2 * CONTROL_BLOCK_SIZE;
+
+
+/// A way of driving an SPI bus. `Spi` works the same over any of them, so
+/// code can move between the register-level controller and the kernel
+/// driver without changes.
+pub trait SpiBackend: Send {
+    /// Sends `buffer` and replaces it with the bytes clocked in at the same
+    /// time, with chip select asserted throughout.
+    fn transfer(&mut self, buffer: &mut [u8]) -> Result<(), Error>;
+    fn set_mode(&mut self, mode: SpiMode) -> Result<(), Error>;
+    /// Sets the clock to at most `freq_hz` and returns the rate achieved.
+    fn set_frequency(&mut self, freq_hz: u32) -> Result<u32, Error>;
+    fn set_cs_active_high(&mut self, active_high: bool) -> Result<(), Error>;
+
+    /// Sends `tx` and returns the bytes clocked in, by DMA where the
+    /// backend can. `Spi::transfer_dma` only calls it for buffers of at
+    /// least `Spi::DMA_THRESHOLD` bytes. The default goes through
+    /// `transfer`.
+    fn transfer_dma(&mut self, tx: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut buffer = tx.to_vec();
+        self.transfer(&mut buffer)?;
+        Ok(buffer)
+    }
+}
+
+
+/// An SPI master on one chip select.
+pub struct Spi {
+    backend: Box<dyn SpiBackend>,
+}
+
+impl Spi {
+
+    /// Buffers shorter than this are always polled by `transfer_dma`, as
+    /// setting up the DMA costs more than it saves.
+    pub const DMA_THRESHOLD: usize = 96;
+
+    /// The DMA channels `new` uses for SPI0's transmit and receive FIFOs,
+    /// clear of the one `WaveEngine` and `Ws2812` default to.
+    pub const DEFAULT_DMA_CHANNELS: (u32, u32) = (11, 12);
+
+    /// Drives SPI0 through its registers on chip select 0 or 1 (GPIO 8 or
+    /// 7), switching the bus pins to SPI0. The kernel's spi driver must not
+    /// be using the controller at the same time.
+    pub fn new(gpio: &GPIO, chip_select: u8) -> Result<Self, Error> {
+        Self::with_dma_channels(gpio, chip_select, Self::DEFAULT_DMA_CHANNELS)
+    }
+
+    /// Like `new`, with `transfer_dma` moving data over the `(transmit,
+    /// receive)` DMA channels. Nothing arbitrates DMA channels between
+    /// drivers: pick two the firmware and kernel leave alone (those in the
+    /// device tree's `brcm,dma-channel-mask` are taken) and that no
+    /// `WaveEngine` or `Ws2812` is using. They're claimed on the first DMA
+    /// transfer and reset when the `Spi` is dropped.
+    pub fn with_dma_channels(gpio: &GPIO, chip_select: u8, dma_channels: (u32, u32)) -> Result<Self, Error> {
+        let base = detect_peripheral_base()?;
+        let mut backend = RegisterSpi::open(DEV_MEM_PATH, base + SPI0_BASE_OFFSET, chip_select, gpio.soc())?;
+        backend.dma_channels = dma_channels;
+        for &pin in SPI0_DATA_PINS.iter().chain(&[SPI0_CHIP_SELECT_PINS[chip_select as usize]]) {
+            gpio.set_function(pin, PinFunction::Alt0)?;
+        }
+        Ok(Self::custom(backend))
+    }
+
+    /// Drives auxiliary SPI `bus`, 1 or 2, through its registers on
+    /// `chip_select`, 0 to 2; see `AuxSpi`.
+    pub fn aux(gpio: &GPIO, bus: u8, chip_select: u8) -> Result<Self, Error> {
+        Ok(Self::custom(AuxSpi::new(gpio, bus, chip_select)?))
+    }
+
+    /// Goes through the kernel's spidev driver for `bus` and `chip_select`,
+    /// i.e. `/dev/spidevB.C`, which must be enabled in the device tree.
+    pub fn spidev(bus: u8, chip_select: u8) -> Result<Self, Error> {
+        Self::spidev_at(format!("/dev/spidev{}.{}", bus, chip_select))
+    }
+
+    pub fn spidev_at(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Ok(Self::custom(Spidev::open(path)?))
+    }
+
+    /// Uses `backend` instead of any of the built-in ones.
+    pub fn custom(backend: impl SpiBackend + 'static) -> Self {
+        Self { backend: Box::new(backend) }
+    }
+
+    pub fn transfer(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        self.backend.transfer(buffer)
+    }
+
+    /// Sends `tx` and returns the bytes clocked in. On SPI0's registers,
+    /// buffers of `DMA_THRESHOLD` bytes or more are fed to and drained from
+    /// the FIFO by DMA, which needs `/dev/vcio` as well; shorter ones, and
+    /// every buffer on the other backends, are polled like `transfer`.
+    pub fn transfer_dma(&mut self, tx: &[u8]) -> Result<Vec<u8>, Error> {
+        if !uses_dma(tx.len()) {
+            let mut buffer = tx.to_vec();
+            self.backend.transfer(&mut buffer)?;
+            return Ok(buffer);
+        }
+        self.backend.transfer_dma(tx)
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.backend.transfer(&mut data.to_vec())
+    }
+
+    /// Fills `buffer` from the device, sending zeros.
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        buffer.iter_mut().for_each(|byte| *byte = 0);
+        self.backend.transfer(buffer)
+    }
+
+    pub fn set_mode(&mut self, mode: SpiMode) -> Result<(), Error> {
+        self.backend.set_mode(mode)
+    }
+
+    pub fn set_frequency(&mut self, freq_hz: u32) -> Result<u32, Error> {
+        self.backend.set_frequency(freq_hz)
+    }
+
+    pub fn set_cs_active_high(&mut self, active_high: bool) -> Result<(), Error> {
+        self.backend.set_cs_active_high(active_high)
+    }
+}
+
+
+// The SPI clock is the core clock divided by an even number.
+fn clock_divider(core_clock_hz: u32, freq_hz: u32) -> Result<u32, Error> {
+    if freq_hz == 0 {
+        return Err(Error::Other("SPI clock frequency must be positive".to_string()));
+    }
+    let divider = core_clock_hz.div_ceil(freq_hz).max(2);
+    let divider = divider + divider % 2;
+    if divider > 65536 {
+        return Err(Error::Other(format!("{} Hz is below the slowest SPI clock", freq_hz)));
+    }
+    Ok(divider)
+}
+
+
+fn uses_dma(length: usize) -> bool {
+    length >= Spi::DMA_THRESHOLD
+}
+
+
+// The control blocks moving `length` bytes through the FIFO with the DMA
+// memory at `bus_base`: the first feeds it as SPI0 asks for data, the
+// second drains it as data arrives. Both move whole words.
+fn dma_blocks(bus_base: u32, length: u32) -> [ControlBlock; 2] {
+    let fifo = PERIPHERAL_BUS_BASE + SPI0_BASE_OFFSET as u32 + SPI_FIFO as u32;
+    let word_bytes = 4 * length.div_ceil(4);
+    let tx_data = bus_base + DMA_DATA_OFFSET;
+    [
+        ControlBlock {
+            transfer_info: TI_WAIT_RESP | TI_SRC_INC | TI_DEST_DREQ | DREQ_SPI0_TX << TI_PERMAP_SHIFT,
+            source: tx_data,
+            destination: fifo,
+            length: word_bytes,
+            ..ControlBlock::default()
+        },
+        ControlBlock {
+            transfer_info: TI_WAIT_RESP | TI_DEST_INC | TI_SRC_DREQ | DREQ_SPI0_RX << TI_PERMAP_SHIFT,
+            source: fifo,
+            destination: tx_data + word_bytes,
+            length: word_bytes,
+            ..ControlBlock::default()
+        },
+    ]
+}
+
+
+struct RegisterSpi {
+    region: MappedRegion,
+    chip_select: u8,
+    core_clock_hz: u32,
+    peripheral_base: i64,
+    soc: Soc,
+    // Transmit and receive, opened on the first DMA transfer.
+    dma_channels: (u32, u32),
+    dma: Option<(DmaChannel, DmaChannel)>,
+}
+
+impl RegisterSpi {
+
+    fn open(path: impl AsRef<Path>, phys_base: i64, chip_select: u8, soc: Soc) -> Result<Self, Error> {
+        if chip_select as usize >= SPI0_CHIP_SELECT_PINS.len() {
+            return Err(Error::Other(format!("SPI0 has no chip select {}", chip_select)));
+        }
+        let core_clock_hz = if soc == Soc::Bcm2711 { 500_000_000 } else { 250_000_000 };
+        let spi = Self {
+            region: MappedRegion::open(path.as_ref(), phys_base, SPI0_BLOCK_LEN)?,
+            chip_select,
+            core_clock_hz,
+            peripheral_base: phys_base - SPI0_BASE_OFFSET,
+            soc,
+            dma_channels: Spi::DEFAULT_DMA_CHANNELS,
+            dma: None,
+        };
+        spi.update_cs(CS_CHIP_SELECT_MASK, chip_select as u32)?;
+        Ok(spi)
+    }
+
+    fn update_cs(&self, clear: u32, set: u32) -> Result<(), Error> {
+        let cs = self.region.read_reg(SPI_CS)?;
+        self.region.write_reg(SPI_CS, (cs & !clear) | set)
+    }
+
+    // Allows for clocking `length` bytes at the current rate.
+    fn dma_timeout(&self, length: u32) -> Result<Duration, Error> {
+        let divider = match self.region.read_reg(SPI_CLK)? & 0xffff {
+            0 => 65536,
+            divider => divider as u64,
+        };
+        let nanos = 8 * length as u64 * divider * 1_000_000_000 / self.core_clock_hz as u64;
+        Ok(TRANSFER_TIMEOUT + Duration::from_nanos(nanos))
+    }
+
+    // Runs `tx` through the FIFO in chunks of at most one control block,
+    // with chip select held throughout.
+    fn dma_transfer(&self, tx_dma: &DmaChannel, rx_dma: &DmaChannel, tx: &[u8]) -> Result<Vec<u8>, Error> {
+        let largest = tx.len().min(MAX_TRANSFER_LEN as usize) as u32;
+        let memory = DmaMemory::allocate(DMA_DATA_OFFSET + 2 * 4 * largest.div_ceil(4), self.soc)?;
+        let mut rx = Vec::with_capacity(tx.len());
+        self.update_cs(0, CS_CLEAR_FIFOS | CS_DMAEN | CS_ADCS)?;
+        let result = tx.chunks(MAX_TRANSFER_LEN as usize).try_for_each(|chunk| {
+            let length = chunk.len() as u32;
+            let [feed, drain] = dma_blocks(memory.bus_address(), length);
+            let words: Vec<u32> = chunk.chunks(4).map(|bytes| {
+                let mut word = [0; 4];
+                word[..bytes.len()].copy_from_slice(bytes);
+                u32::from_le_bytes(word)
+            }).collect();
+            memory.write_words(0, &feed.words())?;
+            memory.write_words(CONTROL_BLOCK_SIZE as usize, &drain.words())?;
+            memory.write_words(DMA_DATA_OFFSET as usize, &words)?;
+            self.region.write_reg(SPI_DLEN, length)?;
+            self.update_cs(0, CS_TA)?;
+            rx_dma.start(memory.bus_address() + CONTROL_BLOCK_SIZE)?;
+            tx_dma.start(memory.bus_address())?;
+            rx_dma.wait(Some(self.dma_timeout(length)?))?;
+            let received = memory.read_words(DMA_DATA_OFFSET as usize + 4 * words.len(), words.len())?;
+            rx.extend(received.iter().flat_map(|word| word.to_le_bytes()).take(chunk.len()));
+            Ok(())
+        });
+        if result.is_err() {
+            let _ = tx_dma.reset();
+            let _ = rx_dma.reset();
+        }
+        self.update_cs(CS_TA | CS_DMAEN | CS_ADCS, 0)?;
+        result.map(|()| rx)
+    }
+}
+
+impl SpiBackend for RegisterSpi {
+    fn transfer(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        self.update_cs(0, CS_CLEAR_FIFOS | CS_TA)?;
+        let (mut sent, mut received) = (0, 0);
+        let mut progress = Instant::now();
+        let result = loop {
+            let cs = self.region.read_reg(SPI_CS)?;
+            if sent == buffer.len() && received == buffer.len() {
+                if cs & CS_DONE != 0 {
+                    break Ok(());
+                }
+            } else if cs & CS_TXD != 0 && sent < buffer.len() {
+                self.region.write_reg(SPI_FIFO, buffer[sent] as u32)?;
+                sent += 1;
+                progress = Instant::now();
+                continue;
+            } else if cs & CS_RXD != 0 && received < sent {
+                buffer[received] = self.region.read_reg(SPI_FIFO)? as u8;
+                received += 1;
+                progress = Instant::now();
+                continue;
+            }
+            if progress.elapsed() > TRANSFER_TIMEOUT {
+                break Err(Error::Timeout(format!("SPI transfer stalled after {} of {} bytes", received, buffer.len())));
+            }
+            std::hint::spin_loop();
+        };
+        self.update_cs(CS_TA, 0)?;
+        result
+    }
+
+    fn set_mode(&mut self, mode: SpiMode) -> Result<(), Error> {
+        let bits = match mode {
+            SpiMode::Mode0 => 0,
+            SpiMode::Mode1 => CS_CPHA,
+            SpiMode::Mode2 => CS_CPOL,
+            SpiMode::Mode3 => CS_CPOL | CS_CPHA,
+        };
+        self.update_cs(CS_CPOL | CS_CPHA, bits)
+    }
+
+    fn set_frequency(&mut self, freq_hz: u32) -> Result<u32, Error> {
+        let divider = clock_divider(self.core_clock_hz, freq_hz)?;
+        // 65536 is written as 0.
+        self.region.write_reg(SPI_CLK, divider & 0xffff)?;
+        Ok(self.core_clock_hz / divider)
+    }
+
+    fn set_cs_active_high(&mut self, active_high: bool) -> Result<(), Error> {
+        let bit = 1 << (CS_CSPOL_SHIFT + self.chip_select as u32);
+        if active_high { self.update_cs(0, bit) } else { self.update_cs(bit, 0) }
+    }
+
+    fn transfer_dma(&mut self, tx: &[u8]) -> Result<Vec<u8>, Error> {
+        let channels = match self.dma.take() {
+            Some(channels) => channels,
+            None => {
+                let (tx_channel, rx_channel) = self.dma_channels;
+                if tx_channel == rx_channel {
+                    return Err(Error::Other(format!("SPI DMA needs two channels, not {} twice", tx_channel)));
+                }
+                (DmaChannel::open(self.peripheral_base, tx_channel)?, DmaChannel::open(self.peripheral_base, rx_channel)?)
+            }
+        };
+        let result = self.dma_transfer(&channels.0, &channels.1, tx);
+        self.dma = Some(channels);
+        result
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Echoes each byte back inverted.
+    struct Inverter;
+
+    impl SpiBackend for Inverter {
+        fn transfer(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+            buffer.iter_mut().for_each(|byte| *byte = !*byte);
+            Ok(())
+        }
+        fn set_mode(&mut self, _mode: SpiMode) -> Result<(), Error> { Ok(()) }
+        fn set_frequency(&mut self, freq_hz: u32) -> Result<u32, Error> { Ok(freq_hz) }
+        fn set_cs_active_high(&mut self, _active_high: bool) -> Result<(), Error> { Ok(()) }
+    }
+
+    #[test]
+    fn test_spi_over_custom_backend() {
+        let mut spi = Spi::custom(Inverter);
+        let mut buffer = [0x0f, 0xa5];
+        spi.transfer(&mut buffer).unwrap();
+        assert_eq!(buffer, [0xf0, 0x5a]);
+        spi.read(&mut buffer).unwrap();
+        assert_eq!(buffer, [0xff, 0xff]);
+        assert!(spi.write(&[1, 2, 3]).is_ok());
+        assert!(Spi::spidev(9, 9).is_err());
+    }
+
+    #[test]
+    fn test_clock_divider() {
+        assert_eq!(clock_divider(250_000_000, 1_000_000).ok(), Some(250));
+        assert_eq!(clock_divider(250_000_000, 3_000_000).ok(), Some(84));
+        assert_eq!(clock_divider(500_000_000, 500_000_000).ok(), Some(2));
+        assert_eq!(clock_divider(250_000_000, 3_815).ok(), Some(65532));
+        assert_eq!(clock_divider(131_072, 2).ok(), Some(65536));
+        assert!(clock_divider(250_000_000, 1_000).is_err());
+        assert!(clock_divider(250_000_000, 0).is_err());
+    }
+
+    #[test]
+    fn test_transfer_dma_threshold() {
+        assert!(!uses_dma(0));
+        assert!(!uses_dma(Spi::DMA_THRESHOLD - 1));
+        assert!(uses_dma(Spi::DMA_THRESHOLD));
+        // Backends without DMA fall back to polling either side of it.
+        let mut spi = Spi::custom(Inverter);
+        assert_eq!(spi.transfer_dma(&[0x0f, 0xa5]).ok(), Some(vec![0xf0, 0x5a]));
+        let long = vec![0x55; Spi::DMA_THRESHOLD + 3];
+        assert_eq!(spi.transfer_dma(&long).ok(), Some(vec![0xaa; Spi::DMA_THRESHOLD + 3]));
+    }
+
+    #[test]
+    fn test_dma_blocks() {
+        let [feed, drain] = dma_blocks(0xc000_0000, 97);
+        let fifo = 0x7e20_4004;
+        assert_eq!(feed.transfer_info, TI_WAIT_RESP | TI_SRC_INC | TI_DEST_DREQ | 6 << 16);
+        assert_eq!((feed.source, feed.destination, feed.length), (0xc000_0040, fifo, 100));
+        assert_eq!(drain.transfer_info, TI_WAIT_RESP | TI_DEST_INC | TI_SRC_DREQ | 7 << 16);
+        assert_eq!((drain.source, drain.destination, drain.length), (fifo, 0xc000_00a4, 100));
+        assert_eq!((feed.next, drain.next), (0, 0));
+    }
+
+    #[test]
+    fn test_register_spi_configuration() {
+        let path = std::env::temp_dir().join(format!("rustberrypi-spi-{}", std::process::id()));
+        std::fs::write(&path, vec![0u8; 4096]).unwrap();
+        let mut spi = RegisterSpi::open(&path, 0, 1, Soc::Bcm2837).ok().unwrap();
+        assert_eq!(spi.region.read_reg(SPI_CS).ok(), Some(1));
+        spi.set_mode(SpiMode::Mode3).unwrap();
+        spi.set_cs_active_high(true).unwrap();
+        assert_eq!(spi.region.read_reg(SPI_CS).ok(), Some(1 | CS_CPOL | CS_CPHA | 1 << 22));
+        spi.set_mode(SpiMode::Mode1).unwrap();
+        spi.set_cs_active_high(false).unwrap();
+        assert_eq!(spi.region.read_reg(SPI_CS).ok(), Some(1 | CS_CPHA));
+        assert_eq!(spi.set_frequency(1_000_000).ok(), Some(1_000_000));
+        assert_eq!(spi.region.read_reg(SPI_CLK).ok(), Some(250));
+        assert!(RegisterSpi::open(&path, 0, 2, Soc::Bcm2837).is_err());
+        drop(spi);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
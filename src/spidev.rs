@@ -0,0 +1,133 @@
+use crate::{open_file, Error, SpiBackend, SpiMode};
+
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+
+const SPI_CPHA: u8 = 0x01;
+const SPI_CPOL: u8 = 0x02;
+const SPI_CS_HIGH: u8 = 0x04;
+
+const DEFAULT_SPEED_HZ: u32 = 500_000;
+
+
+// struct spi_ioc_transfer from linux/spi/spidev.h.
+#[repr(C)]
+#[derive(Default)]
+struct SpiIocTransfer {
+    tx_buf: u64,
+    rx_buf: u64,
+    len: u32,
+    speed_hz: u32,
+    delay_usecs: u16,
+    bits_per_word: u8,
+    cs_change: u8,
+    tx_nbits: u8,
+    rx_nbits: u8,
+    word_delay_usecs: u8,
+    pad: u8,
+}
+
+nix::ioctl_write_ptr!(spi_ioc_message_1, b'k', 0, [SpiIocTransfer; 1]);
+nix::ioctl_write_ptr!(spi_ioc_wr_mode, b'k', 1, u8);
+nix::ioctl_write_ptr!(spi_ioc_wr_max_speed_hz, b'k', 4, u32);
+
+
+fn mode_bits(mode: SpiMode, cs_active_high: bool) -> u8 {
+    let bits = match mode {
+        SpiMode::Mode0 => 0,
+        SpiMode::Mode1 => SPI_CPHA,
+        SpiMode::Mode2 => SPI_CPOL,
+        SpiMode::Mode3 => SPI_CPOL | SPI_CPHA,
+    };
+    if cs_active_high { bits | SPI_CS_HIGH } else { bits }
+}
+
+
+/// SPI through the kernel's spidev driver, e.g. `/dev/spidev0.0`.
+pub(crate) struct Spidev {
+    file: File,
+    path: PathBuf,
+    mode: SpiMode,
+    cs_active_high: bool,
+    speed_hz: u32,
+}
+
+impl Spidev {
+
+    pub(crate) fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let mut spidev = Self {
+            file: open_file(&path)?, path, mode: SpiMode::Mode0, cs_active_high: false, speed_hz: DEFAULT_SPEED_HZ,
+        };
+        spidev.write_mode()?;
+        Ok(spidev)
+    }
+
+    fn write_mode(&mut self) -> Result<(), Error> {
+        let bits = mode_bits(self.mode, self.cs_active_high);
+        unsafe { spi_ioc_wr_mode(self.file.as_raw_fd(), &bits) }
+            .map_err(|e| Error::from_nix(format!("failed to set the SPI mode of {}", self.path.display()), e))?;
+        Ok(())
+    }
+}
+
+impl SpiBackend for Spidev {
+    fn transfer(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        let transfer = SpiIocTransfer {
+            // The kernel reads tx_buf before it writes rx_buf.
+            tx_buf: buffer.as_ptr() as u64,
+            rx_buf: buffer.as_mut_ptr() as u64,
+            len: buffer.len() as u32,
+            speed_hz: self.speed_hz,
+            bits_per_word: 8,
+            ..SpiIocTransfer::default()
+        };
+        unsafe { spi_ioc_message_1(self.file.as_raw_fd(), &[transfer]) }
+            .map_err(|e| Error::from_nix(format!("SPI transfer on {} failed", self.path.display()), e))?;
+        Ok(())
+    }
+
+    fn set_mode(&mut self, mode: SpiMode) -> Result<(), Error> {
+        self.mode = mode;
+        self.write_mode()
+    }
+
+    // The driver rounds to what the controller can do but doesn't say what
+    // that is, so the request is returned.
+    fn set_frequency(&mut self, freq_hz: u32) -> Result<u32, Error> {
+        unsafe { spi_ioc_wr_max_speed_hz(self.file.as_raw_fd(), &freq_hz) }
+            .map_err(|e| Error::from_nix(format!("failed to set the SPI speed of {}", self.path.display()), e))?;
+        self.speed_hz = freq_hz;
+        Ok(freq_hz)
+    }
+
+    fn set_cs_active_high(&mut self, active_high: bool) -> Result<(), Error> {
+        self.cs_active_high = active_high;
+        self.write_mode()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spi_ioc_transfer_layout() {
+        assert_eq!(std::mem::size_of::<SpiIocTransfer>(), 32);
+        assert_eq!(mode_bits(SpiMode::Mode3, false), 0x03);
+        assert_eq!(mode_bits(SpiMode::Mode1, true), 0x05);
+    }
+
+    #[test]
+    fn test_spidev_needs_a_spidev_node() {
+        let path = std::env::temp_dir().join(format!("rustberrypi-spidev-{}", std::process::id()));
+        std::fs::write(&path, b"").unwrap();
+        let error = Spidev::open(&path).err().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(error.errno(), Some(nix::errno::Errno::ENOTTY));
+        assert!(Spidev::open("/dev/spidev-does-not-exist").is_err());
+    }
+}
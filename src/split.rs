@@ -0,0 +1,199 @@
+use crate::{Error, GpioInput, GpioPin, Pin, PinFunction, Pull, Unconfigured, GPIO};
+
+use std::sync::Arc;
+
+
+/// One pin of a `GPIO` given up to `split`, owned outright rather than
+/// borrowed, so it can be moved into another thread or stored in a
+/// long-lived struct. The mapping is shared between the pins and unmapped
+/// once the last of them is dropped. Pins are `Send` and `Sync`.
+pub struct OwnedPin {
+    gpio: Arc<GPIO>,
+    pin: u32,
+}
+
+impl OwnedPin {
+
+    pub fn number(&self) -> u32 {
+        self.pin
+    }
+
+    /// A mode-typed `Pin` for this pin, for the drivers that take one,
+    /// borrowing this handle for as long as it lives.
+    pub fn as_pin(&mut self) -> Result<Pin<'_, Unconfigured>, Error> {
+        self.gpio.pin(self.pin)
+    }
+
+    pub fn function(&self) -> Result<PinFunction, Error> {
+        self.gpio.get_function(self.pin)
+    }
+
+    pub fn set_function(&self, function: PinFunction) -> Result<(), Error> {
+        self.gpio.set_function(self.pin, function)
+    }
+
+    pub fn pull(&self) -> Result<Pull, Error> {
+        self.gpio.get_pull(self.pin)
+    }
+
+    pub fn set_pull(&self, pull: Pull) -> Result<(), Error> {
+        self.gpio.set_pull(self.pin, pull)
+    }
+
+    pub fn read(&self) -> Result<bool, Error> {
+        self.gpio.read(self.pin)
+    }
+
+    /// The pin must already be an output, as for `GPIO::set_high`.
+    pub fn set_high(&self) -> Result<(), Error> {
+        self.gpio.set_high(self.pin)
+    }
+
+    pub fn set_low(&self) -> Result<(), Error> {
+        self.gpio.set_low(self.pin)
+    }
+
+    pub fn toggle(&self) -> Result<(), Error> {
+        self.gpio.toggle(self.pin)
+    }
+
+    /// See `GPIO::output_state`.
+    pub fn output_state(&self) -> Option<bool> {
+        self.gpio.output_state(self.pin).ok().flatten()
+    }
+}
+
+impl GpioInput for OwnedPin {
+    fn is_high(&self) -> Result<bool, Error> {
+        self.read()
+    }
+}
+
+impl GpioPin for OwnedPin {
+    fn set_high(&self) -> Result<(), Error> {
+        OwnedPin::set_high(self)
+    }
+
+    fn set_low(&self) -> Result<(), Error> {
+        OwnedPin::set_low(self)
+    }
+
+    // Falls back to the pin's level if nothing has been driven through
+    // this GPIO yet.
+    fn is_set_high(&self) -> Result<bool, Error> {
+        match self.output_state() {
+            Some(level) => Ok(level),
+            None => self.read(),
+        }
+    }
+
+    fn toggle(&self) -> Result<(), Error> {
+        OwnedPin::toggle(self)
+    }
+}
+
+
+/// The pins of a split `GPIO`, each to be taken once and handed to
+/// whichever part of the application needs it.
+pub struct Pins {
+    pins: Vec<Option<OwnedPin>>,
+}
+
+impl Pins {
+
+    // Why `pin` can't be taken.
+    fn unavailable(&self, pin: u32) -> Error {
+        if (pin as usize) < self.pins.len() { Error::PinInUse(pin) } else { Error::InvalidPin { pin, register: None } }
+    }
+
+    /// Takes pin `pin`. Fails with `PinInUse` if it has already been
+    /// taken, and for pins the SoC doesn't have.
+    pub fn take(&mut self, pin: u32) -> Result<OwnedPin, Error> {
+        match self.pins.get_mut(pin as usize).and_then(Option::take) {
+            Some(owned) => Ok(owned),
+            None => Err(self.unavailable(pin)),
+        }
+    }
+
+    /// Takes each of `pins`, or none of them if any can't be taken.
+    pub fn take_all<const N: usize>(&mut self, pins: [u32; N]) -> Result<[OwnedPin; N], Error> {
+        for (index, &pin) in pins.iter().enumerate() {
+            if pins[..index].contains(&pin) || !self.is_available(pin) {
+                return Err(self.unavailable(pin));
+            }
+        }
+        Ok(pins.map(|pin| self.pins[pin as usize].take().unwrap()))
+    }
+
+    pub fn is_available(&self, pin: u32) -> bool {
+        self.pins.get(pin as usize).is_some_and(Option::is_some)
+    }
+
+    /// The pins not yet taken, lowest first.
+    pub fn available(&self) -> Vec<u32> {
+        self.pins.iter().flatten().map(OwnedPin::number).collect()
+    }
+}
+
+impl IntoIterator for Pins {
+    type Item = OwnedPin;
+    type IntoIter = std::iter::Flatten<std::vec::IntoIter<Option<OwnedPin>>>;
+
+    /// The pins not yet taken, lowest first.
+    fn into_iter(self) -> Self::IntoIter {
+        self.pins.into_iter().flatten()
+    }
+}
+
+
+impl GPIO {
+
+    /// Gives up the `GPIO` for an owned handle to each of the SoC's pins,
+    /// so different threads or subsystems can each own the pins they use
+    /// without sharing the `GPIO`. What the `GPIO` was set up with, such
+    /// as its write policy and debounce periods, carries over to the pins.
+    pub fn split(self) -> Pins {
+        let count = self.soc.gpio_count();
+        let gpio = Arc::new(self);
+        let pins = (0..count).map(|pin| Some(OwnedPin { gpio: Arc::clone(&gpio), pin })).collect();
+        Pins { pins }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Relay;
+
+    #[test]
+    fn test_split() {
+        let mut pins = GPIO::open_for_testing_on(Vec::new()).split();
+        let mut led = pins.take(17).unwrap();
+        assert!(matches!(pins.take(17), Err(Error::PinInUse(17))));
+        assert!(matches!(pins.take(60), Err(Error::InvalidPin { pin: 60, .. })));
+        assert!(!pins.is_available(17) && pins.is_available(18));
+
+        led.set_function(PinFunction::Output).unwrap();
+        led.set_high().unwrap();
+        assert_eq!(led.output_state(), Some(true));
+        let output = led.as_pin().unwrap().into_output().unwrap();
+        output.toggle().unwrap();
+        drop(output);
+        assert_eq!(led.output_state(), Some(false));
+
+        assert!(pins.take_all([5, 6, 5]).is_err());
+        assert!(pins.take_all([5, 17]).is_err());
+        let [relay_pin, other] = pins.take_all([5, 6]).unwrap();
+        assert_eq!(pins.available().len(), pins.into_iter().count());
+
+        let worker = std::thread::spawn(move || {
+            relay_pin.set_function(PinFunction::Output).unwrap();
+            let relay = Relay::new(relay_pin).unwrap();
+            relay.on().unwrap();
+            relay.is_on().unwrap()
+        });
+        assert!(worker.join().unwrap());
+        assert_eq!(other.function().ok(), Some(PinFunction::Input));
+    }
+}
@@ -0,0 +1,150 @@
+use crate::timing::wait_until;
+use crate::{bit_in_bank, Error, Register, GPIO};
+
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+
+fn half_period(freq_hz: f64) -> Duration {
+    assert!(
+        freq_hz.is_finite() && freq_hz > 0.0,
+        "Illegal frequency. Frequency must be positive - Paniced on freq_hz = {}", freq_hz
+    );
+    Duration::from_secs_f64(0.5 / freq_hz)
+}
+
+
+pub struct SquareWaveHandle<'a> {
+    half_period_ns: Arc<AtomicU64>,
+    toggles: Arc<AtomicU64>,
+    running: Arc<AtomicBool>,
+    started: Instant,
+    thread: Option<JoinHandle<()>>,
+    gpio: PhantomData<&'a GPIO>,
+}
+
+impl<'a> SquareWaveHandle<'a> {
+
+    pub fn set_frequency(&self, freq_hz: f64) {
+        self.half_period_ns.store(half_period(freq_hz).as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn requested_frequency(&self) -> f64 {
+        0.5e9 / self.half_period_ns.load(Ordering::Relaxed) as f64
+    }
+
+    pub fn achieved_frequency(&self) -> f64 {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        if elapsed == 0.0 {
+            return 0.0;
+        }
+        self.toggles.load(Ordering::Relaxed) as f64 / 2.0 / elapsed
+    }
+
+    pub fn stop(mut self) {
+        self.join();
+    }
+
+    fn join(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl<'a> Drop for SquareWaveHandle<'a> {
+    fn drop(&mut self) {
+        self.join();
+    }
+}
+
+
+impl GPIO {
+
+    /// Toggles `pin` at `freq_hz` with a 50% duty cycle from a background
+    /// thread until the returned handle is dropped. The pin must already be
+    /// configured as an output. Scheduling jitter means the achieved
+    /// frequency will differ from the requested one; see
+    /// `SquareWaveHandle::achieved_frequency`. If `calibrate` has been run,
+    /// the measured write latency is taken off each edge's spin target.
+    pub fn square_wave(&self, pin: u32, freq_hz: f64) -> Result<SquareWaveHandle<'_>, Error> {
+        let half_period_ns = Arc::new(AtomicU64::new(half_period(freq_hz).as_nanos() as u64));
+        let toggles = Arc::new(AtomicU64::new(0));
+        let running = Arc::new(AtomicBool::new(true));
+
+        self.check_writable()?;
+        // The handle borrows the GPIO, so the mapping outlives the thread.
+        let set = self.register_ptr(Register::GPSET, pin)? as usize;
+        let clear = self.register_ptr(Register::GPCLR, pin)? as usize;
+        let bit: u32 = 1 << bit_in_bank(pin);
+        let calibration = self.calibration_or_none();
+
+        let thread = {
+            let half_period_ns = Arc::clone(&half_period_ns);
+            let toggles = Arc::clone(&toggles);
+            let running = Arc::clone(&running);
+            std::thread::spawn(move || {
+                let mut high = false;
+                let mut deadline = Instant::now();
+                while running.load(Ordering::Relaxed) {
+                    high = !high;
+                    let register = if high { set } else { clear };
+                    unsafe { (register as *mut u32).write_volatile(bit) };
+                    toggles.fetch_add(1, Ordering::Relaxed);
+
+                    deadline += Duration::from_nanos(half_period_ns.load(Ordering::Relaxed));
+                    wait_until(calibration.spin_target(deadline));
+                }
+                unsafe { (clear as *mut u32).write_volatile(bit) };
+            })
+        };
+
+        Ok(SquareWaveHandle {
+            half_period_ns,
+            toggles,
+            running,
+            started: Instant::now(),
+            thread: Some(thread),
+            gpio: PhantomData,
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_half_period() {
+        assert_eq!(half_period(1000.0), Duration::from_micros(500));
+        assert_eq!(half_period(0.5), Duration::from_secs(1));
+        assert_eq!(half_period(38_000.0).as_nanos(), 13_158);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_half_period_rejects_zero() {
+        half_period(0.0);
+    }
+
+    #[test]
+    fn test_square_wave_start_stop() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let wave = gpio.square_wave(21, 1000.0).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(wave.achieved_frequency() > 0.0);
+
+        wave.set_frequency(2000.0);
+        assert!((wave.requested_frequency() - 2000.0).abs() < 1.0);
+        wave.stop();
+
+        let dropped = gpio.square_wave(21, 500.0).unwrap();
+        drop(dropped);
+        assert!(gpio.square_wave(58, 500.0).is_err());
+    }
+}
@@ -0,0 +1,147 @@
+use crate::{Error, Input, Output, Pin};
+
+use std::sync::Mutex;
+
+
+type ChangeCallback = Box<dyn FnMut(usize, bool) + Send>;
+
+
+/// A chain of 74HC165 parallel-in, serial-out shift registers, reading
+/// eight inputs per chip from three pins. Chip 0 is the one whose QH is
+/// wired to the Pi; each chip's SER takes the next one's QH. Input `n` is
+/// D(n % 8) of chip `n / 8`. CLK INH should be tied low.
+pub struct Sr74hc165<'a> {
+    load: Pin<'a, Output>,
+    clock: Pin<'a, Output>,
+    data: Pin<'a, Input>,
+    chips: usize,
+    last: Mutex<Option<Vec<u8>>>,
+    on_change: Mutex<Option<ChangeCallback>>,
+}
+
+impl<'a> Sr74hc165<'a> {
+
+    /// `load` goes to SH/LD, which is active low.
+    pub fn new(load: Pin<'a, Output>, clock: Pin<'a, Output>, data: Pin<'a, Input>, chips: usize) -> Result<Self, Error> {
+        if chips == 0 {
+            return Err(Error::Other("a 74HC165 chain needs at least one chip".to_string()));
+        }
+        load.set_high()?;
+        clock.set_low()?;
+        Ok(Self { load, clock, data, chips, last: Mutex::new(None), on_change: Mutex::new(None) })
+    }
+
+    pub fn chips(&self) -> usize {
+        self.chips
+    }
+
+    /// The number of inputs, eight per chip.
+    pub fn len(&self) -> usize {
+        self.chips * 8
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Calls `callback` with the input number and its new level for every
+    /// input that changed between one `read` and the next. It runs on the
+    /// reading thread, after the read has finished.
+    pub fn on_change(&self, callback: impl FnMut(usize, bool) + Send + 'static) {
+        *self.on_change.lock().unwrap() = Some(Box::new(callback));
+    }
+
+    pub fn clear_on_change(&self) {
+        *self.on_change.lock().unwrap() = None;
+    }
+
+    // Latches the inputs, then clocks them out. QH shows D7 of chip 0 as
+    // soon as the inputs are latched, so each bit is sampled before the
+    // clock edge that brings in the next.
+    fn shift_in(&self) -> Result<Vec<u8>, Error> {
+        self.load.set_low()?;
+        self.load.set_high()?;
+        let mut bytes = vec![0u8; self.chips];
+        for byte in bytes.iter_mut() {
+            for bit in (0..8).rev() {
+                if self.data.read()? {
+                    *byte |= 1 << bit;
+                }
+                self.clock.set_high()?;
+                self.clock.set_low()?;
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Reads every input, one byte per chip, and reports any changes since
+    /// the last read to the `on_change` callback.
+    pub fn read(&self) -> Result<Vec<u8>, Error> {
+        let bytes = self.shift_in()?;
+        let previous = self.last.lock().unwrap().replace(bytes.clone());
+        if let (Some(previous), Some(callback)) = (previous, self.on_change.lock().unwrap().as_mut()) {
+            for (chip, (&old, &new)) in previous.iter().zip(&bytes).enumerate() {
+                for bit in (0..8).filter(|bit| (old ^ new) & (1 << bit) != 0) {
+                    callback(chip * 8 + bit, new & (1 << bit) != 0);
+                }
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Reads the chain and returns the level of `input`.
+    pub fn get(&self, input: usize) -> Result<bool, Error> {
+        if input >= self.len() {
+            return Err(Error::Other(format!("there is no input {} on {} chips", input, self.chips)));
+        }
+        Ok(self.read()?[input / 8] & (1 << (input % 8)) != 0)
+    }
+
+    /// The levels from the last `read`, without reading again.
+    pub fn last_read(&self) -> Option<Vec<u8>> {
+        self.last.lock().unwrap().clone()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockGpio;
+    use std::sync::Arc;
+
+    const LOAD: u32 = 17;
+    const CLOCK: u32 = 27;
+    const DATA: u32 = 22;
+
+    // The load and clock pulses so far.
+    fn pulses(mock: &MockGpio) -> (usize, usize) {
+        (mock.writes(LOAD).iter().filter(|&&level| !level).count(), mock.writes(CLOCK).iter().filter(|&&level| level).count())
+    }
+
+    #[test]
+    fn test_sr74hc165_chain() {
+        let mock = MockGpio::new();
+        let gpio = mock.gpio().unwrap();
+        let output = |pin| gpio.pin(pin).unwrap().into_output().unwrap();
+        let register = Sr74hc165::new(output(LOAD), output(CLOCK), gpio.pin(DATA).unwrap().into_input().unwrap(), 2).unwrap();
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&changes);
+        register.on_change(move |input, level| log.lock().unwrap().push((input, level)));
+
+        assert_eq!(register.last_read(), None);
+        assert_eq!(register.read().unwrap(), vec![0, 0]);
+        assert_eq!(pulses(&mock), (1, 16));
+        assert!(changes.lock().unwrap().is_empty());
+
+        mock.set_input(DATA, true);
+        assert!(register.get(12).unwrap());
+        assert_eq!(register.last_read(), Some(vec![0xff, 0xff]));
+        assert_eq!(*changes.lock().unwrap(), (0..16).map(|input| (input, true)).collect::<Vec<_>>());
+        assert!(register.get(16).is_err());
+
+        changes.lock().unwrap().clear();
+        register.read().unwrap();
+        assert!(changes.lock().unwrap().is_empty());
+    }
+}
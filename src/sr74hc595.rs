@@ -0,0 +1,214 @@
+use crate::{Error, Output, Pin};
+
+use std::sync::Mutex;
+
+
+/// A chain of 74HC595 serial-in, parallel-out shift registers, giving eight
+/// outputs per chip from three pins. Chip 0 is the one whose serial input
+/// is wired to the Pi; each chip's QH' feeds the next one's SER. Output
+/// `n` is Q(n % 8) of chip `n / 8`.
+///
+/// The chips' outputs can't be read back, so the driver keeps their state
+/// and shifts the whole chain out on every change. The outputs only
+/// change, all at once, when the latch is pulsed at the end.
+pub struct Sr74hc595<'a> {
+    data: Pin<'a, Output>,
+    clock: Pin<'a, Output>,
+    latch: Pin<'a, Output>,
+    state: Mutex<Vec<u8>>,
+}
+
+impl<'a> Sr74hc595<'a> {
+
+    /// A chain of `chips` registers with every output driven low.
+    pub fn new(data: Pin<'a, Output>, clock: Pin<'a, Output>, latch: Pin<'a, Output>, chips: usize) -> Result<Self, Error> {
+        if chips == 0 {
+            return Err(Error::Other("a 74HC595 chain needs at least one chip".to_string()));
+        }
+        clock.set_low()?;
+        latch.set_low()?;
+        let register = Self { data, clock, latch, state: Mutex::new(vec![0; chips]) };
+        register.shift_out(&register.state.lock().unwrap())?;
+        Ok(register)
+    }
+
+    pub fn chips(&self) -> usize {
+        self.state.lock().unwrap().len()
+    }
+
+    /// The number of outputs, eight per chip.
+    pub fn len(&self) -> usize {
+        self.chips() * 8
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // The last chip's bits go first, so they travel to the end of the
+    // chain. Each byte goes MSB first, as the first bit in ends up on QH.
+    fn shift_out(&self, bytes: &[u8]) -> Result<(), Error> {
+        for &byte in bytes.iter().rev() {
+            for bit in (0..8).rev() {
+                if byte & (1 << bit) != 0 { self.data.set_high()? } else { self.data.set_low()? }
+                self.clock.set_high()?;
+                self.clock.set_low()?;
+            }
+        }
+        self.latch.set_high()?;
+        self.latch.set_low()
+    }
+
+    /// Sets every chip's outputs at once, `bytes[0]` going to chip 0.
+    pub fn write(&self, bytes: &[u8]) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        if bytes.len() != state.len() {
+            return Err(Error::Other(format!("{} bytes for a chain of {} chips", bytes.len(), state.len())));
+        }
+        self.shift_out(bytes)?;
+        state.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Sets the eight outputs of `chip`, leaving the other chips alone.
+    pub fn write_byte(&self, chip: usize, byte: u8) -> Result<(), Error> {
+        self.update(|state| {
+            let chips = state.len();
+            let value = state.get_mut(chip)
+                .ok_or_else(|| Error::Other(format!("there is no chip {} in a chain of {}", chip, chips)))?;
+            *value = byte;
+            Ok(())
+        })
+    }
+
+    // Applies `change` to a copy of the state and shifts the result out,
+    // keeping the new state only once it's on the outputs.
+    fn update(&self, change: impl FnOnce(&mut Vec<u8>) -> Result<(), Error>) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        let mut next = state.clone();
+        change(&mut next)?;
+        self.shift_out(&next)?;
+        *state = next;
+        Ok(())
+    }
+
+    fn check_output(&self, output: usize) -> Result<(), Error> {
+        if output >= self.len() {
+            return Err(Error::Other(format!("there is no output {} on {} chips", output, self.chips())));
+        }
+        Ok(())
+    }
+
+    pub fn set(&self, output: usize, high: bool) -> Result<(), Error> {
+        self.check_output(output)?;
+        self.update(|state| {
+            let bit = 1 << (output % 8);
+            state[output / 8] = if high { state[output / 8] | bit } else { state[output / 8] & !bit };
+            Ok(())
+        })
+    }
+
+    /// The level last written to `output`.
+    pub fn get(&self, output: usize) -> Result<bool, Error> {
+        self.check_output(output)?;
+        Ok(self.state.lock().unwrap()[output / 8] & (1 << (output % 8)) != 0)
+    }
+
+    /// The levels last written, one byte per chip.
+    pub fn outputs(&self) -> Vec<u8> {
+        self.state.lock().unwrap().clone()
+    }
+
+    /// A handle to one output that can be set like an output pin.
+    pub fn output(&self, output: usize) -> Result<ShiftRegisterPin<'_, 'a>, Error> {
+        self.check_output(output)?;
+        Ok(ShiftRegisterPin { register: self, output })
+    }
+}
+
+
+/// One output of a `Sr74hc595` chain.
+pub struct ShiftRegisterPin<'r, 'a> {
+    register: &'r Sr74hc595<'a>,
+    output: usize,
+}
+
+impl<'r, 'a> ShiftRegisterPin<'r, 'a> {
+
+    pub fn output(&self) -> usize {
+        self.output
+    }
+
+    pub fn set_high(&self) -> Result<(), Error> {
+        self.register.set(self.output, true)
+    }
+
+    pub fn set_low(&self) -> Result<(), Error> {
+        self.register.set(self.output, false)
+    }
+
+    pub fn toggle(&self) -> Result<(), Error> {
+        self.register.set(self.output, !self.is_set_high()?)
+    }
+
+    pub fn is_set_high(&self) -> Result<bool, Error> {
+        self.register.get(self.output)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MockGpio, MockOperation};
+
+    const DATA: u32 = 17;
+    const CLOCK: u32 = 27;
+    const LATCH: u32 = 22;
+
+    // The data level at each rising clock edge, and the number of latch
+    // pulses.
+    fn shifted(operations: &[MockOperation]) -> (Vec<bool>, usize) {
+        let mut data = false;
+        let mut bits = Vec::new();
+        let mut latches = 0;
+        for operation in operations {
+            match *operation {
+                MockOperation::Write { pin: DATA, level } => data = level,
+                MockOperation::Write { pin: CLOCK, level: true } => bits.push(data),
+                MockOperation::Write { pin: LATCH, level: true } => latches += 1,
+                _ => {}
+            }
+        }
+        (bits, latches)
+    }
+
+    fn bits_of(byte: u8) -> Vec<bool> {
+        (0..8).rev().map(|bit| byte & (1 << bit) != 0).collect()
+    }
+
+    #[test]
+    fn test_sr74hc595_chain() {
+        let mock = MockGpio::new();
+        let gpio = mock.gpio().unwrap();
+        let output = |pin| gpio.pin(pin).unwrap().into_output().unwrap();
+        let register = Sr74hc595::new(output(DATA), output(CLOCK), output(LATCH), 2).unwrap();
+        assert_eq!(shifted(&mock.take_operations()), (vec![false; 16], 1));
+
+        register.write(&[0x01, 0x80]).unwrap();
+        let expected: Vec<bool> = bits_of(0x80).into_iter().chain(bits_of(0x01)).collect();
+        assert_eq!(shifted(&mock.operations()), (expected, 1));
+        assert!(register.write(&[0]).is_err());
+
+        let pin = register.output(9).unwrap();
+        pin.set_high().unwrap();
+        assert_eq!(register.outputs(), vec![0x01, 0x82]);
+        pin.toggle().unwrap();
+        assert!(!pin.is_set_high().unwrap());
+        register.write_byte(0, 0xf0).unwrap();
+        assert_eq!(register.outputs(), vec![0xf0, 0x80]);
+        assert!(register.get(3).ok() == Some(false) && register.get(4).ok() == Some(true));
+        assert!(register.output(16).is_err());
+        assert!(register.write_byte(2, 0).is_err());
+    }
+}
@@ -0,0 +1,397 @@
+use crate::timing::wait_until;
+use crate::{bit_in_bank, Error, Output, Pin, Register, GPIO};
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+
+// Coil patterns, A in bit 0 to D in bit 3. Full steps energise two coils
+// at a time for more torque; half steps alternate between one and two.
+const FULL_STEPS: [u8; 4] = [0b0011, 0b0110, 0b1100, 0b1001];
+const HALF_STEPS: [u8; 8] = [0b0001, 0b0011, 0b0010, 0b0110, 0b0100, 0b1100, 0b1000, 0b1001];
+
+// The A4988 wants STEP high for at least 1 µs, and DIR settled 200 ns
+// before it rises.
+const STEP_PULSE: Duration = Duration::from_micros(2);
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum StepMode {
+    Full,
+    Half,
+}
+
+
+// The speed for the next step, in steps per second, of a move with
+// `remaining` steps left including that one. Speeds up or slows down by
+// `acceleration` so the move ends at about the speed reached after one
+// step from standstill; with no acceleration every step is at full speed.
+fn next_speed(speed: f64, remaining: u64, max_speed: f64, acceleration: f64) -> f64 {
+    if acceleration <= 0.0 {
+        return max_speed;
+    }
+    let slowest = (2.0 * acceleration).sqrt().min(max_speed);
+    let stopping_steps = speed * speed / (2.0 * acceleration);
+    if remaining as f64 <= stopping_steps {
+        (speed * speed - 2.0 * acceleration).max(0.0).sqrt().max(slowest)
+    } else {
+        (speed * speed + 2.0 * acceleration).sqrt().min(max_speed)
+    }
+}
+
+
+// A pin as the motion thread drives it, straight through GPSET/GPCLR.
+struct RawPin {
+    set: usize,
+    clear: usize,
+    bit: u32,
+}
+
+impl RawPin {
+    fn new(gpio: &GPIO, pin: u32) -> Result<Self, Error> {
+        Ok(Self {
+            set: gpio.register_ptr(Register::GPSET, pin)? as usize,
+            clear: gpio.register_ptr(Register::GPCLR, pin)? as usize,
+            bit: 1 << bit_in_bank(pin),
+        })
+    }
+
+    fn write(&self, high: bool) {
+        let register = if high { self.set } else { self.clear };
+        unsafe { (register as *mut u32).write_volatile(self.bit) };
+    }
+}
+
+enum Coils {
+    Uln2003 { pins: Vec<RawPin>, sequence: &'static [u8], phase: usize },
+    StepDir { step: RawPin, dir: RawPin },
+}
+
+impl Coils {
+    fn step(&mut self, forward: bool) {
+        match self {
+            Coils::Uln2003 { pins, sequence, phase } => {
+                *phase = if forward { (*phase + 1) % sequence.len() } else { (*phase + sequence.len() - 1) % sequence.len() };
+                for (coil, pin) in pins.iter().enumerate() {
+                    pin.write(sequence[*phase] & (1 << coil) != 0);
+                }
+            }
+            Coils::StepDir { step, dir } => {
+                dir.write(forward);
+                let started = Instant::now();
+                step.write(true);
+                wait_until(started + STEP_PULSE);
+                step.write(false);
+            }
+        }
+    }
+
+    // Leaves the motor unpowered. Step/dir drivers have their own enable
+    // line, which is left alone.
+    fn release(&self) {
+        if let Coils::Uln2003 { pins, .. } = self {
+            pins.iter().for_each(|pin| pin.write(false));
+        }
+    }
+}
+
+
+struct Motion {
+    position: i64,
+    target: i64,
+    // Signed, in steps per second.
+    velocity: f64,
+    max_speed: f64,
+    acceleration: f64,
+    running: bool,
+}
+
+impl Motion {
+    // Picks the direction and speed of the next step, slowing to a stop
+    // first if the target has moved behind the motor.
+    fn next_step(&mut self) -> (bool, f64) {
+        let distance = self.target - self.position;
+        let slowest = (2.0 * self.acceleration).sqrt().min(self.max_speed);
+        if (distance > 0) != (self.velocity > 0.0) && (self.acceleration <= 0.0 || self.velocity.abs() <= slowest) {
+            self.velocity = 0.0;
+        }
+        let forward = if self.velocity != 0.0 { self.velocity > 0.0 } else { distance > 0 };
+        let remaining = if (distance > 0) == forward { distance.unsigned_abs() } else { 0 };
+        let speed = next_speed(self.velocity.abs(), remaining, self.max_speed, self.acceleration);
+        self.velocity = if forward { speed } else { -speed };
+        (forward, speed)
+    }
+}
+
+struct Shared {
+    motion: Mutex<Motion>,
+    changed: Condvar,
+}
+
+fn run(shared: &Shared, mut coils: Coils) {
+    let mut last_step = Instant::now();
+    loop {
+        let (forward, speed) = {
+            let mut motion = shared.motion.lock().unwrap();
+            loop {
+                if !motion.running {
+                    coils.release();
+                    return;
+                }
+                if motion.position != motion.target {
+                    break;
+                }
+                motion.velocity = 0.0;
+                motion = shared.changed.wait(motion).unwrap();
+            }
+            motion.next_step()
+        };
+        // After a rest the deadline has passed, so the first step is at once.
+        wait_until(last_step + Duration::from_secs_f64(1.0 / speed));
+        let mut motion = shared.motion.lock().unwrap();
+        // A stop while waiting cancels the step.
+        if motion.position == motion.target {
+            continue;
+        }
+        coils.step(forward);
+        last_step = Instant::now();
+        motion.position += if forward { 1 } else { -1 };
+        shared.changed.notify_all();
+    }
+}
+
+
+/// A stepper motor moved by a background thread, either through a ULN2003
+/// darlington board driving a unipolar motor such as the 28BYJ-48, or
+/// through a step/dir driver such as the A4988 or DRV8825. Moves speed up
+/// and slow down at a set acceleration, and `move_to` returns at once;
+/// `wait` blocks until the motor arrives. The thread drives the pins
+/// through their registers, so a register backend is needed.
+///
+/// Positions count steps, or half steps in `StepMode::Half`, from where
+/// the motor was when the driver was set up.
+pub struct Stepper<'a> {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+    _pins: Vec<Pin<'a, Output>>,
+}
+
+impl<'a> Stepper<'a> {
+
+    pub const DEFAULT_MAX_SPEED: f64 = 500.0;
+
+    pub const DEFAULT_ACCELERATION: f64 = 1000.0;
+
+    /// A motor whose coils A to D are driven from `pins` through a ULN2003.
+    pub fn uln2003(pins: [Pin<'a, Output>; 4], mode: StepMode) -> Result<Self, Error> {
+        let gpio = pins[0].gpio();
+        let raw = pins.iter().map(|pin| RawPin::new(gpio, pin.number())).collect::<Result<Vec<_>, _>>()?;
+        let sequence: &'static [u8] = match mode {
+            StepMode::Full => &FULL_STEPS,
+            StepMode::Half => &HALF_STEPS,
+        };
+        let coils = Coils::Uln2003 { pins: raw, sequence, phase: 0 };
+        Self::start(gpio, Vec::from(pins), coils)
+    }
+
+    /// A motor behind a step/dir driver, stepping forwards with `dir` high.
+    pub fn step_dir(step: Pin<'a, Output>, dir: Pin<'a, Output>) -> Result<Self, Error> {
+        let gpio = step.gpio();
+        step.set_low()?;
+        let coils = Coils::StepDir { step: RawPin::new(gpio, step.number())?, dir: RawPin::new(gpio, dir.number())? };
+        Self::start(gpio, vec![step, dir], coils)
+    }
+
+    fn start(gpio: &'a GPIO, pins: Vec<Pin<'a, Output>>, coils: Coils) -> Result<Self, Error> {
+        gpio.check_writable()?;
+        let shared = Arc::new(Shared {
+            motion: Mutex::new(Motion {
+                position: 0,
+                target: 0,
+                velocity: 0.0,
+                max_speed: Self::DEFAULT_MAX_SPEED,
+                acceleration: Self::DEFAULT_ACCELERATION,
+                running: true,
+            }),
+            changed: Condvar::new(),
+        });
+        // The thread only runs while the driver, and so its borrow of the
+        // GPIO, is alive.
+        let thread = {
+            let shared = Arc::clone(&shared);
+            std::thread::Builder::new()
+                .name("rustberrypi-stepper".to_string())
+                .spawn(move || run(&shared, coils))
+                .map_err(|e| Error::from_io("failed to start the stepper thread", e))?
+        };
+        Ok(Self { shared, thread: Some(thread), _pins: pins })
+    }
+
+    fn update(&self, change: impl FnOnce(&mut Motion)) {
+        change(&mut self.shared.motion.lock().unwrap());
+        self.shared.changed.notify_all();
+    }
+
+    /// The top speed in steps per second.
+    pub fn set_max_speed(&self, steps_per_sec: f64) -> Result<(), Error> {
+        if !(steps_per_sec.is_finite() && steps_per_sec > 0.0) {
+            return Err(Error::Other(format!("stepper speed must be positive, not {}", steps_per_sec)));
+        }
+        self.update(|motion| motion.max_speed = steps_per_sec);
+        Ok(())
+    }
+
+    /// In steps per second squared; 0 moves at full speed from the first
+    /// step, which only a lightly loaded motor will follow.
+    pub fn set_acceleration(&self, steps_per_sec2: f64) -> Result<(), Error> {
+        if !(steps_per_sec2.is_finite() && steps_per_sec2 >= 0.0) {
+            return Err(Error::Other(format!("stepper acceleration can't be {}", steps_per_sec2)));
+        }
+        self.update(|motion| motion.acceleration = steps_per_sec2);
+        Ok(())
+    }
+
+    /// Starts moving to `position`, replacing any move in progress.
+    pub fn move_to(&self, position: i64) {
+        self.update(|motion| motion.target = position);
+    }
+
+    pub fn move_by(&self, steps: i64) {
+        self.update(|motion| motion.target += steps);
+    }
+
+    /// Stops at the next step, without slowing down.
+    pub fn stop(&self) {
+        self.update(|motion| motion.target = motion.position);
+    }
+
+    /// Redefines the current position, stopping any move.
+    pub fn set_position(&self, position: i64) {
+        self.update(|motion| {
+            motion.position = position;
+            motion.target = position;
+        });
+    }
+
+    pub fn position(&self) -> i64 {
+        self.shared.motion.lock().unwrap().position
+    }
+
+    pub fn target(&self) -> i64 {
+        self.shared.motion.lock().unwrap().target
+    }
+
+    /// The current speed in steps per second, negative when moving
+    /// backwards.
+    pub fn speed(&self) -> f64 {
+        self.shared.motion.lock().unwrap().velocity
+    }
+
+    pub fn is_moving(&self) -> bool {
+        let motion = self.shared.motion.lock().unwrap();
+        motion.position != motion.target
+    }
+
+    /// Blocks until the motor reaches its target.
+    pub fn wait(&self, timeout: Option<Duration>) -> Result<(), Error> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut motion = self.shared.motion.lock().unwrap();
+        while motion.position != motion.target {
+            motion = match deadline {
+                None => self.shared.changed.wait(motion).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(Error::Timeout(format!("the stepper is still {} steps away", motion.target - motion.position)));
+                    }
+                    self.shared.changed.wait_timeout(motion, deadline - now).unwrap().0
+                }
+            };
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Drop for Stepper<'a> {
+    fn drop(&mut self) {
+        self.update(|motion| motion.running = false);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_speed_ramp() {
+        assert_eq!(next_speed(0.0, 10, 200.0, 0.0), 200.0);
+        // Speeds up to the limit on a long move...
+        let mut speed = 0.0;
+        let mut speeds = Vec::new();
+        for remaining in (1..=200).rev() {
+            speed = next_speed(speed, remaining, 100.0, 400.0);
+            speeds.push(speed);
+        }
+        assert!((speeds[0] - 800f64.sqrt()).abs() < 1e-9);
+        assert_eq!(speeds[100], 100.0);
+        // ...and is back near the starting speed for the last step.
+        assert!(speeds[199] < 40.0);
+        assert!(speeds.windows(2).take(10).all(|pair| pair[1] > pair[0]));
+    }
+
+    #[test]
+    fn test_reversal_slows_down_first() {
+        let mut motion = Motion { position: 10, target: 0, velocity: 100.0, max_speed: 100.0, acceleration: 400.0, running: true };
+        let (forward, speed) = motion.next_step();
+        assert!(forward && speed < 100.0);
+        motion.velocity = 20.0;
+        assert!(!motion.next_step().0);
+        motion.velocity = 100.0;
+        motion.acceleration = 0.0;
+        assert_eq!(motion.next_step(), (false, 100.0));
+    }
+
+    #[test]
+    fn test_uln2003_sequence() {
+        let mut words = [0u32; 8];
+        let base = words.as_mut_ptr() as usize;
+        // Each coil gets its own pair of words: set, then clear.
+        let pins = (0..4).map(|coil| RawPin { set: base + coil * 8, clear: base + coil * 8 + 4, bit: 1 }).collect();
+        let mut coils = Coils::Uln2003 { pins, sequence: &HALF_STEPS, phase: 0 };
+        coils.step(true);
+        words = [0; 8];
+        coils.step(true);
+        // Only coil B is on in the third half step.
+        assert_eq!(words, [0, 1, 1, 0, 0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn test_stepper_moves() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let pins = [5, 6, 13, 19].map(|pin| gpio.pin(pin).unwrap().into_output().unwrap());
+        let stepper = Stepper::uln2003(pins, StepMode::Half).unwrap();
+        stepper.set_acceleration(0.0).unwrap();
+        stepper.set_max_speed(5000.0).unwrap();
+        assert!(stepper.set_max_speed(0.0).is_err());
+        stepper.move_by(20);
+        stepper.wait(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(stepper.position(), 20);
+        stepper.move_to(-5);
+        stepper.wait(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!((stepper.position(), stepper.is_moving()), (-5, false));
+
+        stepper.set_max_speed(10.0).unwrap();
+        stepper.move_by(1000);
+        assert!(matches!(stepper.wait(Some(Duration::from_millis(10))), Err(Error::Timeout(_))));
+        stepper.stop();
+        stepper.wait(Some(Duration::from_secs(5))).unwrap();
+        drop(stepper);
+        // The coils are left unpowered.
+        assert_eq!(unsafe { gpio.read_raw(0x28) }, 1 << 19);
+    }
+}
@@ -0,0 +1,291 @@
+use crate::{Error, GpioBackend, PinFunction, Pull};
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+
+// udev applies permissions to a freshly exported gpioN directory
+// asynchronously, so it may take a moment to become usable.
+const EXPORT_TIMEOUT: Duration = Duration::from_millis(500);
+const EXPORT_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+// The SoC's pin controllers label their chips "pinctrl-bcm2835",
+// "pinctrl-bcm2711" and so on; the firmware's expander and any add-on
+// chips have labels of their own.
+const SOC_CHIP_LABEL_PREFIX: &str = "pinctrl-";
+
+
+// Where the SoC's pins start in the kernel's global GPIO numbering: the
+// `base` of the `gpiochipN` under `root` labelled as the SoC's, else of the
+// lowest-based chip. Kernels since 6.6 number the SoC's chip from 512, not
+// 0. With no chips listed, as on very old kernels, the pins start at 0.
+fn chip_base(root: &Path) -> u32 {
+    let read = |chip: &Path, name: &str| std::fs::read_to_string(chip.join(name)).ok().map(|value| value.trim().to_string());
+    let mut chips: Vec<(bool, u32)> = match std::fs::read_dir(root) {
+        Ok(entries) => entries.filter_map(|entry| {
+            let chip = entry.ok()?.path();
+            chip.file_name()?.to_str()?.strip_prefix("gpiochip")?;
+            let base = read(&chip, "base")?.parse().ok()?;
+            let soc = read(&chip, "label").is_some_and(|label| label.starts_with(SOC_CHIP_LABEL_PREFIX));
+            Some((!soc, base))
+        }).collect(),
+        Err(_) => Vec::new(),
+    };
+    chips.sort();
+    chips.first().map_or(0, |&(_, base)| base)
+}
+
+
+/// Pin access through the deprecated `/sys/class/gpio` interface. Pins are
+/// exported on first use; the ones exported here are unexported on drop.
+/// sysfs has no bias control. Pins are numbered as on the SoC, offset by
+/// its chip's base for sysfs.
+pub(crate) struct SysfsGpio {
+    root: PathBuf,
+    base: u32,
+    exported: Mutex<HashSet<u32>>,
+    // Levels written to inputs, which sysfs refuses, to start them at when
+    // they're made outputs.
+    pending: Mutex<HashMap<u32, bool>>,
+}
+
+impl SysfsGpio {
+
+    pub(crate) fn open(root: impl Into<PathBuf>) -> Result<Self, Error> {
+        let root = root.into();
+        if !root.join("export").exists() {
+            return Err(Error::Unsupported(format!("{} has no export file", root.display())));
+        }
+        Ok(Self {
+            base: chip_base(&root),
+            root,
+            exported: Mutex::new(HashSet::new()),
+            pending: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn pin_file(&self, pin: u32, name: &str) -> PathBuf {
+        self.root.join(format!("gpio{}", self.base + pin)).join(name)
+    }
+
+    fn write_control(&self, file: PathBuf, value: &str) -> Result<(), Error> {
+        std::fs::write(&file, value)
+            .map_err(|e| Error::from_io(format!("failed to write {:?} to {}", value, file.display()), e))
+    }
+
+    fn ensure_exported(&self, pin: u32) -> Result<(), Error> {
+        let value = self.pin_file(pin, "value");
+        if value.exists() {
+            return Ok(());
+        }
+        self.write_control(self.root.join("export"), &(self.base + pin).to_string())?;
+        self.exported.lock().unwrap().insert(pin);
+
+        let deadline = Instant::now() + EXPORT_TIMEOUT;
+        while std::fs::OpenOptions::new().write(true).open(&value).is_err() {
+            if Instant::now() >= deadline {
+                return Err(Error::Timeout(format!(
+                    "pin {} was exported but {} did not become writable", pin, value.display())));
+            }
+            std::thread::sleep(EXPORT_POLL_INTERVAL);
+        }
+        Ok(())
+    }
+
+    fn read_pin_file(&self, pin: u32, name: &str) -> Result<String, Error> {
+        self.ensure_exported(pin)?;
+        let file = self.pin_file(pin, name);
+        std::fs::read_to_string(&file)
+            .map(|contents| contents.trim().to_string())
+            .map_err(|e| Error::from_io(format!("failed to read {}", file.display()), e))
+    }
+}
+
+impl GpioBackend for SysfsGpio {
+
+    fn function(&self, pin: u32) -> Result<PinFunction, Error> {
+        match self.read_pin_file(pin, "direction")?.as_str() {
+            "out" => Ok(PinFunction::Output),
+            _ => Ok(PinFunction::Input),
+        }
+    }
+
+    fn set_function(&self, pin: u32, function: PinFunction) -> Result<(), Error> {
+        // "high" and "low" make the pin an output already at that level.
+        let direction = match function {
+            PinFunction::Input => "in",
+            PinFunction::Output => match self.pending.lock().unwrap().remove(&pin) {
+                Some(true) => "high",
+                Some(false) => "low",
+                None => "out",
+            },
+            _ => return Err(Error::Unsupported(format!(
+                "cannot set pin {} to {:?}: the sysfs backend only supports Input and Output", pin, function))),
+        };
+        self.ensure_exported(pin)?;
+        self.write_control(self.pin_file(pin, "direction"), direction)
+    }
+
+    // The bias isn't visible through sysfs, so every pin reports no pull.
+    fn pull(&self, _pin: u32) -> Result<Pull, Error> {
+        Ok(Pull::None)
+    }
+
+    fn set_pull(&self, pin: u32, _pull: Pull) -> Result<(), Error> {
+        Err(Error::Unsupported(format!("cannot set the pull on pin {}: the sysfs backend has no bias control", pin)))
+    }
+
+    fn write(&self, pin: u32, level: bool) -> Result<(), Error> {
+        if self.read_pin_file(pin, "direction")? != "out" {
+            self.pending.lock().unwrap().insert(pin, level);
+            return Ok(());
+        }
+        self.write_control(self.pin_file(pin, "value"), if level { "1" } else { "0" })
+    }
+
+    fn read(&self, pin: u32) -> Result<bool, Error> {
+        Ok(self.read_pin_file(pin, "value")? == "1")
+    }
+}
+
+impl Drop for SysfsGpio {
+    fn drop(&mut self) {
+        for pin in self.exported.lock().unwrap().drain() {
+            let _ = std::fs::write(self.root.join("unexport"), (self.base + pin).to_string());
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PinSetup, Soc, GPIO};
+
+    fn fake_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("rustberrypi-sysfs-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("export"), "").unwrap();
+        std::fs::write(root.join("unexport"), "").unwrap();
+        root
+    }
+
+    fn fake_pin(root: &std::path::Path, pin: u32, direction: &str, value: &str) {
+        let dir = root.join(format!("gpio{}", pin));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("direction"), direction).unwrap();
+        std::fs::write(dir.join("value"), value).unwrap();
+    }
+
+    #[test]
+    fn test_sysfs_already_exported_pin() {
+        let root = fake_root("existing");
+        fake_pin(&root, 17, "in\n", "1\n");
+
+        let sysfs = SysfsGpio::open(&root).ok().unwrap();
+        assert_eq!(sysfs.function(17).ok(), Some(PinFunction::Input));
+        assert_eq!(sysfs.read(17).ok(), Some(true));
+        assert!(sysfs.set_function(17, PinFunction::Output).is_ok());
+        assert!(sysfs.write(17, false).is_ok());
+        assert_eq!(sysfs.function(17).ok(), Some(PinFunction::Output));
+        assert_eq!(sysfs.read(17).ok(), Some(false));
+        assert!(sysfs.set_function(17, PinFunction::Alt0).is_err());
+        assert!(sysfs.set_pull(17, Pull::Up).is_err());
+        drop(sysfs);
+
+        let unexport = std::fs::read_to_string(root.join("unexport")).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(unexport, "");
+    }
+
+    #[test]
+    fn test_sysfs_input_keeps_pending_level() {
+        let root = fake_root("pending");
+        fake_pin(&root, 17, "in\n", "0\n");
+
+        let sysfs = SysfsGpio::open(&root).ok().unwrap();
+        assert!(sysfs.write(17, true).is_ok());
+        assert_eq!(std::fs::read_to_string(root.join("gpio17/value")).unwrap(), "0\n");
+        assert!(sysfs.set_function(17, PinFunction::Output).is_ok());
+        assert_eq!(std::fs::read_to_string(root.join("gpio17/direction")).unwrap(), "high");
+        drop(sysfs);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_configure_starts_outputs_at_initial_level() {
+        let root = fake_root("configure");
+        fake_pin(&root, 17, "in\n", "0\n");
+        fake_pin(&root, 22, "in\n", "0\n");
+
+        let gpio = GPIO::from_backend(Box::new(SysfsGpio::open(&root).ok().unwrap()), Soc::Bcm2711);
+        let setups = [
+            PinSetup { pin: 17, function: PinFunction::Output, pull: Pull::None, initial_level: Some(true), edge: None },
+            PinSetup { pin: 22, function: PinFunction::Output, pull: Pull::None, initial_level: Some(false), edge: None },
+        ];
+        assert!(gpio.configure(&setups).is_ok());
+        let direction = |pin: u32| std::fs::read_to_string(root.join(format!("gpio{}/direction", pin))).unwrap();
+        assert_eq!((direction(17).as_str(), direction(22).as_str()), ("high", "low"));
+        assert_eq!(gpio.output_state(17).ok(), Some(Some(true)));
+        drop(gpio);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_sysfs_exports_and_unexports() {
+        let root = fake_root("export");
+        let sysfs = SysfsGpio::open(&root).ok().unwrap();
+
+        let udev_root = root.clone();
+        let udev = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            fake_pin(&udev_root, 5, "in", "0");
+        });
+        assert!(sysfs.set_function(5, PinFunction::Output).is_ok());
+        udev.join().unwrap();
+        assert_eq!(std::fs::read_to_string(root.join("export")).unwrap(), "5");
+        drop(sysfs);
+
+        let unexport = std::fs::read_to_string(root.join("unexport")).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(unexport, "5");
+    }
+
+    #[test]
+    fn test_sysfs_offsets_pins_by_chip_base() {
+        let root = fake_root("base");
+        for &(chip, label) in [(504, "raspberrypi-exp-gpio"), (512, "pinctrl-bcm2711")].iter() {
+            let dir = root.join(format!("gpiochip{}", chip));
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("base"), format!("{}\n", chip)).unwrap();
+            std::fs::write(dir.join("label"), format!("{}\n", label)).unwrap();
+        }
+        assert_eq!(chip_base(&root), 512);
+        fake_pin(&root, 529, "in\n", "1\n");
+
+        let sysfs = SysfsGpio::open(&root).ok().unwrap();
+        assert_eq!(sysfs.read(17).ok(), Some(true));
+        let udev_root = root.clone();
+        let udev = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            fake_pin(&udev_root, 517, "in", "0");
+        });
+        assert!(sysfs.set_function(5, PinFunction::Output).is_ok());
+        udev.join().unwrap();
+        assert_eq!(std::fs::read_to_string(root.join("export")).unwrap(), "517");
+        drop(sysfs);
+
+        let unexport = std::fs::read_to_string(root.join("unexport")).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(unexport, "517");
+        assert_eq!(chip_base(&root), 0);
+    }
+
+    #[test]
+    fn test_sysfs_requires_export_file() {
+        let root = std::env::temp_dir().join(format!("rustberrypi-sysfs-missing-{}", std::process::id()));
+        assert!(SysfsGpio::open(&root).is_err());
+    }
+}
@@ -0,0 +1,125 @@
+use crate::region::MappedRegion;
+use crate::{detect_peripheral_base, Error, DEV_MEM_PATH};
+
+use std::path::Path;
+
+
+const ST_BASE_OFFSET: i64 = 0x3000;
+const ST_BLOCK_LEN: usize = 0x1c;
+
+const ST_CS: usize = 0x00;
+const ST_CLO: usize = 0x04;
+const ST_CHI: usize = 0x08;
+const ST_C0: usize = 0x0c;
+
+const COMPARE_CHANNELS: u32 = 4;
+
+
+/// The SoC's free-running 1 MHz system timer. It counts regardless of
+/// kernel scheduling, so it makes a steadier time base than the clocks
+/// behind `Instant`. Compare channels 0 and 2 are used by the GPU; 1 and 3
+/// are free.
+pub struct SystemTimer {
+    region: MappedRegion,
+}
+
+impl SystemTimer {
+
+    /// Maps the timer through `/dev/mem`.
+    pub fn new() -> Result<Self, Error> {
+        let base = detect_peripheral_base()?;
+        Self::open(DEV_MEM_PATH, base + ST_BASE_OFFSET)
+    }
+
+    pub(crate) fn open(path: impl AsRef<Path>, phys_base: i64) -> Result<Self, Error> {
+        Ok(Self { region: MappedRegion::open(path.as_ref(), phys_base, ST_BLOCK_LEN)? })
+    }
+
+    /// Microseconds since the timer started counting.
+    pub fn now_us(&self) -> Result<u64, Error> {
+        // The halves are separate registers; re-read if the low word
+        // wrapped between them.
+        loop {
+            let high = self.region.read_reg(ST_CHI)?;
+            let low = self.region.read_reg(ST_CLO)?;
+            if self.region.read_reg(ST_CHI)? == high {
+                return Ok((high as u64) << 32 | low as u64);
+            }
+        }
+    }
+
+    /// Spins until `us` microseconds have passed on the timer.
+    pub fn busy_wait_us(&self, us: u64) -> Result<(), Error> {
+        let start = self.now_us()?;
+        while self.now_us()?.wrapping_sub(start) < us {
+            std::hint::spin_loop();
+        }
+        Ok(())
+    }
+
+    fn check_channel(channel: u32) -> Result<(), Error> {
+        if channel >= COMPARE_CHANNELS {
+            return Err(Error::Other(format!("the system timer has no compare channel {}", channel)));
+        }
+        Ok(())
+    }
+
+    /// Arms compare `channel` to match when the low 32 bits of the counter
+    /// reach `value`. Any earlier match is cleared.
+    pub fn set_compare(&self, channel: u32, value: u32) -> Result<(), Error> {
+        Self::check_channel(channel)?;
+        self.region.write_reg(ST_C0 + 4 * channel as usize, value)?;
+        self.clear_match(channel)
+    }
+
+    /// Arms compare `channel` to match `us` microseconds from now.
+    pub fn set_compare_in(&self, channel: u32, us: u32) -> Result<(), Error> {
+        let now = self.region.read_reg(ST_CLO)?;
+        self.set_compare(channel, now.wrapping_add(us))
+    }
+
+    pub fn compare(&self, channel: u32) -> Result<u32, Error> {
+        Self::check_channel(channel)?;
+        self.region.read_reg(ST_C0 + 4 * channel as usize)
+    }
+
+    pub fn has_matched(&self, channel: u32) -> Result<bool, Error> {
+        Self::check_channel(channel)?;
+        Ok(self.region.read_reg(ST_CS)? & (1 << channel) != 0)
+    }
+
+    pub fn clear_match(&self, channel: u32) -> Result<(), Error> {
+        Self::check_channel(channel)?;
+        // Write-1-to-clear.
+        self.region.write_reg(ST_CS, 1 << channel)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_system_timer_registers() {
+        let path = std::env::temp_dir().join(format!("rustberrypi-timer-{}", std::process::id()));
+        std::fs::write(&path, vec![0u8; 4096]).unwrap();
+        let timer = SystemTimer::open(&path, 0).ok().unwrap();
+
+        timer.region.write_reg(ST_CLO, 0xffff_fff0).unwrap();
+        timer.region.write_reg(ST_CHI, 2).unwrap();
+        assert_eq!(timer.now_us().ok(), Some(0x2_ffff_fff0));
+        // Returns straight away even though the file's counter is stopped.
+        assert_eq!(timer.busy_wait_us(0).ok(), Some(()));
+
+        timer.set_compare_in(3, 0x20).unwrap();
+        assert_eq!(timer.compare(3).ok(), Some(0x10));
+        // The file doesn't clear on write, so the clear reads back as a match.
+        assert_eq!(timer.region.read_reg(ST_CS).ok(), Some(1 << 3));
+        assert!(timer.has_matched(3).unwrap());
+        assert!(!timer.has_matched(1).unwrap());
+        assert!(timer.set_compare(4, 0).is_err());
+        drop(timer);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,116 @@
+use crate::{Backend, Register, GPIO};
+
+use std::time::{Duration, Instant};
+
+
+// Sleeping is only accurate to a scheduler tick, so the last stretch before
+// a deadline is spent spinning.
+pub(crate) const SPIN_THRESHOLD: Duration = Duration::from_micros(100);
+
+const CALIBRATION_ROUNDS: u32 = 1000;
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct CalibrationData {
+    pub toggle: Duration,
+    pub read: Duration,
+}
+
+impl CalibrationData {
+
+    /// Reported by backends without real register latency; nothing is
+    /// subtracted from spin targets.
+    pub const NONE: CalibrationData = CalibrationData {
+        toggle: Duration::from_nanos(0),
+        read: Duration::from_nanos(0),
+    };
+
+    // The register write happens after the wait, so finishing the wait early
+    // by the write latency puts the edge on the deadline.
+    pub(crate) fn spin_target(&self, deadline: Instant) -> Instant {
+        deadline.checked_sub(self.toggle).unwrap_or(deadline)
+    }
+}
+
+
+pub(crate) fn wait_until(deadline: Instant) {
+    let now = Instant::now();
+    if deadline > now + SPIN_THRESHOLD {
+        std::thread::sleep(deadline - now - SPIN_THRESHOLD);
+    }
+    while Instant::now() < deadline {
+        std::hint::spin_loop();
+    }
+}
+
+
+impl GPIO {
+
+    /// Measures the average latency of a GPSET/GPCLR write and a GPLEV read.
+    /// The measurement runs on the first call and is cached. Writes use an
+    /// empty mask, so no pin changes state.
+    pub fn calibrate(&self) -> CalibrationData {
+        *self.calibration.get_or_init(|| self.measure_latencies())
+    }
+
+    pub(crate) fn calibration_or_none(&self) -> CalibrationData {
+        self.calibration.get().copied().unwrap_or(CalibrationData::NONE)
+    }
+
+    fn measure_latencies(&self) -> CalibrationData {
+        if !matches!(self.backend, Some(Backend::GpioMem) | Some(Backend::DevMem)) {
+            return CalibrationData::NONE;
+        }
+        let registers = (
+            self.register_ptr(Register::GPSET, 0),
+            self.register_ptr(Register::GPCLR, 0),
+            self.register_ptr(Register::GPLEV, 0),
+        );
+        let (Ok(set), Ok(clear), Ok(level)) = registers else {
+            return CalibrationData::NONE;
+        };
+
+        let start = Instant::now();
+        for _ in 0..CALIBRATION_ROUNDS {
+            unsafe {
+                set.write_volatile(0);
+                clear.write_volatile(0);
+            }
+        }
+        let toggle = start.elapsed() / (2 * CALIBRATION_ROUNDS);
+
+        let start = Instant::now();
+        for _ in 0..CALIBRATION_ROUNDS {
+            unsafe { level.read_volatile() };
+        }
+        let read = start.elapsed() / CALIBRATION_ROUNDS;
+
+        CalibrationData { toggle, read }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calibration_on_mock_is_noop() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        assert_eq!(gpio.calibration_or_none(), CalibrationData::NONE);
+        assert_eq!(gpio.calibrate(), CalibrationData::NONE);
+
+        let deadline = Instant::now() + Duration::from_millis(1);
+        assert_eq!(gpio.calibrate().spin_target(deadline), deadline);
+    }
+
+    #[test]
+    fn test_calibration_adjusts_spin_target() {
+        let measured = CalibrationData {
+            toggle: Duration::from_nanos(120),
+            read: Duration::from_nanos(80),
+        };
+        let deadline = Instant::now() + Duration::from_millis(1);
+        assert_eq!(measured.spin_target(deadline), deadline - Duration::from_nanos(120));
+    }
+}
@@ -0,0 +1,232 @@
+use crate::{Error, GPIO};
+
+use std::collections::VecDeque;
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum RegisterAccess {
+    Read,
+    Write,
+}
+
+
+/// One traced access to the GPIO register block.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct TraceEntry {
+    pub access: RegisterAccess,
+    /// Bytes from the start of the block, e.g. 0x1c for GPSET0.
+    pub offset: usize,
+    pub value: u32,
+    /// Since the trace was started.
+    pub timestamp: Duration,
+}
+
+// One entry per line, as written to trace files: nanoseconds, R or W, the
+// offset and the value.
+impl Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let access = match self.access {
+            RegisterAccess::Read => 'R',
+            RegisterAccess::Write => 'W',
+        };
+        write!(f, "{} {} {:#04x} {:#010x}", self.timestamp.as_nanos(), access, self.offset, self.value)
+    }
+}
+
+impl FromStr for TraceEntry {
+    type Err = Error;
+
+    fn from_str(line: &str) -> Result<Self, Error> {
+        let invalid = || Error::Other(format!("invalid register trace line {:?}", line));
+        let hex = |field: &str| field.strip_prefix("0x").and_then(|digits| u64::from_str_radix(digits, 16).ok());
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 4 {
+            return Err(invalid());
+        }
+        let access = match fields[1] {
+            "R" => RegisterAccess::Read,
+            "W" => RegisterAccess::Write,
+            _ => return Err(invalid()),
+        };
+        Ok(TraceEntry {
+            access,
+            offset: hex(fields[2]).ok_or_else(invalid)? as usize,
+            value: hex(fields[3]).filter(|&value| value <= u32::MAX as u64).ok_or_else(invalid)? as u32,
+            timestamp: Duration::from_nanos(fields[0].parse().map_err(|_| invalid())?),
+        })
+    }
+}
+
+/// Reads back a trace written by `GPIO::start_trace_to_file`.
+pub fn read_trace(path: impl AsRef<Path>) -> Result<Vec<TraceEntry>, Error> {
+    let path = path.as_ref();
+    let file = File::open(path)
+        .map_err(|e| Error::from_io(format!("failed to open {}", path.display()), e))?;
+    BufReader::new(file).lines()
+        .map(|line| line.map_err(|e| Error::from_io(format!("failed to read {}", path.display()), e)))
+        .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|line| line?.parse())
+        .collect()
+}
+
+
+struct Recording {
+    started: Instant,
+    capacity: usize,
+    entries: VecDeque<TraceEntry>,
+    file: Option<BufWriter<File>>,
+    // The first failure writing to `file`, after which it's dropped.
+    file_error: Option<std::io::Error>,
+}
+
+/// Where a register backend and its `GPIO` record accesses. Shared between
+/// the two, as both touch the block.
+#[derive(Default)]
+pub(crate) struct RegisterTrace {
+    enabled: AtomicBool,
+    recording: Mutex<Option<Recording>>,
+}
+
+impl RegisterTrace {
+
+    pub(crate) fn record(&self, access: RegisterAccess, offset: usize, value: u32) {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut recording = self.recording.lock().unwrap();
+        let recording = match recording.as_mut() {
+            Some(recording) => recording,
+            None => return,
+        };
+        let entry = TraceEntry { access, offset, value, timestamp: recording.started.elapsed() };
+        if let Some(file) = recording.file.as_mut() {
+            if let Err(e) = writeln!(file, "{}", entry) {
+                recording.file = None;
+                recording.file_error = Some(e);
+            }
+        }
+        if recording.capacity > 0 {
+            if recording.entries.len() == recording.capacity {
+                recording.entries.pop_front();
+            }
+            recording.entries.push_back(entry);
+        }
+    }
+
+    fn start(&self, capacity: usize, file: Option<File>) {
+        *self.recording.lock().unwrap() = Some(Recording {
+            started: Instant::now(),
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+            file: file.map(BufWriter::new),
+            file_error: None,
+        });
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+}
+
+
+impl GPIO {
+
+    /// Starts recording register accesses, keeping the latest `capacity`
+    /// in memory. Any earlier trace is discarded. Only accesses made
+    /// through the register backend and `read_register`/`write_register`
+    /// are seen: the raw word accessors, `Pin::fast_path` handles, and
+    /// background threads writing straight through register pointers (soft
+    /// PWM, steppers, square waves, the edge poller), aren't traced.
+    pub fn start_trace(&self, capacity: usize) {
+        self.trace.start(capacity, None);
+    }
+
+    /// Like `start_trace`, also writing every access to `path` as a line of
+    /// text that `read_trace` can load. `capacity` may be 0 to only write
+    /// the file.
+    pub fn start_trace_to_file(&self, capacity: usize, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        let file = File::create(path)
+            .map_err(|e| Error::from_io(format!("failed to create {}", path.display()), e))?;
+        self.trace.start(capacity, Some(file));
+        Ok(())
+    }
+
+    /// The entries held in memory so far, oldest first.
+    pub fn trace_entries(&self) -> Vec<TraceEntry> {
+        self.trace.recording.lock().unwrap().as_ref()
+            .map(|recording| recording.entries.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Stops recording and returns the entries held in memory. Fails if the
+    /// trace file couldn't be written.
+    pub fn stop_trace(&self) -> Result<Vec<TraceEntry>, Error> {
+        self.trace.enabled.store(false, Ordering::Relaxed);
+        let recording = match self.trace.recording.lock().unwrap().take() {
+            Some(recording) => recording,
+            None => return Ok(Vec::new()),
+        };
+        let mut file_error = recording.file_error;
+        if let Some(mut file) = recording.file {
+            file_error = file.flush().err().or(file_error);
+        }
+        if let Some(e) = file_error {
+            return Err(Error::from_io("failed to write the register trace", e));
+        }
+        Ok(recording.entries.into_iter().collect())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PinFunction, Register};
+
+    #[test]
+    fn test_trace_entry_lines() {
+        let entry = TraceEntry {
+            access: RegisterAccess::Write,
+            offset: 0x1c,
+            value: 1 << 17,
+            timestamp: Duration::from_micros(1500),
+        };
+        assert_eq!(entry.to_string(), "1500000 W 0x1c 0x00020000");
+        assert_eq!(entry.to_string().parse::<TraceEntry>().ok(), Some(entry));
+        assert!("1500000 X 0x1c 0x00020000".parse::<TraceEntry>().is_err());
+        assert!("1500000 R 0x1c 0x100000000".parse::<TraceEntry>().is_err());
+        assert!("R 0x1c 0x0".parse::<TraceEntry>().is_err());
+    }
+
+    #[test]
+    fn test_register_trace() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        gpio.set_high(4).unwrap();
+        assert!(gpio.trace_entries().is_empty());
+
+        let path = std::env::temp_dir().join(format!("rustberrypi-trace-{}", std::process::id()));
+        gpio.start_trace_to_file(2, &path).unwrap();
+        gpio.set_function(17, PinFunction::Output).unwrap();
+        gpio.set_high(17).unwrap();
+        let entries = gpio.stop_trace().unwrap();
+        let accesses: Vec<_> = entries.iter().map(|entry| (entry.access, entry.offset, entry.value)).collect();
+        assert_eq!(accesses.last(), Some(&(RegisterAccess::Write, Register::GPSET as usize, 1 << 17)));
+        assert_eq!(entries.len(), 2);
+
+        let from_file = read_trace(&path).unwrap();
+        assert!(from_file.len() > entries.len());
+        assert!(from_file.iter().any(|entry| {
+            entry.access == RegisterAccess::Write && entry.offset == Register::GPFSEL as usize + 4 && entry.value == 1 << 21
+        }));
+        assert!(from_file.ends_with(&entries));
+        gpio.set_low(17).unwrap();
+        assert!(gpio.trace_entries().is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,194 @@
+use crate::region::MappedRegion;
+use crate::{detect_peripheral_base, open_file, Error, DEV_MEM_PATH};
+
+use std::fs::File;
+use std::io::Write;
+use std::os::raw::c_int;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+
+const DEV_WATCHDOG_PATH: &str = "/dev/watchdog";
+
+// The power manager, whose block the pad controls are also in.
+const PM_BASE_OFFSET: i64 = 0x100000;
+const PM_BLOCK_LEN: usize = 0x28;
+const PM_RSTC: usize = 0x1c;
+const PM_WDOG: usize = 0x24;
+
+// As with the pad controls, writes without this in the top byte are
+// ignored.
+const PM_PASSWORD: u32 = 0x5a << 24;
+const PM_WDOG_TIME_SET: u32 = 0xfffff;
+const PM_RSTC_WRCFG_CLR: u32 = 0xffff_ffcf;
+const PM_RSTC_WRCFG_FULL_RESET: u32 = 0x20;
+const PM_RSTC_RESET: u32 = 0x102;
+
+// The watchdog counts down at 65536 Hz.
+const PM_WDOG_TICKS_PER_SECOND: u64 = 1 << 16;
+
+// From linux/watchdog.h.
+nix::ioctl_read!(wdioc_keepalive, b'W', 5, c_int);
+nix::ioctl_readwrite!(wdioc_settimeout, b'W', 6, c_int);
+
+// Written before closing /dev/watchdog to stop it rather than let it fire.
+const MAGIC_CLOSE: &[u8] = b"V";
+
+
+enum Driver {
+    Registers(MappedRegion),
+    // /dev/watchdog starts counting as soon as it's opened, so it's only
+    // opened by `start`.
+    Device { path: PathBuf, file: Option<File> },
+}
+
+
+/// The SoC's watchdog, which resets the board if it isn't fed within its
+/// timeout, so an unattended controller whose control loop wedges
+/// reboots rather than sitting with its outputs stuck. Dropping the
+/// `Watchdog` doesn't stop it, so a crashed process is caught too; call
+/// `stop` on a clean exit.
+pub struct Watchdog {
+    driver: Driver,
+    timeout: Option<Duration>,
+}
+
+impl Watchdog {
+
+    /// The longest timeout the hardware can count, just under 16 s.
+    pub const MAX_TIMEOUT: Duration = Duration::from_micros(PM_WDOG_TIME_SET as u64 * 1_000_000 / PM_WDOG_TICKS_PER_SECOND);
+
+    /// Drives the watchdog's registers through `/dev/mem`, or the kernel's
+    /// `/dev/watchdog` if they can't be mapped.
+    pub fn new() -> Result<Self, Error> {
+        match detect_peripheral_base().and_then(|base| Self::open(DEV_MEM_PATH, base + PM_BASE_OFFSET)) {
+            Ok(watchdog) => Ok(watchdog),
+            Err(_) if Path::new(DEV_WATCHDOG_PATH).exists() => Ok(Self::open_device(DEV_WATCHDOG_PATH)),
+            Err(error) => Err(error),
+        }
+    }
+
+    pub(crate) fn open(path: impl AsRef<Path>, phys_base: i64) -> Result<Self, Error> {
+        let region = MappedRegion::open(path.as_ref(), phys_base, PM_BLOCK_LEN)?;
+        Ok(Self { driver: Driver::Registers(region), timeout: None })
+    }
+
+    /// Drives the kernel's watchdog device at `path`, e.g. `/dev/watchdog`,
+    /// which works where `/dev/mem` isn't allowed and doesn't fight the
+    /// kernel's driver over the registers.
+    pub fn open_device(path: impl Into<PathBuf>) -> Self {
+        Self { driver: Driver::Device { path: path.into(), file: None }, timeout: None }
+    }
+
+    fn check_timeout(timeout: Duration) -> Result<(), Error> {
+        if timeout.is_zero() || timeout > Self::MAX_TIMEOUT {
+            return Err(Error::Other(format!(
+                "watchdog timeout must be more than 0 and at most {:?}, not {:?}", Self::MAX_TIMEOUT, timeout)));
+        }
+        Ok(())
+    }
+
+    // Reloads the countdown with `timeout` and arms the reset.
+    fn load(region: &MappedRegion, timeout: Duration) -> Result<(), Error> {
+        let ticks = (timeout.as_micros() as u64 * PM_WDOG_TICKS_PER_SECOND / 1_000_000) as u32;
+        region.write_reg(PM_WDOG, PM_PASSWORD | ticks & PM_WDOG_TIME_SET)?;
+        let rstc = region.read_reg(PM_RSTC)?;
+        region.write_reg(PM_RSTC, PM_PASSWORD | rstc & PM_RSTC_WRCFG_CLR | PM_RSTC_WRCFG_FULL_RESET)
+    }
+
+    /// Starts the watchdog, or changes its timeout if it's running. The
+    /// board resets unless `feed` is called within `timeout` of this and
+    /// of each feed after. The kernel's device counts in whole seconds, so
+    /// `timeout` is rounded up there.
+    pub fn start(&mut self, timeout: Duration) -> Result<(), Error> {
+        Self::check_timeout(timeout)?;
+        match &mut self.driver {
+            Driver::Registers(region) => Self::load(region, timeout)?,
+            Driver::Device { path, file } => {
+                let device = match file.take() {
+                    Some(device) => device,
+                    None => open_file(path.clone())?,
+                };
+                let mut seconds = timeout.as_secs_f64().ceil() as c_int;
+                let result = unsafe { wdioc_settimeout(device.as_raw_fd(), &mut seconds) };
+                *file = Some(device);
+                result.map_err(|e| Error::from_nix(format!("failed to set the timeout of {}", path.display()), e))?;
+            }
+        }
+        self.timeout = Some(timeout);
+        Ok(())
+    }
+
+    /// Restarts the countdown. Fails if the watchdog isn't running.
+    pub fn feed(&self) -> Result<(), Error> {
+        let timeout = self.timeout.ok_or_else(|| Error::Other("the watchdog isn't running".to_string()))?;
+        match &self.driver {
+            Driver::Registers(region) => Self::load(region, timeout),
+            Driver::Device { path, file } => {
+                let device = file.as_ref().ok_or_else(|| Error::Other("the watchdog isn't running".to_string()))?;
+                let mut unused = 0;
+                unsafe { wdioc_keepalive(device.as_raw_fd(), &mut unused) }
+                    .map(|_| ())
+                    .map_err(|e| Error::from_nix(format!("failed to feed {}", path.display()), e))
+            }
+        }
+    }
+
+    /// Stops the watchdog. A kernel built with `nowayout` won't stop its
+    /// device once started, and the board resets after the timeout anyway.
+    pub fn stop(&mut self) -> Result<(), Error> {
+        match &mut self.driver {
+            Driver::Registers(region) => region.write_reg(PM_RSTC, PM_PASSWORD | PM_RSTC_RESET)?,
+            Driver::Device { path, file } => {
+                if let Some(mut device) = file.take() {
+                    device.write_all(MAGIC_CLOSE)
+                        .map_err(|e| Error::from_io(format!("failed to stop {}", path.display()), e))?;
+                }
+            }
+        }
+        self.timeout = None;
+        Ok(())
+    }
+
+    /// The timeout the watchdog was started with, while it's running.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watchdog_registers() {
+        let path = std::env::temp_dir().join(format!("rustberrypi-watchdog-{}", std::process::id()));
+        std::fs::write(&path, vec![0u8; 4096]).unwrap();
+        let mut watchdog = Watchdog::open(&path, 0).ok().unwrap();
+        // A second mapping of the file sees the watchdog's writes.
+        let region = MappedRegion::open(&path, 0, PM_BLOCK_LEN).ok().unwrap();
+        let read = |offset| region.read_reg(offset).ok();
+
+        assert!(watchdog.feed().is_err());
+        assert!(watchdog.start(Duration::from_secs(16)).is_err());
+        assert!(watchdog.start(Duration::ZERO).is_err());
+        region.write_reg(PM_RSTC, 0x30).unwrap();
+        watchdog.start(Duration::from_secs(10)).unwrap();
+        assert_eq!(read(PM_WDOG), Some(0x5a00_0000 | 10 << 16));
+        assert_eq!(read(PM_RSTC), Some(0x5a00_0000 | PM_RSTC_WRCFG_FULL_RESET));
+        region.write_reg(PM_WDOG, 0).unwrap();
+        watchdog.feed().unwrap();
+        assert_eq!(read(PM_WDOG), Some(0x5a00_0000 | 10 << 16));
+        assert_eq!(watchdog.timeout(), Some(Duration::from_secs(10)));
+
+        watchdog.stop().unwrap();
+        assert_eq!(read(PM_RSTC), Some(0x5a00_0000 | PM_RSTC_RESET));
+        assert!(watchdog.feed().is_err());
+        drop(watchdog);
+        drop(region);
+        std::fs::remove_file(&path).unwrap();
+        assert!(Watchdog::MAX_TIMEOUT < Duration::from_secs(16) && Watchdog::MAX_TIMEOUT > Duration::from_millis(15_990));
+    }
+}
@@ -0,0 +1,295 @@
+use crate::gpiochip::read_edge_event;
+use crate::{bit_in_bank, check_pin, EdgeKind, Error, PinEvent, PinFunction, Register, Trigger, GPIO};
+
+use nix::sys::epoll::{epoll_create1, epoll_ctl, epoll_wait, EpollCreateFlags, EpollEvent, EpollFlags, EpollOp};
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+
+// How many line fds one epoll_wait reports at most; the rest are reported
+// by the next.
+const MAX_EPOLL_EVENTS: usize = 64;
+
+// How long `run` waits between checks of its stop flag.
+const RUN_SLICE: Duration = Duration::from_millis(50);
+
+
+enum Source {
+    // Holds the enable registers this watch turned on.
+    Registers(Vec<Register>),
+    Line(File),
+}
+
+struct Watch<'h> {
+    trigger: Trigger,
+    handler: Box<dyn FnMut(PinEvent) + 'h>,
+    source: Source,
+}
+
+
+/// Watches many input pins from the calling thread, for keypads and banks
+/// of limit switches where a thread or callback per pin doesn't scale.
+/// Line backends wait on all the pins' line fds with one epoll; the
+/// register backend reads each GPEDS bank once per poll interval, however
+/// many of its pins are watched. Handlers run on the thread calling `poll`
+/// or `run`, so they may borrow from it and needn't be `Send`.
+///
+/// A pin shouldn't also have an `on_edge` callback, which would take its
+/// events. Watches are removed when the `Watcher` is dropped.
+pub struct Watcher<'a, 'h> {
+    gpio: &'a GPIO,
+    watches: BTreeMap<u32, Watch<'h>>,
+    epoll: Option<RawFd>,
+    poll_interval: Duration,
+}
+
+impl<'a, 'h> Watcher<'a, 'h> {
+
+    pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+    pub fn new(gpio: &'a GPIO) -> Self {
+        Self { gpio, watches: BTreeMap::new(), epoll: None, poll_interval: Self::DEFAULT_POLL_INTERVAL }
+    }
+
+    /// How often GPEDS is read on the register backend. Shorter catches
+    /// edges sooner at the cost of CPU time; edges in between aren't lost,
+    /// but two on the same pin count as one.
+    pub fn set_poll_interval(&mut self, interval: Duration) {
+        self.poll_interval = interval;
+    }
+
+    pub fn poll_interval(&self) -> Duration {
+        self.poll_interval
+    }
+
+    fn epoll(&mut self) -> Result<RawFd, Error> {
+        if let Some(epoll) = self.epoll {
+            return Ok(epoll);
+        }
+        let epoll = epoll_create1(EpollCreateFlags::EPOLL_CLOEXEC)
+            .map_err(|e| Error::from_nix("failed to create an epoll instance", e))?;
+        self.epoll = Some(epoll);
+        Ok(epoll)
+    }
+
+    /// Calls `handler` with each `trigger` edge on `pin`. A pin can be
+    /// watched once at a time.
+    pub fn watch(&mut self, pin: u32, trigger: Trigger, handler: impl FnMut(PinEvent) + 'h) -> Result<(), Error> {
+        check_pin(pin)?;
+        self.gpio.check_writable()?;
+        if self.watches.contains_key(&pin) {
+            return Err(Error::PinInUse(pin));
+        }
+        let source = if self.gpio.buffer.is_null() {
+            let file = self.gpio.driver.edge_event_file(pin, trigger)?;
+            let mut event = EpollEvent::new(EpollFlags::EPOLLIN, pin as u64);
+            epoll_ctl(self.epoll()?, EpollOp::EpollCtlAdd, file.as_raw_fd(), &mut event)
+                .map_err(|e| Error::from_nix(format!("failed to watch pin {}", pin), e))?;
+            Source::Line(file)
+        } else {
+            let enabled = self.gpio.enable_wait_triggers(pin, trigger)?;
+            // Only edges after the watch starts count.
+            if self.gpio.poll_event(pin)? {
+                self.gpio.clear_event(pin)?;
+            }
+            Source::Registers(enabled)
+        };
+        self.watches.insert(pin, Watch { trigger, handler: Box::new(handler), source });
+        Ok(())
+    }
+
+    /// Stops watching `pin` and turns off the edge detection its watch
+    /// enabled. Returns whether it was watched.
+    pub fn unwatch(&mut self, pin: u32) -> Result<bool, Error> {
+        let Some(watch) = self.watches.remove(&pin) else {
+            return Ok(false);
+        };
+        match watch.source {
+            Source::Registers(enabled) => self.gpio.disable_wait_triggers(pin, &enabled)?,
+            // Closing the fd takes it out of the epoll set, and setting the
+            // function reconfigures the line without edge flags.
+            Source::Line(file) => {
+                drop(file);
+                self.gpio.driver.set_function(pin, PinFunction::Input)?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// The watched pins, in order.
+    pub fn pins(&self) -> Vec<u32> {
+        self.watches.keys().copied().collect()
+    }
+
+    /// Waits up to `timeout`, or forever if `None`, for edges on the
+    /// watched pins, and runs the handlers for those that arrive. Returns
+    /// how many edges were handled, which is 0 on timeout. Debouncing set
+    /// with `GPIO::set_debounce` applies.
+    pub fn poll(&mut self, timeout: Option<Duration>) -> Result<usize, Error> {
+        if self.watches.is_empty() {
+            return Err(Error::Other("the watcher has no pins to watch".to_string()));
+        }
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let handled = if self.gpio.buffer.is_null() {
+                self.poll_lines(deadline)?
+            } else {
+                self.poll_registers()?
+            };
+            if handled > 0 || deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Ok(handled);
+            }
+            if !self.gpio.buffer.is_null() {
+                let remaining = deadline.map_or(self.poll_interval, |deadline| deadline - Instant::now());
+                std::thread::sleep(self.poll_interval.min(remaining));
+            }
+        }
+    }
+
+    /// Handles edges until `stop` is set, checking it at least every 50 ms.
+    pub fn run(&mut self, stop: &AtomicBool) -> Result<(), Error> {
+        while !stop.load(Ordering::SeqCst) {
+            self.poll(Some(RUN_SLICE))?;
+        }
+        Ok(())
+    }
+
+    fn dispatch(gpio: &GPIO, watch: &mut Watch<'h>, event: PinEvent) -> usize {
+        if !gpio.debounce.accept(event.pin, event.timestamp) {
+            return 0;
+        }
+        (watch.handler)(event);
+        1
+    }
+
+    // One read of GPEDS per bank with a watched pin, and of GPLEV if a
+    // pin in it is watched for both edges.
+    fn poll_registers(&mut self) -> Result<usize, Error> {
+        let mut banks: BTreeMap<u32, (u32, Option<u32>)> = BTreeMap::new();
+        let seen = Instant::now();
+        let mut handled = 0;
+        for (&pin, watch) in self.watches.iter_mut() {
+            let bank = pin - bit_in_bank(pin);
+            let (events, level) = match banks.get(&bank) {
+                Some(&read) => read,
+                None => {
+                    let events = self.gpio.read_register(Register::GPEDS, pin)?;
+                    banks.insert(bank, (events, None));
+                    (events, None)
+                }
+            };
+            let bit = 1 << bit_in_bank(pin);
+            if events & bit == 0 {
+                continue;
+            }
+            // Other pins' events in the bank are left for whoever's
+            // waiting on them.
+            self.gpio.clear_event(pin)?;
+            let edge = match watch.trigger {
+                Trigger::Rising => EdgeKind::Rising,
+                Trigger::Falling => EdgeKind::Falling,
+                Trigger::Both => {
+                    let level = match level {
+                        Some(level) => level,
+                        None => {
+                            let level = self.gpio.read_register(Register::GPLEV, pin)?;
+                            banks.insert(bank, (events, Some(level)));
+                            level
+                        }
+                    };
+                    if level & bit != 0 { EdgeKind::Rising } else { EdgeKind::Falling }
+                }
+            };
+            handled += Self::dispatch(self.gpio, watch, PinEvent { pin, edge, timestamp: seen });
+        }
+        Ok(handled)
+    }
+
+    fn poll_lines(&mut self, deadline: Option<Instant>) -> Result<usize, Error> {
+        let epoll = self.epoll()?;
+        let timeout_ms = match deadline {
+            None => -1,
+            Some(deadline) => deadline.saturating_duration_since(Instant::now()).as_millis() as isize,
+        };
+        let mut ready = [EpollEvent::empty(); MAX_EPOLL_EVENTS];
+        let count = match epoll_wait(epoll, &mut ready, timeout_ms) {
+            Ok(count) => count,
+            Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => 0,
+            Err(e) => return Err(Error::from_nix("failed to wait for edges", e)),
+        };
+        let mut handled = 0;
+        for ready in &ready[..count] {
+            let pin = ready.data() as u32;
+            let Some(watch) = self.watches.get_mut(&pin) else { continue };
+            let Source::Line(file) = &watch.source else { continue };
+            // The fd is non-blocking, so this drains what's queued.
+            let mut events = Vec::new();
+            loop {
+                match read_edge_event(file) {
+                    Ok(Some(event)) => events.push(event),
+                    Ok(None) => continue,
+                    Err(_) => break,
+                }
+            }
+            for event in events {
+                handled += Self::dispatch(self.gpio, watch, event);
+            }
+        }
+        Ok(handled)
+    }
+}
+
+impl<'a, 'h> Drop for Watcher<'a, 'h> {
+    fn drop(&mut self) {
+        for pin in self.pins() {
+            let _ = self.unwatch(pin);
+        }
+        if let Some(epoll) = self.epoll.take() {
+            let _ = nix::unistd::close(epoll);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watcher_dispatches_register_events() {
+        let gpio = GPIO::open_for_testing_on(Vec::new());
+        let mut edges = Vec::new();
+        {
+            let mut watcher = Watcher::new(&gpio);
+            assert!(watcher.poll(Some(Duration::ZERO)).is_err());
+            watcher.watch(40, Trigger::Both, |event| edges.push((event.pin, event.edge))).unwrap();
+            watcher.watch(41, Trigger::Falling, |_| panic!("pin 41 has no edge")).unwrap();
+            assert!(matches!(watcher.watch(40, Trigger::Rising, |_| {}), Err(Error::PinInUse(40))));
+            assert_eq!(watcher.pins(), vec![40, 41]);
+            assert_eq!(gpio.read_register(Register::GPREN, 40).ok(), Some(1 << 8));
+            assert_eq!(gpio.read_register(Register::GPFEN, 40).ok(), Some(1 << 8 | 1 << 9));
+
+            assert_eq!(watcher.poll(Some(Duration::from_millis(5))).ok(), Some(0));
+            unsafe {
+                gpio.write_raw(0x38, 1 << 8);
+                gpio.write_raw(0x44, 1 << 8);
+            }
+            assert_eq!(watcher.poll(None).ok(), Some(1));
+            assert_eq!(watcher.unwatch(41).ok(), Some(true));
+            assert_eq!(watcher.unwatch(41).ok(), Some(false));
+            assert_eq!(gpio.read_register(Register::GPFEN, 40).ok(), Some(1 << 8));
+        }
+        assert_eq!(edges, vec![(40, EdgeKind::Rising)]);
+        assert_eq!(gpio.read_register(Register::GPREN, 40).ok(), Some(0));
+        assert_eq!(gpio.read_register(Register::GPFEN, 40).ok(), Some(0));
+
+        let mut watcher = Watcher::new(&gpio);
+        watcher.watch(4, Trigger::Rising, |_| {}).unwrap();
+        let stop = AtomicBool::new(true);
+        assert!(watcher.run(&stop).is_ok());
+    }
+}
@@ -0,0 +1,425 @@
+use crate::clock::{Clock, ClockManager, ClockSource, Mash};
+use crate::dma::{
+    ControlBlock, DmaChannel, DmaMemory, CONTROL_BLOCK_SIZE, DREQ_PCM_TX, DREQ_PWM, MAX_TRANSFER_LEN, PERIPHERAL_BUS_BASE,
+    TI_DEST_DREQ, TI_DEST_INC, TI_NO_WIDE_BURSTS, TI_PERMAP_SHIFT, TI_SRC_INC, TI_WAIT_RESP,
+};
+use crate::pcm::{Pcm, PCM_BASE_OFFSET, PCM_FIFO};
+use crate::pwm::{
+    CTL_CLRF, CTL_MODE_SERIALISER, CTL_PWEN, CTL_USEF, DMAC_ENAB, DMAC_PANIC_SHIFT, PWM_BASE_OFFSET, PWM_BLOCK_LEN,
+    PWM_CTL, PWM_DMAC, PWM_FIF1, PWM_RNG1,
+};
+use crate::region::MappedRegion;
+use crate::{
+    check_pin_mask, detect_peripheral_base, pins_in, Error, PinChange, Register, GPIO, DEV_MEM_PATH, GPIO_BASE_OFFSET,
+};
+
+use std::time::Duration;
+
+
+// The PWM serialiser shifts out RNG1 bits per FIFO word, and the PCM
+// block a frame of that many bits; at 10 MHz, ten bits take a microsecond,
+// which paces each delay word.
+const PACING_CLOCK_HZ: u32 = 10_000_000;
+const PACING_BITS_PER_US: u32 = 10;
+
+// Both GPSET words, or both GPCLR words, in one transfer.
+const MASK_TRANSFER_LEN: u32 = 8;
+
+// Data area: one dummy word fed to the FIFO, then set0, set1, clear0 and
+// clear1 for each pulse.
+const DATA_WORDS_PER_PULSE: u32 = 4;
+
+
+/// One step of a waveform: drive the `set` pins high and the `clear` pins
+/// low at the same moment, then hold for `delay_us` microseconds.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Pulse {
+    pub set: u64,
+    pub clear: u64,
+    pub delay_us: u32,
+}
+
+/// A timed sequence of pulses, played back by `WaveEngine`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Wave {
+    pulses: Vec<Pulse>,
+    // Every pulse gets its GPSET and GPCLR transfers, even with empty
+    // masks, so the masks can be rewritten while the wave plays.
+    fixed_masks: bool,
+}
+
+impl Wave {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn pulse(mut self, set: u64, clear: u64, delay_us: u32) -> Self {
+        self.pulses.push(Pulse { set, clear, delay_us });
+        self
+    }
+
+    pub(crate) fn with_fixed_masks(mut self) -> Self {
+        self.fixed_masks = true;
+        self
+    }
+
+    pub fn pulses(&self) -> &[Pulse] {
+        &self.pulses
+    }
+
+    /// How long one pass of the wave takes.
+    pub fn duration(&self) -> Duration {
+        Duration::from_micros(self.pulses.iter().map(|pulse| pulse.delay_us as u64).sum())
+    }
+
+    /// Every pin the wave drives.
+    pub fn pins(&self) -> u64 {
+        self.pulses.iter().fold(0, |pins, pulse| pins | pulse.set | pulse.clear)
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if self.pulses.is_empty() {
+            return Err(Error::Other("a wave needs at least one pulse".to_string()));
+        }
+        for pulse in &self.pulses {
+            check_pin_mask(pulse.set)?;
+            check_pin_mask(pulse.clear)?;
+            if pulse.set & pulse.clear != 0 {
+                return Err(Error::Other(format!(
+                    "pins {:#x} are both set and cleared in one pulse", pulse.set & pulse.clear)));
+            }
+        }
+        Ok(())
+    }
+}
+
+
+/// The peripheral whose FIFO paces a `WaveEngine`'s delays. Whichever is
+/// picked is taken over by the engine, so the other stays free for
+/// `HwPwm` or audio.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Pacing {
+    Pwm,
+    Pcm,
+}
+
+impl Pacing {
+
+    fn fifo_bus_address(self) -> u32 {
+        match self {
+            Pacing::Pwm => PERIPHERAL_BUS_BASE + (PWM_BASE_OFFSET as u32) + PWM_FIF1 as u32,
+            Pacing::Pcm => PERIPHERAL_BUS_BASE + (PCM_BASE_OFFSET as u32) + PCM_FIFO as u32,
+        }
+    }
+
+    fn dreq(self) -> u32 {
+        match self {
+            Pacing::Pwm => DREQ_PWM,
+            Pacing::Pcm => DREQ_PCM_TX,
+        }
+    }
+
+    fn clock(self) -> Clock {
+        match self {
+            Pacing::Pwm => Clock::Pwm,
+            Pacing::Pcm => Clock::Pcm,
+        }
+    }
+}
+
+
+enum Pacer {
+    Pwm(MappedRegion),
+    // Disabled altogether when dropped.
+    Pcm(Pcm),
+}
+
+
+// The control blocks and data for a wave, laid out from `bus_base`: blocks
+// first, then the data words they read from.
+#[derive(Debug)]
+struct Program {
+    blocks: Vec<ControlBlock>,
+    data: Vec<u32>,
+}
+
+impl Program {
+
+    fn compile(wave: &Wave, bus_base: u32, repeat: bool, pacing: Pacing) -> Program {
+        let mut blocks = Vec::new();
+        let mut data = vec![0];
+        // Sources are data indices until the block count is known.
+        for pulse in wave.pulses() {
+            let first_word = data.len() as u32;
+            data.extend_from_slice(&[pulse.set as u32, (pulse.set >> 32) as u32, pulse.clear as u32, (pulse.clear >> 32) as u32]);
+            for &(mask, register, word) in [(pulse.set, Register::GPSET, 0), (pulse.clear, Register::GPCLR, 2)].iter() {
+                if mask != 0 || wave.fixed_masks {
+                    blocks.push(ControlBlock {
+                        transfer_info: TI_NO_WIDE_BURSTS | TI_WAIT_RESP | TI_SRC_INC | TI_DEST_INC,
+                        source: first_word + word,
+                        destination: PERIPHERAL_BUS_BASE + (GPIO_BASE_OFFSET as u32) + register as u32,
+                        length: MASK_TRANSFER_LEN,
+                        ..ControlBlock::default()
+                    });
+                }
+            }
+            let mut remaining = pulse.delay_us as u64 * 4;
+            while remaining > 0 {
+                let length = remaining.min(MAX_TRANSFER_LEN as u64) as u32;
+                blocks.push(ControlBlock {
+                    transfer_info: TI_NO_WIDE_BURSTS | TI_WAIT_RESP | TI_DEST_DREQ | pacing.dreq() << TI_PERMAP_SHIFT,
+                    source: 0,
+                    destination: pacing.fifo_bus_address(),
+                    length,
+                    ..ControlBlock::default()
+                });
+                remaining -= length as u64;
+            }
+        }
+        debug_assert_eq!(data.len() as u32, 1 + DATA_WORDS_PER_PULSE * wave.pulses().len() as u32);
+
+        let data_base = bus_base + CONTROL_BLOCK_SIZE * blocks.len() as u32;
+        let count = blocks.len();
+        for (index, block) in blocks.iter_mut().enumerate() {
+            block.source = data_base + 4 * block.source;
+            block.next = if index + 1 < count {
+                bus_base + CONTROL_BLOCK_SIZE * (index as u32 + 1)
+            } else if repeat {
+                bus_base
+            } else {
+                0
+            };
+        }
+        Program { blocks, data }
+    }
+
+    fn size(&self) -> u32 {
+        CONTROL_BLOCK_SIZE * self.blocks.len() as u32 + 4 * self.data.len() as u32
+    }
+}
+
+
+/// Plays `Wave`s through the DMA controller, which writes GPSET/GPCLR on
+/// its own while the PWM or PCM controller paces the delays, so timing
+/// holds to about a microsecond whatever the CPU is doing. It takes over
+/// the pacing controller and its clock: with PWM pacing, the default, it
+/// can't be used alongside `HwPwm`, and with PCM pacing not alongside
+/// `Pcm` or the kernel's I2S audio. Needs
+/// `/dev/mem` and `/dev/vcio`, which usually means root. DMA writes bypass
+/// the `GPIO`'s record of driven levels.
+pub struct WaveEngine<'a> {
+    gpio: &'a GPIO,
+    dma: DmaChannel,
+    pacing: Pacing,
+    pacer: Pacer,
+    memory: Option<DmaMemory>,
+    // Where the playing wave's data words start in `memory`, and its pulse
+    // count if it was built with fixed masks.
+    data_offset: usize,
+    fixed_pulses: Option<usize>,
+}
+
+impl<'a> WaveEngine<'a> {
+
+    /// A channel that's free on every model so far.
+    pub const DEFAULT_DMA_CHANNEL: u32 = 10;
+
+    pub fn new(gpio: &'a GPIO) -> Result<Self, Error> {
+        Self::with_channel(gpio, Self::DEFAULT_DMA_CHANNEL)
+    }
+
+    pub fn with_channel(gpio: &'a GPIO, dma_channel: u32) -> Result<Self, Error> {
+        Self::with_pacing(gpio, dma_channel, Pacing::Pwm)
+    }
+
+    pub fn with_pacing(gpio: &'a GPIO, dma_channel: u32, pacing: Pacing) -> Result<Self, Error> {
+        gpio.check_writable()?;
+        let base = detect_peripheral_base()?;
+        let dma = DmaChannel::open(base, dma_channel)?;
+
+        let clocks = ClockManager::new()?;
+        let source_hz = ClockSource::PllD.frequency_hz(gpio.soc()).unwrap_or(500_000_000);
+        clocks.configure(pacing.clock(), ClockSource::PllD, source_hz / PACING_CLOCK_HZ, 0, Mash::Integer)?;
+
+        let pacer = match pacing {
+            Pacing::Pwm => {
+                let pwm = MappedRegion::open(DEV_MEM_PATH, base + PWM_BASE_OFFSET, PWM_BLOCK_LEN)?;
+                pwm.write_reg(PWM_CTL, 0)?;
+                pwm.write_reg(PWM_RNG1, PACING_BITS_PER_US)?;
+                pwm.write_reg(PWM_DMAC, DMAC_ENAB | 15 << DMAC_PANIC_SHIFT | 15)?;
+                pwm.write_reg(PWM_CTL, CTL_CLRF)?;
+                pwm.write_reg(PWM_CTL, CTL_USEF | CTL_MODE_SERIALISER | CTL_PWEN)?;
+                Pacer::Pwm(pwm)
+            }
+            Pacing::Pcm => Pacer::Pcm(Pcm::open_pacer(DEV_MEM_PATH, base + PCM_BASE_OFFSET, PACING_BITS_PER_US)?),
+        };
+        Ok(Self { gpio, dma, pacing, pacer, memory: None, data_offset: 0, fixed_pulses: None })
+    }
+
+    pub fn pacing(&self) -> Pacing {
+        self.pacing
+    }
+
+    /// Starts playing `wave`, once or over and over, replacing anything
+    /// already playing. Every pin level the wave drives is vetted by the
+    /// write policy first.
+    pub fn play(&mut self, wave: &Wave, repeat: bool) -> Result<(), Error> {
+        wave.validate()?;
+        let (set, clear) = wave.pulses().iter().fold((0, 0), |(set, clear), pulse| (set | pulse.set, clear | pulse.clear));
+        self.check_policy(set, clear)?;
+
+        self.stop()?;
+        // The size doesn't depend on the base address, so compile once to
+        // size the allocation and again against it.
+        let size = Program::compile(wave, 0, repeat, self.pacing).size();
+        let memory = DmaMemory::allocate(size, self.gpio.soc())?;
+        let program = Program::compile(wave, memory.bus_address(), repeat, self.pacing);
+        for (index, block) in program.blocks.iter().enumerate() {
+            memory.write_words(CONTROL_BLOCK_SIZE as usize * index, &block.words())?;
+        }
+        self.data_offset = CONTROL_BLOCK_SIZE as usize * program.blocks.len();
+        memory.write_words(self.data_offset, &program.data)?;
+        self.dma.start(memory.bus_address())?;
+        self.memory = Some(memory);
+        self.fixed_pulses = wave.fixed_masks.then_some(wave.pulses().len());
+        Ok(())
+    }
+
+    // Vets the levels as one batch, as `GPIO::write_mask` does.
+    fn check_policy(&self, set: u64, clear: u64) -> Result<(), Error> {
+        let _guard = self.gpio.config_lock.lock().unwrap();
+        let changes = pins_in(set).map(|pin| (pin, true))
+            .chain(pins_in(clear).map(|pin| (pin, false)))
+            .map(|(pin, new)| Ok(PinChange::Level { pin, old: self.gpio.output_state(pin)?, new }))
+            .collect::<Result<Vec<_>, Error>>()?;
+        self.gpio.check_policies(&changes)
+    }
+
+    /// Replaces the masks of pulse `index` of the wave playing, which must
+    /// have been built with fixed masks, without stopping it. The words
+    /// are written one at a time, so the DMA controller may pick up a mix
+    /// of old and new masks for one pass.
+    pub(crate) fn rewrite_pulse(&self, index: usize, set: u64, clear: u64) -> Result<(), Error> {
+        let (Some(memory), Some(count)) = (&self.memory, self.fixed_pulses) else {
+            return Err(Error::Other("no wave with fixed masks is playing".to_string()));
+        };
+        if index >= count {
+            return Err(Error::Other(format!("the wave has no pulse {}", index)));
+        }
+        Wave::new().pulse(set, clear, 0).validate()?;
+        self.check_policy(set, clear)?;
+        let words = [set as u32, (set >> 32) as u32, clear as u32, (clear >> 32) as u32];
+        memory.write_words(self.data_offset + 4 * (1 + DATA_WORDS_PER_PULSE as usize * index), &words)
+    }
+
+    pub fn is_busy(&self) -> Result<bool, Error> {
+        self.dma.is_active()
+    }
+
+    /// Blocks until a wave played once has finished.
+    pub fn wait(&self, timeout: Option<Duration>) -> Result<(), Error> {
+        self.dma.wait(timeout)
+    }
+
+    /// Stops playback wherever it has got to.
+    pub fn stop(&mut self) -> Result<(), Error> {
+        self.dma.reset()?;
+        self.memory = None;
+        self.fixed_pulses = None;
+        Ok(())
+    }
+}
+
+impl<'a> Drop for WaveEngine<'a> {
+    fn drop(&mut self) {
+        // The channel must stop before its control blocks are freed.
+        let _ = self.dma.reset();
+        self.memory = None;
+        match &self.pacer {
+            Pacer::Pwm(pwm) => {
+                let _ = pwm.write_reg(PWM_DMAC, 0);
+                let _ = pwm.write_reg(PWM_CTL, 0);
+            }
+            Pacer::Pcm(pcm) => {
+                let _ = pcm.disable_dma();
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GPSET0_BUS: u32 = 0x7e20_001c;
+    const GPCLR0_BUS: u32 = 0x7e20_0028;
+    const FIF1_BUS: u32 = 0x7e20_c018;
+    const PCM_FIFO_BUS: u32 = 0x7e20_3004;
+
+    #[test]
+    fn test_compile_wave() {
+        let wave = Wave::new()
+            .pulse(1 << 4, 0, 10)
+            .pulse(1 << 40, 1 << 4, 0)
+            .pulse(0, 1 << 40, 20_000);
+        assert_eq!(wave.duration(), Duration::from_micros(20_010));
+        assert_eq!(wave.pins(), 1 << 4 | 1 << 40);
+
+        let base = 0xc000_0000;
+        let program = Program::compile(&wave, base, false, Pacing::Pwm);
+        // set, delay | set, clear | clear, and a delay split in two.
+        assert_eq!(program.blocks.len(), 7);
+        assert_eq!(program.data.len(), 13);
+        assert_eq!(program.size(), 7 * 32 + 13 * 4);
+
+        let data_base = base + 7 * 32;
+        let blocks = &program.blocks;
+        assert_eq!(blocks[0].destination, GPSET0_BUS);
+        assert_eq!(blocks[0].source, data_base + 4);
+        assert_eq!(blocks[0].length, 8);
+        assert_eq!(blocks[1].destination, FIF1_BUS);
+        assert_eq!(blocks[1].source, data_base);
+        assert_eq!(blocks[1].length, 40);
+        assert_eq!(blocks[1].transfer_info & TI_DEST_DREQ, TI_DEST_DREQ);
+        assert_eq!(blocks[1].transfer_info >> TI_PERMAP_SHIFT & 0x1f, DREQ_PWM);
+        assert_eq!(blocks[2].source, data_base + 4 * 5);
+        assert_eq!(blocks[3].destination, GPCLR0_BUS);
+        assert_eq!(blocks[3].source, data_base + 4 * 7);
+        assert_eq!(blocks[4].destination, GPCLR0_BUS);
+        assert_eq!(blocks[5].length, MAX_TRANSFER_LEN);
+        assert_eq!(blocks[6].length, 80_000 - MAX_TRANSFER_LEN);
+        assert_eq!(&program.data[5..9], &[0, 1 << 8, 1 << 4, 0]);
+
+        assert_eq!(blocks[0].next, base + 32);
+        assert_eq!(blocks[6].next, 0);
+        assert_eq!(Program::compile(&wave, base, true, Pacing::Pwm).blocks[6].next, base);
+
+        let paced_by_pcm = Program::compile(&wave, base, false, Pacing::Pcm);
+        assert_eq!(paced_by_pcm.blocks[1].destination, PCM_FIFO_BUS);
+        assert_eq!(paced_by_pcm.blocks[1].transfer_info >> TI_PERMAP_SHIFT & 0x1f, DREQ_PCM_TX);
+        assert_eq!(paced_by_pcm.blocks[0], program.blocks[0]);
+
+        // With fixed masks, empty masks get their transfers too.
+        let fixed = Program::compile(&wave.clone().with_fixed_masks(), base, false, Pacing::Pwm);
+        assert_eq!(fixed.blocks.len(), 9);
+        assert_eq!(fixed.data, program.data);
+        assert_eq!(fixed.blocks[1].destination, GPCLR0_BUS);
+        assert_eq!(fixed.blocks[1].source, base + 9 * 32 + 4 * 3);
+    }
+
+    #[test]
+    fn test_invalid_waves() {
+        assert!(Wave::new().validate().is_err());
+        assert!(Wave::new().pulse(1 << 58, 0, 1).validate().is_err());
+        assert!(Wave::new().pulse(1 << 3, 1 << 3, 1).validate().is_err());
+        assert!(Wave::new().pulse(1 << 3, 1 << 4, 1).validate().is_ok());
+    }
+
+    #[test]
+    fn test_control_block_layout() {
+        assert_eq!(CONTROL_BLOCK_SIZE, 32);
+        assert_eq!(std::mem::align_of::<ControlBlock>(), 32);
+    }
+}
@@ -0,0 +1,182 @@
+use crate::clock::{Clock, ClockManager, ClockSource, Mash};
+use crate::dma::{
+    ControlBlock, DmaChannel, DmaMemory, CONTROL_BLOCK_SIZE, DREQ_PWM, PERIPHERAL_BUS_BASE, TI_DEST_DREQ,
+    TI_NO_WIDE_BURSTS, TI_PERMAP_SHIFT, TI_SRC_INC, TI_WAIT_RESP,
+};
+use crate::pwm::{
+    CTL_CLRF, CTL_MODE_SERIALISER, CTL_PWEN, CTL_USEF, DMAC_ENAB, DMAC_PANIC_SHIFT, PWM_BASE_OFFSET, PWM_BLOCK_LEN,
+    PWM_CTL, PWM_DMAC, PWM_FIF1, PWM_RNG1,
+};
+use crate::region::MappedRegion;
+use crate::{detect_peripheral_base, Error, PwmChannel, GPIO, DEV_MEM_PATH};
+
+use std::time::Duration;
+
+
+// Each data bit is sent as three serialiser bits at 2.4 MHz: 110 for a one
+// and 100 for a zero, giving the 800 kHz bit rate the LEDs expect.
+const SERIAL_CLOCK_HZ: u32 = 2_400_000;
+const SERIAL_BITS_PER_BIT: usize = 3;
+const BITS_PER_PIXEL: usize = 24;
+// Low time after the data that latches it; at least 50 us, here 8 words
+// (about 107 us).
+const RESET_WORDS: usize = 8;
+
+const SHOW_TIMEOUT: Duration = Duration::from_millis(100);
+
+
+// Packs the serialiser bit stream for `pixels`, sent green, red, blue and
+// most significant bit first, scaled by `brightness` out of 255.
+fn encode(pixels: &[(u8, u8, u8)], brightness: u8) -> Vec<u32> {
+    let bits = pixels.len() * BITS_PER_PIXEL * SERIAL_BITS_PER_BIT;
+    let mut words = vec![0u32; bits.div_ceil(32) + RESET_WORDS];
+    let scale = |channel: u8| ((channel as u16 * brightness as u16 + 127) / 255) as u8;
+    let mut position = 0;
+    for &(red, green, blue) in pixels {
+        for &byte in [scale(green), scale(red), scale(blue)].iter() {
+            for bit in (0..8).rev() {
+                let pattern = if byte & (1 << bit) != 0 { 0b110 } else { 0b100 };
+                for serial_bit in (0..SERIAL_BITS_PER_BIT).rev() {
+                    if pattern & (1 << serial_bit) != 0 {
+                        words[position / 32] |= 1 << (31 - position % 32);
+                    }
+                    position += 1;
+                }
+            }
+        }
+    }
+    words
+}
+
+
+/// A WS2812 ("NeoPixel") strip on GPIO 12 or 18. The bit stream is clocked
+/// out by the PWM serialiser, fed by DMA, as software can't meet the
+/// LEDs' timing. It takes over the PWM controller and its clock. Needs
+/// `/dev/mem` and `/dev/vcio`.
+pub struct Ws2812 {
+    pixels: Vec<(u8, u8, u8)>,
+    brightness: u8,
+    dma: DmaChannel,
+    pwm: MappedRegion,
+    memory: DmaMemory,
+}
+
+impl Ws2812 {
+
+    pub const DEFAULT_DMA_CHANNEL: u32 = 10;
+
+    pub fn new(gpio: &GPIO, pin: u32, count: usize) -> Result<Self, Error> {
+        Self::with_channel(gpio, pin, count, Self::DEFAULT_DMA_CHANNEL)
+    }
+
+    pub fn with_channel(gpio: &GPIO, pin: u32, count: usize, dma_channel: u32) -> Result<Self, Error> {
+        let Some(&(_, function)) = PwmChannel::Pwm0.pins().iter().find(|&&(candidate, _)| candidate == pin) else {
+            return Err(Error::Other(format!("a WS2812 strip needs PWM0, on pin 12 or 18, not pin {}", pin)));
+        };
+        let base = detect_peripheral_base()?;
+        let dma = DmaChannel::open(base, dma_channel)?;
+        let pwm = MappedRegion::open(DEV_MEM_PATH, base + PWM_BASE_OFFSET, PWM_BLOCK_LEN)?;
+        let words = encode(&vec![(0, 0, 0); count], 0).len() as u32;
+        let memory = DmaMemory::allocate(CONTROL_BLOCK_SIZE + 4 * words, gpio.soc())?;
+
+        let clocks = ClockManager::new()?;
+        let oscillator_hz = ClockSource::Oscillator.frequency_hz(gpio.soc()).unwrap_or(19_200_000);
+        // Integer division is within the LEDs' tolerance on every model.
+        clocks.configure(Clock::Pwm, ClockSource::Oscillator, oscillator_hz / SERIAL_CLOCK_HZ, 0, Mash::Integer)?;
+        pwm.write_reg(PWM_CTL, 0)?;
+        pwm.write_reg(PWM_RNG1, 32)?;
+        pwm.write_reg(PWM_DMAC, DMAC_ENAB | 7 << DMAC_PANIC_SHIFT | 3)?;
+        pwm.write_reg(PWM_CTL, CTL_CLRF)?;
+        pwm.write_reg(PWM_CTL, CTL_USEF | CTL_MODE_SERIALISER | CTL_PWEN)?;
+        gpio.set_function(pin, function)?;
+
+        Ok(Self { pixels: vec![(0, 0, 0); count], brightness: 255, dma, pwm, memory })
+    }
+
+    pub fn len(&self) -> usize {
+        self.pixels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pixels.is_empty()
+    }
+
+    /// Sets pixel `index` to `(red, green, blue)`. Takes effect on `show`.
+    pub fn set_pixel(&mut self, index: usize, rgb: (u8, u8, u8)) -> Result<(), Error> {
+        let count = self.pixels.len();
+        let pixel = self.pixels.get_mut(index)
+            .ok_or_else(|| Error::Other(format!("pixel {} is past the end of a {}-pixel strip", index, count)))?;
+        *pixel = rgb;
+        Ok(())
+    }
+
+    pub fn pixel(&self, index: usize) -> Option<(u8, u8, u8)> {
+        self.pixels.get(index).copied()
+    }
+
+    pub fn fill(&mut self, rgb: (u8, u8, u8)) {
+        self.pixels.iter_mut().for_each(|pixel| *pixel = rgb);
+    }
+
+    pub fn clear(&mut self) {
+        self.fill((0, 0, 0));
+    }
+
+    /// Scales every pixel by `brightness` out of 255 when sent.
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness;
+    }
+
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    /// Sends the pixels to the strip, after any previous frame has gone.
+    pub fn show(&mut self) -> Result<(), Error> {
+        self.dma.wait(Some(SHOW_TIMEOUT))?;
+        let words = encode(&self.pixels, self.brightness);
+        let block = ControlBlock {
+            transfer_info: TI_NO_WIDE_BURSTS | TI_WAIT_RESP | TI_SRC_INC | TI_DEST_DREQ | DREQ_PWM << TI_PERMAP_SHIFT,
+            source: self.memory.bus_address() + CONTROL_BLOCK_SIZE,
+            destination: PERIPHERAL_BUS_BASE + (PWM_BASE_OFFSET as u32) + PWM_FIF1 as u32,
+            length: 4 * words.len() as u32,
+            ..ControlBlock::default()
+        };
+        self.memory.write_words(0, &block.words())?;
+        self.memory.write_words(CONTROL_BLOCK_SIZE as usize, &words)?;
+        self.dma.start(self.memory.bus_address())
+    }
+}
+
+impl Drop for Ws2812 {
+    fn drop(&mut self) {
+        let _ = self.dma.wait(Some(SHOW_TIMEOUT));
+        let _ = self.dma.reset();
+        let _ = self.pwm.write_reg(PWM_DMAC, 0);
+        let _ = self.pwm.write_reg(PWM_CTL, 0);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_pixels() {
+        // Green 0x80, red 0x00, blue 0xff: 72 serial bits.
+        let words = encode(&[(0x00, 0x80, 0xff)], 255);
+        assert_eq!(words.len(), 3 + RESET_WORDS);
+        // 110 then 100s for green and red, then 110s for blue.
+        assert_eq!(words[0], 0b1101_0010_0100_1001_0010_0100_1001_0010);
+        assert_eq!(words[1], 0b0100_1001_0010_0100_1101_1011_0110_1101);
+        assert_eq!(words[2], 0b1011_0110_0000_0000_0000_0000_0000_0000);
+        assert!(words[3..].iter().all(|&word| word == 0));
+    }
+
+    #[test]
+    fn test_encode_scales_brightness() {
+        assert_eq!(encode(&[(255, 255, 255)], 0), encode(&[(0, 0, 0)], 255));
+        assert_eq!(encode(&[(200, 100, 2)], 128), encode(&[(100, 50, 1)], 255));
+    }
+}